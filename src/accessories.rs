@@ -0,0 +1,468 @@
+use crate::config::{app_dir, load_config, systemd_dir, LoggingConfig};
+use crate::discovery::{discover_accessories, discover_processes};
+use crate::docker::{
+  compose_service_names, logging_yaml, login_accessory_registries, wait_for_accessory_healthy,
+  wait_for_postgres_ready, wait_for_redis_ready,
+};
+use crate::env::{load_env_file_contents, write_env_file_contents};
+use crate::error::{Error, Result};
+use crate::events::record_event;
+use crate::log::*;
+use crate::plugins::{accessory_plugin, plugins_dir, run_accessory_plugin, Plugin, PluginKind};
+use crate::systemd::{apply_unit_changes, restart_app_target, write_unit};
+use rand::Rng;
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Settings of a new accessory; whatever is unset gets the accessory type's default.
+#[derive(Debug, Clone, Default)]
+pub struct AccessoryOptions {
+  /// Version (default: 17 for postgres, 7 for redis)
+  pub version: Option<String>,
+  /// Postgres username (defaults to app name)
+  pub user: Option<String>,
+  /// Postgres database name (defaults to app name)
+  pub database: Option<String>,
+  /// Postgres password (generated when unset)
+  pub password: Option<String>,
+}
+
+/// Sets up the accessories (databases, caches) an app's processes use.
+#[derive(Debug, Clone)]
+pub struct AccessoryManager {
+  app: String,
+}
+
+impl AccessoryManager {
+  pub fn new(app: impl Into<String>) -> Self {
+    AccessoryManager { app: app.into() }
+  }
+
+  /// Accessories set up for the app.
+  pub fn list(&self) -> Result<Vec<String>> {
+    let systemd_dir = systemd_dir();
+    let processes = discover_processes(&systemd_dir, &self.app)?;
+    Ok(discover_accessories(
+      &systemd_dir,
+      &app_dir(&self.app),
+      &self.app,
+      &processes,
+    )?)
+  }
+
+  /// Add an accessory of type `kind` to the app and start it, restarting the app so its
+  /// processes pick up the connection settings written to .env.
+  pub async fn add(&self, kind: &str, opts: AccessoryOptions) -> Result<()> {
+    let app = self.app.as_str();
+    let mut args = vec![kind.to_string()];
+    if let Some(version) = &opts.version {
+      args.push(format!("--version={}", version));
+    }
+    let result = self.add_kind(kind, opts).await;
+    record_event(app, "accessory add", &args, &result);
+    result
+  }
+
+  async fn add_kind(&self, kind: &str, opts: AccessoryOptions) -> Result<()> {
+    let app = self.app.as_str();
+    match kind {
+      "postgres" => add_postgres(app, &ensure_app_dir_exists(app)?, opts).await?,
+      "redis" => add_redis(app, &ensure_app_dir_exists(app)?, opts).await?,
+      _ => match accessory_plugin(kind)? {
+        Some(plugin) => add_plugin(app, &ensure_app_dir_exists(app)?, &plugin, opts).await?,
+        None => {
+          return Err(Error::Config(anyhow::anyhow!(
+            "unsupported accessory type: {} (no accessory-{} plugin in {})",
+            kind,
+            kind,
+            plugins_dir().display()
+          )))
+        }
+      },
+    }
+    Ok(())
+  }
+}
+
+/// Verify that the app directory exists
+pub fn ensure_app_dir_exists(app: &str) -> Result<PathBuf> {
+  let dir = app_dir(app);
+  if !dir.exists() {
+    return Err(Error::Config(anyhow::anyhow!(
+      "app directory does not exist: {}. Run 'hl init' first.",
+      dir.display()
+    )));
+  }
+  Ok(dir)
+}
+
+async fn add_postgres(app: &str, dir: &Path, opts: AccessoryOptions) -> anyhow::Result<()> {
+  // Set defaults
+  let version = opts.version.unwrap_or_else(|| "17".to_string());
+  let user = opts.user.unwrap_or_else(|| app.to_string());
+  let database = opts.database.unwrap_or_else(|| app.to_string());
+  let password = opts.password.unwrap_or_else(generate_password);
+  let postgres_host = format!("{app}_pg", app = app);
+
+  // Load config to get the network name and any image override
+  let config = load_config(app).await?;
+  let image = config.accessory_image("postgres", "postgres", &version);
+  let compose_postgres = render_postgres_compose(
+    &image,
+    &postgres_host,
+    &config.network,
+    config.logging.as_ref(),
+  );
+
+  let postgres_compose_path = dir.join("compose.postgres.yml");
+  fs::write(&postgres_compose_path, compose_postgres).await?;
+
+  ok(&format!("created {}", postgres_compose_path.display()));
+
+  // Update .env file
+  let env_path = dir.join(".env");
+  let mut env_content = if env_path.exists() {
+    load_env_file_contents(&env_path)?
+  } else {
+    HashMap::new()
+  };
+
+  // Track if we made any changes
+  let mut changed = false;
+
+  // Append missing or modified variables
+  if env_content.get("POSTGRES_USER") != Some(&user) {
+    env_content.insert("POSTGRES_USER".into(), user);
+    changed = true;
+  }
+  if env_content.get("POSTGRES_PASSWORD") != Some(&password) {
+    env_content.insert("POSTGRES_PASSWORD".into(), password);
+    changed = true;
+  }
+  if env_content.get("POSTGRES_DB") != Some(&database) {
+    env_content.insert("POSTGRES_DB".into(), database);
+    changed = true;
+  }
+  if env_content.get("POSTGRES_HOST") != Some(&postgres_host) {
+    env_content.insert("POSTGRES_HOST".into(), postgres_host);
+    changed = true;
+  }
+
+  if changed {
+    write_env_file_contents(&env_path, &env_content).await?;
+    // Set permissions to 600
+    let mut perms = fs::metadata(&env_path).await?.permissions();
+    perms.set_mode(0o600);
+    fs::set_permissions(&env_path, perms).await?;
+
+    ok(&format!(
+      "updated {} with postgres credentials (chmod 600)",
+      env_path.display()
+    ));
+  } else {
+    log("all postgres environment variables already exist in .env");
+  }
+
+  // Regenerate the systemd unit to include the new compose.postgres.yml file
+  let systemd_dir = systemd_dir();
+  let processes = discover_processes(&systemd_dir, app)?;
+  let accessories = with_accessory(
+    discover_accessories(&systemd_dir, dir, app, &processes)?,
+    "postgres",
+  );
+  write_unit(app, &processes, &accessories, config.systemd.mode).await?;
+  ok("regenerated systemd unit file to include postgres compose file");
+  login_accessory_registries(&config, &accessories).await?;
+  apply_unit_changes(&format!("app-{}-acc.service", app)).await?;
+  log("waiting for postgres to be ready...");
+  wait_for_postgres_ready(app, config.accessory_ready_timeout("postgres")?).await?;
+  ok("postgres is ready");
+  restart_app_target(app).await?;
+
+  Ok(())
+}
+
+/// compose.postgres.yml; the container logs like the app's processes (hl.yml `logging:`).
+fn render_postgres_compose(
+  image: &str,
+  postgres_host: &str,
+  network: &str,
+  logging: Option<&LoggingConfig>,
+) -> String {
+  format!(
+    r#"services:
+  pg:
+    image: {image}
+    container_name: {postgres_host}
+    restart: unless-stopped{logging}
+    environment:
+      POSTGRES_USER: ${{POSTGRES_USER}}
+      POSTGRES_PASSWORD: ${{POSTGRES_PASSWORD}}
+      POSTGRES_DB: ${{POSTGRES_DB}}
+    volumes:
+      - ./pgdata:/var/lib/postgresql/data
+    networks: [{network}]
+    expose: ["5432"]
+    healthcheck:
+      test: ["CMD-SHELL", "pg_isready -U $$POSTGRES_USER -d $$POSTGRES_DB || exit 1"]
+      interval: 5s
+      timeout: 3s
+      retries: 10
+
+networks:
+  {network}:
+    external: true
+    name: {network}
+"#,
+    logging = logging_yaml(logging),
+  )
+}
+
+/// compose.redis.yml; the container logs like the app's processes (hl.yml `logging:`).
+fn render_redis_compose(
+  image: &str,
+  app: &str,
+  network: &str,
+  logging: Option<&LoggingConfig>,
+) -> String {
+  format!(
+    r#"services:
+  redis:
+    image: {image}
+    container_name: {app}_redis
+    restart: unless-stopped{logging}
+    volumes:
+      - ./redisdata:/data
+    networks: [{network}]
+    expose: ["6379"]
+    healthcheck:
+      test: ["CMD", "redis-cli", "ping"]
+      interval: 5s
+      timeout: 3s
+      retries: 10
+
+networks:
+  {network}:
+    external: true
+    name: {network}
+"#,
+    logging = logging_yaml(logging),
+  )
+}
+
+/// The app's accessories including `name`, which discovery only knows about once the units
+/// (and state.yml) are rewritten with it.
+fn with_accessory(mut accessories: Vec<String>, name: &str) -> Vec<String> {
+  if !accessories.iter().any(|a| a == name) {
+    accessories.push(name.to_string());
+    accessories.sort();
+  }
+  accessories
+}
+
+/// Generate a random strong password (alphanumeric only to avoid URI encoding issues)
+fn generate_password() -> String {
+  const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+  const PASSWORD_LEN: usize = 32;
+  let mut rng = rand::rng();
+
+  (0..PASSWORD_LEN)
+    .map(|_| {
+      let idx = rng.random_range(0..CHARSET.len());
+      CHARSET[idx] as char
+    })
+    .collect()
+}
+
+async fn add_redis(app: &str, dir: &Path, opts: AccessoryOptions) -> anyhow::Result<()> {
+  // Set default version
+  let version = opts.version.unwrap_or_else(|| "7".to_string());
+
+  // Load config to get the network name and any image override
+  let config = load_config(app).await?;
+  let image = config.accessory_image("redis", "redis", &version);
+  let compose_redis = render_redis_compose(&image, app, &config.network, config.logging.as_ref());
+
+  let redis_compose_path = dir.join("compose.redis.yml");
+  fs::write(&redis_compose_path, compose_redis).await?;
+
+  ok(&format!("created {}", redis_compose_path.display()));
+
+  // Update .env file
+  let env_path = dir.join(".env");
+  let mut env_content = if env_path.exists() {
+    fs::read_to_string(&env_path).await?
+  } else {
+    String::new()
+  };
+
+  // Check if Redis URL already exists
+  let has_redis_url = env_content.contains("REDIS_URL=");
+
+  if !has_redis_url {
+    // Ensure the file ends with a newline before appending
+    if !env_content.is_empty() && !env_content.ends_with('\n') {
+      env_content.push('\n');
+    }
+
+    let redis_url = format!("REDIS_URL=redis://{}_redis:6379/0\n", app);
+    env_content.push_str(&redis_url);
+
+    // Write the updated content
+    fs::write(&env_path, &env_content).await?;
+
+    // Set permissions to 600
+    let mut perms = fs::metadata(&env_path).await?.permissions();
+    perms.set_mode(0o600);
+    fs::set_permissions(&env_path, perms).await?;
+
+    ok(&format!(
+      "updated {} with REDIS_URL (chmod 600)",
+      env_path.display()
+    ));
+  } else {
+    log("REDIS_URL already exists in .env");
+  }
+
+  let systemd_dir = systemd_dir();
+  let processes = discover_processes(&systemd_dir, app)?;
+  let accessories = with_accessory(
+    discover_accessories(&systemd_dir, dir, app, &processes)?,
+    "redis",
+  );
+  write_unit(app, &processes, &accessories, config.systemd.mode).await?;
+  ok("regenerated systemd unit file to include redis compose file");
+  login_accessory_registries(&config, &accessories).await?;
+  apply_unit_changes(&format!("app-{}-acc.service", app)).await?;
+  log("waiting for redis to be ready...");
+  wait_for_redis_ready(app, config.accessory_ready_timeout("redis")?).await?;
+  ok("redis is ready");
+  restart_app_target(app).await?;
+
+  Ok(())
+}
+
+/// Add the accessory an `accessory-<type>` plugin describes.
+async fn add_plugin(
+  app: &str,
+  dir: &Path,
+  plugin: &Plugin,
+  opts: AccessoryOptions,
+) -> anyhow::Result<()> {
+  let PluginKind::Accessory(kind) = &plugin.kind else {
+    anyhow::bail!("{} is not an accessory plugin", plugin.name);
+  };
+  let config = load_config(app).await?;
+  let mut env = vec![
+    ("HL_APP", app.to_string()),
+    ("HL_APP_DIR", dir.to_string_lossy().to_string()),
+    ("HL_NETWORK", config.network.clone()),
+    ("HL_ACCESSORY", kind.clone()),
+  ];
+  for (key, value) in [
+    ("HL_ACCESSORY_VERSION", opts.version),
+    ("HL_ACCESSORY_USER", opts.user),
+    ("HL_ACCESSORY_DATABASE", opts.database),
+    ("HL_ACCESSORY_PASSWORD", opts.password),
+  ] {
+    if let Some(value) = value {
+      env.push((key, value));
+    }
+  }
+  let output = run_accessory_plugin(plugin, &env).await?;
+  if compose_service_names(&output.compose)?.is_empty() {
+    anyhow::bail!(
+      "plugin {} printed a compose overlay without services",
+      plugin.name
+    );
+  }
+
+  let compose_path = dir.join(format!("compose.{}.yml", kind));
+  fs::write(&compose_path, &output.compose).await?;
+  ok(&format!("created {}", compose_path.display()));
+
+  let env_path = dir.join(".env");
+  let mut env_content = if env_path.exists() {
+    load_env_file_contents(&env_path)?
+  } else {
+    HashMap::new()
+  };
+  let mut changed = Vec::new();
+  for (key, value) in output.env {
+    if env_content.get(&key) != Some(&value) {
+      env_content.insert(key.clone(), value);
+      changed.push(key);
+    }
+  }
+  if !changed.is_empty() {
+    write_env_file_contents(&env_path, &env_content).await?;
+    let mut perms = fs::metadata(&env_path).await?.permissions();
+    perms.set_mode(0o600);
+    fs::set_permissions(&env_path, perms).await?;
+    ok(&format!(
+      "updated {} with {} (chmod 600)",
+      env_path.display(),
+      changed.join(", ")
+    ));
+  }
+
+  let systemd_dir = systemd_dir();
+  let processes = discover_processes(&systemd_dir, app)?;
+  let accessories = with_accessory(
+    discover_accessories(&systemd_dir, dir, app, &processes)?,
+    kind,
+  );
+  write_unit(app, &processes, &accessories, config.systemd.mode).await?;
+  ok(&format!(
+    "regenerated systemd unit file to include {} compose file",
+    kind
+  ));
+  login_accessory_registries(&config, &accessories).await?;
+  apply_unit_changes(&format!("app-{}-acc.service", app)).await?;
+  log(&format!("waiting for {} to be ready...", kind));
+  wait_for_accessory_healthy(app, kind, config.accessory_ready_timeout(kind)?).await?;
+  ok(&format!("{} is ready", kind));
+  restart_app_target(app).await?;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_accessory_compose_logging() {
+    let logging = LoggingConfig {
+      max_size: Some("10m".to_string()),
+      max_file: Some(3),
+      ..Default::default()
+    };
+    for (compose, service) in [
+      (
+        render_postgres_compose("postgres:17", "blog_pg", "traefik_proxy", Some(&logging)),
+        "pg",
+      ),
+      (
+        render_redis_compose("redis:7", "blog", "traefik_proxy", Some(&logging)),
+        "redis",
+      ),
+    ] {
+      let parsed: serde_yaml::Value = serde_yaml::from_str(&compose).unwrap();
+      let section = &parsed["services"][service]["logging"];
+      assert_eq!(section["driver"].as_str(), Some("json-file"), "{}", service);
+      assert_eq!(section["options"]["max-size"].as_str(), Some("10m"));
+      assert_eq!(section["options"]["max-file"].as_str(), Some("3"));
+    }
+
+    let compose = render_postgres_compose("postgres:17", "blog_pg", "traefik_proxy", None);
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&compose).unwrap();
+    assert!(parsed["services"]["pg"]["logging"].is_null());
+    assert_eq!(
+      parsed["services"]["pg"]["container_name"].as_str(),
+      Some("blog_pg")
+    );
+  }
+}
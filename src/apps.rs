@@ -0,0 +1,150 @@
+use crate::config::{hl_root, split_env_app_name};
+use anyhow::Result;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Names of all apps on this host: directories under the hl root that contain an hl.yml, and
+/// the instances of their environments.
+pub fn list_apps() -> Result<Vec<String>> {
+  let root = hl_root();
+  if !root.exists() {
+    return Ok(Vec::new());
+  }
+  let mut apps = Vec::new();
+  for entry in std::fs::read_dir(&root)? {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().to_string();
+    if entry.path().join("hl.yml").exists() || split_env_app_name(&name).is_some() {
+      apps.push(name);
+    }
+  }
+  apps.sort();
+  Ok(apps)
+}
+
+/// Outcome of an operation on a single app.
+pub struct AppResult {
+  pub app: String,
+  pub result: Result<()>,
+}
+
+/// Run `op` for every app, at most `jobs` at a time. Results come back in `apps` order.
+pub async fn run_for_apps<F, Fut>(apps: Vec<String>, jobs: usize, op: F) -> Vec<AppResult>
+where
+  F: Fn(String) -> Fut,
+  Fut: Future<Output = Result<()>> + Send + 'static,
+{
+  let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+  let mut set = JoinSet::new();
+  for (idx, app) in apps.iter().enumerate() {
+    let semaphore = semaphore.clone();
+    let fut = op(app.clone());
+    set.spawn(async move {
+      let result = match semaphore.acquire_owned().await {
+        Ok(_permit) => fut.await,
+        Err(e) => Err(e.into()),
+      };
+      (idx, result)
+    });
+  }
+
+  let mut results: Vec<Option<Result<()>>> = apps.iter().map(|_| None).collect();
+  while let Some(joined) = set.join_next().await {
+    match joined {
+      Ok((idx, result)) => results[idx] = Some(result),
+      // A panicking task leaves its slot empty and is reported below
+      Err(e) => crate::log::debug(&format!("app task failed to join: {}", e)),
+    }
+  }
+
+  apps
+    .into_iter()
+    .zip(results)
+    .map(|(app, result)| AppResult {
+      app,
+      result: result.unwrap_or_else(|| Err(anyhow::anyhow!("task panicked"))),
+    })
+    .collect()
+}
+
+/// Print a per-app result table and return the number of failures.
+pub fn print_summary(results: &[AppResult]) -> usize {
+  let width = results
+    .iter()
+    .map(|r| r.app.len())
+    .max()
+    .unwrap_or(0)
+    .max("APP".len());
+  println!("{:<width$}  RESULT", "APP", width = width);
+  let mut failures = 0;
+  for r in results {
+    match &r.result {
+      Ok(()) => println!("{:<width$}  ok", r.app, width = width),
+      Err(e) => {
+        failures += 1;
+        println!("{:<width$}  failed: {:#}", r.app, e, width = width);
+      }
+    }
+  }
+  failures
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serial_test::serial;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use tempfile::TempDir;
+
+  #[test]
+  #[serial]
+  fn test_list_apps() {
+    let temp_dir = TempDir::new().unwrap();
+    std::env::set_var("HL_ROOT_OVERRIDE", temp_dir.path());
+    for app in ["blog", "recipes"] {
+      std::fs::create_dir_all(temp_dir.path().join(app)).unwrap();
+      std::fs::write(temp_dir.path().join(app).join("hl.yml"), "").unwrap();
+    }
+    std::fs::create_dir_all(temp_dir.path().join("not-an-app")).unwrap();
+    std::fs::write(temp_dir.path().join("blog/hl.staging.yml"), "").unwrap();
+    std::fs::create_dir_all(temp_dir.path().join("blog-staging")).unwrap();
+
+    assert_eq!(
+      list_apps().unwrap(),
+      vec!["blog", "blog-staging", "recipes"]
+    );
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+  }
+
+  #[tokio::test]
+  async fn test_run_for_apps_bounds_parallelism_and_keeps_order() {
+    let running = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let apps: Vec<String> = (0..6).map(|i| format!("app{}", i)).collect();
+
+    let results = run_for_apps(apps, 2, |app| {
+      let running = running.clone();
+      let peak = peak.clone();
+      async move {
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        peak.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        running.fetch_sub(1, Ordering::SeqCst);
+        if app == "app3" {
+          anyhow::bail!("boom");
+        }
+        Ok(())
+      }
+    })
+    .await;
+
+    assert!(peak.load(Ordering::SeqCst) <= 2);
+    let names: Vec<_> = results.iter().map(|r| r.app.as_str()).collect();
+    assert_eq!(names, vec!["app0", "app1", "app2", "app3", "app4", "app5"]);
+    assert_eq!(print_summary(&results), 1);
+    assert!(results[3].result.is_err());
+  }
+}
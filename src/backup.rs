@@ -0,0 +1,263 @@
+use crate::config::hl_root;
+use crate::log::debug;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/*
+`hl backup` writes a single .tar.gz with:
+  ./backup.yml  what the app was made of (BackupManifest), read first by `hl restore`
+  ./app/        the app directory: hl.yml, .env*, compose files, releases/, logs/ and the data
+                directories (pgdata, redisdata, volumes/); backups/ is left out
+  ./git/        the app's bare git repository, so the host keeps accepting pushes
+  ./volume/     the hl.yml `persistence:` volume, when there is one
+Accessory data belongs to the container users (postgres runs as uid 999), so archiving and
+extracting both happen in a helper container running as root, which keeps ownership intact.
+File names reach the helper's shell as positional parameters, never as script text.
+ */
+
+/// Name of the manifest at the root of a backup archive.
+pub const BACKUP_MANIFEST: &str = "backup.yml";
+
+const HELPER_IMAGE: &str = "alpine:latest";
+
+/// Describes what a backup holds, so it can be restored on a host that knows nothing about
+/// the app.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupManifest {
+  pub app: String,
+  pub processes: Vec<String>,
+  pub accessories: Vec<String>,
+  /// Docker volume archived under ./volume
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub volume: Option<String>,
+  /// Whether the bare git repository is archived under ./git (older backups lack it)
+  #[serde(default)]
+  pub git: bool,
+  /// Unix seconds
+  pub created_at: u64,
+  /// hl version that wrote the backup
+  pub hl_version: String,
+}
+
+/// `docker run` arguments archiving an app dir (and its git repository and volume) with the
+/// manifest into `out`, handed to `owner` (uid, gid) afterwards.
+pub fn archive_args(
+  app_dir: &Path,
+  git_dir: Option<&Path>,
+  manifest: &Path,
+  volume: Option<&str>,
+  out: &Path,
+  owner: (u32, u32),
+) -> Vec<String> {
+  let out_dir = out.parent().unwrap_or(Path::new("/"));
+  let out_name = out
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_default();
+  let mut args = vec![
+    "run".to_string(),
+    "--rm".to_string(),
+    "-v".to_string(),
+    format!("{}:/backup/{}:ro", manifest.display(), BACKUP_MANIFEST),
+    "-v".to_string(),
+    format!("{}:/backup/app:ro", app_dir.display()),
+  ];
+  if let Some(git_dir) = git_dir {
+    args.push("-v".to_string());
+    args.push(format!("{}:/backup/git:ro", git_dir.display()));
+  }
+  if let Some(volume) = volume {
+    args.push("-v".to_string());
+    args.push(format!("{}:/backup/volume:ro", volume));
+  }
+  args.extend([
+    "-v".to_string(),
+    format!("{}:/out", out_dir.display()),
+    HELPER_IMAGE.to_string(),
+    "sh".to_string(),
+    "-c".to_string(),
+    r#"tar -czf "/out/$1" --numeric-owner --exclude=./app/backups -C /backup . && chown "$2" "/out/$1""#
+      .to_string(),
+    "sh".to_string(),
+    out_name,
+    format!("{}:{}", owner.0, owner.1),
+  ]);
+  args
+}
+
+/// `docker run` arguments extracting a backup's app dir (and git repository and volume) back
+/// in place. The git repository is handed to `owner` (uid, gid), who receives the pushes.
+pub fn extract_args(
+  archive: &Path,
+  app_dir: &Path,
+  git_dir: Option<&Path>,
+  volume: Option<&str>,
+  owner: (u32, u32),
+) -> Vec<String> {
+  let in_dir = archive.parent().unwrap_or(Path::new("/"));
+  let in_name = archive
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_default();
+  let mut args = vec![
+    "run".to_string(),
+    "--rm".to_string(),
+    "-v".to_string(),
+    format!("{}:/in:ro", in_dir.display()),
+    "-v".to_string(),
+    format!("{}:/restore/app", app_dir.display()),
+  ];
+  let mut script =
+    r#"tar -xzf "/in/$1" --numeric-owner -C /restore/app --strip-components=2 ./app"#.to_string();
+  if let Some(git_dir) = git_dir {
+    args.push("-v".to_string());
+    args.push(format!("{}:/restore/git", git_dir.display()));
+    script.push_str(
+      r#" && tar -xzf "/in/$1" --numeric-owner -C /restore/git --strip-components=2 ./git && chown -R "$2" /restore/git"#,
+    );
+  }
+  if let Some(volume) = volume {
+    args.push("-v".to_string());
+    args.push(format!("{}:/restore/volume", volume));
+    script.push_str(
+      r#" && tar -xzf "/in/$1" --numeric-owner -C /restore/volume --strip-components=2 ./volume"#,
+    );
+  }
+  args.extend([
+    HELPER_IMAGE.to_string(),
+    "sh".to_string(),
+    "-c".to_string(),
+    script,
+    "sh".to_string(),
+    in_name,
+    format!("{}:{}", owner.0, owner.1),
+  ]);
+  args
+}
+
+/// Read the manifest out of a backup archive.
+pub async fn read_manifest(archive: &Path) -> Result<BackupManifest> {
+  let output = Command::new("tar")
+    .arg("-xzOf")
+    .arg(archive)
+    .arg(format!("./{}", BACKUP_MANIFEST))
+    .stdin(Stdio::null())
+    .output()
+    .await
+    .context("failed to run tar")?;
+  if !output.status.success() {
+    anyhow::bail!(
+      "{} is not an hl backup: {}",
+      archive.display(),
+      String::from_utf8_lossy(&output.stderr).trim()
+    );
+  }
+  serde_yaml::from_slice(&output.stdout)
+    .with_context(|| format!("invalid {} in {}", BACKUP_MANIFEST, archive.display()))
+}
+
+/// Run the helper container with `args`.
+pub async fn run_helper(args: &[String]) -> Result<()> {
+  debug(&format!("executing: docker {}", args.join(" ")));
+  let status = Command::new("docker")
+    .args(args)
+    .stdin(Stdio::null())
+    .status()
+    .await?;
+  if !status.success() {
+    anyhow::bail!("backup helper container failed with status: {}", status);
+  }
+  Ok(())
+}
+
+/// Scratch directory for the manifest while archiving.
+pub fn staging_dir(app: &str) -> PathBuf {
+  hl_root().join(format!(".backup-{}", app))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_archive_and_extract_args() {
+    let args = archive_args(
+      Path::new("/home/u/hl/apps/myapp"),
+      Some(Path::new("/home/u/hl/git/myapp.git")),
+      Path::new("/home/u/hl/apps/.backup-myapp/backup.yml"),
+      Some("myapp-data"),
+      Path::new("/tmp/myapp.tar.gz"),
+      (1000, 1000),
+    );
+    let joined = args.join(" ");
+    assert!(joined.contains("-v /home/u/hl/apps/myapp:/backup/app:ro"));
+    assert!(joined.contains("-v /home/u/hl/git/myapp.git:/backup/git:ro"));
+    assert!(joined.contains("-v myapp-data:/backup/volume:ro -v /tmp:/out alpine:latest"));
+    assert!(joined.contains("--exclude=./app/backups"));
+    assert_eq!(args[args.len() - 2..], ["myapp.tar.gz", "1000:1000"]);
+
+    let args = extract_args(
+      Path::new("/tmp/myapp.tar.gz"),
+      Path::new("/home/u/hl/apps/myapp"),
+      None,
+      None,
+      (1000, 1000),
+    );
+    assert_eq!(args[3], "/tmp:/in:ro");
+    assert!(args[args.len() - 4].ends_with("--strip-components=2 ./app"));
+    assert!(!args.iter().any(|a| a.contains("/restore/git")));
+  }
+
+  #[test]
+  fn test_helper_args_keep_file_names_out_of_the_script() {
+    // A name with shell syntax is handed to sh as $1, never spliced into the script
+    let out = Path::new("/tmp/x$(touch pwned);.tar.gz");
+    let args = archive_args(
+      Path::new("/home/u/hl/apps/myapp"),
+      None,
+      Path::new("/home/u/hl/apps/.backup-myapp/backup.yml"),
+      None,
+      out,
+      (1000, 1000),
+    );
+    let script_at = args.iter().position(|a| a == "-c").unwrap() + 1;
+    assert!(!args[script_at].contains("pwned"));
+    assert_eq!(args[script_at + 2], "x$(touch pwned);.tar.gz");
+
+    let args = extract_args(
+      out,
+      Path::new("/home/u/hl/apps/myapp"),
+      Some(Path::new("/home/u/hl/git/myapp.git")),
+      Some("myapp-data"),
+      (1000, 1000),
+    );
+    let script_at = args.iter().position(|a| a == "-c").unwrap() + 1;
+    assert!(!args[script_at].contains("pwned"));
+    assert!(args[script_at].contains("./git && chown -R \"$2\" /restore/git"));
+    assert_eq!(args[script_at + 2], "x$(touch pwned);.tar.gz");
+  }
+
+  #[test]
+  fn test_manifest_roundtrip() {
+    let manifest = BackupManifest {
+      app: "myapp".to_string(),
+      processes: vec!["web".to_string(), "worker".to_string()],
+      accessories: vec!["postgres".to_string()],
+      volume: None,
+      git: true,
+      created_at: 1_700_000_000,
+      hl_version: "0.1.0".to_string(),
+    };
+    let yaml = serde_yaml::to_string(&manifest).unwrap();
+    assert!(yaml.contains("createdAt: 1700000000"));
+    assert!(!yaml.contains("volume"));
+    assert_eq!(
+      serde_yaml::from_str::<BackupManifest>(&yaml).unwrap(),
+      manifest
+    );
+  }
+}
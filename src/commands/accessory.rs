@@ -1,15 +1,16 @@
 use anyhow::Result;
 use clap::{Args, Subcommand};
-use hl::config::{app_dir, load_config, systemd_dir};
+use hl::accessories::{ensure_app_dir_exists, AccessoryManager, AccessoryOptions};
+use hl::config::systemd_dir;
 use hl::discovery::{discover_accessories, discover_processes};
-use hl::docker::{wait_for_postgres_ready, wait_for_redis_ready};
-use hl::env::{load_env_file_contents, write_env_file_contents};
+use hl::docker::{
+  compose_service_names, container_disk_usage_kib, inspect_compose_project, ContainerInfo,
+};
 use hl::git::infer_app_name;
 use hl::log::*;
-use hl::systemd::{apply_unit_changes, restart_app_target, write_unit};
-use rand::Rng;
-use std::collections::HashMap;
-use std::os::unix::fs::PermissionsExt;
+use hl::output::{emit, output_format};
+use hl::time::{format_duration, parse_rfc3339, unix_now};
+use serde::Serialize;
 use tokio::fs;
 
 #[derive(Args)]
@@ -22,11 +23,14 @@ pub struct AccessoriesArgs {
 pub enum AccessoriesCommand {
   /// Add an accessory to an app
   Add(AddArgs),
+  /// Show health, last healthcheck output, uptime and data size of each accessory
+  #[command(alias = "ls")]
+  Status,
 }
 
 #[derive(Args)]
 pub struct AddArgs {
-  /// Accessory type (e.g., postgres, redis)
+  /// Accessory type: postgres, redis or one added by an accessory-<type> plugin
   pub accessory: String,
 
   /// Version (default: 17 for postgres, 7 for redis)
@@ -49,239 +53,196 @@ pub struct AddArgs {
 pub async fn execute(opts: AccessoriesArgs) -> Result<()> {
   match opts.command {
     AccessoriesCommand::Add(args) => execute_add(args).await,
+    AccessoriesCommand::Status => execute_status().await,
   }
 }
 
 async fn execute_add(opts: AddArgs) -> Result<()> {
   let app = infer_app_name().await?;
-  match opts.accessory.as_str() {
-    "postgres" => add_postgres(&app, opts).await,
-    "redis" => add_redis(&app, opts).await,
-    _ => {
-      anyhow::bail!("unsupported accessory type: {}", opts.accessory);
-    }
-  }
+  let manager = AccessoryManager::new(app);
+  manager
+    .add(
+      &opts.accessory,
+      AccessoryOptions {
+        version: opts.version,
+        user: opts.user,
+        database: opts.database,
+        password: opts.password,
+      },
+    )
+    .await?;
+  Ok(())
 }
 
-/// Verify that the app directory exists
-fn ensure_app_dir_exists(app: &str) -> Result<std::path::PathBuf> {
-  let dir = app_dir(app);
-  if !dir.exists() {
-    anyhow::bail!(
-      "app directory does not exist: {}. Run 'hl init' first.",
-      dir.display()
-    );
-  }
-  Ok(dir)
+/// One accessory container as `hl accessory status` reports it.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessoryStatus {
+  accessory: String,
+  service: String,
+  /// None when the service has no container
+  container: Option<String>,
+  state: Option<String>,
+  health: Option<String>,
+  started_at: Option<String>,
+  /// Running, and healthy if it has a healthcheck
+  healthy: bool,
+  last_check: Option<LastCheck>,
+  data: Vec<DataDir>,
 }
 
-async fn add_postgres(app: &str, opts: AddArgs) -> Result<()> {
-  let dir = ensure_app_dir_exists(app)?;
-
-  // Set defaults
-  let version = opts.version.unwrap_or_else(|| "17".to_string());
-  let user = opts.user.unwrap_or_else(|| app.to_string());
-  let database = opts.database.unwrap_or_else(|| app.to_string());
-  let password = opts.password.unwrap_or_else(generate_password);
-  let postgres_host = format!("{app}_pg", app = app);
-
-  // Load config to get the network name
-  let config = load_config(app).await?;
-  let network = config.network;
-
-  let compose_postgres = format!(
-    r#"services:
-  pg:
-    image: postgres:{version}
-    container_name: {postgres_host}
-    restart: unless-stopped
-    environment:
-      POSTGRES_USER: ${{POSTGRES_USER}}
-      POSTGRES_PASSWORD: ${{POSTGRES_PASSWORD}}
-      POSTGRES_DB: ${{POSTGRES_DB}}
-    volumes:
-      - ./pgdata:/var/lib/postgresql/data
-    networks: [{network}]
-    expose: ["5432"]
-    healthcheck:
-      test: ["CMD-SHELL", "pg_isready -U $$POSTGRES_USER -d $$POSTGRES_DB || exit 1"]
-      interval: 5s
-      timeout: 3s
-      retries: 10
-
-networks:
-  {network}:
-    external: true
-    name: {network}
-"#,
-    version = version,
-    postgres_host = postgres_host,
-    network = network
-  );
-
-  let postgres_compose_path = dir.join("compose.postgres.yml");
-  fs::write(&postgres_compose_path, compose_postgres).await?;
-
-  ok(&format!("created {}", postgres_compose_path.display()));
-
-  // Update .env file
-  let env_path = dir.join(".env");
-  let mut env_content = if env_path.exists() {
-    load_env_file_contents(&env_path)?
-  } else {
-    HashMap::new()
-  };
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LastCheck {
+  exit_code: i64,
+  output: String,
+}
 
-  // Track if we made any changes
-  let mut changed = false;
+/// A bind mount under the app directory holding the accessory's data.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DataDir {
+  /// Relative to the app directory
+  path: String,
+  size_kib: Option<u64>,
+}
 
-  // Append missing or modified variables
-  if env_content.get("POSTGRES_USER") != Some(&user) {
-    env_content.insert("POSTGRES_USER".into(), user);
-    changed = true;
-  }
-  if env_content.get("POSTGRES_PASSWORD") != Some(&password) {
-    env_content.insert("POSTGRES_PASSWORD".into(), password);
-    changed = true;
-  }
-  if env_content.get("POSTGRES_DB") != Some(&database) {
-    env_content.insert("POSTGRES_DB".into(), database);
-    changed = true;
-  }
-  if env_content.get("POSTGRES_HOST") != Some(&postgres_host) {
-    env_content.insert("POSTGRES_HOST".into(), postgres_host);
-    changed = true;
+async fn execute_status() -> Result<()> {
+  let app = infer_app_name().await?;
+  let dir = ensure_app_dir_exists(&app)?;
+  let systemd_dir = systemd_dir();
+  let processes = discover_processes(&systemd_dir, &app)?;
+  let accessories = discover_accessories(&systemd_dir, &dir, &app, &processes)?;
+  if accessories.is_empty() && output_format().is_none() {
+    log(&format!("{} has no accessories", app));
+    return Ok(());
   }
 
-  if changed {
-    write_env_file_contents(&env_path, &env_content).await?;
-    // Set permissions to 600
-    let mut perms = fs::metadata(&env_path).await?.permissions();
-    perms.set_mode(0o600);
-    fs::set_permissions(&env_path, perms).await?;
-
-    ok(&format!(
-      "updated {} with postgres credentials (chmod 600)",
-      env_path.display()
-    ));
-  } else {
-    log("all postgres environment variables already exist in .env");
+  let containers = inspect_compose_project(&format!("{}-acc", app)).await?;
+  let mut statuses = Vec::new();
+  for accessory in &accessories {
+    let overlay = dir.join(format!("compose.{}.yml", accessory));
+    let services = compose_service_names(&fs::read_to_string(&overlay).await?)?;
+    for service in services {
+      let container = containers
+        .iter()
+        .find(|c| c.compose_service() == Some(service.as_str()));
+      statuses.push(container_status(accessory, &service, container, &dir).await);
+    }
   }
 
-  // Regenerate the systemd unit to include the new compose.postgres.yml file
-  let systemd_dir = systemd_dir();
-  let processes = discover_processes(&systemd_dir, app)?;
-  let accessories = discover_accessories(&systemd_dir, &dir, app, &processes)?;
-  write_unit(app, &processes, &accessories).await?;
-  ok("regenerated systemd unit file to include postgres compose file");
-  apply_unit_changes(&format!("app-{}-acc.service", app)).await?;
-  log("waiting for postgres to be ready...");
-  wait_for_postgres_ready(app).await?;
-  ok("postgres is ready");
-  restart_app_target(app).await?;
-
+  emit(&statuses, |statuses| {
+    let now = unix_now();
+    for status in statuses {
+      print_container_status(status, now);
+    }
+  })?;
+  let unhealthy = statuses.iter().filter(|s| !s.healthy).count();
+  if unhealthy > 0 {
+    anyhow::bail!("{} accessory container(s) not healthy", unhealthy);
+  }
   Ok(())
 }
 
-/// Generate a random strong password (alphanumeric only to avoid URI encoding issues)
-fn generate_password() -> String {
-  const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
-  const PASSWORD_LEN: usize = 32;
-  let mut rng = rand::rng();
-
-  (0..PASSWORD_LEN)
-    .map(|_| {
-      let idx = rng.random_range(0..CHARSET.len());
-      CHARSET[idx] as char
-    })
-    .collect()
+/// Gather the status of an accessory service's container, if it has one.
+async fn container_status(
+  accessory: &str,
+  service: &str,
+  container: Option<&ContainerInfo>,
+  app_dir: &std::path::Path,
+) -> AccessoryStatus {
+  let mut status = AccessoryStatus {
+    accessory: accessory.to_string(),
+    service: service.to_string(),
+    container: None,
+    state: None,
+    health: None,
+    started_at: None,
+    healthy: false,
+    last_check: None,
+    data: Vec::new(),
+  };
+  let Some(container) = container else {
+    return status;
+  };
+  let name = container.name.trim_start_matches('/');
+  let running = container.state.status == "running";
+  let health = container.state.health.as_ref().map(|h| h.status.clone());
+  status.healthy = running && health.as_deref().is_none_or(|h| h == "healthy");
+  status.container = Some(name.to_string());
+  status.state = Some(container.state.status.clone());
+  status.health = health;
+  status.started_at = running.then(|| container.state.started_at.clone());
+  status.last_check = container.last_probe().map(|probe| LastCheck {
+    exit_code: probe.exit_code,
+    output: probe.output.trim().to_string(),
+  });
+
+  // Data lives in bind mounts under the app directory (./pgdata, ./redisdata, ...)
+  for mount in &container.mounts {
+    let Ok(relative) = std::path::Path::new(&mount.source).strip_prefix(app_dir) else {
+      continue;
+    };
+    let size_kib = if running {
+      container_disk_usage_kib(name, &mount.destination).await
+    } else {
+      None
+    };
+    status.data.push(DataDir {
+      path: relative.display().to_string(),
+      size_kib,
+    });
+  }
+  status
 }
 
-async fn add_redis(app: &str, opts: AddArgs) -> Result<()> {
-  let dir = ensure_app_dir_exists(app)?;
-
-  // Set default version
-  let version = opts.version.unwrap_or_else(|| "7".to_string());
-
-  // Load config to get the network name
-  let config = load_config(app).await?;
-  let network = config.network;
-
-  let compose_redis = format!(
-    r#"services:
-  redis:
-    image: redis:{}
-    container_name: {}_redis
-    restart: unless-stopped
-    volumes:
-      - ./redisdata:/data
-    networks: [{}]
-    expose: ["6379"]
-    healthcheck:
-      test: ["CMD", "redis-cli", "ping"]
-      interval: 5s
-      timeout: 3s
-      retries: 10
-
-networks:
-  {}:
-    external: true
-    name: {}
-"#,
-    version, app, network, network, network
-  );
-
-  let redis_compose_path = dir.join("compose.redis.yml");
-  fs::write(&redis_compose_path, compose_redis).await?;
-
-  ok(&format!("created {}", redis_compose_path.display()));
-
-  // Update .env file
-  let env_path = dir.join(".env");
-  let mut env_content = if env_path.exists() {
-    fs::read_to_string(&env_path).await?
-  } else {
-    String::new()
+fn print_container_status(status: &AccessoryStatus, now: u64) {
+  let (Some(container), Some(mut state)) = (&status.container, status.state.clone()) else {
+    warn(&format!(
+      "{} ({}): no container",
+      status.accessory, status.service
+    ));
+    return;
   };
-
-  // Check if Redis URL already exists
-  let has_redis_url = env_content.contains("REDIS_URL=");
-
-  if !has_redis_url {
-    // Ensure the file ends with a newline before appending
-    if !env_content.is_empty() && !env_content.ends_with('\n') {
-      env_content.push('\n');
-    }
-
-    let redis_url = format!("REDIS_URL=redis://{}_redis:6379/0\n", app);
-    env_content.push_str(&redis_url);
-
-    // Write the updated content
-    fs::write(&env_path, &env_content).await?;
-
-    // Set permissions to 600
-    let mut perms = fs::metadata(&env_path).await?.permissions();
-    perms.set_mode(0o600);
-    fs::set_permissions(&env_path, perms).await?;
-
-    ok(&format!(
-      "updated {} with REDIS_URL (chmod 600)",
-      env_path.display()
+  if let Some(health) = &status.health {
+    state.push_str(&format!(", {}", health));
+  }
+  if let Some(started) = status.started_at.as_deref().and_then(parse_rfc3339) {
+    state.push_str(&format!(
+      ", up {}",
+      format_duration(now.saturating_sub(started))
     ));
+  }
+
+  let line = format!("{} ({}): {}", status.accessory, container, state);
+  if status.healthy {
+    ok(&line);
   } else {
-    log("REDIS_URL already exists in .env");
+    warn(&line);
   }
 
-  let systemd_dir = systemd_dir();
-  let processes = discover_processes(&systemd_dir, app)?;
-  let accessories = discover_accessories(&systemd_dir, &dir, app, &processes)?;
-  write_unit(app, &processes, &accessories).await?;
-  ok("regenerated systemd unit file to include redis compose file");
-  apply_unit_changes(&format!("app-{}-acc.service", app)).await?;
-  log("waiting for redis to be ready...");
-  wait_for_redis_ready(app).await?;
-  ok("redis is ready");
-  restart_app_target(app).await?;
+  if let Some(check) = &status.last_check {
+    log(&format!(
+      "  last check: exit {}: {}",
+      check.exit_code, check.output
+    ));
+  }
+  for data in &status.data {
+    log(&format!(
+      "  data: ./{} {}",
+      data.path,
+      data
+        .size_kib
+        .map(format_kib)
+        .unwrap_or_else(|| "(size unavailable)".to_string())
+    ));
+  }
+}
 
-  Ok(())
+fn format_kib(kib: u64) -> String {
+  match kib {
+    0..=1023 => format!("{} KiB", kib),
+    1024..=1_048_575 => format!("{:.1} MiB", kib as f64 / 1024.0),
+    _ => format!("{:.1} GiB", kib as f64 / 1_048_576.0),
+  }
 }
@@ -0,0 +1,154 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  apps::list_apps,
+  config::{app_dir, load_config, systemd_dir},
+  discovery::{discover_accessories, discover_processes},
+  log::*,
+  output::{emit, output_format},
+  releases::{load_releases, releases_dir},
+  systemd::user_unit_active_state,
+  time::{format_age, format_rfc3339, parse_rfc3339, unix_now},
+};
+use serde::Serialize;
+
+#[derive(Args)]
+pub struct AppsArgs {}
+
+/// An app as listed by `hl apps`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AppSummary {
+  app: String,
+  domain: Option<String>,
+  image: Option<String>,
+  processes: Vec<String>,
+  accessories: Vec<String>,
+  state: String,
+  /// Short sha of the most recent deploy
+  release: Option<String>,
+  deployed_at: Option<String>,
+}
+
+/// One row of the apps table.
+struct AppRow {
+  app: String,
+  domain: String,
+  image: String,
+  processes: String,
+  accessories: String,
+  state: String,
+  deployed: String,
+}
+
+pub async fn execute(_args: AppsArgs) -> Result<()> {
+  let apps = list_apps()?;
+  if apps.is_empty() && output_format().is_none() {
+    log("no apps on this host (hl init creates one)");
+    return Ok(());
+  }
+
+  let mut summaries = Vec::new();
+  for app in apps {
+    summaries.push(app_summary(app).await);
+  }
+  emit(&summaries, |summaries| {
+    let now = unix_now();
+    let rows: Vec<AppRow> = summaries.iter().map(|s| app_row(s, now)).collect();
+    print_table(&rows);
+  })
+}
+
+/// Gather what's shown for an app. Anything that can't be read is left out rather than
+/// hiding the other apps.
+async fn app_summary(app: String) -> AppSummary {
+  let (domain, image) = match load_config(&app).await {
+    Ok(cfg) => (Some(cfg.domain), Some(cfg.image)),
+    Err(e) => {
+      debug(&format!("{}: {:#}", app, e));
+      (None, None)
+    }
+  };
+  let units_dir = systemd_dir();
+  let processes = discover_processes(&units_dir, &app).unwrap_or_default();
+  let accessories =
+    discover_accessories(&units_dir, &app_dir(&app), &app, &processes).unwrap_or_default();
+  let state = if processes.is_empty() {
+    "not deployed".to_string()
+  } else {
+    user_unit_active_state(&format!("app-{}.target", app))
+      .await
+      .unwrap_or_else(|_| "unknown".to_string())
+  };
+  let last = load_releases(&releases_dir(&app))
+    .ok()
+    .and_then(|releases| releases.into_iter().next())
+    .map(|(_, release)| release);
+
+  AppSummary {
+    app,
+    domain,
+    image,
+    processes,
+    accessories,
+    state,
+    release: last.as_ref().map(|r| r.short_sha().to_string()),
+    deployed_at: last.as_ref().map(|r| format_rfc3339(r.deployed_at)),
+  }
+}
+
+/// What the table shows for an app: "-" for anything missing.
+fn app_row(summary: &AppSummary, now: u64) -> AppRow {
+  let text = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+  let list = |items: &[String]| {
+    if items.is_empty() {
+      "-".to_string()
+    } else {
+      items.join(",")
+    }
+  };
+  let deployed = match (&summary.release, &summary.deployed_at) {
+    (Some(sha), Some(at)) => match parse_rfc3339(at) {
+      Some(at) => format!("{} ({})", format_age(at, now), sha),
+      None => sha.clone(),
+    },
+    _ => "-".to_string(),
+  };
+  AppRow {
+    app: summary.app.clone(),
+    domain: text(&summary.domain),
+    image: text(&summary.image),
+    processes: list(&summary.processes),
+    accessories: list(&summary.accessories),
+    state: summary.state.clone(),
+    deployed,
+  }
+}
+
+fn print_table(rows: &[AppRow]) {
+  let width = |header: &str, field: fn(&AppRow) -> &str| {
+    rows
+      .iter()
+      .map(|r| field(r).len())
+      .max()
+      .unwrap_or(0)
+      .max(header.len())
+  };
+  let w_app = width("APP", |r| &r.app);
+  let w_domain = width("DOMAIN", |r| &r.domain);
+  let w_image = width("IMAGE", |r| &r.image);
+  let w_procs = width("PROCESSES", |r| &r.processes);
+  let w_accs = width("ACCESSORIES", |r| &r.accessories);
+  let w_state = width("STATE", |r| &r.state);
+
+  println!(
+    "{:<w_app$}  {:<w_domain$}  {:<w_image$}  {:<w_procs$}  {:<w_accs$}  {:<w_state$}  DEPLOYED",
+    "APP", "DOMAIN", "IMAGE", "PROCESSES", "ACCESSORIES", "STATE",
+  );
+  for r in rows {
+    println!(
+      "{:<w_app$}  {:<w_domain$}  {:<w_image$}  {:<w_procs$}  {:<w_accs$}  {:<w_state$}  {}",
+      r.app, r.domain, r.image, r.processes, r.accessories, r.state, r.deployed,
+    );
+  }
+}
@@ -0,0 +1,109 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use hl::{
+  backup::{archive_args, run_helper, staging_dir, BackupManifest, BACKUP_MANIFEST},
+  config::{app_dir, hl_git_root, load_config, systemd_dir},
+  discovery::{discover_accessories, discover_processes},
+  git::infer_app_name,
+  log::*,
+  systemd::{restart_app_target, start_accessories, stop_app_target},
+  time::unix_now,
+};
+
+#[derive(Args)]
+pub struct BackupArgs {
+  /// App to back up (default: HL_APP)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Archive to write (default: ./<app>-backup-<timestamp>.tar.gz)
+  #[arg(long)]
+  pub out: Option<PathBuf>,
+
+  /// Stop the app while archiving so accessory data is consistent, then start it again
+  #[arg(long)]
+  pub stop: bool,
+}
+
+pub async fn execute(args: BackupArgs) -> Result<()> {
+  let app = match &args.app {
+    Some(app) => app.clone(),
+    None => infer_app_name().await?,
+  };
+  let cfg = load_config(&app).await?;
+  let dir = app_dir(&app);
+  let units_dir = systemd_dir();
+  let processes = discover_processes(&units_dir, &app)?;
+  let accessories = discover_accessories(&units_dir, &dir, &app, &processes)?;
+
+  let out = args
+    .out
+    .unwrap_or_else(|| PathBuf::from(format!("{}-backup-{}.tar.gz", app, unix_now())));
+  // The helper container mounts the output directory, so it has to be absolute
+  let out = std::env::current_dir()?.join(out);
+  let out_dir = out.parent().context("invalid --out path")?;
+  std::fs::create_dir_all(out_dir)?;
+  // Hand the archive to whoever owns the directory it lands in, not the container's root
+  let owner = std::fs::metadata(out_dir)?;
+
+  let git_root = hl_git_root(&app);
+  let git_dir = git_root.is_dir().then_some(git_root.as_path());
+  if git_dir.is_none() {
+    warn(&format!(
+      "{} does not exist; the backup won't include a git repository",
+      git_root.display()
+    ));
+  }
+
+  let manifest = BackupManifest {
+    app: app.clone(),
+    processes: processes.clone(),
+    accessories: accessories.clone(),
+    volume: cfg.persistence_volume(),
+    git: git_dir.is_some(),
+    created_at: unix_now(),
+    hl_version: env!("CARGO_PKG_VERSION").to_string(),
+  };
+  let staging = staging_dir(&app);
+  std::fs::create_dir_all(&staging)?;
+  let manifest_path = staging.join(BACKUP_MANIFEST);
+  std::fs::write(&manifest_path, serde_yaml::to_string(&manifest)?)?;
+
+  if args.stop {
+    log(&format!("stopping {} for the backup", app));
+    stop_app_target(&app).await?;
+  } else if !accessories.is_empty() {
+    warn("accessories keep running while their data is archived; use --stop for a consistent copy");
+  }
+
+  log(&format!("archiving {} to {}", app, out.display()));
+  let result = run_helper(&archive_args(
+    &dir,
+    git_dir,
+    &manifest_path,
+    manifest.volume.as_deref(),
+    &out,
+    (owner.uid(), owner.gid()),
+  ))
+  .await;
+  let _ = std::fs::remove_dir_all(&staging);
+
+  if args.stop {
+    log(&format!("starting {}", app));
+    if !accessories.is_empty() {
+      start_accessories(&app).await?;
+    }
+    restart_app_target(&app).await?;
+  }
+  result?;
+
+  ok(&format!("backed up {} to {}", app, out.display()));
+  log(&format!(
+    "To restore it on another host:\n  hl restore {}",
+    out.display()
+  ));
+  Ok(())
+}
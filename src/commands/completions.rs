@@ -0,0 +1,53 @@
+use anyhow::Result;
+use clap::{Args, CommandFactory};
+use hl::{
+  apps::list_apps,
+  completions::{render, Names, Shell},
+  config::{app_dir, systemd_dir},
+  discovery::{discover_accessories, discover_processes},
+  git::infer_app_name,
+  plugins::accessory_plugin_types,
+};
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+  /// Shell to write the completion script for
+  #[arg(value_enum, required_unless_present = "list")]
+  pub shell: Option<Shell>,
+
+  /// Print names the completion scripts offer (used by the scripts themselves)
+  #[arg(long, value_enum, hide = true)]
+  pub list: Option<Names>,
+}
+
+pub async fn execute(args: CompletionsArgs) -> Result<()> {
+  if let Some(names) = args.list {
+    for name in list_names(names).await.unwrap_or_default() {
+      println!("{}", name);
+    }
+    return Ok(());
+  }
+  if let Some(shell) = args.shell {
+    print!("{}", render(shell, crate::Cli::command()));
+  }
+  Ok(())
+}
+
+async fn list_names(names: Names) -> Result<Vec<String>> {
+  match names {
+    Names::Apps => list_apps(),
+    Names::Services => {
+      let app = infer_app_name().await?;
+      let units_dir = systemd_dir();
+      let mut services = discover_processes(&units_dir, &app)?;
+      let accessories = discover_accessories(&units_dir, &app_dir(&app), &app, &services)?;
+      services.extend(accessories);
+      Ok(services)
+    }
+    Names::AccessoryTypes => {
+      let mut types: Vec<String> = ["postgres", "redis"].map(String::from).to_vec();
+      types.extend(accessory_plugin_types()?);
+      Ok(types)
+    }
+  }
+}
@@ -0,0 +1,30 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use hl::{
+  config::load_config,
+  git::infer_app_name,
+  output::{output_format, render},
+};
+
+#[derive(Args)]
+pub struct ConfigArgs {
+  #[command(subcommand)]
+  pub command: ConfigCommands,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+  /// Print hl.yml as deploys see it, with every default filled in
+  Show,
+}
+
+pub async fn execute(args: ConfigArgs) -> Result<()> {
+  let app = infer_app_name().await?;
+  match args.command {
+    ConfigCommands::Show => {
+      let cfg = load_config(&app).await?;
+      print!("{}", render(&cfg, output_format().unwrap_or_default())?);
+      Ok(())
+    }
+  }
+}
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  config::{app_dir, env_app_name, load_config, validate_env_name},
+  git::infer_app_name,
+};
+
+use crate::commands::run::run_one_off;
+
+#[derive(Args)]
+pub struct ConsoleArgs {
+  /// App to open a console for (default: HL_APP)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Open a console in an environment (e.g. staging) of the app
+  #[arg(long)]
+  pub env: Option<String>,
+
+  /// Image tag to run instead of :latest (e.g. a short sha)
+  #[arg(long, default_value = "latest")]
+  pub tag: String,
+}
+
+pub async fn execute(args: ConsoleArgs) -> Result<()> {
+  if let Some(env) = &args.env {
+    validate_env_name(env)?;
+  }
+  let app = match &args.app {
+    Some(app) => app.clone(),
+    None => infer_app_name().await?,
+  };
+  let app = env_app_name(&app, args.env.as_deref());
+  let cfg = load_config(&app).await?;
+  let Some(console) = cfg.console.filter(|c| !c.command.is_empty()) else {
+    anyhow::bail!(
+      "no console command configured; add one to {}, e.g.\nconsole:\n  command: [bin/rails, console]",
+      app_dir(&app).join("hl.yml").display()
+    );
+  };
+  run_one_off(&app, &args.tag, &console.command).await
+}
@@ -1,16 +1,6 @@
 use anyhow::Result;
 use clap::Args;
-use hl::{
-  config::{app_dir, hl_git_root, load_config, systemd_dir},
-  discovery::discover_accessories,
-  docker::*,
-  env::load_build_secrets,
-  git::{export_commit, infer_app_name},
-  health::wait_for_healthy,
-  log::*,
-  procfile::parse_procfile,
-  systemd::{enable_accessories_if_present, reload_systemd_daemon, start_accessories, write_unit},
-};
+use hl::{deployer::Deployer, git::infer_app_name, log::*, systemd::print_unit_changes};
 
 #[derive(Args)]
 pub struct DeployArgs {
@@ -18,139 +8,117 @@ pub struct DeployArgs {
   #[arg(long)]
   pub sha: String,
 
-  /// Git branch name
-  #[arg(long, default_value = "master")]
-  pub branch: String,
-}
+  /// Git branch name (default: master, unless deploying a --tag)
+  #[arg(long)]
+  pub branch: Option<String>,
 
-pub async fn execute(opts: DeployArgs) -> Result<()> {
-  let app = infer_app_name().await?;
-  // Export the commit to a temporary directory
-  let repo_path = hl_git_root(&app)
-    .to_str()
-    .expect("repo path is not valid UTF-8")
-    .to_string();
+  /// Release tag (e.g. v1.4.2); the image is additionally tagged with it
+  #[arg(long)]
+  pub tag: Option<String>,
 
-  debug(&format!("repository path: {}", repo_path));
+  /// Deploy the commit to an environment (hl.<env>.yml over hl.yml, as <app>-<env>)
+  #[arg(long)]
+  pub env: Option<String>,
 
-  let worktree = export_commit(&repo_path, &opts.sha).await?;
+  /// Only show how this commit would change the app's systemd units; build and change nothing
+  #[arg(long)]
+  pub check_units: bool,
 
-  debug(&format!("exported worktree to: {}", worktree.display()));
+  /// Pushed ref (e.g. refs/tags/v1.4.2), set by the post-receive hook; pushes hl.yml's
+  /// `deploy:` settings exclude are skipped. Defaults to the --branch or --tag ref.
+  #[arg(long = "ref")]
+  pub git_ref: Option<String>,
+}
 
-  // Check for Procfile and parse if present
-  let procfile_path = worktree.join("Procfile");
-  let processes = if procfile_path.exists() {
-    debug("found Procfile, parsing processes");
-    let procs = parse_procfile(&procfile_path).await?;
-    debug(&format!("parsed {} processes from Procfile", procs.len()));
-    for (name, cmd) in &procs {
-      debug(&format!("  {}: {}", name, cmd));
+impl DeployArgs {
+  /// The ref checked against hl.yml's `deploy:` settings: --ref, else the ref of the branch
+  /// (or tag) being deployed.
+  fn refname(&self) -> String {
+    match (&self.git_ref, &self.branch, &self.tag) {
+      (Some(refname), _, _) => refname.clone(),
+      (None, Some(branch), _) => format!("refs/heads/{}", branch),
+      (None, None, Some(tag)) => format!("refs/tags/{}", tag),
+      (None, None, None) => "refs/heads/master".to_string(),
     }
-    Some(procs)
-  } else {
-    debug("no Procfile found, using default configuration");
-    None
-  };
-
-  let cfg = load_config(&app).await?;
-
-  // Regenerate base compose.yml so hl.yml changes (volumes, image, network) propagate
-  let app_directory = app_dir(&cfg.app);
-  log("regenerating base compose file");
-  write_base_compose_file(&app_directory, &cfg.image, &cfg.network, &cfg.volumes).await?;
-
-  // Generate process-specific compose files
-  log("generating process compose files");
-  write_process_compose_files(&app_directory, processes.as_ref(), &cfg.app, &cfg.resolver).await?;
-
-  let systemd_dir = systemd_dir();
-  let process_names = processes
-    .map(|p| p.keys().cloned().collect::<Vec<String>>())
-    .unwrap_or_else(|| vec!["web".to_string()]);
-  let accessories = discover_accessories(&systemd_dir, &app_directory, &app, &process_names)?;
-  write_unit(&app, &process_names, &accessories).await?;
-
-  let tags = tag_for(&cfg, &opts.sha, &opts.branch);
-
-  log(&format!(
-    "building {} {} ({})",
-    cfg.app,
-    opts.branch,
-    &opts.sha[..7.min(opts.sha.len())]
-  ));
-
-  // Build using the exported worktree
-  let dockerfile = worktree.join("Dockerfile");
-
-  debug(&format!("dockerfile path: {}", dockerfile.display()));
-
-  // Check if Dockerfile exists
-  if !dockerfile.exists() {
-    anyhow::bail!("Dockerfile not found at: {}", dockerfile.display());
   }
+}
 
-  debug(&format!("build context: {}", worktree.display()));
-
-  // load build-time secrets from .env.build
-  let secrets = load_build_secrets(&app)?;
-
-  build_and_push(BuildPushOptions {
-    context: worktree.to_string_lossy().to_string(),
-    dockerfile: Some(dockerfile.to_string_lossy().to_string()),
-    git_sha: opts.sha.clone(),
-    tags: vec![tags.sha.clone(), tags.branch_sha, tags.latest.clone()],
-    platforms: Some(cfg.platforms.clone()),
-    secrets,
-  })
-  .await?;
-
-  wait_for_accessories(&cfg.app, &accessories).await?;
-
-  log("running migrations");
-  run_migrations(&cfg, &tags.sha).await?;
-
-  log("retagging latest");
-  retag_latest(&cfg.image, &tags.sha).await?;
-
-  log("reloading systemd daemon");
-  reload_systemd_daemon().await?;
-  log("ensuring accessories systemd service is enabled when present");
-  enable_accessories_if_present(&cfg.app, &accessories).await?;
-
-  log("restarting services");
-  restart_compose(&cfg, &process_names, &accessories).await?;
-
-  log("waiting for healthchecks to pass");
-  wait_for_healthy(&cfg).await?;
-
-  // Clean up the temporary worktree
-  if let Err(e) = tokio::fs::remove_dir_all(&worktree).await {
-    eprintln!(
-      "Warning: failed to cleanup worktree at {}: {}",
-      worktree.display(),
-      e
-    );
+pub async fn execute(opts: DeployArgs) -> Result<()> {
+  let repo_app = infer_app_name().await?;
+  let mut deployer = Deployer::new(repo_app, &opts.sha);
+  deployer = match (&opts.branch, &opts.tag) {
+    (Some(branch), _) => deployer.branch(branch),
+    (None, Some(_)) => deployer.without_branch(),
+    (None, None) => deployer,
+  };
+  if let Some(tag) = &opts.tag {
+    deployer = deployer.tag(tag);
+  }
+  if let Some(env) = &opts.env {
+    deployer = deployer.env(env);
   }
+  if opts.check_units {
+    return check_units(&deployer, &opts).await;
+  }
+  let refname = opts.refname();
+  let cfg = deployer.config().await?;
+  if let Err(reason) = cfg.deploy.accepts_ref(&refname) {
+    if opts.git_ref.is_none() {
+      anyhow::bail!("not deploying {}: {}", refname, reason);
+    }
+    if refname.starts_with("refs/heads/") {
+      // Most likely a push to the wrong remote; make it stand out in the push output
+      warn(&format!(
+        "refusing to deploy {}: {}; the push was stored but nothing was deployed",
+        refname, reason
+      ));
+    } else {
+      log(&format!("not deploying {}: {}", refname, reason));
+    }
+    return Ok(());
+  }
+  deployer.run().await?;
+  Ok(())
+}
 
-  ok("deploy complete");
+/// Print the unit changes deploying `opts.sha` would make, without writing anything.
+async fn check_units(deployer: &Deployer, opts: &DeployArgs) -> Result<()> {
+  let changes = deployer.unit_changes().await?;
+  if changes.is_empty() {
+    ok(&format!(
+      "deploying {} leaves the units of {} unchanged",
+      opts.sha,
+      deployer.app()
+    ));
+  } else {
+    print_unit_changes(&changes);
+    log(&format!("{} unit file(s) would change", changes.len()));
+  }
   Ok(())
 }
 
-async fn wait_for_accessories(app: &str, accessories: &[String]) -> Result<()> {
-  if !accessories.is_empty() {
-    // Ensure accessories are started and ready before running migrations
-    log("enabling and starting accessories");
-    start_accessories(app).await?;
-    if accessories.contains(&"postgres".to_string()) {
-      log("waiting for postgres to be ready...");
-      wait_for_postgres_ready(app).await?;
-      ok("postgres is ready");
-    }
-    if accessories.contains(&"redis".to_string()) {
-      log("waiting for redis to be ready...");
-      wait_for_redis_ready(app).await?;
-      ok("redis is ready");
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn args(extra: &[&str]) -> DeployArgs {
+    #[derive(clap::Parser)]
+    struct Cli {
+      #[command(flatten)]
+      deploy: DeployArgs,
     }
+    let argv = ["hl", "--sha", "abc1234"].iter().chain(extra).copied();
+    <Cli as clap::Parser>::parse_from(argv).deploy
+  }
+
+  #[test]
+  fn test_refname_without_ref() {
+    assert_eq!(args(&[]).refname(), "refs/heads/master");
+    assert_eq!(args(&["--branch", "main"]).refname(), "refs/heads/main");
+    assert_eq!(args(&["--tag", "v1.4.2"]).refname(), "refs/tags/v1.4.2");
+    assert_eq!(
+      args(&["--branch", "main", "--ref", "refs/heads/other"]).refname(),
+      "refs/heads/other"
+    );
   }
-  Ok(())
 }
@@ -0,0 +1,251 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  config::{
+    app_dir, env_app_name, hl_git_root, load_config, systemd_dir, validate_env_name, HLConfig,
+  },
+  discovery::{discover_accessories, discover_processes},
+  docker::{
+    compose_service_names, image_id, inspect_compose_project, pinned_tag, render_base_compose,
+    render_process_compose, with_process_image, WebRouter,
+  },
+  drift::{file_drift, name_drift, print_drift, replica_drift, Drift},
+  git::{file_at_commit, infer_app_name},
+  log::*,
+  procfile::parse_procfile_content,
+  releases::{load_releases, releases_dir, short_sha},
+  sidecar::render_sidecar_compose,
+  state::load_state,
+  systemd::plan_unit_changes,
+  textdiff::format_diff,
+  units_spec_builder::WriteOutcome,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+#[derive(Args)]
+pub struct DiffArgs {
+  /// App to compare (default: HL_APP)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Compare an environment (e.g. staging) of the app
+  #[arg(long)]
+  pub env: Option<String>,
+}
+
+pub async fn execute(args: DiffArgs) -> Result<()> {
+  if let Some(env) = &args.env {
+    validate_env_name(env)?;
+  }
+  let repo_app = match &args.app {
+    Some(app) => app.clone(),
+    None => infer_app_name().await?,
+  };
+  let app = env_app_name(&repo_app, args.env.as_deref());
+  let cfg = load_config(&app).await?;
+  let dir = app_dir(&app);
+  let systemd_dir = systemd_dir();
+  let state = load_state(&dir).unwrap_or_default();
+  let release = match state.release.clone() {
+    Some(sha) => Some(sha),
+    None => load_releases(&releases_dir(&app))?
+      .first()
+      .map(|(_, r)| r.sha.clone()),
+  };
+  log(&format!("comparing {} against its desired state", app));
+
+  let mut drifts = Vec::new();
+  let recorded = discover_processes(&systemd_dir, &app)?;
+  let accessories = discover_accessories(&systemd_dir, &dir, &app, &recorded)?;
+
+  // The processes a deploy of the running release would set up
+  let processes = match &release {
+    Some(sha) => {
+      let repo_path = hl_git_root(&repo_app).to_string_lossy().to_string();
+      let procfile = match file_at_commit(&repo_path, sha, "Procfile").await? {
+        Some(content) => Some(parse_procfile_content(&content)?),
+        None => None,
+      };
+      drifts.extend(process_file_drift(&cfg, &dir, procfile.as_ref()));
+      let mut names: Vec<String> = match procfile {
+        Some(procs) => procs.into_keys().collect(),
+        None => vec!["web".to_string()],
+      };
+      names.sort();
+      names
+    }
+    None => {
+      warn(&format!(
+        "{} has no recorded release; comparing its processes against what is set up",
+        app
+      ));
+      recorded
+        .iter()
+        .filter(|p| !cfg.sidecars.contains_key(*p))
+        .cloned()
+        .collect()
+    }
+  };
+  let mut desired = processes;
+  let mut sidecars: Vec<String> = cfg.sidecars.keys().cloned().collect();
+  sidecars.sort();
+  for sidecar in &sidecars {
+    drifts.extend(file_drift(
+      &dir.join(format!("compose.{}.yml", sidecar)),
+      &render_sidecar_compose(&cfg, sidecar, &cfg.sidecars[sidecar])?,
+    ));
+  }
+  desired.extend(sidecars);
+  drifts.extend(name_drift("process", &desired, &recorded));
+
+  // Generated files: compose.yml as hl.yml renders it, accessory overlays, units
+  let tag = pinned_tag(&dir, &cfg.image).unwrap_or_else(|| "latest".to_string());
+  let named_volumes: Vec<String> = cfg.persistence_volume().into_iter().collect();
+  drifts.extend(file_drift(
+    &dir.join("compose.yml"),
+    &render_base_compose(
+      &cfg.image,
+      &tag,
+      &cfg.network,
+      &cfg.base_volumes(),
+      &named_volumes,
+      cfg.systemd.mode,
+      cfg.logging.as_ref(),
+    ),
+  ));
+  for accessory in &accessories {
+    let file = format!("compose.{}.yml", accessory);
+    if !dir.join(&file).exists() {
+      drifts.push(Drift::new(file, "missing"));
+    }
+  }
+  for change in plan_unit_changes(&app, &desired, &accessories, cfg.systemd.mode).await? {
+    let (detail, path) = match &change.outcome {
+      WriteOutcome::Created(p) => ("missing", p),
+      WriteOutcome::Updated(p) => ("differs from what hl would generate", p),
+      WriteOutcome::Removed(p) => ("left over from a removed process", p),
+      WriteOutcome::Unchanged(_) => continue,
+    };
+    drifts.push(Drift {
+      subject: path.display().to_string(),
+      detail: detail.to_string(),
+      diff: Some(format_diff(&change.old, &change.new)),
+    });
+  }
+
+  // Images: compose.yml pins the release that was deployed, and that image is present
+  if let Some(sha) = &release {
+    let short = short_sha(sha);
+    if tag != short {
+      drifts.push(Drift::new(
+        "compose.yml",
+        format!("pinned to {}:{} but {} is deployed", cfg.image, tag, short),
+      ));
+    }
+  }
+  let pinned = format!("{}:{}", cfg.image, tag);
+  let pinned_id = image_id(&pinned).await?;
+  if pinned_id.is_none() {
+    drifts.push(Drift::new(
+      format!("image {}", pinned),
+      "not present locally",
+    ));
+  }
+  let latest = format!("{}:latest", cfg.image);
+  if tag != "latest" && pinned_id.is_some() && image_id(&latest).await? != pinned_id {
+    drifts.push(Drift::new(
+      format!("image {}", latest),
+      format!("does not point at the pinned {}", pinned),
+    ));
+  }
+
+  // Containers: every process at its scale, every accessory service once
+  let expected: BTreeMap<String, u32> = desired
+    .iter()
+    .map(|p| (p.clone(), state.scale.get(p).copied().unwrap_or(1)))
+    .collect();
+  drifts.extend(replica_drift(
+    "process",
+    &expected,
+    &running_services(&app).await?,
+  ));
+  let mut expected = BTreeMap::new();
+  for accessory in &accessories {
+    let Ok(content) = std::fs::read_to_string(dir.join(format!("compose.{}.yml", accessory)))
+    else {
+      continue;
+    };
+    for service in compose_service_names(&content)? {
+      expected.insert(service, 1);
+    }
+  }
+  drifts.extend(replica_drift(
+    "accessory",
+    &expected,
+    &running_services(&format!("{}-acc", app)).await?,
+  ));
+
+  if drifts.is_empty() {
+    ok(&format!("{} matches its desired state", app));
+    return Ok(());
+  }
+  print_drift(&drifts);
+  anyhow::bail!(
+    "{} difference(s) between the desired and actual state of {}",
+    drifts.len(),
+    app
+  )
+}
+
+/// Drift of the Procfile processes' compose files from what the release's Procfile renders.
+fn process_file_drift(
+  cfg: &HLConfig,
+  dir: &Path,
+  procfile: Option<&HashMap<String, String>>,
+) -> Vec<Drift> {
+  let router = WebRouter::for_config(cfg);
+  let tag = pinned_tag(dir, &cfg.image).unwrap_or_else(|| "latest".to_string());
+  let commands: Vec<(&str, Option<&String>)> = match procfile {
+    Some(procs) => procs.iter().map(|(n, c)| (n.as_str(), Some(c))).collect(),
+    None => vec![("web", None)],
+  };
+  let mut drifts: Vec<Drift> = commands
+    .into_iter()
+    .filter_map(|(name, command)| {
+      let mut expected = render_process_compose(
+        name,
+        command,
+        &cfg.app,
+        &cfg.resolver,
+        &router,
+        &cfg.processes,
+      );
+      if cfg
+        .processes
+        .get(name)
+        .is_some_and(|p| p.dockerfile.is_some())
+      {
+        let image = format!("{}:{}", cfg.process_image(name), tag);
+        expected = with_process_image(&expected, &image);
+      }
+      file_drift(&dir.join(format!("compose.{}.yml", name)), &expected)
+    })
+    .collect();
+  drifts.sort_by(|a, b| a.subject.cmp(&b.subject));
+  drifts
+}
+
+/// Running containers of a compose project, counted by service.
+async fn running_services(project: &str) -> Result<BTreeMap<String, u32>> {
+  let mut running = BTreeMap::new();
+  for container in inspect_compose_project(project).await? {
+    if container.state.status != "running" {
+      continue;
+    }
+    if let Some(service) = container.compose_service() {
+      *running.entry(service.to_string()).or_insert(0) += 1;
+    }
+  }
+  Ok(running)
+}
@@ -0,0 +1,116 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  config::{app_dir, load_global_config, systemd_dir, systemd_scope, SystemdScope},
+  discovery::{discover_accessories, discover_processes},
+  docker::{create_network, network_exists},
+  doctor::{app_boot_checks, host_boot_checks, preflight_checks, print_report},
+  git::infer_app_name,
+  log::*,
+  systemd::{enable_lingering, is_lingering_enabled},
+};
+use std::io::{IsTerminal, Write};
+
+#[derive(Args)]
+pub struct DoctorArgs {
+  /// Verify the app's boot path (lingering, docker, target enablement and unit ordering)
+  /// instead of the host preflight checks
+  #[arg(long)]
+  pub boot: bool,
+
+  /// Subnet for the Traefik network when doctor offers to create it (e.g. 172.30.0.0/16)
+  #[arg(long)]
+  pub subnet: Option<String>,
+}
+
+pub async fn execute(args: DoctorArgs) -> Result<()> {
+  offer_lingering().await?;
+  if !args.boot {
+    return preflight(args.subnet.as_deref()).await;
+  }
+
+  let app = infer_app_name().await?;
+  let systemd_dir = systemd_dir();
+  let processes = discover_processes(&systemd_dir, &app)?;
+  let accessories = discover_accessories(&systemd_dir, &app_dir(&app), &app, &processes)?;
+
+  log(&format!("checking boot path for app: {}", app));
+  let mut checks = host_boot_checks().await;
+  checks.extend(app_boot_checks(&app, &systemd_dir, &processes, &accessories).await);
+
+  let failures = print_report(&checks);
+  if failures > 0 {
+    anyhow::bail!(
+      "{} check(s) failed; {} will not come back after a reboot",
+      failures,
+      app
+    );
+  }
+
+  ok("all checks passed");
+  Ok(())
+}
+
+/// When user units would die with the login session, offer to enable lingering. Only asks on
+/// a terminal; elsewhere the lingering check reports it.
+pub async fn offer_lingering() -> Result<()> {
+  if systemd_scope() != SystemdScope::User
+    || !std::io::stdin().is_terminal()
+    || is_lingering_enabled().await.unwrap_or(true)
+  {
+    return Ok(());
+  }
+  print!(
+    "lingering is disabled, so apps stop when you log out and don't start at boot. \
+     Enable it now? [Y/n] "
+  );
+  std::io::stdout().flush()?;
+  let mut answer = String::new();
+  std::io::stdin().read_line(&mut answer)?;
+  if matches!(answer.trim().to_lowercase().as_str(), "n" | "no") {
+    return Ok(());
+  }
+  match enable_lingering().await {
+    Ok(()) => ok("lingering enabled"),
+    Err(e) => warn(&format!("{:#}", e)),
+  }
+  Ok(())
+}
+
+/// When the Traefik network compose files join doesn't exist, offer to create it; deploys
+/// otherwise fail on it with a compose error. Only asks on a terminal; elsewhere the network
+/// check reports it.
+pub async fn offer_network(network: &str, subnet: Option<&str>) -> Result<()> {
+  if !std::io::stdin().is_terminal() || network_exists(network).await.unwrap_or(true) {
+    return Ok(());
+  }
+  print!(
+    "docker network {} doesn't exist; apps and Traefik join it. Create it now? [Y/n] ",
+    network
+  );
+  std::io::stdout().flush()?;
+  let mut answer = String::new();
+  std::io::stdin().read_line(&mut answer)?;
+  if matches!(answer.trim().to_lowercase().as_str(), "n" | "no") {
+    return Ok(());
+  }
+  create_network(network, subnet).await?;
+  ok(&format!(
+    "created network {}; attach Traefik to it (networks: [{}] in its compose file)",
+    network, network
+  ));
+  Ok(())
+}
+
+/// Host checks that don't need an app: is this machine ready to deploy to?
+async fn preflight(subnet: Option<&str>) -> Result<()> {
+  let global = load_global_config().await?;
+  offer_network(global.network.as_deref().unwrap_or("traefik_proxy"), subnet).await?;
+  log("checking host");
+  let failures = print_report(&preflight_checks(&global).await);
+  if failures > 0 {
+    anyhow::bail!("{} check(s) failed", failures);
+  }
+  ok("all checks passed");
+  Ok(())
+}
@@ -1,10 +1,19 @@
 use anyhow::{Context, Result};
 use clap::{Args, Subcommand};
 use hl::{
-  config::{app_dir, build_env_file, env_file},
-  env::load_env_file_contents,
+  config::{app_dir, build_env_file, env_file, load_config},
+  env::{generate_secret, load_env_file_contents, quote_env_value, write_env_file_contents},
+  events::record_event,
   git::infer_app_name,
+  health::wait_for_healthy,
+  log::*,
+  output::emit,
+  releases::{env_digest, env_snapshot_path, find_release, open_snapshot, releases_dir},
+  systemd::{cancel_env_cleanup, restart_app_target, schedule_env_cleanup},
+  time::{format_rfc3339, unix_now},
 };
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
 use std::path::Path;
 use tokio::fs;
 
@@ -23,6 +32,61 @@ pub enum EnvCommands {
     /// Store as build-time secrets
     #[arg(long)]
     build: bool,
+    /// Restart the app afterwards (asked interactively when omitted on a terminal)
+    #[arg(long, conflicts_with = "build")]
+    restart: bool,
+  },
+  /// Remove environment variables
+  Rm {
+    /// Keys to remove
+    #[arg(required = true)]
+    keys: Vec<String>,
+    /// Remove build-time secrets
+    #[arg(long)]
+    build: bool,
+    /// Restart the app afterwards (asked interactively when omitted on a terminal)
+    #[arg(long, conflicts_with = "build")]
+    restart: bool,
+  },
+  /// Set every variable from a dotenv file ("-" reads stdin)
+  Push {
+    /// File to read, e.g. .env.production
+    #[arg(long)]
+    file: String,
+    /// Replace the whole file instead of merging into it
+    #[arg(long)]
+    replace: bool,
+    /// Push build-time secrets
+    #[arg(long)]
+    build: bool,
+    /// Restart the app afterwards (asked interactively when omitted on a terminal)
+    #[arg(long, conflicts_with = "build")]
+    restart: bool,
+  },
+  /// Print all variables (unmasked) in dotenv format, e.g. `hl env pull > .env.backup`
+  Pull {
+    /// Pull build-time secrets
+    #[arg(long)]
+    build: bool,
+  },
+  /// Edit the env file in $EDITOR; it is validated before replacing the original
+  Edit {
+    /// Edit build-time secrets
+    #[arg(long)]
+    build: bool,
+    /// Restart the app afterwards (asked interactively when omitted on a terminal)
+    #[arg(long, conflicts_with = "build")]
+    restart: bool,
+  },
+  /// Print a variable's value (masked unless --show)
+  Get {
+    key: String,
+    /// Print the actual value
+    #[arg(long)]
+    show: bool,
+    /// Read build-time secrets
+    #[arg(long)]
+    build: bool,
   },
   /// List environment variable keys (values masked)
   Ls {
@@ -30,14 +94,139 @@ pub enum EnvCommands {
     #[arg(long)]
     build: bool,
   },
+  /// Compare the runtime env with a local dotenv file, or with the build secrets (values
+  /// masked); exits non-zero when they differ
+  Diff {
+    /// Local file to compare against, e.g. .env.local
+    #[arg(long, required_unless_present = "build", conflicts_with = "build")]
+    file: Option<String>,
+    /// Compare .env.build against the runtime env instead
+    #[arg(long)]
+    build: bool,
+  },
+  /// Restore .env from the snapshot taken when a release was deployed
+  Restore {
+    /// Release sha (full or short)
+    #[arg(long)]
+    release: String,
+  },
+  /// Replace a secret with a generated value, keeping the old one as KEY_PREVIOUS for a
+  /// grace period
+  Rotate {
+    /// Variable to rotate (must already be set)
+    key: String,
+    /// Value generator: hex<N> (N random bytes, hex-encoded) or alnum<N>
+    #[arg(long, default_value = "hex32")]
+    generator: String,
+    /// Days to keep KEY_PREVIOUS before a timer removes it
+    #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(u64).range(1..))]
+    grace_days: u64,
+  },
+  /// Drop KEY_PREVIOUS after a rotation (run by the cleanup timer)
+  #[command(hide = true)]
+  FinishRotation { key: String },
 }
 
 pub async fn execute(args: EnvArgs) -> Result<()> {
   let app = infer_app_name().await?;
   match args.command {
-    EnvCommands::Set { pairs, build } => set_env(&app, pairs, build).await,
+    EnvCommands::Set {
+      pairs,
+      build,
+      restart,
+    } => {
+      // Values are secrets; the log only says which keys changed
+      let keys = pairs
+        .iter()
+        .map(|p| p.split('=').next().unwrap_or_default().to_string())
+        .collect();
+      let result = async {
+        set_env(&app, pairs, build).await?;
+        apply_env_change(&app, build, restart).await
+      }
+      .await;
+      record_event(&app, "env set", &with_build_flag(keys, build), &result);
+      result
+    }
+    EnvCommands::Rm {
+      keys,
+      build,
+      restart,
+    } => {
+      let result = async {
+        if remove_env(&app, &keys, build).await? > 0 {
+          apply_env_change(&app, build, restart).await?;
+        }
+        Ok(())
+      }
+      .await;
+      record_event(&app, "env rm", &with_build_flag(keys, build), &result);
+      result
+    }
+    EnvCommands::Push {
+      file,
+      replace,
+      build,
+      restart,
+    } => {
+      let mut args = vec![format!("--file={}", file)];
+      if replace {
+        args.push("--replace".to_string());
+      }
+      let result = async {
+        push_env(&app, &file, replace, build).await?;
+        apply_env_change(&app, build, restart).await
+      }
+      .await;
+      record_event(&app, "env push", &with_build_flag(args, build), &result);
+      result
+    }
+    EnvCommands::Pull { build } => pull_env(&app, build).await,
+    EnvCommands::Edit { build, restart } => {
+      let result = edit_env(&app, build, restart).await;
+      record_event(&app, "env edit", &with_build_flag(vec![], build), &result);
+      result
+    }
+    EnvCommands::Get { key, show, build } => get_env(&app, &key, show, build).await,
     EnvCommands::Ls { build } => list_env(&app, build).await,
+    EnvCommands::Diff { file, .. } => diff_env(&app, file.as_deref()).await,
+    EnvCommands::Restore { release } => {
+      let result = restore_env(&app, &release).await;
+      record_event(
+        &app,
+        "env restore",
+        &[format!("--release={}", release)],
+        &result,
+      );
+      result
+    }
+    EnvCommands::Rotate {
+      key,
+      generator,
+      grace_days,
+    } => {
+      let result = rotate_env(&app, &key, &generator, grace_days).await;
+      record_event(&app, "env rotate", std::slice::from_ref(&key), &result);
+      result
+    }
+    EnvCommands::FinishRotation { key } => {
+      let result = finish_rotation(&app, &key).await;
+      record_event(
+        &app,
+        "env finish-rotation",
+        std::slice::from_ref(&key),
+        &result,
+      );
+      result
+    }
+  }
+}
+
+fn with_build_flag(mut args: Vec<String>, build: bool) -> Vec<String> {
+  if build {
+    args.push("--build".to_string());
   }
+  args
 }
 
 async fn set_env(app: &str, pairs: Vec<String>, build: bool) -> Result<()> {
@@ -88,25 +277,476 @@ async fn set_env(app: &str, pairs: Vec<String>, build: bool) -> Result<()> {
   Ok(())
 }
 
-async fn list_env(app: &str, build: bool) -> Result<()> {
+/// Returns how many keys were removed.
+async fn remove_env(app: &str, keys: &[String], build: bool) -> Result<usize> {
   let file_path = if build {
     build_env_file(app)
   } else {
     env_file(app)
   };
-  let text = fs::read_to_string(&file_path).await.unwrap_or_default();
+  if !file_path.exists() {
+    anyhow::bail!("{} does not exist", file_path.display());
+  }
+  let permissions = std::fs::metadata(&file_path)?.permissions();
 
-  for line in text.lines() {
-    if line.is_empty() || line.starts_with('#') {
-      continue;
+  let mut map = load_env_file_contents(&file_path)?;
+  let mut removed = 0;
+  for key in keys {
+    if map.remove(key).is_some() {
+      removed += 1;
+    } else {
+      warn(&format!("{} is not set in {}", key, file_path.display()));
     }
-    if let Some(pos) = line.find('=') {
-      if pos > 0 {
-        println!("{}=***", &line[..pos]);
+  }
+  if removed == 0 {
+    return Ok(0);
+  }
+
+  write_env_file_contents(&file_path, &map).await?;
+  std::fs::set_permissions(&file_path, permissions)?;
+  println!("updated {}", file_path.display());
+  Ok(removed)
+}
+
+async fn push_env(app: &str, file: &str, replace: bool, build: bool) -> Result<()> {
+  let file_path = if build {
+    build_env_file(app)
+  } else {
+    env_file(app)
+  };
+  let incoming = if file == "-" {
+    parse_env(dotenvy::from_read_iter(std::io::stdin()))?
+  } else {
+    parse_env(dotenvy::from_path_iter(file).with_context(|| format!("failed to read {}", file))?)?
+  };
+
+  let mut map = if replace || !file_path.exists() {
+    HashMap::new()
+  } else {
+    load_env_file_contents(&file_path)?
+  };
+  let added = incoming.keys().filter(|k| !map.contains_key(*k)).count();
+  let count = incoming.len();
+  map.extend(incoming);
+
+  fs::create_dir_all(app_dir(app)).await?;
+  write_private_env(&file_path, &map).await?;
+  println!(
+    "updated {} ({} variables, {} new)",
+    file_path.display(),
+    count,
+    added
+  );
+  Ok(())
+}
+
+fn parse_env<R: std::io::Read>(iter: dotenvy::Iter<R>) -> Result<HashMap<String, String>> {
+  let mut map = HashMap::new();
+  for item in iter {
+    let (k, v) = item?;
+    map.insert(k, v);
+  }
+  Ok(map)
+}
+
+async fn pull_env(app: &str, build: bool) -> Result<()> {
+  let file_path = if build {
+    build_env_file(app)
+  } else {
+    env_file(app)
+  };
+  if !file_path.exists() {
+    anyhow::bail!("{} does not exist", file_path.display());
+  }
+  let map = load_env_file_contents(&file_path)?;
+  let mut keys: Vec<_> = map.keys().collect();
+  keys.sort();
+  for key in keys {
+    println!("{}={}", key, quote_env_value(&map[key]));
+  }
+  Ok(())
+}
+
+async fn edit_env(app: &str, build: bool, restart: bool) -> Result<()> {
+  let file_path = if build {
+    build_env_file(app)
+  } else {
+    env_file(app)
+  };
+  let original = fs::read(&file_path).await.unwrap_or_default();
+  // Keep the working copy next to the original (not in /tmp) so secrets stay in the app dir
+  // and the final rename is atomic
+  let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+  let edit_path = file_path.with_file_name(format!("{}.edit-{}", file_name, std::process::id()));
+  fs::create_dir_all(app_dir(app)).await?;
+  fs::write(&edit_path, &original).await?;
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(&edit_path, std::fs::Permissions::from_mode(0o600))?;
+  }
+
+  let result = edit_until_valid(&edit_path).await;
+  let edited = match result {
+    Ok(Some(edited)) => edited,
+    Ok(None) | Err(_) => {
+      let _ = fs::remove_file(&edit_path).await;
+      return result.map(|_| log("aborted; env file left unchanged"));
+    }
+  };
+  if edited.as_bytes() == original.as_slice() {
+    fs::remove_file(&edit_path).await?;
+    log("no changes");
+    return Ok(());
+  }
+
+  fs::rename(&edit_path, &file_path).await?;
+  ok(&format!("updated {}", file_path.display()));
+  apply_env_change(app, build, restart).await
+}
+
+/// Processes only see `.env` changes after a restart: restart when asked, offer it on a
+/// terminal, otherwise point at `hl restart`. Build secrets wait for the next deploy.
+async fn apply_env_change(app: &str, build: bool, restart: bool) -> Result<()> {
+  if build {
+    return Ok(());
+  }
+  let restart = restart || (std::io::stdin().is_terminal() && confirm_restart(app)?);
+  if restart {
+    log(&format!("restarting {}...", app));
+    restart_app_target(app).await?;
+    ok("restart complete");
+  } else {
+    log("restart the app (hl restart) for processes to pick it up");
+  }
+  Ok(())
+}
+
+fn confirm_restart(app: &str) -> Result<bool> {
+  print!(
+    "restart {} now so processes pick up the change? [y/N] ",
+    app
+  );
+  std::io::stdout().flush()?;
+  let mut input = String::new();
+  std::io::stdin().read_line(&mut input)?;
+  Ok(matches!(input.trim(), "y" | "Y" | "yes"))
+}
+
+/// Open `path` in $EDITOR until it parses. Returns the content, or None if the user gives up.
+async fn edit_until_valid(path: &Path) -> Result<Option<String>> {
+  let editor = std::env::var("VISUAL")
+    .or_else(|_| std::env::var("EDITOR"))
+    .unwrap_or_else(|_| "vi".to_string());
+  let mut command = shell_words::split(&editor).context("invalid $EDITOR")?;
+  if command.is_empty() {
+    anyhow::bail!("$EDITOR is empty");
+  }
+  let program = command.remove(0);
+
+  loop {
+    let status = tokio::process::Command::new(&program)
+      .args(&command)
+      .arg(path)
+      .status()
+      .await
+      .with_context(|| format!("failed to run editor {}", program))?;
+    if !status.success() {
+      anyhow::bail!("editor exited with status: {}", status);
+    }
+
+    let content = fs::read_to_string(path).await?;
+    match validate_env_content(&content) {
+      Ok(_) => return Ok(Some(content)),
+      Err(e) => {
+        err(&format!("{:#}", e));
+        log("press enter to fix it, or type q to discard your changes");
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        if input.trim().eq_ignore_ascii_case("q") {
+          return Ok(None);
+        }
       }
     }
   }
+}
+
+/// Check that every line parses as dotenv and every key is a valid variable name.
+fn validate_env_content(content: &str) -> Result<usize> {
+  let key_re = regex::Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").expect("valid regex");
+  let mut count = 0;
+  for item in dotenvy::from_read_iter(content.as_bytes()) {
+    let (key, _) = item.context("invalid env file")?;
+    if !key_re.is_match(&key) {
+      anyhow::bail!("invalid variable name: {:?}", key);
+    }
+    count += 1;
+  }
+  Ok(count)
+}
 
+async fn get_env(app: &str, key: &str, show: bool, build: bool) -> Result<()> {
+  let file_path = if build {
+    build_env_file(app)
+  } else {
+    env_file(app)
+  };
+  let value = lookup_env(&file_path, key)?;
+  if show {
+    println!("{}", value);
+  } else {
+    println!("{}", mask_value(&value));
+  }
+  Ok(())
+}
+
+fn lookup_env(file_path: &Path, key: &str) -> Result<String> {
+  if !file_path.exists() {
+    anyhow::bail!("{} does not exist", file_path.display());
+  }
+  load_env_file_contents(file_path)?
+    .remove(key)
+    .with_context(|| format!("{} is not set in {}", key, file_path.display()))
+}
+
+/// Stand-in for a value that only says how long it is.
+fn mask_value(value: &str) -> String {
+  format!(
+    "*** ({} chars, use --show to reveal)",
+    value.chars().count()
+  )
+}
+
+#[derive(Debug, PartialEq)]
+enum EnvDiff {
+  /// Only in the runtime env
+  Missing,
+  /// Only in the other file
+  Extra,
+  /// In both with different values; the lengths of each
+  Changed(usize, usize),
+}
+
+/// Without a file (i.e. with --build), .env.build is compared.
+async fn diff_env(app: &str, file: Option<&str>) -> Result<()> {
+  let runtime_path = env_file(app);
+  let runtime = if runtime_path.exists() {
+    load_env_file_contents(&runtime_path)?
+  } else {
+    HashMap::new()
+  };
+  let (label, other) = match file {
+    Some(file) => (
+      file.to_string(),
+      parse_env(
+        dotenvy::from_path_iter(file).with_context(|| format!("failed to read {}", file))?,
+      )?,
+    ),
+    None => {
+      let path = build_env_file(app);
+      let other = if path.exists() {
+        load_env_file_contents(&path)?
+      } else {
+        HashMap::new()
+      };
+      (path.display().to_string(), other)
+    }
+  };
+
+  let diffs = diff_env_maps(&runtime, &other);
+  if diffs.is_empty() {
+    ok(&format!("{} and {} match", runtime_path.display(), label));
+    return Ok(());
+  }
+  println!("--- {}", runtime_path.display());
+  println!("+++ {}", label);
+  for (key, diff) in &diffs {
+    match diff {
+      EnvDiff::Missing => println!("- {}", key),
+      EnvDiff::Extra => println!("+ {}", key),
+      EnvDiff::Changed(left, right) => {
+        println!("~ {} (*** {} chars vs *** {} chars)", key, left, right)
+      }
+    }
+  }
+  anyhow::bail!("{} difference(s)", diffs.len())
+}
+
+/// Keys that differ between the runtime env and another env, sorted by key.
+fn diff_env_maps(
+  runtime: &HashMap<String, String>,
+  other: &HashMap<String, String>,
+) -> Vec<(String, EnvDiff)> {
+  let mut keys: Vec<&String> = runtime.keys().chain(other.keys()).collect();
+  keys.sort();
+  keys.dedup();
+  keys
+    .into_iter()
+    .filter_map(|key| {
+      let diff = match (runtime.get(key), other.get(key)) {
+        (Some(_), None) => EnvDiff::Missing,
+        (None, Some(_)) => EnvDiff::Extra,
+        (Some(a), Some(b)) if a != b => EnvDiff::Changed(a.chars().count(), b.chars().count()),
+        _ => return None,
+      };
+      Some((key.clone(), diff))
+    })
+    .collect()
+}
+
+async fn list_env(app: &str, build: bool) -> Result<()> {
+  let file_path = if build {
+    build_env_file(app)
+  } else {
+    env_file(app)
+  };
+  let text = fs::read_to_string(&file_path).await.unwrap_or_default();
+
+  let keys: Vec<String> = text
+    .lines()
+    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    .filter_map(|line| match line.find('=') {
+      Some(pos) if pos > 0 => Some(line[..pos].to_string()),
+      _ => None,
+    })
+    .collect();
+  emit(&keys, |keys| {
+    for key in keys {
+      println!("{}=***", key);
+    }
+  })
+}
+
+async fn restore_env(app: &str, sha: &str) -> Result<()> {
+  let (manifest, release) = find_release(app, sha)?.with_context(|| {
+    format!(
+      "no release matching {} in {}",
+      sha,
+      releases_dir(app).display()
+    )
+  })?;
+  let snapshot = env_snapshot_path(&manifest);
+  if !snapshot.exists() {
+    anyhow::bail!(
+      "release {} has no .env snapshot (it had no .env when it was deployed)",
+      release.short_sha()
+    );
+  }
+
+  let content = open_snapshot(&fs::read(&snapshot).await?)
+    .with_context(|| format!("failed to open {}", snapshot.display()))?;
+  if release.env_sha256.as_deref() != Some(env_digest(&content).as_str()) {
+    anyhow::bail!(
+      "{} does not match the hash recorded for release {}; refusing to restore it",
+      snapshot.display(),
+      release.short_sha()
+    );
+  }
+
+  let file_path = env_file(app);
+  fs::write(&file_path, &content).await?;
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(&file_path, std::fs::Permissions::from_mode(0o600))?;
+  }
+
+  ok(&format!(
+    "restored {} from release {} (deployed {})",
+    file_path.display(),
+    release.short_sha(),
+    format_rfc3339(release.deployed_at)
+  ));
+  log("restart the app (hl restart) for processes to pick it up");
+  Ok(())
+}
+
+fn previous_key(key: &str) -> String {
+  format!("{}_PREVIOUS", key)
+}
+
+/// Move the current value of `key` to KEY_PREVIOUS and set `value` in its place.
+fn rotated_env(
+  mut map: HashMap<String, String>,
+  key: &str,
+  value: String,
+) -> Result<HashMap<String, String>> {
+  let old = map
+    .insert(key.to_string(), value)
+    .with_context(|| format!("{} is not set; use `hl env set {}=...` first", key, key))?;
+  map.insert(previous_key(key), old);
+  Ok(map)
+}
+
+async fn write_private_env(path: &Path, map: &HashMap<String, String>) -> Result<()> {
+  write_env_file_contents(path, map).await?;
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+  }
+  Ok(())
+}
+
+async fn rotate_env(app: &str, key: &str, generator: &str, grace_days: u64) -> Result<()> {
+  let cfg = load_config(app).await?;
+  let file_path = env_file(app);
+  let original = fs::read(&file_path)
+    .await
+    .with_context(|| format!("failed to read {}", file_path.display()))?;
+  let map = rotated_env(
+    load_env_file_contents(&file_path)?,
+    key,
+    generate_secret(generator)?,
+  )?;
+  write_private_env(&file_path, &map).await?;
+  log(&format!(
+    "rotated {} (old value kept as {})",
+    key,
+    previous_key(key)
+  ));
+
+  log(&format!("restarting {} with the new value...", app));
+  let restarted = match restart_app_target(app).await {
+    Ok(()) => wait_for_healthy(&cfg).await,
+    Err(e) => Err(e),
+  };
+  if let Err(e) = restarted {
+    err(&format!(
+      "{} is unhealthy after rotating {}: {:#}",
+      app, key, e
+    ));
+    fs::write(&file_path, &original).await?;
+    restart_app_target(app)
+      .await
+      .context("failed to restart with the previous .env")?;
+    anyhow::bail!("rotation of {} was reverted; {} is unchanged", key, key);
+  }
+
+  let cleanup_at = unix_now() + grace_days * 86_400;
+  schedule_env_cleanup(app, key, cleanup_at).await?;
+  ok(&format!(
+    "{} rotated; {} will be removed at {}",
+    key,
+    previous_key(key),
+    format_rfc3339(cleanup_at)
+  ));
+  Ok(())
+}
+
+async fn finish_rotation(app: &str, key: &str) -> Result<()> {
+  let file_path = env_file(app);
+  let mut map = load_env_file_contents(&file_path)?;
+  let previous = previous_key(key);
+  if map.remove(&previous).is_some() {
+    write_private_env(&file_path, &map).await?;
+    log(&format!("removed {}, restarting {}...", previous, app));
+    restart_app_target(app).await?;
+  } else {
+    debug(&format!("{} is already gone", previous));
+  }
+  cancel_env_cleanup(app, key).await?;
+  ok(&format!("rotation of {} finished", key));
   Ok(())
 }
 
@@ -300,4 +940,167 @@ mod tests {
 
     Ok(())
   }
+
+  #[tokio::test]
+  #[serial]
+  async fn test_remove_env_keeps_other_keys_and_permissions() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let app_name = "testapp";
+    std::env::set_var("HL_ROOT_OVERRIDE", temp_dir.path().to_str().unwrap());
+
+    set_env(
+      app_name,
+      vec!["A=1".to_string(), "B=2".to_string(), "C=3".to_string()],
+      false,
+    )
+    .await?;
+    remove_env(app_name, &["A".to_string(), "MISSING".to_string()], false).await?;
+
+    let file_path = env_file(app_name);
+    assert_eq!(fs::read_to_string(&file_path).await?, "B=2\nC=3\n");
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      let mode = std::fs::metadata(&file_path)?.permissions().mode();
+      assert_eq!(mode & 0o777, 0o600);
+    }
+
+    assert!(remove_env(app_name, &["A".to_string()], true)
+      .await
+      .is_err());
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+    Ok(())
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn test_push_env_merges_or_replaces() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let app_name = "testapp";
+    std::env::set_var("HL_ROOT_OVERRIDE", temp_dir.path().to_str().unwrap());
+
+    set_env(
+      app_name,
+      vec!["KEEP=1".to_string(), "A=old".to_string()],
+      false,
+    )
+    .await?;
+    let source = temp_dir.path().join(".env.production");
+    fs::write(&source, "# production\nA=new\nB=\"quoted value\"\n").await?;
+
+    push_env(app_name, source.to_str().unwrap(), false, false).await?;
+    assert_eq!(
+      fs::read_to_string(env_file(app_name)).await?,
+      "A=new\nB='quoted value'\nKEEP=1\n"
+    );
+    assert_eq!(
+      load_env_file_contents(&env_file(app_name))?["B"],
+      "quoted value"
+    );
+
+    push_env(app_name, source.to_str().unwrap(), true, false).await?;
+    assert_eq!(
+      fs::read_to_string(env_file(app_name)).await?,
+      "A=new\nB='quoted value'\n"
+    );
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+    Ok(())
+  }
+
+  #[test]
+  fn test_validate_env_content() {
+    assert_eq!(
+      validate_env_content("# comment\nA=1\n\nB='two words'\n").unwrap(),
+      2
+    );
+    assert!(validate_env_content("A=\"unterminated\n").is_err());
+    assert!(validate_env_content("1BAD=x\n").is_err());
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn test_lookup_env() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let app_name = "testapp";
+    std::env::set_var("HL_ROOT_OVERRIDE", temp_dir.path().to_str().unwrap());
+
+    set_env(app_name, vec!["API_KEY=secret123".to_string()], false).await?;
+    assert_eq!(lookup_env(&env_file(app_name), "API_KEY")?, "secret123");
+    assert!(lookup_env(&env_file(app_name), "MISSING").is_err());
+    assert!(lookup_env(&build_env_file(app_name), "API_KEY").is_err());
+    assert_eq!(
+      mask_value("secret123"),
+      "*** (9 chars, use --show to reveal)"
+    );
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+    Ok(())
+  }
+
+  #[test]
+  fn test_diff_env_maps() {
+    let runtime = HashMap::from([
+      ("SAME".to_string(), "1".to_string()),
+      ("CHANGED".to_string(), "abc".to_string()),
+      ("ONLY_RUNTIME".to_string(), "x".to_string()),
+    ]);
+    let other = HashMap::from([
+      ("SAME".to_string(), "1".to_string()),
+      ("CHANGED".to_string(), "abcde".to_string()),
+      ("ONLY_LOCAL".to_string(), "y".to_string()),
+    ]);
+    assert_eq!(
+      diff_env_maps(&runtime, &other),
+      vec![
+        ("CHANGED".to_string(), EnvDiff::Changed(3, 5)),
+        ("ONLY_LOCAL".to_string(), EnvDiff::Extra),
+        ("ONLY_RUNTIME".to_string(), EnvDiff::Missing),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_rotated_env() {
+    let map = HashMap::from([("SECRET".to_string(), "old".to_string())]);
+    let rotated = rotated_env(map, "SECRET", "new".to_string()).unwrap();
+    assert_eq!(rotated["SECRET"], "new");
+    assert_eq!(rotated["SECRET_PREVIOUS"], "old");
+
+    assert!(rotated_env(HashMap::new(), "SECRET", "new".to_string()).is_err());
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn test_restore_env_from_release() -> Result<()> {
+    use hl::releases::{record_release, Release};
+
+    let temp_dir = TempDir::new()?;
+    let app_name = "testapp";
+    std::env::set_var("HL_ROOT_OVERRIDE", temp_dir.path().to_str().unwrap());
+
+    set_env(app_name, vec!["KEY=old".to_string()], false).await?;
+    record_release(
+      app_name,
+      Release {
+        sha: "abcdef0123".to_string(),
+        branch: Some("master".to_string()),
+        tag: None,
+        images: vec![],
+        deployed_at: 100,
+        env_sha256: None,
+      },
+    )?;
+    set_env(app_name, vec!["KEY=new".to_string()], false).await?;
+
+    restore_env(app_name, "abcdef0").await?;
+    let content = fs::read_to_string(env_file(app_name)).await?;
+    assert_eq!(content, "KEY=old\n");
+
+    assert!(restore_env(app_name, "1234567").await.is_err());
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+    Ok(())
+  }
 }
@@ -0,0 +1,86 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  config::{env_app_name, events_file, validate_env_name},
+  events::load_events,
+  log::*,
+  time::format_rfc3339,
+};
+
+#[derive(Args)]
+pub struct EventsArgs {
+  /// Only show events of this app (default: every app on the host)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Only show events of an environment (e.g. staging) of --app
+  #[arg(long, requires = "app")]
+  pub env: Option<String>,
+
+  /// Only show this action, e.g. deploy or "env set"
+  #[arg(long)]
+  pub action: Option<String>,
+
+  /// Number of most recent events to show
+  #[arg(short = 'n', long, default_value_t = 50)]
+  pub tail: usize,
+}
+
+pub async fn execute(args: EventsArgs) -> Result<()> {
+  if let Some(env) = &args.env {
+    validate_env_name(env)?;
+  }
+  let app = args
+    .app
+    .as_deref()
+    .map(|app| env_app_name(app, args.env.as_deref()));
+
+  let events: Vec<_> = load_events(&events_file())?
+    .into_iter()
+    .filter(|e| app.as_ref().is_none_or(|app| &e.app == app))
+    .filter(|e| args.action.as_ref().is_none_or(|a| &e.action == a))
+    .collect();
+  if events.is_empty() {
+    log(&format!("no events in {}", events_file().display()));
+    return Ok(());
+  }
+
+  let events = &events[events.len().saturating_sub(args.tail)..];
+  let w_app = events.iter().map(|e| e.app.len()).max().unwrap_or(0).max(3);
+  let w_action = events
+    .iter()
+    .map(|e| e.action.len())
+    .max()
+    .unwrap_or(0)
+    .max(6);
+  let w_user = events
+    .iter()
+    .map(|e| e.user.len())
+    .max()
+    .unwrap_or(0)
+    .max(4);
+  println!(
+    "{:<20}  {:<w_app$}  {:<w_action$}  {:<w_user$}  {:<6}  ARGS",
+    "TIME", "APP", "ACTION", "USER", "RESULT"
+  );
+  for e in events {
+    let outcome = match e.ok {
+      Some(true) => "ok",
+      Some(false) => "failed",
+      None => "-",
+    };
+    println!(
+      "{:<20}  {:<w_app$}  {:<w_action$}  {:<w_user$}  {:<6}  {}",
+      format_rfc3339(e.at),
+      e.app,
+      e.action,
+      e.user,
+      outcome,
+      e.args.join(" ")
+    );
+    if let Some(error) = &e.error {
+      println!("  {}", error);
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,71 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  config::{app_dir, env_app_name, validate_env_name},
+  docker::compose_target,
+  git::infer_app_name,
+  log::*,
+};
+use std::io::IsTerminal;
+use std::process::Stdio;
+use tokio::process::Command;
+
+#[derive(Args)]
+pub struct ExecArgs {
+  /// App to run in (default: HL_APP)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Run in an environment (e.g. staging) of the app
+  #[arg(long)]
+  pub env: Option<String>,
+
+  /// Process (web, worker, ...) or accessory (postgres, redis) to run in
+  #[arg(long, short, default_value = "web")]
+  pub service: String,
+
+  /// Command to run (default: sh)
+  #[arg(last = true)]
+  pub command: Vec<String>,
+}
+
+pub async fn execute(args: ExecArgs) -> Result<()> {
+  if let Some(env) = &args.env {
+    validate_env_name(env)?;
+  }
+  let app = match &args.app {
+    Some(app) => app.clone(),
+    None => infer_app_name().await?,
+  };
+  let app = env_app_name(&app, args.env.as_deref());
+  let target = compose_target(&app, &args.service)?;
+
+  let mut docker_args = target.compose_args();
+  docker_args.push("exec".to_string());
+  // compose allocates a TTY by default, which fails when input is piped
+  if !std::io::stdin().is_terminal() {
+    docker_args.push("-T".to_string());
+  }
+  docker_args.push(target.service.clone());
+  if args.command.is_empty() {
+    docker_args.push("sh".to_string());
+  } else {
+    docker_args.extend(args.command.iter().cloned());
+  }
+
+  debug(&format!("executing: docker {}", docker_args.join(" ")));
+  let status = Command::new("docker")
+    .args(&docker_args)
+    .current_dir(app_dir(&app))
+    .stdin(Stdio::inherit())
+    .stdout(Stdio::inherit())
+    .stderr(Stdio::inherit())
+    .status()
+    .await?;
+
+  // Pass the command's exit code through, like docker compose exec does
+  if !status.success() {
+    std::process::exit(status.code().unwrap_or(1));
+  }
+  Ok(())
+}
@@ -0,0 +1,76 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use hl::{
+  config::{app_dir, env_app_name, load_config, systemd_dir, validate_env_name},
+  discovery::{discover_accessories, discover_processes},
+  docker::{compose_config, compose_file_args, pinned_tag},
+  git::infer_app_name,
+  log::*,
+};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct ExportArgs {
+  /// App name (default: HL_APP)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Environment (e.g. staging) of the app
+  #[arg(long)]
+  pub env: Option<String>,
+
+  /// Write the stack to this file (mode 600) instead of printing it
+  #[arg(long, value_name = "FILE")]
+  pub out: Option<PathBuf>,
+}
+
+pub async fn execute(args: ExportArgs) -> Result<()> {
+  let app = match args.app {
+    Some(app) => app,
+    None => infer_app_name().await?,
+  };
+  if let Some(env) = &args.env {
+    validate_env_name(env)?;
+  }
+  let app = env_app_name(&app, args.env.as_deref());
+  let cfg = load_config(&app).await?;
+  let dir = app_dir(&app);
+  let units_dir = systemd_dir();
+  let processes = discover_processes(&units_dir, &app)?;
+  let accessories = discover_accessories(&units_dir, &dir, &app, &processes)?;
+
+  let mut compose_args = compose_file_args(&dir, &processes, &accessories);
+  compose_args.extend(["-p".to_string(), app.clone()]);
+  let stack = compose_config(&dir, &compose_args).await?;
+  let release = pinned_tag(&dir, &cfg.image).unwrap_or_else(|| "latest".to_string());
+  let content = format!(
+    "# {} (release {}) exported by hl from {}: processes {}{}.\n\
+     # Resolved from .env, so it contains the app's secrets.\n{}",
+    app,
+    release,
+    dir.display(),
+    processes.join(", "),
+    if accessories.is_empty() {
+      String::new()
+    } else {
+      format!(", accessories {}", accessories.join(", "))
+    },
+    stack
+  );
+
+  match args.out {
+    Some(path) => {
+      std::fs::write(&path, &content)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+      std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+      ok(&format!(
+        "exported {} to {} (chmod 600)",
+        app,
+        path.display()
+      ));
+    }
+    None => print!("{}", content),
+  }
+  Ok(())
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use hl::{
+  config::{deploy_home, hl_git_root},
+  git::{list_bare_repos, post_receive_hook_is_current, write_post_receive_hook},
+  log::*,
+};
+
+#[derive(Args)]
+pub struct HooksArgs {
+  #[command(subcommand)]
+  pub command: HooksCommands,
+}
+
+#[derive(Subcommand)]
+pub enum HooksCommands {
+  /// Rewrite the post-receive hooks of the bare repos from the current template
+  Sync {
+    /// Only this app's repo (default: every repo on this host)
+    #[arg(long)]
+    app: Option<String>,
+  },
+}
+
+pub async fn execute(args: HooksArgs) -> Result<()> {
+  match args.command {
+    HooksCommands::Sync { app } => sync(app).await,
+  }
+}
+
+async fn sync(app: Option<String>) -> Result<()> {
+  let apps = match app {
+    Some(app) => {
+      if !hl_git_root(&app).exists() {
+        anyhow::bail!(
+          "{} has no git repository at {}",
+          app,
+          hl_git_root(&app).display()
+        );
+      }
+      vec![app]
+    }
+    None => list_bare_repos()?,
+  };
+  if apps.is_empty() {
+    log("no git repositories on this host");
+    return Ok(());
+  }
+
+  let home = deploy_home().to_string_lossy().to_string();
+  let mut updated = 0;
+  for app in &apps {
+    let git_dir = hl_git_root(app);
+    if post_receive_hook_is_current(&git_dir, app, &home) {
+      debug(&format!("{}: post-receive hook is current", app));
+      continue;
+    }
+    write_post_receive_hook(&git_dir, app, &home).await?;
+    log(&format!("{}: post-receive hook updated", app));
+    updated += 1;
+  }
+  ok(&format!(
+    "{} of {} hook(s) updated, the rest were current",
+    updated,
+    apps.len()
+  ));
+  Ok(())
+}
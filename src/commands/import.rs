@@ -0,0 +1,268 @@
+use crate::commands::init::{init_app, InitArgs, Preset};
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use hl::config::{env_file, systemd_dir};
+use hl::env::{load_env_file_contents, write_env_file_contents};
+use hl::heroku_import::read_heroku_import;
+use hl::kamal_import::read_kamal_import;
+use hl::log::*;
+use hl::output::from_json;
+use hl::state::update_state;
+use hl::units_spec_builder::scale_env_var;
+use hl::Error;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct ImportArgs {
+  #[command(subcommand)]
+  pub source: ImportSource,
+}
+
+#[derive(Subcommand)]
+pub enum ImportSource {
+  /// Create an app from a Kamal deploy.yml: service, image, proxy host and port,
+  /// healthcheck, env and postgres/redis accessories
+  Kamal(KamalArgs),
+  /// Create an app from a Heroku app.json and Procfile: env, postgres/redis add-ons,
+  /// formation scale and the release command as migrations
+  Heroku(HerokuArgs),
+}
+
+#[derive(Args)]
+pub struct KamalArgs {
+  /// Kamal config to import
+  #[arg(default_value = "config/deploy.yml")]
+  pub config: PathBuf,
+
+  /// Application name (default: the Kamal service)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Domain name (default: proxy.host)
+  #[arg(long)]
+  pub domain: Option<String>,
+
+  /// Framework the generated hl.yml is tailored to. Defaults to the one detected in the
+  /// current directory, else rails
+  #[arg(long, value_enum)]
+  pub preset: Option<Preset>,
+
+  /// Traefik network name. Defaults to the global config's, then "traefik_proxy"
+  #[arg(long)]
+  pub network: Option<String>,
+
+  /// ACME resolver name. Defaults to the global config's, then "myresolver"
+  #[arg(long)]
+  pub resolver: Option<String>,
+}
+
+#[derive(Args)]
+pub struct HerokuArgs {
+  /// Project directory with app.json and the Procfile
+  #[arg(default_value = ".")]
+  pub dir: PathBuf,
+
+  /// Application name (default: app.json name)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Domain name
+  #[arg(long)]
+  pub domain: String,
+
+  /// Docker image reference. Defaults to <registry>/<app> when the global config sets a
+  /// registry
+  #[arg(long)]
+  pub image: Option<String>,
+
+  /// Port the app listens on, passed to it as PORT like Heroku does. Defaults to the
+  /// preset's
+  #[arg(long)]
+  pub port: Option<u16>,
+
+  /// Framework the generated hl.yml is tailored to. Defaults to the one detected in the
+  /// project directory, else rails
+  #[arg(long, value_enum)]
+  pub preset: Option<Preset>,
+
+  /// Traefik network name. Defaults to the global config's, then "traefik_proxy"
+  #[arg(long)]
+  pub network: Option<String>,
+
+  /// ACME resolver name. Defaults to the global config's, then "myresolver"
+  #[arg(long)]
+  pub resolver: Option<String>,
+}
+
+pub async fn execute(args: ImportArgs) -> Result<()> {
+  match args.source {
+    ImportSource::Kamal(args) => import_kamal(args).await,
+    ImportSource::Heroku(args) => import_heroku(args).await,
+  }
+}
+
+async fn import_kamal(args: KamalArgs) -> Result<()> {
+  let kamal = read_kamal_import(&args.config, args.app.as_deref())?;
+  let app = args.app.unwrap_or_else(|| kamal.service.clone());
+  let Some(domain) = args.domain.or(kamal.domain) else {
+    return Err(
+      Error::Config(anyhow::anyhow!(
+        "{} has no proxy host; pass --domain",
+        args.config.display()
+      ))
+      .into(),
+    );
+  };
+  log(&format!(
+    "importing Kamal service {} from {}",
+    kamal.service,
+    args.config.display()
+  ));
+  if kamal.hosts.len() > 1 {
+    warn(&format!(
+      "{} deploys to {} hosts ({}); hl runs the app on this one",
+      args.config.display(),
+      kamal.hosts.len(),
+      kamal.hosts.join(", ")
+    ));
+  }
+
+  let opts = InitArgs {
+    app: app.clone(),
+    image: kamal.import.image.clone(),
+    domain: Some(domain),
+    port: None,
+    preset: args.preset,
+    network: args.network,
+    resolver: args.resolver,
+    env: None,
+    from_compose: None,
+    repo: None,
+    dockerfile: false,
+    update: false,
+    subnet: None,
+    yes: false,
+  };
+  init_app(opts, Some(kamal.import)).await?;
+
+  if !kamal.roles.is_empty() {
+    log("hl runs processes from the repository's Procfile; add the other Kamal roles to it:");
+    for (role, cmd) in &kamal.roles {
+      log(&format!("  {}: {}", role, cmd));
+    }
+  }
+  if !kamal.unresolved_secrets.is_empty() {
+    warn(&format!(
+      "set the secrets Kamal resolves at deploy time: HL_APP={} hl env set {}",
+      app,
+      kamal
+        .unresolved_secrets
+        .iter()
+        .map(|key| format!("{}=...", key))
+        .collect::<Vec<_>>()
+        .join(" ")
+    ));
+  }
+  Ok(())
+}
+
+async fn import_heroku(args: HerokuArgs) -> Result<()> {
+  let Some(app) = args.app.clone().or_else(|| {
+    let path = args.dir.join("app.json");
+    let content = std::fs::read_to_string(path).ok()?;
+    from_json::<serde_yaml::Value>(&content)
+      .ok()?
+      .get("name")?
+      .as_str()
+      .map(str::to_string)
+  }) else {
+    return Err(Error::Config(anyhow::anyhow!("app.json has no name; pass --app")).into());
+  };
+  let heroku = read_heroku_import(&args.dir, &app)?;
+  log(&format!(
+    "importing Heroku app {} from {}",
+    heroku.name.as_deref().unwrap_or(&app),
+    args.dir.display()
+  ));
+
+  let opts = InitArgs {
+    app: app.clone(),
+    image: args.image,
+    domain: Some(args.domain),
+    port: args.port,
+    preset: args.preset,
+    network: args.network,
+    resolver: args.resolver,
+    env: None,
+    from_compose: None,
+    repo: Some(args.dir.clone()),
+    dockerfile: false,
+    update: false,
+    subnet: None,
+    yes: false,
+  };
+  let has_release = heroku.import.migrations.is_some();
+  init_app(opts, Some(heroku.import)).await?;
+
+  // Heroku tells processes their port in PORT, and Procfile commands often pass it on
+  let env_path = env_file(&app);
+  let mut env = load_env_file_contents(&env_path)?;
+  if let Some(port) = env.get("SERVICE_PORT").cloned() {
+    env.entry("PORT".to_string()).or_insert(port);
+  }
+  let mut scale = Vec::new();
+  for (process, count) in &heroku.scale {
+    if !heroku.processes.contains(process) {
+      warn(&format!(
+        "formation {} has no Procfile process; not scaled",
+        process
+      ));
+    } else if process == "web" {
+      if *count > 1 {
+        warn(&format!(
+          "web runs one container on hl (formation has {})",
+          count
+        ));
+      }
+    } else {
+      env.insert(scale_env_var(process), count.to_string());
+      scale.push((process.clone(), *count));
+    }
+  }
+  write_env_file_contents(&env_path, &env).await?;
+  update_state(&app, &systemd_dir(), |state| {
+    state.scale.extend(scale.iter().cloned())
+  })?;
+  for (process, count) in &scale {
+    ok(&format!("{} will run {} container(s)", process, count));
+  }
+
+  if has_release {
+    warn("the Procfile's release command is now hl.yml's migrations; remove it from the Procfile so deploys don't also run it as a process");
+  }
+  for addon in &heroku.skipped_addons {
+    warn(&format!(
+      "add-on {} not imported; hl has no accessory for it",
+      addon
+    ));
+  }
+  if !heroku.required_env.is_empty() {
+    warn(&format!(
+      "set the variables app.json requires: HL_APP={} hl env set {}",
+      app,
+      heroku
+        .required_env
+        .iter()
+        .map(|key| format!("{}=...", key))
+        .collect::<Vec<_>>()
+        .join(" ")
+    ));
+  }
+  if let Some(script) = &heroku.postdeploy {
+    log(&format!(
+      "Heroku ran a postdeploy script; after the first deploy run it with:\n  HL_APP={} hl run -- {}",
+      app, script
+    ));
+  }
+  Ok(())
+}
@@ -0,0 +1,212 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  config::{app_dir, env_app_name, hl_git_root, load_config, systemd_dir, validate_env_name},
+  discovery::{discover_accessories, discover_processes},
+  docker::{compose_service_values, list_image_tags, pinned_tag},
+  git::{infer_app_name, repo_remote_uri},
+  log::*,
+  output::emit,
+  releases::{correlate_image_tags, load_releases, releases_dir},
+  systemd::user_unit_active_state,
+  time::format_rfc3339,
+};
+use serde::Serialize;
+
+#[derive(Args)]
+pub struct InfoArgs {
+  /// App name (default: HL_APP)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Environment (e.g. staging) of the app
+  #[arg(long)]
+  pub env: Option<String>,
+}
+
+/// Everything worth pasting into a ticket about an app.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AppInfo {
+  app: String,
+  domains: Vec<String>,
+  image: String,
+  /// Release :latest points at, or the most recent deploy when that can't be told
+  sha: Option<String>,
+  branch: Option<String>,
+  deployed_at: Option<String>,
+  state: String,
+  processes: Vec<ProcessInfo>,
+  accessories: Vec<AccessoryInfo>,
+  health_url: String,
+  git_remote: String,
+}
+
+#[derive(Serialize)]
+struct ProcessInfo {
+  name: String,
+  /// Command from the process overlay; none runs the image's CMD
+  command: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AccessoryInfo {
+  name: String,
+  image: Option<String>,
+}
+
+pub async fn execute(args: InfoArgs) -> Result<()> {
+  let app = match args.app {
+    Some(app) => app,
+    None => infer_app_name().await?,
+  };
+  if let Some(env) = &args.env {
+    validate_env_name(env)?;
+  }
+  let app = env_app_name(&app, args.env.as_deref());
+  let info = app_info(&app).await?;
+  emit(&info, print_info)
+}
+
+async fn app_info(app: &str) -> Result<AppInfo> {
+  let cfg = load_config(app).await?;
+  let dir = app_dir(app);
+  let units_dir = systemd_dir();
+  let process_names = discover_processes(&units_dir, app).unwrap_or_default();
+  let accessory_names =
+    discover_accessories(&units_dir, &dir, app, &process_names).unwrap_or_default();
+
+  let processes = process_names
+    .iter()
+    .map(|name| ProcessInfo {
+      name: name.clone(),
+      command: overlay_value(&dir, name, "command").map(|command| match command {
+        serde_yaml::Value::Sequence(words) => shell_words::join(words.iter().map(yaml_scalar)),
+        other => yaml_scalar(&other),
+      }),
+    })
+    .collect();
+  let accessories = accessory_names
+    .iter()
+    .map(|name| AccessoryInfo {
+      name: name.clone(),
+      image: overlay_value(&dir, name, "image").map(|image| yaml_scalar(&image)),
+    })
+    .collect();
+
+  let releases = load_releases(&releases_dir(app)).unwrap_or_default();
+  // compose.yml names the release processes run; apps never pinned run :latest, and
+  // rollbacks move that without recording a release, so ask docker which one it is
+  let live_sha = match pinned_tag(&dir, &cfg.image).filter(|tag| tag != "latest") {
+    Some(tag) => Some(tag),
+    None => match list_image_tags(&cfg.image).await {
+      Ok(listing) => correlate_image_tags(&listing, &releases)
+        .into_iter()
+        .find(|image| image.latest)
+        .and_then(|image| image.sha),
+      Err(e) => {
+        debug(&format!("listing image tags: {:#}", e));
+        None
+      }
+    },
+  };
+  let release = match &live_sha {
+    Some(sha) => releases
+      .iter()
+      .find(|(_, r)| r.sha.starts_with(sha.as_str())),
+    None => releases.first(),
+  }
+  .map(|(_, release)| release);
+
+  let state = if process_names.is_empty() {
+    "not deployed".to_string()
+  } else {
+    user_unit_active_state(&format!("app-{}.target", app))
+      .await
+      .unwrap_or_else(|_| "unknown".to_string())
+  };
+
+  let mut domains = vec![cfg.domain.clone()];
+  domains.extend(cfg.domains.iter().cloned());
+  Ok(AppInfo {
+    app: app.to_string(),
+    domains,
+    image: cfg.image.clone(),
+    sha: live_sha.or_else(|| release.map(|r| r.short_sha().to_string())),
+    branch: release.and_then(|r| r.branch.clone().or_else(|| r.tag.clone())),
+    deployed_at: release.map(|r| format_rfc3339(r.deployed_at)),
+    state,
+    processes,
+    accessories,
+    health_url: cfg.health.url.clone(),
+    git_remote: repo_remote_uri(&hl_git_root(app).to_string_lossy()),
+  })
+}
+
+/// `key` of the first service in `compose.<name>.yml` that sets it.
+fn overlay_value(dir: &std::path::Path, name: &str, key: &str) -> Option<serde_yaml::Value> {
+  let content = std::fs::read_to_string(dir.join(format!("compose.{}.yml", name))).ok()?;
+  compose_service_values(&content, key)
+    .ok()?
+    .into_iter()
+    .next()
+    .map(|(_, value)| value)
+}
+
+fn yaml_scalar(value: &serde_yaml::Value) -> String {
+  match value {
+    serde_yaml::Value::String(s) => s.clone(),
+    other => serde_yaml::to_string(other)
+      .unwrap_or_default()
+      .trim()
+      .to_string(),
+  }
+}
+
+fn print_info(info: &AppInfo) {
+  let or_dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+  println!("app:          {}", info.app);
+  println!("domains:      {}", info.domains.join(", "));
+  println!("image:        {}", info.image);
+  match (&info.sha, &info.branch) {
+    (Some(sha), Some(branch)) => println!(
+      "sha:          {} ({}, deployed {})",
+      sha,
+      branch,
+      or_dash(&info.deployed_at)
+    ),
+    (sha, _) => println!("sha:          {}", or_dash(sha)),
+  }
+  println!("state:        {}", info.state);
+  println!("health:       {}", info.health_url);
+  println!("git remote:   {}", info.git_remote);
+
+  println!("processes:");
+  let width = info
+    .processes
+    .iter()
+    .map(|p| p.name.len())
+    .max()
+    .unwrap_or(0);
+  for p in &info.processes {
+    let command = p.command.as_deref().unwrap_or("(image default)");
+    println!("  {:<width$}  {}", p.name, command);
+  }
+  if info.processes.is_empty() {
+    println!("  -");
+  }
+
+  println!("accessories:");
+  let width = info
+    .accessories
+    .iter()
+    .map(|a| a.name.len())
+    .max()
+    .unwrap_or(0);
+  for a in &info.accessories {
+    println!("  {:<width$}  {}", a.name, or_dash(&a.image));
+  }
+  if info.accessories.is_empty() {
+    println!("  -");
+  }
+}
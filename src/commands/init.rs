@@ -1,10 +1,27 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Args;
-use hl::config::{hl_git_root, home_dir};
-use hl::docker::{write_base_compose_file, write_process_compose_files};
+use hl::accessories::{AccessoryManager, AccessoryOptions};
+use hl::compose_import::{parse_compose_import, ComposeImport};
+use hl::config::{
+  deploy_home, env_app_name, env_overlay_path, global_config_path, hl_git_root, load_config,
+  load_global_config, resolve_config, systemd_dir, validate_env_name, GlobalConfig, SystemdMode,
+};
+use hl::discovery::{discover_accessories, discover_processes};
+use hl::docker::{
+  pinned_tag, render_base_compose, write_base_compose_file, write_process_compose_files, WebRouter,
+};
+use hl::dockerfile::parse_dockerfile;
+use hl::doctor::{host_boot_checks, print_report};
+use hl::env::{load_env_file_contents, quote_env_value, write_env_file_contents};
 use hl::git::{init_bare_repo, repo_remote_uri};
+use hl::procfile::parse_procfile_content;
+use hl::textdiff::format_diff;
+use hl::units_spec_builder::{render_units, UnitsSpec};
 use hl::{config::app_dir, log::*, systemd::write_unit};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 
 #[derive(Args)]
@@ -13,29 +30,151 @@ pub struct InitArgs {
   #[arg(long)]
   pub app: String,
 
-  /// Docker image reference
+  /// Docker image reference. Defaults to <registry>/<app> when the global config sets a
+  /// registry
   #[arg(long)]
-  pub image: String,
+  pub image: Option<String>,
 
   /// Domain name
+  #[arg(long, required_unless_present = "update")]
+  pub domain: Option<String>,
+
+  /// Internal container port. Defaults to the preset's (3000 for rails)
+  #[arg(long)]
+  pub port: Option<u16>,
+
+  /// Framework the generated hl.yml is tailored to (migrations, health path, secrets, port).
+  /// Defaults to the one detected in the project directory, else rails
+  #[arg(long, value_enum)]
+  pub preset: Option<Preset>,
+
+  /// Traefik network name. Defaults to the global config's, then "traefik_proxy"
+  #[arg(long)]
+  pub network: Option<String>,
+
+  /// ACME resolver name. Defaults to the global config's, then "myresolver"
+  #[arg(long)]
+  pub resolver: Option<String>,
+
+  /// Add an environment (e.g. staging) of an existing app instead: writes hl.<env>.yml
+  /// and sets it up as <app>-<env>, deployed from the app's repository
+  #[arg(long)]
+  pub env: Option<String>,
+
+  /// Import an existing docker-compose.yml: the web service's image, port and environment,
+  /// and its postgres/redis services as accessories
+  #[arg(long, conflicts_with = "env")]
+  pub from_compose: Option<PathBuf>,
+
+  /// Project directory to prefill hl.yml from (framework, Dockerfile EXPOSE port). Defaults to
+  /// the current directory when it looks like a project
+  #[arg(long)]
+  pub repo: Option<PathBuf>,
+
+  /// Write a starter Dockerfile for the preset into the project directory when it has none,
+  /// without asking
   #[arg(long)]
-  pub domain: String,
+  pub dockerfile: bool,
 
-  /// Internal container port
+  /// Change an initialized app instead: apply the given --image, --domain, --port, --network
+  /// and --resolver to hl.yml and .env, re-render compose.yml and the units, show the diff and
+  /// rewrite only what changed
+  #[arg(long, conflicts_with_all = ["env", "from_compose", "preset", "repo", "dockerfile"])]
+  pub update: bool,
+
+  /// Subnet for the Traefik network when init offers to create it (e.g. 172.30.0.0/16)
   #[arg(long)]
-  pub port: u16,
+  pub subnet: Option<String>,
+
+  /// Apply --update's changes without asking
+  #[arg(long, short = 'y', requires = "update")]
+  pub yes: bool,
+}
 
-  /// Traefik network name. Defaults to "traefik_proxy"
-  #[arg(long, default_value = "traefik_proxy")]
-  pub network: String,
+impl InitArgs {
+  fn preset(&self) -> Preset {
+    self.preset.unwrap_or(Preset::Rails)
+  }
 
-  /// ACME resolver name. Defaults to "myresolver"
-  #[arg(long, default_value = "myresolver")]
-  pub resolver: String,
+  fn domain(&self) -> &str {
+    // Only --update runs without a domain
+    self.domain.as_deref().unwrap_or_default()
+  }
 }
 
 pub async fn execute(opts: InitArgs) -> Result<()> {
-  let dir = app_dir(&opts.app);
+  if opts.update {
+    return update(&opts).await;
+  }
+  let import = match &opts.from_compose {
+    Some(path) => Some(read_compose_import(
+      path,
+      &env_app_name(&opts.app, opts.env.as_deref()),
+    )?),
+    None => None,
+  };
+  init_app(opts, import).await
+}
+
+/// Create the app from `opts`, taking what `import` brings over from an existing setup
+/// (`--from-compose`, `hl import`) where the flags leave it open.
+pub(crate) async fn init_app(mut opts: InitArgs, import: Option<ComposeImport>) -> Result<()> {
+  if let Some(env) = &opts.env {
+    validate_env_name(env)?;
+    if !app_dir(&opts.app).join("hl.yml").exists() {
+      anyhow::bail!(
+        "{} has no hl.yml; initialize the app before adding environments to it",
+        opts.app
+      );
+    }
+  }
+  let app = env_app_name(&opts.app, opts.env.as_deref());
+  let config_path = match &opts.env {
+    Some(env) => env_overlay_path(&opts.app, env),
+    None => app_dir(&app).join("hl.yml"),
+  };
+  if config_path.exists() {
+    anyhow::bail!(
+      "{} is already initialized ({} exists); use --update to change its settings",
+      app,
+      config_path.display()
+    );
+  }
+  if let Some(import) = &import {
+    // Flags win over what the compose file says
+    opts.image = opts.image.take().or_else(|| import.image.clone());
+    opts.port = opts.port.or(import.port);
+  }
+  let repo = match &opts.repo {
+    Some(dir) => Some(detect_repo(dir)?),
+    None => std::env::current_dir()
+      .ok()
+      .filter(|dir| looks_like_project(dir))
+      .map(|dir| detect_repo(&dir))
+      .transpose()?,
+  };
+  if let Some(repo) = &repo {
+    opts.preset = opts.preset.or(repo.preset);
+    opts.port = opts.port.or(repo.port);
+    report_repo(repo, opts.preset());
+  }
+  let mut settings = Settings::resolve(&opts, &app, &load_global_config().await?)?;
+  if let Some(import) = &import {
+    if let Some(path) = &import.health_path {
+      settings.health_path = path.clone();
+    }
+    settings.build_args = import.build_args.clone();
+    settings.migrations = import.migrations.clone();
+  }
+  match &repo {
+    Some(repo) if !repo.dockerfile => offer_dockerfile(&opts, repo, settings.port)?,
+    None if opts.dockerfile => {
+      anyhow::bail!("--dockerfile needs a project directory; run init from it or pass --repo")
+    }
+    _ => {}
+  }
+  crate::commands::doctor::offer_network(&settings.network, opts.subnet.as_deref()).await?;
+  let dir = app_dir(&app);
   fs::create_dir_all(&dir).await?;
 
   let compose_path = dir.join("compose.yml");
@@ -43,12 +182,26 @@ pub async fn execute(opts: InitArgs) -> Result<()> {
   if !Path::new(&env_path).exists() {
     let env_content = format!(
       "APP={}\nDOMAIN={}\nSERVICE_PORT={}\n",
-      opts.app, opts.domain, opts.port
+      app,
+      opts.domain(),
+      settings.port
     );
     fs::write(&env_path, env_content).await?;
   }
+  if let Some(import) = &import {
+    import_env(&env_path, import).await?;
+  }
 
-  write_base_compose_file(&dir, &opts.image, &opts.network, &[]).await?;
+  write_base_compose_file(
+    &dir,
+    &settings.image,
+    &settings.network,
+    &[],
+    &[],
+    SystemdMode::default(),
+    None,
+  )
+  .await?;
   log(&format!(
     "wrote {} and {}",
     compose_path.display(),
@@ -57,17 +210,49 @@ pub async fn execute(opts: InitArgs) -> Result<()> {
 
   // Write a default compose.web.yml (this might be overwritten later upon deploy if a Procfile is present)
   // We need it here so that the init command creates all necessary files and accessories can boot up correctly
-  write_process_compose_files(&dir, None, &opts.app, &opts.resolver).await?;
-  write_config_file(&opts).await?;
-  write_unit(&opts.app, &["web".to_string()], &[]).await?;
+  write_process_compose_files(
+    &dir,
+    None,
+    &app,
+    &settings.resolver,
+    &WebRouter::default(),
+    &Default::default(),
+  )
+  .await?;
+  if let Some(env) = &opts.env {
+    write_env_overlay(&opts, env, &settings).await?;
+  } else {
+    write_config_file(&opts, &settings).await?;
+  }
+  crate::commands::doctor::offer_lingering().await?;
+  write_unit(&app, &["web".to_string()], &[], SystemdMode::default()).await?;
 
   ok(&format!(
     "created app {} (will be enabled on first deploy)",
-    opts.app
+    app
   ));
 
+  if let Some(import) = import {
+    import_accessories(&app, import).await?;
+  }
+
+  // App units only come into play after the first deploy, so just verify host-level boot links
+  log("checking host boot path");
+  if print_report(&host_boot_checks().await) > 0 {
+    warn("apps on this host will not come back after a reboot until the above is fixed");
+  }
+
+  if let Some(env) = &opts.env {
+    // Environments deploy from the app's repository
+    log(&format!(
+      "To deploy a commit to {}:\n  HL_APP={} hl deploy --env {} --sha <sha> --branch <branch>",
+      env, opts.app, env
+    ));
+    return Ok(());
+  }
+
   // Create bare git repository
-  let home = home_dir().to_string_lossy().to_string();
+  let home = deploy_home().to_string_lossy().to_string();
   let git_root = hl_git_root(opts.app.as_str());
   let git_dir = git_root.to_string_lossy().to_string();
 
@@ -84,39 +269,979 @@ pub async fn execute(opts: InitArgs) -> Result<()> {
   Ok(())
 }
 
-async fn write_config_file(opts: &InitArgs) -> Result<()> {
+fn read_compose_import(path: &Path, app: &str) -> Result<ComposeImport> {
+  let content =
+    std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+  let base_dir = path.parent().unwrap_or(Path::new("."));
+  let import = parse_compose_import(&content, base_dir, app)
+    .with_context(|| format!("failed to import {}", path.display()))?;
+  log(&format!(
+    "importing service {} from {}",
+    import.web,
+    path.display()
+  ));
+  Ok(import)
+}
+
+/// Add the web service's environment to `.env`. Values already there (APP, DOMAIN,
+/// SERVICE_PORT) are kept.
+async fn import_env(env_path: &Path, import: &ComposeImport) -> Result<()> {
+  let mut env = load_env_file_contents(env_path)?;
+  let mut imported = 0;
+  for (key, value) in &import.env {
+    if !env.contains_key(key) {
+      env.insert(key.clone(), value.clone());
+      imported += 1;
+    }
+  }
+  write_env_file_contents(env_path, &env).await?;
+  let mut perms = fs::metadata(env_path).await?.permissions();
+  perms.set_mode(0o600);
+  fs::set_permissions(env_path, perms).await?;
+  ok(&format!(
+    "imported {} variable(s) of {} into {} (chmod 600)",
+    imported,
+    import.web,
+    env_path.display()
+  ));
+  Ok(())
+}
+
+/// Set up the compose file's databases as accessories and point out what wasn't imported.
+async fn import_accessories(app: &str, import: ComposeImport) -> Result<()> {
+  for acc in import.accessories {
+    log(&format!("adding {} (service {})", acc.kind, acc.service));
+    let get = |key: &str| acc.env.get(key).cloned();
+    let opts = AccessoryOptions {
+      version: acc.version.clone(),
+      user: get("POSTGRES_USER"),
+      database: get("POSTGRES_DB"),
+      password: get("POSTGRES_PASSWORD"),
+    };
+    AccessoryManager::new(app)
+      .add(acc.kind, opts)
+      .await
+      .with_context(|| {
+        format!(
+          "failed to add {} accessory; retry with: hl accessory add {}",
+          acc.kind, acc.kind
+        )
+      })?;
+    warn(&format!(
+      "{} starts empty; data in the {} service's volume is not migrated",
+      acc.kind, acc.service
+    ));
+  }
+  for service in &import.skipped {
+    warn(&format!(
+      "service {} not imported; add it to the Procfile or as a sidecar",
+      service
+    ));
+  }
+  if !import.volumes.is_empty() {
+    warn(&format!(
+      "volumes of {} not imported; add them to hl.yml volumes: {}",
+      import.web,
+      import.volumes.join(", ")
+    ));
+  }
+  Ok(())
+}
+
+/// A file `--update` would change, with its current and new content.
+struct FileChange {
+  path: PathBuf,
+  old: String,
+  new: String,
+}
+
+/// Re-run init over an existing app: apply the flags to hl.yml and .env, re-render what's
+/// generated from them, and rewrite the files that differ once confirmed.
+async fn update(opts: &InitArgs) -> Result<()> {
+  let app = &opts.app;
+  let dir = app_dir(app);
+  let hl_yml_path = dir.join("hl.yml");
+  if !hl_yml_path.exists() {
+    anyhow::bail!(
+      "{} is not initialized ({} not found)",
+      app,
+      hl_yml_path.display()
+    );
+  }
+  let current = load_config(app).await?;
+  let old_hl_yml = fs::read_to_string(&hl_yml_path).await?;
+  let mut hl_yml = old_hl_yml.clone();
+  if let Some(port) = opts.port {
+    // Keep the health check on the container port
+    hl_yml = hl_yml.replace(
+      &format!("http://{}:{}", app, current.service_port),
+      &format!("http://{}:{}", app, port),
+    );
+  }
+  let port = opts.port.map(|p| p.to_string());
+  for (key, value) in [
+    ("image", &opts.image),
+    ("domain", &opts.domain),
+    ("servicePort", &port),
+    ("network", &opts.network),
+    ("resolver", &opts.resolver),
+  ] {
+    if let Some(value) = value {
+      hl_yml = set_top_level_key(&hl_yml, key, value);
+    }
+  }
+  let cfg = resolve_config(serde_yaml::from_str(&hl_yml)?)
+    .await
+    .context("the updated hl.yml is invalid")?;
+
+  let mut files = vec![(hl_yml_path, old_hl_yml, hl_yml)];
+
+  let env_path = dir.join(".env");
+  let old_env = fs::read_to_string(&env_path).await.unwrap_or_default();
+  let mut env = old_env.clone();
+  if let Some(domain) = &opts.domain {
+    env = set_env_line(&env, "DOMAIN", domain);
+  }
+  if let Some(port) = &port {
+    env = set_env_line(&env, "SERVICE_PORT", port);
+  }
+  files.push((env_path, old_env, env));
+
+  let named_volumes: Vec<String> = cfg.persistence_volume().into_iter().collect();
+  let tag = pinned_tag(&dir, &cfg.image).unwrap_or_else(|| "latest".to_string());
+  let compose = render_base_compose(
+    &cfg.image,
+    &tag,
+    &cfg.network,
+    &cfg.base_volumes(),
+    &named_volumes,
+    cfg.systemd.mode,
+    cfg.logging.as_ref(),
+  );
+  let compose_path = dir.join("compose.yml");
+  let old_compose = fs::read_to_string(&compose_path).await.unwrap_or_default();
+  files.push((compose_path, old_compose, compose));
+
+  let units_dir = systemd_dir();
+  let processes = discover_processes(&units_dir, app).unwrap_or_default();
+  let accessories = discover_accessories(&units_dir, &dir, app, &processes)?;
+  let spec = UnitsSpec::builder(app)?
+    .processes(processes.clone())
+    .accessories(accessories.clone())
+    .mode(cfg.systemd.mode)
+    .build();
+  let units = render_units(&spec);
+  let units_changed = units
+    .iter()
+    .any(|(path, unit)| std::fs::read_to_string(path).unwrap_or_default() != *unit);
+  for (path, unit) in units {
+    let old = std::fs::read_to_string(&path).unwrap_or_default();
+    files.push((path, old, unit));
+  }
+
+  let changes: Vec<FileChange> = files
+    .into_iter()
+    .filter(|(_, old, new)| old != new)
+    .map(|(path, old, new)| FileChange { path, old, new })
+    .collect();
+  if changes.is_empty() {
+    ok(&format!("{} is up to date", app));
+    return Ok(());
+  }
+  for change in &changes {
+    log(&format!("{}:", change.path.display()));
+    print!("{}", format_diff(&change.old, &change.new));
+  }
+
+  if !opts.yes && !confirm(&format!("apply {} change(s)?", changes.len()))? {
+    log("nothing written");
+    return Ok(());
+  }
+  for change in changes.iter().filter(|c| !c.path.starts_with(&units_dir)) {
+    fs::write(&change.path, &change.new).await?;
+    ok(&format!("updated {}", change.path.display()));
+  }
+  if units_changed {
+    write_unit(app, &processes, &accessories, cfg.systemd.mode).await?;
+    ok("updated systemd units");
+  }
+  log("deploy (or `hl restart`) to run the app with the new settings");
+  Ok(())
+}
+
+/// Replace the top-level `key:` line of a YAML document (comments elsewhere survive), or
+/// append one.
+pub(crate) fn set_top_level_key(yaml: &str, key: &str, value: &str) -> String {
+  let line = format!("{}: {}", key, value);
+  let prefix = format!("{}:", key);
+  let mut found = false;
+  let mut lines: Vec<String> = yaml
+    .lines()
+    .map(|l| {
+      if !found && l.starts_with(&prefix) {
+        found = true;
+        line.clone()
+      } else {
+        l.to_string()
+      }
+    })
+    .collect();
+  if !found {
+    lines.push(line);
+  }
+  lines.join("\n") + "\n"
+}
+
+/// Replace the `KEY=` line of a .env file, or append one.
+fn set_env_line(env: &str, key: &str, value: &str) -> String {
+  let line = format!("{}={}", key, quote_env_value(value));
+  let prefix = format!("{}=", key);
+  let mut lines: Vec<String> = env.lines().map(str::to_string).collect();
+  match lines.iter_mut().find(|l| l.starts_with(&prefix)) {
+    Some(existing) => *existing = line,
+    None => lines.push(line),
+  }
+  lines.join("\n") + "\n"
+}
+
+/// Ask a yes/no question on the terminal; anything but y/yes is a no.
+fn confirm(question: &str) -> Result<bool> {
+  if !std::io::stdin().is_terminal() {
+    anyhow::bail!("not a terminal; pass --yes to apply the changes");
+  }
+  print!("{} [y/N] ", question);
+  std::io::stdout().flush()?;
+  let mut answer = String::new();
+  std::io::stdin().read_line(&mut answer)?;
+  Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Files that mark a directory as a project worth detecting from.
+const PROJECT_MARKERS: &[&str] = &[
+  "Dockerfile",
+  "Procfile",
+  "Gemfile",
+  "package.json",
+  "manage.py",
+  "composer.json",
+  "mix.exs",
+  "go.mod",
+];
+
+/// Directories never searched for a health route.
+const SKIP_DIRS: &[&str] = &[
+  "node_modules",
+  "vendor",
+  "target",
+  "_build",
+  "deps",
+  "tmp",
+  "log",
+];
+
+/// What init could tell about the project it's run from.
+#[derive(Debug)]
+struct RepoInfo {
+  dir: PathBuf,
+  preset: Option<Preset>,
+  /// First port the Dockerfile EXPOSEs
+  port: Option<u16>,
+  dockerfile: bool,
+  /// Procfile process names, if there is a Procfile
+  processes: Option<Vec<String>>,
+}
+
+fn looks_like_project(dir: &Path) -> bool {
+  PROJECT_MARKERS.iter().any(|f| dir.join(f).exists())
+}
+
+fn detect_repo(dir: &Path) -> Result<RepoInfo> {
+  if !dir.is_dir() {
+    anyhow::bail!("{} is not a directory", dir.display());
+  }
+  let read = |file: &str| std::fs::read_to_string(dir.join(file)).unwrap_or_default();
+  let preset = if read("Gemfile").contains("rails") {
+    Some(Preset::Rails)
+  } else if dir.join("manage.py").exists() {
+    Some(Preset::Django)
+  } else if dir.join("artisan").exists() {
+    Some(Preset::Laravel)
+  } else if read("mix.exs").contains(":phoenix") {
+    Some(Preset::Phoenix)
+  } else if dir.join("go.mod").exists() {
+    Some(Preset::Go)
+  } else if dir.join("package.json").exists() {
+    Some(Preset::Node)
+  } else {
+    None
+  };
+
+  let dockerfile = dir.join("Dockerfile").exists();
+  let port = parse_dockerfile(&read("Dockerfile"))
+    .exposed_ports
+    .first()
+    .copied();
+  let processes = if dir.join("Procfile").exists() {
+    let procfile = parse_procfile_content(&read("Procfile"))
+      .with_context(|| format!("invalid Procfile in {}", dir.display()))?;
+    let mut names: Vec<_> = procfile.into_keys().collect();
+    names.sort();
+    Some(names)
+  } else {
+    None
+  };
+
+  Ok(RepoInfo {
+    dir: dir.to_path_buf(),
+    preset,
+    port,
+    dockerfile,
+    processes,
+  })
+}
+
+/// Log what was detected and warn about what a deploy will be missing.
+fn report_repo(repo: &RepoInfo, preset: Preset) {
+  let mut found = Vec::new();
+  if let Some(detected) = repo.preset {
+    found.push(format!("{:?} app", detected).to_lowercase());
+  }
+  if let Some(port) = repo.port {
+    found.push(format!("port {}", port));
+  }
+  if let Some(processes) = &repo.processes {
+    found.push(format!("Procfile ({})", processes.join(", ")));
+  }
+  if !found.is_empty() {
+    log(&format!(
+      "detected in {}: {}",
+      repo.dir.display(),
+      found.join(", ")
+    ));
+  }
+
+  if !repo.dockerfile {
+    warn(&format!(
+      "no Dockerfile in {}; deploys build the image from one (--dockerfile writes a starter)",
+      repo.dir.display()
+    ));
+  }
+  if !has_health_route(&repo.dir, preset) {
+    warn(&format!(
+      "no {} route found in {}; add one or change health.url in hl.yml, deploys wait for it",
+      preset.health_path(),
+      repo.dir.display()
+    ));
+  }
+}
+
+/// Write the preset's starter Dockerfile into the project when asked to (--dockerfile or
+/// interactively).
+fn offer_dockerfile(opts: &InitArgs, repo: &RepoInfo, port: u16) -> Result<()> {
+  let path = repo.dir.join("Dockerfile");
+  let question = format!(
+    "write a starter {:?} Dockerfile to {}?",
+    opts.preset(),
+    path.display()
+  );
+  let write = opts.dockerfile || (std::io::stdin().is_terminal() && confirm(&question)?);
+  if !write {
+    return Ok(());
+  }
+  std::fs::write(&path, opts.preset().dockerfile(&repo.dir, port))?;
+  ok(&format!(
+    "wrote {}; review it and commit it before the first deploy",
+    path.display()
+  ));
+  Ok(())
+}
+
+/// Whether the project's source mentions the preset's health route. Rails' is declared as
+/// `rails/health`; for the rest the path itself is looked for.
+fn has_health_route(dir: &Path, preset: Preset) -> bool {
+  let needle = match preset {
+    // routes.rb: get "healthz" => "rails/health#show"
+    Preset::Rails => "\"healthz\"",
+    _ => preset.health_path(),
+  };
+  let mut budget = 5000;
+  contains_text(dir, needle, 4, &mut budget)
+}
+
+fn contains_text(dir: &Path, needle: &str, depth: usize, budget: &mut usize) -> bool {
+  let Ok(entries) = std::fs::read_dir(dir) else {
+    return false;
+  };
+  for entry in entries.flatten() {
+    if *budget == 0 {
+      return false;
+    }
+    *budget -= 1;
+    let name = entry.file_name().to_string_lossy().to_string();
+    if name.starts_with('.') || SKIP_DIRS.contains(&name.as_str()) {
+      continue;
+    }
+    let path = entry.path();
+    let Ok(meta) = entry.metadata() else {
+      continue;
+    };
+    let found = if meta.is_dir() {
+      depth > 0 && contains_text(&path, needle, depth - 1, budget)
+    } else {
+      meta.len() < 512 * 1024
+        && std::fs::read_to_string(&path)
+          .map(|content| content.contains(needle))
+          .unwrap_or(false)
+    };
+    if found {
+      return true;
+    }
+  }
+  false
+}
+
+/// Django project package: the directory holding `wsgi.py`.
+fn django_project(dir: &Path) -> Option<String> {
+  std::fs::read_dir(dir)
+    .ok()?
+    .flatten()
+    .find(|e| e.path().join("wsgi.py").exists())
+    .map(|e| e.file_name().to_string_lossy().to_string())
+}
+
+/// Release name, the `app:` atom of mix.exs.
+fn phoenix_release(dir: &Path) -> Option<String> {
+  let mix = std::fs::read_to_string(dir.join("mix.exs")).ok()?;
+  let re = regex::Regex::new(r"app:\s*:(\w+)").ok()?;
+  Some(re.captures(&mix)?[1].to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Preset {
+  Node,
+  Django,
+  Go,
+  Laravel,
+  Phoenix,
+  Rails,
+}
+
+impl Preset {
+  fn default_port(&self) -> u16 {
+    match self {
+      Preset::Node | Preset::Rails => 3000,
+      Preset::Django | Preset::Laravel => 8000,
+      Preset::Go => 8080,
+      Preset::Phoenix => 4000,
+    }
+  }
+
+  /// Laravel (11+) ships a `/up` route. Rails apps keep hl's historical `/healthz` (map it to
+  /// `rails/health#show`); the others need one added.
+  fn health_path(&self) -> &'static str {
+    match self {
+      Preset::Laravel => "/up",
+      Preset::Rails | Preset::Node | Preset::Django | Preset::Go | Preset::Phoenix => "/healthz",
+    }
+  }
+
+  /// Body of the hl.yml `migrations:` section.
+  fn migrations(&self) -> &'static str {
+    match self {
+      Preset::Rails => "  preset: rails # bin/rails db:migrate with RAILS_ENV=production\n",
+      Preset::Django => "  preset: django # python manage.py migrate --noinput\n",
+      Preset::Laravel => {
+        "  preset: laravel # php artisan migrate --force with APP_ENV=production\n"
+      }
+      Preset::Phoenix => "  command: [bin/migrate] # generated by mix phx.gen.release\n",
+      Preset::Node => "  preset: none # or node-prisma, or command: [npm, run, migrate]\n",
+      Preset::Go => "  preset: none # or command: [/app/migrate, up]\n",
+    }
+  }
+
+  /// hl.yml `console:` section for `hl console`, where the framework has a standard one.
+  fn console(&self) -> Option<&'static str> {
+    match self {
+      Preset::Rails => Some("console:\n  command: [bin/rails, console]\n"),
+      Preset::Django => Some("console:\n  command: [python, manage.py, shell]\n"),
+      Preset::Laravel => Some("console:\n  command: [php, artisan, tinker]\n"),
+      Preset::Node => Some("console:\n  command: [node]\n"),
+      Preset::Go | Preset::Phoenix => None,
+    }
+  }
+
+  /// Multi-stage Dockerfile running as a non-root user and listening on `port`. `dir` is the
+  /// project, for the names the Django and Phoenix entrypoints need.
+  fn dockerfile(&self, dir: &Path, port: u16) -> String {
+    let body = match self {
+      Preset::Rails => format!(
+        r#"ARG RUBY_VERSION=3.3
+FROM ruby:$RUBY_VERSION-slim AS base
+WORKDIR /rails
+ENV RAILS_ENV=production BUNDLE_DEPLOYMENT=1 BUNDLE_PATH=/usr/local/bundle \
+    BUNDLE_WITHOUT=development:test
+
+FROM base AS build
+RUN apt-get update -qq && \
+    apt-get install --no-install-recommends -y build-essential git libpq-dev libyaml-dev pkg-config && \
+    rm -rf /var/lib/apt/lists/*
+COPY Gemfile Gemfile.lock ./
+RUN bundle install && rm -rf ~/.bundle "$BUNDLE_PATH"/ruby/*/cache
+COPY . .
+RUN --mount=type=secret,id=RAILS_MASTER_KEY \
+    RAILS_MASTER_KEY=$(cat /run/secrets/RAILS_MASTER_KEY) SECRET_KEY_BASE_DUMMY=1 \
+    ./bin/rails assets:precompile
+
+FROM base
+RUN apt-get update -qq && \
+    apt-get install --no-install-recommends -y curl libpq5 libyaml-0-2 && \
+    rm -rf /var/lib/apt/lists/*
+COPY --from=build /usr/local/bundle /usr/local/bundle
+COPY --from=build /rails /rails
+RUN useradd --create-home --shell /bin/bash rails && \
+    mkdir -p db log storage tmp && chown -R rails:rails db log storage tmp
+USER rails
+EXPOSE {port}
+CMD ["./bin/rails", "server", "-b", "0.0.0.0", "-p", "{port}"]
+"#,
+        port = port
+      ),
+      Preset::Node => format!(
+        r#"FROM node:22-slim AS build
+WORKDIR /app
+COPY package*.json ./
+RUN npm ci
+COPY . .
+RUN npm run build --if-present && npm prune --omit=dev
+
+FROM node:22-slim
+WORKDIR /app
+ENV NODE_ENV=production PORT={port}
+COPY --from=build --chown=node:node /app /app
+USER node
+EXPOSE {port}
+CMD ["npm", "start"]
+"#,
+        port = port
+      ),
+      Preset::Django => format!(
+        r#"FROM python:3.12-slim AS build
+WORKDIR /app
+RUN python -m venv /venv
+ENV PATH=/venv/bin:$PATH
+COPY requirements.txt ./
+RUN pip install --no-cache-dir -r requirements.txt gunicorn
+COPY . .
+
+FROM python:3.12-slim
+WORKDIR /app
+ENV PATH=/venv/bin:$PATH PYTHONUNBUFFERED=1
+COPY --from=build /venv /venv
+COPY --from=build /app /app
+RUN useradd --create-home app
+USER app
+EXPOSE {port}
+CMD ["gunicorn", "--bind", "0.0.0.0:{port}", "{project}.wsgi"]
+"#,
+        port = port,
+        project = django_project(dir).unwrap_or_else(|| "config".to_string())
+      ),
+      Preset::Laravel => format!(
+        r#"FROM composer:2 AS vendor
+WORKDIR /app
+COPY composer.json composer.lock ./
+RUN composer install --no-dev --no-scripts --no-autoloader --prefer-dist
+COPY . .
+RUN composer dump-autoload --optimize --no-dev
+
+FROM php:8.3-cli
+RUN apt-get update -qq && apt-get install --no-install-recommends -y libpq-dev && \
+    docker-php-ext-install pdo_mysql pdo_pgsql && rm -rf /var/lib/apt/lists/*
+WORKDIR /app
+COPY --from=vendor /app /app
+RUN useradd --create-home app && chown -R app:app storage bootstrap/cache
+USER app
+ENV APP_ENV=production
+EXPOSE {port}
+CMD ["php", "artisan", "serve", "--host=0.0.0.0", "--port={port}"]
+"#,
+        port = port
+      ),
+      Preset::Go => format!(
+        r#"FROM golang:1.23 AS build
+WORKDIR /src
+COPY go.* ./
+RUN go mod download
+COPY . .
+RUN CGO_ENABLED=0 go build -o /out/app .
+
+FROM gcr.io/distroless/static-debian12:nonroot
+COPY --from=build /out/app /app
+ENV PORT={port}
+USER nonroot
+EXPOSE {port}
+ENTRYPOINT ["/app"]
+"#,
+        port = port
+      ),
+      Preset::Phoenix => format!(
+        r#"# Expects the release files from `mix phx.gen.release` (bin/server, bin/migrate)
+FROM elixir:1.17-slim AS build
+RUN apt-get update -qq && apt-get install --no-install-recommends -y build-essential git && \
+    rm -rf /var/lib/apt/lists/*
+WORKDIR /app
+ENV MIX_ENV=prod
+RUN mix local.hex --force && mix local.rebar --force
+COPY mix.exs mix.lock ./
+RUN mix deps.get --only prod && mix deps.compile
+COPY . .
+RUN mix assets.deploy && mix release
+
+FROM debian:bookworm-slim
+RUN apt-get update -qq && \
+    apt-get install --no-install-recommends -y libstdc++6 openssl libncurses6 locales ca-certificates && \
+    rm -rf /var/lib/apt/lists/*
+WORKDIR /app
+RUN useradd --create-home app
+COPY --from=build --chown=app:app /app/_build/prod/rel/{release} ./
+USER app
+ENV PHX_SERVER=true PORT={port} LANG=C.UTF-8
+EXPOSE {port}
+CMD ["/app/bin/server"]
+"#,
+        port = port,
+        release = phoenix_release(dir).unwrap_or_else(|| "app".to_string())
+      ),
+    };
+    format!(
+      "# syntax=docker/dockerfile:1\n# Starter Dockerfile written by `hl init`; adjust it to the app.\n{}",
+      body
+    )
+  }
+
+  /// Build secrets the framework's image build typically needs.
+  fn secrets(&self) -> &'static [&'static str] {
+    match self {
+      Preset::Rails => &["RAILS_MASTER_KEY", "SECRET_KEY_BASE"],
+      _ => &[],
+    }
+  }
+}
+
+/// Image, network and resolver from the flags, else the global config, else built-in defaults.
+struct Settings {
+  port: u16,
+  image: String,
+  network: String,
+  resolver: String,
+  /// hl.yml lines for the values the global config doesn't already provide, so hl.yml only
+  /// repeats what is specific to the app
+  hl_yml_lines: String,
+  /// Path of the health URL, the preset's unless imported
+  health_path: String,
+  /// hl.yml `build.args`, from an import
+  build_args: BTreeMap<String, String>,
+  /// Migrations command, from an import; the preset's otherwise
+  migrations: Option<String>,
+}
+
+impl Settings {
+  fn resolve(opts: &InitArgs, app: &str, global: &GlobalConfig) -> Result<Settings> {
+    let defaults = global.app_defaults(app);
+    let image = match (&opts.image, defaults.get("image").and_then(|v| v.as_str())) {
+      (Some(image), _) => image.clone(),
+      (None, Some(image)) => image.to_string(),
+      (None, None) => anyhow::bail!(
+        "--image is required unless {} sets a registry",
+        global_config_path().display()
+      ),
+    };
+
+    let mut hl_yml_lines = String::new();
+    if opts.image.is_some() {
+      hl_yml_lines.push_str(&format!("image: {}\n", image));
+    }
+    let mut pick = |key: &str, flag: &Option<String>, fallback: &str| {
+      let global = defaults.get(key).and_then(|v| v.as_str());
+      let value = flag.as_deref().or(global).unwrap_or(fallback).to_string();
+      if flag.is_some() || global.is_none() {
+        hl_yml_lines.push_str(&format!("{}: {}\n", key, value));
+      }
+      value
+    };
+    let resolver = pick("resolver", &opts.resolver, "myresolver");
+    let network = pick("network", &opts.network, "traefik_proxy");
+    pick("platforms", &None, "linux/amd64");
+
+    Ok(Settings {
+      port: opts.port.unwrap_or(opts.preset().default_port()),
+      image,
+      network,
+      resolver,
+      hl_yml_lines,
+      health_path: opts.preset().health_path().to_string(),
+      build_args: BTreeMap::new(),
+      migrations: None,
+    })
+  }
+}
+
+async fn write_config_file(opts: &InitArgs, settings: &Settings) -> Result<()> {
   let dir = app_dir(&opts.app);
-  // TODO: hl currently makes a bunch of assumptions about the app being deployed:
-  // - it's a Rails app and environment is production
-  // - it uses RAILS_MASTER_KEY and SECRET_KEY_BASE secrets
-  // - it runs migrations with "bin/rails db:migrate"
-  // - it has a /healthz endpoint
-  // We should make these configurable in the future.
-  let hl_yml = format!(
-    r#"app: {}
-image: {}
-domain: {}
-servicePort: {}
-resolver: {}
-network: {}
-platforms: linux/amd64
-health:
-  url: http://{}:{}/healthz
+  let hl_yml = render_hl_yml(opts, settings);
+  let hl_yml_path = dir.join("hl.yml");
+  fs::write(&hl_yml_path, hl_yml).await?;
+  log(&format!("wrote {}", hl_yml_path.display()));
+  Ok(())
+}
+
+fn render_hl_yml(opts: &InitArgs, settings: &Settings) -> String {
+  let mut hl_yml = format!(
+    r#"app: {app}
+domain: {domain}
+servicePort: {port}
+{lines}health:
+  url: http://{app}:{port}{path}
   interval: 2s
   timeout: 45s
 migrations:
-  command: ["bin/rails", "db:migrate"]
-  env:
-    RAILS_ENV: "production"
-secrets:
-  - RAILS_MASTER_KEY
-  - SECRET_KEY_BASE
+{migrations}"#,
+    app = opts.app,
+    domain = opts.domain(),
+    port = settings.port,
+    lines = settings.hl_yml_lines,
+    path = settings.health_path,
+    migrations = match &settings.migrations {
+      Some(command) => format!("  command: {}\n", yaml_command(command)),
+      None => opts.preset().migrations().to_string(),
+    },
+  );
+  if !opts.preset().secrets().is_empty() {
+    hl_yml.push_str("secrets:\n");
+    for secret in opts.preset().secrets() {
+      hl_yml.push_str(&format!("  - {}\n", secret));
+    }
+  }
+  if let Some(console) = opts.preset().console() {
+    hl_yml.push_str(console);
+  }
+  if !settings.build_args.is_empty() {
+    hl_yml.push_str("build:\n  args:\n");
+    for (key, value) in &settings.build_args {
+      let value = serde_yaml::to_string(value).unwrap_or_default();
+      hl_yml.push_str(&format!("    {}: {}\n", key, value.trim_end()));
+    }
+  }
+  hl_yml
+}
+
+/// A shell command as a YAML flow sequence of its words, e.g. `[bin/rails, db:migrate]`.
+fn yaml_command(command: &str) -> String {
+  let words = shell_words::split(command).unwrap_or_else(|_| vec![command.to_string()]);
+  let words: Vec<String> = words
+    .iter()
+    .map(|word| {
+      let plain = word
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-:=".contains(c));
+      if plain && !word.starts_with(':') && !word.ends_with(':') {
+        word.clone()
+      } else {
+        format!("{:?}", word)
+      }
+    })
+    .collect();
+  format!("[{}]", words.join(", "))
+}
+
+/// Write `hl.<env>.yml` next to the app's hl.yml with what has to differ per environment.
+/// Everything else is inherited from hl.yml.
+async fn write_env_overlay(opts: &InitArgs, env: &str, settings: &Settings) -> Result<()> {
+  // The image is always spelled out so the environment never inherits hl.yml's. The health
+  // URL names the environment's container, on the path the app already answers on.
+  let base = load_config(&opts.app).await?;
+  let path = reqwest::Url::parse(&base.health.url)
+    .map(|u| u.path().to_string())
+    .unwrap_or_else(|_| opts.preset().health_path().to_string());
+  let mut overlay = format!(
+    r#"# Overrides hl.yml for the {env} environment (deployed as {app})
+image: {image}
+domain: {domain}
+servicePort: {port}
+health:
+  url: http://{app}:{port}{path}
 "#,
-    opts.app, opts.image, opts.domain, opts.port, opts.resolver, opts.network, opts.app, opts.port
+    env = env,
+    app = env_app_name(&opts.app, Some(env)),
+    image = settings.image,
+    domain = opts.domain(),
+    port = settings.port,
+    path = path,
   );
+  if let Some(resolver) = &opts.resolver {
+    overlay.push_str(&format!("resolver: {}\n", resolver));
+  }
+  if let Some(network) = &opts.network {
+    overlay.push_str(&format!("network: {}\n", network));
+  }
 
-  let hl_yml_path = dir.join("hl.yml");
-  fs::write(&hl_yml_path, hl_yml).await?;
-  log(&format!("wrote {}", hl_yml_path.display()));
+  let path = env_overlay_path(&opts.app, env);
+  fs::write(&path, overlay).await?;
+  log(&format!("wrote {}", path.display()));
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use clap::ValueEnum;
+  use hl::config::HLConfig;
+
+  #[test]
+  fn test_render_hl_yml_presets() {
+    for preset in Preset::value_variants() {
+      let opts = InitArgs {
+        app: "myapp".to_string(),
+        image: Some("registry.example.com/myapp".to_string()),
+        domain: Some("myapp.example.com".to_string()),
+        port: None,
+        preset: Some(*preset),
+        network: None,
+        resolver: None,
+        env: None,
+        from_compose: None,
+        repo: None,
+        dockerfile: false,
+        update: false,
+        subnet: None,
+        yes: false,
+      };
+      let settings = Settings::resolve(&opts, "myapp", &GlobalConfig::default()).unwrap();
+      let cfg: HLConfig = serde_yaml::from_str(&render_hl_yml(&opts, &settings)).unwrap();
+      assert_eq!(cfg.service_port, preset.default_port());
+      assert!(cfg.health.url.ends_with(preset.health_path()));
+      assert_eq!(cfg.console.is_some(), preset.console().is_some());
+    }
+
+    let django = InitArgs {
+      app: "myapp".to_string(),
+      image: Some("registry.example.com/myapp".to_string()),
+      domain: Some("myapp.example.com".to_string()),
+      port: Some(9000),
+      preset: Some(Preset::Django),
+      network: None,
+      resolver: None,
+      env: None,
+      from_compose: None,
+      repo: None,
+      dockerfile: false,
+      update: false,
+      subnet: None,
+      yes: false,
+    };
+    let settings = Settings::resolve(&django, "myapp", &GlobalConfig::default()).unwrap();
+    let cfg: HLConfig = serde_yaml::from_str(&render_hl_yml(&django, &settings)).unwrap();
+    assert_eq!(cfg.service_port, 9000);
+    assert_eq!(cfg.migrations.command[..2], ["python", "manage.py"]);
+    assert!(cfg.secrets.is_empty());
+
+    // An import brings its health path and build args
+    let mut settings = settings;
+    settings.health_path = "/healthz".to_string();
+    settings.build_args = [("RUBY_VERSION".to_string(), "3.3.0".to_string())].into();
+    let cfg: HLConfig = serde_yaml::from_str(&render_hl_yml(&django, &settings)).unwrap();
+    assert_eq!(cfg.health.url, "http://myapp:9000/healthz");
+    assert_eq!(cfg.build.args["RUBY_VERSION"], "3.3.0");
+    settings.migrations = Some("bin/rails db:migrate VERSION=\"1, 2\"".to_string());
+    let cfg: HLConfig = serde_yaml::from_str(&render_hl_yml(&django, &settings)).unwrap();
+    assert_eq!(
+      cfg.migrations.command,
+      ["bin/rails", "db:migrate", "VERSION=1, 2"]
+    );
+  }
+
+  #[test]
+  fn test_detect_repo() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    std::fs::write(dir.join("Gemfile"), "gem \"rails\", \"~> 7.1\"\n").unwrap();
+    std::fs::write(dir.join("Dockerfile"), "FROM ruby:3.3\nEXPOSE 8080\n").unwrap();
+    std::fs::write(dir.join("Procfile"), "web: bin/rails s\nworker: bin/jobs\n").unwrap();
+    assert!(looks_like_project(dir));
+
+    let repo = detect_repo(dir).unwrap();
+    assert_eq!(repo.preset, Some(Preset::Rails));
+    assert_eq!(repo.port, Some(8080));
+    assert!(repo.dockerfile);
+    assert_eq!(
+      repo.processes,
+      Some(vec!["web".to_string(), "worker".to_string()])
+    );
+    assert!(!has_health_route(dir, Preset::Rails));
+
+    std::fs::create_dir_all(dir.join("config")).unwrap();
+    std::fs::write(
+      dir.join("config/routes.rb"),
+      "get \"up\" => \"rails/health#show\", as: :rails_health_check\n",
+    )
+    .unwrap();
+    assert!(!has_health_route(dir, Preset::Rails));
+    std::fs::write(
+      dir.join("config/routes.rb"),
+      "get \"healthz\" => \"rails/health#show\", as: :rails_health_check\n",
+    )
+    .unwrap();
+    assert!(has_health_route(dir, Preset::Rails));
+  }
+
+  #[test]
+  fn test_update_edits() {
+    let hl_yml = "app: myapp\n# where it's served\ndomain: old.example.com\nhealth:\n  url: x\n";
+    assert_eq!(
+      set_top_level_key(hl_yml, "domain", "new.example.com"),
+      "app: myapp\n# where it's served\ndomain: new.example.com\nhealth:\n  url: x\n"
+    );
+    assert!(set_top_level_key(hl_yml, "network", "proxy").ends_with("  url: x\nnetwork: proxy\n"));
+
+    let env = "APP=myapp\nDOMAIN=old.example.com\nSERVICE_PORT=3000\n";
+    assert_eq!(
+      set_env_line(env, "SERVICE_PORT", "8080"),
+      "APP=myapp\nDOMAIN=old.example.com\nSERVICE_PORT=8080\n"
+    );
+    assert_eq!(set_env_line("", "DOMAIN", "a.b"), "DOMAIN=a.b\n");
+  }
+
+  #[test]
+  fn test_starter_dockerfiles() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    std::fs::write(
+      dir.join("mix.exs"),
+      "def project do\n  [app: :shop, version: \"0.1.0\"]\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(dir.join("shop_site")).unwrap();
+    std::fs::write(dir.join("shop_site/wsgi.py"), "").unwrap();
+
+    for preset in Preset::value_variants() {
+      let dockerfile = preset.dockerfile(dir, 5000);
+      assert_eq!(parse_dockerfile(&dockerfile).exposed_ports, vec![5000]);
+      assert!(dockerfile.contains("\nUSER "), "{:?} runs as root", preset);
+      assert!(
+        dockerfile.matches("\nFROM ").count() >= 2,
+        "{:?} is single-stage",
+        preset
+      );
+    }
+    assert!(Preset::Django
+      .dockerfile(dir, 8000)
+      .contains("\"shop_site.wsgi\""));
+    assert!(Preset::Phoenix
+      .dockerfile(dir, 4000)
+      .contains("/app/_build/prod/rel/shop ./"));
+    // The build secret Rails needs matches what hl passes
+    assert!(parse_dockerfile(&Preset::Rails.dockerfile(dir, 3000))
+      .secrets
+      .contains("RAILS_MASTER_KEY"));
+  }
+}
@@ -1,11 +1,39 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Args;
-use hl::{git::infer_app_name, log::*};
+use colored::{Color, Colorize};
+use hl::{
+  apps::list_apps,
+  config::{app_dir, env_app_name, systemd_dir, validate_env_name},
+  discovery::{discover_accessories, discover_processes},
+  git::infer_app_name,
+  log::*,
+  releases::{deploy_log_path, find_build_log, list_logs, logs_dir, summarize_deploy_log},
+  time::{format_age, unix_now},
+};
+use regex::Regex;
+use std::io::IsTerminal;
+use std::path::Path;
 use std::process::Stdio;
-use tokio::process::Command;
+use std::sync::LazyLock;
+use tokio::{
+  io::{AsyncBufReadExt, BufReader},
+  process::{Child, Command},
+  task::JoinSet,
+};
 
 #[derive(Args)]
 pub struct LogsArgs {
+  /// Apps to show logs for, each line prefixed with its app (default: HL_APP)
+  pub apps: Vec<String>,
+
+  /// Show logs from every app on this host
+  #[arg(long, conflicts_with = "apps")]
+  pub all: bool,
+
+  /// Show logs of an environment (e.g. staging) of the app(s)
+  #[arg(long, conflicts_with = "all")]
+  pub env: Option<String>,
+
   /// Follow log output (stream logs)
   #[arg(short, long)]
   pub follow: bool,
@@ -13,37 +41,397 @@ pub struct LogsArgs {
   /// Number of lines to show from the end of the logs
   #[arg(short = 'n', long)]
   pub tail: Option<String>,
+
+  /// Show logs since a timestamp (2024-05-01T10:00) or relative duration (1h, 30m)
+  #[arg(long)]
+  pub since: Option<String>,
+
+  /// Show logs before a timestamp (2024-05-01T10:00) or relative duration (1h, 30m)
+  #[arg(long)]
+  pub until: Option<String>,
+
+  /// Prefix each line with its timestamp
+  #[arg(short, long)]
+  pub timestamps: bool,
+
+  /// Only show lines matching this regex (e.g. "ERROR|Exception")
+  #[arg(long)]
+  pub grep: Option<String>,
+
+  /// Show lines that do NOT match --grep
+  #[arg(long, requires = "grep")]
+  pub invert: bool,
+
+  /// Show the build log of a deploy (the latest one when no sha is given)
+  #[arg(
+    long,
+    value_name = "SHA",
+    num_args = 0..=1,
+    default_missing_value = "",
+    conflicts_with_all = ["apps", "all"]
+  )]
+  pub build: Option<String>,
+
+  /// List recorded deploys, or show everything hl printed during the deploy of SHA
+  #[arg(
+    long,
+    value_name = "SHA",
+    num_args = 0..=1,
+    default_missing_value = "",
+    conflicts_with_all = ["apps", "all", "build"]
+  )]
+  pub deploys: Option<String>,
 }
 
+/// Colors cycled through for per-app prefixes.
+const PREFIX_COLORS: &[Color] = &[
+  Color::Cyan,
+  Color::Magenta,
+  Color::Yellow,
+  Color::Green,
+  Color::Blue,
+  Color::BrightRed,
+];
+
 pub async fn execute(args: LogsArgs) -> Result<()> {
-  let app = infer_app_name().await?;
+  if let Some(env) = &args.env {
+    validate_env_name(env)?;
+  }
+  if let Some(sha) = &args.build {
+    let app = env_app_name(&infer_app_name().await?, args.env.as_deref());
+    return show_build_log(&app, (!sha.is_empty()).then_some(sha.as_str()), &args).await;
+  }
+  if let Some(sha) = &args.deploys {
+    let app = env_app_name(&infer_app_name().await?, args.env.as_deref());
+    if sha.is_empty() {
+      return list_deploy_logs(&app);
+    }
+    let path = deploy_log_path(&app, sha);
+    if !path.exists() {
+      anyhow::bail!("no deploy log for {} in {}", sha, logs_dir(&app).display());
+    }
+    return print_log_file(&path, &args).await;
+  }
+
+  let multiplex = args.all || !args.apps.is_empty();
+  let apps = if args.all {
+    list_apps()?
+  } else if multiplex {
+    args
+      .apps
+      .iter()
+      .map(|app| env_app_name(app, args.env.as_deref()))
+      .collect()
+  } else {
+    vec![env_app_name(&infer_app_name().await?, args.env.as_deref())]
+  };
+
+  let filter = args
+    .grep
+    .as_deref()
+    .map(Regex::new)
+    .transpose()
+    .map_err(|e| anyhow::anyhow!("invalid --grep regex: {}", e))?;
+  // Piped output (filtered or prefixed) makes compose drop its colors unless forced
+  let piped = filter.is_some() || multiplex;
+  let force_ansi = piped && std::io::stdout().is_terminal();
+  let width = apps.iter().map(String::len).max().unwrap_or(0);
+
+  let mut streams = JoinSet::new();
+  for (idx, app) in apps.iter().enumerate() {
+    let projects = match app_log_commands(app, &args, force_ansi) {
+      Ok(projects) => projects,
+      // One undeployed app shouldn't stop the others from streaming
+      Err(e) if multiplex => {
+        warn(&format!("{}: {}", app, e));
+        continue;
+      }
+      Err(e) => return Err(e),
+    };
+    let prefix = multiplex.then(|| {
+      format!("{:<width$} |", app, width = width)
+        .color(PREFIX_COLORS[idx % PREFIX_COLORS.len()])
+        .to_string()
+    });
+
+    for docker_args in &projects {
+      debug(&format!("executing: docker {}", docker_args.join(" ")));
+      let child = Command::new("docker")
+        .args(docker_args)
+        .current_dir(app_dir(app))
+        .stdin(Stdio::null())
+        .stdout(if piped {
+          Stdio::piped()
+        } else {
+          Stdio::inherit()
+        })
+        .stderr(Stdio::inherit())
+        .spawn()?;
+      let stream = wait_filtered(child, filter.clone(), args.invert, prefix.clone());
+      let app = app.clone();
+      streams.spawn(async move { (app, stream.await) });
+    }
+  }
+
+  // Every stream runs to its end; one failing doesn't hide the others' output or errors
+  let mut failures = Vec::new();
+  while let Some(joined) = streams.join_next().await {
+    match joined {
+      Ok((_, Ok(()))) => {}
+      Ok((app, Err(e))) => failures.push(e.context(app)),
+      Err(e) => failures.push(anyhow::anyhow!("log stream task failed: {}", e)),
+    }
+  }
+  if failures.len() > 1 {
+    for failure in &failures {
+      err(&format!("{:#}", failure));
+    }
+    anyhow::bail!("{} log streams failed", failures.len());
+  }
+  failures.pop().map_or(Ok(()), Err)
+}
+
+/// `docker compose logs` invocations covering an app's processes and accessories, which run
+/// in separate compose projects (<app> and <app>-acc).
+fn app_log_commands(app: &str, args: &LogsArgs, force_ansi: bool) -> Result<Vec<Vec<String>>> {
+  let systemd_dir = systemd_dir();
+  let processes = discover_processes(&systemd_dir, app)?;
+  let accessories = discover_accessories(&systemd_dir, &app_dir(app), app, &processes)?;
+
+  if processes.is_empty() && accessories.is_empty() {
+    anyhow::bail!(
+      "no processes or accessories found for {}; has it been deployed?",
+      app
+    );
+  }
+
+  let mut projects = Vec::new();
+  if !processes.is_empty() {
+    projects.push(compose_logs_args(app, &processes, args, force_ansi));
+  }
+  if !accessories.is_empty() {
+    projects.push(compose_logs_args(
+      &format!("{}-acc", app),
+      &accessories,
+      args,
+      force_ansi,
+    ));
+  }
+  Ok(projects)
+}
+
+async fn show_build_log(app: &str, sha: Option<&str>, args: &LogsArgs) -> Result<()> {
+  let path = find_build_log(app, sha)?.with_context(|| match sha {
+    Some(sha) => format!("no build log for {} in {}", sha, logs_dir(app).display()),
+    None => format!("no build logs in {}", logs_dir(app).display()),
+  })?;
+  debug(&format!("showing build log: {}", path.display()));
+  print_log_file(&path, args).await
+}
 
-  let mut docker_args = vec!["logs".to_string()];
+/// Print a saved log, honoring --grep/--invert and -n.
+async fn print_log_file(path: &Path, args: &LogsArgs) -> Result<()> {
+  let filter = args.grep.as_deref().map(Regex::new).transpose()?;
+  let content = tokio::fs::read_to_string(path).await?;
+  let lines: Vec<&str> = content
+    .lines()
+    .filter(|l| {
+      filter
+        .as_ref()
+        .is_none_or(|f| line_matches(f, l, args.invert))
+    })
+    .collect();
+  let skip = match args.tail.as_deref().map(str::parse::<usize>) {
+    Some(Ok(n)) => lines.len().saturating_sub(n),
+    _ => 0,
+  };
+  for line in &lines[skip..] {
+    println!("{}", line);
+  }
+  Ok(())
+}
+
+fn list_deploy_logs(app: &str) -> Result<()> {
+  let logs = list_logs(app, "deploy-")?;
+  if logs.is_empty() {
+    log(&format!("no deploy logs in {}", logs_dir(app).display()));
+    return Ok(());
+  }
+  let now = unix_now();
+  println!("{:<9} {:<12} RESULT", "SHA", "STARTED");
+  for path in &logs {
+    let content = std::fs::read_to_string(path)?;
+    let summary = summarize_deploy_log(&content);
+    let sha = path
+      .file_stem()
+      .map(|s| {
+        s.to_string_lossy()
+          .trim_start_matches("deploy-")
+          .to_string()
+      })
+      .unwrap_or_default();
+    println!(
+      "{:<9} {:<12} {}",
+      sha,
+      summary
+        .started
+        .map(|t| format_age(t, now))
+        .unwrap_or_else(|| "-".to_string()),
+      summary.result
+    );
+  }
+  println!();
+  println!("show one with `hl logs --deploys <sha>`");
+  Ok(())
+}
+
+/// Wait for a compose logs process. When its output is piped, print only matching lines,
+/// each behind `prefix` if given.
+async fn wait_filtered(
+  mut child: Child,
+  filter: Option<Regex>,
+  invert: bool,
+  prefix: Option<String>,
+) -> Result<()> {
+  if let Some(stdout) = child.stdout.take() {
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await? {
+      if !filter
+        .as_ref()
+        .is_none_or(|f| line_matches(f, &line, invert))
+      {
+        continue;
+      }
+      match &prefix {
+        Some(prefix) => println!("{} {}", prefix, line),
+        None => println!("{}", line),
+      }
+    }
+  }
+  let status = child.wait().await?;
+  if !status.success() {
+    anyhow::bail!("docker compose logs failed with status: {}", status);
+  }
+  Ok(())
+}
+
+static ANSI_ESCAPE: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"\x1b\[[0-9;]*m").expect("valid regex"));
+
+/// Match against the text without ANSI color codes so patterns don't trip over them.
+fn line_matches(filter: &Regex, line: &str, invert: bool) -> bool {
+  filter.is_match(&ANSI_ESCAPE.replace_all(line, "")) != invert
+}
+
+/// `docker compose logs` arguments for one compose project and its overlays.
+fn compose_logs_args(
+  project: &str,
+  overlays: &[String],
+  args: &LogsArgs,
+  force_ansi: bool,
+) -> Vec<String> {
+  let mut docker_args = vec!["compose".to_string()];
+  if force_ansi {
+    docker_args.push("--ansi".to_string());
+    docker_args.push("always".to_string());
+  }
+  docker_args.extend([
+    "-p".to_string(),
+    project.to_string(),
+    "-f".to_string(),
+    "compose.yml".to_string(),
+  ]);
+  for name in overlays {
+    docker_args.push("-f".to_string());
+    docker_args.push(format!("compose.{}.yml", name));
+  }
+  docker_args.push("logs".to_string());
 
   if args.follow {
     docker_args.push("--follow".to_string());
   }
 
-  if let Some(tail) = args.tail {
+  if let Some(tail) = &args.tail {
     docker_args.push("--tail".to_string());
-    docker_args.push(tail);
+    docker_args.push(tail.clone());
   }
 
-  docker_args.push(app.clone());
+  if let Some(since) = &args.since {
+    docker_args.push("--since".to_string());
+    docker_args.push(since.clone());
+  }
 
-  debug(&format!("executing: docker {}", docker_args.join(" ")));
+  if let Some(until) = &args.until {
+    docker_args.push("--until".to_string());
+    docker_args.push(until.clone());
+  }
 
-  let status = Command::new("docker")
-    .args(&docker_args)
-    .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
-    .stderr(Stdio::inherit())
-    .status()
-    .await?;
+  if args.timestamps {
+    docker_args.push("--timestamps".to_string());
+  }
 
-  if !status.success() {
-    anyhow::bail!("docker logs failed with status: {}", status);
+  docker_args
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_compose_logs_args() {
+    let args = LogsArgs {
+      apps: vec![],
+      all: false,
+      follow: true,
+      tail: Some("100".to_string()),
+      since: None,
+      until: None,
+      timestamps: false,
+      grep: None,
+      invert: false,
+      build: None,
+      deploys: None,
+      env: None,
+    };
+    let accessories = vec!["postgres".to_string(), "redis".to_string()];
+
+    assert_eq!(
+      compose_logs_args("testapp-acc", &accessories, &args, false).join(" "),
+      "compose -p testapp-acc -f compose.yml -f compose.postgres.yml -f compose.redis.yml logs --follow --tail 100"
+    );
   }
 
-  Ok(())
+  #[test]
+  fn test_compose_logs_args_time_window() {
+    let args = LogsArgs {
+      apps: vec![],
+      all: false,
+      follow: false,
+      tail: None,
+      since: Some("1h".to_string()),
+      until: Some("2024-05-01T10:00".to_string()),
+      timestamps: true,
+      grep: None,
+      invert: false,
+      build: None,
+      deploys: None,
+      env: None,
+    };
+
+    assert_eq!(
+      compose_logs_args("testapp", &["web".to_string()], &args, true).join(" "),
+      "compose --ansi always -p testapp -f compose.yml -f compose.web.yml logs --since 1h --until 2024-05-01T10:00 --timestamps"
+    );
+  }
+
+  #[test]
+  fn test_line_matches() {
+    let filter = Regex::new("ERROR|Exception").unwrap();
+    assert!(line_matches(&filter, "web-1  | ERROR boom", false));
+    assert!(!line_matches(&filter, "web-1  | GET /up 200", false));
+    assert!(line_matches(&filter, "web-1  | GET /up 200", true));
+    // Colored prefixes don't break anchored patterns
+    let anchored = Regex::new("^web-1").unwrap();
+    assert!(line_matches(&anchored, "\x1b[36mweb-1  |\x1b[0m hi", false));
+  }
 }
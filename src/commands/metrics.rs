@@ -0,0 +1,26 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  log::*,
+  metrics::{collect_all, write_textfile},
+};
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct MetricsArgs {
+  /// Write hl.prom into this node_exporter textfile collector directory instead of printing
+  #[arg(long, value_name = "DIR")]
+  pub textfile_dir: Option<PathBuf>,
+}
+
+pub async fn execute(args: MetricsArgs) -> Result<()> {
+  let content = collect_all().await?;
+  match args.textfile_dir {
+    Some(dir) => {
+      let path = write_textfile(&dir, &content)?;
+      debug(&format!("wrote {}", path.display()));
+    }
+    None => print!("{}", content),
+  }
+  Ok(())
+}
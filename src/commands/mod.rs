@@ -1,8 +1,36 @@
 pub mod accessory;
+pub mod apps;
+pub mod backup;
+pub mod completions;
+pub mod config;
+pub mod console;
+pub mod db;
 pub mod deploy;
+pub mod diff;
+pub mod doctor;
 pub mod env;
+pub mod events;
+pub mod exec;
+pub mod export;
+pub mod hooks;
+pub mod import;
+pub mod info;
 pub mod init;
 pub mod logs;
+pub mod metrics;
+pub mod plugins;
+pub mod process;
+pub mod registry;
+pub mod releases;
 pub mod restart;
+pub mod restore;
 pub mod rollback;
+pub mod run;
+pub mod scale;
+pub mod serve;
+pub mod state;
+pub mod status;
 pub mod teardown;
+pub mod top;
+pub mod units;
+pub mod verify;
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  log::*,
+  output::{emit, output_format},
+  plugins::{discover_plugins, plugins_dir, PluginKind},
+};
+use serde::Serialize;
+
+#[derive(Args)]
+pub struct PluginsArgs {}
+
+/// An installed plugin as `hl plugins` lists it.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PluginSummary {
+  name: String,
+  /// `accessory` or `hook`
+  kind: String,
+  /// Accessory type or hook phase
+  provides: String,
+  path: String,
+}
+
+pub async fn execute(_args: PluginsArgs) -> Result<()> {
+  let dir = plugins_dir();
+  let plugins = discover_plugins(&dir)?;
+  if plugins.is_empty() && output_format().is_none() {
+    log(&format!("no plugins in {}", dir.display()));
+    return Ok(());
+  }
+  let summaries: Vec<PluginSummary> = plugins
+    .into_iter()
+    .map(|p| {
+      let (kind, provides) = match p.kind {
+        PluginKind::Accessory(kind) => ("accessory", kind),
+        PluginKind::Hook(phase) => ("hook", phase.as_str().to_string()),
+      };
+      PluginSummary {
+        name: p.name,
+        kind: kind.to_string(),
+        provides,
+        path: p.path.display().to_string(),
+      }
+    })
+    .collect();
+  emit(&summaries, |summaries| {
+    for p in summaries {
+      println!("{:<10} {:<16} {}", p.kind, p.provides, p.path);
+    }
+  })
+}
@@ -0,0 +1,79 @@
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+use hl::{
+  config::{app_dir, load_config, systemd_dir, HLConfig, SidecarTemplate},
+  discovery::{discover_accessories, discover_processes},
+  git::infer_app_name,
+  log::*,
+  sidecar::{insert_sidecar_entry, render_sidecar_compose},
+  systemd::{apply_unit_changes, write_unit},
+};
+use tokio::fs;
+
+#[derive(Args)]
+pub struct ProcessArgs {
+  #[command(subcommand)]
+  pub command: ProcessCommands,
+}
+
+#[derive(Subcommand)]
+pub enum ProcessCommands {
+  /// Add a sidecar process from a template (nginx-static, cron-runner, queue-dashboard)
+  Add {
+    /// Template name
+    template: String,
+    /// Process name (default: static, cron or dashboard)
+    #[arg(long)]
+    name: Option<String>,
+  },
+}
+
+pub async fn execute(args: ProcessArgs) -> Result<()> {
+  let app = infer_app_name().await?;
+  match args.command {
+    ProcessCommands::Add { template, name } => add_process(&app, &template, name).await,
+  }
+}
+
+async fn add_process(app: &str, template: &str, name: Option<String>) -> Result<()> {
+  let template = SidecarTemplate::parse(template)?;
+  let name = name.unwrap_or_else(|| template.default_process_name().to_string());
+  let dir = app_dir(app);
+  let systemd_dir = systemd_dir();
+  let mut processes = discover_processes(&systemd_dir, app)?;
+  if processes.contains(&name) {
+    anyhow::bail!("{} already has a process named {}", app, name);
+  }
+
+  let cfg = load_config(app).await?;
+  if !cfg.sidecars.contains_key(&name) {
+    let path = dir.join("hl.yml");
+    let content = fs::read_to_string(&path).await?;
+    let updated = insert_sidecar_entry(&content, &template.snippet(&name));
+    // Don't leave a broken hl.yml behind if the existing layout didn't take the entry
+    serde_yaml::from_str::<HLConfig>(&updated).with_context(|| {
+      format!(
+        "could not add sidecars.{} to {}; add it by hand:\n{}",
+        name,
+        path.display(),
+        template.snippet(&name)
+      )
+    })?;
+    fs::write(&path, updated).await?;
+    ok(&format!("added sidecars.{} to {}", name, path.display()));
+  }
+
+  let cfg = load_config(app).await?;
+  let compose = render_sidecar_compose(&cfg, &name, &cfg.sidecars[&name])?;
+  let compose_path = dir.join(format!("compose.{}.yml", name));
+  fs::write(&compose_path, compose).await?;
+  ok(&format!("created {}", compose_path.display()));
+
+  processes.push(name.clone());
+  let accessories = discover_accessories(&systemd_dir, &dir, app, &processes)?;
+  write_unit(app, &processes, &accessories, cfg.systemd.mode).await?;
+  apply_unit_changes(&format!("app-{}-{}.service", app, name)).await?;
+  ok(&format!("{} process {} is running", template.name(), name));
+  log("adjust its settings under sidecars in hl.yml; deploys regenerate it from there");
+  Ok(())
+}
@@ -0,0 +1,184 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use hl::{
+  config::{global_config_path, load_global_config},
+  env::generate_secret,
+  log::*,
+  registry::{
+    htpasswd_line, login, login_with_password, registry_dir, registry_host,
+    render_registry_compose, verify_login, RegistryRoute, REGISTRY_PROJECT,
+  },
+};
+use std::os::unix::fs::PermissionsExt;
+use tokio::process::Command;
+
+#[derive(Args)]
+pub struct RegistryArgs {
+  #[command(subcommand)]
+  pub command: RegistryCommands,
+}
+
+#[derive(Subcommand)]
+pub enum RegistryCommands {
+  /// Log in to a container registry so deploys can push (docker stores the credentials)
+  Login {
+    /// Registry host or image prefix, e.g. ghcr.io/me (default: the global config's registry)
+    registry: Option<String>,
+    /// Registry username (docker prompts when omitted)
+    #[arg(long, short)]
+    username: Option<String>,
+    /// Read the password or token from stdin instead of prompting
+    #[arg(long)]
+    password_stdin: bool,
+  },
+  /// Run a private registry (registry:2) on this host and make it the default for new apps
+  Serve {
+    /// Port published on 127.0.0.1
+    #[arg(long, default_value_t = 5000)]
+    port: u16,
+    /// Also serve it over HTTPS on this domain through Traefik (requires --auth: the registry
+    /// is then reachable from the internet)
+    #[arg(long, requires = "auth")]
+    domain: Option<String>,
+    /// Require a login as this user (a password is generated and printed once)
+    #[arg(long)]
+    auth: Option<String>,
+  },
+}
+
+pub async fn execute(args: RegistryArgs) -> Result<()> {
+  match args.command {
+    RegistryCommands::Login {
+      registry,
+      username,
+      password_stdin,
+    } => {
+      let global = load_global_config().await?;
+      let registry = match registry.or(global.registry.clone()) {
+        Some(registry) => registry,
+        None => anyhow::bail!(
+          "no registry given and {} sets none",
+          global_config_path().display()
+        ),
+      };
+      let host = registry_host(&registry);
+      login(host, username.as_deref(), password_stdin).await?;
+      verify_login(host).await?;
+      ok(&format!("logged in to {}", host));
+      if global.registry.is_none() && host != registry {
+        log(&format!(
+          "to name new apps {}/<app> by default, add `registry: {}` to {}",
+          registry.trim_end_matches('/'),
+          registry,
+          global_config_path().display()
+        ));
+      }
+      Ok(())
+    }
+    RegistryCommands::Serve { port, domain, auth } => serve(port, domain, auth).await,
+  }
+}
+
+/// Start (or reconfigure) the registry, log docker in when it needs auth, and point the
+/// global config's `registry` at it.
+async fn serve(port: u16, domain: Option<String>, auth: Option<String>) -> Result<()> {
+  let global = load_global_config().await?;
+  let dir = registry_dir();
+  tokio::fs::create_dir_all(&dir).await?;
+
+  let password = match &auth {
+    Some(username) => {
+      let password = generate_secret("alnum32")?;
+      let htpasswd = dir.join("htpasswd");
+      tokio::fs::write(&htpasswd, htpasswd_line(username, &password).await?).await?;
+      tokio::fs::set_permissions(&htpasswd, std::fs::Permissions::from_mode(0o600)).await?;
+      Some(password)
+    }
+    None => None,
+  };
+
+  let network = global
+    .network
+    .clone()
+    .unwrap_or_else(|| "traefik_proxy".to_string());
+  let resolver = global
+    .resolver
+    .clone()
+    .unwrap_or_else(|| "myresolver".to_string());
+  let route = domain.as_deref().map(|domain| RegistryRoute {
+    domain,
+    network: &network,
+    resolver: &resolver,
+  });
+  tokio::fs::write(
+    dir.join("compose.yml"),
+    render_registry_compose(port, route.as_ref(), auth.is_some()),
+  )
+  .await?;
+
+  log(&format!("starting registry:2 on 127.0.0.1:{}", port));
+  let status = Command::new("docker")
+    .args([
+      "compose",
+      "-p",
+      REGISTRY_PROJECT,
+      "up",
+      "-d",
+      "--remove-orphans",
+    ])
+    .current_dir(&dir)
+    .status()
+    .await?;
+  if !status.success() {
+    anyhow::bail!("docker compose up failed with status: {}", status);
+  }
+
+  let host = domain
+    .clone()
+    .unwrap_or_else(|| format!("localhost:{}", port));
+  if let (Some(username), Some(password)) = (&auth, &password) {
+    // The registry takes a moment to accept connections after `up`
+    let mut attempt = 0;
+    while let Err(e) = login_with_password(&host, username, password).await {
+      attempt += 1;
+      if attempt == 10 {
+        return Err(e);
+      }
+      tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+    println!("password for {}: {}", username, password);
+    log("it is shown only once; use it to `docker login` from other machines");
+  }
+
+  if global.registry.as_deref() != Some(host.as_str()) {
+    set_global_registry(&host).await?;
+    match &global.registry {
+      Some(previous) => ok(&format!("registry: {} (was {})", host, previous)),
+      None => ok(&format!("registry: {}", host)),
+    }
+  }
+  ok(&format!(
+    "registry running; new apps push to {}/<app>",
+    host
+  ));
+  Ok(())
+}
+
+/// Set `registry:` in the global config, keeping the rest of the file (and its comments).
+async fn set_global_registry(host: &str) -> Result<()> {
+  let path = global_config_path();
+  let content = match tokio::fs::read_to_string(&path).await {
+    Ok(content) => content,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+    Err(e) => return Err(e.into()),
+  };
+  if let Some(parent) = path.parent() {
+    tokio::fs::create_dir_all(parent).await?;
+  }
+  tokio::fs::write(
+    &path,
+    super::init::set_top_level_key(&content, "registry", host),
+  )
+  .await?;
+  Ok(())
+}
@@ -0,0 +1,119 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use hl::{
+  apps::{list_apps, print_summary, run_for_apps},
+  config::{load_config, load_global_config},
+  docker::list_image_tags,
+  git::infer_app_name,
+  log::*,
+  output::{emit, output_format},
+  releases::{correlate_image_tags, gc, load_releases, releases_dir, ImageRelease},
+  time::{format_age, unix_now},
+};
+
+#[derive(Args)]
+pub struct ReleasesArgs {
+  /// Without a subcommand, list deployable image tags
+  #[command(subcommand)]
+  pub command: Option<ReleasesCommands>,
+}
+
+#[derive(Subcommand)]
+pub enum ReleasesCommands {
+  /// List image tags on this host, newest first, marking the current :latest
+  Ls,
+  /// Prune release manifests, images, deploy logs, backups and stale build contexts past the
+  /// retention policy
+  Gc(GcArgs),
+}
+
+/// Arguments of `hl releases gc`, which `hl prune` shares.
+#[derive(Args)]
+pub struct GcArgs {
+  /// Prune every app on this host
+  #[arg(long)]
+  pub all: bool,
+  /// Maximum number of apps pruned at once (with --all)
+  #[arg(long, default_value_t = 4, requires = "all")]
+  pub jobs: usize,
+}
+
+pub async fn execute(args: ReleasesArgs) -> Result<()> {
+  match args.command.unwrap_or(ReleasesCommands::Ls) {
+    ReleasesCommands::Ls => list_releases(&infer_app_name().await?).await,
+    ReleasesCommands::Gc(args) => prune(args).await,
+  }
+}
+
+/// `hl prune` and `hl releases gc`: prune the current app, or every app with `--all`.
+pub async fn prune(args: GcArgs) -> Result<()> {
+  if args.all {
+    return run_gc_all(args.jobs).await;
+  }
+  run_gc(&infer_app_name().await?).await
+}
+
+async fn run_gc_all(jobs: usize) -> Result<()> {
+  let apps = list_apps()?;
+  log(&format!("pruning {} app(s)", apps.len()));
+  let results = run_for_apps(apps, jobs, |app| async move { run_gc(&app).await }).await;
+  let failures = print_summary(&results);
+  if failures > 0 {
+    anyhow::bail!("{} of {} app(s) failed to prune", failures, results.len());
+  }
+  Ok(())
+}
+
+async fn list_releases(app: &str) -> Result<()> {
+  let cfg = load_config(app).await?;
+  let listing = list_image_tags(&cfg.image).await?;
+  let releases = load_releases(&releases_dir(app))?;
+  let images = correlate_image_tags(&listing, &releases);
+
+  if images.is_empty() && output_format().is_none() {
+    log(&format!("no local images found for {}", cfg.image));
+    return Ok(());
+  }
+  emit(&images, |images| print_images(images))
+}
+
+fn print_images(images: &[ImageRelease]) {
+  let now = unix_now();
+  println!(
+    "  {:<9} {:<20} {:<12} {:<12} CREATED",
+    "SHA", "BRANCH", "VERSION", "DEPLOYED"
+  );
+  for image in images {
+    println!(
+      "{} {:<9} {:<20} {:<12} {:<12} {}",
+      if image.latest { "*" } else { " " },
+      image.sha.as_deref().unwrap_or("-"),
+      image.branch.as_deref().unwrap_or("-"),
+      image.version.as_deref().unwrap_or("-"),
+      image
+        .deployed_at
+        .map(|t| format_age(t, now))
+        .unwrap_or_else(|| "-".to_string()),
+      image.created
+    );
+  }
+  println!();
+  println!("* current :latest; roll back with `hl rollback <sha>`");
+}
+
+async fn run_gc(app: &str) -> Result<()> {
+  let cfg = load_config(app).await?;
+  let global = load_global_config().await?;
+  let retention = &global.retention;
+  log(&format!(
+    "pruning {} (keeping {} releases, {} logs, {} backups)",
+    app, retention.releases, retention.logs, retention.backups
+  ));
+
+  let report = gc(&cfg, retention).await?;
+  ok(&format!(
+    "removed {} release(s), {} image(s), {} log(s), {} backup(s), {} stale worktree(s)",
+    report.manifests, report.images, report.logs, report.backups, report.worktrees
+  ));
+  Ok(())
+}
@@ -1,14 +1,47 @@
 use anyhow::Result;
 use clap::Args;
-use hl::{git::infer_app_name, log::*, systemd::restart_app_target};
+use hl::{
+  apps::{list_apps, print_summary, run_for_apps},
+  events::record_event,
+  git::infer_app_name,
+  log::*,
+  systemd::restart_app_target,
+};
 
 #[derive(Args)]
-pub struct RestartArgs {}
+pub struct RestartArgs {
+  /// Restart every app on this host
+  #[arg(long)]
+  pub all: bool,
+
+  /// Maximum number of apps restarted at once (with --all)
+  #[arg(long, default_value_t = 4, requires = "all")]
+  pub jobs: usize,
+}
+
+pub async fn execute(args: RestartArgs) -> Result<()> {
+  if args.all {
+    let apps = list_apps()?;
+    log(&format!("restarting {} app(s)", apps.len()));
+    let results = run_for_apps(apps, args.jobs, |app| async move {
+      let result = restart_app_target(&app).await;
+      record_event(&app, "restart", &["--all".to_string()], &result);
+      result
+    })
+    .await;
+    let failures = print_summary(&results);
+    if failures > 0 {
+      anyhow::bail!("{} of {} app(s) failed to restart", failures, results.len());
+    }
+    ok("restart complete");
+    return Ok(());
+  }
 
-pub async fn execute(_args: RestartArgs) -> Result<()> {
   let app = infer_app_name().await?;
   log(&format!("restarting service for app: {}", app));
-  restart_app_target(&app).await?;
+  let result = restart_app_target(&app).await;
+  record_event(&app, "restart", &[], &result);
+  result?;
   ok("restart complete");
   Ok(())
 }
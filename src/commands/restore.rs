@@ -0,0 +1,110 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  backup::{extract_args, read_manifest, run_helper, BackupManifest},
+  config::{app_dir, deploy_home, hl_git_root, load_config},
+  docker::{ensure_volume, forget_applied_processes, restart_compose},
+  events::record_event,
+  git::{init_bare_repo, repo_remote_uri, validate_app_name, write_post_receive_hook},
+  log::*,
+  systemd::{enable_accessories_if_present, reload_systemd_daemon, write_unit},
+};
+
+#[derive(Args)]
+pub struct RestoreArgs {
+  /// Archive written by `hl backup`
+  pub archive: PathBuf,
+
+  /// Overwrite an app that already exists on this host
+  #[arg(long)]
+  pub force: bool,
+}
+
+pub async fn execute(args: RestoreArgs) -> Result<()> {
+  let archive = std::env::current_dir()?.join(&args.archive);
+  if !archive.exists() {
+    anyhow::bail!("backup not found: {}", archive.display());
+  }
+  let manifest = read_manifest(&archive).await?;
+  let app = &manifest.app;
+  // The name comes from the archive and ends up in paths, unit names and the hook script
+  validate_app_name(app)?;
+  let dir = app_dir(app);
+  if dir.join("hl.yml").exists() && !args.force {
+    anyhow::bail!(
+      "app '{}' already exists at {}; pass --force to overwrite it",
+      app,
+      dir.display()
+    );
+  }
+
+  log(&format!("restoring {} from {}", app, archive.display()));
+  let result = restore(&archive, &manifest).await;
+  record_event(app, "restore", &[archive.display().to_string()], &result);
+  result
+}
+
+async fn restore(archive: &Path, manifest: &BackupManifest) -> Result<()> {
+  let app = &manifest.app;
+  let dir = app_dir(app);
+  std::fs::create_dir_all(&dir)?;
+  if let Some(volume) = &manifest.volume {
+    ensure_volume(volume).await?;
+  }
+  let git_root = hl_git_root(app);
+  let home = deploy_home().to_string_lossy().to_string();
+  if manifest.git {
+    std::fs::create_dir_all(&git_root)?;
+  }
+  // Pushes arrive as the user running hl, so the repository is handed to them
+  let owner = std::fs::metadata(deploy_home())?;
+  run_helper(&extract_args(
+    archive,
+    &dir,
+    manifest.git.then_some(git_root.as_path()),
+    manifest.volume.as_deref(),
+    (owner.uid(), owner.gid()),
+  ))
+  .await?;
+  ok(&format!("restored {}", dir.display()));
+  if manifest.git {
+    // The archived hook points at the old host's hl binary
+    write_post_receive_hook(&git_root, app, &home).await?;
+    ok(&format!(
+      "restored git repository at {}",
+      git_root.display()
+    ));
+  }
+
+  let cfg = load_config(app).await?;
+  write_unit(
+    app,
+    &manifest.processes,
+    &manifest.accessories,
+    cfg.systemd.mode,
+  )
+  .await?;
+
+  if !git_root.exists() {
+    init_bare_repo(&git_root, app, &home).await?;
+    ok(&format!("created git repository at {}", git_root.display()));
+  }
+
+  log("reloading systemd daemon");
+  reload_systemd_daemon().await?;
+  enable_accessories_if_present(app, &manifest.accessories).await?;
+  log("starting services");
+  // The archive's record of what ran doesn't know the data was just replaced
+  forget_applied_processes(&dir)?;
+  restart_compose(&cfg, &manifest.processes, &manifest.accessories).await?;
+
+  ok(&format!("{} is running on this host", app));
+  log(&format!(
+    "Point your git remote at this host:\n  git remote set-url production {}",
+    repo_remote_uri(&git_root.to_string_lossy())
+  ));
+  Ok(())
+}
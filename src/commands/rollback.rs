@@ -1,12 +1,16 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Args;
 use hl::{
   config::{app_dir, load_config},
   discovery::{discover_accessories, discover_processes},
   docker::*,
+  events::record_event,
+  gate::{lower_gate, raise_gate},
   git::infer_app_name,
   health::wait_for_healthy,
   log::*,
+  releases::short_sha,
+  state::update_state,
 };
 
 #[derive(Args)]
@@ -17,22 +21,77 @@ pub struct RollbackArgs {
 
 pub async fn execute(args: RollbackArgs) -> Result<()> {
   let app = infer_app_name().await?;
-  let cfg = load_config(&app).await?;
-  let short_sha = &args.sha[..7.min(args.sha.len())];
+  let result = rollback(&app, &args.sha).await;
+  record_event(&app, "rollback", std::slice::from_ref(&args.sha), &result);
+  result
+}
+
+async fn rollback(app: &str, sha: &str) -> Result<()> {
+  let cfg = load_config(app).await?;
+  let short_sha = short_sha(sha);
   let from = format!("{}:{}", cfg.image, short_sha);
 
+  // Remember what :latest points at so a rollback that fails health can be undone
+  let previous = image_id(&format!("{}:latest", cfg.image)).await?;
+  debug(&format!("previous :latest image: {:?}", previous));
+  let dir = app_dir(app);
+  let previous_tag = pinned_tag(&dir, &cfg.image).unwrap_or_else(|| "latest".to_string());
+
   log(&format!("retagging {} -> {}:latest", from, cfg.image));
   retag_latest(&cfg.image, &from).await?;
+  pin_base_image(&dir, &cfg.image, short_sha).await?;
 
   log("restarting compose");
   let systemd_dir = hl::config::systemd_dir();
-  let processes = discover_processes(&systemd_dir, &app)?;
-  let accessories = discover_accessories(&systemd_dir, &app_dir(&app), &app, &processes)?;
+  let processes = discover_processes(&systemd_dir, app)?;
+  let accessories = discover_accessories(&systemd_dir, &dir, app, &processes)?;
+  if cfg.health.gate {
+    log("raising readiness gate");
+    raise_gate(&cfg).await?;
+  }
   restart_compose(&cfg, &processes, &accessories).await?;
 
   log("waiting for healthchecks to pass");
-  wait_for_healthy(&cfg).await?;
+  if let Err(e) = wait_for_healthy(&cfg).await {
+    let Some(previous) = previous else {
+      return Err(e.context(format!(
+        "rollback to {} failed health checks and no previous :latest was recorded to revert to",
+        short_sha
+      )));
+    };
+    err(&format!(
+      "rollback to {} failed health checks: {}",
+      short_sha, e
+    ));
+    warn("reverting :latest to the image it pointed at before the rollback");
+    restore_latest(&cfg.image, &previous).await?;
+    pin_base_image(&dir, &cfg.image, &previous_tag).await?;
+    restart_compose(&cfg, &processes, &accessories).await?;
+    wait_for_healthy(&cfg)
+      .await
+      .context("reverted release is not healthy either; the app needs manual attention")?;
+    if cfg.health.gate {
+      lower_gate(&cfg).await?;
+    }
+    anyhow::bail!(
+      "rollback to {} was reverted: it failed health checks, :latest is back on {}",
+      short_sha,
+      short_image_id(&previous)
+    );
+  }
+  if cfg.health.gate {
+    log("lowering readiness gate");
+    lower_gate(&cfg).await?;
+  }
+  update_state(app, &systemd_dir, |state| {
+    state.release = Some(sha.to_string())
+  })?;
 
   ok("rollback complete");
   Ok(())
 }
+
+fn short_image_id(id: &str) -> &str {
+  let id = id.strip_prefix("sha256:").unwrap_or(id);
+  &id[..12.min(id.len())]
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  config::{app_dir, env_app_name, env_file, load_config, validate_env_name},
+  docker::build_run_args,
+  git::infer_app_name,
+  log::*,
+};
+use std::io::IsTerminal;
+use std::process::Stdio;
+use tokio::process::Command;
+
+#[derive(Args)]
+pub struct RunArgs {
+  /// App whose image to run (default: HL_APP)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Run in an environment (e.g. staging) of the app
+  #[arg(long)]
+  pub env: Option<String>,
+
+  /// Image tag to run instead of :latest (e.g. a short sha)
+  #[arg(long, default_value = "latest")]
+  pub tag: String,
+
+  /// Command to run, e.g. -- bin/rails db:seed
+  #[arg(last = true, required = true)]
+  pub command: Vec<String>,
+}
+
+pub async fn execute(args: RunArgs) -> Result<()> {
+  if let Some(env) = &args.env {
+    validate_env_name(env)?;
+  }
+  let app = match &args.app {
+    Some(app) => app.clone(),
+    None => infer_app_name().await?,
+  };
+  let app = env_app_name(&app, args.env.as_deref());
+  run_one_off(&app, &args.tag, &args.command).await
+}
+
+/// Run `command` in a new container of the app's image at `tag`, attached to the terminal,
+/// and exit with its code when it fails.
+pub async fn run_one_off(app: &str, tag: &str, command: &[String]) -> Result<()> {
+  let cfg = load_config(app).await?;
+
+  let image = format!("{}:{}", cfg.image, tag);
+  let env_path = env_file(app).to_string_lossy().to_string();
+  // Keep stdin open for consoles and seeds that prompt; only ask for a TTY when there is one
+  let flags: &[&str] = if std::io::stdin().is_terminal() {
+    &["-it"]
+  } else {
+    &["-i"]
+  };
+  let docker_args = build_run_args(&cfg, &image, &env_path, command, flags);
+
+  log(&format!("running {} in {}", command.join(" "), image));
+  debug(&format!("executing: docker {}", docker_args.join(" ")));
+  let status = Command::new("docker")
+    .args(&docker_args)
+    .current_dir(app_dir(app))
+    .stdin(Stdio::inherit())
+    .stdout(Stdio::inherit())
+    .stderr(Stdio::inherit())
+    .status()
+    .await?;
+
+  // Pass the command's exit code through, like docker run does
+  if !status.success() {
+    std::process::exit(status.code().unwrap_or(1));
+  }
+  Ok(())
+}
@@ -0,0 +1,148 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use hl::{
+  config::{app_dir, env_app_name, env_file, load_config, systemd_dir, validate_env_name},
+  discovery::{discover_accessories, discover_processes},
+  env::{
+    env_status, load_env_file_contents, record_applied_env, write_env_file_contents, EnvStatus,
+  },
+  events::record_event,
+  git::infer_app_name,
+  log::*,
+  state::update_state,
+  systemd::{apply_unit_changes, write_unit},
+  units_spec_builder::scale_env_var,
+};
+use std::collections::HashMap;
+
+#[derive(Args)]
+pub struct ScaleArgs {
+  /// App to scale (default: HL_APP)
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// Scale an environment (e.g. staging) of the app
+  #[arg(long)]
+  pub env: Option<String>,
+
+  /// PROCESS=COUNT pairs, e.g. worker=3. Without any, show the current scale
+  pub scales: Vec<String>,
+}
+
+pub async fn execute(args: ScaleArgs) -> Result<()> {
+  if let Some(env) = &args.env {
+    validate_env_name(env)?;
+  }
+  let app = match &args.app {
+    Some(app) => app.clone(),
+    None => infer_app_name().await?,
+  };
+  let app = env_app_name(&app, args.env.as_deref());
+
+  let units_dir = systemd_dir();
+  let processes = discover_processes(&units_dir, &app)?;
+  if processes.is_empty() {
+    anyhow::bail!("no processes found for {}; has it been deployed?", app);
+  }
+  let env_path = env_file(&app);
+  let env = if env_path.exists() {
+    load_env_file_contents(&env_path)?
+  } else {
+    Default::default()
+  };
+
+  if args.scales.is_empty() {
+    for process in &processes {
+      let scale = match process.as_str() {
+        "web" => "1 (fixed)".to_string(),
+        _ => env
+          .get(&scale_env_var(process))
+          .cloned()
+          .unwrap_or_else(|| "1".to_string()),
+      };
+      log(&format!("{}: {}", process, scale));
+    }
+    return Ok(());
+  }
+
+  let scales = args
+    .scales
+    .iter()
+    .map(|s| parse_scale(s, &processes))
+    .collect::<Result<Vec<_>>>()?;
+  let result = scale(&app, &processes, env, &scales).await;
+  record_event(&app, "scale", &args.scales, &result);
+  result
+}
+
+async fn scale(
+  app: &str,
+  processes: &[String],
+  mut env: HashMap<String, String>,
+  scales: &[(String, u32)],
+) -> Result<()> {
+  let env_path = env_file(app);
+  let units_dir = systemd_dir();
+  // Only the scale variables change, so an .env that was current stays current
+  let was_current = matches!(env_status(app)?, EnvStatus::Current);
+  for (process, count) in scales {
+    env.insert(scale_env_var(process), count.to_string());
+  }
+  write_env_file_contents(&env_path, &env).await?;
+  if was_current {
+    record_applied_env(app)?;
+  }
+
+  // Units from before scaling support don't pass --scale yet
+  let cfg = load_config(app).await?;
+  let accessories = discover_accessories(&units_dir, &app_dir(app), app, processes)?;
+  write_unit(app, processes, &accessories, cfg.systemd.mode).await?;
+  update_state(app, &units_dir, |state| {
+    state.scale.extend(scales.iter().cloned())
+  })?;
+  for (process, count) in scales {
+    apply_unit_changes(&format!("app-{}-{}.service", app, process)).await?;
+    ok(&format!("scaled {} to {}", process, count));
+  }
+  Ok(())
+}
+
+/// Parse `worker=3`, checking the process exists and can be scaled.
+fn parse_scale(spec: &str, processes: &[String]) -> Result<(String, u32)> {
+  let (process, count) = spec
+    .split_once('=')
+    .with_context(|| format!("expected PROCESS=COUNT, got {}", spec))?;
+  let count: u32 = count
+    .parse()
+    .with_context(|| format!("invalid count for {}: {}", process, count))?;
+  if process == "web" {
+    anyhow::bail!("web can't be scaled: Traefik and health checks address its single container");
+  }
+  if !processes.iter().any(|p| p == process) {
+    anyhow::bail!(
+      "no process named {} (processes: {})",
+      process,
+      processes.join(", ")
+    );
+  }
+  Ok((process.to_string(), count))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_scale() {
+    let processes = vec!["web".to_string(), "worker".to_string()];
+    assert_eq!(
+      parse_scale("worker=3", &processes).unwrap(),
+      ("worker".to_string(), 3)
+    );
+    assert!(parse_scale("worker=0", &processes).is_ok());
+    assert!(parse_scale("web=2", &processes).is_err());
+    assert!(parse_scale("mailer=2", &processes).is_err());
+    assert!(parse_scale("worker", &processes).is_err());
+    assert!(parse_scale("worker=-1", &processes).is_err());
+  }
+}
@@ -0,0 +1,339 @@
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+use hl::{
+  config::{app_dir, env_app_name, hl_git_root, split_env_app_name, validate_env_name},
+  git::{fetch_into, infer_app_name, resolve_commit},
+  log::*,
+  systemd::install_serve_unit,
+  webhook::{
+    authorize, generate_token, load_webhook_config, parse_request_head, parse_trigger, response,
+    route, save_webhook_config, webhook_config_path, DeployTrigger, Request, Trigger,
+    WebhookConfig,
+  },
+};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:9876";
+/// Limits on what a client may send; GitHub push payloads are well under the body limit.
+const MAX_HEAD: usize = 16 * 1024;
+const MAX_BODY: usize = 5 * 1024 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Args)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct ServeArgs {
+  /// Without a subcommand, run the server
+  #[command(subcommand)]
+  pub command: Option<ServeCommands>,
+
+  /// Address to listen on. The server speaks plain HTTP: keep it on localhost or a private
+  /// network, or put a TLS-terminating proxy in front of it
+  #[arg(long, default_value = DEFAULT_LISTEN)]
+  pub listen: SocketAddr,
+}
+
+#[derive(Subcommand)]
+pub enum ServeCommands {
+  /// Print an app's webhook token, creating it on first use
+  Token {
+    /// App name (default: HL_APP)
+    #[arg(long)]
+    app: Option<String>,
+    /// Environment (e.g. staging) of the app
+    #[arg(long)]
+    env: Option<String>,
+    /// Replace the token; requests with the old one are refused from then on
+    #[arg(long)]
+    rotate: bool,
+    /// Repository to fetch pushed commits from when they aren't in the app's bare repository
+    /// (default: the repository URL in the push event)
+    #[arg(long, value_name = "URL")]
+    remote: Option<String>,
+  },
+  /// Install and start a systemd unit that keeps `hl serve` running
+  Install {
+    /// Address the server listens on
+    #[arg(long, default_value = DEFAULT_LISTEN)]
+    listen: SocketAddr,
+  },
+}
+
+pub async fn execute(args: ServeArgs) -> Result<()> {
+  match args.command {
+    None => serve(args.listen).await,
+    Some(ServeCommands::Token {
+      app,
+      env,
+      rotate,
+      remote,
+    }) => token(app, env, rotate, remote).await,
+    Some(ServeCommands::Install { listen }) => {
+      if !listen.ip().is_loopback() {
+        warn(&format!(
+          "{} is reachable from other hosts over plain HTTP; put a TLS-terminating proxy in front of it",
+          listen
+        ));
+      }
+      let path = install_serve_unit(&listen.to_string()).await?;
+      ok(&format!(
+        "installed {}; hl serve is listening on {}",
+        path.display(),
+        listen
+      ));
+      Ok(())
+    }
+  }
+}
+
+async fn token(
+  app: Option<String>,
+  env: Option<String>,
+  rotate: bool,
+  remote: Option<String>,
+) -> Result<()> {
+  let app = match app {
+    Some(app) => app,
+    None => infer_app_name().await?,
+  };
+  if let Some(env) = &env {
+    validate_env_name(env)?;
+  }
+  let app = env_app_name(&app, env.as_deref());
+  let dir = app_dir(&app);
+  if !dir.exists() {
+    anyhow::bail!("{} is not initialized ({} not found)", app, dir.display());
+  }
+  let mut cfg = load_webhook_config(&app)?.unwrap_or_default();
+  if cfg.token.is_empty() || rotate {
+    cfg.token = generate_token()?;
+    // Only the token goes to stdout, so `$(hl serve token)` captures it
+    debug(&format!(
+      "new webhook token for {} in {}",
+      app,
+      webhook_config_path(&app).display()
+    ));
+  }
+  if remote.is_some() {
+    cfg.remote = remote;
+  }
+  save_webhook_config(&app, &cfg)?;
+  println!("{}", cfg.token);
+  debug(&format!(
+    "endpoints: POST /apps/{}/deploy?sha=<sha>, /apps/{}/rollback?sha=<sha>, /apps/{}/restart",
+    app, app, app
+  ));
+  Ok(())
+}
+
+/// Actions of an app run one at a time, in the order their requests came in.
+type AppLocks = Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>;
+
+async fn serve(listen: SocketAddr) -> Result<()> {
+  let listener = TcpListener::bind(listen)
+    .await
+    .with_context(|| format!("Failed to listen on {}", listen))?;
+  log(&format!("listening on http://{}", listen));
+  let locks = AppLocks::default();
+  loop {
+    let (stream, peer) = match listener.accept().await {
+      Ok(accepted) => accepted,
+      Err(e) => {
+        warn(&format!("failed to accept a connection: {}", e));
+        continue;
+      }
+    };
+    let locks = locks.clone();
+    tokio::spawn(async move {
+      if let Err(e) = handle_connection(stream, peer, locks).await {
+        debug(&format!("connection from {}: {:#}", peer, e));
+      }
+    });
+  }
+}
+
+async fn handle_connection(mut stream: TcpStream, peer: SocketAddr, locks: AppLocks) -> Result<()> {
+  let (status, body) = match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+    Ok(Ok(request)) => {
+      let (status, body) = handle(&request, &locks).await;
+      log(&format!(
+        "{} {} {} -> {}",
+        peer, request.method, request.path, status
+      ));
+      (status, body)
+    }
+    Ok(Err((status, message))) => (status, message),
+    Err(_) => (400, "timed out reading the request".to_string()),
+  };
+  stream.write_all(response(status, &body).as_bytes()).await?;
+  stream.shutdown().await?;
+  Ok(())
+}
+
+/// Read one request: the head up to the blank line, then Content-Length bytes of body.
+async fn read_request(stream: &mut TcpStream) -> std::result::Result<Request, (u16, String)> {
+  let mut buf = Vec::new();
+  let mut chunk = [0u8; 8192];
+  let head_end = loop {
+    if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+      break pos;
+    }
+    if buf.len() > MAX_HEAD {
+      return Err((413, "request head too large".to_string()));
+    }
+    match stream.read(&mut chunk).await {
+      Ok(0) | Err(_) => return Err((400, "incomplete request".to_string())),
+      Ok(n) => buf.extend_from_slice(&chunk[..n]),
+    }
+  };
+  let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+  let mut request = parse_request_head(&head).map_err(|e| (400, e.to_string()))?;
+  let length = request.content_length().map_err(|e| (400, e.to_string()))?;
+  if length > MAX_BODY {
+    return Err((413, "request body too large".to_string()));
+  }
+  let mut body = buf.split_off(head_end + 4);
+  while body.len() < length {
+    match stream.read(&mut chunk).await {
+      Ok(0) | Err(_) => return Err((400, "incomplete request body".to_string())),
+      Ok(n) => body.extend_from_slice(&chunk[..n]),
+    }
+  }
+  body.truncate(length);
+  request.body = body;
+  Ok(request)
+}
+
+/// Status and body answering `request`.
+async fn handle(request: &Request, locks: &AppLocks) -> (u16, String) {
+  let Some((app, action)) = route(&request.path) else {
+    return (404, "not found".to_string());
+  };
+  if request.method != "POST" {
+    return (405, "use POST".to_string());
+  }
+  // Unknown apps get the same answer as wrong tokens, so app names can't be probed
+  let webhook = match load_webhook_config(&app) {
+    Ok(Some(webhook)) if authorize(request, &webhook.token) => webhook,
+    Ok(_) => return (401, "unauthorized".to_string()),
+    Err(e) => {
+      err(&format!("{:#}", e));
+      return (500, "failed to read the app's webhook config".to_string());
+    }
+  };
+  let trigger = match parse_trigger(request, action) {
+    Ok(Trigger::Ping) => return (200, "pong".to_string()),
+    Ok(Trigger::Ignored(reason)) => return (202, format!("ignored: {}", reason)),
+    Ok(trigger) => trigger,
+    Err(e) => return (400, format!("{:#}", e)),
+  };
+
+  let lock = locks.lock().await.entry(app.clone()).or_default().clone();
+  let what = format!("{} of {}", action.as_str(), app);
+  let job = async move {
+    let _guard = lock.lock().await;
+    run_trigger(&app, trigger, &webhook).await
+  };
+  if request.wait() {
+    return match job.await {
+      Ok(output) => (200, output),
+      Err(e) => {
+        err(&format!("{} failed: {:#}", what, e));
+        (500, format!("{:#}", e))
+      }
+    };
+  }
+  log(&format!("starting {}", what));
+  let started = format!("{} started", what);
+  tokio::spawn(async move {
+    match job.await {
+      Ok(_) => ok(&format!("{} finished", what)),
+      Err(e) => err(&format!("{} failed: {:#}", what, e)),
+    }
+  });
+  (202, started)
+}
+
+/// Run the hl command a trigger stands for, returning its output.
+async fn run_trigger(app: &str, trigger: Trigger, webhook: &WebhookConfig) -> Result<String> {
+  let (hl_app, args) = match trigger {
+    Trigger::Deploy(deploy) => deploy_args(app, &deploy, webhook).await?,
+    Trigger::Rollback(sha) => (app.to_string(), vec!["rollback".to_string(), sha]),
+    Trigger::Restart => (app.to_string(), vec!["restart".to_string()]),
+    Trigger::Ping | Trigger::Ignored(_) => return Ok(String::new()),
+  };
+  let hl = std::env::current_exe().context("Failed to locate the hl binary")?;
+  debug(&format!("running HL_APP={} hl {}", hl_app, args.join(" ")));
+  let output = Command::new(hl)
+    .args(&args)
+    .env("HL_APP", &hl_app)
+    .stdin(std::process::Stdio::null())
+    .output()
+    .await
+    .context("Failed to run hl")?;
+  let text = format!(
+    "{}{}",
+    String::from_utf8_lossy(&output.stdout),
+    String::from_utf8_lossy(&output.stderr)
+  );
+  if !output.status.success() {
+    anyhow::bail!("hl {} failed ({})\n{}", args[0], output.status, text);
+  }
+  Ok(text)
+}
+
+/// The app and `hl deploy` arguments deploying `deploy`, after fetching its commit into the
+/// bare repository if a push didn't put it there.
+async fn deploy_args(
+  app: &str,
+  deploy: &DeployTrigger,
+  webhook: &WebhookConfig,
+) -> Result<(String, Vec<String>)> {
+  let (repo_app, env) = match split_env_app_name(app) {
+    Some((base, env)) => (base, Some(env)),
+    None => (app.to_string(), None),
+  };
+  let git_dir = hl_git_root(&repo_app).display().to_string();
+  if resolve_commit(&git_dir, &deploy.sha).await?.is_none() {
+    let remote = webhook
+      .remote
+      .as_deref()
+      .or(deploy.clone_url.as_deref())
+      .with_context(|| {
+        format!(
+          "commit {} is not in {} and there is no remote to fetch it from (hl serve token --remote)",
+          deploy.sha, git_dir
+        )
+      })?;
+    let refspec = match &deploy.refname {
+      Some(refname) => format!("+{}:{}", refname, refname),
+      None => deploy.sha.clone(),
+    };
+    log(&format!("fetching {} from {}", refspec, remote));
+    fetch_into(&git_dir, remote, &refspec).await?;
+  }
+  let sha = resolve_commit(&git_dir, &deploy.sha)
+    .await?
+    .with_context(|| format!("commit {} not found in {}", deploy.sha, git_dir))?;
+
+  let mut args = vec!["deploy".to_string(), "--sha".to_string(), sha];
+  if let Some(branch) = &deploy.branch {
+    args.extend(["--branch".to_string(), branch.clone()]);
+  }
+  if let Some(tag) = &deploy.tag {
+    args.extend(["--tag".to_string(), tag.clone()]);
+  }
+  if let Some(refname) = &deploy.refname {
+    args.extend(["--ref".to_string(), refname.clone()]);
+  }
+  if let Some(env) = env {
+    args.extend(["--env".to_string(), env]);
+  }
+  Ok((repo_app, args))
+}
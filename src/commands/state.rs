@@ -0,0 +1,54 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use hl::{
+  config::{app_dir, systemd_dir},
+  git::infer_app_name,
+  log::*,
+  state::{load_state, save_state, scan_state, state_path},
+};
+
+#[derive(Args)]
+pub struct StateArgs {
+  /// Without a subcommand, print the app's recorded state
+  #[command(subcommand)]
+  pub command: Option<StateCommands>,
+}
+
+#[derive(Subcommand)]
+pub enum StateCommands {
+  /// Print state.yml (processes, accessories, release, scale)
+  Show,
+  /// Rewrite state.yml from the app's unit files, compose files, releases and .env
+  Rebuild,
+}
+
+pub async fn execute(args: StateArgs) -> Result<()> {
+  let app = infer_app_name().await?;
+  let dir = app_dir(&app);
+  if !dir.exists() {
+    anyhow::bail!("app directory not found: {}", dir.display());
+  }
+  match args.command.unwrap_or(StateCommands::Show) {
+    StateCommands::Show => match load_state(&dir) {
+      Some(state) => print!("{}", serde_yaml::to_string(&state)?),
+      None => log(&format!(
+        "{} has no state.yml yet; hl state rebuild writes one from its files",
+        app
+      )),
+    },
+    StateCommands::Rebuild => {
+      let scanned = scan_state(&app, &systemd_dir())?;
+      if load_state(&dir).as_ref() == Some(&scanned) {
+        ok(&format!(
+          "{} already matches the app's files",
+          state_path(&dir).display()
+        ));
+        return Ok(());
+      }
+      save_state(&dir, &scanned)?;
+      print!("{}", serde_yaml::to_string(&scanned)?);
+      ok(&format!("rebuilt {}", state_path(&dir).display()));
+    }
+  }
+  Ok(())
+}
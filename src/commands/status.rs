@@ -0,0 +1,117 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  config::systemd_dir,
+  discovery::discover_processes,
+  docker::inspect_compose_project,
+  env::{env_status, EnvStatus},
+  git::infer_app_name,
+  log::*,
+  output::emit,
+  time::{format_age, format_duration, format_rfc3339, parse_rfc3339, unix_now},
+};
+use serde::Serialize;
+
+#[derive(Args)]
+pub struct StatusArgs {}
+
+/// What `hl status` reports for an app.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AppStatus {
+  app: String,
+  processes: Vec<ProcessStatus>,
+  /// current, stale or unknown
+  env: String,
+  /// When .env changed without a restart, if it is stale
+  env_changed_at: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProcessStatus {
+  name: String,
+  /// Container state (running, exited, ...); none when the process has no container
+  state: Option<String>,
+  health: Option<String>,
+  started_at: Option<String>,
+}
+
+pub async fn execute(_args: StatusArgs) -> Result<()> {
+  let app = infer_app_name().await?;
+  let processes = discover_processes(&systemd_dir(), &app)?;
+  if processes.is_empty() {
+    anyhow::bail!("no processes found for {}; has it been deployed?", app);
+  }
+
+  let containers = inspect_compose_project(&app).await?;
+  let processes = processes
+    .into_iter()
+    .map(|name| {
+      let container = containers
+        .iter()
+        .find(|c| c.compose_service() == Some(name.as_str()));
+      ProcessStatus {
+        state: container.map(|c| c.state.status.clone()),
+        health: container.and_then(|c| c.state.health.as_ref().map(|h| h.status.clone())),
+        started_at: container
+          .filter(|c| c.state.status == "running")
+          .map(|c| c.state.started_at.clone()),
+        name,
+      }
+    })
+    .collect();
+  let (env, env_changed_at) = match env_status(&app)? {
+    EnvStatus::Current => ("current", None),
+    EnvStatus::Unknown => ("unknown", None),
+    EnvStatus::Stale { changed_at } => ("stale", changed_at.map(format_rfc3339)),
+  };
+  let status = AppStatus {
+    app,
+    processes,
+    env: env.to_string(),
+    env_changed_at,
+  };
+  emit(&status, print_status)
+}
+
+fn print_status(status: &AppStatus) {
+  let now = unix_now();
+  for process in &status.processes {
+    let Some(mut state) = process.state.clone() else {
+      warn(&format!("{}: no container", process.name));
+      continue;
+    };
+    if let Some(health) = &process.health {
+      state.push_str(&format!(", {}", health));
+    }
+    if process.state.as_deref() == Some("running") {
+      if let Some(started) = process.started_at.as_deref().and_then(parse_rfc3339) {
+        state.push_str(&format!(
+          ", up {}",
+          format_duration(now.saturating_sub(started))
+        ));
+      }
+      ok(&format!("{}: {}", process.name, state));
+    } else {
+      warn(&format!("{}: {}", process.name, state));
+    }
+  }
+
+  match status.env.as_str() {
+    "current" => ok("env: up to date"),
+    "stale" => {
+      let when = status
+        .env_changed_at
+        .as_deref()
+        .and_then(parse_rfc3339)
+        .map(|t| format!(" {}", format_age(t, now)))
+        .unwrap_or_default();
+      warn(&format!(
+        "env: changed{}, restart required (hl restart)",
+        when
+      ));
+    }
+    _ => log("env: unknown (not restarted by hl since tracking began)"),
+  }
+}
@@ -3,10 +3,11 @@ use std::{path::Path, process::Stdio};
 use anyhow::Result;
 use clap::Args;
 use hl::{
-  config::{app_dir, hl_git_root, systemd_dir},
+  config::{app_dir, hl_git_root, load_config, systemd_dir, APP_VOLUMES_DIR},
+  events::record_event,
   git::infer_app_name,
   log::*,
-  systemd::{reload_systemd_daemon, stop_disable_app_target},
+  systemd::{is_app_unit_file, reload_systemd_daemon, stop_disable_app_target},
 };
 use tokio::{fs, process::Command};
 
@@ -15,10 +16,23 @@ pub struct TeardownArgs {
   /// Skip confirmation prompt
   #[arg(long)]
   pub force: bool,
+
+  /// Keep the persistence volume, hl.yml volumes and accessory data (pgdata, redisdata)
+  #[arg(long)]
+  pub keep_data: bool,
 }
 
+/// Data directories inside the app directory: accessory data (consistent with accessory.rs)
+/// and the host side of hl.yml `volumes:`
+const DATA_DIRS: &[&str] = &["pgdata", "redisdata", APP_VOLUMES_DIR];
+
 pub async fn execute(args: TeardownArgs) -> Result<()> {
   let app = &infer_app_name().await?;
+  // Read before the app directory (and hl.yml with it) goes away
+  let persistence_volume = load_config(app)
+    .await
+    .ok()
+    .and_then(|cfg| cfg.persistence_volume());
 
   // Confirmation prompt unless --force is used
   if !args.force {
@@ -28,8 +42,24 @@ pub async fn execute(args: TeardownArgs) -> Result<()> {
     ));
     log("   - Stop all running services (web, workers, accessories)");
     log("   - Remove systemd unit files");
-    log(&format!("   - Remove git repository: ~/hl/git/{}.git", app));
-    log(&format!("   - Remove app directory: ~/hl/apps/{}", app));
+    log(&format!(
+      "   - Remove git repository: {}",
+      hl_git_root(app).display()
+    ));
+    if args.keep_data {
+      log(&format!(
+        "   - Remove app directory: {} (keeping volumes and accessory data)",
+        app_dir(app).display()
+      ));
+    } else {
+      log(&format!(
+        "   - Remove app directory: {}",
+        app_dir(app).display()
+      ));
+      if let Some(volume) = &persistence_volume {
+        log(&format!("   - Remove persistence volume: {}", volume));
+      }
+    }
     log("");
     log("Type the app name to confirm deletion:");
 
@@ -44,7 +74,17 @@ pub async fn execute(args: TeardownArgs) -> Result<()> {
   }
 
   log(&format!("tearing down app: {}", app));
+  let flags: Vec<String> = args
+    .keep_data
+    .then(|| "--keep-data".to_string())
+    .into_iter()
+    .collect();
+  let result = teardown(app, args.keep_data, persistence_volume.as_deref()).await;
+  record_event(app, "teardown", &flags, &result);
+  result
+}
 
+async fn teardown(app: &str, keep_data: bool, persistence_volume: Option<&str>) -> Result<()> {
   // Step 1: Stop and disable the app target (this stops all services)
   stop_disable_app_target(app).await?;
 
@@ -53,9 +93,22 @@ pub async fn execute(args: TeardownArgs) -> Result<()> {
   reload_systemd_daemon().await?;
 
   remove_git_repo(app).await?;
-  remove_app_dir(app).await?;
-
-  ok(&format!("app '{}' has been completely removed", app));
+  if keep_data {
+    remove_app_dir_keeping_data(app).await?;
+    if let Some(volume) = persistence_volume {
+      log(&format!("kept persistence volume: {}", volume));
+    }
+    ok(&format!(
+      "app '{}' has been removed; its data was kept",
+      app
+    ));
+  } else {
+    remove_app_dir(app).await?;
+    if let Some(volume) = persistence_volume {
+      remove_volume(volume).await?;
+    }
+    ok(&format!("app '{}' has been completely removed", app));
+  }
 
   Ok(())
 }
@@ -65,6 +118,9 @@ async fn remove_systemd_units(app: &str) -> Result<()> {
   let unit_patterns = vec![
     format!("app-{}.target", app),
     format!("app-{}-*.service", app),
+    format!("app-{}-cron-*.timer", app),
+    format!("hl-{}-env-cleanup-*.service", app),
+    format!("hl-{}-env-cleanup-*.timer", app),
   ];
 
   debug(&format!(
@@ -72,30 +128,45 @@ async fn remove_systemd_units(app: &str) -> Result<()> {
     systemd_path.display()
   ));
 
+  // Units of environments (`<app>-<env>`) share the app's prefix; they are left alone
+  let mut files = Vec::new();
+  let mut entries = fs::read_dir(&systemd_path).await?;
+  while let Some(entry) = entries.next_entry().await? {
+    files.push(entry.file_name().to_string_lossy().to_string());
+  }
+  let ours =
+    |filename: &str| !filename.starts_with("app-") || is_app_unit_file(app, filename, &files);
+
   for pattern in unit_patterns {
     // Find all matching files
-    let mut entries = fs::read_dir(&systemd_path).await?;
-    while let Some(entry) = entries.next_entry().await? {
-      let filename = entry.file_name();
-      let filename_str = filename.to_string_lossy();
-
+    for filename in files.iter().filter(|f| ours(f)) {
       // Check if filename matches the pattern
-      if pattern.contains('*') {
+      let matches = if pattern.contains('*') {
         let prefix = pattern.split('*').next().unwrap();
         let suffix = pattern.split('*').next_back().unwrap();
-        if filename_str.starts_with(prefix) && filename_str.ends_with(suffix) {
-          let path = entry.path();
-          debug(&format!("removing unit file: {}", path.display()));
-          fs::remove_file(&path).await?;
-        }
-      } else if filename_str == pattern {
-        let path = entry.path();
+        filename.starts_with(prefix) && filename.ends_with(suffix)
+      } else {
+        *filename == pattern
+      };
+      let path = systemd_path.join(filename);
+      if matches && path.is_file() {
         debug(&format!("removing unit file: {}", path.display()));
         fs::remove_file(&path).await?;
       }
     }
   }
 
+  // Drop-in directories: empty ones go with their units, customized ones are left for review
+  for filename in files.iter().filter(|f| f.starts_with("app-") && ours(f)) {
+    let path = systemd_path.join(filename);
+    if filename.ends_with(".d") && fs::remove_dir(&path).await.is_err() {
+      warn(&format!(
+        "kept {}: it holds local overrides",
+        path.display()
+      ));
+    }
+  }
+
   log("removed systemd unit files");
 
   Ok(())
@@ -141,10 +212,52 @@ async fn remove_app_dir(app: &str) -> Result<()> {
   Ok(())
 }
 
+/// Remove everything in the app directory except its data, so re-initializing the app
+/// picks its databases and uploads back up.
+async fn remove_app_dir_keeping_data(app: &str) -> Result<()> {
+  let app_path = app_dir(app);
+  if !app_path.exists() {
+    return Ok(());
+  }
+
+  let mut entries = fs::read_dir(&app_path).await?;
+  while let Some(entry) = entries.next_entry().await? {
+    let name = entry.file_name();
+    if DATA_DIRS.contains(&name.to_string_lossy().as_ref()) {
+      debug(&format!("keeping {}", entry.path().display()));
+      continue;
+    }
+    if entry.file_type().await?.is_dir() {
+      fs::remove_dir_all(entry.path()).await?;
+    } else {
+      fs::remove_file(entry.path()).await?;
+    }
+  }
+  log(&format!(
+    "removed app files from {} (data kept)",
+    app_path.display()
+  ));
+  Ok(())
+}
+
+async fn remove_volume(volume: &str) -> Result<()> {
+  let status = Command::new("docker")
+    .args(["volume", "rm", volume])
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::inherit())
+    .status()
+    .await?;
+  if status.success() {
+    log(&format!("removed persistence volume: {}", volume));
+  } else {
+    warn(&format!("failed to remove persistence volume {}", volume));
+  }
+  Ok(())
+}
+
 async fn remove_accessory_data_volumes(app_path: &Path) -> Result<()> {
-  // TODO: Consider extracting these volume names to constants or config (consistent with usage at accessory.rs)
-  let volume_dirs = vec!["pgdata", "redisdata"];
-  for volume_dir in volume_dirs {
+  for volume_dir in DATA_DIRS {
     let volume_path = app_path.join(volume_dir);
     if volume_path.exists() {
       debug(&format!(
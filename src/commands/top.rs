@@ -0,0 +1,104 @@
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use hl::{
+  apps::list_apps,
+  config::{env_app_name, validate_env_name},
+  log::*,
+  stats::{aggregate, container_stats, format_size},
+};
+
+#[derive(Args)]
+pub struct TopArgs {
+  /// Show the containers of one app instead of a line per app
+  #[arg(long)]
+  pub app: Option<String>,
+
+  /// With --app, show an environment (e.g. staging) of it
+  #[arg(long, requires = "app")]
+  pub env: Option<String>,
+
+  /// Column to sort by, highest first
+  #[arg(long, value_enum, default_value_t = SortBy::Mem)]
+  pub sort: SortBy,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortBy {
+  Cpu,
+  Mem,
+}
+
+pub async fn execute(args: TopArgs) -> Result<()> {
+  if let Some(env) = &args.env {
+    validate_env_name(env)?;
+  }
+  let apps = match &args.app {
+    Some(app) => vec![env_app_name(app, args.env.as_deref())],
+    None => list_apps()?,
+  };
+  let mut stats = container_stats(&apps).await?;
+  if stats.is_empty() {
+    log("no running containers");
+    return Ok(());
+  }
+
+  if args.app.is_some() {
+    match args.sort {
+      SortBy::Cpu => stats.sort_by(|a, b| b.cpu.total_cmp(&a.cpu)),
+      SortBy::Mem => stats.sort_by_key(|s| std::cmp::Reverse(s.mem_bytes)),
+    }
+    let w_name = stats.iter().map(|s| s.name.len()).max().unwrap_or(0).max(9);
+    let w_service = stats
+      .iter()
+      .map(|s| s.service.len())
+      .max()
+      .unwrap_or(0)
+      .max(7);
+    println!(
+      "{:<w_name$}  {:<w_service$}  {:>8}  {:>10}",
+      "CONTAINER", "SERVICE", "CPU", "MEM"
+    );
+    for s in &stats {
+      println!(
+        "{:<w_name$}  {:<w_service$}  {:>7.1}%  {:>10}",
+        s.name,
+        s.service,
+        s.cpu,
+        format_size(s.mem_bytes)
+      );
+    }
+  }
+
+  let mut totals = aggregate(&stats);
+  match args.sort {
+    SortBy::Cpu => totals.sort_by(|a, b| b.cpu.total_cmp(&a.cpu)),
+    SortBy::Mem => totals.sort_by_key(|a| std::cmp::Reverse(a.mem_bytes)),
+  }
+  if args.app.is_some() {
+    println!();
+  }
+  let w_app = totals.iter().map(|a| a.app.len()).max().unwrap_or(0).max(5);
+  println!(
+    "{:<w_app$}  {:>10}  {:>8}  {:>10}",
+    "APP", "CONTAINERS", "CPU", "MEM"
+  );
+  for a in &totals {
+    println!(
+      "{:<w_app$}  {:>10}  {:>7.1}%  {:>10}",
+      a.app,
+      a.containers,
+      a.cpu,
+      format_size(a.mem_bytes)
+    );
+  }
+  if totals.len() > 1 {
+    println!(
+      "{:<w_app$}  {:>10}  {:>7.1}%  {:>10}",
+      "TOTAL",
+      totals.iter().map(|a| a.containers).sum::<usize>(),
+      totals.iter().map(|a| a.cpu).sum::<f64>(),
+      format_size(totals.iter().map(|a| a.mem_bytes).sum())
+    );
+  }
+  Ok(())
+}
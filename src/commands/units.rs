@@ -0,0 +1,69 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use hl::{
+  config::{app_dir, env_app_name, load_config, systemd_dir, validate_env_name},
+  discovery::{discover_accessories, discover_processes},
+  git::infer_app_name,
+  log::*,
+  systemd::{plan_unit_changes, print_unit_changes},
+};
+
+#[derive(Args)]
+pub struct UnitsArgs {
+  #[command(subcommand)]
+  pub command: UnitsCommands,
+}
+
+#[derive(Subcommand)]
+pub enum UnitsCommands {
+  /// Show how regenerating the app's systemd units would change them
+  Diff {
+    /// App name (default: HL_APP)
+    #[arg(long)]
+    app: Option<String>,
+    /// Environment (e.g. staging) of the app
+    #[arg(long)]
+    env: Option<String>,
+    /// Exit with an error when any unit would change
+    #[arg(long)]
+    check: bool,
+  },
+}
+
+pub async fn execute(args: UnitsArgs) -> Result<()> {
+  match args.command {
+    UnitsCommands::Diff { app, env, check } => diff(app, env, check).await,
+  }
+}
+
+/// Compare the unit files on disk with what the app's current processes, accessories and
+/// hl.yml render to, e.g. after editing hl.yml or upgrading hl.
+async fn diff(app: Option<String>, env: Option<String>, check: bool) -> Result<()> {
+  let app = match app {
+    Some(app) => app,
+    None => infer_app_name().await?,
+  };
+  if let Some(env) = &env {
+    validate_env_name(env)?;
+  }
+  let app = env_app_name(&app, env.as_deref());
+  let cfg = load_config(&app).await?;
+  let units_dir = systemd_dir();
+  let processes = discover_processes(&units_dir, &app)?;
+  if processes.is_empty() {
+    anyhow::bail!("{} has no process units yet; deploy it first", app);
+  }
+  let accessories = discover_accessories(&units_dir, &app_dir(&app), &app, &processes)?;
+
+  let changes = plan_unit_changes(&app, &processes, &accessories, cfg.systemd.mode).await?;
+  if changes.is_empty() {
+    ok(&format!("units of {} are up to date", app));
+    return Ok(());
+  }
+  print_unit_changes(&changes);
+  if check {
+    anyhow::bail!("{} unit file(s) would change", changes.len());
+  }
+  log("the next deploy writes these changes");
+  Ok(())
+}
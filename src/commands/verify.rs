@@ -0,0 +1,70 @@
+use anyhow::Result;
+use clap::Args;
+use hl::{
+  config::{app_dir, build_env_file, env_file, load_config, systemd_dir},
+  discovery::{discover_accessories, discover_processes},
+  doctor::{print_report, unit_file_checks, Check},
+  env::load_env_file_contents,
+  git::infer_app_name,
+  log::*,
+  releases::{load_releases, releases_dir},
+  verify::{compose_checks, env_checks, image_check, route_check},
+};
+
+#[derive(Args)]
+pub struct VerifyArgs {}
+
+pub async fn execute(_args: VerifyArgs) -> Result<()> {
+  let app = infer_app_name().await?;
+  log(&format!("verifying {}", app));
+
+  let cfg = match load_config(&app).await {
+    Ok(cfg) => cfg,
+    Err(e) => {
+      print_report(&[Check::fail(
+        "hl.yml",
+        format!("{:#}", e),
+        "fix hl.yml; nothing else can be checked without it",
+      )]);
+      anyhow::bail!("{} is not coherent", app);
+    }
+  };
+  let mut checks = vec![Check::pass("hl.yml", "parses")];
+
+  let dir = app_dir(&app);
+  let systemd_dir = systemd_dir();
+  let processes = discover_processes(&systemd_dir, &app)?;
+  let accessories = discover_accessories(&systemd_dir, &dir, &app, &processes)?;
+  let mut sidecars: Vec<String> = cfg.sidecars.keys().cloned().collect();
+  sidecars.sort();
+
+  checks.extend(compose_checks(&dir, &processes, &accessories, &sidecars));
+  checks.extend(unit_file_checks(
+    &app,
+    &systemd_dir,
+    &processes,
+    &accessories,
+  ));
+
+  let load = |path: std::path::PathBuf| {
+    if path.exists() {
+      load_env_file_contents(&path)
+    } else {
+      Ok(Default::default())
+    }
+  };
+  let env = load(env_file(&app))?;
+  let build_env = load(build_env_file(&app))?;
+  checks.extend(env_checks(&cfg, &env, &build_env, &accessories));
+
+  let releases = load_releases(&releases_dir(&app))?;
+  checks.push(image_check(&cfg, releases.first().map(|(_, r)| r)).await);
+  checks.push(route_check(&cfg).await);
+
+  let failures = print_report(&checks);
+  if failures > 0 {
+    anyhow::bail!("{} check(s) failed for {}", failures, app);
+  }
+  ok(&format!("{} is consistent", app));
+  Ok(())
+}
@@ -0,0 +1,362 @@
+use clap::{builder::ValueHint, Arg, Command, ValueEnum};
+
+/// Shells `hl completions` writes scripts for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+  Bash,
+  Zsh,
+  Fish,
+}
+
+/// Names the scripts look up at completion time with `hl completions --list <kind>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Names {
+  /// Apps on this host
+  Apps,
+  /// Processes and accessories of the current app
+  Services,
+  /// Accessory types `hl accessory add` knows, including those from plugins
+  AccessoryTypes,
+}
+
+/// Where the values of an argument come from.
+#[derive(Debug, Clone, PartialEq)]
+enum Source {
+  Words(Vec<String>),
+  Names(&'static str),
+  Files,
+  /// Free-form value: complete nothing rather than flags
+  Any,
+}
+
+/// One (sub)command, addressed by its words (e.g. `hl accessory add`).
+struct Node {
+  path: String,
+  /// (name or alias, path of the subcommand, about)
+  subcommands: Vec<(String, String, String)>,
+  /// (flag spellings, help, value source when the flag takes one)
+  flags: Vec<(Vec<String>, String, Option<Source>)>,
+  positional: Option<Source>,
+}
+
+/// Completion script for `shell`, generated from the CLI definition so it never drifts.
+pub fn render(shell: Shell, mut cmd: Command) -> String {
+  cmd.build();
+  let mut nodes = Vec::new();
+  collect(&cmd, cmd.get_name().to_string(), &mut nodes);
+  match shell {
+    Shell::Bash => render_bash(&nodes),
+    // zsh runs the bash function through its bash completion compatibility layer
+    Shell::Zsh => format!(
+      "autoload -U +X compinit && compinit\nautoload -U +X bashcompinit && bashcompinit\n{}",
+      render_bash(&nodes)
+    ),
+    Shell::Fish => render_fish(&nodes),
+  }
+}
+
+fn collect(cmd: &Command, path: String, nodes: &mut Vec<Node>) {
+  let mut node = Node {
+    path: path.clone(),
+    subcommands: Vec::new(),
+    flags: Vec::new(),
+    positional: None,
+  };
+  for arg in cmd.get_arguments().filter(|a| !a.is_hide_set()) {
+    if arg.is_positional() {
+      if node.positional.is_none() {
+        node.positional = source(arg);
+      }
+      continue;
+    }
+    let mut spellings: Vec<String> = arg
+      .get_long_and_visible_aliases()
+      .unwrap_or_default()
+      .into_iter()
+      .map(|l| format!("--{}", l))
+      .collect();
+    if let Some(short) = arg.get_short() {
+      spellings.push(format!("-{}", short));
+    }
+    let value = arg
+      .get_action()
+      .takes_values()
+      .then(|| source(arg).unwrap_or(Source::Any));
+    node.flags.push((spellings, help(arg.get_help()), value));
+  }
+  let mut children = Vec::new();
+  for sub in cmd.get_subcommands().filter(|s| !s.is_hide_set()) {
+    let child = format!("{} {}", path, sub.get_name());
+    for name in std::iter::once(sub.get_name()).chain(sub.get_all_aliases()) {
+      node
+        .subcommands
+        .push((name.to_string(), child.clone(), help(sub.get_about())));
+    }
+    children.push((child, sub));
+  }
+  nodes.push(node);
+  for (child, sub) in children {
+    collect(sub, child, nodes);
+  }
+}
+
+fn source(arg: &Arg) -> Option<Source> {
+  let values: Vec<String> = arg
+    .get_possible_values()
+    .iter()
+    .filter(|v| !v.is_hide_set())
+    .map(|v| v.get_name().to_string())
+    .collect();
+  if !values.is_empty() && arg.get_action().takes_values() {
+    return Some(Source::Words(values));
+  }
+  match arg.get_id().as_str() {
+    "app" | "apps" => Some(Source::Names("apps")),
+    "service" => Some(Source::Names("services")),
+    "accessory" => Some(Source::Names("accessory-types")),
+    _ => match arg.get_value_hint() {
+      ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath => Some(Source::Files),
+      _ => None,
+    },
+  }
+}
+
+fn help(text: Option<&clap::builder::StyledStr>) -> String {
+  text
+    .map(|t| t.to_string().lines().next().unwrap_or_default().to_string())
+    .unwrap_or_default()
+}
+
+fn bash_compgen(source: &Source) -> String {
+  match source {
+    Source::Words(words) => format!("compgen -W \"{}\" -- \"$cur\"", words.join(" ")),
+    Source::Names(kind) => format!(
+      "compgen -W \"$(hl completions --list {} 2>/dev/null)\" -- \"$cur\"",
+      kind
+    ),
+    Source::Files => "compgen -f -- \"$cur\"".to_string(),
+    Source::Any => "true".to_string(),
+  }
+}
+
+fn render_bash(nodes: &[Node]) -> String {
+  let mut transitions = Vec::new();
+  let mut values = Vec::new();
+  let mut words = Vec::new();
+  let mut positionals = Vec::new();
+  for node in nodes {
+    for (name, target, _) in &node.subcommands {
+      transitions.push(format!(
+        "      \"{} {}\") path=\"{}\" ;;\n",
+        node.path, name, target
+      ));
+    }
+    for (spellings, _, value) in &node.flags {
+      if let Some(value) = value {
+        let patterns: Vec<String> = spellings
+          .iter()
+          .map(|s| format!("\"{} {}\"", node.path, s))
+          .collect();
+        values.push(format!(
+          "    {}) COMPREPLY=($({})); return ;;\n",
+          patterns.join("|"),
+          bash_compgen(value)
+        ));
+      }
+    }
+    let node_words: Vec<&str> = node
+      .subcommands
+      .iter()
+      .map(|(n, _, _)| n.as_str())
+      .chain(
+        node
+          .flags
+          .iter()
+          .flat_map(|(s, _, _)| s.iter().map(String::as_str)),
+      )
+      .collect();
+    words.push(format!(
+      "    \"{}\") words=\"{}\" ;;\n",
+      node.path,
+      node_words.join(" ")
+    ));
+    if let Some(positional) = &node.positional {
+      positionals.push(format!(
+        "      \"{}\") COMPREPLY+=($({})) ;;\n",
+        node.path,
+        bash_compgen(positional)
+      ));
+    }
+  }
+
+  format!(
+    r#"_hl() {{
+  local cur prev path words i
+  cur="${{COMP_WORDS[COMP_CWORD]}}"
+  prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+  path="hl"
+  for ((i = 1; i < COMP_CWORD; i++)); do
+    case "$path ${{COMP_WORDS[i]}}" in
+{transitions}    esac
+  done
+
+  case "$path $prev" in
+{values}  esac
+
+  words=""
+  case "$path" in
+{words}  esac
+  COMPREPLY=($(compgen -W "$words" -- "$cur"))
+  if [[ "$cur" != -* ]]; then
+    case "$path" in
+{positionals}    esac
+  fi
+}}
+complete -o default -F _hl hl
+"#,
+    transitions = transitions.concat(),
+    values = values.concat(),
+    words = words.concat(),
+    positionals = positionals.concat(),
+  )
+}
+
+fn fish_quote(s: &str) -> String {
+  format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish_values(source: &Source) -> String {
+  match source {
+    Source::Words(words) => format!(" -x -a {}", fish_quote(&words.join(" "))),
+    Source::Names(kind) => format!(
+      " -x -a {}",
+      fish_quote(&format!("(hl completions --list {} 2>/dev/null)", kind))
+    ),
+    Source::Files => " -r -F".to_string(),
+    Source::Any => " -x".to_string(),
+  }
+}
+
+fn render_fish(nodes: &[Node]) -> String {
+  let mut transitions = Vec::new();
+  let mut lines = Vec::new();
+  for node in nodes {
+    let condition = fish_quote(&format!("test (__hl_path) = \"{}\"", node.path));
+    for (name, target, about) in &node.subcommands {
+      // Aliases lead to the path of the subcommand they stand for
+      transitions.push(format!(
+        "      case {}\n        set path {}\n",
+        fish_quote(&format!("{} {}", node.path, name)),
+        fish_quote(target)
+      ));
+      lines.push(format!(
+        "complete -c hl -n {} -a {} -d {}\n",
+        condition,
+        fish_quote(name),
+        fish_quote(about)
+      ));
+    }
+    for (spellings, about, value) in &node.flags {
+      let mut line = format!("complete -c hl -n {}", condition);
+      for spelling in spellings {
+        match spelling.strip_prefix("--") {
+          Some(long) => line.push_str(&format!(" -l {}", long)),
+          None => line.push_str(&format!(" -s {}", spelling.trim_start_matches('-'))),
+        }
+      }
+      if let Some(value) = value {
+        line.push_str(&fish_values(value));
+      }
+      line.push_str(&format!(" -d {}\n", fish_quote(about)));
+      lines.push(line);
+    }
+    if let Some(positional) = &node.positional {
+      lines.push(format!(
+        "complete -c hl -n {}{}\n",
+        condition,
+        fish_values(positional)
+      ));
+    }
+  }
+
+  format!(
+    r#"function __hl_path
+  set -l path hl
+  for word in (commandline -opc)[2..-1]
+    switch "$path $word"
+{transitions}    end
+  end
+  echo $path
+end
+
+complete -c hl -f
+{lines}"#,
+    transitions = transitions.concat(),
+    lines = lines.concat(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use clap::{Arg, ArgAction, Command};
+
+  fn cli() -> Command {
+    Command::new("hl")
+      .arg(
+        Arg::new("verbose")
+          .short('v')
+          .long("verbose")
+          .global(true)
+          .action(ArgAction::SetTrue),
+      )
+      .subcommand(
+        Command::new("accessory")
+          .about("Manage accessories")
+          .subcommand(Command::new("add").arg(Arg::new("accessory"))),
+      )
+      .subcommand(
+        Command::new("exec")
+          .arg(Arg::new("service").long("service").short('s'))
+          .arg(Arg::new("app").long("app")),
+      )
+      .subcommand(
+        Command::new("status").alias("ps").arg(
+          Arg::new("output")
+            .long("output")
+            .value_parser(["text", "json"]),
+        ),
+      )
+  }
+
+  #[test]
+  fn test_render_bash() {
+    let script = render(Shell::Bash, cli());
+    assert!(script.contains("      \"hl accessory\") path=\"hl accessory\" ;;\n"));
+    assert!(script.contains("      \"hl ps\") path=\"hl status\" ;;\n"));
+    assert!(script.contains(
+      "    \"hl exec --service\"|\"hl exec -s\") COMPREPLY=($(compgen -W \"$(hl completions --list services 2>/dev/null)\" -- \"$cur\")); return ;;\n"
+    ));
+    assert!(script.contains(
+      "    \"hl status --output\") COMPREPLY=($(compgen -W \"text json\" -- \"$cur\")); return ;;\n"
+    ));
+    assert!(script.contains(
+      "      \"hl accessory add\") COMPREPLY+=($(compgen -W \"$(hl completions --list accessory-types 2>/dev/null)\" -- \"$cur\")) ;;\n"
+    ));
+    // Global flags reach subcommands
+    assert!(script.contains("    \"hl exec\") words=\"--service -s --app --verbose -v"));
+    assert!(script.ends_with("complete -o default -F _hl hl\n"));
+  }
+
+  #[test]
+  fn test_render_fish() {
+    let script = render(Shell::Fish, cli());
+    assert!(script.contains("      case 'hl ps'\n        set path 'hl status'\n"));
+    assert!(script.contains(
+      "complete -c hl -n 'test (__hl_path) = \"hl\"' -a 'accessory' -d 'Manage accessories'\n"
+    ));
+    assert!(script.contains(
+      "complete -c hl -n 'test (__hl_path) = \"hl exec\"' -l app -x -a '(hl completions --list apps 2>/dev/null)'"
+    ));
+  }
+}
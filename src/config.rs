@@ -1,16 +1,122 @@
-use crate::log::debug;
+use crate::error::Error;
+use crate::log::{debug, warn};
 use anyhow::{Context, Result};
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
 use tokio::fs;
 
+/// The global config as path and systemd helpers see it, read once per process.
+static HOST: OnceLock<GlobalConfig> = OnceLock::new();
+
+fn host_config() -> &'static GlobalConfig {
+  HOST.get_or_init(|| {
+    let path = global_config_path();
+    let Ok(content) = std::fs::read_to_string(&path) else {
+      return GlobalConfig::default();
+    };
+    match serde_yaml::from_str::<GlobalConfig>(&content) {
+      Ok(global) => global,
+      Err(e) => {
+        warn(&format!("ignoring {}: {}", path.display(), e));
+        GlobalConfig::default()
+      }
+    }
+  })
+}
+
+fn configured_paths() -> &'static PathsConfig {
+  &host_config().paths
+}
+
+/// Which systemd manager runs the app units on this host (`systemd.scope` in the global config).
+pub fn systemd_scope() -> SystemdScope {
+  host_config().systemd.scope
+}
+
+/// Account system units run as: the deploy user. None when units are user units.
+pub fn system_unit_user() -> Result<Option<String>> {
+  match systemd_scope() {
+    SystemdScope::User => Ok(None),
+    SystemdScope::System => pick_unit_user(
+      host_config().systemd.user.as_deref(),
+      std::env::var("SUDO_USER").ok().as_deref(),
+      std::env::var("USER").ok().as_deref(),
+    )
+    .map(Some),
+  }
+}
+
+/// `systemd.user`, else whoever ran `sudo hl ...`, else the user running hl; never root.
+fn pick_unit_user(
+  configured: Option<&str>,
+  sudo_user: Option<&str>,
+  user: Option<&str>,
+) -> Result<String> {
+  fn non_empty(name: Option<&str>) -> Option<&str> {
+    name.map(str::trim).filter(|name| !name.is_empty())
+  }
+  let user = non_empty(configured)
+    .or(non_empty(sudo_user).filter(|name| *name != "root"))
+    .or(non_empty(user))
+    .context("set systemd.user in the global config to the account app units run as")?;
+  if user == "root" {
+    anyhow::bail!(
+      "system units would run as root; set systemd.user in the global config to the deploy user"
+    );
+  }
+  Ok(user.to_string())
+}
+
+/// Home directory of `user` in an /etc/passwd listing.
+fn passwd_home(passwd: &str, user: &str) -> Option<PathBuf> {
+  passwd.lines().find_map(|line| {
+    let fields: Vec<&str> = line.split(':').collect();
+    (fields.len() >= 7 && fields[0] == user).then(|| PathBuf::from(fields[5]))
+  })
+}
+
+/// Home of the deploy user, which holds `~/hl` and the hl binary: the system unit user's home
+/// from /etc/passwd (hl may run as root), else `$HOME`.
+pub fn deploy_home() -> PathBuf {
+  let Ok(Some(user)) = system_unit_user() else {
+    return home_dir();
+  };
+  std::fs::read_to_string("/etc/passwd")
+    .ok()
+    .and_then(|passwd| passwd_home(&passwd, &user))
+    .unwrap_or_else(home_dir)
+}
+
+/// Expand a leading `~/` to the deploy user's home directory.
+fn expand_home(path: &str) -> PathBuf {
+  match path.strip_prefix("~/") {
+    Some(rest) => deploy_home().join(rest),
+    None => PathBuf::from(path),
+  }
+}
+
+/// `paths.root` as written in the global config.
+pub fn configured_root() -> Option<String> {
+  configured_paths().root.clone()
+}
+
+/// Base directory holding `apps/` and `git/`: `paths.root` or `~/hl`.
+fn hl_base() -> PathBuf {
+  match &configured_paths().root {
+    Some(root) => expand_home(root),
+    None => deploy_home().join("hl"),
+  }
+}
+
 pub fn hl_root() -> PathBuf {
   // Allow overriding for tests
   if let Ok(override_root) = std::env::var("HL_ROOT_OVERRIDE") {
     return PathBuf::from(override_root);
   }
-  home_dir().join("hl").join("apps")
+  hl_base().join("apps")
 }
 
 pub fn home_dir() -> PathBuf {
@@ -18,19 +124,32 @@ pub fn home_dir() -> PathBuf {
   PathBuf::from(home)
 }
 
+/// Directory holding the apps' bare repositories.
+pub fn hl_git_dir() -> PathBuf {
+  hl_base().join("git")
+}
+
 pub fn hl_git_root(app: &str) -> PathBuf {
-  home_dir()
-    .join("hl")
-    .join("git")
-    .join(format!("{}.git", app))
+  hl_git_dir().join(format!("{}.git", app))
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Audit log of operations that changed something on the host (`hl events`).
+pub fn events_file() -> PathBuf {
+  hl_base().join("events.jsonl")
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct HLConfig {
   pub app: String,
   pub image: String,
   pub domain: String,
+  /// More hosts routed to the app besides `domain` (e.g. www.example.com)
+  #[serde(default)]
+  pub domains: Vec<String>,
+  /// Permanently redirect `www.` hosts to the same URL without the `www.`
+  #[serde(default)]
+  pub redirect_www: bool,
   pub service_port: u16,
   #[serde(default = "default_resolver")]
   pub resolver: String,
@@ -41,27 +160,660 @@ pub struct HLConfig {
   pub health: HealthConfig,
   #[serde(default)]
   pub migrations: MigrationsConfig,
+  /// Build secret ids resolved through `secretsProvider` at deploy time
   #[serde(default)]
   pub secrets: Vec<String>,
+  #[serde(default, alias = "secrets_provider")]
+  pub secrets_provider: Option<SecretsProviderConfig>,
   #[serde(default)]
-  pub volumes: Vec<String>,
+  pub volumes: VolumesConfig,
+  /// Named volume mounted into every process (e.g. SQLite under /rails/storage)
+  #[serde(default)]
+  pub persistence: Option<PersistenceConfig>,
+  #[serde(default)]
+  pub build: BuildConfig,
+  #[serde(default)]
+  pub deploy: DeployConfig,
+  /// Per-accessory overrides, keyed by accessory name (postgres, redis, ...)
+  #[serde(default, serialize_with = "sorted_map")]
+  pub accessories: HashMap<String, AccessoryConfig>,
+  /// Per-process settings, keyed by Procfile process name
+  #[serde(default, serialize_with = "sorted_map")]
+  pub processes: HashMap<String, ProcessConfig>,
+  /// Extra processes generated from built-in templates, keyed by process name
+  #[serde(default, serialize_with = "sorted_map")]
+  pub sidecars: HashMap<String, SidecarConfig>,
+  #[serde(default)]
+  pub systemd: SystemdConfig,
+  #[serde(default)]
+  pub notifications: NotificationsConfig,
+  /// Scheduled one-off containers of the app image, run by systemd timers
+  #[serde(default)]
+  pub cron: Vec<CronJob>,
+  #[serde(default)]
+  pub console: Option<ConsoleConfig>,
+  /// Docker log driver of the app's containers; unset keeps the daemon's default
+  #[serde(default)]
+  pub logging: Option<LoggingConfig>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// `logging:` in hl.yml, rendered as the compose `logging:` of every process.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LoggingConfig {
+  /// Defaults to `loki` when `loki` is set, else `json-file`
+  #[serde(default)]
+  pub driver: Option<String>,
+  /// Rotate a container's log at this size, e.g. `10m` (json-file and local drivers)
+  #[serde(default)]
+  pub max_size: Option<String>,
+  /// Rotated files kept per container
+  #[serde(default)]
+  pub max_file: Option<u32>,
+  /// Loki push endpoint, e.g. `https://loki.example.com/loki/api/v1/push` (needs the
+  /// grafana/loki-docker-driver plugin)
+  #[serde(default)]
+  pub loki: Option<String>,
+  /// Any other driver options, passed through as-is
+  #[serde(default)]
+  pub options: BTreeMap<String, String>,
+}
+
+impl LoggingConfig {
+  pub fn driver(&self) -> &str {
+    match (&self.driver, &self.loki) {
+      (Some(driver), _) => driver,
+      (None, Some(_)) => "loki",
+      (None, None) => "json-file",
+    }
+  }
+
+  /// Driver options, keyed the way docker spells them.
+  pub fn driver_options(&self) -> BTreeMap<String, String> {
+    let mut options = self.options.clone();
+    if let Some(max_size) = &self.max_size {
+      options.insert("max-size".into(), max_size.clone());
+    }
+    if let Some(max_file) = self.max_file {
+      options.insert("max-file".into(), max_file.to_string());
+    }
+    if let Some(loki) = &self.loki {
+      options.insert("loki-url".into(), loki.clone());
+    }
+    options
+  }
+}
+
+/// What `hl console` runs.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConsoleConfig {
+  /// e.g. `[bin/rails, console]` or `[python, manage.py, shell]`
+  pub command: Vec<String>,
+}
+
+/// A `cron:` entry, rendered into `app-<app>-cron-<name>.service` and `.timer`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CronJob {
+  pub name: String,
+  /// systemd calendar expression, e.g. `daily` or `*-*-* 03:30:00`
+  pub schedule: String,
+  pub command: Vec<String>,
+}
+
+/// Where `secrets:` come from instead of plaintext `.env.build`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SecretsProviderConfig {
+  #[serde(rename = "type")]
+  pub kind: SecretsProvider,
+  /// op: secret reference with a `{key}` placeholder, e.g. `op://Production/myapp/{key}`
+  #[serde(default)]
+  pub reference: Option<String>,
+  /// vault: KV path holding one field per secret, e.g. `secret/myapp`
+  #[serde(default)]
+  pub path: Option<String>,
+  /// sops: encrypted dotenv file, relative to the app dir
+  #[serde(default)]
+  pub file: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretsProvider {
+  Op,
+  Vault,
+  Sops,
+}
+
+/// Where deploy results are posted.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct NotificationsConfig {
+  /// URLs that receive a JSON POST when a deploy succeeds or fails
+  #[serde(default)]
+  pub webhooks: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SystemdConfig {
+  #[serde(default)]
+  pub mode: SystemdMode,
+}
+
+/// How process units run their container.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemdMode {
+  /// `up -d` in a oneshot unit that stays active; docker's restart policy supervises
+  #[default]
+  Oneshot,
+  /// `up` in the foreground (Type=exec); systemd supervises and the journal gets the logs
+  Attached,
+}
+
+/// `processes.<name>` in hl.yml: hardware a Procfile process needs.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ProcessConfig {
+  /// NVIDIA GPUs to reserve: `all`, a count, or a list of device indexes/UUIDs
+  #[serde(default)]
+  pub gpus: Option<GpuRequest>,
+  /// Host devices to pass through, e.g. `/dev/dri:/dev/dri` for VAAPI
+  #[serde(default)]
+  pub devices: Vec<String>,
+  /// Dockerfile (relative to the repository root) building this process its own image,
+  /// `<image>-<process>`, instead of running the app's
+  #[serde(default)]
+  pub dockerfile: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum GpuRequest {
+  Count(u32),
+  Ids(Vec<GpuId>),
+  /// `all`, or a single device id
+  Named(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum GpuId {
+  Index(u32),
+  Uuid(String),
+}
+
+impl std::fmt::Display for GpuId {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      GpuId::Index(index) => write!(f, "{}", index),
+      GpuId::Uuid(uuid) => f.write_str(uuid),
+    }
+  }
+}
+
+/// A process generated from a built-in template instead of the Procfile.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SidecarConfig {
+  pub template: SidecarTemplate,
+  /// Image override (defaults: nginx:alpine for nginx-static, the app image otherwise)
+  #[serde(default)]
+  pub image: Option<String>,
+  /// Command to run (queue-dashboard)
+  #[serde(default)]
+  pub command: Option<String>,
+  /// URL prefix routed to the sidecar (nginx-static, queue-dashboard)
+  #[serde(default)]
+  pub path_prefix: Option<String>,
+  /// Container port behind `pathPrefix` (queue-dashboard)
+  #[serde(default)]
+  pub port: Option<u16>,
+  /// Directory under the app dir to serve (nginx-static)
+  #[serde(default)]
+  pub root: Option<String>,
+  /// Crontab path inside the image (cron-runner)
+  #[serde(default)]
+  pub crontab: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SidecarTemplate {
+  NginxStatic,
+  CronRunner,
+  QueueDashboard,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessoryConfig {
+  /// Image repository to pull instead of Docker Hub, e.g. `mirror.local/library/postgres`
+  #[serde(default)]
+  pub image: Option<String>,
+  #[serde(default)]
+  pub pull_secret: Option<PullSecret>,
+  /// How long deploys wait for the accessory to accept connections, e.g. `2m`; default 60s
+  #[serde(default)]
+  pub ready_timeout: Option<String>,
+}
+
+/// Registry credentials used to pull an accessory image. The password is read from the
+/// app's `.env` so it never lives in hl.yml.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PullSecret {
+  pub registry: String,
+  pub username: String,
+  pub password_env: String,
+}
+
+impl HLConfig {
+  /// Traefik rule matching every host of the app. `primary` stands for `domain`: `${DOMAIN}`
+  /// in compose files (resolved from .env), the literal domain elsewhere.
+  pub fn host_rule(&self, primary: &str) -> String {
+    std::iter::once(primary)
+      .chain(
+        self
+          .domains
+          .iter()
+          .filter(|d| **d != self.domain)
+          .map(String::as_str),
+      )
+      .map(|host| format!("Host(`{}`)", host))
+      .collect::<Vec<_>>()
+      .join(" || ")
+  }
+
+  /// Docker volume backing `persistence:`, if configured.
+  pub fn persistence_volume(&self) -> Option<String> {
+    self
+      .persistence
+      .as_ref()
+      .map(|p| format!("{}-{}", self.app, p.name))
+  }
+
+  /// `volumes:` names become directories under the app dir and `persistence.name` part of a
+  /// docker volume name, so both must be plain names (docker's own volume name rule).
+  pub fn validate_volume_names(&self) -> Result<()> {
+    let named = match &self.volumes {
+      VolumesConfig::Named(named) => named.keys().map(|name| ("volumes", name)).collect(),
+      VolumesConfig::Mounts(_) => Vec::new(),
+    };
+    let persistence = self
+      .persistence
+      .iter()
+      .map(|p| ("persistence.name", &p.name));
+    for (key, name) in named.into_iter().chain(persistence) {
+      let mut chars = name.chars();
+      let valid = chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+      if !valid {
+        anyhow::bail!(
+          "invalid {} name {:?}: use letters, digits, '_', '.' and '-', starting with a letter or digit",
+          key,
+          name
+        );
+      }
+    }
+    Ok(())
+  }
+
+  /// Host directories backing a `volumes:` map, created before deploying.
+  pub fn volume_dirs(&self) -> Vec<PathBuf> {
+    match &self.volumes {
+      VolumesConfig::Mounts(_) => Vec::new(),
+      VolumesConfig::Named(named) => named
+        .keys()
+        .map(|name| app_dir(&self.app).join(APP_VOLUMES_DIR).join(name))
+        .collect(),
+    }
+  }
+
+  /// Mounts for the base compose service: `volumes:` plus the persistence volume.
+  pub fn base_volumes(&self) -> Vec<String> {
+    let mut volumes = match &self.volumes {
+      VolumesConfig::Mounts(mounts) => mounts.clone(),
+      VolumesConfig::Named(named) => named
+        .iter()
+        .map(|(name, path)| format!("./{}/{}:{}", APP_VOLUMES_DIR, name, path))
+        .collect(),
+    };
+    if let (Some(p), Some(volume)) = (&self.persistence, self.persistence_volume()) {
+      volumes.push(format!("{}:{}", volume, p.path));
+    }
+    volumes
+  }
+
+  /// Processes built from their own Dockerfile, sorted, with that Dockerfile's path.
+  pub fn process_dockerfiles(&self) -> Vec<(String, String)> {
+    let mut dockerfiles: Vec<(String, String)> = self
+      .processes
+      .iter()
+      .filter_map(|(name, p)| Some((name.clone(), p.dockerfile.clone()?)))
+      .collect();
+    dockerfiles.sort();
+    dockerfiles
+  }
+
+  /// Repository of the image a process with its own Dockerfile runs.
+  pub fn process_image(&self, process: &str) -> String {
+    format!("{}-{}", self.image, process)
+  }
+
+  /// Image reference for an accessory, honoring any repository override in hl.yml.
+  pub fn accessory_image(&self, accessory: &str, default_repo: &str, version: &str) -> String {
+    let repo = self
+      .accessories
+      .get(accessory)
+      .and_then(|a| a.image.as_deref())
+      .unwrap_or(default_repo);
+    format!("{}:{}", repo, version)
+  }
+
+  /// How long to wait for an accessory to become ready, from `accessories.<name>.readyTimeout`.
+  pub fn accessory_ready_timeout(&self, accessory: &str) -> Result<Duration> {
+    let timeout = self
+      .accessories
+      .get(accessory)
+      .and_then(|a| a.ready_timeout.as_deref())
+      .unwrap_or("60s");
+    let ms = parse_duration(timeout)
+      .with_context(|| format!("invalid accessories.{}.readyTimeout", accessory))?;
+    Ok(Duration::from_millis(ms))
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct HealthConfig {
   pub url: String,
   #[serde(default = "default_interval")]
   pub interval: String,
   #[serde(default = "default_timeout")]
   pub timeout: String,
+  /// Serve 503s from a sidecar while processes restart, until the health gate passes
+  #[serde(default)]
+  pub gate: bool,
+  /// Also give the web container a docker healthcheck probing `url` from inside it (needs
+  /// curl or wget in the image; Traefik stops routing to containers that turn unhealthy)
+  #[serde(default)]
+  pub container: bool,
+  /// Status codes that count as healthy, e.g. `200-299` or `200,204`; default: below 400
+  #[serde(default)]
+  pub status: Option<String>,
+  /// Text the response body must contain
+  #[serde(default)]
+  pub body: Option<String>,
+  /// Regex the response body must match
+  #[serde(default)]
+  pub body_regex: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Migrations as they run: `command` and `env` already include the preset's.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(from = "RawMigrationsConfig")]
 pub struct MigrationsConfig {
-  #[serde(default = "default_migration_command")]
+  pub preset: Option<MigrationPreset>,
   pub command: Vec<String>,
-  #[serde(default)]
+  #[serde(serialize_with = "sorted_map")]
   pub env: HashMap<String, String>,
+  /// User (name or UID[:GID]) to run migrations as instead of the image default
+  pub user: Option<String>,
+  /// Working directory inside the container instead of the image WORKDIR
+  pub workdir: Option<String>,
+  /// Extra mounts for the migration container only; `./` paths are relative to the app dir
+  pub volumes: Vec<String>,
+}
+
+/// `migrations:` as written in hl.yml.
+#[derive(Deserialize)]
+struct RawMigrationsConfig {
+  #[serde(default)]
+  preset: Option<MigrationPreset>,
+  #[serde(default)]
+  command: Option<Vec<String>>,
+  #[serde(default)]
+  env: HashMap<String, String>,
+  #[serde(default)]
+  user: Option<String>,
+  #[serde(default)]
+  workdir: Option<String>,
+  #[serde(default)]
+  volumes: Vec<String>,
+}
+
+impl From<RawMigrationsConfig> for MigrationsConfig {
+  /// An explicit `command` wins over the preset's, and `env` entries over the preset's
+  /// entries. Without a preset, the command defaults to `bin/rails db:migrate` as it always has.
+  fn from(raw: RawMigrationsConfig) -> Self {
+    let (preset_command, mut env) = match raw.preset {
+      Some(preset) => (preset.command(), preset.env()),
+      None => (default_migration_command(), HashMap::new()),
+    };
+    env.extend(raw.env);
+    Self {
+      preset: raw.preset,
+      command: raw.command.unwrap_or(preset_command),
+      env,
+      user: raw.user,
+      workdir: raw.workdir,
+      volumes: raw.volumes,
+    }
+  }
+}
+
+/// Framework defaults for the migration command and environment.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MigrationPreset {
+  Rails,
+  Django,
+  Laravel,
+  NodePrisma,
+  /// Don't run migrations
+  None,
+}
+
+impl MigrationPreset {
+  pub fn command(&self) -> Vec<String> {
+    let command: &[&str] = match self {
+      MigrationPreset::Rails => &["bin/rails", "db:migrate"],
+      MigrationPreset::Django => &["python", "manage.py", "migrate", "--noinput"],
+      MigrationPreset::Laravel => &["php", "artisan", "migrate", "--force"],
+      MigrationPreset::NodePrisma => &["npx", "prisma", "migrate", "deploy"],
+      MigrationPreset::None => &[],
+    };
+    command.iter().map(|s| s.to_string()).collect()
+  }
+
+  pub fn env(&self) -> HashMap<String, String> {
+    let env: &[(&str, &str)] = match self {
+      MigrationPreset::Rails => &[("RAILS_ENV", "production")],
+      MigrationPreset::Laravel => &[("APP_ENV", "production")],
+      MigrationPreset::Django | MigrationPreset::NodePrisma | MigrationPreset::None => &[],
+    };
+    env
+      .iter()
+      .map(|(k, v)| (k.to_string(), v.to_string()))
+      .collect()
+  }
+}
+
+/// Directory under the app dir holding the host side of a `volumes:` map.
+pub const APP_VOLUMES_DIR: &str = "volumes";
+
+/// `volumes:` in hl.yml, mounted into every process.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum VolumesConfig {
+  /// Compose mount strings, passed through as-is (`./uploads:/app/uploads`)
+  Mounts(Vec<String>),
+  /// Container paths keyed by name (`uploads: /app/storage`), bind-mounted from
+  /// `<app dir>/volumes/<name>` so they survive deploys and `hl teardown --keep-data`
+  Named(BTreeMap<String, String>),
+}
+
+impl Default for VolumesConfig {
+  fn default() -> Self {
+    VolumesConfig::Mounts(Vec::new())
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PersistenceConfig {
+  /// Mount path inside the containers
+  pub path: String,
+  /// Volume name suffix; the docker volume is `<app>-<name>`
+  #[serde(default = "default_persistence_name")]
+  pub name: String,
+}
+
+fn default_persistence_name() -> String {
+  "data".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BuildConfig {
+  /// Passed to buildx as `--build-arg KEY=VALUE` (GIT_SHA is always set)
+  #[serde(default, serialize_with = "sorted_map")]
+  pub args: HashMap<String, String>,
+  /// How many images (the app's and `processes.<name>.dockerfile` ones) build at once
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub concurrency: Option<usize>,
+}
+
+impl BuildConfig {
+  pub fn concurrency(&self) -> usize {
+    self.concurrency.unwrap_or(2).max(1)
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeployConfig {
+  #[serde(default)]
+  pub strategy: DeployStrategy,
+  /// Percentages of traffic sent to the new release, in order (gradual strategy only)
+  #[serde(default = "default_steps")]
+  pub steps: Vec<u8>,
+  /// How long to hold each step before health-checking and moving on
+  #[serde(default = "default_step_interval")]
+  pub step_interval: String,
+  /// Directory watched by Traefik's file provider; required for the gradual strategy since
+  /// weighted services can't be expressed with docker labels
+  #[serde(default)]
+  pub traefik_dynamic_dir: Option<String>,
+  /// What pushing a tag matching `tag_pattern` does
+  #[serde(default)]
+  pub tags: TagPushes,
+  /// Tags the post-receive hook deploys; `*` matches any run of characters
+  #[serde(default = "default_tag_pattern")]
+  pub tag_pattern: String,
+  /// Branches whose pushes deploy (`*` wildcards allowed); empty deploys every branch
+  #[serde(default)]
+  pub branches: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagPushes {
+  /// Deploy both branch pushes and matching tags
+  #[default]
+  Deploy,
+  /// Deploy branch pushes only
+  Ignore,
+  /// Deploy matching tags only; branch pushes just update the repository
+  Only,
+}
+
+impl DeployConfig {
+  /// Whether a push of `refname` (as the post-receive hook sees it, e.g. `refs/tags/v1.2.0`)
+  /// should deploy; Err says why not.
+  pub fn accepts_ref(&self, refname: &str) -> std::result::Result<(), String> {
+    if let Some(tag) = refname.strip_prefix("refs/tags/") {
+      if self.tags == TagPushes::Ignore {
+        return Err("tag pushes don't deploy (deploy.tags: ignore)".to_string());
+      }
+      if !glob_match(&self.tag_pattern, tag) {
+        return Err(format!(
+          "tag doesn't match deploy.tagPattern {:?}",
+          self.tag_pattern
+        ));
+      }
+    } else if self.tags == TagPushes::Only {
+      return Err("only tag pushes deploy (deploy.tags: only)".to_string());
+    } else if let Some(branch) = refname.strip_prefix("refs/heads/") {
+      if !self.branches.is_empty() && !self.branches.iter().any(|b| glob_match(b, branch)) {
+        return Err(format!(
+          "branch {} is not deployable (deploy.branches: {})",
+          branch,
+          self.branches.join(", ")
+        ));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Match `text` against a pattern where `*` stands for any run of characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+  let Some((first, rest)) = pattern.split_once('*') else {
+    return pattern == text;
+  };
+  let Some(mut remaining) = text.strip_prefix(first) else {
+    return false;
+  };
+  let mut parts: Vec<&str> = rest.split('*').collect();
+  let last = parts.pop().unwrap_or_default();
+  for part in parts {
+    match remaining.find(part) {
+      Some(at) => remaining = &remaining[at + part.len()..],
+      None => return false,
+    }
+  }
+  remaining.ends_with(last)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeployStrategy {
+  /// Restart processes in place, then health-gate
+  #[default]
+  Restart,
+  /// Shift traffic to the new release in weighted steps before restarting
+  Gradual,
+}
+
+impl Default for DeployConfig {
+  fn default() -> Self {
+    Self {
+      strategy: DeployStrategy::default(),
+      steps: default_steps(),
+      step_interval: default_step_interval(),
+      traefik_dynamic_dir: None,
+      tags: TagPushes::default(),
+      tag_pattern: default_tag_pattern(),
+      branches: Vec::new(),
+    }
+  }
+}
+
+/// Serialize a map with its keys in order, so `hl config show` output is stable.
+fn sorted_map<S: Serializer, V: Serialize>(
+  map: &HashMap<String, V>,
+  serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+  serializer.collect_map(map.iter().collect::<BTreeMap<_, _>>())
+}
+
+fn default_steps() -> Vec<u8> {
+  vec![10, 50, 100]
+}
+
+fn default_step_interval() -> String {
+  "30s".to_string()
+}
+
+fn default_tag_pattern() -> String {
+  "v*".to_string()
 }
 
 fn default_resolver() -> String {
@@ -91,25 +843,305 @@ fn default_migration_command() -> Vec<String> {
 impl Default for MigrationsConfig {
   fn default() -> Self {
     Self {
+      preset: None,
       command: default_migration_command(),
       env: HashMap::new(),
+      user: None,
+      workdir: None,
+      volumes: Vec::new(),
     }
   }
 }
 
+/// Host-level settings shared by every app, read from `~/.config/hl/config.yml`.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct GlobalConfig {
+  #[serde(default)]
+  pub retention: RetentionConfig,
+  /// Registry prefix; apps whose hl.yml has no `image` use `<registry>/<app>`
+  #[serde(default)]
+  pub registry: Option<String>,
+  /// Defaults for the hl.yml keys of the same name
+  #[serde(default)]
+  pub network: Option<String>,
+  #[serde(default)]
+  pub resolver: Option<String>,
+  #[serde(default)]
+  pub platforms: Option<String>,
+  #[serde(default)]
+  pub notifications: Option<NotificationsConfig>,
+  #[serde(default)]
+  pub metrics: MetricsConfig,
+  #[serde(default)]
+  pub paths: PathsConfig,
+  #[serde(default)]
+  pub systemd: HostSystemdConfig,
+  /// Workstation only: run commands on this server over SSH
+  #[serde(default)]
+  pub remote: Option<crate::remote::RemoteConfig>,
+}
+
+/// `systemd:` in the global config.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HostSystemdConfig {
+  #[serde(default)]
+  pub scope: SystemdScope,
+  /// Account system units run as (default: `$SUDO_USER`, else `$USER`)
+  #[serde(default)]
+  pub user: Option<String>,
+}
+
+/// Which systemd manager owns the app units.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemdScope {
+  /// The deploy user's manager (`systemctl --user`); needs lingering to run without a login
+  #[default]
+  User,
+  /// The system manager, with units in /etc/systemd/system that run as the deploy user
+  System,
+}
+
+/// `metrics:` in the global config.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsConfig {
+  /// node_exporter textfile collector directory; deploys refresh `hl.prom` there when set
+  #[serde(default)]
+  pub textfile_dir: Option<String>,
+}
+
+impl MetricsConfig {
+  pub fn textfile_dir(&self) -> Option<PathBuf> {
+    self.textfile_dir.as_deref().map(expand_home)
+  }
+}
+
+/// Where hl keeps its files, for hosts that put them on a data disk.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PathsConfig {
+  /// Replaces `~/hl`: apps live in `<root>/apps` and git repositories in `<root>/git`
+  #[serde(default)]
+  pub root: Option<String>,
+  /// Replaces `~/.config/systemd/user` (or /etc/systemd/system with `systemd.scope: system`);
+  /// must be a directory the systemd manager loads units from
+  #[serde(default)]
+  pub systemd_dir: Option<String>,
+}
+
+impl GlobalConfig {
+  /// hl.yml values every app inherits unless its own hl.yml sets them.
+  pub fn app_defaults(&self, app: &str) -> serde_yaml::Mapping {
+    let mut defaults = serde_yaml::Mapping::new();
+    if let Some(registry) = &self.registry {
+      defaults.insert(
+        "image".into(),
+        format!("{}/{}", registry.trim_end_matches('/'), app).into(),
+      );
+    }
+    for (key, value) in [
+      ("network", &self.network),
+      ("resolver", &self.resolver),
+      ("platforms", &self.platforms),
+    ] {
+      if let Some(value) = value {
+        defaults.insert(key.into(), value.clone().into());
+      }
+    }
+    if let Some(notifications) = &self.notifications {
+      if let Ok(value) = serde_yaml::to_value(notifications) {
+        defaults.insert("notifications".into(), value);
+      }
+    }
+    defaults
+  }
+}
+
+/// How many deploy artifacts `hl releases gc` keeps per app, newest first.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RetentionConfig {
+  /// Release manifests; images of pruned releases are removed along with them
+  #[serde(default = "default_keep_releases")]
+  pub releases: usize,
+  #[serde(default = "default_keep_logs")]
+  pub logs: usize,
+  #[serde(default = "default_keep_backups")]
+  pub backups: usize,
+  /// Age after which build contexts left in the temp dir by interrupted deploys are removed
+  #[serde(default = "default_worktree_max_age")]
+  pub worktrees: String,
+}
+
+impl Default for RetentionConfig {
+  fn default() -> Self {
+    Self {
+      releases: default_keep_releases(),
+      logs: default_keep_logs(),
+      backups: default_keep_backups(),
+      worktrees: default_worktree_max_age(),
+    }
+  }
+}
+
+fn default_keep_releases() -> usize {
+  10
+}
+
+fn default_keep_logs() -> usize {
+  20
+}
+
+fn default_keep_backups() -> usize {
+  7
+}
+
+fn default_worktree_max_age() -> String {
+  "1d".to_string()
+}
+
+pub fn global_config_path() -> PathBuf {
+  home_dir().join(".config/hl/config.yml")
+}
+
+/// Load the global config, falling back to defaults when the file doesn't exist.
+pub async fn load_global_config() -> Result<GlobalConfig> {
+  read_global_config()
+    .await
+    .map_err(|e| Error::Config(e).into())
+}
+
+async fn read_global_config() -> Result<GlobalConfig> {
+  let path = global_config_path();
+  if !path.exists() {
+    return Ok(GlobalConfig::default());
+  }
+  debug(&format!("loading global config from: {}", path.display()));
+
+  let content = fs::read_to_string(&path)
+    .await
+    .context(format!("Failed to read config file: {}", path.display()))?;
+  serde_yaml::from_str(&content).context(format!("Failed to parse config file: {}", path.display()))
+}
+
+/// Name an environment of an app is deployed under: `myapp` + `staging` is `myapp-staging`,
+/// with its own app dir, .env, compose project and units. No environment is the app itself.
+pub fn env_app_name(app: &str, env: Option<&str>) -> String {
+  match env {
+    Some(env) => format!("{}-{}", app, env),
+    None => app.to_string(),
+  }
+}
+
+/// `hl.<env>.yml`, the overlay applied on top of an app's hl.yml for an environment.
+pub fn env_overlay_path(app: &str, env: &str) -> PathBuf {
+  app_dir(app).join(format!("hl.{}.yml", env))
+}
+
+pub fn validate_env_name(env: &str) -> Result<()> {
+  if env.is_empty() || !env.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+    anyhow::bail!(
+      "invalid environment {:?}: only letters, digits and '_' are allowed",
+      env
+    );
+  }
+  Ok(())
+}
+
+/// Find the app and environment an instance name (`myapp-staging`) was derived from, by
+/// looking for a matching `hl.<env>.yml` overlay.
+pub fn split_env_app_name(name: &str) -> Option<(String, String)> {
+  name.match_indices('-').rev().find_map(|(i, _)| {
+    let (app, env) = (&name[..i], &name[i + 1..]);
+    env_overlay_path(app, env)
+      .exists()
+      .then(|| (app.to_string(), env.to_string()))
+  })
+}
+
+/// Load an app's hl.yml with `hl.<env>.yml` merged over it. Mappings merge key by key, any
+/// other value in the overlay replaces the base one, and `app` is always `<app>-<env>`.
+pub async fn load_env_config(app: &str, env: &str) -> Result<HLConfig> {
+  let base_path = app_dir(app).join("hl.yml");
+  let overlay_path = env_overlay_path(app, env);
+  debug(&format!(
+    "loading config from: {} + {}",
+    base_path.display(),
+    overlay_path.display()
+  ));
+
+  let mut merged = read_yaml(&base_path).await?;
+  if overlay_path.exists() {
+    merge_yaml(&mut merged, read_yaml(&overlay_path).await?);
+  }
+  if let serde_yaml::Value::Mapping(map) = &mut merged {
+    map.insert("app".into(), env_app_name(app, Some(env)).into());
+  }
+  resolve_config(merged).await.context(format!(
+    "Failed to parse config {} with {}",
+    base_path.display(),
+    overlay_path.display()
+  ))
+}
+
+/// Fill in the global config's defaults under an app's hl.yml and parse the result.
+pub async fn resolve_config(hl_yml: serde_yaml::Value) -> Result<HLConfig> {
+  let global = load_global_config().await?;
+  let app = hl_yml
+    .get("app")
+    .and_then(|v| v.as_str())
+    .unwrap_or_default();
+  let mut merged = serde_yaml::Value::Mapping(global.app_defaults(app));
+  merge_yaml(&mut merged, hl_yml);
+  let config: HLConfig = serde_yaml::from_value(merged)?;
+  config.validate_volume_names()?;
+  Ok(config)
+}
+
+async fn read_yaml(path: &std::path::Path) -> Result<serde_yaml::Value> {
+  if !path.exists() {
+    anyhow::bail!("Config file not found at: {}", path.display());
+  }
+  let content = fs::read_to_string(path)
+    .await
+    .context(format!("Failed to read config file: {}", path.display()))?;
+  serde_yaml::from_str(&content).context(format!("Failed to parse config file: {}", path.display()))
+}
+
+fn merge_yaml(base: &mut serde_yaml::Value, overlay: serde_yaml::Value) {
+  match (base, overlay) {
+    (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) => {
+      for (key, value) in overlay {
+        match base.get_mut(&key) {
+          Some(existing) => merge_yaml(existing, value),
+          None => {
+            base.insert(key, value);
+          }
+        }
+      }
+    }
+    (base, overlay) => *base = overlay,
+  }
+}
+
 pub async fn load_config(app: &str) -> Result<HLConfig> {
+  read_config(app).await.map_err(|e| Error::Config(e).into())
+}
+
+async fn read_config(app: &str) -> Result<HLConfig> {
   let path = app_dir(app).join("hl.yml");
   debug(&format!("loading config from: {}", path.display()));
 
   if !path.exists() {
+    // Environments have no hl.yml of their own
+    if let Some((base, env)) = split_env_app_name(app) {
+      return load_env_config(&base, &env).await;
+    }
     anyhow::bail!("Config file not found at: {}", path.display());
   }
 
-  let content = fs::read_to_string(&path)
+  let config = resolve_config(read_yaml(&path).await?)
     .await
-    .context(format!("Failed to read config file: {}", path.display()))?;
-
-  let config: HLConfig = serde_yaml::from_str(&content)
     .context(format!("Failed to parse config file: {}", path.display()))?;
 
   debug(&format!(
@@ -135,12 +1167,16 @@ pub fn build_env_file(app: &str) -> PathBuf {
 }
 
 pub fn systemd_dir() -> PathBuf {
-  home_dir().join(".config/systemd/user")
+  match (&configured_paths().systemd_dir, systemd_scope()) {
+    (Some(dir), _) => expand_home(dir),
+    (None, SystemdScope::User) => home_dir().join(".config/systemd/user"),
+    (None, SystemdScope::System) => PathBuf::from("/etc/systemd/system"),
+  }
 }
 
 /// Parse duration strings like "2s", "45s", "100ms" into milliseconds
 pub fn parse_duration(s: &str) -> Result<u64> {
-  let re = regex::Regex::new(r"^(\d+)(ms|s|m)$")?;
+  let re = regex::Regex::new(r"^(\d+)(ms|s|m|h|d)$")?;
   let caps = re
     .captures(s)
     .ok_or_else(|| anyhow::anyhow!("bad duration: {}", s))?;
@@ -152,8 +1188,316 @@ pub fn parse_duration(s: &str) -> Result<u64> {
     "ms" => n,
     "s" => n * 1000,
     "m" => n * 60_000,
+    "h" => n * 3_600_000,
+    "d" => n * 86_400_000,
     _ => anyhow::bail!("bad duration: {}", s),
   };
 
   Ok(ms)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_pick_unit_user() {
+    assert_eq!(
+      pick_unit_user(Some("deploy"), Some("alice"), Some("root")).unwrap(),
+      "deploy"
+    );
+    // `sudo hl ...` writes units for whoever ran sudo, not for root
+    assert_eq!(
+      pick_unit_user(None, Some("alice"), Some("root")).unwrap(),
+      "alice"
+    );
+    assert_eq!(pick_unit_user(None, None, Some("bob")).unwrap(), "bob");
+    assert!(pick_unit_user(None, Some("root"), Some("root")).is_err());
+    assert!(pick_unit_user(Some(" "), None, None).is_err());
+  }
+
+  #[test]
+  fn test_passwd_home() {
+    let passwd = "root:x:0:0:root:/root:/bin/bash\n\
+                  deploy:x:1000:1000:Deploy,,,:/srv/deploy:/bin/bash\n";
+    assert_eq!(
+      passwd_home(passwd, "deploy"),
+      Some(PathBuf::from("/srv/deploy"))
+    );
+    assert_eq!(passwd_home(passwd, "dep"), None);
+  }
+  use serial_test::serial;
+
+  #[test]
+  fn test_deploy_accepts_ref() {
+    let deploy = DeployConfig::default();
+    assert!(deploy.accepts_ref("refs/heads/main").is_ok());
+    assert!(deploy.accepts_ref("refs/tags/v1.4.2").is_ok());
+    assert!(deploy.accepts_ref("refs/tags/nightly").is_err());
+
+    let deploy: DeployConfig =
+      serde_yaml::from_str("tags: only\ntagPattern: release-*-final").unwrap();
+    assert!(deploy.accepts_ref("refs/tags/release-7-final").is_ok());
+    assert!(deploy.accepts_ref("refs/tags/release-7").is_err());
+    assert!(deploy.accepts_ref("refs/heads/main").is_err());
+
+    let deploy: DeployConfig = serde_yaml::from_str("tags: ignore").unwrap();
+    assert!(deploy.accepts_ref("refs/tags/v1.4.2").is_err());
+    assert!(deploy.accepts_ref("refs/heads/main").is_ok());
+
+    let deploy: DeployConfig = serde_yaml::from_str("branches: [main, release/*]").unwrap();
+    assert!(deploy.accepts_ref("refs/heads/main").is_ok());
+    assert!(deploy.accepts_ref("refs/heads/release/2.1").is_ok());
+    assert_eq!(
+      deploy.accepts_ref("refs/heads/feature-x").unwrap_err(),
+      "branch feature-x is not deployable (deploy.branches: main, release/*)"
+    );
+    // Tags are governed by deploy.tags, not the branch list
+    assert!(deploy.accepts_ref("refs/tags/v1.4.2").is_ok());
+
+    assert!(glob_match("*", ""));
+    assert!(glob_match("v*.*", "v1.2"));
+    assert!(!glob_match("a*a", "a"));
+  }
+
+  #[test]
+  fn test_accessory_image_override() {
+    let cfg: HLConfig = serde_yaml::from_str(
+      r#"
+app: testapp
+image: registry.example.com/testapp
+domain: testapp.example.com
+servicePort: 3000
+health:
+  url: http://testapp:3000/healthz
+accessories:
+  postgres:
+    image: mirror.local/library/postgres
+    pullSecret:
+      registry: mirror.local
+      username: deploy
+      passwordEnv: MIRROR_PASSWORD
+    readyTimeout: 2m
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      cfg.accessory_ready_timeout("postgres").unwrap(),
+      Duration::from_secs(120)
+    );
+    assert_eq!(
+      cfg.accessory_ready_timeout("redis").unwrap(),
+      Duration::from_secs(60)
+    );
+    assert_eq!(
+      cfg.accessory_image("postgres", "postgres", "17"),
+      "mirror.local/library/postgres:17"
+    );
+    assert_eq!(cfg.accessory_image("redis", "redis", "7"), "redis:7");
+    let secret = cfg.accessories["postgres"].pull_secret.as_ref().unwrap();
+    assert_eq!(secret.password_env, "MIRROR_PASSWORD");
+  }
+
+  #[test]
+  fn test_retention_defaults() {
+    let global: GlobalConfig = serde_yaml::from_str("retention:\n  releases: 3\n").unwrap();
+    assert_eq!(global.retention.releases, 3);
+    assert_eq!(global.retention.logs, 20);
+    assert_eq!(global.retention.backups, 7);
+
+    let empty: GlobalConfig = serde_yaml::from_str("{}").unwrap();
+    assert_eq!(empty.retention.releases, 10);
+    assert!(empty.paths.root.is_none());
+    assert_eq!(empty.systemd.scope, SystemdScope::User);
+    let global: GlobalConfig = serde_yaml::from_str("systemd:\n  scope: system\n").unwrap();
+    assert_eq!(global.systemd.scope, SystemdScope::System);
+
+    let global: GlobalConfig =
+      serde_yaml::from_str("paths:\n  root: /srv/hl\n  systemdDir: ~/.config/systemd/user\n")
+        .unwrap();
+    assert_eq!(global.paths.root.as_deref(), Some("/srv/hl"));
+    assert_eq!(
+      expand_home(global.paths.systemd_dir.as_deref().unwrap()),
+      home_dir().join(".config/systemd/user")
+    );
+  }
+
+  #[test]
+  fn test_global_app_defaults() {
+    let global: GlobalConfig = serde_yaml::from_str(
+      "registry: registry.example.com/me/\nnetwork: web\nresolver: le\n\
+       notifications:\n  webhooks: [https://hooks.example.com/deploys]\n",
+    )
+    .unwrap();
+    let mut merged = serde_yaml::Value::Mapping(global.app_defaults("myapp"));
+    merge_yaml(
+      &mut merged,
+      serde_yaml::from_str(
+        "app: myapp\ndomain: myapp.example.com\nservicePort: 3000\nresolver: other\n\
+         health:\n  url: http://myapp:3000/up\n",
+      )
+      .unwrap(),
+    );
+    let cfg: HLConfig = serde_yaml::from_value(merged).unwrap();
+
+    assert_eq!(cfg.image, "registry.example.com/me/myapp");
+    assert_eq!(cfg.network, "web");
+    // hl.yml wins over the global config, which wins over the built-in defaults
+    assert_eq!(cfg.resolver, "other");
+    assert_eq!(cfg.platforms, "linux/amd64");
+    assert_eq!(
+      cfg.notifications.webhooks,
+      vec!["https://hooks.example.com/deploys"]
+    );
+  }
+
+  #[test]
+  fn test_migration_presets() {
+    let django: MigrationsConfig = serde_yaml::from_str("preset: django").unwrap();
+    assert_eq!(
+      django.command,
+      vec!["python", "manage.py", "migrate", "--noinput"]
+    );
+
+    // Explicit command and env entries win over the preset's
+    let rails: MigrationsConfig = serde_yaml::from_str(
+      "preset: rails\ncommand: [bin/rails, db:prepare]\nenv:\n  RAILS_ENV: staging\n  X: \"1\"\n",
+    )
+    .unwrap();
+    assert_eq!(rails.command, vec!["bin/rails", "db:prepare"]);
+    assert_eq!(rails.env["RAILS_ENV"], "staging");
+    assert_eq!(rails.env["X"], "1");
+
+    let none: MigrationsConfig = serde_yaml::from_str("preset: none").unwrap();
+    assert!(none.command.is_empty());
+
+    // No preset keeps the historical default
+    let legacy: MigrationsConfig = serde_yaml::from_str("env: {}").unwrap();
+    assert_eq!(legacy.command, vec!["bin/rails", "db:migrate"]);
+    assert!(legacy.env.is_empty());
+  }
+
+  #[test]
+  #[serial]
+  fn test_named_volumes() {
+    let cfg: HLConfig = serde_yaml::from_str(
+      r#"
+app: testapp
+image: registry.example.com/testapp
+domain: testapp.example.com
+servicePort: 3000
+health:
+  url: http://testapp:3000/healthz
+volumes:
+  uploads: /app/storage
+  cache: /app/tmp/cache
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      cfg.base_volumes(),
+      vec![
+        "./volumes/cache:/app/tmp/cache",
+        "./volumes/uploads:/app/storage"
+      ]
+    );
+    assert_eq!(
+      cfg.volume_dirs()[1],
+      app_dir("testapp").join("volumes/uploads")
+    );
+    assert!(cfg.validate_volume_names().is_ok());
+
+    for bad in ["../../etc", "a/b", ".hidden", "-x", ""] {
+      let mut cfg = cfg.clone();
+      cfg.volumes = VolumesConfig::Named(BTreeMap::from([(bad.to_string(), "/x".to_string())]));
+      assert!(cfg.validate_volume_names().is_err(), "{}", bad);
+    }
+    let mut cfg = cfg.clone();
+    cfg.persistence = Some(PersistenceConfig {
+      path: "/data".to_string(),
+      name: "data/../x".to_string(),
+    });
+    assert!(cfg.validate_volume_names().is_err());
+  }
+
+  #[test]
+  fn test_persistence_volume() {
+    let cfg: HLConfig = serde_yaml::from_str(
+      r#"
+app: testapp
+image: registry.example.com/testapp
+domain: testapp.example.com
+servicePort: 3000
+health:
+  url: http://testapp:3000/healthz
+volumes:
+  - ./uploads:/app/uploads
+persistence:
+  path: /rails/storage
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(cfg.persistence_volume().as_deref(), Some("testapp-data"));
+    assert_eq!(
+      cfg.base_volumes(),
+      vec!["./uploads:/app/uploads", "testapp-data:/rails/storage"]
+    );
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn test_load_env_config_overlay() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    std::env::set_var("HL_ROOT_OVERRIDE", temp_dir.path());
+    std::fs::create_dir_all(app_dir("my-app")).unwrap();
+    std::fs::write(
+      app_dir("my-app").join("hl.yml"),
+      r#"
+app: my-app
+image: registry.example.com/my-app
+domain: my-app.example.com
+servicePort: 3000
+health:
+  url: http://my-app:3000/healthz
+  timeout: 60s
+migrations:
+  env:
+    RAILS_ENV: production
+"#,
+    )
+    .unwrap();
+    std::fs::write(
+      env_overlay_path("my-app", "staging"),
+      r#"
+image: registry.example.com/my-app-staging
+domain: my-app-staging.example.com
+health:
+  url: http://my-app-staging:3000/healthz
+migrations:
+  env:
+    RAILS_ENV: staging
+"#,
+    )
+    .unwrap();
+
+    // The environment is found from its instance name alone
+    let cfg = load_config("my-app-staging").await.unwrap();
+    assert_eq!(cfg.app, "my-app-staging");
+    assert_eq!(cfg.domain, "my-app-staging.example.com");
+    assert_eq!(cfg.service_port, 3000);
+    assert_eq!(cfg.health.url, "http://my-app-staging:3000/healthz");
+    assert_eq!(cfg.health.timeout, "60s");
+    assert_eq!(cfg.migrations.env["RAILS_ENV"], "staging");
+
+    assert_eq!(
+      load_config("my-app").await.unwrap().domain,
+      "my-app.example.com"
+    );
+    assert!(load_config("my-app-production").await.is_err());
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+  }
+}
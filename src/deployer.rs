@@ -0,0 +1,590 @@
+use crate::config::{
+  app_dir, env_app_name, env_overlay_path, hl_git_root, load_config, load_global_config,
+  systemd_dir, validate_env_name, DeployStrategy, HLConfig,
+};
+use crate::discovery::discover_accessories;
+use crate::docker::*;
+use crate::dockerfile::check_build_inputs;
+use crate::env::load_build_secrets;
+use crate::error::{Categorize, Error, Result};
+use crate::events::record_event;
+use crate::gate::with_gate;
+use crate::git::{export_commit, WorktreeGuard};
+use crate::health::wait_for_healthy;
+use crate::log::*;
+use crate::metrics::refresh_textfile;
+use crate::notify::notify_deploy;
+use crate::plugins::{run_hooks, HookPhase};
+use crate::procfile::parse_procfile;
+use crate::registry::check_push_access;
+use crate::releases::{
+  build_log_path, deploy_log_path, gc, process_build_log_path, record_release, short_sha, Release,
+};
+use crate::rollout::{finish_rollout, shift_traffic};
+use crate::secrets::{merge_build_secrets, resolve_secrets};
+use crate::sidecar::write_sidecar_compose_files;
+use crate::state::update_state;
+use crate::systemd::{
+  enable_accessories_if_present, enable_cron_timers, plan_unit_changes, reload_systemd_daemon,
+  start_accessories, write_unit,
+};
+use crate::time::unix_now;
+use crate::units_spec_builder::UnitChange;
+use anyhow::Context;
+
+/*
+The pipeline behind `hl deploy`, for tools that embed hl rather than shell out to it:
+
+  Deployer::new("blog", sha).branch("main").tag("v1.4.2").run().await?;
+
+deploys a commit already pushed to the app's hl git repository exactly like the
+post-receive hook does, deploy log, event and notifications included.
+ */
+
+/// Deploys one commit of an app.
+#[derive(Debug, Clone)]
+pub struct Deployer {
+  repo_app: String,
+  sha: String,
+  branch: Option<String>,
+  tag: Option<String>,
+  env: Option<String>,
+}
+
+impl Deployer {
+  /// Deploy `sha` from the hl git repository of `app`.
+  pub fn new(app: impl Into<String>, sha: impl Into<String>) -> Self {
+    Deployer {
+      repo_app: app.into(),
+      sha: sha.into(),
+      branch: Some("master".to_string()),
+      tag: None,
+      env: None,
+    }
+  }
+
+  /// Branch the commit was pushed to (default: master).
+  pub fn branch(mut self, branch: impl Into<String>) -> Self {
+    self.branch = Some(branch.into());
+    self
+  }
+
+  /// Deploy a commit that was pushed as a tag rather than to a branch.
+  pub fn without_branch(mut self) -> Self {
+    self.branch = None;
+    self
+  }
+
+  /// The pushed branch, or the tag for tag deploys.
+  fn pushed_ref(&self) -> &str {
+    self
+      .branch
+      .as_deref()
+      .or(self.tag.as_deref())
+      .unwrap_or("-")
+  }
+
+  /// Release tag (e.g. v1.4.2); the image is additionally tagged with it.
+  pub fn tag(mut self, tag: impl Into<String>) -> Self {
+    self.tag = Some(tag.into());
+    self
+  }
+
+  /// Deploy to an environment (`hl.<env>.yml` over hl.yml, as `<app>-<env>`).
+  pub fn env(mut self, env: impl Into<String>) -> Self {
+    self.env = Some(env.into());
+    self
+  }
+
+  /// Name the commit is deployed under.
+  pub fn app(&self) -> String {
+    env_app_name(&self.repo_app, self.env.as_deref())
+  }
+
+  /// hl.yml of the app (merged with the environment's overlay) that the deploy would use.
+  pub async fn config(&self) -> Result<HLConfig> {
+    self.check_env()?;
+    load_config(&self.app()).await.categorize(Error::Config)
+  }
+
+  fn check_env(&self) -> Result<()> {
+    if let Some(env) = &self.env {
+      validate_env_name(env).categorize(Error::Config)?;
+      if !env_overlay_path(&self.repo_app, env).exists() {
+        return Err(Error::Config(anyhow::anyhow!(
+          "{} has no {} environment; create it with hl init --app {} --env {} ...",
+          self.repo_app,
+          env,
+          self.repo_app,
+          env
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  /// How deploying the commit would change the app's systemd units; builds and writes nothing.
+  pub async fn unit_changes(&self) -> Result<Vec<UnitChange>> {
+    let cfg = self.config().await?;
+    let app = self.app();
+    let repo_path = hl_git_root(&self.repo_app).to_string_lossy().to_string();
+    let worktree = export_commit(&repo_path, &self.sha).await?;
+    let procfile_path = worktree.join("Procfile");
+    let procfile = if procfile_path.exists() {
+      Some(parse_procfile(&procfile_path).await)
+    } else {
+      None
+    };
+    let _ = tokio::fs::remove_dir_all(&worktree).await;
+    let mut process_names = match procfile {
+      Some(procs) => procs?.into_keys().collect::<Vec<String>>(),
+      None => vec!["web".to_string()],
+    };
+    process_names.extend(cfg.sidecars.keys().cloned());
+    let accessories = discover_accessories(&systemd_dir(), &app_dir(&app), &app, &process_names)?;
+    Ok(plan_unit_changes(&app, &process_names, &accessories, cfg.systemd.mode).await?)
+  }
+
+  /// Build, release and restart the commit, keeping a deploy log and notifying the
+  /// configured webhooks of the outcome.
+  pub async fn run(&self) -> Result<()> {
+    self.check_env()?;
+    let app = self.app();
+    // Hook-triggered deploys have no terminal to scroll back through; keep a copy
+    let log_path = deploy_log_path(&app, &self.sha);
+    if let Err(e) = set_log_file(&log_path) {
+      warn(&format!(
+        "not keeping a deploy log at {}: {}",
+        log_path.display(),
+        e
+      ));
+    }
+    record(
+      Level::Info,
+      &format!("deploying {} ({})", self.sha, self.pushed_ref()),
+    );
+
+    let result = self.deploy(&app).await;
+    let mut event_args = vec![format!("sha={}", self.sha)];
+    event_args.extend(self.branch.iter().map(|b| format!("branch={}", b)));
+    event_args.extend(self.tag.iter().map(|t| format!("tag={}", t)));
+    record_event(&app, "deploy", &event_args, &result);
+    if let Err(e) = &result {
+      record(Level::Error, &format!("deploy failed: {}", e));
+      let mut env = self.hook_env(&app);
+      env.push(("HL_ERROR", e.to_string()));
+      if let Err(e) = run_hooks(HookPhase::DeployFailed, &env).await {
+        warn(&format!("{:#}", e));
+      }
+    }
+    if let Ok(cfg) = load_config(&app).await {
+      notify_deploy(
+        &cfg,
+        &self.sha,
+        self.branch.as_deref(),
+        self.tag.as_deref(),
+        &result,
+      )
+      .await;
+    }
+    if let Ok(global) = load_global_config().await {
+      refresh_textfile(&global).await;
+    }
+    result
+  }
+
+  async fn deploy(&self, app: &str) -> Result<()> {
+    if let Some(tag) = &self.tag {
+      validate_version_tag(tag).categorize(Error::Config)?;
+    }
+    // Export the commit to a temporary directory
+    let repo_path = hl_git_root(&self.repo_app)
+      .to_str()
+      .expect("repo path is not valid UTF-8")
+      .to_string();
+
+    debug(&format!("repository path: {}", repo_path));
+
+    let worktree = export_commit(&repo_path, &self.sha).await?;
+    // Removed however the deploy ends
+    let _worktree_guard = WorktreeGuard::new(worktree.clone());
+
+    debug(&format!("exported worktree to: {}", worktree.display()));
+
+    // Check for Procfile and parse if present
+    let procfile_path = worktree.join("Procfile");
+    let processes = if procfile_path.exists() {
+      debug("found Procfile, parsing processes");
+      let procs = parse_procfile(&procfile_path)
+        .await
+        .categorize(Error::Config)?;
+      debug(&format!("parsed {} processes from Procfile", procs.len()));
+      for (name, cmd) in &procs {
+        debug(&format!("  {}: {}", name, cmd));
+      }
+      Some(procs)
+    } else {
+      debug("no Procfile found, using default configuration");
+      None
+    };
+
+    let cfg = load_config(app).await.categorize(Error::Config)?;
+
+    // Regenerate base compose.yml so hl.yml changes (volumes, image, network) propagate
+    let app_directory = app_dir(&cfg.app);
+    log("regenerating base compose file");
+    let named_volumes: Vec<String> = cfg.persistence_volume().into_iter().collect();
+    for volume in &named_volumes {
+      ensure_volume(volume).await?;
+    }
+    for dir in cfg.volume_dirs() {
+      tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    write_base_compose_file(
+      &app_directory,
+      &cfg.image,
+      &cfg.network,
+      &cfg.base_volumes(),
+      &named_volumes,
+      cfg.systemd.mode,
+      cfg.logging.as_ref(),
+    )
+    .await?;
+
+    // Generate process-specific compose files
+    log("generating process compose files");
+    write_process_compose_files(
+      &app_directory,
+      processes.as_ref(),
+      &cfg.app,
+      &cfg.resolver,
+      &WebRouter::for_config(&cfg),
+      &cfg.processes,
+    )
+    .await?;
+    write_process_images(&app_directory, &cfg).await?;
+
+    let web_command = processes.as_ref().and_then(|p| p.get("web").cloned());
+    let systemd_dir = systemd_dir();
+    let mut process_names = processes
+      .map(|p| p.keys().cloned().collect::<Vec<String>>())
+      .unwrap_or_else(|| vec!["web".to_string()]);
+    if let Some(name) = cfg.sidecars.keys().find(|n| process_names.contains(n)) {
+      return Err(Error::Config(anyhow::anyhow!(
+        "sidecar {} has the same name as a Procfile process",
+        name
+      )));
+    }
+    for name in cfg.processes.keys().filter(|n| !process_names.contains(n)) {
+      warn(&format!(
+        "hl.yml processes.{} matches no Procfile process; its settings are unused",
+        name
+      ));
+    }
+    process_names.extend(write_sidecar_compose_files(&app_directory, &cfg).await?);
+    let accessories = discover_accessories(&systemd_dir, &app_directory, app, &process_names)?;
+    write_unit(app, &process_names, &accessories, cfg.systemd.mode).await?;
+
+    let tags = tag_for(&cfg, &self.sha, self.branch.as_deref(), self.tag.as_deref());
+    let mut hook_env = self.hook_env(app);
+    hook_env.push(("HL_IMAGE", tags.sha.clone()));
+    run_hooks(HookPhase::PreBuild, &hook_env).await?;
+
+    log(&format!(
+      "building {} {} ({})",
+      cfg.app,
+      self.pushed_ref(),
+      short_sha(&self.sha)
+    ));
+    let process_images = self.process_images(&cfg, &process_names);
+    self
+      .build(&cfg, &worktree, tags.all(), &process_images)
+      .await
+      .categorize(Error::Build)?;
+
+    wait_for_accessories(&cfg, &accessories).await?;
+
+    log("running migrations");
+    run_migrations(&cfg, &tags.sha).await?;
+    run_hooks(HookPhase::PreRestart, &hook_env).await?;
+
+    let gradual = cfg.deploy.strategy == DeployStrategy::Gradual;
+    if gradual {
+      log("shifting traffic gradually to the new release");
+      shift_traffic(&cfg, &tags.sha, web_command.as_ref()).await?;
+    }
+
+    log("retagging latest");
+    retag_latest(&cfg.image, &tags.sha).await?;
+    if let (_, Some(tag)) = split_image_ref(&tags.sha) {
+      log(&format!("pinning compose.yml to {}", tag));
+      pin_base_image(&app_directory, &cfg.image, tag).await?;
+    }
+
+    log("reloading systemd daemon");
+    reload_systemd_daemon().await?;
+    log("ensuring accessories systemd service is enabled when present");
+    enable_accessories_if_present(&cfg.app, &accessories).await?;
+    if !cfg.cron.is_empty() {
+      log("enabling cron timers");
+      let names: Vec<String> = cfg.cron.iter().map(|job| job.name.clone()).collect();
+      enable_cron_timers(&cfg.app, &names).await?;
+    }
+
+    let restart = async {
+      log("restarting services");
+      restart_compose(&cfg, &process_names, &accessories).await?;
+
+      log("waiting for healthchecks to pass");
+      // With a gradual rollout the canary keeps serving all traffic if this fails
+      wait_for_healthy(&cfg)
+        .await
+        .categorize(Error::Health)
+        .map_err(anyhow::Error::from)
+    };
+    // A gradual rollout already keeps traffic on healthy containers while restarting
+    if cfg.health.gate && !gradual {
+      with_gate(&cfg, restart).await?;
+    } else {
+      restart.await?;
+    }
+
+    if gradual {
+      log("handing traffic back to the restarted processes");
+      finish_rollout(&cfg).await?;
+    }
+
+    update_state(app, &systemd_dir, |state| {
+      state.release = Some(self.sha.clone())
+    })?;
+    let mut images = tags.all();
+    images.retain(|t| t != &tags.latest);
+    images.extend(
+      process_images
+        .iter()
+        .flat_map(|(_, _, tags)| tags.iter().cloned()),
+    );
+    record_release(
+      app,
+      Release {
+        sha: self.sha.clone(),
+        branch: self.branch.clone(),
+        tag: self.tag.clone(),
+        images,
+        deployed_at: unix_now(),
+        env_sha256: None,
+      },
+    )?;
+    // Retention is housekeeping; never fail a healthy deploy over it
+    match load_global_config().await {
+      Ok(global) => {
+        if let Err(e) = gc(&cfg, &global.retention).await {
+          warn(&format!("release gc failed: {}", e));
+        }
+      }
+      Err(e) => warn(&format!("skipping release gc: {}", e)),
+    }
+
+    run_hooks(HookPhase::PostDeploy, &hook_env).await?;
+    ok("deploy complete");
+    Ok(())
+  }
+
+  /// Variables every deploy hook gets.
+  fn hook_env(&self, app: &str) -> Vec<(&'static str, String)> {
+    let mut env = vec![
+      ("HL_APP", app.to_string()),
+      ("HL_APP_DIR", app_dir(app).to_string_lossy().to_string()),
+      ("HL_SHA", self.sha.clone()),
+    ];
+    if let Some(branch) = &self.branch {
+      env.push(("HL_BRANCH", branch.clone()));
+    }
+    if let Some(tag) = &self.tag {
+      env.push(("HL_TAG", tag.clone()));
+    }
+    env
+  }
+
+  /// Build the exported worktree's Dockerfile and push it under `tags`.
+  /// (process, Dockerfile, tags) of the processes of this release built from their own
+  /// Dockerfile. Their images aren't retagged :latest; overlays are pinned to the release.
+  fn process_images(
+    &self,
+    cfg: &HLConfig,
+    process_names: &[String],
+  ) -> Vec<(String, String, Vec<String>)> {
+    cfg
+      .process_dockerfiles()
+      .into_iter()
+      .filter(|(process, _)| process_names.contains(process))
+      .map(|(process, dockerfile)| {
+        let tags = image_tags(
+          &cfg.process_image(&process),
+          &self.sha,
+          self.branch.as_deref(),
+          self.tag.as_deref(),
+        );
+        let mut all = tags.all();
+        all.retain(|t| t != &tags.latest);
+        (process, dockerfile, all)
+      })
+      .collect()
+  }
+
+  async fn build(
+    &self,
+    cfg: &HLConfig,
+    worktree: &std::path::Path,
+    tags: Vec<String>,
+    process_images: &[(String, String, Vec<String>)],
+  ) -> anyhow::Result<()> {
+    let dockerfile = worktree.join("Dockerfile");
+
+    debug(&format!("dockerfile path: {}", dockerfile.display()));
+
+    // Check if Dockerfile exists
+    if !dockerfile.exists() {
+      anyhow::bail!("Dockerfile not found at: {}", dockerfile.display());
+    }
+    for (process, path, _) in process_images {
+      if !worktree.join(path).exists() {
+        anyhow::bail!("processes.{}.dockerfile {} not found", process, path);
+      }
+    }
+
+    debug(&format!("build context: {}", worktree.display()));
+
+    // load build-time secrets from .env.build, then any the secrets provider resolves
+    let mut secrets = load_build_secrets(&cfg.app)?;
+    if let Some(provider) = &cfg.secrets_provider {
+      log(&format!(
+        "resolving {} secret(s) via {:?}",
+        cfg.secrets.len(),
+        provider.kind
+      ));
+      secrets = merge_build_secrets(secrets, resolve_secrets(cfg).await?);
+    }
+
+    // Catch args/secrets that never reach the build (or that the build expects but won't get)
+    let build_arg_names: Vec<String> = cfg.build.args.keys().cloned().collect();
+    let secret_ids: Vec<String> = secrets.iter().map(|s| s.id.clone()).collect();
+    let dockerfiles =
+      std::iter::once("Dockerfile").chain(process_images.iter().map(|(_, path, _)| path.as_str()));
+    for path in dockerfiles {
+      let dockerfile_content = tokio::fs::read_to_string(worktree.join(path)).await?;
+      for warning in check_build_inputs(&dockerfile_content, &build_arg_names, &secret_ids) {
+        if process_images.is_empty() {
+          warn(&warning);
+        } else {
+          warn(&format!("{}: {}", path, warning));
+        }
+      }
+    }
+
+    // An expired login would otherwise only surface after the build, when the push fails
+    check_push_access(&cfg.image).await;
+
+    let options = |dockerfile: std::path::PathBuf, tags, log_file| BuildPushOptions {
+      context: worktree.to_string_lossy().to_string(),
+      dockerfile: Some(dockerfile.to_string_lossy().to_string()),
+      git_sha: self.sha.clone(),
+      build_args: cfg.build.args.clone(),
+      tags,
+      platforms: Some(cfg.platforms.clone()),
+      secrets: secrets.clone(),
+      log_file: Some(log_file),
+    };
+    let mut builds = vec![options(
+      dockerfile,
+      tags,
+      build_log_path(&cfg.app, &self.sha),
+    )];
+    if process_images.is_empty() {
+      return build_and_push(builds.remove(0)).await;
+    }
+    for (process, path, tags) in process_images {
+      log(&format!("building {} from {}", process, path));
+      builds.push(options(
+        worktree.join(path),
+        tags.clone(),
+        process_build_log_path(&cfg.app, &self.sha, process),
+      ));
+    }
+    build_and_push_all(builds, cfg.build.concurrency()).await
+  }
+}
+
+async fn wait_for_accessories(cfg: &HLConfig, accessories: &[String]) -> anyhow::Result<()> {
+  let app = &cfg.app;
+  if !accessories.is_empty() {
+    login_accessory_registries(cfg, accessories).await?;
+    // Ensure accessories are started and ready before running migrations
+    log("enabling and starting accessories");
+    start_accessories(app).await?;
+    // Probe concurrently so the slowest accessory, not the sum, bounds the wait
+    let postgres = async {
+      if accessories.contains(&"postgres".to_string()) {
+        log("waiting for postgres to be ready...");
+        wait_for_postgres_ready(app, cfg.accessory_ready_timeout("postgres")?).await?;
+        ok("postgres is ready");
+      }
+      Ok::<_, anyhow::Error>(())
+    };
+    let redis = async {
+      if accessories.contains(&"redis".to_string()) {
+        log("waiting for redis to be ready...");
+        wait_for_redis_ready(app, cfg.accessory_ready_timeout("redis")?).await?;
+        ok("redis is ready");
+      }
+      Ok::<_, anyhow::Error>(())
+    };
+    // Accessories from plugins are ready once their compose healthchecks pass
+    let others = async {
+      for accessory in accessories {
+        if accessory != "postgres" && accessory != "redis" {
+          log(&format!("waiting for {} to be ready...", accessory));
+          let timeout = cfg.accessory_ready_timeout(accessory)?;
+          wait_for_accessory_healthy(app, accessory, timeout).await?;
+          ok(&format!("{} is ready", accessory));
+        }
+      }
+      Ok::<_, anyhow::Error>(())
+    };
+    let (postgres, redis, others) = tokio::join!(postgres, redis, others);
+    postgres?;
+    redis?;
+    others?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_deployer_builder() {
+    let deployer = Deployer::new("blog", "abc1234");
+    assert_eq!(deployer.app(), "blog");
+    assert_eq!(deployer.branch.as_deref(), Some("master"));
+    let deployer = deployer.branch("main").tag("v1.4.2").env("staging");
+    assert_eq!(deployer.app(), "blog-staging");
+    assert_eq!(deployer.branch.as_deref(), Some("main"));
+    assert_eq!(deployer.tag.as_deref(), Some("v1.4.2"));
+    assert_eq!(deployer.pushed_ref(), "main");
+
+    // A pushed tag is deployed as a version, not as a branch named after the tag
+    let tagged = Deployer::new("blog", "abc1234")
+      .without_branch()
+      .tag("v1.4.2");
+    assert_eq!(tagged.branch, None);
+    assert_eq!(tagged.pushed_ref(), "v1.4.2");
+    assert!(!tagged
+      .hook_env("blog")
+      .iter()
+      .any(|(key, _)| *key == "HL_BRANCH"));
+  }
+}
@@ -1,11 +1,59 @@
+use crate::config::app_dir;
+use crate::state::load_state;
 use regex::Regex;
 use std::fs;
 use std::io::{self, Read};
 use std::path::Path;
 
-/// Finds process unit names by scanning app-<app>-*.service,
-/// excluding the accessories unit (-acc.service).
+/// The app's processes, as recorded in its state.yml, or scanned for when there is none.
 pub fn discover_processes(systemd_dir: &Path, app: &str) -> std::io::Result<Vec<String>> {
+  match load_state(&app_dir(app)) {
+    Some(state) => Ok(state.processes),
+    None => scan_processes(systemd_dir, app),
+  }
+}
+
+/// The app's accessories, as recorded in its state.yml, or scanned for when there is none.
+pub fn discover_accessories(
+  systemd_dir: &Path,
+  app_dir: &Path,
+  app: &str,
+  known_processes: &[String],
+) -> io::Result<Vec<String>> {
+  match load_state(app_dir) {
+    Some(state) => Ok(state.accessories),
+    None => scan_accessories(systemd_dir, app_dir, app, known_processes),
+  }
+}
+
+/// The accessories the app's state.yml, else its accessories unit, records; None when there is
+/// neither. Unlike `discover_accessories` it never guesses from the compose files in the app dir.
+pub fn recorded_accessories(
+  systemd_dir: &Path,
+  app_dir: &Path,
+  app: &str,
+) -> io::Result<Option<Vec<String>>> {
+  if let Some(state) = load_state(app_dir) {
+    return Ok(Some(state.accessories));
+  }
+  let unit_path = systemd_dir.join(format!("app-{}-acc.service", app));
+  if !unit_path.exists() {
+    return Ok(None);
+  }
+  let content = fs::read_to_string(&unit_path)?;
+  Ok(parse_compose_acc_env(&content).map(|paths| {
+    sorted_dedup(
+      paths
+        .iter()
+        .filter_map(|p| extract_accessory_from_overlay_path(p))
+        .collect(),
+    )
+  }))
+}
+
+/// Finds process unit names by scanning app-<app>-*.service,
+/// excluding the accessories unit (-acc.service) and cron jobs (app-<app>-cron-*).
+pub fn scan_processes(systemd_dir: &Path, app: &str) -> std::io::Result<Vec<String>> {
   let mut procs = Vec::new();
   let pattern_prefix = format!("app-{}-", app);
   for entry in std::fs::read_dir(systemd_dir)? {
@@ -18,7 +66,7 @@ pub fn discover_processes(systemd_dir: &Path, app: &str) -> std::io::Result<Vec<
     if !fname.starts_with(&pattern_prefix) {
       continue;
     }
-    if fname.ends_with("-acc.service") {
+    if fname.ends_with("-acc.service") || fname.starts_with(&format!("{}cron-", pattern_prefix)) {
       continue;
     }
     // app-<app>-<proc>.service → extract <proc>
@@ -34,7 +82,7 @@ pub fn discover_processes(systemd_dir: &Path, app: &str) -> std::io::Result<Vec<
   Ok(procs)
 }
 
-/// Discover existing accessories for an app.
+/// Discover existing accessories for an app from its files.
 ///
 /// Strategy:
 ///  1) If the systemd unit `app-<app>-acc.service` exists, parse Environment=COMPOSE_ACC
@@ -44,7 +92,7 @@ pub fn discover_processes(systemd_dir: &Path, app: &str) -> std::io::Result<Vec<
 ///     - any `compose.<proc>.yml` where <proc> is in `known_processes`
 ///
 /// Returns a sorted, deduped list of accessory names.
-pub fn discover_accessories(
+pub fn scan_accessories(
   systemd_dir: &Path,
   app_dir: &Path,
   app: &str,
@@ -94,8 +142,8 @@ pub fn discover_accessories(
       .strip_prefix("compose.")
       .and_then(|s| s.strip_suffix(".yml"))
     {
-      // skip process overlays
-      if proc_set.contains(stem) {
+      // skip process overlays and the user's overrides of them
+      if proc_set.contains(stem) || stem.starts_with("override.") {
         continue;
       }
       // treat it as accessory
@@ -1,14 +1,28 @@
-use crate::config::{app_dir, env_file, HLConfig};
-use crate::log::debug;
-use crate::systemd::restart_app_target;
-use anyhow::Result;
-use std::collections::HashMap;
+use crate::config::{
+  app_dir, env_file, parse_duration, systemd_dir, GpuRequest, HLConfig, LoggingConfig,
+  ProcessConfig, SystemdMode,
+};
+use crate::discovery::{discover_accessories, discover_processes, recorded_accessories};
+use crate::engine::{self, EngineError};
+use crate::env::{env_hash, load_env_file_contents};
+use crate::error::Error;
+use crate::log::{debug, err, is_quiet, is_verbose, log, progress_stdout, warn};
+use crate::output::from_json;
+use crate::registry::login_with_password;
+use crate::releases::short_sha;
+use crate::systemd::{restart_app_target, restart_process_units, user_unit_active_state};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::time::Duration;
 use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
 
+#[derive(Clone)]
 pub struct BuildSecret {
   // the name you'll use in Dockerfile mounts, eg.: RAILS_MASTER_KEY
   pub id: String,
@@ -29,9 +43,13 @@ pub struct BuildPushOptions {
   pub context: String,
   pub dockerfile: Option<String>,
   pub git_sha: String,
+  /// Extra `--build-arg`s from hl.yml `build.args`
+  pub build_args: HashMap<String, String>,
   pub tags: Vec<String>,
   pub platforms: Option<String>,
   pub secrets: Vec<BuildSecret>,
+  /// Write the full build output here and only show step progress (see `BuildProgress`)
+  pub log_file: Option<PathBuf>,
 }
 
 pub async fn build_and_push(opts: BuildPushOptions) -> Result<()> {
@@ -57,6 +75,13 @@ pub async fn build_and_push(opts: BuildPushOptions) -> Result<()> {
   args.push("--build-arg".into());
   args.push(format!("GIT_SHA={}", opts.git_sha).into());
 
+  let mut build_args: Vec<_> = opts.build_args.iter().collect();
+  build_args.sort();
+  for (k, v) in build_args {
+    args.push("--build-arg".into());
+    args.push(format!("{}={}", k, v).into());
+  }
+
   if let Some(platforms) = &opts.platforms {
     args.push("--platform".into());
     args.push(platforms.into());
@@ -72,6 +97,11 @@ pub async fn build_and_push(opts: BuildPushOptions) -> Result<()> {
     args.push(dockerfile.into());
   }
 
+  if opts.log_file.is_some() {
+    args.push("--progress".into());
+    args.push("rawjson".into());
+  }
+
   // Collect env just for docker child
   let mut docker_child_env: HashMap<String, String> = HashMap::new();
 
@@ -97,67 +127,589 @@ pub async fn build_and_push(opts: BuildPushOptions) -> Result<()> {
   ));
 
   let mut cmd = Command::new("docker");
-  cmd
-    .args(&args)
-    .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
-    .stderr(Stdio::inherit());
+  cmd.args(&args).stdin(Stdio::inherit());
+  if opts.log_file.is_some() {
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+  } else {
+    cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+  }
 
   // Only the docker child sees these env vars
   for (k, v) in docker_child_env {
     cmd.env(k, v);
   }
 
-  let status = cmd.status().await?;
+  let status = match &opts.log_file {
+    Some(log_file) => {
+      let (status, failure) = run_logged_build(cmd.spawn()?, log_file).await?;
+      if let (false, Some(failure)) = (status.success(), failure) {
+        print_build_output(&failure.step, &failure.excerpt);
+        return Err(
+          Error::Build(anyhow::anyhow!(
+            "docker build failed at {}: {} (full log: {})",
+            failure.step,
+            failure.error,
+            log_file.display()
+          ))
+          .into(),
+        );
+      }
+      if !status.success() {
+        print_log_tail(log_file, 30).await;
+        return Err(
+          Error::Build(anyhow::anyhow!(
+            "docker build failed with status: {} (full log: {})",
+            status,
+            log_file.display()
+          ))
+          .into(),
+        );
+      }
+      status
+    }
+    None => cmd.status().await?,
+  };
   if !status.success() {
-    anyhow::bail!("docker build failed with status: {}", status);
+    return Err(
+      Error::Build(anyhow::anyhow!(
+        "docker build failed with status: {}",
+        status
+      ))
+      .into(),
+    );
   }
 
   debug("docker build completed successfully");
   Ok(())
 }
 
-pub async fn retag_latest(image: &str, from_tag: &str) -> Result<()> {
-  // Pull the source image
-  let status = Command::new("docker")
-    .args(["pull", from_tag])
-    .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
-    .stderr(Stdio::inherit())
-    .status()
-    .await?;
+/// Write the build's progress into `log_file` in the plain format, echoing only step progress
+/// (or everything in verbose mode). Returns the failed step, if the build reported one.
+async fn run_logged_build(
+  mut child: tokio::process::Child,
+  log_file: &Path,
+) -> Result<(std::process::ExitStatus, Option<BuildFailure>)> {
+  if let Some(dir) = log_file.parent() {
+    fs::create_dir_all(dir).await?;
+  }
+  let mut file = fs::File::create(log_file)
+    .await
+    .with_context(|| format!("Failed to create build log {}", log_file.display()))?;
+
+  let stdout = child.stdout.take().context("build stdout not piped")?;
+  let stderr = child.stderr.take().context("build stderr not piped")?;
+  let mut out_lines = BufReader::new(stdout).lines();
+  let mut err_lines = BufReader::new(stderr).lines();
+  let (mut out_done, mut err_done) = (false, false);
+  let mut progress = BuildProgress::default();
+
+  while !(out_done && err_done) {
+    let (line, from_stdout) = tokio::select! {
+      line = out_lines.next_line(), if !out_done => (line?, true),
+      line = err_lines.next_line(), if !err_done => (line?, false),
+    };
+    let Some(line) = line else {
+      if from_stdout {
+        out_done = true;
+      } else {
+        err_done = true;
+      }
+      continue;
+    };
+    let fed = progress.feed(&line);
+    for line in &fed.log {
+      file.write_all(line.as_bytes()).await?;
+      file.write_all(b"\n").await?;
+    }
+    let shown = if is_verbose() { &fed.log } else { &fed.show };
+    for line in shown {
+      log(&format!("  {}", line));
+    }
+  }
+  file.flush().await?;
 
-  if !status.success() {
-    anyhow::bail!("docker pull failed");
+  let status = child.wait().await?;
+  if status.success() {
+    if let Some(summary) = progress.summary() {
+      log(&format!("  {}", summary));
+    }
+  }
+  Ok((status, progress.failure()))
+}
+
+/*
+With `--progress rawjson` buildx prints one BuildKit SolveStatus per line: the vertexes (steps)
+that started, completed or failed since the last one, and their output, base64 encoded.
+`BuildProgress` turns that back into the `--progress plain` format for the build log, and into
+a line per finished Dockerfile step, the phase the build is in and its cache hits for the
+deploy output. Lines that aren't JSON (buildx's own errors) are passed through.
+ */
+
+/// Where a build is, judged by the vertexes it's running; only moves forward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BuildPhase {
+  /// Loading the Dockerfile, context and base image metadata
+  Prepare,
+  /// Running Dockerfile steps
+  Steps,
+  /// Exporting the image and pushing it
+  Push,
+}
+
+impl BuildPhase {
+  pub fn of_vertex(name: &str) -> BuildPhase {
+    if is_build_step(name) {
+      BuildPhase::Steps
+    } else if ["exporting", "pushing", "writing image", "naming to"]
+      .iter()
+      .any(|prefix| name.starts_with(prefix))
+    {
+      BuildPhase::Push
+    } else {
+      BuildPhase::Prepare
+    }
+  }
+
+  fn describe(self) -> &'static str {
+    match self {
+      BuildPhase::Prepare => "preparing build",
+      BuildPhase::Steps => "running build steps",
+      BuildPhase::Push => "exporting and pushing image",
+    }
+  }
+}
+
+/// A Dockerfile step: `[build 3/7] RUN bundle install`, or `[3/7] ...` for single-stage builds.
+fn is_build_step(name: &str) -> bool {
+  let Some(label) = name
+    .strip_prefix('[')
+    .and_then(|rest| rest.split_once(']'))
+    .map(|(label, _)| label)
+  else {
+    return false;
+  };
+  let position = label.rsplit(' ').next().unwrap_or_default();
+  position
+    .split_once('/')
+    .is_some_and(|(n, of)| n.parse::<u32>().is_ok() && of.parse::<u32>().is_ok())
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct SolveStatus {
+  #[serde(default)]
+  vertexes: Vec<SolveVertex>,
+  #[serde(default)]
+  logs: Vec<SolveLog>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SolveVertex {
+  digest: String,
+  #[serde(default)]
+  name: String,
+  #[serde(default)]
+  started: Option<String>,
+  #[serde(default)]
+  completed: Option<String>,
+  #[serde(default)]
+  cached: bool,
+  #[serde(default)]
+  error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SolveLog {
+  vertex: String,
+  /// Base64, as Go encodes []byte
+  #[serde(default)]
+  data: String,
+}
+
+/// Output lines of a vertex kept for the excerpt shown when it fails.
+const FAILED_STEP_EXCERPT_LINES: usize = 20;
+
+#[derive(Debug, Default)]
+struct VertexProgress {
+  /// Number in the plain format (`#5`), in order of first appearance
+  number: usize,
+  name: String,
+  started: Option<std::time::Instant>,
+  done: bool,
+  cached: bool,
+  error: Option<String>,
+  /// Output not yet ending in a newline
+  partial: String,
+  /// The last output lines
+  tail: std::collections::VecDeque<String>,
+}
+
+/// The step a build failed at.
+#[derive(Debug, PartialEq)]
+pub struct BuildFailure {
+  pub step: String,
+  pub error: String,
+  /// The step's last output lines
+  pub excerpt: Vec<String>,
+}
+
+/// Lines from feeding `BuildProgress` one line of build output.
+#[derive(Debug, Default, PartialEq)]
+pub struct FedLines {
+  /// For the build log, in the `--progress plain` format
+  pub log: Vec<String>,
+  /// For the deploy output
+  pub show: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct BuildProgress {
+  vertexes: HashMap<String, VertexProgress>,
+  phase: Option<BuildPhase>,
+}
+
+impl BuildProgress {
+  pub fn feed(&mut self, line: &str) -> FedLines {
+    let mut fed = FedLines::default();
+    let status = line
+      .starts_with('{')
+      .then(|| from_json::<SolveStatus>(line).ok())
+      .flatten();
+    let Some(status) = status else {
+      if is_build_summary_line(line) {
+        fed.show.push(line.to_string());
+      }
+      fed.log.push(line.to_string());
+      return fed;
+    };
+
+    for vertex in status.vertexes {
+      self.update_vertex(vertex, &mut fed);
+    }
+    for entry in status.logs {
+      let Some(progress) = self.vertexes.get_mut(&entry.vertex) else {
+        continue;
+      };
+      progress
+        .partial
+        .push_str(&String::from_utf8_lossy(&decode_base64(&entry.data)));
+      while let Some(end) = progress.partial.find('\n') {
+        let text: String = progress.partial.drain(..=end).collect();
+        progress.push_line(text.trim_end_matches(['\r', '\n']), &mut fed);
+      }
+    }
+    fed
+  }
+
+  fn update_vertex(&mut self, vertex: SolveVertex, fed: &mut FedLines) {
+    let next_number = self.vertexes.len() + 1;
+    let progress = self
+      .vertexes
+      .entry(vertex.digest.clone())
+      .or_insert_with(|| VertexProgress {
+        number: next_number,
+        ..Default::default()
+      });
+    if progress.name.is_empty() && !vertex.name.is_empty() {
+      progress.name = vertex.name.clone();
+      fed
+        .log
+        .push(format!("#{} {}", progress.number, progress.name));
+    }
+    if vertex.started.is_some() && progress.started.is_none() {
+      progress.started = Some(std::time::Instant::now());
+      let phase = BuildPhase::of_vertex(&progress.name);
+      if self.phase.is_none_or(|current| phase > current) {
+        self.phase = Some(phase);
+        fed.show.push(format!("{}...", phase.describe()));
+      }
+    }
+    if let Some(error) = vertex.error.filter(|e| !e.is_empty()) {
+      if progress.error.is_none() {
+        let pending = std::mem::take(&mut progress.partial);
+        if !pending.is_empty() {
+          progress.push_line(pending.trim_end(), fed);
+        }
+        fed
+          .log
+          .push(format!("#{} ERROR: {}", progress.number, error));
+        progress.error = Some(error);
+      }
+      return;
+    }
+    if vertex.completed.is_none() || progress.done {
+      return;
+    }
+    progress.done = true;
+    progress.cached = vertex.cached;
+    let elapsed = progress
+      .started
+      .map(|started| started.elapsed().as_secs_f64())
+      .unwrap_or_default();
+    if vertex.cached {
+      fed.log.push(format!("#{} CACHED", progress.number));
+    } else {
+      fed
+        .log
+        .push(format!("#{} DONE {:.1}s", progress.number, elapsed));
+    }
+    if is_build_step(&progress.name) {
+      let outcome = if vertex.cached {
+        "cached".to_string()
+      } else {
+        format!("{:.1}s", elapsed)
+      };
+      fed.show.push(format!("{} ({})", progress.name, outcome));
+    }
+  }
+
+  /// Dockerfile steps finished and how many came from the cache.
+  pub fn summary(&self) -> Option<String> {
+    let steps: Vec<&VertexProgress> = self
+      .vertexes
+      .values()
+      .filter(|v| v.done && is_build_step(&v.name))
+      .collect();
+    if steps.is_empty() {
+      return None;
+    }
+    let cached = steps.iter().filter(|v| v.cached).count();
+    Some(format!(
+      "{} step(s), {} cached ({}%)",
+      steps.len(),
+      cached,
+      cached * 100 / steps.len()
+    ))
+  }
+
+  /// The step that failed first; steps buildx canceled because of it don't count.
+  pub fn failure(&self) -> Option<BuildFailure> {
+    let mut failed: Vec<&VertexProgress> = self
+      .vertexes
+      .values()
+      .filter(|v| {
+        v.error
+          .as_deref()
+          .is_some_and(|e| !e.contains("context canceled"))
+      })
+      .collect();
+    failed.sort_by_key(|v| v.number);
+    let vertex = failed.first()?;
+    Some(BuildFailure {
+      step: vertex.name.clone(),
+      error: vertex.error.clone().unwrap_or_default(),
+      excerpt: vertex.tail.iter().cloned().collect(),
+    })
   }
+}
 
-  // Tag it as latest
+impl VertexProgress {
+  fn push_line(&mut self, text: &str, fed: &mut FedLines) {
+    fed.log.push(format!("#{} {}", self.number, text));
+    if self.tail.len() == FAILED_STEP_EXCERPT_LINES {
+      self.tail.pop_front();
+    }
+    self.tail.push_back(text.to_string());
+  }
+}
+
+/// Decode standard base64 (with or without padding); invalid characters are skipped.
+fn decode_base64(input: &str) -> Vec<u8> {
+  let value = |c: u8| match c {
+    b'A'..=b'Z' => Some(c - b'A'),
+    b'a'..=b'z' => Some(c - b'a' + 26),
+    b'0'..=b'9' => Some(c - b'0' + 52),
+    b'+' => Some(62),
+    b'/' => Some(63),
+    _ => None,
+  };
+  let mut out = Vec::with_capacity(input.len() * 3 / 4);
+  let (mut buffer, mut bits) = (0u32, 0);
+  for sextet in input.bytes().filter_map(value) {
+    buffer = (buffer << 6) | sextet as u32;
+    bits += 6;
+    if bits >= 8 {
+      bits -= 8;
+      out.push((buffer >> bits) as u8);
+      buffer &= (1 << bits) - 1;
+    }
+  }
+  out
+}
+
+async fn print_log_tail(log_file: &Path, lines: usize) {
+  let Ok(content) = fs::read_to_string(log_file).await else {
+    return;
+  };
+  let all: Vec<String> = content.lines().map(str::to_string).collect();
+  print_build_output("the build", &all[all.len().saturating_sub(lines)..]);
+}
+
+/// Show the last build output lines as one error, so they also land in the deploy log.
+fn print_build_output(source: &str, lines: &[String]) {
+  if lines.is_empty() {
+    return;
+  }
+  err(&format!("output of {}:\n  {}", source, lines.join("\n  ")));
+}
+
+/// Lines worth showing in the deploy stream from `--progress plain` output: step headers
+/// (`#5 [build 3/7] RUN bundle install`), pushes and errors.
+pub fn is_build_summary_line(line: &str) -> bool {
+  let Some(rest) = line.strip_prefix('#') else {
+    return line.contains("ERROR") || line.starts_with("error:");
+  };
+  let body = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+  body.starts_with(" [") || body.starts_with(" pushing") || body.contains("ERROR")
+}
+
+pub async fn retag_latest(image: &str, from_tag: &str) -> Result<()> {
+  if let Some(result) = via_engine(engine::pull(from_tag).await) {
+    result?;
+  } else {
+    docker_cmd(&["pull", from_tag]).await?;
+  }
+  tag_and_push_latest(image, from_tag).await
+}
+
+/// Point :latest back at a previously recorded local image ID (see `image_id`), e.g. to undo
+/// a retag whose release failed its health checks.
+pub async fn restore_latest(image: &str, previous_id: &str) -> Result<()> {
+  tag_and_push_latest(image, previous_id).await
+}
+
+async fn tag_and_push_latest(image: &str, source: &str) -> Result<()> {
   let latest = format!("{}:latest", image);
+  if let Some(result) = via_engine(engine::tag(source, &latest).await) {
+    result?;
+  } else {
+    docker_cmd(&["tag", source, &latest]).await?;
+  }
+  match via_engine(engine::push(&latest).await) {
+    Some(result) => result,
+    None => docker_cmd(&["push", &latest]).await,
+  }
+}
+
+/// The outcome of an Engine API call, or None when the daemon's API can't be reached and the
+/// docker CLI should do it instead.
+fn via_engine<T>(result: std::result::Result<T, EngineError>) -> Option<Result<T>> {
+  match result {
+    Err(EngineError::Unavailable(reason)) => {
+      debug(&format!("{}; falling back to the docker CLI", reason));
+      None
+    }
+    result => Some(result.map_err(|e| Error::Docker(e.into()).into())),
+  }
+}
+
+/// Run a docker CLI command with inherited stdio, failing with the full command line so an
+/// error says which image or tag it was about. For what the Engine API client doesn't cover
+/// or can't reach.
+async fn docker_cmd(args: &[&str]) -> Result<()> {
   let status = Command::new("docker")
-    .args(["tag", from_tag, &latest])
+    .args(args)
     .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
+    .stdout(progress_stdout())
     .stderr(Stdio::inherit())
     .status()
+    .await
+    .context("failed to run docker")?;
+  if !status.success() {
+    return Err(
+      Error::Docker(anyhow::anyhow!(
+        "docker {} failed with status: {}",
+        args.join(" "),
+        status
+      ))
+      .into(),
+    );
+  }
+  Ok(())
+}
+
+/// Local image ID for a reference, or None if the image isn't present on this host.
+pub async fn image_id(reference: &str) -> Result<Option<String>> {
+  if let Some(result) = via_engine(engine::image_id(reference).await) {
+    return result;
+  }
+  let output = Command::new("docker")
+    .args(["image", "inspect", "--format", "{{.Id}}", reference])
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
     .await?;
+  if !output.status.success() {
+    return Ok(None);
+  }
+  let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+  Ok((!id.is_empty()).then_some(id))
+}
 
-  if !status.success() {
-    anyhow::bail!("docker tag failed");
+/// Local tags of `image` as `docker image ls` lines of `<tag>\t<id>\t<created since>`,
+/// newest first.
+pub async fn list_image_tags(image: &str) -> Result<String> {
+  let output = Command::new("docker")
+    .args([
+      "image",
+      "ls",
+      "--format",
+      "{{.Tag}}\t{{.ID}}\t{{.CreatedSince}}",
+      image,
+    ])
+    .stdin(Stdio::null())
+    .stderr(Stdio::inherit())
+    .output()
+    .await?;
+  if !output.status.success() {
+    anyhow::bail!("docker image ls failed with status: {}", output.status);
   }
+  Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
 
-  // Push latest
+/// Remove a local image tag. Only the tag on this host goes away; the registry keeps it.
+pub async fn remove_image(reference: &str) -> Result<()> {
   let status = Command::new("docker")
-    .args(["push", &latest])
-    .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
+    .args(["image", "rm", reference])
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
     .stderr(Stdio::inherit())
     .status()
     .await?;
-
   if !status.success() {
-    anyhow::bail!("docker push failed");
+    anyhow::bail!(
+      "docker image rm {} failed with status: {}",
+      reference,
+      status
+    );
   }
+  Ok(())
+}
 
+/// Log in to the registries of any accessories with a `pullSecret`, so compose (and the
+/// systemd units running it) can pull their images.
+pub async fn login_accessory_registries(cfg: &HLConfig, accessories: &[String]) -> Result<()> {
+  for name in accessories {
+    let Some(secret) = cfg
+      .accessories
+      .get(name)
+      .and_then(|a| a.pull_secret.as_ref())
+    else {
+      continue;
+    };
+    let env = load_env_file_contents(&env_file(&cfg.app))?;
+    let password = env.get(&secret.password_env).with_context(|| {
+      format!(
+        "pull secret for {} references {} but it is not set in .env",
+        name, secret.password_env
+      )
+    })?;
+
+    debug(&format!(
+      "logging in to {} as {} for accessory {}",
+      secret.registry, secret.username, name
+    ));
+    login_with_password(&secret.registry, &secret.username, password).await?;
+  }
   Ok(())
 }
 
@@ -174,14 +726,12 @@ pub async fn restart_compose(
     anyhow::bail!("App directory not found: {}", dir.display());
   }
 
-  let mut args = vec!["compose".to_string()];
-  args.push("-f".into());
-  args.push("compose.yml".into());
-  for name in processes.iter().chain(accessories.iter()) {
-    args.push("-f".into());
-    args.push(format!("compose.{name}.yml"));
-  }
+  let compose_args = compose_file_args(&dir, processes, accessories);
+  let mut args = compose_args.clone();
   args.push("pull".into());
+  if is_quiet() {
+    args.push("--quiet".into());
+  }
 
   debug("pulling latest images with docker compose");
 
@@ -189,23 +739,202 @@ pub async fn restart_compose(
     .args(&args)
     .current_dir(&dir)
     .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
+    .stdout(progress_stdout())
     .stderr(Stdio::inherit())
     .status()
     .await?;
 
   if !status.success() {
-    anyhow::bail!("docker compose pull failed with status: {}", status);
+    return Err(
+      Error::Docker(anyhow::anyhow!(
+        "docker compose pull failed with status: {}",
+        status
+      ))
+      .into(),
+    );
+  }
+
+  let fingerprints = match process_fingerprints(&cfg.app, &dir, &compose_args, processes).await {
+    Ok(fingerprints) => Some(fingerprints),
+    Err(e) => {
+      warn(&format!(
+        "can't tell which processes changed ({:#}); restarting all of them",
+        e
+      ));
+      None
+    }
+  };
+  let target_active = user_unit_active_state(&format!("app-{}.target", cfg.app))
+    .await
+    .is_ok_and(|state| state == "active");
+  let applied = target_active
+    .then(|| load_applied_processes(&dir))
+    .flatten();
+  match (&fingerprints, applied) {
+    (Some(current), Some(applied)) => {
+      let mut changed = changed_processes(current, &applied);
+      // A process that crashed since is started again even when nothing changed
+      let unchanged: Vec<&String> = processes.iter().filter(|p| !changed.contains(p)).collect();
+      for process in unchanged {
+        let state = user_unit_active_state(&format!("app-{}-{}.service", cfg.app, process)).await;
+        if !state.is_ok_and(|state| state == "active") {
+          changed.push(process.clone());
+        }
+      }
+      if changed.is_empty() {
+        log("no process changed; nothing to restart");
+      } else {
+        log(&format!("restarting {}", changed.join(", ")));
+        restart_process_units(&cfg.app, &changed).await?;
+      }
+    }
+    _ => restart_app_target(&cfg.app).await?,
+  }
+  if let Some(fingerprints) = fingerprints {
+    if let Err(e) = save_applied_processes(&dir, fingerprints) {
+      warn(&format!("failed to record restarted processes: {:#}", e));
+    }
+  }
+
+  Ok(())
+}
+
+/// `compose` and the `-f` files of an app's whole stack in `dir`: the base file, process and
+/// accessory overlays, then the user's override files.
+pub fn compose_file_args(dir: &Path, processes: &[String], accessories: &[String]) -> Vec<String> {
+  let mut args = vec!["compose".to_string()];
+  args.push("-f".into());
+  args.push("compose.yml".into());
+  for name in processes.iter().chain(accessories.iter()) {
+    args.push("-f".into());
+    args.push(format!("compose.{name}.yml"));
+  }
+  for name in processes {
+    let user_override = override_file(name);
+    if dir.join(&user_override).exists() {
+      args.push("-f".into());
+      args.push(user_override);
+    }
   }
+  args
+}
+
+/// The stack `compose_args` (see `compose_file_args`) describes, merged and resolved by
+/// `docker compose config`: one file, with .env interpolated and env_files inlined.
+pub async fn compose_config(dir: &Path, compose_args: &[String]) -> Result<String> {
+  let output = Command::new("docker")
+    .args(compose_args)
+    .arg("config")
+    .current_dir(dir)
+    .stdin(Stdio::null())
+    .output()
+    .await
+    .context("failed to run docker")?;
+  if !output.status.success() {
+    return Err(
+      Error::Docker(anyhow::anyhow!(
+        "docker compose config failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+      ))
+      .into(),
+    );
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
 
-  restart_app_target(&cfg.app).await?;
+/// What each process was last (re)started with, by `restart_compose`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedProcesses {
+  /// Fingerprint of each process, see `process_fingerprints`
+  pub processes: BTreeMap<String, String>,
+}
+
+fn applied_processes_path(dir: &Path) -> PathBuf {
+  dir.join("processes.applied.yml")
+}
 
+fn load_applied_processes(dir: &Path) -> Option<AppliedProcesses> {
+  let content = std::fs::read_to_string(applied_processes_path(dir)).ok()?;
+  serde_yaml::from_str(&content).ok()
+}
+
+fn save_applied_processes(dir: &Path, processes: BTreeMap<String, String>) -> Result<()> {
+  let applied = AppliedProcesses { processes };
+  std::fs::write(
+    applied_processes_path(dir),
+    serde_yaml::to_string(&applied)?,
+  )?;
   Ok(())
 }
 
+/// Forget what processes were started with, so the next `restart_compose` restarts them all
+/// (e.g. after their data was replaced underneath them).
+pub fn forget_applied_processes(dir: &Path) -> Result<()> {
+  match std::fs::remove_file(applied_processes_path(dir)) {
+    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+    _ => Ok(()),
+  }
+}
+
+/// Fingerprint of each process: its service as `docker compose config` resolves it from every
+/// overlay (with the image reference replaced by the image id, so a retag to identical content
+/// counts as unchanged), its systemd unit and the .env. Hashed with `env_hash`.
+async fn process_fingerprints(
+  app: &str,
+  dir: &Path,
+  compose_args: &[String],
+  processes: &[String],
+) -> Result<BTreeMap<String, String>> {
+  let config: serde_yaml::Value = serde_yaml::from_str(&compose_config(dir, compose_args).await?)?;
+  let env = std::fs::read(env_file(app)).unwrap_or_default();
+  let mut fingerprints = BTreeMap::new();
+  for process in processes {
+    let mut service = config["services"][process.as_str()].clone();
+    if let Some(reference) = service["image"].as_str().map(str::to_string) {
+      let id = image_id(&reference).await?.unwrap_or(reference);
+      service["image"] = serde_yaml::Value::String(id);
+    }
+    let unit = std::fs::read(systemd_dir().join(format!("app-{}-{}.service", app, process)))
+      .unwrap_or_default();
+    let mut content = serde_yaml::to_string(&service)?.into_bytes();
+    content.extend_from_slice(&unit);
+    content.extend_from_slice(&env);
+    fingerprints.insert(process.clone(), env_hash(&content));
+  }
+  Ok(fingerprints)
+}
+
+/// Processes whose fingerprint differs from the one they were last started with, or that
+/// weren't running then.
+pub fn changed_processes(
+  current: &BTreeMap<String, String>,
+  applied: &AppliedProcesses,
+) -> Vec<String> {
+  current
+    .iter()
+    .filter(|(process, fingerprint)| applied.processes.get(*process) != Some(fingerprint))
+    .map(|(process, _)| process.clone())
+    .collect()
+}
+
 /// Build the docker run command arguments for migrations
 fn build_migration_args(cfg: &HLConfig, image_tag: &str, env_path: &str) -> Vec<String> {
+  build_run_args(cfg, image_tag, env_path, &cfg.migrations.command, &[])
+}
+
+/// `docker run` arguments for a one-off container of the app: its .env, network and
+/// persistent volume, plus the migrations env, user, workdir and volumes. `flags` (e.g. `-it`)
+/// go before the image.
+pub fn build_run_args(
+  cfg: &HLConfig,
+  image_tag: &str,
+  env_path: &str,
+  command: &[String],
+  flags: &[&str],
+) -> Vec<String> {
   let mut args = vec!["run".to_string(), "--rm".to_string()];
+  args.extend(flags.iter().map(|f| f.to_string()));
 
   // Add env file
   args.push("--env-file".to_string());
@@ -217,6 +946,25 @@ fn build_migration_args(cfg: &HLConfig, image_tag: &str, env_path: &str) -> Vec<
     args.push(format!("{}={}", k, v));
   }
 
+  // Migrations must see the same persistent data as the app (e.g. a SQLite database)
+  if let (Some(p), Some(volume)) = (&cfg.persistence, cfg.persistence_volume()) {
+    args.push("-v".to_string());
+    args.push(format!("{}:{}", volume, p.path));
+  }
+  for volume in &cfg.migrations.volumes {
+    args.push("-v".to_string());
+    args.push(resolve_bind_source(&app_dir(&cfg.app), volume));
+  }
+
+  if let Some(user) = &cfg.migrations.user {
+    args.push("--user".to_string());
+    args.push(user.clone());
+  }
+  if let Some(workdir) = &cfg.migrations.workdir {
+    args.push("--workdir".to_string());
+    args.push(workdir.clone());
+  }
+
   // Add network
   args.push("--network".to_string());
   args.push(cfg.network.clone());
@@ -225,13 +973,24 @@ fn build_migration_args(cfg: &HLConfig, image_tag: &str, env_path: &str) -> Vec<
   args.push(image_tag.to_string());
 
   // Add command
-  for cmd_part in &cfg.migrations.command {
-    args.push(cmd_part.clone());
-  }
+  args.extend(command.iter().cloned());
 
   args
 }
 
+/// Make a `./src:/dst` bind mount absolute against `base`; `docker run` doesn't resolve
+/// relative sources the way compose does. Named volumes and absolute paths pass through.
+fn resolve_bind_source(base: &Path, volume: &str) -> String {
+  if volume.starts_with("./") || volume.starts_with("../") {
+    base
+      .join(volume.strip_prefix("./").unwrap_or(volume))
+      .to_string_lossy()
+      .to_string()
+  } else {
+    volume.to_string()
+  }
+}
+
 pub async fn run_migrations(cfg: &HLConfig, image_tag: &str) -> Result<()> {
   if cfg.migrations.command.is_empty() {
     debug("migrations command is empty, skipping");
@@ -271,13 +1030,15 @@ pub async fn run_migrations(cfg: &HLConfig, image_tag: &str) -> Result<()> {
     .args(&args)
     .current_dir(&dir)
     .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
+    .stdout(progress_stdout())
     .stderr(Stdio::inherit())
     .status()
     .await?;
 
   if !status.success() {
-    anyhow::bail!("migrations failed with status: {}", status);
+    return Err(
+      Error::Migration(anyhow::anyhow!("migrations failed with status: {}", status)).into(),
+    );
   }
 
   debug("migrations completed successfully");
@@ -287,55 +1048,345 @@ pub async fn run_migrations(cfg: &HLConfig, image_tag: &str) -> Result<()> {
 
 pub struct ImageTags {
   pub sha: String,
-  pub branch_sha: String,
+  /// `<branch>-<sha>`; tag deploys have no branch and get the version tag instead
+  pub branch_sha: Option<String>,
   pub latest: String,
+  /// Human-readable release tag (e.g. `:v1.4.2`), only set for tag deploys
+  pub version: Option<String>,
 }
 
-pub fn tag_for(cfg: &HLConfig, sha: &str, branch: &str) -> ImageTags {
-  let short = &sha[..7.min(sha.len())];
+impl ImageTags {
+  /// All tags to push, in the order they are passed to buildx.
+  pub fn all(&self) -> Vec<String> {
+    let mut tags = vec![self.sha.clone()];
+    tags.extend(self.branch_sha.clone());
+    tags.push(self.latest.clone());
+    if let Some(version) = &self.version {
+      tags.push(version.clone());
+    }
+    tags
+  }
+}
+
+pub fn tag_for(
+  cfg: &HLConfig,
+  sha: &str,
+  branch: Option<&str>,
+  version: Option<&str>,
+) -> ImageTags {
+  image_tags(&cfg.image, sha, branch, version)
+}
+
+/// Tags of `image` for a release; `tag_for` is the app image's.
+pub fn image_tags(
+  image: &str,
+  sha: &str,
+  branch: Option<&str>,
+  version: Option<&str>,
+) -> ImageTags {
+  let short = short_sha(sha);
   ImageTags {
-    sha: format!("{}:{}", cfg.image, short),
-    branch_sha: format!("{}:{}-{}", cfg.image, branch, short),
-    latest: format!("{}:latest", cfg.image),
+    sha: format!("{}:{}", image, short),
+    branch_sha: branch.map(|branch| format!("{}:{}-{}", image, branch, short)),
+    latest: format!("{}:latest", image),
+    version: version.map(|v| format!("{}:{}", image, v)),
+  }
+}
+
+/// Validate a release version against Docker's tag grammar
+/// (`[A-Za-z0-9_][A-Za-z0-9_.-]{0,127}`).
+pub fn validate_version_tag(version: &str) -> Result<()> {
+  let re = regex::Regex::new(r"^[A-Za-z0-9_][A-Za-z0-9_.-]{0,127}$")?;
+  if !re.is_match(version) {
+    anyhow::bail!(
+      "invalid release tag {:?}: not a valid docker image tag",
+      version
+    );
+  }
+  Ok(())
+}
+
+/// Whether a docker network exists, e.g. the external Traefik network compose files join.
+pub async fn network_exists(name: &str) -> Result<bool> {
+  let status = Command::new("docker")
+    .args(["network", "inspect", name])
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+    .await
+    .context("failed to run docker")?;
+  Ok(status.success())
+}
+
+/// Create a bridge network, optionally on a fixed subnet (e.g. one that doesn't collide with
+/// a VPN's routes).
+pub async fn create_network(name: &str, subnet: Option<&str>) -> Result<()> {
+  let mut args = vec!["network", "create"];
+  if let Some(subnet) = subnet {
+    args.extend(["--subnet", subnet]);
+  }
+  args.push(name);
+  let status = Command::new("docker")
+    .args(&args)
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::inherit())
+    .status()
+    .await
+    .context("failed to run docker")?;
+  if !status.success() {
+    anyhow::bail!(
+      "docker network create {} failed with status: {}",
+      name,
+      status
+    );
   }
+  Ok(())
 }
 
-/// Generate the base compose.yml file content for an application
+/// Create a docker volume if it doesn't exist yet (`docker volume create` is idempotent).
+pub async fn ensure_volume(name: &str) -> Result<()> {
+  let status = Command::new("docker")
+    .args(["volume", "create", name])
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::inherit())
+    .status()
+    .await?;
+  if !status.success() {
+    anyhow::bail!(
+      "docker volume create {} failed with status: {}",
+      name,
+      status
+    );
+  }
+  Ok(())
+}
+
+/// Generate the base compose.yml file content for an application. The release it is pinned
+/// to (see `pin_base_image`) is kept.
 pub async fn write_base_compose_file(
   dir: &Path,
   image: &str,
   network: &str,
   volumes: &[String],
+  named_volumes: &[String],
+  mode: SystemdMode,
+  logging: Option<&LoggingConfig>,
 ) -> Result<()> {
+  let tag = pinned_tag(dir, image).unwrap_or_else(|| "latest".to_string());
+  let compose = render_base_compose(image, &tag, network, volumes, named_volumes, mode, logging);
+  let compose_path = dir.join("compose.yml");
+  fs::write(&compose_path, compose).await?;
+  Ok(())
+}
+
+/// Content of the base compose.yml written by `write_base_compose_file`.
+pub fn render_base_compose(
+  image: &str,
+  tag: &str,
+  network: &str,
+  volumes: &[String],
+  named_volumes: &[String],
+  mode: SystemdMode,
+  logging: Option<&LoggingConfig>,
+) -> String {
   let volumes_section = if volumes.is_empty() {
     String::new()
   } else {
     let entries: Vec<String> = volumes.iter().map(|v| format!("      - {}", v)).collect();
     format!("\n    volumes:\n{}", entries.join("\n"))
   };
-  let compose = format!(
+  // Each process runs in its own compose project, so named volumes are created up front
+  // (see `ensure_volume`) and referenced as external to share them across projects
+  let named_volumes_section = if named_volumes.is_empty() {
+    String::new()
+  } else {
+    let entries: Vec<String> = named_volumes
+      .iter()
+      .map(|v| format!("  {}:\n    external: true", v))
+      .collect();
+    format!("volumes:\n{}\n", entries.join("\n"))
+  };
+  // Attached units restart the process themselves; a docker restart policy would revive a
+  // crashed container behind systemd's back, so the unit would never see it fail
+  let restart = match mode {
+    SystemdMode::Oneshot => "unless-stopped",
+    SystemdMode::Attached => "\"no\"",
+  };
+  // Processes extend `base`, so they all inherit its log driver
+  let logging_section = logging_yaml(logging);
+  format!(
     r#"
 services:
   base:
-    image: {image}:latest
-    restart: unless-stopped
+    image: {image}:{tag}
+    restart: {restart}
     env_file: [.env]
     networks: [{network}]
-    profiles: ["_template"]{volumes_section}
+    profiles: ["_template"]{volumes_section}{logging_section}
 networks:
   {network}:
     external: true
     name: {network}
-"#,
+{named_volumes_section}"#,
     image = image,
     network = network,
-    volumes_section = volumes_section
-  );
-  let compose_path = dir.join("compose.yml");
-  fs::write(&compose_path, compose).await?;
+    volumes_section = volumes_section,
+    named_volumes_section = named_volumes_section
+  )
+}
+
+/// A service's `logging:` key (starting with a newline, indented for a service), or nothing
+/// when hl.yml leaves the log driver to the docker daemon.
+pub fn logging_yaml(logging: Option<&LoggingConfig>) -> String {
+  let Some(logging) = logging else {
+    return String::new();
+  };
+  let mut section = format!("\n    logging:\n      driver: {}", logging.driver());
+  let options = logging.driver_options();
+  if !options.is_empty() {
+    section.push_str("\n      options:");
+    for (key, value) in &options {
+      section.push_str(&format!("\n        {}: {:?}", key, value));
+    }
+  }
+  section
+}
+
+/// Split an image reference into repository and tag (`localhost:5000/app:abc1234` ->
+/// (`localhost:5000/app`, Some(`abc1234`))).
+pub fn split_image_ref(reference: &str) -> (&str, Option<&str>) {
+  match reference.rsplit_once(':') {
+    Some((repo, tag)) if !tag.contains('/') => (repo, Some(tag)),
+    _ => (reference, None),
+  }
+}
+
+/// Tag the base service of `dir`'s compose.yml runs, when it runs `image` at all (an image
+/// renamed in hl.yml starts over from :latest).
+pub fn pinned_tag(dir: &Path, image: &str) -> Option<String> {
+  let content = std::fs::read_to_string(dir.join("compose.yml")).ok()?;
+  let reference = compose_service_values(&content, "image")
+    .ok()?
+    .into_iter()
+    .find(|(service, _)| service == "base")?
+    .1;
+  match split_image_ref(reference.as_str()?) {
+    (repo, Some(tag)) if repo == image => Some(tag.to_string()),
+    _ => None,
+  }
+}
+
+/// Point the app's processes at one release (`image:<tag>`) rather than the moving :latest, so
+/// `compose pull` and restarts are deterministic and compose.yml records what is running.
+pub async fn pin_base_image(dir: &Path, image: &str, tag: &str) -> Result<()> {
+  let path = dir.join("compose.yml");
+  let content = fs::read_to_string(&path)
+    .await
+    .with_context(|| format!("failed to read {}", path.display()))?;
+  let line = format!("    image: {}:{}", image, tag);
+  let mut replaced = false;
+  let pinned: Vec<&str> = content
+    .lines()
+    .map(|l| {
+      // The base service's is the only 4-space `image:` hl writes
+      if !replaced && l.starts_with("    image: ") {
+        replaced = true;
+        line.as_str()
+      } else {
+        l
+      }
+    })
+    .collect();
+  if !replaced {
+    anyhow::bail!("{} has no base image to pin", path.display());
+  }
+  debug(&format!("pinning {} to {}:{}", path.display(), image, tag));
+  fs::write(&path, pinned.join("\n") + "\n").await?;
+
+  // Processes built from their own Dockerfile run `<image>-<process>` of the same release
+  let prefix = format!("    image: {}-", image);
+  let mut entries = fs::read_dir(dir).await?;
+  while let Some(entry) = entries.next_entry().await? {
+    let path = entry.path();
+    let name = entry.file_name().to_string_lossy().to_string();
+    if name == "compose.yml" || !name.starts_with("compose.") || !name.ends_with(".yml") {
+      continue;
+    }
+    let content = fs::read_to_string(&path).await?;
+    if !content.lines().any(|l| l.starts_with(&prefix)) {
+      continue;
+    }
+    let pinned: Vec<String> = content
+      .lines()
+      .map(|l| match l.strip_prefix("    image: ") {
+        Some(reference) if l.starts_with(&prefix) => {
+          format!("    image: {}:{}", split_image_ref(reference).0, tag)
+        }
+        _ => l.to_string(),
+      })
+      .collect();
+    debug(&format!("pinning {} to {}", path.display(), tag));
+    fs::write(&path, pinned.join("\n") + "\n").await?;
+  }
+  Ok(())
+}
+
+/// A process overlay running `image` (its `processes.<name>.dockerfile` build) instead of the
+/// base service's.
+pub fn with_process_image(overlay: &str, image: &str) -> String {
+  // Every overlay extends the base service; the image overrides the one it inherits
+  overlay.replacen(
+    "      service: base\n",
+    &format!("      service: base\n    image: {}\n", image),
+    1,
+  )
+}
+
+/// Point the overlays of processes with their own Dockerfile at their image, at the release
+/// compose.yml is pinned to; `write_process_compose_files` leaves them on the base image.
+pub async fn write_process_images(dir: &Path, cfg: &HLConfig) -> Result<()> {
+  let tag = pinned_tag(dir, &cfg.image).unwrap_or_else(|| "latest".to_string());
+  for (process, _) in cfg.process_dockerfiles() {
+    let path = dir.join(format!("compose.{}.yml", process));
+    let Ok(content) = fs::read_to_string(&path).await else {
+      continue;
+    };
+    let image = format!("{}:{}", cfg.process_image(&process), tag);
+    fs::write(&path, with_process_image(&content, &image)).await?;
+  }
   Ok(())
 }
 
+/// Build and push every image of a release, at most `limit` at a time. All builds run to the
+/// end; the first failure is returned and the others are reported.
+pub async fn build_and_push_all(builds: Vec<BuildPushOptions>, limit: usize) -> Result<()> {
+  let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(limit.max(1)));
+  let mut tasks = tokio::task::JoinSet::new();
+  for opts in builds {
+    let semaphore = semaphore.clone();
+    tasks.spawn(async move {
+      let _permit = semaphore.acquire_owned().await?;
+      let image = opts.tags.first().cloned().unwrap_or_default();
+      build_and_push(opts)
+        .await
+        .with_context(|| format!("building {}", image))
+    });
+  }
+  let mut first_error = None;
+  while let Some(result) = tasks.join_next().await {
+    if let Err(e) = result.map_err(anyhow::Error::from).and_then(|r| r) {
+      match first_error {
+        None => first_error = Some(e),
+        Some(_) => err(&format!("{:#}", e)),
+      }
+    }
+  }
+  first_error.map_or(Ok(()), Err)
+}
+
 /// Clean up orphaned process compose files that are no longer needed.
 ///
 /// This function:
@@ -346,6 +1397,7 @@ networks:
 async fn cleanup_orphaned_compose_files(
   dir: &Path,
   processes: Option<&std::collections::HashMap<String, String>>,
+  app: &str,
 ) -> Result<()> {
   // Read directory entries
   let entries = match tokio::fs::read_dir(dir).await {
@@ -371,8 +1423,14 @@ async fn cleanup_orphaned_compose_files(
     expected_files.insert("compose.web.yml".to_string());
   }
 
-  // Known accessory files to preserve
-  let accessory_files = ["compose.postgres.yml", "compose.redis.yml"];
+  // Accessory overlays to preserve, plugin ones included. Apps with neither a state.yml nor an
+  // accessories unit predate plugins, so only the built-in kinds can be theirs.
+  let accessories = recorded_accessories(&systemd_dir(), dir, app)?
+    .unwrap_or_else(|| vec!["postgres".to_string(), "redis".to_string()]);
+  let accessory_files: std::collections::HashSet<String> = accessories
+    .iter()
+    .map(|accessory| format!("compose.{}.yml", accessory))
+    .collect();
 
   // Find orphaned compose files
   let mut entries_stream = entries;
@@ -385,13 +1443,13 @@ async fn cleanup_orphaned_compose_files(
       continue;
     }
 
-    // Skip the base compose.yml
-    if file_name_str == "compose.yml" {
+    // Skip the base compose.yml and the user's overrides
+    if file_name_str == "compose.yml" || file_name_str.starts_with("compose.override.") {
       continue;
     }
 
     // Skip known accessory files
-    if accessory_files.contains(&file_name_str.as_ref()) {
+    if accessory_files.contains(file_name_str.as_ref()) {
       continue;
     }
 
@@ -419,6 +1477,90 @@ async fn cleanup_orphaned_compose_files(
   Ok(())
 }
 
+/// How Traefik routes to the web process.
+pub struct WebRouter {
+  pub rule: String,
+  pub redirect_www: bool,
+  /// Container healthcheck of the web process; Traefik only routes to healthy containers
+  pub healthcheck: Option<ContainerHealthcheck>,
+}
+
+impl Default for WebRouter {
+  /// Only the `DOMAIN` from .env
+  fn default() -> Self {
+    Self {
+      rule: "Host(`${DOMAIN}`)".to_string(),
+      redirect_www: false,
+      healthcheck: None,
+    }
+  }
+}
+
+impl WebRouter {
+  pub fn for_config(cfg: &HLConfig) -> Self {
+    Self {
+      rule: cfg.host_rule("${DOMAIN}"),
+      redirect_www: cfg.redirect_www,
+      healthcheck: cfg
+        .health
+        .container
+        .then(|| ContainerHealthcheck::for_config(cfg))
+        .flatten(),
+    }
+  }
+}
+
+/// A compose `healthcheck:` probing the app's health URL from inside its container.
+#[derive(Debug, PartialEq)]
+pub struct ContainerHealthcheck {
+  /// The health URL with its host replaced by localhost
+  pub url: String,
+  pub interval_ms: u64,
+  /// Grace period after start, during which failures don't count: the deploy's health timeout
+  pub start_period_ms: u64,
+}
+
+impl ContainerHealthcheck {
+  /// None when `health.url` or its durations can't be parsed.
+  pub fn for_config(cfg: &HLConfig) -> Option<Self> {
+    let mut url = reqwest::Url::parse(&cfg.health.url).ok()?;
+    url.set_host(Some("localhost")).ok()?;
+    Some(Self {
+      url: url.to_string(),
+      interval_ms: parse_duration(&cfg.health.interval).ok()?,
+      start_period_ms: parse_duration(&cfg.health.timeout).ok()?,
+    })
+  }
+
+  fn yaml(&self) -> String {
+    // Images ship curl or wget (or neither: then the container turns unhealthy, so it's opt-in).
+    // The URL is quoted for the container's shell, and `$` doubled so compose doesn't
+    // interpolate it.
+    let url = shell_words::quote(&self.url).replace('$', "$$");
+    let probe = format!(
+      "curl -fsS -m 3 -o /dev/null {url} || wget -q -T 3 -O /dev/null {url}",
+      url = url
+    );
+    format!(
+      r#"
+    healthcheck:
+      test: ["CMD-SHELL", {probe:?}]
+      interval: {interval}ms
+      timeout: 5s
+      retries: 3
+      start_period: {start}ms"#,
+      interval = self.interval_ms,
+      start = self.start_period_ms
+    )
+  }
+}
+
+/// `compose.override.<process>.yml`: the user's own overlay for a process, for compose options
+/// hl doesn't model. It's applied after hl's overlay but never written or removed by hl.
+pub fn override_file(process: &str) -> String {
+  format!("compose.override.{}.yml", process)
+}
+
 /// Generate process-specific compose files from a Procfile
 ///
 /// For each process in the map, creates a `compose.{process}.yml` file
@@ -433,19 +1575,24 @@ async fn cleanup_orphaned_compose_files(
 /// * `processes` - Optional map of process names to commands from Procfile
 /// * `app` - Application name for Traefik labels
 /// * `resolver` - Traefik certificate resolver name
+/// * `router` - Hosts and redirects for the web process
+/// * `settings` - hl.yml `processes:` (GPUs and devices), keyed by process name
 pub async fn write_process_compose_files(
   dir: &Path,
   processes: Option<&std::collections::HashMap<String, String>>,
   app: &str,
   resolver: &str,
+  router: &WebRouter,
+  settings: &HashMap<String, ProcessConfig>,
 ) -> Result<()> {
   // Clean up orphaned compose files before writing new ones
-  cleanup_orphaned_compose_files(dir, processes).await?;
+  cleanup_orphaned_compose_files(dir, processes, app).await?;
 
   if let Some(procs) = processes {
     // Generate a compose file for each process
     for (process_name, command) in procs {
-      let compose_content = generate_process_compose(process_name, Some(command), app, resolver);
+      let compose_content =
+        render_process_compose(process_name, Some(command), app, resolver, router, settings);
       let compose_path = dir.join(format!("compose.{}.yml", process_name));
       fs::write(&compose_path, compose_content).await?;
       debug(&format!(
@@ -455,7 +1602,7 @@ pub async fn write_process_compose_files(
     }
   } else {
     // No Procfile, create default web process (will use default Dockerfile CMD)
-    let compose_content = generate_process_compose("web", None, app, resolver);
+    let compose_content = render_process_compose("web", None, app, resolver, router, settings);
     let compose_path = dir.join("compose.web.yml");
     fs::write(&compose_path, compose_content).await?;
     debug(&format!(
@@ -466,12 +1613,29 @@ pub async fn write_process_compose_files(
   Ok(())
 }
 
+/// Content of `compose.<process>.yml` as written by `write_process_compose_files`.
+pub fn render_process_compose(
+  process_name: &str,
+  command: Option<&String>,
+  app: &str,
+  resolver: &str,
+  router: &WebRouter,
+  settings: &HashMap<String, ProcessConfig>,
+) -> String {
+  let mut content = generate_process_compose(process_name, command, app, resolver, router);
+  if let Some(settings) = settings.get(process_name) {
+    content.push_str(&process_devices_yaml(settings));
+  }
+  content
+}
+
 /// Generate the YAML content for a process-specific compose file
 fn generate_process_compose(
   process_name: &str,
   command: Option<&String>,
   app: &str,
   resolver: &str,
+  router: &WebRouter,
 ) -> String {
   let mut service_def = format!(
     r#"
@@ -491,153 +1655,740 @@ services:
     container_name: {app}
     labels:
       traefik.enable: true
-      traefik.http.routers.{app}.rule: Host(`${{DOMAIN}}`)
+      traefik.http.routers.{app}.rule: {rule}
       traefik.http.routers.{app}.entrypoints: websecure
       traefik.http.routers.{app}.tls.certresolver: {resolver}
       traefik.http.services.{app}.loadbalancer.server.port: ${{SERVICE_PORT}}"#,
       app = app,
+      rule = router.rule,
       resolver = resolver
     ));
+    if router.redirect_www {
+      // `$$` keeps compose from interpolating the regex group
+      service_def.push_str(&format!(
+        r#"
+      traefik.http.middlewares.{app}-www.redirectregex.regex: ^https?://www\.(.+)
+      traefik.http.middlewares.{app}-www.redirectregex.replacement: https://$${{1}}
+      traefik.http.middlewares.{app}-www.redirectregex.permanent: true
+      traefik.http.routers.{app}.middlewares: {app}-www"#,
+        app = app
+      ));
+    }
+    if let Some(healthcheck) = &router.healthcheck {
+      service_def.push_str(&healthcheck.yaml());
+    }
+  }
+
+  // Add command override if provided
+  if let Some(cmd) = command {
+    service_def.push_str(&format!(
+      r#"
+    command: [{}]"#,
+      command_yaml(cmd)
+    ));
+  }
+
+  // Close the YAML with a newline
+  service_def.push('\n');
+
+  service_def
+}
+
+/// Service keys passing a process its GPUs (as a compose device reservation, which needs the
+/// NVIDIA container toolkit) and host devices; appended to its overlay.
+fn process_devices_yaml(settings: &ProcessConfig) -> String {
+  let mut yaml = String::new();
+  if let Some(gpus) = &settings.gpus {
+    let selector = match gpus {
+      GpuRequest::Count(count) => format!("count: {}", count),
+      GpuRequest::Named(name) if name == "all" => "count: all".to_string(),
+      GpuRequest::Named(id) => format!("device_ids: [{:?}]", id),
+      GpuRequest::Ids(ids) => format!(
+        "device_ids: [{}]",
+        ids
+          .iter()
+          .map(|id| format!("{:?}", id.to_string()))
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+    };
+    yaml.push_str(&format!(
+      r#"    deploy:
+      resources:
+        reservations:
+          devices:
+            - driver: nvidia
+              {selector}
+              capabilities: [gpu]
+"#
+    ));
+  }
+  if !settings.devices.is_empty() {
+    yaml.push_str("    devices:\n");
+    for device in &settings.devices {
+      yaml.push_str(&format!("      - {:?}\n", device));
+    }
+  }
+  yaml
+}
+
+/// Render a Procfile command as the items of a compose `command: [...]` list.
+pub(crate) fn command_yaml(cmd: &str) -> String {
+  // Parse command string into individual arguments
+  let args = match shell_words::split(cmd) {
+    Ok(parts) => parts,
+    Err(_) => vec![cmd.to_string()],
+  };
+  args
+    .iter()
+    .map(|arg| format!("\"{}\"", arg))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// Subset of `docker inspect` output used for status reporting.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerInfo {
+  pub name: String,
+  pub state: ContainerState,
+  pub config: ContainerConfig,
+  #[serde(default)]
+  pub mounts: Vec<ContainerMount>,
+  #[serde(default)]
+  pub network_settings: Option<NetworkSettings>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerState {
+  pub status: String,
+  pub started_at: String,
+  #[serde(default)]
+  pub health: Option<ContainerHealth>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerHealth {
+  pub status: String,
+  #[serde(default)]
+  pub log: Option<Vec<HealthProbe>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct HealthProbe {
+  pub exit_code: i64,
+  pub output: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerConfig {
+  #[serde(default)]
+  pub labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerMount {
+  #[serde(default)]
+  pub source: String,
+  pub destination: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NetworkSettings {
+  #[serde(default)]
+  pub networks: Option<HashMap<String, NetworkEndpoint>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NetworkEndpoint {
+  #[serde(rename = "IPAddress", default)]
+  pub ip_address: String,
+  #[serde(rename = "Aliases", default)]
+  pub aliases: Option<Vec<String>>,
+  #[serde(rename = "DNSNames", default)]
+  pub dns_names: Option<Vec<String>>,
+}
+
+impl ContainerInfo {
+  /// Compose service this container belongs to, from its compose labels.
+  pub fn compose_service(&self) -> Option<&str> {
+    self
+      .config
+      .labels
+      .as_ref()?
+      .get("com.docker.compose.service")
+      .map(String::as_str)
+  }
+
+  /// Output of the most recent healthcheck probe, if the container has a healthcheck.
+  pub fn last_probe(&self) -> Option<&HealthProbe> {
+    self.state.health.as_ref()?.log.as_ref()?.last()
+  }
+
+  /// IP of this container on `network` if `host` resolves to it there, by container name,
+  /// service alias or DNS name.
+  pub fn address_on(&self, network: &str, host: &str) -> Option<&str> {
+    let endpoint = self
+      .network_settings
+      .as_ref()?
+      .networks
+      .as_ref()?
+      .get(network)?;
+    let named = |names: &Option<Vec<String>>| names.iter().flatten().any(|n| n == host);
+    let answers = self.name.trim_start_matches('/') == host
+      || named(&endpoint.aliases)
+      || named(&endpoint.dns_names);
+    (answers && !endpoint.ip_address.is_empty()).then_some(endpoint.ip_address.as_str())
+  }
+}
+
+/// Parse `docker inspect` JSON.
+pub fn parse_container_inspect(json: &str) -> Result<Vec<ContainerInfo>> {
+  from_json(json).context("Failed to parse docker inspect output")
+}
+
+/// Inspect every container (running or not) of a compose project.
+pub async fn inspect_compose_project(project: &str) -> Result<Vec<ContainerInfo>> {
+  let output = Command::new("docker")
+    .args([
+      "ps",
+      "-aq",
+      "--filter",
+      &format!("label=com.docker.compose.project={}", project),
+    ])
+    .stdin(Stdio::null())
+    .stderr(Stdio::inherit())
+    .output()
+    .await?;
+  if !output.status.success() {
+    anyhow::bail!("docker ps failed with status: {}", output.status);
+  }
+  let ids: Vec<String> = String::from_utf8_lossy(&output.stdout)
+    .split_whitespace()
+    .map(str::to_string)
+    .collect();
+  inspect_containers(&ids).await
+}
+
+/// Inspect every container attached to a docker network.
+pub async fn inspect_network_containers(network: &str) -> Result<Vec<ContainerInfo>> {
+  let output = Command::new("docker")
+    .args([
+      "network",
+      "inspect",
+      "-f",
+      "{{range $id, $c := .Containers}}{{$id}} {{end}}",
+      network,
+    ])
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .await?;
+  if !output.status.success() {
+    anyhow::bail!(
+      "docker network inspect failed with status: {}",
+      output.status
+    );
+  }
+  let ids: Vec<String> = String::from_utf8_lossy(&output.stdout)
+    .split_whitespace()
+    .map(str::to_string)
+    .collect();
+  inspect_containers(&ids).await
+}
+
+/// IP that `host` resolves to on `network`, looked up the way docker's embedded DNS would.
+pub async fn resolve_in_network(network: &str, host: &str) -> Option<String> {
+  let containers = inspect_network_containers(network).await.ok()?;
+  containers
+    .iter()
+    .filter(|c| c.state.status == "running")
+    .find_map(|c| c.address_on(network, host))
+    .map(str::to_string)
+}
+
+/// Name of the container `host` refers to on `network`, running or not.
+pub async fn container_in_network(network: &str, host: &str) -> Option<String> {
+  let containers = inspect_network_containers(network).await.ok()?;
+  containers
+    .iter()
+    .find(|c| c.address_on(network, host).is_some())
+    .map(|c| c.name.trim_start_matches('/').to_string())
+}
+
+/// Last `lines` lines of a container's output (stdout and stderr), empty if there are none.
+pub async fn container_logs_tail(container: &str, lines: usize) -> String {
+  let output = Command::new("docker")
+    .args(["logs", "--tail", &lines.to_string(), container])
+    .stdin(Stdio::null())
+    .output()
+    .await;
+  match output {
+    Ok(output) => {
+      String::from_utf8_lossy(&output.stdout).into_owned()
+        + &String::from_utf8_lossy(&output.stderr)
+    }
+    Err(_) => String::new(),
+  }
+}
+
+async fn inspect_containers(ids: &[String]) -> Result<Vec<ContainerInfo>> {
+  if ids.is_empty() {
+    return Ok(Vec::new());
+  }
+
+  let output = Command::new("docker")
+    .arg("inspect")
+    .args(ids)
+    .stdin(Stdio::null())
+    .stderr(Stdio::inherit())
+    .output()
+    .await?;
+  if !output.status.success() {
+    anyhow::bail!("docker inspect failed with status: {}", output.status);
+  }
+  parse_container_inspect(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Size in KiB of a path inside a running container. Data directories are usually owned by
+/// the container user, so they're measured from inside rather than from the host.
+pub async fn container_disk_usage_kib(container: &str, path: &str) -> Option<u64> {
+  let output = Command::new("docker")
+    .args(["exec", container, "du", "-sk", path])
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .await
+    .ok()?;
+  if !output.status.success() {
+    return None;
+  }
+  String::from_utf8_lossy(&output.stdout)
+    .split_whitespace()
+    .next()?
+    .parse()
+    .ok()
+}
+
+/// Service names declared in a compose file.
+pub fn compose_service_names(content: &str) -> Result<Vec<String>> {
+  let doc: serde_yaml::Value = serde_yaml::from_str(content)?;
+  Ok(
+    doc
+      .get("services")
+      .and_then(|s| s.as_mapping())
+      .map(|m| {
+        m.keys()
+          .filter_map(|k| k.as_str().map(str::to_string))
+          .collect()
+      })
+      .unwrap_or_default(),
+  )
+}
+
+/// `key` of every service in a compose file that sets it, e.g. each service's `image`.
+pub fn compose_service_values(
+  content: &str,
+  key: &str,
+) -> Result<Vec<(String, serde_yaml::Value)>> {
+  let doc: serde_yaml::Value = serde_yaml::from_str(content)?;
+  Ok(
+    doc
+      .get("services")
+      .and_then(|s| s.as_mapping())
+      .map(|m| {
+        m.iter()
+          .filter_map(|(name, service)| {
+            Some((name.as_str()?.to_string(), service.get(key)?.clone()))
+          })
+          .collect()
+      })
+      .unwrap_or_default(),
+  )
+}
+
+/// Compose project, files and service a process or accessory of an app runs in.
+#[derive(Debug, PartialEq)]
+pub struct ComposeTarget {
+  pub project: String,
+  /// Compose files, relative to the app dir
+  pub files: Vec<String>,
+  pub service: String,
+}
+
+impl ComposeTarget {
+  /// `docker compose -p <project> -f ...` arguments addressing the target's project.
+  pub fn compose_args(&self) -> Vec<String> {
+    let mut args = vec![
+      "compose".to_string(),
+      "-p".to_string(),
+      self.project.clone(),
+    ];
+    for file in &self.files {
+      args.push("-f".to_string());
+      args.push(file.clone());
+    }
+    args
+  }
+}
+
+/// Resolve `name` to where it runs: a process (`web`, `worker`) in the `<app>` project, or an
+/// accessory in `<app>-acc`, by its accessory name (`postgres`) or compose service (`pg`).
+pub fn compose_target(app: &str, name: &str) -> Result<ComposeTarget> {
+  let dir = app_dir(app);
+  let units_dir = systemd_dir();
+  let processes = discover_processes(&units_dir, app)?;
+  if processes.iter().any(|p| p == name) {
+    let mut files = vec!["compose.yml".to_string(), format!("compose.{}.yml", name)];
+    if dir.join(override_file(name)).exists() {
+      files.push(override_file(name));
+    }
+    return Ok(ComposeTarget {
+      project: app.to_string(),
+      files,
+      service: name.to_string(),
+    });
   }
 
-  // Add command override if provided
-  if let Some(cmd) = command {
-    // Parse command string into individual arguments
-    let args = match shell_words::split(cmd) {
-      Ok(parts) => parts,
-      Err(_) => vec![cmd.to_string()],
+  let accessories = discover_accessories(&units_dir, &dir, app, &processes)?;
+  for accessory in &accessories {
+    let file = format!("compose.{}.yml", accessory);
+    let services = std::fs::read_to_string(dir.join(&file))
+      .map_err(anyhow::Error::from)
+      .and_then(|content| compose_service_names(&content))
+      .with_context(|| format!("failed to read {}", file))?;
+    let service = if accessory == name {
+      services.into_iter().next()
+    } else {
+      services.into_iter().find(|s| s == name)
     };
-    let args_yaml = args
-      .iter()
-      .map(|arg| format!("\"{}\"", arg))
-      .collect::<Vec<_>>()
-      .join(",");
-
-    service_def.push_str(&format!(
-      r#"
-    command: [{}]"#,
-      args_yaml
-    ));
+    if let Some(service) = service {
+      return Ok(ComposeTarget {
+        project: format!("{}-acc", app),
+        files: vec!["compose.yml".to_string(), file],
+        service,
+      });
+    }
   }
 
-  // Close the YAML with a newline
-  service_def.push('\n');
+  anyhow::bail!(
+    "{} has no process or accessory named {} (processes: {}; accessories: {})",
+    app,
+    name,
+    list_or_none(&processes),
+    list_or_none(&accessories)
+  )
+}
 
-  service_def
+fn list_or_none(items: &[String]) -> String {
+  if items.is_empty() {
+    "none".to_string()
+  } else {
+    items.join(", ")
+  }
 }
 
 /// Wait for postgres to be ready by executing pg_isready inside a container.
 /// Uses docker compose exec to probe the postgres service.
-pub async fn wait_for_postgres_ready(app: &str) -> Result<()> {
+pub async fn wait_for_postgres_ready(app: &str, timeout: Duration) -> Result<()> {
+  wait_for_accessory_ready(
+    app,
+    "postgres",
+    "pg",
+    "pg_isready -h 127.0.0.1 -p ${POSTGRES_PORT:-5432}",
+    timeout,
+  )
+  .await
+}
+
+/// Wait for redis to be ready by executing redis-cli ping inside a container.
+/// Uses docker compose exec to probe the redis service.
+pub async fn wait_for_redis_ready(app: &str, timeout: Duration) -> Result<()> {
+  wait_for_accessory_ready(
+    app,
+    "redis",
+    "redis",
+    "redis-cli -h 127.0.0.1 ping | grep -q PONG",
+    timeout,
+  )
+  .await
+}
+
+/// Retry `check` inside the accessory's `service` container once a second until it succeeds
+/// or `timeout` runs out.
+async fn wait_for_accessory_ready(
+  app: &str,
+  accessory: &str,
+  service: &str,
+  check: &str,
+  timeout: Duration,
+) -> Result<()> {
   let dir = app_dir(app);
 
   if !dir.exists() {
     anyhow::bail!("App directory not found: {}", dir.display());
   }
 
-  let mut compose_files = vec!["-f".to_string(), "compose.yml".to_string()];
-  compose_files.push("-f".to_string());
-  compose_files.push("compose.postgres.yml".to_string());
-
   let project_name = format!("{}-acc", app);
+  let secs = timeout.as_secs().max(1);
   debug(&format!(
-    "waiting for postgres to be ready (project: {}, timeout: 60s)",
-    project_name
+    "waiting for {} to be ready (project: {}, timeout: {}s)",
+    accessory, project_name, secs
   ));
 
-  // Build the probe command: pg_isready with retry loop
-  let probe_script = "for i in $(seq 1 60); do pg_isready -h 127.0.0.1 -p ${POSTGRES_PORT:-5432} && exit 0; sleep 1; done; exit 1";
-
-  let mut args = vec!["compose".to_string(), "-p".to_string(), project_name];
-  args.extend(compose_files);
-  args.extend(vec![
-    "exec".to_string(),
-    "-T".to_string(),
-    "pg".to_string(),
-    "sh".to_string(),
-    "-lc".to_string(),
-    probe_script.to_string(),
-  ]);
-
+  let probe_script = format!(
+    "for i in $(seq 1 {}); do {} && exit 0; sleep 1; done; exit 1",
+    secs, check
+  );
+  let compose_file = format!("compose.{}.yml", accessory);
   let status = Command::new("docker")
-    .args(&args)
+    .args([
+      "compose",
+      "-p",
+      &project_name,
+      "-f",
+      "compose.yml",
+      "-f",
+      &compose_file,
+      "exec",
+      "-T",
+      service,
+      "sh",
+      "-lc",
+      &probe_script,
+    ])
     .current_dir(&dir)
     .stdin(Stdio::null())
-    .stdout(Stdio::inherit())
+    .stdout(progress_stdout())
     .stderr(Stdio::inherit())
     .status()
     .await?;
 
   if !status.success() {
-    anyhow::bail!(
-      "postgres readiness probe failed after 60 seconds (status: {})",
-      status
+    return Err(
+      Error::Health(anyhow::anyhow!(
+        "{} readiness probe failed after {} seconds (status: {})",
+        accessory,
+        secs,
+        status
+      ))
+      .into(),
     );
   }
 
-  debug("postgres is ready");
+  debug(&format!("{} is ready", accessory));
 
   Ok(())
 }
 
-/// Wait for redis to be ready by executing redis-cli ping inside a container.
-/// Uses docker compose exec to probe the redis service.
-pub async fn wait_for_redis_ready(app: &str) -> Result<()> {
-  let dir = app_dir(app);
-
-  if !dir.exists() {
-    anyhow::bail!("App directory not found: {}", dir.display());
+/// Wait until every service of an accessory without a built-in probe (e.g. one a plugin
+/// added) has a running container that passes its compose healthcheck, if it has one.
+pub async fn wait_for_accessory_healthy(
+  app: &str,
+  accessory: &str,
+  timeout: Duration,
+) -> Result<()> {
+  let overlay = app_dir(app).join(format!("compose.{}.yml", accessory));
+  let content = tokio::fs::read_to_string(&overlay)
+    .await
+    .with_context(|| format!("Failed to read {}", overlay.display()))?;
+  let services = compose_service_names(&content)?;
+  let project = format!("{}-acc", app);
+  let deadline = std::time::Instant::now() + timeout;
+  loop {
+    let containers = inspect_compose_project(&project).await?;
+    let pending: Vec<&String> = services
+      .iter()
+      .filter(|service| {
+        !containers.iter().any(|c| {
+          c.compose_service() == Some(service.as_str())
+            && c.state.status == "running"
+            && c
+              .state
+              .health
+              .as_ref()
+              .is_none_or(|h| h.status == "healthy")
+        })
+      })
+      .collect();
+    if pending.is_empty() {
+      debug(&format!("{} is ready", accessory));
+      return Ok(());
+    }
+    if std::time::Instant::now() >= deadline {
+      return Err(
+        Error::Health(anyhow::anyhow!(
+          "{} not ready after {} seconds (waiting for {})",
+          accessory,
+          timeout.as_secs(),
+          pending
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+        ))
+        .into(),
+      );
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
   }
+}
 
-  let mut compose_files = vec!["-f".to_string(), "compose.yml".to_string()];
-  compose_files.push("-f".to_string());
-  compose_files.push("compose.redis.yml".to_string());
-
-  let project_name = format!("{}-acc", app);
-  debug(&format!(
-    "waiting for redis to be ready (project: {}, timeout: 60s)",
-    project_name
-  ));
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
 
-  // Build the probe command: redis-cli ping with retry loop
-  let probe_script = "for i in $(seq 1 60); do redis-cli -h 127.0.0.1 ping | grep -q PONG && exit 0; sleep 1; done; exit 1";
+  #[test]
+  fn test_render_base_compose_attached_has_no_restart_policy() {
+    let compose = render_base_compose(
+      "img",
+      "latest",
+      "web",
+      &[],
+      &[],
+      SystemdMode::Attached,
+      None,
+    );
+    assert!(compose.contains("    restart: \"no\"\n"));
+  }
 
-  let mut args = vec!["compose".to_string(), "-p".to_string(), project_name];
-  args.extend(compose_files);
-  args.extend(vec![
-    "exec".to_string(),
-    "-T".to_string(),
-    "redis".to_string(),
-    "sh".to_string(),
-    "-lc".to_string(),
-    probe_script.to_string(),
-  ]);
+  #[test]
+  fn test_render_base_compose_logging() {
+    let logging: LoggingConfig =
+      serde_yaml::from_str("maxSize: 10m\nmaxFile: 3\noptions:\n  compress: \"true\"\n").unwrap();
+    let compose = render_base_compose(
+      "img",
+      "latest",
+      "web",
+      &[],
+      &[],
+      SystemdMode::Oneshot,
+      Some(&logging),
+    );
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&compose).unwrap();
+    let section = &parsed["services"]["base"]["logging"];
+    assert_eq!(section["driver"].as_str(), Some("json-file"));
+    assert_eq!(section["options"]["max-size"].as_str(), Some("10m"));
+    assert_eq!(section["options"]["max-file"].as_str(), Some("3"));
+    assert_eq!(section["options"]["compress"].as_str(), Some("true"));
+
+    let loki: LoggingConfig =
+      serde_yaml::from_str("loki: https://loki.example.com/loki/api/v1/push\n").unwrap();
+    let compose = render_base_compose(
+      "img",
+      "latest",
+      "web",
+      &[],
+      &[],
+      SystemdMode::Oneshot,
+      Some(&loki),
+    );
+    assert!(compose.contains(
+      "      driver: loki\n      options:\n        loki-url: \"https://loki.example.com/loki/api/v1/push\"\n"
+    ));
+  }
 
-  let status = Command::new("docker")
-    .args(&args)
-    .current_dir(&dir)
-    .stdin(Stdio::null())
-    .stdout(Stdio::inherit())
-    .stderr(Stdio::inherit())
-    .status()
-    .await?;
+  #[tokio::test]
+  async fn test_pin_base_image_survives_rewrite() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    let image = "localhost:5000/testapp";
+    write_base_compose_file(dir, image, "web", &[], &[], SystemdMode::Oneshot, None).await?;
+    assert_eq!(pinned_tag(dir, image).as_deref(), Some("latest"));
+
+    pin_base_image(dir, image, "abc1234").await?;
+    let content = fs::read_to_string(dir.join("compose.yml")).await?;
+    assert!(content.contains("    image: localhost:5000/testapp:abc1234\n"));
+    assert!(content.starts_with("\nservices:\n"));
+
+    // Regenerating on the next deploy keeps the release until it is pinned again
+    write_base_compose_file(dir, image, "web", &[], &[], SystemdMode::Oneshot, None).await?;
+    assert_eq!(pinned_tag(dir, image).as_deref(), Some("abc1234"));
+    // ...unless hl.yml now names another image
+    assert_eq!(pinned_tag(dir, "ghcr.io/me/testapp"), None);
 
-  if !status.success() {
-    anyhow::bail!(
-      "redis readiness probe failed after 60 seconds (status: {})",
-      status
+    assert_eq!(
+      split_image_ref("localhost:5000/app"),
+      ("localhost:5000/app", None)
     );
+    Ok(())
   }
 
-  debug("redis is ready");
+  #[test]
+  fn test_compose_file_args() {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::write(temp_dir.path().join(override_file("web")), "services: {}\n").unwrap();
+    let args = compose_file_args(
+      temp_dir.path(),
+      &["web".to_string(), "worker".to_string()],
+      &["postgres".to_string()],
+    );
+    assert_eq!(
+      args.join(" "),
+      "compose -f compose.yml -f compose.web.yml -f compose.worker.yml -f compose.postgres.yml \
+       -f compose.override.web.yml"
+    );
+  }
 
-  Ok(())
-}
+  #[test]
+  fn test_changed_processes() {
+    let current: BTreeMap<String, String> = [("web", "aaa"), ("worker", "bbb"), ("clock", "ccc")]
+      .into_iter()
+      .map(|(p, f)| (p.to_string(), f.to_string()))
+      .collect();
+    let applied = AppliedProcesses {
+      processes: [("web", "old"), ("worker", "bbb")]
+        .into_iter()
+        .map(|(p, f)| (p.to_string(), f.to_string()))
+        .collect(),
+    };
+    // web changed and clock is new; worker runs what it was started with
+    assert_eq!(changed_processes(&current, &applied), vec!["clock", "web"]);
+    let yaml = serde_yaml::to_string(&applied).unwrap();
+    assert_eq!(
+      serde_yaml::from_str::<AppliedProcesses>(&yaml).unwrap(),
+      applied
+    );
+  }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
-  use tempfile::TempDir;
+  #[tokio::test]
+  async fn test_pin_process_images() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    let image = "localhost:5000/testapp";
+    write_base_compose_file(dir, image, "web", &[], &[], SystemdMode::Oneshot, None).await?;
+    let overlay = generate_process_compose(
+      "worker",
+      Some(&"bin/worker".to_string()),
+      "testapp",
+      "myresolver",
+      &WebRouter::default(),
+    );
+    let worker = with_process_image(&overlay, "localhost:5000/testapp-worker:latest");
+    assert!(
+      worker.contains("      service: base\n    image: localhost:5000/testapp-worker:latest\n")
+    );
+    fs::write(dir.join("compose.worker.yml"), &worker).await?;
+    fs::write(dir.join("compose.web.yml"), &overlay).await?;
+
+    pin_base_image(dir, image, "abc1234").await?;
+    let worker = fs::read_to_string(dir.join("compose.worker.yml")).await?;
+    assert!(worker.contains("    image: localhost:5000/testapp-worker:abc1234\n"));
+    assert!(worker.contains("bin/worker"));
+    // Overlays running the base image are left alone
+    assert_eq!(
+      fs::read_to_string(dir.join("compose.web.yml")).await?,
+      overlay
+    );
+    Ok(())
+  }
 
   #[tokio::test]
   async fn test_write_base_compose_file() -> Result<()> {
@@ -645,7 +2396,16 @@ mod tests {
     let dir_path = temp_dir.path();
     let image = "registry.example.com/testapp";
     let network = "traefik_proxy";
-    write_base_compose_file(dir_path, image, network, &[]).await?;
+    write_base_compose_file(
+      dir_path,
+      image,
+      network,
+      &[],
+      &[],
+      SystemdMode::Oneshot,
+      None,
+    )
+    .await?;
     let compose_path = dir_path.join("compose.yml");
     assert!(compose_path.exists(), "compose.yml should be created");
     let content = fs::read_to_string(&compose_path).await?;
@@ -676,7 +2436,16 @@ networks:
     let image = "registry.example.com/testapp";
     let network = "traefik_proxy";
     let volumes = vec!["./data:/app/packages/server/data".to_string()];
-    write_base_compose_file(dir_path, image, network, &volumes).await?;
+    write_base_compose_file(
+      dir_path,
+      image,
+      network,
+      &volumes,
+      &[],
+      SystemdMode::Oneshot,
+      None,
+    )
+    .await?;
     let compose_path = dir_path.join("compose.yml");
     assert!(compose_path.exists(), "compose.yml should be created");
     let content = fs::read_to_string(&compose_path).await?;
@@ -702,6 +2471,243 @@ networks:
     Ok(())
   }
 
+  #[tokio::test]
+  async fn test_write_base_compose_file_with_named_volumes() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir_path = temp_dir.path();
+    let volumes = vec!["testapp-data:/rails/storage".to_string()];
+    let named_volumes = vec!["testapp-data".to_string()];
+    write_base_compose_file(
+      dir_path,
+      "registry.example.com/testapp",
+      "traefik_proxy",
+      &volumes,
+      &named_volumes,
+      SystemdMode::Oneshot,
+      None,
+    )
+    .await?;
+    let content = fs::read_to_string(dir_path.join("compose.yml")).await?;
+    let expected = r#"
+services:
+  base:
+    image: registry.example.com/testapp:latest
+    restart: unless-stopped
+    env_file: [.env]
+    networks: [traefik_proxy]
+    profiles: ["_template"]
+    volumes:
+      - testapp-data:/rails/storage
+networks:
+  traefik_proxy:
+    external: true
+    name: traefik_proxy
+volumes:
+  testapp-data:
+    external: true
+"#;
+    assert_eq!(content, expected);
+    Ok(())
+  }
+
+  #[test]
+  fn test_parse_container_inspect() {
+    let json = r#"[
+  {
+    "Id": "abc",
+    "Name": "/testapp_pg",
+    "State": {
+      "Status": "running",
+      "StartedAt": "2024-05-01T10:00:00.123456789Z",
+      "Health": {
+        "Status": "healthy",
+        "FailingStreak": 0,
+        "Log": [
+          {"Start": "x", "End": "y", "ExitCode": 1, "Output": "no response\n"},
+          {"Start": "x", "End": "y", "ExitCode": 0, "Output": "accepting connections\n"}
+        ]
+      }
+    },
+    "Config": {"Labels": {"com.docker.compose.service": "pg"}},
+    "Mounts": [{"Type": "bind", "Source": "/srv/testapp/pgdata", "Destination": "/var/lib/postgresql/data"}],
+    "NetworkSettings": {"Networks": {
+      "testapp_default": {"IPAddress": "172.18.0.2", "Aliases": ["testapp_pg", "pg"], "DNSNames": null},
+      "traefik_proxy": {"IPAddress": "172.20.0.5", "Aliases": null, "DNSNames": ["testapp_pg", "pg", "abc"]}
+    }}
+  },
+  {
+    "Name": "/testapp_redis",
+    "State": {"Status": "exited", "StartedAt": "0001-01-01T00:00:00Z"},
+    "Config": {"Labels": null}
+  }
+]"#;
+    let containers = parse_container_inspect(json).unwrap();
+    assert_eq!(containers.len(), 2);
+    assert_eq!(containers[0].compose_service(), Some("pg"));
+    let probe = containers[0].last_probe().unwrap();
+    assert_eq!(probe.exit_code, 0);
+    assert_eq!(probe.output, "accepting connections\n");
+    assert_eq!(
+      containers[0].mounts[0].destination,
+      "/var/lib/postgresql/data"
+    );
+    assert!(containers[1].state.health.is_none());
+    assert!(containers[1].compose_service().is_none());
+
+    assert_eq!(
+      containers[0].address_on("testapp_default", "pg"),
+      Some("172.18.0.2")
+    );
+    assert_eq!(
+      containers[0].address_on("traefik_proxy", "testapp_pg"),
+      Some("172.20.0.5")
+    );
+    assert_eq!(containers[0].address_on("traefik_proxy", "redis"), None);
+    assert_eq!(
+      containers[1].address_on("testapp_default", "testapp_redis"),
+      None
+    );
+  }
+
+  #[test]
+  fn test_compose_target_args() {
+    let target = ComposeTarget {
+      project: "myapp-acc".to_string(),
+      files: vec![
+        "compose.yml".to_string(),
+        "compose.postgres.yml".to_string(),
+      ],
+      service: "pg".to_string(),
+    };
+    assert_eq!(
+      target.compose_args(),
+      vec![
+        "compose",
+        "-p",
+        "myapp-acc",
+        "-f",
+        "compose.yml",
+        "-f",
+        "compose.postgres.yml"
+      ]
+    );
+  }
+
+  #[test]
+  fn test_compose_service_names() {
+    let names = compose_service_names(
+      "services:\n  pg:\n    image: postgres:17\nnetworks:\n  traefik_proxy: {}\n",
+    )
+    .unwrap();
+    assert_eq!(names, vec!["pg"]);
+
+    let images = compose_service_values(
+      "services:\n  pg:\n    image: postgres:17\n  tools:\n    build: .\n",
+      "image",
+    )
+    .unwrap();
+    assert_eq!(images, vec![("pg".to_string(), "postgres:17".into())]);
+  }
+
+  #[test]
+  fn test_build_progress() {
+    let mut progress = BuildProgress::default();
+    let fed = progress.feed(
+      r#"{"vertexes":[{"digest":"sha256:a","name":"[internal] load build definition from Dockerfile","started":"2024-05-01T10:00:00Z","completed":"2024-05-01T10:00:01Z"}]}"#,
+    );
+    assert_eq!(
+      fed.log,
+      vec![
+        "#1 [internal] load build definition from Dockerfile",
+        "#1 DONE 0.0s"
+      ]
+    );
+    assert_eq!(fed.show, vec!["preparing build..."]);
+
+    let fed = progress.feed(
+      r#"{"vertexes":[{"digest":"sha256:b","name":"[build 2/3] COPY Gemfile ./","started":"2024-05-01T10:00:01Z","completed":"2024-05-01T10:00:01Z","cached":true}]}"#,
+    );
+    assert_eq!(
+      fed.show,
+      vec![
+        "running build steps...",
+        "[build 2/3] COPY Gemfile ./ (cached)"
+      ]
+    );
+
+    // Output arrives base64 encoded and split anywhere: "Fetching gems\nInstalling rack\n"
+    progress.feed(
+      r#"{"vertexes":[{"digest":"sha256:c","name":"[build 3/3] RUN bundle install","started":"2024-05-01T10:00:02Z"}]}"#,
+    );
+    let fed = progress
+      .feed(r#"{"logs":[{"vertex":"sha256:c","stream":1,"data":"RmV0Y2hpbmcgZ2VtcwpJbnN0YWxs"}]}"#);
+    assert_eq!(fed.log, vec!["#3 Fetching gems"]);
+    assert!(fed.show.is_empty());
+    let fed = progress.feed(r#"{"logs":[{"vertex":"sha256:c","stream":1,"data":"aW5nIHJhY2sK"}]}"#);
+    assert_eq!(fed.log, vec!["#3 Installing rack"]);
+    assert!(progress.failure().is_none());
+
+    let fed = progress.feed(
+      r#"{"vertexes":[{"digest":"sha256:c","name":"[build 3/3] RUN bundle install","started":"2024-05-01T10:00:02Z","completed":"2024-05-01T10:00:09Z","error":"process \"/bin/sh -c bundle install\" did not complete successfully: exit code: 5"}]}"#,
+    );
+    assert_eq!(
+      fed.log,
+      vec!["#3 ERROR: process \"/bin/sh -c bundle install\" did not complete successfully: exit code: 5"]
+    );
+    let failure = progress.failure().unwrap();
+    assert_eq!(failure.step, "[build 3/3] RUN bundle install");
+    assert!(failure.error.ends_with("exit code: 5"));
+    assert_eq!(failure.excerpt, vec!["Fetching gems", "Installing rack"]);
+    assert_eq!(
+      progress.summary().as_deref(),
+      Some("1 step(s), 1 cached (100%)")
+    );
+
+    // buildx's own messages aren't JSON
+    let fed = progress.feed("ERROR: failed to solve: process did not complete");
+    assert_eq!(fed.show, fed.log);
+
+    assert_eq!(BuildPhase::of_vertex("[3/7] RUN make"), BuildPhase::Steps);
+    assert_eq!(
+      BuildPhase::of_vertex("exporting to image"),
+      BuildPhase::Push
+    );
+    assert_eq!(
+      BuildPhase::of_vertex("[internal] load metadata for docker.io/library/ruby:3.3"),
+      BuildPhase::Prepare
+    );
+    assert_eq!(decode_base64("aGk="), b"hi");
+  }
+
+  #[test]
+  fn test_is_build_summary_line() {
+    assert!(is_build_summary_line("#5 [build 3/7] RUN bundle install"));
+    assert!(is_build_summary_line("#12 pushing layers"));
+    assert!(is_build_summary_line("#8 ERROR: process did not complete"));
+    assert!(is_build_summary_line("ERROR: failed to solve"));
+    assert!(!is_build_summary_line("#5 0.532 Fetching gem metadata"));
+    assert!(!is_build_summary_line("#5 DONE 12.3s"));
+  }
+
+  /// Minimal config for tests; defaults are applied exactly as when loading hl.yml.
+  fn test_config() -> HLConfig {
+    serde_yaml::from_str(
+      r#"
+app: testapp
+image: registry.example.com/testapp
+domain: testapp.example.com
+servicePort: 3000
+health:
+  url: http://testapp:3000/healthz
+migrations:
+  command: ["bin/rails", "db:migrate"]
+  env:
+    RAILS_ENV: production
+"#,
+    )
+    .unwrap()
+  }
+
   #[test]
   fn test_build_migration_args() {
     use std::collections::HashMap;
@@ -714,6 +2720,8 @@ networks:
       app: "testapp".to_string(),
       image: "registry.example.com/testapp".to_string(),
       domain: "testapp.example.com".to_string(),
+      domains: vec![],
+      redirect_www: false,
       service_port: 3000,
       resolver: "myresolver".to_string(),
       network: "traefik_proxy".to_string(),
@@ -722,13 +2730,31 @@ networks:
         url: "http://testapp:3000/healthz".to_string(),
         interval: "2s".to_string(),
         timeout: "45s".to_string(),
+        gate: false,
+        container: false,
+        status: None,
+        body: None,
+        body_regex: None,
       },
       migrations: crate::config::MigrationsConfig {
         command: vec!["bin/rails".to_string(), "db:migrate".to_string()],
         env: env_vars,
+        ..Default::default()
       },
       secrets: vec![],
-      volumes: vec![],
+      secrets_provider: None,
+      volumes: Default::default(),
+      persistence: None,
+      build: Default::default(),
+      deploy: Default::default(),
+      accessories: HashMap::new(),
+      processes: HashMap::new(),
+      sidecars: HashMap::new(),
+      systemd: Default::default(),
+      notifications: Default::default(),
+      cron: vec![],
+      console: None,
+      logging: None,
     };
 
     let image_tag = "registry.example.com/testapp:abc1234";
@@ -743,6 +2769,80 @@ networks:
     );
   }
 
+  #[test]
+  fn test_build_run_args() {
+    let cfg = test_config();
+    let command = vec!["bin/rails".to_string(), "db:seed".to_string()];
+    let args = build_run_args(
+      &cfg,
+      "registry.example.com/testapp:latest",
+      ".env",
+      &command,
+      &["-it"],
+    );
+    assert_eq!(
+      args.join(" "),
+      "run --rm -it --env-file .env -e RAILS_ENV=production --network traefik_proxy registry.example.com/testapp:latest bin/rails db:seed"
+    );
+  }
+
+  #[test]
+  fn test_build_migration_args_mounts_persistence() {
+    let mut cfg = test_config();
+    cfg.persistence = serde_yaml::from_str("path: /rails/storage").unwrap();
+
+    let args = build_migration_args(&cfg, "registry.example.com/testapp:abc1234", ".env");
+    let mount = args.iter().position(|a| a == "-v").unwrap();
+    assert_eq!(args[mount + 1], "testapp-data:/rails/storage");
+  }
+
+  #[test]
+  fn test_build_migration_args_user_workdir_volumes() {
+    let mut cfg = test_config();
+    cfg.migrations = serde_yaml::from_str(
+      "command: [bin/migrate]\nuser: \"1000:1000\"\nworkdir: /app/db\nvolumes:\n  - ./config/db.yml:/app/config/db.yml:ro\n  - migrate-cache:/cache\n",
+    )
+    .unwrap();
+
+    let args = build_migration_args(&cfg, "registry.example.com/testapp:abc1234", ".env");
+    let result = args.join(" ");
+    assert!(result.contains("--user 1000:1000 --workdir /app/db --network"));
+    assert!(result.contains("testapp/config/db.yml:/app/config/db.yml:ro -v migrate-cache:/cache"));
+    assert!(result.ends_with("registry.example.com/testapp:abc1234 bin/migrate"));
+  }
+
+  #[test]
+  fn test_tag_for_with_version() {
+    let cfg = test_config();
+    let tags = tag_for(&cfg, "abc1234567890", None, Some("v1.4.2"));
+    assert_eq!(
+      tags.all(),
+      vec![
+        "registry.example.com/testapp:abc1234",
+        "registry.example.com/testapp:latest",
+        "registry.example.com/testapp:v1.4.2",
+      ]
+    );
+
+    let tags = tag_for(&cfg, "abc1234567890", Some("master"), None);
+    assert_eq!(
+      tags.all(),
+      vec![
+        "registry.example.com/testapp:abc1234",
+        "registry.example.com/testapp:master-abc1234",
+        "registry.example.com/testapp:latest",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_validate_version_tag() {
+    assert!(validate_version_tag("v1.4.2").is_ok());
+    assert!(validate_version_tag("v2.0.0-rc.1").is_ok());
+    assert!(validate_version_tag("v1/2").is_err());
+    assert!(validate_version_tag(".v1").is_err());
+  }
+
   #[tokio::test]
   async fn test_write_process_compose_files_with_procfile() -> Result<()> {
     use std::collections::HashMap;
@@ -760,7 +2860,15 @@ networks:
       "bundle exec sidekiq -C config/sidekiq.yml".to_string(),
     );
 
-    write_process_compose_files(dir_path, Some(&processes), "testapp", "myresolver").await?;
+    write_process_compose_files(
+      dir_path,
+      Some(&processes),
+      "testapp",
+      "myresolver",
+      &WebRouter::default(),
+      &HashMap::new(),
+    )
+    .await?;
 
     // Check web compose file
     let web_path = dir_path.join("compose.web.yml");
@@ -813,7 +2921,15 @@ services:
     let temp_dir = TempDir::new()?;
     let dir_path = temp_dir.path();
 
-    write_process_compose_files(dir_path, None, "testapp", "myresolver").await?;
+    write_process_compose_files(
+      dir_path,
+      None,
+      "testapp",
+      "myresolver",
+      &WebRouter::default(),
+      &HashMap::new(),
+    )
+    .await?;
 
     // Check default web compose file
     let web_path = dir_path.join("compose.web.yml");
@@ -842,6 +2958,73 @@ services:
     Ok(())
   }
 
+  #[test]
+  fn test_generate_web_compose_with_healthcheck() {
+    let mut cfg = test_config();
+    cfg.health.url = "http://testapp:3000/up?full=1".to_string();
+    assert!(WebRouter::for_config(&cfg).healthcheck.is_none());
+
+    cfg.health.container = true;
+    let router = WebRouter::for_config(&cfg);
+    let healthcheck = router.healthcheck.as_ref().unwrap();
+    assert_eq!(healthcheck.url, "http://localhost:3000/up?full=1");
+
+    let yaml = generate_process_compose("web", None, "testapp", "myresolver", &router);
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+    let section = &parsed["services"]["web"]["healthcheck"];
+    assert_eq!(section["test"][0].as_str(), Some("CMD-SHELL"));
+    assert!(section["test"][1]
+      .as_str()
+      .unwrap()
+      .starts_with("curl -fsS -m 3 -o /dev/null 'http://localhost:3000/up?full=1' || wget"));
+    assert_eq!(section["interval"].as_str(), Some("2000ms"));
+    assert_eq!(section["start_period"].as_str(), Some("45000ms"));
+
+    // `&` would background curl and `$` would be expanded by compose or the shell
+    cfg.health.url = "http://testapp:3000/up?a=1&b=$HOME".to_string();
+    let router = WebRouter::for_config(&cfg);
+    let yaml = generate_process_compose("web", None, "testapp", "myresolver", &router);
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(
+      parsed["services"]["web"]["healthcheck"]["test"][1].as_str(),
+      Some(
+        "curl -fsS -m 3 -o /dev/null 'http://localhost:3000/up?a=1&b=$$HOME' || \
+         wget -q -T 3 -O /dev/null 'http://localhost:3000/up?a=1&b=$$HOME'"
+      )
+    );
+
+    // Only the web process is probed
+    let worker = generate_process_compose("worker", None, "testapp", "myresolver", &router);
+    assert!(!worker.contains("healthcheck"));
+  }
+
+  #[test]
+  fn test_process_devices_yaml() {
+    let settings: HashMap<String, ProcessConfig> = serde_yaml::from_str(
+      "ml:\n  gpus: all\nworker:\n  gpus: [0, GPU-abc]\n  devices: [/dev/dri:/dev/dri]\n",
+    )
+    .unwrap();
+    let overlay = |name: &str| {
+      let yaml =
+        generate_process_compose(name, None, "testapp", "myresolver", &WebRouter::default())
+          + &process_devices_yaml(&settings[name]);
+      serde_yaml::from_str::<serde_yaml::Value>(&yaml).unwrap()["services"][name].clone()
+    };
+
+    let ml = overlay("ml");
+    let device = &ml["deploy"]["resources"]["reservations"]["devices"][0];
+    assert_eq!(device["driver"].as_str(), Some("nvidia"));
+    assert_eq!(device["count"].as_str(), Some("all"));
+    assert_eq!(device["capabilities"][0].as_str(), Some("gpu"));
+    assert!(ml["devices"].is_null());
+
+    let worker = overlay("worker");
+    let device = &worker["deploy"]["resources"]["reservations"]["devices"][0];
+    assert_eq!(device["device_ids"][0].as_str(), Some("0"));
+    assert_eq!(device["device_ids"][1].as_str(), Some("GPU-abc"));
+    assert_eq!(worker["devices"][0].as_str(), Some("/dev/dri:/dev/dri"));
+  }
+
   #[test]
   fn test_generate_process_compose_with_command() {
     let result = generate_process_compose(
@@ -849,6 +3032,7 @@ services:
       Some(&"bundle exec sidekiq".to_string()),
       "testapp",
       "myresolver",
+      &WebRouter::default(),
     );
     let expected = r#"
 services:
@@ -867,7 +3051,8 @@ services:
 
   #[test]
   fn test_generate_process_compose_without_command() {
-    let result = generate_process_compose("web", None, "testapp", "myresolver");
+    let result =
+      generate_process_compose("web", None, "testapp", "myresolver", &WebRouter::default());
     let expected = r#"
 services:
   web:
@@ -889,6 +3074,29 @@ services:
     );
   }
 
+  #[test]
+  fn test_generate_process_compose_multiple_domains() {
+    let mut cfg = test_config();
+    cfg.domains = vec![
+      "testapp.example.com".to_string(),
+      "www.testapp.example.com".to_string(),
+    ];
+    cfg.redirect_www = true;
+    let result = generate_process_compose(
+      "web",
+      None,
+      "testapp",
+      "myresolver",
+      &WebRouter::for_config(&cfg),
+    );
+    assert!(result.contains(
+      "traefik.http.routers.testapp.rule: Host(`${DOMAIN}`) || Host(`www.testapp.example.com`)\n"
+    ));
+    assert!(result
+      .contains("traefik.http.middlewares.testapp-www.redirectregex.replacement: https://$${1}\n"));
+    assert!(result.ends_with("traefik.http.routers.testapp.middlewares: testapp-www\n"));
+  }
+
   #[test]
   fn test_generate_process_compose_with_complex_command() {
     let result = generate_process_compose(
@@ -896,6 +3104,7 @@ services:
       Some(&"bundle exec rake db:migrate db:seed".to_string()),
       "testapp",
       "myresolver",
+      &WebRouter::default(),
     );
     let expected = r#"
 services:
@@ -951,7 +3160,15 @@ services:
     processes.insert("web".to_string(), "bundle exec rails server".to_string());
     processes.insert("worker".to_string(), "bundle exec sidekiq".to_string());
 
-    write_process_compose_files(dir_path, Some(&processes), "testapp", "myresolver").await?;
+    write_process_compose_files(
+      dir_path,
+      Some(&processes),
+      "testapp",
+      "myresolver",
+      &WebRouter::default(),
+      &HashMap::new(),
+    )
+    .await?;
 
     // Verify orphaned files are deleted
     for file_name in &orphaned_files {
@@ -997,6 +3214,36 @@ services:
     Ok(())
   }
 
+  #[tokio::test]
+  async fn test_cleanup_keeps_plugin_accessory_overlays() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir_path = temp_dir.path();
+    crate::state::save_state(
+      dir_path,
+      &crate::state::AppState {
+        processes: vec!["web".to_string()],
+        accessories: vec!["mongo".to_string()],
+        ..Default::default()
+      },
+    )?;
+    fs::write(dir_path.join("compose.mongo.yml"), "# plugin accessory").await?;
+    fs::write(dir_path.join("compose.worker.yml"), "# orphaned worker").await?;
+
+    write_process_compose_files(
+      dir_path,
+      None,
+      "testapp",
+      "myresolver",
+      &WebRouter::default(),
+      &HashMap::new(),
+    )
+    .await?;
+
+    assert!(dir_path.join("compose.mongo.yml").exists());
+    assert!(!dir_path.join("compose.worker.yml").exists());
+    Ok(())
+  }
+
   #[tokio::test]
   async fn test_cleanup_orphaned_compose_files_without_procfile() -> Result<()> {
     let temp_dir = TempDir::new()?;
@@ -1019,7 +3266,15 @@ services:
     );
 
     // Call with None (default web process only)
-    write_process_compose_files(dir_path, None, "testapp", "myresolver").await?;
+    write_process_compose_files(
+      dir_path,
+      None,
+      "testapp",
+      "myresolver",
+      &WebRouter::default(),
+      &HashMap::new(),
+    )
+    .await?;
 
     // Verify orphaned files are deleted
     assert!(
@@ -1059,12 +3314,22 @@ services:
 
     // Create orphaned file
     fs::write(dir_path.join("compose.cron.yml"), "# orphaned cron").await?;
+    // The user's override of a process is theirs, even for a process that is gone
+    fs::write(dir_path.join("compose.override.cron.yml"), "# user's").await?;
 
     let mut processes = HashMap::new();
     processes.insert("web".to_string(), "bundle exec rails server".to_string());
     processes.insert("worker".to_string(), "bundle exec sidekiq".to_string());
 
-    write_process_compose_files(dir_path, Some(&processes), "testapp", "myresolver").await?;
+    write_process_compose_files(
+      dir_path,
+      Some(&processes),
+      "testapp",
+      "myresolver",
+      &WebRouter::default(),
+      &HashMap::new(),
+    )
+    .await?;
 
     // Verify current files are updated (still exist)
     assert!(
@@ -1081,6 +3346,7 @@ services:
       !dir_path.join("compose.cron.yml").exists(),
       "compose.cron.yml should be deleted"
     );
+    assert!(dir_path.join("compose.override.cron.yml").exists());
 
     Ok(())
   }
@@ -1096,9 +3362,15 @@ services:
     processes.insert("web".to_string(), "bundle exec rails server".to_string());
 
     // Should handle missing directory gracefully (cleanup will skip, but write will fail)
-    let result =
-      write_process_compose_files(&nonexistent_dir, Some(&processes), "testapp", "myresolver")
-        .await;
+    let result = write_process_compose_files(
+      &nonexistent_dir,
+      Some(&processes),
+      "testapp",
+      "myresolver",
+      &WebRouter::default(),
+      &HashMap::new(),
+    )
+    .await;
 
     // The write operation should fail because directory doesn't exist
     assert!(
@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Build args BuildKit provides on its own; declaring them without a default is fine.
+const PREDEFINED_ARGS: &[&str] = &[
+  "HTTP_PROXY",
+  "HTTPS_PROXY",
+  "FTP_PROXY",
+  "NO_PROXY",
+  "ALL_PROXY",
+  "http_proxy",
+  "https_proxy",
+  "ftp_proxy",
+  "no_proxy",
+  "all_proxy",
+  "BUILDPLATFORM",
+  "BUILDOS",
+  "BUILDARCH",
+  "BUILDVARIANT",
+  "TARGETPLATFORM",
+  "TARGETOS",
+  "TARGETARCH",
+  "TARGETVARIANT",
+];
+
+/// Build inputs a Dockerfile refers to.
+#[derive(Debug, Default, PartialEq)]
+pub struct DockerfileRefs {
+  /// Declared ARG names, mapped to whether any declaration has a default value
+  pub args: BTreeMap<String, bool>,
+  /// Secret ids mounted with `RUN --mount=type=secret,id=<id>`
+  pub secrets: BTreeSet<String>,
+  /// Ports from `EXPOSE`, in order
+  pub exposed_ports: Vec<u16>,
+}
+
+/// Collect ARG declarations, secret mounts and exposed ports, following line continuations and skipping
+/// comments.
+pub fn parse_dockerfile(content: &str) -> DockerfileRefs {
+  let mut refs = DockerfileRefs::default();
+
+  for instruction in logical_lines(content) {
+    let mut words = instruction.split_whitespace();
+    let Some(keyword) = words.next() else {
+      continue;
+    };
+    match keyword.to_ascii_uppercase().as_str() {
+      "ARG" => {
+        for decl in words {
+          let (name, has_default) = match decl.split_once('=') {
+            Some((name, _)) => (name, true),
+            None => (decl, false),
+          };
+          let entry = refs.args.entry(name.to_string()).or_insert(false);
+          *entry |= has_default;
+        }
+      }
+      "RUN" => {
+        for flag in words.take_while(|w| w.starts_with("--")) {
+          let Some(spec) = flag.strip_prefix("--mount=") else {
+            continue;
+          };
+          let opts: Vec<_> = spec.split(',').collect();
+          if !opts.contains(&"type=secret") {
+            continue;
+          }
+          if let Some(id) = opts.iter().find_map(|o| o.strip_prefix("id=")) {
+            refs.secrets.insert(id.to_string());
+          }
+        }
+      }
+      "EXPOSE" => {
+        let ports = words.filter_map(|w| w.split('/').next()?.parse::<u16>().ok());
+        refs.exposed_ports.extend(ports);
+      }
+      _ => {}
+    }
+  }
+
+  refs
+}
+
+/// Join `\`-continued lines into single instructions, dropping comments and blank lines.
+fn logical_lines(content: &str) -> Vec<String> {
+  let mut lines = Vec::new();
+  let mut current = String::new();
+  for raw in content.lines() {
+    let line = raw.trim();
+    if line.starts_with('#') || (line.is_empty() && current.is_empty()) {
+      continue;
+    }
+    match line.strip_suffix('\\') {
+      Some(rest) => {
+        current.push_str(rest);
+        current.push(' ');
+      }
+      None => {
+        current.push_str(line);
+        lines.push(std::mem::take(&mut current));
+      }
+    }
+  }
+  if !current.trim().is_empty() {
+    lines.push(current);
+  }
+  lines
+}
+
+/// Compare what hl passes to the build against what the Dockerfile uses and describe every
+/// mismatch. `build_args` are the hl.yml `build.args` keys (GIT_SHA is always passed and never
+/// reported); `secrets` are the ids from `.env.build`.
+pub fn check_build_inputs(content: &str, build_args: &[String], secrets: &[String]) -> Vec<String> {
+  let refs = parse_dockerfile(content);
+  let build_args: BTreeSet<&str> = build_args.iter().map(String::as_str).collect();
+  let secrets: BTreeSet<&str> = secrets.iter().map(String::as_str).collect();
+  let mut warnings = Vec::new();
+
+  for arg in &build_args {
+    if !refs.args.contains_key(*arg) {
+      warnings.push(format!(
+        "build.args.{} is set in hl.yml but the Dockerfile never declares ARG {}",
+        arg, arg
+      ));
+    }
+  }
+
+  for secret in &secrets {
+    if !refs.secrets.contains(*secret) {
+      warnings.push(format!(
+        "build secret {} (.env.build) is never mounted with RUN --mount=type=secret,id={}",
+        secret, secret
+      ));
+    }
+  }
+
+  for (arg, has_default) in &refs.args {
+    let provided = arg == "GIT_SHA"
+      || build_args.contains(arg.as_str())
+      || PREDEFINED_ARGS.contains(&arg.as_str());
+    if !has_default && !provided {
+      warnings.push(format!(
+        "Dockerfile declares ARG {} without a default but hl.yml build.args doesn't set it",
+        arg
+      ));
+    }
+  }
+
+  for id in &refs.secrets {
+    if !secrets.contains(id.as_str()) {
+      warnings.push(format!(
+        "Dockerfile mounts secret {} but it is not set in .env.build (hl env set --build)",
+        id
+      ));
+    }
+  }
+
+  warnings
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DOCKERFILE: &str = r#"# syntax=docker/dockerfile:1
+ARG RUBY_VERSION=3.3.0
+FROM ruby:$RUBY_VERSION-slim AS base
+ARG GIT_SHA
+ARG TARGETARCH
+ARG BUNDLE_WITHOUT NODE_ENV=production
+
+# precompile assets with the master key
+RUN --mount=type=cache,target=/root/.cache \
+    --mount=type=secret,id=RAILS_MASTER_KEY,required=true \
+    RAILS_MASTER_KEY=$(cat /run/secrets/RAILS_MASTER_KEY) bin/rails assets:precompile
+EXPOSE 3000/tcp 9394
+"#;
+
+  fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn test_parse_dockerfile() {
+    let refs = parse_dockerfile(DOCKERFILE);
+    assert_eq!(refs.args.get("RUBY_VERSION"), Some(&true));
+    assert_eq!(refs.args.get("BUNDLE_WITHOUT"), Some(&false));
+    assert_eq!(refs.args.get("NODE_ENV"), Some(&true));
+    assert_eq!(refs.args.len(), 5);
+    assert_eq!(
+      refs.secrets,
+      BTreeSet::from(["RAILS_MASTER_KEY".to_string()])
+    );
+    assert_eq!(refs.exposed_ports, vec![3000, 9394]);
+  }
+
+  #[test]
+  fn test_check_build_inputs_clean() {
+    let warnings = check_build_inputs(
+      DOCKERFILE,
+      &strings(&["BUNDLE_WITHOUT"]),
+      &strings(&["RAILS_MASTER_KEY"]),
+    );
+    assert!(warnings.is_empty(), "{:?}", warnings);
+  }
+
+  #[test]
+  fn test_check_build_inputs_reports_mismatches() {
+    let warnings = check_build_inputs(
+      DOCKERFILE,
+      &strings(&["NODE_VERSION"]),
+      &strings(&["SECRET_KEY_BASE"]),
+    );
+    assert_eq!(
+      warnings,
+      vec![
+        "build.args.NODE_VERSION is set in hl.yml but the Dockerfile never declares ARG NODE_VERSION",
+        "build secret SECRET_KEY_BASE (.env.build) is never mounted with RUN --mount=type=secret,id=SECRET_KEY_BASE",
+        "Dockerfile declares ARG BUNDLE_WITHOUT without a default but hl.yml build.args doesn't set it",
+        "Dockerfile mounts secret RAILS_MASTER_KEY but it is not set in .env.build (hl env set --build)",
+      ]
+    );
+  }
+}
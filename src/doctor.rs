@@ -0,0 +1,557 @@
+use crate::config::{deploy_home, hl_git_root, hl_root, systemd_scope, GlobalConfig, SystemdScope};
+use crate::git::{list_bare_repos, post_receive_hook_is_current};
+use crate::log::{ok, warn};
+use crate::registry::{docker_config, has_registry_auth, registry_host, verify_login};
+use crate::systemd::{
+  is_lingering_enabled, system_unit_enabled_state, systemctl_hint, user_unit_enabled_state,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Oldest docker compose hl is tested with.
+const MIN_COMPOSE_VERSION: (u32, u32) = (2, 20);
+
+/// Free space below which builds and image pulls start failing in practice.
+const MIN_FREE_KIB: u64 = 5 * 1024 * 1024;
+
+/// Outcome of a single doctor check.
+#[derive(Debug, Clone)]
+pub struct Check {
+  pub name: String,
+  pub ok: bool,
+  pub detail: String,
+  /// Actionable fix-it hint, shown only when the check fails
+  pub hint: Option<String>,
+}
+
+impl Check {
+  pub fn pass(name: &str, detail: impl Into<String>) -> Self {
+    Check {
+      name: name.to_string(),
+      ok: true,
+      detail: detail.into(),
+      hint: None,
+    }
+  }
+
+  pub fn fail(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+    Check {
+      name: name.to_string(),
+      ok: false,
+      detail: detail.into(),
+      hint: Some(hint.into()),
+    }
+  }
+}
+
+/// Print checks and return the number of failures.
+pub fn print_report(checks: &[Check]) -> usize {
+  let mut failures = 0;
+  for check in checks {
+    if check.ok {
+      ok(&format!("{}: {}", check.name, check.detail));
+    } else {
+      failures += 1;
+      warn(&format!("{}: {}", check.name, check.detail));
+      if let Some(hint) = &check.hint {
+        warn(&format!("  fix: {}", hint));
+      }
+    }
+  }
+  failures
+}
+
+/// Host-level links of the boot path: the user manager must survive without a login session
+/// (lingering) and docker itself must be started at boot.
+pub async fn host_boot_checks() -> Vec<Check> {
+  let mut checks = Vec::new();
+
+  match is_lingering_enabled().await {
+    _ if systemd_scope() == SystemdScope::System => checks.push(Check::pass(
+      "lingering",
+      "not needed: app units are system units",
+    )),
+    Ok(true) => checks.push(Check::pass("lingering", "enabled for current user")),
+    Ok(false) => checks.push(Check::fail(
+      "lingering",
+      "disabled; user units only start after an interactive login",
+      "sudo loginctl enable-linger $USER",
+    )),
+    Err(e) => checks.push(Check::fail(
+      "lingering",
+      format!("could not query loginctl: {}", e),
+      "ensure systemd-logind is available on this host",
+    )),
+  }
+
+  let docker_service = system_unit_enabled_state("docker.service")
+    .await
+    .unwrap_or_default();
+  let docker_socket = system_unit_enabled_state("docker.socket")
+    .await
+    .unwrap_or_default();
+  if docker_service == "enabled" || docker_socket == "enabled" {
+    checks.push(Check::pass("docker", "docker starts at boot"));
+  } else {
+    checks.push(Check::fail(
+      "docker",
+      format!(
+        "docker.service is {:?} and docker.socket is {:?}",
+        docker_service, docker_socket
+      ),
+      "sudo systemctl enable docker.service",
+    ));
+  }
+
+  checks
+}
+
+/// Everything a host needs before hl can deploy to it: docker with buildx and a recent
+/// compose, a systemd user session that survives logout, the shared Traefik network, a login
+/// for the registry, free disk space, and the hl binary the git hooks call being this one.
+pub async fn preflight_checks(global: &GlobalConfig) -> Vec<Check> {
+  let mut checks = Vec::new();
+
+  match command_output("docker", &["version", "--format", "{{.Server.Version}}"]).await {
+    Some(version) => checks.push(Check::pass("docker", format!("engine {}", version))),
+    None => checks.push(Check::fail(
+      "docker",
+      "the docker daemon is not reachable",
+      "install docker and add yourself to the docker group: sudo usermod -aG docker $USER",
+    )),
+  }
+
+  match command_output("docker", &["buildx", "version"]).await {
+    Some(version) => checks.push(Check::pass("buildx", version)),
+    None => checks.push(Check::fail(
+      "buildx",
+      "docker buildx is not installed",
+      "install the docker-buildx-plugin package",
+    )),
+  }
+
+  let compose = command_output("docker", &["compose", "version", "--short"]).await;
+  match compose.as_deref().map(|v| (v, parse_version(v))) {
+    Some((version, Some(v))) if v >= MIN_COMPOSE_VERSION => {
+      checks.push(Check::pass("compose", version))
+    }
+    Some((version, _)) => checks.push(Check::fail(
+      "compose",
+      format!(
+        "docker compose {} is older than {}.{}",
+        version, MIN_COMPOSE_VERSION.0, MIN_COMPOSE_VERSION.1
+      ),
+      "upgrade the docker-compose-plugin package",
+    )),
+    None => checks.push(Check::fail(
+      "compose",
+      "the docker compose plugin is not installed",
+      "install the docker-compose-plugin package",
+    )),
+  }
+
+  match systemd_scope() {
+    SystemdScope::User => {
+      match command_output("systemctl", &["--user", "show-environment"]).await {
+        Some(_) => checks.push(Check::pass("systemd", "user session reachable")),
+        None => checks.push(Check::fail(
+          "systemd",
+          "cannot talk to the systemd user manager",
+          "log in through ssh (not su), or export XDG_RUNTIME_DIR=/run/user/$(id -u)",
+        )),
+      }
+    }
+    SystemdScope::System => {
+      let dir = crate::config::systemd_dir();
+      if writable(&dir) {
+        checks.push(Check::pass(
+          "systemd",
+          format!("system units go to {}", dir.display()),
+        ));
+      } else {
+        checks.push(Check::fail(
+          "systemd",
+          format!("{} is not writable by this user", dir.display()),
+          "run hl as root, or point paths.systemdDir at a directory this user owns that systemd loads units from",
+        ));
+      }
+    }
+  }
+  checks.extend(host_boot_checks().await);
+
+  let network = global.network.as_deref().unwrap_or("traefik_proxy");
+  match command_output("docker", &["network", "inspect", network]).await {
+    Some(_) => checks.push(Check::pass("network", format!("{} exists", network))),
+    None => checks.push(Check::fail(
+      "network",
+      format!("docker network {} not found", network),
+      format!(
+        "docker network create {} (and attach Traefik to it)",
+        network
+      ),
+    )),
+  }
+
+  if let Some(registry) = &global.registry {
+    let host = registry_host(registry);
+    if !has_registry_auth(&docker_config(), host) {
+      checks.push(Check::fail(
+        "registry",
+        format!("no credentials for {}", host),
+        format!("hl registry login {}", host),
+      ));
+    } else if let Err(e) = verify_login(host).await {
+      checks.push(Check::fail(
+        "registry",
+        format!("{:#}", e),
+        format!("hl registry login {}", host),
+      ));
+    } else {
+      checks.push(Check::pass("registry", format!("logged in to {}", host)));
+    }
+  }
+
+  let docker_root = command_output("docker", &["info", "--format", "{{.DockerRootDir}}"])
+    .await
+    .unwrap_or_else(|| "/var/lib/docker".to_string());
+  for (name, path) in [
+    ("disk hl", hl_root()),
+    ("disk docker", PathBuf::from(docker_root)),
+  ] {
+    let dir = existing_ancestor(&path);
+    let dir_arg = dir.to_string_lossy().to_string();
+    let free = command_output("df", &["-Pk", &dir_arg])
+      .await
+      .and_then(|out| parse_df_available(&out));
+    match free {
+      Some(kib) if kib >= MIN_FREE_KIB => checks.push(Check::pass(
+        name,
+        format!("{} GiB free on {}", kib / 1024 / 1024, dir.display()),
+      )),
+      Some(kib) => checks.push(Check::fail(
+        name,
+        format!("only {} MiB free on {}", kib / 1024, dir.display()),
+        "free space, e.g. hl releases gc --all and docker builder prune",
+      )),
+      None => checks.push(Check::fail(
+        name,
+        format!("could not read free space of {}", dir.display()),
+        "check that df is available",
+      )),
+    }
+  }
+
+  checks.push(installed_binary_check());
+  checks.push(hooks_check());
+  checks
+}
+
+/// Every bare repo's post-receive hook should match what this hl would install; hooks from
+/// older versions (or a moved home) deploy the wrong way or not at all.
+fn hooks_check() -> Check {
+  let home = deploy_home().to_string_lossy().to_string();
+  let repos = match list_bare_repos() {
+    Ok(repos) => repos,
+    Err(e) => {
+      return Check::fail(
+        "hooks",
+        format!("cannot list git repos: {}", e),
+        "check ~/hl/git",
+      )
+    }
+  };
+  let stale: Vec<&str> = repos
+    .iter()
+    .filter(|app| !post_receive_hook_is_current(&hl_git_root(app), app, &home))
+    .map(String::as_str)
+    .collect();
+  if stale.is_empty() {
+    Check::pass(
+      "hooks",
+      format!("{} post-receive hook(s) current", repos.len()),
+    )
+  } else {
+    Check::fail(
+      "hooks",
+      format!("stale post-receive hook in: {}", stale.join(", ")),
+      "hl hooks sync",
+    )
+  }
+}
+
+/// The git hooks and timers call `~/.local/bin/hl`; it should be the binary running now.
+fn installed_binary_check() -> Check {
+  let installed = deploy_home().join(".local/bin/hl");
+  let hint = |current: &Path| format!("cp {} {}", current.display(), installed.display());
+  let Ok(current) = std::env::current_exe() else {
+    return Check::fail("binary", "cannot locate the running binary", "reinstall hl");
+  };
+  if !installed.exists() {
+    return Check::fail(
+      "binary",
+      format!("{} not found; git hooks cannot deploy", installed.display()),
+      hint(&current),
+    );
+  }
+  let same = fs::canonicalize(&installed).ok() == fs::canonicalize(&current).ok()
+    || matches!((fs::read(&installed), fs::read(&current)), (Ok(a), Ok(b)) if a == b);
+  if same {
+    Check::pass("binary", format!("{} is this binary", installed.display()))
+  } else {
+    Check::fail(
+      "binary",
+      format!("{} differs from {}", installed.display(), current.display()),
+      hint(&current),
+    )
+  }
+}
+
+/// Stdout of a command that succeeded, trimmed.
+async fn command_output(program: &str, args: &[&str]) -> Option<String> {
+  let output = Command::new(program)
+    .args(args)
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .await
+    .ok()?;
+  output
+    .status
+    .success()
+    .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// `2.29.1` or `v2.29.1-desktop.1` -> (2, 29)
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+  let mut parts = version.trim_start_matches('v').split(['.', '-']);
+  Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+/// Available KiB from `df -Pk` output.
+fn parse_df_available(output: &str) -> Option<u64> {
+  output
+    .lines()
+    .nth(1)?
+    .split_whitespace()
+    .nth(3)?
+    .parse()
+    .ok()
+}
+
+fn existing_ancestor(path: &Path) -> PathBuf {
+  path
+    .ancestors()
+    .find(|p| p.exists())
+    .unwrap_or(Path::new("/"))
+    .to_path_buf()
+}
+
+/// Whether this user can create files in `dir` (or the closest existing parent).
+fn writable(dir: &Path) -> bool {
+  let probe = existing_ancestor(dir).join(format!(".hl-doctor-{}", std::process::id()));
+  let created = fs::File::create(&probe).is_ok();
+  let _ = fs::remove_file(&probe);
+  created
+}
+
+/// App-level links of the boot path: the target must be enabled, and the generated units must
+/// wait for docker and order accessories before processes.
+pub async fn app_boot_checks(
+  app: &str,
+  systemd_dir: &Path,
+  processes: &[String],
+  accessories: &[String],
+) -> Vec<Check> {
+  let target = format!("app-{}.target", app);
+  let mut checks = Vec::new();
+
+  let state = user_unit_enabled_state(&target).await.unwrap_or_default();
+  if state == "enabled" {
+    checks.push(Check::pass("target", format!("{} is enabled", target)));
+  } else {
+    checks.push(Check::fail(
+      "target",
+      format!("{} is {:?}; nothing starts the app at boot", target, state),
+      systemctl_hint(&format!("enable {}", target)),
+    ));
+  }
+
+  checks.extend(unit_file_checks(app, systemd_dir, processes, accessories));
+  checks
+}
+
+/// Static checks on the generated unit files. These don't talk to systemd, so they describe what
+/// will happen on the next boot rather than what is currently running.
+pub fn unit_file_checks(
+  app: &str,
+  systemd_dir: &Path,
+  processes: &[String],
+  accessories: &[String],
+) -> Vec<Check> {
+  let mut checks = Vec::new();
+  let target = format!("app-{}.target", app);
+  let acc_unit = format!("app-{}-acc.service", app);
+  let regenerate_hint = "redeploy the app to regenerate its unit files";
+
+  let target_content = match fs::read_to_string(systemd_dir.join(&target)) {
+    Ok(content) => content,
+    Err(_) => {
+      checks.push(Check::fail(
+        "units",
+        format!("{} not found in {}", target, systemd_dir.display()),
+        regenerate_hint,
+      ));
+      return checks;
+    }
+  };
+  let wants = unit_directive_values(&target_content, "Wants");
+
+  let mut units = Vec::new();
+  if !accessories.is_empty() {
+    units.push(acc_unit.clone());
+  }
+  for proc in processes {
+    units.push(format!("app-{}-{}.service", app, proc));
+  }
+
+  for unit in &units {
+    let name = format!("unit {}", unit);
+    let content = match fs::read_to_string(systemd_dir.join(unit)) {
+      Ok(content) => content,
+      Err(_) => {
+        checks.push(Check::fail(&name, "unit file is missing", regenerate_hint));
+        continue;
+      }
+    };
+
+    if !wants.iter().any(|w| w == unit) {
+      checks.push(Check::fail(
+        &name,
+        format!("not pulled in by {}", target),
+        regenerate_hint,
+      ));
+      continue;
+    }
+
+    if !content.contains("docker version") {
+      checks.push(Check::fail(
+        &name,
+        "does not wait for docker before starting",
+        regenerate_hint,
+      ));
+      continue;
+    }
+
+    if unit != &acc_unit && !accessories.is_empty() {
+      let after = unit_directive_values(&content, "After");
+      if !after.iter().any(|a| a == &acc_unit) {
+        checks.push(Check::fail(
+          &name,
+          format!("may start before {}", acc_unit),
+          regenerate_hint,
+        ));
+        continue;
+      }
+    }
+
+    checks.push(Check::pass(&name, "starts with the target"));
+  }
+
+  checks
+}
+
+/// Collect the space-separated values of every `<key>=` line in a unit file.
+fn unit_directive_values(content: &str, key: &str) -> Vec<String> {
+  let prefix = format!("{}=", key);
+  content
+    .lines()
+    .filter_map(|l| l.trim().strip_prefix(&prefix))
+    .flat_map(|v| v.split_whitespace().map(str::to_string))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::units_spec_builder::{render_and_write, UnitsSpec};
+  use tempfile::TempDir;
+
+  fn write_spec(temp_dir: &TempDir, accessories: Vec<String>) -> std::path::PathBuf {
+    let systemd_dir = temp_dir.path().join("systemd");
+    let spec = UnitsSpec {
+      app_name: "testapp".to_string(),
+      processes: vec!["web".to_string(), "worker".to_string()],
+      accessories,
+      systemd_dir: systemd_dir.clone(),
+      app_dir: temp_dir.path().join("apps").join("testapp"),
+      env_file: None,
+      mode: crate::config::SystemdMode::Oneshot,
+      cron: vec![],
+      run_as: None,
+    };
+    render_and_write(&spec).unwrap();
+    systemd_dir
+  }
+
+  #[test]
+  fn test_preflight_parsers() {
+    assert_eq!(parse_version("2.29.1"), Some((2, 29)));
+    assert_eq!(parse_version("v2.5.0-desktop.1"), Some((2, 5)));
+    assert_eq!(parse_version("garbage"), None);
+
+    let df =
+      "Filesystem 1024-blocks Used Available Capacity Mounted on\n/dev/sda1 100 40 60 40% /\n";
+    assert_eq!(parse_df_available(df), Some(60));
+  }
+
+  #[test]
+  fn test_unit_file_checks_pass_for_generated_units() {
+    let temp_dir = TempDir::new().unwrap();
+    let systemd_dir = write_spec(&temp_dir, vec!["postgres".to_string()]);
+    let processes = vec!["web".to_string(), "worker".to_string()];
+    let accessories = vec!["postgres".to_string()];
+
+    let checks = unit_file_checks("testapp", &systemd_dir, &processes, &accessories);
+
+    assert_eq!(checks.len(), 3);
+    assert!(checks.iter().all(|c| c.ok), "{:?}", checks);
+  }
+
+  #[test]
+  fn test_unit_file_checks_detect_missing_accessory_ordering() {
+    let temp_dir = TempDir::new().unwrap();
+    // Units rendered without accessories don't order processes after the acc unit
+    let systemd_dir = write_spec(&temp_dir, vec![]);
+    fs::write(
+      systemd_dir.join("app-testapp.target"),
+      "[Unit]\nWants=app-testapp-acc.service app-testapp-web.service app-testapp-worker.service\n",
+    )
+    .unwrap();
+    fs::write(
+      systemd_dir.join("app-testapp-acc.service"),
+      "ExecStartPre=/usr/bin/bash -lc 'docker version'\n",
+    )
+    .unwrap();
+    let processes = vec!["web".to_string(), "worker".to_string()];
+    let accessories = vec!["postgres".to_string()];
+
+    let checks = unit_file_checks("testapp", &systemd_dir, &processes, &accessories);
+
+    let failed: Vec<_> = checks.iter().filter(|c| !c.ok).collect();
+    assert_eq!(failed.len(), 2);
+    assert!(failed[0]
+      .detail
+      .contains("may start before app-testapp-acc.service"));
+  }
+
+  #[test]
+  fn test_unit_file_checks_missing_target() {
+    let temp_dir = TempDir::new().unwrap();
+    let checks = unit_file_checks("testapp", temp_dir.path(), &["web".to_string()], &[]);
+    assert_eq!(checks.len(), 1);
+    assert!(!checks[0].ok);
+    assert!(checks[0].detail.contains("app-testapp.target not found"));
+  }
+}
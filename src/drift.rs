@@ -0,0 +1,156 @@
+use crate::log::warn;
+use crate::textdiff::{colorize_diff, format_diff};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/*
+`hl diff` compares what an app should look like (hl.yml, the Procfile of the release it runs,
+its accessories) with what is actually on the host: the generated compose and unit files, the
+containers running and the release compose.yml is pinned to. Every mismatch is a `Drift`;
+redeploying fixes most of them, which is why file diffs read from the current file to the one
+a deploy would write.
+ */
+
+/// One difference between an app's desired and actual state.
+#[derive(Debug, PartialEq)]
+pub struct Drift {
+  /// What drifted, e.g. `compose.web.yml` or `process worker`
+  pub subject: String,
+  pub detail: String,
+  /// Unified diff from the actual to the desired content, for generated files
+  pub diff: Option<String>,
+}
+
+impl Drift {
+  pub fn new(subject: impl Into<String>, detail: impl Into<String>) -> Self {
+    Drift {
+      subject: subject.into(),
+      detail: detail.into(),
+      diff: None,
+    }
+  }
+}
+
+/// Names that should exist but don't, and ones that exist but shouldn't.
+pub fn name_drift(kind: &str, desired: &[String], actual: &[String]) -> Vec<Drift> {
+  let mut drifts: Vec<Drift> = desired
+    .iter()
+    .filter(|name| !actual.contains(name))
+    .map(|name| Drift::new(format!("{} {}", kind, name), "expected but not set up"))
+    .collect();
+  drifts.extend(
+    actual
+      .iter()
+      .filter(|name| !desired.contains(name))
+      .map(|name| Drift::new(format!("{} {}", kind, name), "set up but not expected")),
+  );
+  drifts
+}
+
+/// How `path` differs from the `desired` content hl would generate, if it does.
+pub fn file_drift(path: &Path, desired: &str) -> Option<Drift> {
+  let subject = path
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_else(|| path.display().to_string());
+  match std::fs::read_to_string(path) {
+    Ok(actual) if actual == desired => None,
+    Ok(actual) => Some(Drift {
+      subject,
+      detail: "differs from what hl.yml generates".to_string(),
+      diff: Some(format_diff(&actual, desired)),
+    }),
+    Err(_) => Some(Drift::new(subject, "missing")),
+  }
+}
+
+/// Services whose running container count differs from the expected one.
+pub fn replica_drift(
+  kind: &str,
+  expected: &BTreeMap<String, u32>,
+  running: &BTreeMap<String, u32>,
+) -> Vec<Drift> {
+  let mut drifts = Vec::new();
+  for (service, want) in expected {
+    let have = running.get(service).copied().unwrap_or(0);
+    if have != *want {
+      drifts.push(Drift::new(
+        format!("{} {}", kind, service),
+        format!("{} of {} container(s) running", have, want),
+      ));
+    }
+  }
+  for (service, have) in running {
+    if !expected.contains_key(service) {
+      drifts.push(Drift::new(
+        format!("{} {}", kind, service),
+        format!("{} container(s) running but not expected", have),
+      ));
+    }
+  }
+  drifts
+}
+
+pub fn print_drift(drifts: &[Drift]) {
+  for drift in drifts {
+    warn(&format!("{}: {}", drift.subject, drift.detail));
+    if let Some(diff) = &drift.diff {
+      print!("{}", colorize_diff(diff));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn names(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn test_drift() {
+    let drifts = name_drift(
+      "process",
+      &names(&["web", "worker"]),
+      &names(&["web", "clock"]),
+    );
+    assert_eq!(
+      drifts,
+      vec![
+        Drift::new("process worker", "expected but not set up"),
+        Drift::new("process clock", "set up but not expected"),
+      ]
+    );
+
+    let expected = BTreeMap::from([("web".to_string(), 2), ("worker".to_string(), 1)]);
+    let running = BTreeMap::from([("web".to_string(), 1), ("worker".to_string(), 1)]);
+    assert_eq!(
+      replica_drift("process", &expected, &running),
+      vec![Drift::new("process web", "1 of 2 container(s) running")]
+    );
+    let running = BTreeMap::from([
+      ("web".to_string(), 2),
+      ("worker".to_string(), 1),
+      ("old".to_string(), 1),
+    ]);
+    assert_eq!(
+      replica_drift("process", &expected, &running),
+      vec![Drift::new(
+        "process old",
+        "1 container(s) running but not expected"
+      )]
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("compose.yml");
+    assert_eq!(
+      file_drift(&path, "services: {}\n"),
+      Some(Drift::new("compose.yml", "missing"))
+    );
+    std::fs::write(&path, "services: {}\n").unwrap();
+    assert_eq!(file_drift(&path, "services: {}\n"), None);
+    let drift = file_drift(&path, "services:\n  web: {}\n").unwrap();
+    assert!(drift.diff.unwrap().contains("+   web: {}"));
+  }
+}
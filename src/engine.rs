@@ -0,0 +1,398 @@
+/*
+A small client for the Docker Engine API on its unix socket, for the image operations of a
+deploy and rollback: inspect, tag, pull and push. Failures come back as the daemon's own
+message and status instead of a CLI exit code, and pulls and pushes report their progress as
+events. The rest of hl keeps using the docker CLI: buildx and compose have no API equivalent,
+and `run`/`exec` attach to the terminal.
+
+When the daemon isn't on a local unix socket (DOCKER_HOST over tcp or ssh, a docker context)
+or the socket can't be reached, calls fail with `EngineError::Unavailable` and docker.rs runs
+the CLI instead.
+ */
+
+use crate::log::{debug, log};
+use crate::output::{from_json, to_json_line};
+use crate::registry::{docker_config, registry_auth, registry_host};
+use base64::{engine::general_purpose::URL_SAFE, Engine};
+use http_body_util::{BodyExt, Empty};
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use std::path::PathBuf;
+use tokio::net::UnixStream;
+
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+#[derive(Debug, thiserror::Error)]
+pub enum EngineError {
+  /// The daemon isn't reachable on a local socket
+  #[error("{0}")]
+  Unavailable(String),
+  /// No such image (404)
+  #[error("{0}")]
+  NotFound(String),
+  /// The daemon rejected the request
+  #[error("{method} {path} failed ({status}): {message}")]
+  Api {
+    method: Method,
+    path: String,
+    status: StatusCode,
+    message: String,
+  },
+  /// A pull or push failed part way, e.g. the registry refused the credentials
+  #[error("{action} {reference} failed: {message}")]
+  Progress {
+    action: &'static str,
+    reference: String,
+    message: String,
+  },
+  /// The connection broke or a reply didn't parse
+  #[error("{0}")]
+  Protocol(String),
+}
+
+/// The daemon's socket: DOCKER_HOST when it names a unix socket, the standard one otherwise.
+fn socket_path(docker_host: Option<&str>, config_json: &str) -> Result<PathBuf, EngineError> {
+  if let Some(host) = docker_host.filter(|h| !h.is_empty()) {
+    return match host.strip_prefix("unix://") {
+      Some(path) => Ok(PathBuf::from(path)),
+      None => Err(EngineError::Unavailable(format!(
+        "DOCKER_HOST {} isn't a unix socket",
+        host
+      ))),
+    };
+  }
+  // Contexts keep their endpoint in ~/.docker/contexts; leave those to the CLI
+  let context = from_json::<serde_yaml::Value>(config_json)
+    .ok()
+    .and_then(|c| c.get("currentContext")?.as_str().map(str::to_string))
+    .or_else(|| std::env::var("DOCKER_CONTEXT").ok())
+    .filter(|c| !c.is_empty() && c != "default");
+  match context {
+    Some(context) => Err(EngineError::Unavailable(format!(
+      "docker context {} is in use",
+      context
+    ))),
+    None => Ok(PathBuf::from(DEFAULT_SOCKET)),
+  }
+}
+
+async fn send(
+  method: Method,
+  path: &str,
+  registry_auth: Option<String>,
+) -> Result<Response<Incoming>, EngineError> {
+  let socket = socket_path(
+    std::env::var("DOCKER_HOST").ok().as_deref(),
+    &docker_config(),
+  )?;
+  let stream = UnixStream::connect(&socket).await.map_err(|e| {
+    EngineError::Unavailable(format!("can't connect to {}: {}", socket.display(), e))
+  })?;
+  let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+    .await
+    .map_err(|e| EngineError::Protocol(format!("handshake with the docker daemon: {}", e)))?;
+  tokio::spawn(async move {
+    if let Err(e) = connection.await {
+      debug(&format!("docker daemon connection: {}", e));
+    }
+  });
+
+  let mut request = Request::builder()
+    .method(method.clone())
+    .uri(path)
+    .header("Host", "docker");
+  if let Some(auth) = registry_auth {
+    request = request.header("X-Registry-Auth", auth);
+  }
+  let request = request
+    .body(Empty::<Bytes>::new())
+    .map_err(|e| EngineError::Protocol(e.to_string()))?;
+  debug(&format!("docker API: {} {}", method, path));
+  let response = sender
+    .send_request(request)
+    .await
+    .map_err(|e| EngineError::Protocol(format!("{} {}: {}", method, path, e)))?;
+
+  let status = response.status();
+  if status.is_success() {
+    return Ok(response);
+  }
+  let body = read_body(response).await?;
+  // Errors are `{"message": "..."}`
+  let message = from_json::<ApiMessage>(&body)
+    .map(|m| m.message)
+    .unwrap_or(body);
+  if status == StatusCode::NOT_FOUND {
+    return Err(EngineError::NotFound(message));
+  }
+  Err(EngineError::Api {
+    method,
+    path: path.to_string(),
+    status,
+    message,
+  })
+}
+
+#[derive(Deserialize)]
+struct ApiMessage {
+  message: String,
+}
+
+async fn read_body(response: Response<Incoming>) -> Result<String, EngineError> {
+  let bytes = response
+    .into_body()
+    .collect()
+    .await
+    .map_err(|e| EngineError::Protocol(format!("reading the docker daemon's reply: {}", e)))?
+    .to_bytes();
+  Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// ID of a local image, or None when it isn't on this host.
+pub async fn image_id(reference: &str) -> Result<Option<String>, EngineError> {
+  #[derive(Deserialize)]
+  struct Inspect {
+    #[serde(rename = "Id")]
+    id: String,
+  }
+  let path = format!("/images/{}/json", encode(reference));
+  match send(Method::GET, &path, None).await {
+    Ok(response) => {
+      let body = read_body(response).await?;
+      let inspect: Inspect = from_json(&body)
+        .map_err(|e| EngineError::Protocol(format!("image inspect of {}: {:#}", reference, e)))?;
+      Ok(Some(inspect.id))
+    }
+    Err(EngineError::NotFound(_)) => Ok(None),
+    Err(e) => Err(e),
+  }
+}
+
+/// Tag the local image `source` (a reference or an image ID) as `target`.
+pub async fn tag(source: &str, target: &str) -> Result<(), EngineError> {
+  let (repo, tag) = split_reference(target);
+  let path = format!(
+    "/images/{}/tag?repo={}&tag={}",
+    encode(source),
+    encode(repo),
+    encode(tag.unwrap_or("latest"))
+  );
+  send(Method::POST, &path, None).await.map(|_| ())
+}
+
+/// Pull `reference` from its registry.
+pub async fn pull(reference: &str) -> Result<(), EngineError> {
+  let (repo, tag) = split_reference(reference);
+  let path = format!(
+    "/images/create?fromImage={}&tag={}",
+    encode(repo),
+    encode(tag.unwrap_or("latest"))
+  );
+  let response = send(Method::POST, &path, Some(auth_header(reference).await)).await?;
+  follow_progress(response, "pull", reference).await
+}
+
+/// Push `reference` to its registry. Only that tag: without one the daemon would push every
+/// local tag of the repository.
+pub async fn push(reference: &str) -> Result<(), EngineError> {
+  let (repo, tag) = split_reference(reference);
+  let path = format!(
+    "/images/{}/push?tag={}",
+    encode(repo),
+    encode(tag.unwrap_or("latest"))
+  );
+  let response = send(Method::POST, &path, Some(auth_header(reference).await)).await?;
+  follow_progress(response, "push", reference).await
+}
+
+/// `X-Registry-Auth` for the registry of `reference`: base64url JSON credentials, `{}` to go
+/// anonymously. The daemon requires the header on pushes even then.
+async fn auth_header(reference: &str) -> String {
+  let host = registry_host(split_reference(reference).0);
+  let auth = match registry_auth(host).await {
+    Ok(auth) => auth,
+    Err(e) => {
+      debug(&format!("no credentials for {}: {:#}", host, e));
+      None
+    }
+  };
+  let json = auth
+    .and_then(|auth| to_json_line(&auth).ok())
+    .unwrap_or_else(|| "{}".to_string());
+  URL_SAFE.encode(json)
+}
+
+/// One line of a pull or push progress stream.
+#[derive(Debug, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct ProgressEvent {
+  #[serde(default)]
+  id: Option<String>,
+  #[serde(default)]
+  status: Option<String>,
+  /// Set on byte counts; those are too chatty to log
+  #[serde(default)]
+  progress: Option<String>,
+  #[serde(default)]
+  error: Option<String>,
+}
+
+impl ProgressEvent {
+  /// The line to log for this event, if it is worth one.
+  fn message(&self) -> Option<String> {
+    if self.progress.is_some() {
+      return None;
+    }
+    let status = self.status.as_deref()?;
+    Some(match &self.id {
+      Some(id) => format!("{}: {}", id, status),
+      None => status.to_string(),
+    })
+  }
+}
+
+/// Log a pull or push as it goes. The daemon answers 200 before it starts, so failures arrive
+/// as an `error` event in the stream.
+async fn follow_progress(
+  response: Response<Incoming>,
+  action: &'static str,
+  reference: &str,
+) -> Result<(), EngineError> {
+  let mut body = response.into_body();
+  let mut buffer = Vec::new();
+  while let Some(frame) = body.frame().await {
+    let frame =
+      frame.map_err(|e| EngineError::Protocol(format!("{} {}: {}", action, reference, e)))?;
+    let Ok(data) = frame.into_data() else {
+      continue;
+    };
+    buffer.extend_from_slice(&data);
+    while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+      let line: Vec<u8> = buffer.drain(..=end).collect();
+      handle_event(&String::from_utf8_lossy(&line), action, reference)?;
+    }
+  }
+  handle_event(&String::from_utf8_lossy(&buffer), action, reference)
+}
+
+fn handle_event(line: &str, action: &'static str, reference: &str) -> Result<(), EngineError> {
+  let line = line.trim();
+  if line.is_empty() {
+    return Ok(());
+  }
+  let Ok(event) = from_json::<ProgressEvent>(line) else {
+    debug(&format!("{} {}: {}", action, reference, line));
+    return Ok(());
+  };
+  if let Some(message) = event.error {
+    return Err(EngineError::Progress {
+      action,
+      reference: reference.to_string(),
+      message,
+    });
+  }
+  if let Some(message) = event.message() {
+    log(&message);
+  }
+  Ok(())
+}
+
+/// Repository and tag of an image reference: the tag follows the last `:` after the last `/`,
+/// so a registry port isn't taken for one.
+fn split_reference(reference: &str) -> (&str, Option<&str>) {
+  let name_start = reference.rfind('/').map_or(0, |i| i + 1);
+  match reference[name_start..].rfind(':') {
+    Some(i) => (
+      &reference[..name_start + i],
+      Some(&reference[name_start + i + 1..]),
+    ),
+    None => (reference, None),
+  }
+}
+
+/// Percent-encode a path segment or query value. `/` stays: the daemon routes image names
+/// with slashes in them.
+fn encode(value: &str) -> String {
+  value
+    .bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+        (b as char).to_string()
+      }
+      _ => format!("%{:02X}", b),
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_socket_path() {
+    assert_eq!(
+      socket_path(None, "").unwrap(),
+      PathBuf::from("/var/run/docker.sock")
+    );
+    assert_eq!(
+      socket_path(Some("unix:///run/user/1000/docker.sock"), "").unwrap(),
+      PathBuf::from("/run/user/1000/docker.sock")
+    );
+    assert!(matches!(
+      socket_path(Some("tcp://10.0.0.2:2375"), ""),
+      Err(EngineError::Unavailable(_))
+    ));
+    assert!(matches!(
+      socket_path(None, r#"{"currentContext": "remote"}"#),
+      Err(EngineError::Unavailable(_))
+    ));
+  }
+
+  #[test]
+  fn test_split_reference() {
+    assert_eq!(
+      split_reference("registry.example.com:5000/team/app:latest"),
+      ("registry.example.com:5000/team/app", Some("latest"))
+    );
+    assert_eq!(
+      split_reference("localhost:5000/app"),
+      ("localhost:5000/app", None)
+    );
+    assert_eq!(split_reference("app:abc1234"), ("app", Some("abc1234")));
+  }
+
+  #[test]
+  fn test_encode() {
+    assert_eq!(
+      encode("registry.example.com:5000/app"),
+      "registry.example.com%3A5000/app"
+    );
+    assert_eq!(encode("sha256:abc"), "sha256%3Aabc");
+  }
+
+  #[test]
+  fn test_progress_events() {
+    let status: ProgressEvent =
+      from_json(r#"{"status":"Pushed","progressDetail":{},"id":"5f70bf18a086"}"#).unwrap();
+    assert_eq!(status.message().as_deref(), Some("5f70bf18a086: Pushed"));
+    let bytes: ProgressEvent = from_json(
+      r#"{"status":"Pushing","progressDetail":{"current":512,"total":1024},"progress":"[=>  ]","id":"5f70bf18a086"}"#,
+    )
+    .unwrap();
+    assert_eq!(bytes.message(), None);
+
+    assert!(handle_event(r#"{"status":"Waiting","id":"a"}"#, "push", "app:latest").is_ok());
+    let failed = handle_event(
+      r#"{"errorDetail":{"message":"denied: requested access to the resource is denied"},"error":"denied: requested access to the resource is denied"}"#,
+      "push",
+      "app:latest",
+    )
+    .unwrap_err();
+    assert_eq!(
+      failed.to_string(),
+      "push app:latest failed: denied: requested access to the resource is denied"
+    );
+  }
+}
@@ -1,11 +1,15 @@
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
 use tokio::fs;
 
 use crate::{
-  config::build_env_file,
+  config::{app_dir, build_env_file, env_file},
   docker::BuildSecret,
   log::{debug, log},
+  time::unix_now,
 };
 
 /// Read environment variable key-value pairs from a .env (or .env.build) file
@@ -23,6 +27,116 @@ pub fn load_env_file_contents(path: &std::path::Path) -> Result<HashMap<String,
   Ok(map)
 }
 
+/// Generate a secret value. `hex<N>` gives N random bytes hex-encoded (hex32 is 64 chars);
+/// `alnum<N>` gives N random letters and digits.
+pub fn generate_secret(generator: &str) -> Result<String> {
+  use rand::Rng;
+  let parse_len = |n: &str| -> Result<usize> {
+    match n.parse::<usize>() {
+      Ok(len) if len > 0 => Ok(len),
+      _ => anyhow::bail!(
+        "invalid generator {:?}: expected a positive length",
+        generator
+      ),
+    }
+  };
+  let mut rng = rand::rng();
+  if let Some(n) = generator.strip_prefix("hex") {
+    Ok(
+      (0..parse_len(n)?)
+        .map(|_| format!("{:02x}", rng.random::<u8>()))
+        .collect(),
+    )
+  } else if let Some(n) = generator.strip_prefix("alnum") {
+    Ok(
+      (0..parse_len(n)?)
+        .map(|_| rng.sample(rand::distr::Alphanumeric) as char)
+        .collect(),
+    )
+  } else {
+    anyhow::bail!(
+      "unknown generator {:?}; use hex<N> (e.g. hex32) or alnum<N>",
+      generator
+    )
+  }
+}
+
+/// Content hash of an env file (64-bit FNV-1a, hex). Identifies an environment without
+/// storing its values; not a cryptographic digest.
+pub fn env_hash(content: &[u8]) -> String {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for byte in content {
+    hash ^= *byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  format!("{:016x}", hash)
+}
+
+/// The .env the app's processes were last (re)started with.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedEnv {
+  pub hash: String,
+  /// Unix seconds
+  pub applied_at: u64,
+}
+
+/// Whether running processes see the current .env.
+#[derive(Debug, PartialEq)]
+pub enum EnvStatus {
+  /// Processes haven't been restarted by hl since this was tracked
+  Unknown,
+  Current,
+  /// .env changed after the last restart; `changed_at` is its mtime (unix seconds)
+  Stale {
+    changed_at: Option<u64>,
+  },
+}
+
+fn applied_env_path(app: &str) -> PathBuf {
+  app_dir(app).join("env.applied.yml")
+}
+
+fn current_env_hash(app: &str) -> Result<String> {
+  let path = env_file(app);
+  let content = if path.exists() {
+    std::fs::read(&path)?
+  } else {
+    Vec::new()
+  };
+  Ok(env_hash(&content))
+}
+
+/// Record the current .env as the one processes were started with. Called whenever the app
+/// target is restarted.
+pub fn record_applied_env(app: &str) -> Result<()> {
+  let applied = AppliedEnv {
+    hash: current_env_hash(app)?,
+    applied_at: unix_now(),
+  };
+  std::fs::write(applied_env_path(app), serde_yaml::to_string(&applied)?)?;
+  Ok(())
+}
+
+/// Compare the current .env with the one recorded at the last restart.
+pub fn env_status(app: &str) -> Result<EnvStatus> {
+  let path = applied_env_path(app);
+  if !path.exists() {
+    return Ok(EnvStatus::Unknown);
+  }
+  let applied: AppliedEnv = serde_yaml::from_str(&std::fs::read_to_string(&path)?)
+    .with_context(|| format!("Failed to parse {}", path.display()))?;
+  if applied.hash == current_env_hash(app)? {
+    return Ok(EnvStatus::Current);
+  }
+  let changed_at = std::fs::metadata(env_file(app))
+    .and_then(|m| m.modified())
+    .ok()
+    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    .map(|d| d.as_secs());
+  Ok(EnvStatus::Stale { changed_at })
+}
+
 /// Write environment variable key-value pairs to a .env file
 /// # Arguments
 /// * `path` - Path to the .env file
@@ -40,7 +154,7 @@ pub async fn write_env_file_contents(
   let mut file_content = String::new();
   for key in sorted_keys {
     if let Some(value) = content.get(key) {
-      file_content.push_str(&format!("{}={}\n", key, value));
+      file_content.push_str(&format!("{}={}\n", key, quote_env_value(value)));
     }
   }
 
@@ -48,6 +162,26 @@ pub async fn write_env_file_contents(
   Ok(())
 }
 
+/// Quote a value so dotenv parsers (dotenvy, docker compose) read it back unchanged. Plain
+/// values are written as-is; single quotes are preferred since nothing is expanded in them.
+pub fn quote_env_value(value: &str) -> String {
+  let plain = !value
+    .chars()
+    .any(|c| c.is_whitespace() || matches!(c, '#' | '"' | '\'' | '\\' | '$' | '`'));
+  if plain {
+    value.to_string()
+  } else if !value.contains('\'') && !value.contains('\n') {
+    format!("'{}'", value)
+  } else {
+    let escaped = value
+      .replace('\\', "\\\\")
+      .replace('"', "\\\"")
+      .replace('$', "\\$")
+      .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+  }
+}
+
 /// Load build environment variables for the given app
 /// # Arguments
 /// * `app` - Application name
@@ -89,3 +223,79 @@ pub fn load_build_secrets(app: &str) -> Result<Vec<BuildSecret>> {
       .collect::<Vec<BuildSecret>>(),
   )
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use serial_test::serial;
+  use tempfile::TempDir;
+
+  #[test]
+  #[serial]
+  fn test_env_status_tracks_restarts() {
+    let temp_dir = TempDir::new().unwrap();
+    std::env::set_var("HL_ROOT_OVERRIDE", temp_dir.path());
+    std::fs::create_dir_all(app_dir("testapp")).unwrap();
+    std::fs::write(env_file("testapp"), "KEY=1\n").unwrap();
+
+    assert_eq!(env_status("testapp").unwrap(), EnvStatus::Unknown);
+    record_applied_env("testapp").unwrap();
+    assert_eq!(env_status("testapp").unwrap(), EnvStatus::Current);
+
+    std::fs::write(env_file("testapp"), "KEY=2\n").unwrap();
+    assert!(matches!(
+      env_status("testapp").unwrap(),
+      EnvStatus::Stale {
+        changed_at: Some(_)
+      }
+    ));
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+  }
+
+  #[tokio::test]
+  async fn test_write_env_file_contents_round_trips() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join(".env");
+    let values = HashMap::from([
+      ("PLAIN".to_string(), "postgres://u:p@db/app?x=1".to_string()),
+      (
+        "SPACES".to_string(),
+        "two words # not a comment".to_string(),
+      ),
+      ("DOLLAR".to_string(), "pa$$word".to_string()),
+      ("QUOTE".to_string(), "it's \"quoted\"".to_string()),
+      ("MULTI".to_string(), "line1\nline2".to_string()),
+      ("EMPTY".to_string(), String::new()),
+    ]);
+
+    write_env_file_contents(&path, &values).await.unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(content.contains("PLAIN=postgres://u:p@db/app?x=1\n"));
+    assert!(content.contains("SPACES='two words # not a comment'\n"));
+    assert_eq!(load_env_file_contents(&path).unwrap(), values);
+  }
+
+  #[test]
+  fn test_generate_secret() {
+    let hex = generate_secret("hex32").unwrap();
+    assert_eq!(hex.len(), 64);
+    assert!(hex.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_ne!(hex, generate_secret("hex32").unwrap());
+
+    let alnum = generate_secret("alnum20").unwrap();
+    assert_eq!(alnum.len(), 20);
+    assert!(alnum.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    assert!(generate_secret("hex0").is_err());
+    assert!(generate_secret("uuid").is_err());
+  }
+
+  #[test]
+  fn test_env_hash() {
+    assert_eq!(env_hash(b""), "cbf29ce484222325");
+    assert_eq!(env_hash(b"a"), "af63dc4c8601ec8c");
+    assert_ne!(env_hash(b"KEY=1\n"), env_hash(b"KEY=2\n"));
+  }
+}
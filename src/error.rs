@@ -0,0 +1,147 @@
+/*
+Errors of hl's public API (`Deployer`, `AccessoryManager`) and the categories behind the CLI's
+exit codes. Internals keep using anyhow; where a failure happens that a caller would act on
+differently (a failed build, an unhealthy release, a missing hl.yml) it is raised as the
+matching variant, which travels up inside anyhow::Error through any added context. The API
+converts back with `categorize`, `hl` exits with `exit_code_of`.
+ */
+
+/// Exit codes of `hl`, one per category; 2 stays clap's for usage errors.
+pub mod exit_code {
+  pub const OTHER: i32 = 1;
+  pub const CONFIG: i32 = 3;
+  pub const BUILD: i32 = 4;
+  pub const DOCKER: i32 = 5;
+  pub const SYSTEMD: i32 = 6;
+  pub const MIGRATION: i32 = 7;
+  pub const HEALTH: i32 = 8;
+  pub const HOOK: i32 = 9;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  /// The app isn't set up (no hl.yml, app directory or environment) or its config is invalid
+  #[error("{0:#}")]
+  Config(anyhow::Error),
+  /// Building or pushing the image failed
+  #[error("{0:#}")]
+  Build(anyhow::Error),
+  /// A docker or docker compose command failed
+  #[error("{0:#}")]
+  Docker(anyhow::Error),
+  /// A systemd call (over D-Bus or systemctl) failed or a unit didn't start
+  #[error("{0:#}")]
+  Systemd(anyhow::Error),
+  /// The release's migrations failed
+  #[error("{0:#}")]
+  Migration(anyhow::Error),
+  /// The restarted release or an accessory never became healthy
+  #[error("{0:#}")]
+  Health(anyhow::Error),
+  /// A pre-build or pre-restart hook plugin failed
+  #[error("{0:#}")]
+  Hook(anyhow::Error),
+  /// Reading or writing the app's files failed
+  #[error(transparent)]
+  Io(#[from] std::io::Error),
+  /// Any other step failed
+  #[error("{0:#}")]
+  Other(anyhow::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      Error::Config(_) => exit_code::CONFIG,
+      Error::Build(_) => exit_code::BUILD,
+      Error::Docker(_) => exit_code::DOCKER,
+      Error::Systemd(_) => exit_code::SYSTEMD,
+      Error::Migration(_) => exit_code::MIGRATION,
+      Error::Health(_) => exit_code::HEALTH,
+      Error::Hook(_) => exit_code::HOOK,
+      Error::Io(_) | Error::Other(_) => exit_code::OTHER,
+    }
+  }
+
+  /// The variant holding errors of this one's category; None for uncategorized errors.
+  fn category(&self) -> Option<fn(anyhow::Error) -> Error> {
+    match self {
+      Error::Config(_) => Some(Error::Config),
+      Error::Build(_) => Some(Error::Build),
+      Error::Docker(_) => Some(Error::Docker),
+      Error::Systemd(_) => Some(Error::Systemd),
+      Error::Migration(_) => Some(Error::Migration),
+      Error::Health(_) => Some(Error::Health),
+      Error::Hook(_) => Some(Error::Hook),
+      Error::Io(_) | Error::Other(_) => None,
+    }
+  }
+
+  /// `err` in the category it was raised with, or `fallback` when it has none; the context
+  /// added on the way up is kept.
+  pub fn categorize(err: anyhow::Error, fallback: fn(anyhow::Error) -> Error) -> Error {
+    let category = err
+      .chain()
+      .find_map(|cause| cause.downcast_ref::<Error>()?.category());
+    category.unwrap_or(fallback)(err)
+  }
+}
+
+impl From<anyhow::Error> for Error {
+  fn from(err: anyhow::Error) -> Self {
+    Error::categorize(err, Error::Other)
+  }
+}
+
+/// Exit code for an error `hl` failed with.
+pub fn exit_code_of(err: &anyhow::Error) -> i32 {
+  err
+    .chain()
+    .find_map(|cause| {
+      let err = cause.downcast_ref::<Error>()?;
+      err.category().map(|_| err.exit_code())
+    })
+    .unwrap_or(exit_code::OTHER)
+}
+
+/// Sort an anyhow result into a category where its error has none yet.
+pub trait Categorize<T> {
+  fn categorize(self, fallback: fn(anyhow::Error) -> Error) -> Result<T>;
+}
+
+impl<T> Categorize<T> for anyhow::Result<T> {
+  fn categorize(self, fallback: fn(anyhow::Error) -> Error) -> Result<T> {
+    self.map_err(|err| Error::categorize(err, fallback))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::Context;
+
+  #[test]
+  fn test_categorize() {
+    let health: anyhow::Error = Error::Health(anyhow::anyhow!("timed out")).into();
+    let err = Err::<(), _>(health)
+      .context("rollback to abc1234")
+      .unwrap_err();
+    assert_eq!(exit_code_of(&err), exit_code::HEALTH);
+    let err = Error::categorize(err, Error::Build);
+    assert!(matches!(err, Error::Health(_)));
+    assert_eq!(err.to_string(), "rollback to abc1234: timed out");
+
+    let plain = anyhow::anyhow!("no such file");
+    assert_eq!(exit_code_of(&plain), exit_code::OTHER);
+    assert!(matches!(
+      Error::categorize(plain, Error::Config),
+      Error::Config(_)
+    ));
+    assert!(matches!(
+      Error::from(anyhow::anyhow!("boom")),
+      Error::Other(_)
+    ));
+  }
+}
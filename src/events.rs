@@ -0,0 +1,135 @@
+use crate::config::events_file;
+use crate::log::warn;
+use crate::output::{from_json, to_json_line};
+use crate::time::unix_now;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::io::Write;
+use std::path::Path;
+
+/// An operation that changed an app, one line of `~/hl/events.jsonl`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+  /// Unix seconds
+  pub at: u64,
+  pub user: String,
+  pub app: String,
+  /// Command that ran, e.g. `deploy` or `env set`
+  pub action: String,
+  /// What it was asked to do. Never values of env variables, only their keys.
+  #[serde(default)]
+  pub args: Vec<String>,
+  /// Whether the operation succeeded; unset for events logged before outcomes were recorded
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub ok: Option<bool>,
+  /// Why the operation failed
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+/// Append an event for `app` to the audit log once the operation is over, with how it
+/// ended. Best effort: a failure to write only warns.
+pub fn record_event<T, E: Display>(
+  app: &str,
+  action: &str,
+  args: &[String],
+  outcome: &Result<T, E>,
+) {
+  let event = Event {
+    at: unix_now(),
+    user: current_user(),
+    app: app.to_string(),
+    action: action.to_string(),
+    args: args.to_vec(),
+    ok: Some(outcome.is_ok()),
+    error: outcome.as_ref().err().map(|e| format!("{:#}", e)),
+  };
+  if let Err(e) = append_event(&events_file(), &event) {
+    warn(&format!(
+      "failed to record {} in the event log: {}",
+      action, e
+    ));
+  }
+}
+
+fn append_event(path: &Path, event: &Event) -> Result<()> {
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  let mut file = std::fs::OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)?;
+  // One write per line so concurrent hl processes don't interleave
+  file.write_all(format!("{}\n", to_json_line(event)?).as_bytes())?;
+  Ok(())
+}
+
+/// Events in the log, oldest first. Lines that don't parse are skipped.
+pub fn load_events(path: &Path) -> Result<Vec<Event>> {
+  if !path.exists() {
+    return Ok(Vec::new());
+  }
+  Ok(
+    std::fs::read_to_string(path)?
+      .lines()
+      .filter(|line| !line.trim().is_empty())
+      .filter_map(|line| from_json(line).ok())
+      .collect(),
+  )
+}
+
+/// Who ran hl: the invoking user under sudo, otherwise the login user.
+fn current_user() -> String {
+  ["SUDO_USER", "USER", "LOGNAME"]
+    .iter()
+    .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+    .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  #[test]
+  fn test_append_and_load_events() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("events.jsonl");
+    assert!(load_events(&path).unwrap().is_empty());
+
+    let deploy = Event {
+      at: 1_700_000_000,
+      user: "deploy".to_string(),
+      app: "myapp".to_string(),
+      action: "deploy".to_string(),
+      args: vec!["sha=abc1234".to_string(), "branch=main".to_string()],
+      ok: Some(true),
+      error: None,
+    };
+    let env_set = Event {
+      action: "env set".to_string(),
+      args: vec!["SECRET_KEY_BASE".to_string()],
+      ok: Some(false),
+      error: Some("no .env file".to_string()),
+      ..deploy.clone()
+    };
+    append_event(&path, &deploy).unwrap();
+    append_event(&path, &env_set).unwrap();
+    std::fs::OpenOptions::new()
+      .append(true)
+      .open(&path)
+      .unwrap()
+      .write_all(b"not json\n")
+      .unwrap();
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(content.starts_with(
+      r#"{"at":1700000000,"user":"deploy","app":"myapp","action":"deploy","args":["sha=abc1234","branch=main"],"ok":true}"#
+    ));
+    assert!(content.contains(r#""ok":false,"error":"no .env file"}"#));
+    assert_eq!(load_events(&path).unwrap(), vec![deploy, env_set]);
+  }
+}
@@ -0,0 +1,185 @@
+use crate::config::{app_dir, HLConfig};
+use crate::log::{debug, log, progress_stdout, warn};
+use anyhow::Result;
+use std::future::Future;
+use std::process::Stdio;
+use tokio::{fs, process::Command};
+
+/*
+Readiness gate: a tiny nginx sidecar (compose project <app>-gate) that answers every request for
+the app's domain with `503 Retry-After`. hl raises it right before restarting processes and
+lowers it once the health gate passes, so load balancers and uptime monitors see an explicit
+"release in progress" instead of Traefik 404/502s. Its router outranks the app's docker-label
+router. It comes down again whether or not the restart passes the health gate, so a failed
+release never leaves the domain stuck on 503s.
+ */
+
+const GATE_OVERLAY: &str = "gate.compose.yml";
+
+/// Render the compose file for the readiness gate sidecar.
+pub fn render_gate_compose(cfg: &HLConfig) -> String {
+  format!(
+    r#"
+services:
+  gate:
+    image: nginx:alpine
+    container_name: {app}-gate
+    restart: "no"
+    networks: [{network}]
+    command:
+      - sh
+      - -c
+      - |
+        echo 'server {{ listen 80; location / {{ add_header Retry-After 10 always; return 503 "release in progress"; }} }}' > /etc/nginx/conf.d/default.conf
+        exec nginx -g 'daemon off;'
+    labels:
+      traefik.enable: true
+      traefik.http.routers.{app}-gate.rule: {rule}
+      traefik.http.routers.{app}-gate.entrypoints: websecure
+      traefik.http.routers.{app}-gate.priority: 10000
+      traefik.http.routers.{app}-gate.tls.certresolver: {resolver}
+      traefik.http.services.{app}-gate.loadbalancer.server.port: 80
+networks:
+  {network}:
+    external: true
+    name: {network}
+"#,
+    app = cfg.app,
+    rule = cfg.host_rule(&cfg.domain),
+    resolver = cfg.resolver,
+    network = cfg.network,
+  )
+}
+
+async fn gate_compose(app: &str, action: &[&str]) -> Result<()> {
+  let project = format!("{}-gate", app);
+  let mut args = vec!["compose", "-p", &project, "-f", GATE_OVERLAY];
+  args.extend_from_slice(action);
+  debug(&format!("executing: docker {}", args.join(" ")));
+
+  let status = Command::new("docker")
+    .args(&args)
+    .current_dir(app_dir(app))
+    .stdin(Stdio::null())
+    .stdout(progress_stdout())
+    .stderr(Stdio::inherit())
+    .status()
+    .await?;
+  if !status.success() {
+    anyhow::bail!(
+      "docker compose {} failed with status: {}",
+      action.join(" "),
+      status
+    );
+  }
+  Ok(())
+}
+
+/// Start the gate so the app's domain answers 503 until `lower_gate` is called.
+pub async fn raise_gate(cfg: &HLConfig) -> Result<()> {
+  let path = app_dir(&cfg.app).join(GATE_OVERLAY);
+  fs::write(&path, render_gate_compose(cfg)).await?;
+  gate_compose(&cfg.app, &["up", "-d", "--wait", "gate"]).await
+}
+
+/// Remove the gate, handing traffic back to the app's own router.
+pub async fn lower_gate(cfg: &HLConfig) -> Result<()> {
+  if !app_dir(&cfg.app).join(GATE_OVERLAY).exists() {
+    return Ok(());
+  }
+  gate_compose(&cfg.app, &["down"]).await
+}
+
+/// Run `step` behind the gate: raised before it, lowered after it whatever its outcome.
+pub async fn with_gate<T>(cfg: &HLConfig, step: impl Future<Output = Result<T>>) -> Result<T> {
+  log("raising readiness gate");
+  gated(raise_gate(cfg), step, || {
+    log("lowering readiness gate");
+    lower_gate(cfg)
+  })
+  .await
+}
+
+/// `step` between `raise` and `lower`. `lower` runs on every path, including a failed `raise`
+/// that may have left the gate half up; the first error is the one returned.
+async fn gated<T, L: Future<Output = Result<()>>>(
+  raise: impl Future<Output = Result<()>>,
+  step: impl Future<Output = Result<T>>,
+  lower: impl FnOnce() -> L,
+) -> Result<T> {
+  let result = match raise.await {
+    Ok(()) => step.await,
+    Err(e) => Err(e.context("failed to raise the readiness gate")),
+  };
+  match (result, lower().await) {
+    (Ok(value), Ok(())) => Ok(value),
+    (Ok(_), Err(e)) => Err(e.context("failed to lower the readiness gate")),
+    (Err(e), lowered) => {
+      if let Err(lower_err) = lowered {
+        warn(&format!(
+          "failed to lower the readiness gate: {:#}",
+          lower_err
+        ));
+      }
+      Err(e)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::Cell;
+
+  #[tokio::test]
+  async fn test_gated_lowers_on_failure() {
+    let lowered = Cell::new(0);
+    let lower = || async {
+      lowered.set(lowered.get() + 1);
+      Ok(())
+    };
+
+    let result = gated(async { Ok(()) }, async { Ok(7) }, lower).await;
+    assert_eq!(result.unwrap(), 7);
+    assert_eq!(lowered.get(), 1);
+
+    let result: Result<()> = gated(
+      async { Ok(()) },
+      async { anyhow::bail!("web never became healthy") },
+      lower,
+    )
+    .await;
+    assert_eq!(result.unwrap_err().to_string(), "web never became healthy");
+    assert_eq!(lowered.get(), 2);
+
+    let result = gated(
+      async { anyhow::bail!("compose up failed") },
+      async { Ok(()) },
+      lower,
+    )
+    .await;
+    assert!(result.is_err());
+    assert_eq!(lowered.get(), 3);
+  }
+
+  #[test]
+  fn test_render_gate_compose() {
+    let cfg: HLConfig = serde_yaml::from_str(
+      r#"
+app: testapp
+image: registry.example.com/testapp
+domain: testapp.example.com
+servicePort: 3000
+health:
+  url: http://testapp:3000/healthz
+  gate: true
+"#,
+    )
+    .unwrap();
+    let content = render_gate_compose(&cfg);
+    assert!(content.contains("container_name: testapp-gate"));
+    assert!(content.contains("traefik.http.routers.testapp-gate.rule: Host(`testapp.example.com`)"));
+    assert!(content.contains("return 503 \"release in progress\"; } }'"));
+    assert!(content.contains("networks: [traefik_proxy]"));
+  }
+}
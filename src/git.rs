@@ -1,10 +1,12 @@
-use crate::log::debug;
+use crate::log::{debug, warn};
+use crate::releases::short_sha;
 use anyhow::{Context, Result};
 use regex::Regex;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
@@ -12,26 +14,38 @@ use tokio::process::Command;
 /// Compiled regex for parsing app names from hl git remote URLs.
 static APP_NAME_RE: OnceLock<Regex> = OnceLock::new();
 
-/// Compiled regex for validating app names read from the `HL_APP` env var.
+/// Compiled regex for validating app names (`HL_APP`, backup manifests).
 static VALID_NAME_RE: OnceLock<Regex> = OnceLock::new();
 
 /// Parse an app name from a git remote URL matching the hl convention.
-/// Expects URLs containing `/hl/git/<app>.git`.
+/// Expects URLs containing `/hl/git/<app>.git`, or `<paths.root>/git/<app>.git` when the
+/// global config moves the root.
 pub fn parse_app_name_from_remote_url(url: &str) -> Option<String> {
-  let re = APP_NAME_RE
-    .get_or_init(|| Regex::new(r"/hl/git/([^/]+)\.git\b").expect("APP_NAME_RE is a valid regex"));
+  let re = APP_NAME_RE.get_or_init(|| app_name_regex(crate::config::configured_root().as_deref()));
   re.captures(url).map(|c| c[1].to_string())
 }
 
+/// Regex capturing the app of a remote under `/hl/git/` or `<root>/git/`. A `~/` root matches
+/// under any home directory.
+fn app_name_regex(root: Option<&str>) -> Regex {
+  let mut prefixes = vec![regex::escape("/hl/git/")];
+  if let Some(root) = root {
+    let root = root.trim_start_matches('~').trim_end_matches('/');
+    if !root.is_empty() {
+      prefixes.push(regex::escape(&format!("{}/git/", root)));
+    }
+  }
+  Regex::new(&format!(r"(?:{})([^/]+)\.git\b", prefixes.join("|")))
+    .expect("APP_NAME_RE is a valid regex")
+}
+
 /// Infer the app name from the `HL_APP` env var.
 pub async fn infer_app_name() -> Result<String> {
   // Check HL_APP env var first
   if let Ok(app) = std::env::var("HL_APP") {
     let app = app.trim().to_string();
     if !app.is_empty() {
-      let valid_name_re = VALID_NAME_RE
-        .get_or_init(|| Regex::new(r"^[A-Za-z0-9_-]+$").expect("VALID_NAME_RE is a valid regex"));
-      if !valid_name_re.is_match(&app) {
+      if validate_app_name(&app).is_err() {
         anyhow::bail!(
           "Invalid HL_APP value {:?}. App names may only contain letters, digits, '-' and '_'",
           app
@@ -44,6 +58,19 @@ pub async fn infer_app_name() -> Result<String> {
   anyhow::bail!("HL_APP is not set. Set HL_APP=<app> before running this command.");
 }
 
+/// Check that `app` is a usable app name: it ends up in paths, unit names and commands.
+pub fn validate_app_name(app: &str) -> Result<()> {
+  let valid_name_re = VALID_NAME_RE
+    .get_or_init(|| Regex::new(r"^[A-Za-z0-9_-]+$").expect("VALID_NAME_RE is a valid regex"));
+  if !valid_name_re.is_match(app) {
+    anyhow::bail!(
+      "invalid app name {:?}: only letters, digits, '-' and '_' are allowed",
+      app
+    );
+  }
+  Ok(())
+}
+
 /// Export a git commit to a temporary directory
 ///
 /// This uses `git archive` to stream the commit contents as a tar,
@@ -76,9 +103,109 @@ pub async fn export_commit(repo_path: &str, sha: &str) -> Result<PathBuf> {
   debug(&format!("temp dir base: {}", tmpdir.display()));
 
   let tmpdir = create_temp_dir(&tmpdir, sha).await?;
+  let guard = WorktreeGuard::new(tmpdir.clone());
 
   debug(&format!("created temp dir: {}", tmpdir.display()));
 
+  archive_into(repo_path, sha, &tmpdir).await?;
+  export_submodules(repo_path, sha, &tmpdir).await?;
+  smudge_lfs_files(repo_path, &tmpdir).await?;
+
+  debug(&format!(
+    "successfully exported commit {} to {}",
+    sha,
+    tmpdir.display()
+  ));
+
+  Ok(guard.keep())
+}
+
+/// Removes an exported worktree when dropped, so deploys that fail (or are interrupted
+/// between awaits) don't leave their build context behind.
+pub struct WorktreeGuard(Option<PathBuf>);
+
+impl WorktreeGuard {
+  pub fn new(path: PathBuf) -> Self {
+    Self(Some(path))
+  }
+
+  /// Disarm the guard, handing the worktree over to the caller.
+  pub fn keep(mut self) -> PathBuf {
+    self.0.take().expect("worktree guard already disarmed")
+  }
+}
+
+impl Drop for WorktreeGuard {
+  fn drop(&mut self) {
+    let Some(path) = self.0.take() else {
+      return;
+    };
+    match std::fs::remove_dir_all(&path) {
+      Ok(()) => debug(&format!("removed worktree {}", path.display())),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+      Err(e) => warn(&format!(
+        "failed to clean up worktree at {}: {}",
+        path.display(),
+        e
+      )),
+    }
+  }
+}
+
+/// Whether a temp dir entry is a worktree made by `export_commit` (`hl-<sha7>-[n]`).
+fn is_worktree_name(name: &str) -> bool {
+  let Some((sha, suffix)) = name
+    .strip_prefix("hl-")
+    .and_then(|rest| rest.split_once('-'))
+  else {
+    return false;
+  };
+  !sha.is_empty()
+    && sha.len() <= 7
+    && sha.chars().all(|c| c.is_ascii_alphanumeric())
+    && suffix.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Remove worktrees under `base` (the temp dir) last modified more than `max_age` ago, left
+/// behind by deploys that were killed before they could clean up. Returns how many went.
+pub fn prune_stale_worktrees(base: &Path, max_age: Duration) -> Result<usize> {
+  let now = SystemTime::now();
+  let mut removed = 0;
+  for entry in std::fs::read_dir(base)? {
+    let entry = entry?;
+    let name = entry.file_name();
+    if !entry.file_type()?.is_dir() || !is_worktree_name(&name.to_string_lossy()) {
+      continue;
+    }
+    let age = entry
+      .metadata()?
+      .modified()
+      .ok()
+      .and_then(|modified| now.duration_since(modified).ok())
+      .unwrap_or_default();
+    if age < max_age {
+      continue;
+    }
+    match std::fs::remove_dir_all(entry.path()) {
+      Ok(()) => {
+        debug(&format!(
+          "removed stale worktree {}",
+          entry.path().display()
+        ));
+        removed += 1;
+      }
+      Err(e) => warn(&format!(
+        "failed to remove stale worktree {}: {}",
+        entry.path().display(),
+        e
+      )),
+    }
+  }
+  Ok(removed)
+}
+
+/// Stream `git archive <sha>` of the repository at `repo_path` into `tmpdir` through `tar -x`.
+async fn archive_into(repo_path: &str, sha: &str, tmpdir: &Path) -> Result<()> {
   // Spawn git archive process
   debug(&format!(
     "spawning git archive command: git --git-dir {} archive {}",
@@ -106,7 +233,7 @@ pub async fn export_commit(repo_path: &str, sha: &str) -> Result<PathBuf> {
 
   let mut tar_extract = Command::new("tar")
     .arg("-xC")
-    .arg(&tmpdir)
+    .arg(tmpdir)
     .stdin(Stdio::piped())
     .stdout(Stdio::inherit())
     .stderr(Stdio::inherit())
@@ -170,18 +297,287 @@ pub async fn export_commit(repo_path: &str, sha: &str) -> Result<PathBuf> {
     );
   }
 
+  Ok(())
+}
+
+/// A submodule as declared in `.gitmodules`.
+#[derive(Debug, PartialEq)]
+pub struct Submodule {
+  pub name: String,
+  pub path: String,
+  pub url: String,
+}
+
+/// Submodules declared in a `.gitmodules` file.
+pub fn parse_gitmodules(content: &str) -> Vec<Submodule> {
+  let mut modules = Vec::new();
+  let mut current: Option<(String, Option<String>, Option<String>)> = None;
+  let mut flush = |current: Option<(String, Option<String>, Option<String>)>| {
+    if let Some((name, Some(path), Some(url))) = current {
+      modules.push(Submodule { name, path, url });
+    }
+  };
+  for line in content.lines() {
+    let line = line.trim();
+    if line.starts_with('#') || line.starts_with(';') || line.is_empty() {
+      continue;
+    }
+    if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+      flush(current.take());
+      current = section
+        .strip_prefix("submodule")
+        .map(|name| name.trim().trim_matches('"').to_string())
+        .map(|name| (name, None, None));
+      continue;
+    }
+    let (Some(module), Some((key, value))) = (current.as_mut(), line.split_once('=')) else {
+      continue;
+    };
+    let value = value.trim().trim_matches('"').to_string();
+    match key.trim() {
+      "path" => module.1 = Some(value),
+      "url" => module.2 = Some(value),
+      _ => {}
+    }
+  }
+  flush(current);
+  modules
+}
+
+/// `git archive` leaves submodules as empty directories, so fill each one in from a mirror of
+/// its repository kept next to the app's bare repo (`<repo>/modules/<name>`), fetched only
+/// when the pinned commit isn't there yet. Nested submodules are handled the same way.
+async fn export_submodules(repo_path: &str, sha: &str, target: &Path) -> Result<()> {
+  let gitmodules = target.join(".gitmodules");
+  if !gitmodules.exists() {
+    return Ok(());
+  }
+  let modules = parse_gitmodules(&fs::read_to_string(&gitmodules).await?);
+  for module in modules {
+    let Some(module_sha) = gitlink_sha(repo_path, sha, &module.path).await? else {
+      debug(&format!(
+        "submodule {} is not in commit {}; skipping",
+        module.name, sha
+      ));
+      continue;
+    };
+    if !module.url.contains(':') && !module.url.starts_with('/') {
+      anyhow::bail!(
+        "submodule {} has the relative url {:?}, which can't be resolved on the server; \
+         use an absolute url in .gitmodules",
+        module.name,
+        module.url
+      );
+    }
+    let mirror = Path::new(repo_path)
+      .join("modules")
+      .join(module.name.replace('/', "_"));
+    let mirror = mirror.to_string_lossy().to_string();
+    fetch_submodule(&module, &mirror, &module_sha).await?;
+
+    let module_dir = target.join(&module.path);
+    fs::create_dir_all(&module_dir).await?;
+    debug(&format!(
+      "exporting submodule {} at {} into {}",
+      module.name,
+      module_sha,
+      module_dir.display()
+    ));
+    archive_into(&mirror, &module_sha, &module_dir).await?;
+    Box::pin(export_submodules(&mirror, &module_sha, &module_dir)).await?;
+  }
+  Ok(())
+}
+
+/// Commit a submodule at `path` is pinned to in `sha`, or None if the path isn't a submodule.
+async fn gitlink_sha(repo_path: &str, sha: &str, path: &str) -> Result<Option<String>> {
+  let output = Command::new("git")
+    .args(["--git-dir", repo_path, "ls-tree", sha, "--", path])
+    .stderr(Stdio::inherit())
+    .output()
+    .await
+    .context("Failed to run git ls-tree")?;
+  if !output.status.success() {
+    anyhow::bail!("git ls-tree failed with status: {}", output.status);
+  }
+  // <mode> commit <sha>\t<path>
+  Ok(
+    String::from_utf8_lossy(&output.stdout)
+      .split_whitespace()
+      .collect::<Vec<_>>()
+      .windows(3)
+      .find(|w| w[0] == "160000" && w[1] == "commit")
+      .map(|w| w[2].to_string()),
+  )
+}
+
+/// Make sure the submodule's mirror at `mirror` has `sha`, cloning or fetching as needed.
+async fn fetch_submodule(module: &Submodule, mirror: &str, sha: &str) -> Result<()> {
+  // The url comes from the pushed .gitmodules; never let it be read as an option
+  if module.url.starts_with('-') {
+    anyhow::bail!(
+      "refusing to fetch submodule {} from {:?}",
+      module.name,
+      module.url
+    );
+  }
+  let has_commit = |mirror: String| async move {
+    Command::new("git")
+      .args([
+        "--git-dir",
+        &mirror,
+        "cat-file",
+        "-e",
+        &format!("{}^{{commit}}", sha),
+      ])
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .status()
+      .await
+      .is_ok_and(|s| s.success())
+  };
+  if has_commit(mirror.to_string()).await {
+    return Ok(());
+  }
+
+  let args: Vec<&str> = if Path::new(mirror).exists() {
+    vec!["--git-dir", mirror, "fetch", "--quiet", "--prune", "origin"]
+  } else {
+    vec!["clone", "--quiet", "--mirror", "--", &module.url, mirror]
+  };
   debug(&format!(
-    "successfully exported commit {} to {}",
-    sha,
-    tmpdir.display()
+    "fetching submodule {}: git {}",
+    module.name,
+    args.join(" ")
   ));
+  let status = Command::new("git")
+    .args(&args)
+    .stdin(Stdio::null())
+    .stderr(Stdio::inherit())
+    .status()
+    .await
+    .context("Failed to run git")?;
+  if !status.success() {
+    anyhow::bail!(
+      "failed to fetch submodule {} from {}; make sure this host can read it \
+       (e.g. add a deploy key for it to the server's ssh config)",
+      module.name,
+      module.url
+    );
+  }
+  if !has_commit(mirror.to_string()).await {
+    anyhow::bail!(
+      "submodule {} is pinned to {}, which {} doesn't have; push that commit to the \
+       submodule's remote first",
+      module.name,
+      sha,
+      module.url
+    );
+  }
+  Ok(())
+}
+
+/// First line of every Git LFS pointer file.
+const LFS_POINTER_HEADER: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// Whether a `.gitattributes` file routes any paths through the LFS filter.
+pub fn uses_lfs(gitattributes: &str) -> bool {
+  gitattributes.lines().any(|line| {
+    !line.trim_start().starts_with('#') && line.split_whitespace().any(|a| a == "filter=lfs")
+  })
+}
+
+/// Object id of an LFS pointer file, or None if `content` isn't one.
+pub fn parse_lfs_pointer(content: &[u8]) -> Option<String> {
+  // Pointers are tiny; anything bigger is real content
+  if content.len() > 1024 {
+    return None;
+  }
+  let text = std::str::from_utf8(content).ok()?;
+  let mut lines = text.lines();
+  if lines.next()? != LFS_POINTER_HEADER {
+    return None;
+  }
+  lines
+    .find_map(|line| line.strip_prefix("oid sha256:"))
+    .map(str::to_string)
+}
+
+/// `git archive` exports LFS-tracked files as pointers. Replace each with its content via
+/// `git lfs smudge`, which reads objects pushed to the bare repo (`<repo>/lfs/objects`) and
+/// otherwise downloads them from the repository's LFS server.
+async fn smudge_lfs_files(repo_path: &str, target: &Path) -> Result<()> {
+  let attributes = fs::read_to_string(target.join(".gitattributes"))
+    .await
+    .unwrap_or_default();
+  if !uses_lfs(&attributes) {
+    return Ok(());
+  }
+  let pointers = find_lfs_pointers(target)?;
+  if pointers.is_empty() {
+    return Ok(());
+  }
+  debug(&format!("smudging {} LFS file(s)", pointers.len()));
+
+  for path in pointers {
+    let relative = path.strip_prefix(target).unwrap_or(&path);
+    let pointer = std::fs::File::open(&path)?;
+    let output = Command::new("git")
+      .args(["lfs", "smudge", "--"])
+      .arg(relative)
+      .env("GIT_DIR", repo_path)
+      .current_dir(target)
+      .stdin(Stdio::from(pointer))
+      .stderr(Stdio::piped())
+      .output()
+      .await
+      .context("Failed to run git lfs")?;
+    if !output.status.success() {
+      let stderr = String::from_utf8_lossy(&output.stderr);
+      if stderr.contains("'lfs' is not a git command") {
+        anyhow::bail!(
+          "{} is stored in Git LFS but git-lfs is not installed on this host; \
+           install it (e.g. apt install git-lfs) and redeploy",
+          relative.display()
+        );
+      }
+      anyhow::bail!(
+        "git lfs smudge failed for {} ({}); push the LFS objects with the commit \
+         (git lfs push --all <remote>) or set lfs.url in the bare repo's config",
+        relative.display(),
+        stderr.trim()
+      );
+    }
+    fs::write(&path, &output.stdout).await?;
+  }
+  Ok(())
+}
 
-  Ok(tmpdir)
+/// Every LFS pointer file under `dir`.
+fn find_lfs_pointers(dir: &Path) -> Result<Vec<PathBuf>> {
+  let mut pointers = Vec::new();
+  let mut pending = vec![dir.to_path_buf()];
+  while let Some(dir) = pending.pop() {
+    for entry in std::fs::read_dir(&dir)? {
+      let entry = entry?;
+      let file_type = entry.file_type()?;
+      if file_type.is_dir() {
+        pending.push(entry.path());
+      } else if file_type.is_file() && entry.metadata()?.len() <= 1024 {
+        let content = std::fs::read(entry.path())?;
+        if parse_lfs_pointer(&content).is_some() {
+          pointers.push(entry.path());
+        }
+      }
+    }
+  }
+  pointers.sort();
+  Ok(pointers)
 }
 
 /// Create a unique temporary directory with the given prefix
 async fn create_temp_dir(base: &std::path::Path, sha: &str) -> Result<PathBuf> {
-  let prefix = format!("hl-{}-", &sha[..7.min(sha.len())]);
+  let prefix = format!("hl-{}-", short_sha(sha));
 
   // Try to create temp directory with incrementing suffix
   for i in 0..100 {
@@ -203,6 +599,114 @@ async fn create_temp_dir(base: &std::path::Path, sha: &str) -> Result<PathBuf> {
   anyhow::bail!("Failed to create unique temp directory after 100 attempts")
 }
 
+/// Content of `path` at commit `sha`, or None when the commit has no such file.
+pub async fn file_at_commit(repo_path: &str, sha: &str, path: &str) -> Result<Option<String>> {
+  let output = Command::new("git")
+    .args(["--git-dir", repo_path, "show", &format!("{}:{}", sha, path)])
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .await
+    .context("Failed to run git show")?;
+  if output.status.success() {
+    return Ok(Some(String::from_utf8_lossy(&output.stdout).to_string()));
+  }
+  let has_commit = Command::new("git")
+    .args([
+      "--git-dir",
+      repo_path,
+      "cat-file",
+      "-e",
+      &format!("{}^{{commit}}", sha),
+    ])
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+    .await
+    .context("Failed to run git cat-file")?
+    .success();
+  if !has_commit {
+    anyhow::bail!("commit {} not found in {}", sha, repo_path);
+  }
+  Ok(None)
+}
+
+/// Whether `sha` is a full or abbreviated commit SHA.
+pub fn is_commit_sha(sha: &str) -> bool {
+  (4..=64).contains(&sha.len()) && sha.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Whether `name` is usable as a branch or tag name: nothing git or a command line would read
+/// as an option, a range or a refspec.
+pub fn is_ref_name(name: &str) -> bool {
+  !name.is_empty()
+    && !name.starts_with('-')
+    && !name.contains("..")
+    && !name
+      .chars()
+      .any(|c| c.is_whitespace() || c.is_control() || ":~^?*[\\".contains(c))
+}
+
+/// Full SHA of the commit `rev` names in `repo_path` (peeling tags), or None when the
+/// repository doesn't have it.
+pub async fn resolve_commit(repo_path: &str, rev: &str) -> Result<Option<String>> {
+  if rev.starts_with('-') {
+    anyhow::bail!("invalid revision {:?}", rev);
+  }
+  let output = Command::new("git")
+    .args([
+      "--git-dir",
+      repo_path,
+      "rev-parse",
+      "--verify",
+      "--quiet",
+      "--end-of-options",
+      &format!("{}^{{commit}}", rev),
+    ])
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .await
+    .context("Failed to run git rev-parse")?;
+  Ok(
+    output
+      .status
+      .success()
+      .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string()),
+  )
+}
+
+/// Fetch `refspec` (a `+src:dst` refspec or a commit SHA) from `remote` into `repo_path`.
+pub async fn fetch_into(repo_path: &str, remote: &str, refspec: &str) -> Result<()> {
+  // Both may come from a webhook payload; never let them be read as options
+  if remote.starts_with('-') || refspec.starts_with('-') {
+    anyhow::bail!("refusing to fetch {:?} from {:?}", refspec, remote);
+  }
+  debug(&format!(
+    "fetching {} from {} into {}",
+    refspec, remote, repo_path
+  ));
+  let status = Command::new("git")
+    .args([
+      "--git-dir",
+      repo_path,
+      "fetch",
+      "--quiet",
+      "--no-tags",
+      "--",
+      remote,
+      refspec,
+    ])
+    .stdin(Stdio::null())
+    .status()
+    .await
+    .context("Failed to run git fetch")?;
+  if !status.success() {
+    anyhow::bail!("git fetch {} {} failed ({})", remote, refspec, status);
+  }
+  Ok(())
+}
+
 /// Generate the SSH URI for a git repository
 /// Given the git directory path, constructs an SSH URI
 /// using the current user's username and the system's hostname.
@@ -224,7 +728,9 @@ pub fn repo_remote_uri(git_dir: &str) -> String {
 /// Initialize a bare git repository with a post-receive hook
 ///
 /// Creates a bare git repository at the specified path and installs a post-receive
-/// hook that triggers `hl deploy` when commits are pushed.
+/// hook that triggers `hl deploy` when commits are pushed. Pushing a tag deploys the tagged
+/// commit and additionally tags the image with the version; `deploy.tags` in hl.yml decides
+/// which pushes actually deploy.
 ///
 /// # Arguments
 /// * `git_dir` - Path where the bare repository should be created
@@ -261,23 +767,41 @@ pub async fn init_bare_repo(git_dir: &Path, app_name: &str, home_dir: &str) -> R
 
   debug("bare git repository initialized successfully");
 
-  // Create post-receive hook
-  let hooks_dir = git_dir.join("hooks");
-  let hook_path = hooks_dir.join("post-receive");
+  write_post_receive_hook(git_dir, app_name, home_dir).await
+}
 
-  let hook_content = format!(
+/// The post-receive hook `hl init` installs: every pushed branch or tag runs `hl deploy`
+/// through the binary in `home_dir`, which applies hl.yml's `deploy:` filters.
+pub fn post_receive_hook(app_name: &str, home_dir: &str) -> String {
+  format!(
     r#"#!/usr/bin/env bash
 set -euo pipefail
 while read -r oldrev newrev refname; do
-  case "$refname" in refs/heads/*) branch="${{refname#refs/heads/}}";;
+  case "$refname" in
+    refs/heads/*)
+      branch="${{refname#refs/heads/}}"
+      HL_APP={app} {home}/.local/bin/hl deploy --sha "$newrev" --branch "$branch" --ref "$refname"
+      ;;
+    refs/tags/*)
+      # Skip tag deletions; peel annotated tags to the commit they point at
+      [ "$newrev" = "0000000000000000000000000000000000000000" ] && continue
+      tag="${{refname#refs/tags/}}"
+      sha="$(git rev-parse "$newrev^{{commit}}")"
+      HL_APP={app} {home}/.local/bin/hl deploy --sha "$sha" --tag "$tag" --ref "$refname"
+      ;;
     *) continue;;
   esac
-  HL_APP={} {}/.local/bin/hl deploy --sha "$newrev" --branch "$branch"
 done
 "#,
-    app_name, home_dir
-  );
+    app = app_name,
+    home = home_dir
+  )
+}
 
+/// Write (or overwrite) a bare repository's post-receive hook from the current template.
+pub async fn write_post_receive_hook(git_dir: &Path, app_name: &str, home_dir: &str) -> Result<()> {
+  let hook_path = git_dir.join("hooks").join("post-receive");
+  let hook_content = post_receive_hook(app_name, home_dir);
   fs::write(&hook_path, hook_content)
     .await
     .context("Failed to write post-receive hook")?;
@@ -299,6 +823,32 @@ done
   Ok(())
 }
 
+/// Whether a bare repository's installed post-receive hook matches the current template.
+pub fn post_receive_hook_is_current(git_dir: &Path, app_name: &str, home_dir: &str) -> bool {
+  std::fs::read_to_string(git_dir.join("hooks").join("post-receive"))
+    .is_ok_and(|installed| installed == post_receive_hook(app_name, home_dir))
+}
+
+/// Apps that have a bare repository on this host, by repository name.
+pub fn list_bare_repos() -> Result<Vec<String>> {
+  let dir = crate::config::hl_git_dir();
+  if !dir.exists() {
+    return Ok(Vec::new());
+  }
+  let mut apps = Vec::new();
+  for entry in std::fs::read_dir(&dir)? {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().to_string();
+    if let Some(app) = name.strip_suffix(".git") {
+      if entry.path().join("HEAD").exists() {
+        apps.push(app.to_string());
+      }
+    }
+  }
+  apps.sort();
+  Ok(apps)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -316,6 +866,105 @@ mod tests {
     tokio::fs::remove_dir(&tmpdir).await.ok();
   }
 
+  #[test]
+  fn test_prune_stale_worktrees() {
+    assert!(is_worktree_name("hl-abc1234-"));
+    assert!(is_worktree_name("hl-abc1234-12"));
+    assert!(!is_worktree_name("hl-abc1234"));
+    assert!(!is_worktree_name("hl-registry-x"));
+
+    let base = tempfile::TempDir::new().unwrap();
+    for name in ["hl-abc1234-", "hl-def5678-1", "hl-keep", "other"] {
+      std::fs::create_dir(base.path().join(name)).unwrap();
+    }
+    std::fs::write(base.path().join("hl-abc1234-/Dockerfile"), "FROM scratch").unwrap();
+
+    // Fresh worktrees may belong to a deploy in progress
+    assert_eq!(
+      prune_stale_worktrees(base.path(), Duration::from_secs(3600)).unwrap(),
+      0
+    );
+    assert_eq!(
+      prune_stale_worktrees(base.path(), Duration::ZERO).unwrap(),
+      2
+    );
+    assert!(!base.path().join("hl-abc1234-").exists());
+    assert!(base.path().join("hl-keep").exists());
+    assert!(base.path().join("other").exists());
+
+    let guarded = base.path().join("hl-0000000-");
+    std::fs::create_dir(&guarded).unwrap();
+    drop(WorktreeGuard::new(guarded.clone()));
+    assert!(!guarded.exists());
+  }
+
+  #[test]
+  fn test_parse_gitmodules() {
+    let modules = parse_gitmodules(
+      r#"[submodule "vendor/theme"]
+	path = vendor/theme
+	url = git@github.com:acme/theme.git
+# a comment
+[submodule "docs"]
+	url = https://github.com/acme/docs.git
+	path = docs
+	branch = main
+[core]
+	path = ignored
+"#,
+    );
+    assert_eq!(
+      modules,
+      vec![
+        Submodule {
+          name: "vendor/theme".to_string(),
+          path: "vendor/theme".to_string(),
+          url: "git@github.com:acme/theme.git".to_string(),
+        },
+        Submodule {
+          name: "docs".to_string(),
+          path: "docs".to_string(),
+          url: "https://github.com/acme/docs.git".to_string(),
+        },
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_fetch_submodule_rejects_option_url() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let mirror = temp_dir.path().join("evil.git");
+    let module = Submodule {
+      name: "evil".to_string(),
+      path: "evil".to_string(),
+      url: format!(
+        "--upload-pack=touch {}",
+        temp_dir.path().join("pwn").display()
+      ),
+    };
+    let result = fetch_submodule(&module, &mirror.display().to_string(), "0123abcd").await;
+    assert!(result
+      .unwrap_err()
+      .to_string()
+      .contains("refusing to fetch"));
+    assert!(!temp_dir.path().join("pwn").exists());
+  }
+
+  #[test]
+  fn test_lfs_detection() {
+    assert!(uses_lfs("*.psd filter=lfs diff=lfs merge=lfs -text\n"));
+    assert!(!uses_lfs("# *.psd filter=lfs\n*.sh text eol=lf\n"));
+
+    let pointer = b"version https://git-lfs.github.com/spec/v1\n\
+oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+size 12345\n";
+    assert_eq!(
+      parse_lfs_pointer(pointer).as_deref(),
+      Some("4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393")
+    );
+    assert_eq!(parse_lfs_pointer(b"just a file\n"), None);
+  }
+
   #[tokio::test]
   async fn test_init_bare_repo() {
     use std::os::unix::fs::PermissionsExt;
@@ -348,15 +997,28 @@ mod tests {
       r#"#!/usr/bin/env bash
 set -euo pipefail
 while read -r oldrev newrev refname; do
-  case "$refname" in refs/heads/*) branch="${{refname#refs/heads/}}";;
+  case "$refname" in
+    refs/heads/*)
+      branch="${{refname#refs/heads/}}"
+      HL_APP={app} {home}/.local/bin/hl deploy --sha "$newrev" --branch "$branch" --ref "$refname"
+      ;;
+    refs/tags/*)
+      # Skip tag deletions; peel annotated tags to the commit they point at
+      [ "$newrev" = "0000000000000000000000000000000000000000" ] && continue
+      tag="${{refname#refs/tags/}}"
+      sha="$(git rev-parse "$newrev^{{commit}}")"
+      HL_APP={app} {home}/.local/bin/hl deploy --sha "$sha" --tag "$tag" --ref "$refname"
+      ;;
     *) continue;;
   esac
-  HL_APP={} {}/.local/bin/hl deploy --sha "$newrev" --branch "$branch"
 done
 "#,
-      app_name, home_dir
+      app = app_name,
+      home = home_dir
     );
     assert_eq!(hook_contents, expected_hook);
+    assert!(post_receive_hook_is_current(&git_dir, app_name, home_dir));
+    assert!(!post_receive_hook_is_current(&git_dir, "renamed", home_dir));
 
     // Cleanup
     tokio::fs::remove_dir_all(&git_dir).await.ok();
@@ -392,6 +1054,28 @@ done
     );
   }
 
+  #[test]
+  fn test_app_name_regex_with_configured_root() {
+    let re = app_name_regex(Some("/srv/data/"));
+    let app = |url: &str| re.captures(url).map(|c| c[1].to_string());
+    assert_eq!(
+      app("deploy@host:/srv/data/git/blog.git").as_deref(),
+      Some("blog")
+    );
+    assert_eq!(
+      app("ssh://u@h/home/u/hl/git/shop.git").as_deref(),
+      Some("shop")
+    );
+    assert_eq!(app("ssh://u@h/srv/other/git/blog.git"), None);
+
+    let re = app_name_regex(Some("~/data"));
+    assert_eq!(
+      re.captures("u@h:/home/u/data/git/blog.git")
+        .map(|c| c[1].to_string()),
+      Some("blog".to_string())
+    );
+  }
+
   #[test]
   fn test_parse_app_name_from_remote_url_with_dashes() {
     let url = "ssh://u@h/home/u/hl/git/my-cool-app.git";
@@ -409,6 +1093,14 @@ done
     assert_eq!(result.unwrap(), "envapp");
   }
 
+  #[test]
+  fn test_validate_app_name() {
+    assert!(validate_app_name("my-app_2").is_ok());
+    for bad in ["", "../etc", "a b", "app;rm -rf /", "-x/y"] {
+      assert!(validate_app_name(bad).is_err(), "{}", bad);
+    }
+  }
+
   #[tokio::test]
   async fn test_infer_app_name_empty_env_falls_through() {
     std::env::set_var("HL_APP", "");
@@ -1,78 +1,415 @@
-use crate::config::{parse_duration, HLConfig};
-use anyhow::Result;
+use crate::config::{parse_duration, HLConfig, HealthConfig};
+use crate::docker::{container_in_network, container_logs_tail, resolve_in_network};
+use crate::error::Error;
+use crate::log::{debug, trace, warn};
+use anyhow::{Context, Result};
+use rand::Rng;
+use regex::Regex;
 use std::process::Stdio;
 use std::time::{Duration, Instant};
 use tokio::{process::Command, time::sleep};
 
+/// Longest wait between probes once backoff has grown (unless `health.interval` is longer).
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Container log lines shown when a health wait gives up.
+const LOG_TAIL_LINES: usize = 50;
+
 pub async fn wait_for_healthy(cfg: &HLConfig) -> Result<()> {
+  wait_for_healthy_at(cfg, &cfg.health.url).await
+}
+
+/// Like `wait_for_healthy`, but probes `url` instead of `health.url` (e.g. a canary container)
+/// using the app's network, interval and timeout.
+pub async fn wait_for_healthy_at(cfg: &HLConfig, url: &str) -> Result<()> {
   let network = &cfg.network;
-  let url = &cfg.health.url;
   let timeout = &cfg.health.timeout;
   let interval = &cfg.health.interval;
   let timeout_ms = parse_duration(timeout)?;
   let interval_ms = parse_duration(interval)?;
   let timeout_duration = Duration::from_millis(timeout_ms);
   let interval_duration = Duration::from_millis(interval_ms);
+  let expectation = HealthExpectation::from_config(&cfg.health)?;
+  let client = reqwest::Client::builder()
+    .timeout(Duration::from_secs(3))
+    .redirect(reqwest::redirect::Policy::none())
+    .build()?;
   let start = Instant::now();
 
-  while start.elapsed() < timeout_duration {
-    if curl_in_network(network, url).await {
-      return Ok(());
+  // Probe natively at the container's IP; a curl container on the network is the fallback
+  // for hosts that can't reach container IPs (e.g. Docker Desktop) or urls docker can't resolve
+  let mut via_container = false;
+  let mut attempts = 0;
+  let last = loop {
+    attempts += 1;
+    let response = if via_container {
+      curl_in_network(network, url).await
+    } else {
+      match fetch_native(&client, network, url).await {
+        NativeProbe::Response(status, body) => Ok((status, body)),
+        NativeProbe::Unresolved => curl_in_network(network, url).await,
+        // The container answered, it just isn't listening yet
+        NativeProbe::NotReady(error) => Err(error),
+        NativeProbe::Unreachable(error) => match curl_in_network(network, url).await {
+          Ok(response) => {
+            debug("container IPs are unreachable from this host; probing from a container");
+            via_container = true;
+            Ok(response)
+          }
+          // Both failed: the app isn't answering yet, and the native error says more
+          Err(_) => Err(error),
+        },
+      }
+    };
+    let reason = match response {
+      Ok((status, body)) => match expectation.check(status, &body) {
+        Ok(()) => return Ok(()),
+        Err(reason) => reason,
+      },
+      Err(reason) => reason,
+    };
+    debug(&format!("health probe {} of {}: {}", attempts, url, reason));
+
+    let remaining = timeout_duration.saturating_sub(start.elapsed());
+    if remaining.is_zero() {
+      break reason;
+    }
+    sleep(jittered(backoff_delay(interval_duration, attempts)).min(remaining)).await;
+  };
+
+  dump_container_logs(network, url).await;
+  Err(
+    Error::Health(anyhow::anyhow!(
+      "health check of {} timed out after {} attempts in {}: {}",
+      url,
+      attempts,
+      timeout,
+      last
+    ))
+    .into(),
+  )
+}
+
+/// Wait before probe `attempt + 1`: `interval`, doubling with every failed attempt up to
+/// `MAX_BACKOFF`, so slow boots aren't hammered while fast ones still pass quickly.
+fn backoff_delay(interval: Duration, attempt: u32) -> Duration {
+  let cap = interval.max(MAX_BACKOFF);
+  interval
+    .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+    .min(cap)
+}
+
+/// `delay` +/- 20%, so several waits started together don't probe in lockstep.
+fn jittered(delay: Duration) -> Duration {
+  delay.mul_f64(rand::rng().random_range(0.8..1.2))
+}
+
+/// Show the tail of the probed container's logs, which usually says why it never got healthy.
+async fn dump_container_logs(network: &str, url: &str) {
+  let Some(host) = reqwest::Url::parse(url)
+    .ok()
+    .and_then(|u| u.host_str().map(str::to_string))
+  else {
+    return;
+  };
+  let Some(container) = container_in_network(network, &host).await else {
+    return;
+  };
+  let logs = container_logs_tail(&container, LOG_TAIL_LINES).await;
+  if logs.trim().is_empty() {
+    return;
+  }
+  warn(&format!(
+    "last {} log lines of {}:",
+    LOG_TAIL_LINES, container
+  ));
+  for line in logs.lines() {
+    eprintln!("  {}", line);
+  }
+}
+
+/// What a health response must look like to pass, from hl.yml `health:`.
+#[derive(Debug)]
+pub struct HealthExpectation {
+  /// Inclusive ranges; empty accepts anything below 400, like `curl -f`
+  statuses: Vec<(u16, u16)>,
+  body: Option<String>,
+  body_regex: Option<Regex>,
+}
+
+impl HealthExpectation {
+  pub fn from_config(health: &HealthConfig) -> Result<Self> {
+    Ok(Self {
+      statuses: match &health.status {
+        Some(spec) => parse_status_spec(spec)?,
+        None => Vec::new(),
+      },
+      body: health.body.clone(),
+      body_regex: health
+        .body_regex
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .context("invalid health.bodyRegex")?,
+    })
+  }
+
+  /// Ok, or why the response doesn't count as healthy.
+  pub fn check(&self, status: u16, body: &str) -> std::result::Result<(), String> {
+    let status_ok = if self.statuses.is_empty() {
+      status < 400
+    } else {
+      self
+        .statuses
+        .iter()
+        .any(|(low, high)| (*low..=*high).contains(&status))
+    };
+    if !status_ok {
+      return Err(format!("status {}", status));
+    }
+    if let Some(text) = &self.body {
+      if !body.contains(text.as_str()) {
+        return Err(format!("status {}, body without {:?}", status, text));
+      }
+    }
+    if let Some(re) = &self.body_regex {
+      if !re.is_match(body) {
+        return Err(format!(
+          "status {}, body not matching {:?}",
+          status,
+          re.as_str()
+        ));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// `200-299`, `204` or `200,204,301-302` -> inclusive ranges.
+pub fn parse_status_spec(spec: &str) -> Result<Vec<(u16, u16)>> {
+  spec
+    .split(',')
+    .map(|part| {
+      let part = part.trim();
+      let (low, high) = part.split_once('-').unwrap_or((part, part));
+      let parse = |code: &str| -> Result<u16> {
+        match code.trim().parse::<u16>() {
+          Ok(code) if (100..=599).contains(&code) => Ok(code),
+          _ => anyhow::bail!(
+            "invalid health.status {:?}: expected codes like 200-299",
+            spec
+          ),
+        }
+      };
+      let (low, high) = (parse(low)?, parse(high)?);
+      if low > high {
+        anyhow::bail!(
+          "invalid health.status {:?}: {} is an empty range",
+          spec,
+          part
+        );
+      }
+      Ok((low, high))
+    })
+    .collect()
+}
+
+enum NativeProbe {
+  Response(u16, String),
+  /// The url's host isn't a container on the network (or the url isn't plain http)
+  Unresolved,
+  /// The container's IP refused the connection or broke it off: the app isn't up yet
+  NotReady(String),
+  /// The container's IP timed out or has no route from this host
+  Unreachable(String),
+}
+
+/// Fetch `url` from this host, with its hostname swapped for the container's IP on `network`.
+async fn fetch_native(client: &reqwest::Client, network: &str, url: &str) -> NativeProbe {
+  let Some(host) = reqwest::Url::parse(url)
+    .ok()
+    .filter(|u| u.scheme() == "http")
+    .and_then(|u| u.host_str().map(str::to_string))
+  else {
+    return NativeProbe::Unresolved;
+  };
+  let ip = match host.parse::<std::net::IpAddr>() {
+    Ok(_) => host.clone(),
+    Err(_) => match resolve_in_network(network, &host).await {
+      Some(ip) => ip,
+      None => return NativeProbe::Unresolved,
+    },
+  };
+  let Some((native_url, host_header)) = native_url(url, &ip) else {
+    return NativeProbe::Unresolved;
+  };
+  trace(&format!("GET {} (Host: {})", native_url, host_header));
+  let response = client
+    .get(native_url)
+    .header(reqwest::header::HOST, host_header)
+    .send()
+    .await;
+  match response {
+    Ok(resp) => {
+      let status = resp.status().as_u16();
+      NativeProbe::Response(status, resp.text().await.unwrap_or_default())
+    }
+    Err(e) if is_unreachable(e.is_timeout(), io_error_kind(&e)) => {
+      NativeProbe::Unreachable(e.to_string())
+    }
+    Err(e) => NativeProbe::NotReady(e.to_string()),
+  }
+}
+
+/// Whether a failed native request means this host can't reach container IPs (timeouts, no
+/// route), as opposed to a container that is reachable but not serving yet (connection refused).
+fn is_unreachable(timeout: bool, kind: Option<std::io::ErrorKind>) -> bool {
+  use std::io::ErrorKind;
+  timeout
+    || matches!(
+      kind,
+      Some(ErrorKind::TimedOut | ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable)
+    )
+}
+
+/// Kind of the I/O error underneath a request error, if one caused it.
+fn io_error_kind(error: &reqwest::Error) -> Option<std::io::ErrorKind> {
+  let mut source = std::error::Error::source(error);
+  while let Some(e) = source {
+    if let Some(io) = e.downcast_ref::<std::io::Error>() {
+      return Some(io.kind());
     }
-    sleep(interval_duration).await;
+    source = e.source();
   }
+  None
+}
 
-  anyhow::bail!("health check timed out in docker network: {}", url)
+/// `url` pointed at `ip`, and the Host header that keeps it addressed to the original name.
+fn native_url(url: &str, ip: &str) -> Option<(String, String)> {
+  let mut parsed = reqwest::Url::parse(url).ok()?;
+  let host = parsed.host_str()?.to_string();
+  let host_header = match parsed.port() {
+    Some(port) => format!("{}:{}", host, port),
+    None => host,
+  };
+  parsed.set_host(Some(ip)).ok()?;
+  Some((parsed.to_string(), host_header))
 }
 
-async fn curl_in_network(network: &str, url: &str) -> bool {
-  let status = Command::new("docker")
+/// Status and body of `url` fetched from a container on `network`, or curl's error.
+async fn curl_in_network(network: &str, url: &str) -> std::result::Result<(u16, String), String> {
+  let output = Command::new("docker")
     .args([
       "run",
       "--rm",
       "--network",
       network,
       "curlimages/curl:8.16.0",
-      "-fsS",
+      "-sS",
       "-m",
       "3",
+      "-w",
+      "\n%{http_code}",
       url,
     ])
     .stdin(Stdio::null())
-    .stdout(Stdio::null())
-    .stderr(Stdio::null())
-    .status()
-    .await;
-
-  match status {
-    Ok(status) => status.success(),
-    Err(_) => false,
+    .output()
+    .await
+    .map_err(|e| format!("failed to run docker: {}", e))?;
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    return Err(match stderr.lines().rfind(|l| !l.trim().is_empty()) {
+      Some(line) => line.trim().to_string(),
+      None => format!("curl exited with {}", output.status),
+    });
   }
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  stdout
+    .rsplit_once('\n')
+    .and_then(|(body, status)| Some((status.trim().parse().ok()?, body.to_string())))
+    .ok_or_else(|| "unparseable curl output".to_string())
 }
 
-pub async fn wait_for_healthy_http(url: &str, timeout: &str, interval: &str) -> Result<()> {
-  let timeout_ms = parse_duration(timeout)?;
-  let interval_ms = parse_duration(interval)?;
-  let timeout_duration = Duration::from_millis(timeout_ms);
-  let interval_duration = Duration::from_millis(interval_ms);
-  let start = Instant::now();
-  let client = reqwest::Client::builder()
-    .timeout(Duration::from_secs(3))
-    .build()?;
+#[cfg(test)]
+mod tests {
+  use super::*;
 
-  while start.elapsed() < timeout_duration {
-    if ping_http(&client, url).await {
-      return Ok(());
-    }
-    sleep(interval_duration).await;
+  #[test]
+  fn test_health_expectation() {
+    assert_eq!(
+      parse_status_spec("200-299, 304").unwrap(),
+      vec![(200, 299), (304, 304)]
+    );
+    assert!(parse_status_spec("299-200").is_err());
+    assert!(parse_status_spec("ok").is_err());
+
+    let health: HealthConfig = serde_yaml::from_str(
+      "url: http://app:3000/up\nstatus: \"204\"\nbody: ready\nbodyRegex: \"db: (ok|up)\"\n",
+    )
+    .unwrap();
+    let expectation = HealthExpectation::from_config(&health).unwrap();
+    assert!(expectation.check(204, "ready; db: ok").is_ok());
+    assert_eq!(
+      expectation.check(200, "ready; db: ok").unwrap_err(),
+      "status 200"
+    );
+    assert!(expectation.check(204, "db: ok").is_err());
+    assert!(expectation.check(204, "ready; db: down").is_err());
+
+    // Without assertions, anything curl -f would accept passes
+    let health: HealthConfig = serde_yaml::from_str("url: http://app:3000/up\n").unwrap();
+    let expectation = HealthExpectation::from_config(&health).unwrap();
+    assert!(expectation.check(302, "").is_ok());
+    assert!(expectation.check(500, "").is_err());
   }
 
-  anyhow::bail!("health check timed out: {}", url)
-}
+  #[test]
+  fn test_backoff_delay() {
+    let interval = Duration::from_secs(2);
+    assert_eq!(backoff_delay(interval, 1), Duration::from_secs(2));
+    assert_eq!(backoff_delay(interval, 2), Duration::from_secs(4));
+    assert_eq!(backoff_delay(interval, 3), Duration::from_secs(8));
+    assert_eq!(backoff_delay(interval, 4), MAX_BACKOFF);
+    assert_eq!(backoff_delay(interval, 60), MAX_BACKOFF);
+    // An interval above the cap is never shortened
+    let slow = Duration::from_secs(30);
+    assert_eq!(backoff_delay(slow, 3), slow);
+    let delay = jittered(Duration::from_secs(10));
+    assert!(delay >= Duration::from_secs(8) && delay <= Duration::from_secs(12));
+  }
+
+  #[tokio::test]
+  async fn test_native_probe_failures() {
+    use std::io::ErrorKind;
+    assert!(is_unreachable(true, None));
+    assert!(is_unreachable(false, Some(ErrorKind::HostUnreachable)));
+    assert!(!is_unreachable(false, Some(ErrorKind::ConnectionRefused)));
+
+    // A port nothing listens on refuses the connection: not ready, no curl container
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap()
+      .port();
+    let error = reqwest::get(format!("http://127.0.0.1:{}/up", port))
+      .await
+      .unwrap_err();
+    assert_eq!(io_error_kind(&error), Some(ErrorKind::ConnectionRefused));
+    assert!(!is_unreachable(error.is_timeout(), io_error_kind(&error)));
+  }
 
-async fn ping_http(client: &reqwest::Client, url: &str) -> bool {
-  match client.get(url).send().await {
-    Ok(resp) => resp.status().is_success() || resp.status().is_redirection(),
-    Err(_) => false,
+  #[test]
+  fn test_native_url() {
+    assert_eq!(
+      native_url("http://recipes:8080/healthz?full=1", "172.18.0.4"),
+      Some((
+        "http://172.18.0.4:8080/healthz?full=1".to_string(),
+        "recipes:8080".to_string()
+      ))
+    );
+    assert_eq!(
+      native_url("http://recipes/up", "172.18.0.4").unwrap().1,
+      "recipes"
+    );
   }
 }
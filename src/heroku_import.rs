@@ -0,0 +1,244 @@
+use crate::compose_import::{ComposeImport, ImportedAccessory};
+use crate::env::generate_secret;
+use crate::output::from_json;
+use crate::procfile::parse_procfile_content;
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/*
+`hl import heroku` reads a Heroku app's app.json and Procfile into what `hl init
+--from-compose` takes from a compose file. Postgres and Redis add-ons become accessories with
+the `<NAME>_URL` Heroku would have set pointing at them, `env` values go into .env (generated
+for `"generator": "secret"`), and `formation` quantities become process scale. The Procfile's
+`release` command becomes hl.yml's migrations; the other process types are what deploys run.
+ */
+
+/// What `hl import heroku` takes from app.json and the Procfile.
+#[derive(Debug, Default)]
+pub struct HerokuImport {
+  /// app.json `name`
+  pub name: Option<String>,
+  /// Env, accessories and migrations, as init imports them
+  pub import: ComposeImport,
+  /// Procfile process types, without `release`
+  pub processes: Vec<String>,
+  /// Replica count of each process from `formation`
+  pub scale: BTreeMap<String, u32>,
+  /// Required env keys app.json has no value or generator for
+  pub required_env: Vec<String>,
+  /// `scripts.postdeploy`, which Heroku runs once after the first deploy
+  pub postdeploy: Option<String>,
+  /// Add-ons hl has no accessory for
+  pub skipped_addons: Vec<String>,
+}
+
+/// Parse app.json (and the Procfile, if there is one) for `app`.
+pub fn parse_heroku_import(
+  app_json: &str,
+  procfile: Option<&str>,
+  app: &str,
+) -> Result<HerokuImport> {
+  let doc: Value = from_json(app_json).context("not a valid app.json")?;
+  let mut heroku = HerokuImport {
+    name: doc.get("name").and_then(Value::as_str).map(str::to_string),
+    postdeploy: doc
+      .get("scripts")
+      .and_then(|s| s.get("postdeploy"))
+      .and_then(Value::as_str)
+      .map(str::to_string),
+    ..Default::default()
+  };
+  let import = &mut heroku.import;
+  import.web = "web".to_string();
+
+  if let Some(env) = doc.get("env").and_then(Value::as_mapping) {
+    for (key, spec) in env {
+      let key = key.as_str().unwrap_or_default().to_string();
+      let value = match spec {
+        Value::String(value) => Some(value.clone()),
+        Value::Mapping(_) => match (
+          spec.get("value"),
+          spec.get("generator").and_then(Value::as_str),
+        ) {
+          (Some(Value::String(value)), _) => Some(value.clone()),
+          (Some(value @ (Value::Number(_) | Value::Bool(_))), _) => {
+            Some(serde_yaml::to_string(value)?.trim_end().to_string())
+          }
+          // Heroku's secret generator makes 64 hex characters
+          (_, Some("secret")) => Some(generate_secret("hex32")?),
+          _ => None,
+        },
+        _ => None,
+      };
+      match value {
+        Some(value) => {
+          import.env.insert(key, value);
+        }
+        None if spec.get("required").and_then(Value::as_bool) != Some(false) => {
+          heroku.required_env.push(key)
+        }
+        None => {}
+      }
+    }
+  }
+
+  for addon in doc
+    .get("addons")
+    .and_then(Value::as_sequence)
+    .into_iter()
+    .flatten()
+  {
+    let (plan, attachment) = match addon {
+      Value::String(plan) => (plan.as_str(), None),
+      _ => (
+        addon
+          .get("plan")
+          .and_then(Value::as_str)
+          .unwrap_or_default(),
+        addon.get("as").and_then(Value::as_str),
+      ),
+    };
+    let service = plan.split(':').next().unwrap_or_default().to_string();
+    match addon_kind(&service) {
+      Some("postgres") => {
+        let password = generate_secret("alnum32")?;
+        let url_var = format!("{}_URL", attachment.unwrap_or("DATABASE"));
+        import.env.insert(
+          url_var,
+          format!("postgres://{app}:{password}@{app}_pg:5432/{app}"),
+        );
+        import.accessories.push(ImportedAccessory {
+          kind: "postgres",
+          service,
+          version: None,
+          env: [
+            ("POSTGRES_USER".to_string(), app.to_string()),
+            ("POSTGRES_DB".to_string(), app.to_string()),
+            ("POSTGRES_PASSWORD".to_string(), password),
+          ]
+          .into(),
+        });
+      }
+      Some(kind) => {
+        // Adding redis sets REDIS_URL; other attachment names get theirs here
+        if let Some(attachment) = attachment.filter(|a| *a != "REDIS") {
+          import.env.insert(
+            format!("{}_URL", attachment),
+            format!("redis://{}_redis:6379/0", app),
+          );
+        }
+        import.accessories.push(ImportedAccessory {
+          kind,
+          service,
+          version: None,
+          env: BTreeMap::new(),
+        });
+      }
+      None => heroku.skipped_addons.push(service),
+    }
+  }
+
+  let mut processes: Vec<String> = match procfile {
+    Some(content) => {
+      let mut procfile = parse_procfile_content(content)?;
+      import.migrations = procfile.remove("release");
+      procfile.into_keys().collect()
+    }
+    None => Vec::new(),
+  };
+  processes.sort();
+  heroku.processes = processes;
+
+  if let Some(formation) = doc.get("formation").and_then(Value::as_mapping) {
+    for (process, spec) in formation {
+      let quantity = spec
+        .get("quantity")
+        .and_then(Value::as_u64)
+        .and_then(|q| u32::try_from(q).ok());
+      if let (Some(process), Some(quantity)) = (process.as_str(), quantity) {
+        heroku.scale.insert(process.to_string(), quantity);
+      }
+    }
+  }
+  Ok(heroku)
+}
+
+/// Accessory hl manages for a Heroku add-on service.
+fn addon_kind(service: &str) -> Option<&'static str> {
+  match service {
+    "heroku-postgresql" => Some("postgres"),
+    "heroku-redis" | "heroku-keyvalue" | "rediscloud" | "redistogo" => Some("redis"),
+    _ => None,
+  }
+}
+
+/// Read `dir`'s app.json and Procfile.
+pub fn read_heroku_import(dir: &Path, app: &str) -> Result<HerokuImport> {
+  let path = dir.join("app.json");
+  let app_json =
+    std::fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+  let procfile = std::fs::read_to_string(dir.join("Procfile")).ok();
+  parse_heroku_import(&app_json, procfile.as_deref(), app)
+    .with_context(|| format!("failed to import {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const APP_JSON: &str = r#"{
+  "name": "shop",
+  "env": {
+    "RAILS_ENV": "production",
+    "WEB_CONCURRENCY": { "value": 2 },
+    "SECRET_KEY_BASE": { "description": "Rails secret", "generator": "secret" },
+    "STRIPE_KEY": { "description": "Stripe API key", "required": true },
+    "SENTRY_DSN": { "required": false }
+  },
+  "addons": [
+    "heroku-postgresql:essential-0",
+    { "plan": "heroku-redis:mini", "as": "CACHE" },
+    "papertrail"
+  ],
+  "formation": {
+    "web": { "quantity": 1, "size": "basic" },
+    "worker": { "quantity": 2 }
+  },
+  "scripts": { "postdeploy": "bin/rails db:seed" }
+}"#;
+
+  const PROCFILE: &str =
+    "web: bundle exec puma -p $PORT\nworker: bundle exec sidekiq\nrelease: bin/rails db:migrate\n";
+
+  #[test]
+  fn test_parse_heroku_import() {
+    let heroku = parse_heroku_import(APP_JSON, Some(PROCFILE), "shop").unwrap();
+    assert_eq!(heroku.name.as_deref(), Some("shop"));
+    assert_eq!(heroku.processes, vec!["web", "worker"]);
+    assert_eq!(heroku.scale["worker"], 2);
+    assert_eq!(heroku.required_env, vec!["STRIPE_KEY"]);
+    assert_eq!(heroku.postdeploy.as_deref(), Some("bin/rails db:seed"));
+
+    let import = &heroku.import;
+    assert_eq!(import.migrations.as_deref(), Some("bin/rails db:migrate"));
+    assert_eq!(import.env["RAILS_ENV"], "production");
+    assert_eq!(import.env["WEB_CONCURRENCY"], "2");
+    assert_eq!(import.env["SECRET_KEY_BASE"].len(), 64);
+    assert!(!import.env.contains_key("SENTRY_DSN"));
+    assert_eq!(import.env["CACHE_URL"], "redis://shop_redis:6379/0");
+    assert_eq!(heroku.skipped_addons, vec!["papertrail"]);
+
+    let postgres = &import.accessories[0];
+    assert_eq!(postgres.kind, "postgres");
+    assert_eq!(
+      import.env["DATABASE_URL"],
+      format!(
+        "postgres://shop:{}@shop_pg:5432/shop",
+        postgres.env["POSTGRES_PASSWORD"]
+      )
+    );
+    assert_eq!(import.accessories[1].kind, "redis");
+  }
+}
@@ -0,0 +1,347 @@
+use crate::compose_import::{accessory_kind, rewrite_host, ComposeImport, ImportedAccessory};
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/*
+`hl import kamal` reads a Kamal config/deploy.yml into what `hl init --from-compose` takes from
+a compose file: the image (under the registry server), proxy host and app port, healthcheck
+path, builder args, clear env, and postgres/redis accessories. Secret env values come from
+.kamal/secrets when they are written out there; ones Kamal computes (`$(op read ...)`,
+`$VAR`) are left for `hl env set`. Server roles other than web become Procfile suggestions,
+since hl reads processes from the repository's Procfile.
+ */
+
+/// What `hl import kamal` takes from a deploy.yml.
+#[derive(Debug, Default)]
+pub struct KamalImport {
+  /// Kamal `service`, the default app name
+  pub service: String,
+  /// `proxy.host` (Kamal 2) or the first of `proxy.hosts`
+  pub domain: Option<String>,
+  /// Image, port, env, accessories and health path, as init imports them
+  pub import: ComposeImport,
+  /// (role, cmd) of server roles besides web, for the Procfile
+  pub roles: Vec<(String, String)>,
+  /// Hosts deploy.yml deploys to; hl runs the app on this one
+  pub hosts: Vec<String>,
+  /// Secret env keys whose value .kamal/secrets doesn't spell out
+  pub unresolved_secrets: Vec<String>,
+}
+
+/// Parse a deploy.yml for `app` (default: its service); `secrets` is the content of
+/// .kamal/secrets, if there is one.
+pub fn parse_kamal_import(
+  content: &str,
+  secrets: Option<&str>,
+  app: Option<&str>,
+) -> Result<KamalImport> {
+  let doc: Value = serde_yaml::from_str(content).context("not a valid Kamal deploy.yml")?;
+  let service = str_at(&doc, &["service"])
+    .context("deploy.yml has no service")?
+    .to_string();
+  let secrets = secrets.map(parse_secrets).unwrap_or_default();
+
+  let mut kamal = KamalImport {
+    service,
+    domain: str_at(&doc, &["proxy", "host"])
+      .or_else(|| {
+        doc
+          .get("proxy")
+          .and_then(|p| p.get("hosts"))
+          .and_then(Value::as_sequence)
+          .and_then(|hosts| hosts.first())
+          .and_then(Value::as_str)
+      })
+      .map(str::to_string),
+    ..Default::default()
+  };
+  let import = &mut kamal.import;
+  import.web = "web".to_string();
+  import.image =
+    str_at(&doc, &["image"]).map(|image| match str_at(&doc, &["registry", "server"]) {
+      Some(server) if !server.contains("docker.io") => {
+        format!("{}/{}", server.trim_end_matches('/'), image)
+      }
+      _ => image.to_string(),
+    });
+  // Kamal 2 puts the port and healthcheck under proxy, Kamal 1 under healthcheck
+  import.port =
+    u16_at(&doc, &["proxy", "app_port"]).or_else(|| u16_at(&doc, &["healthcheck", "port"]));
+  import.health_path = str_at(&doc, &["proxy", "healthcheck", "path"])
+    .or_else(|| str_at(&doc, &["healthcheck", "path"]))
+    .map(str::to_string);
+  import.build_args = string_map(doc.get("builder").and_then(|b| b.get("args")));
+  import.volumes = string_list(doc.get("volumes"));
+
+  let (env, unresolved) = kamal_env(doc.get("env"), &secrets);
+  import.env = env;
+  kamal.unresolved_secrets = unresolved;
+
+  if let Some(accessories) = doc.get("accessories").and_then(Value::as_mapping) {
+    for (name, accessory) in accessories {
+      let name = name.as_str().unwrap_or_default().to_string();
+      let kind = accessory
+        .get("image")
+        .and_then(Value::as_str)
+        .and_then(accessory_kind);
+      let Some((kind, version)) = kind else {
+        import.skipped.push(name);
+        continue;
+      };
+      let (env, unresolved) = kamal_env(accessory.get("env"), &secrets);
+      kamal.unresolved_secrets.extend(unresolved);
+      import.accessories.push(ImportedAccessory {
+        kind,
+        service: name,
+        version,
+        env,
+      });
+    }
+  }
+  // Kamal names accessory containers <service>-<accessory>, which is what the app connects to
+  let app = app.unwrap_or(&kamal.service);
+  for accessory in &import.accessories {
+    let host = match accessory.kind {
+      "postgres" => format!("{}_pg", app),
+      _ => format!("{}_redis", app),
+    };
+    let container = format!("{}-{}", kamal.service, accessory.service);
+    for value in import.env.values_mut() {
+      *value = rewrite_host(value, &container, &host);
+    }
+  }
+
+  match doc.get("servers") {
+    Some(hosts @ Value::Sequence(_)) => kamal.hosts = string_list(Some(hosts)),
+    Some(Value::Mapping(roles)) => {
+      for (role, spec) in roles {
+        let role = role.as_str().unwrap_or_default().to_string();
+        let hosts = match spec {
+          Value::Mapping(_) => string_list(spec.get("hosts")),
+          other => string_list(Some(other)),
+        };
+        for host in hosts {
+          if !kamal.hosts.contains(&host) {
+            kamal.hosts.push(host);
+          }
+        }
+        if role == "web" {
+          continue;
+        }
+        let cmd = match spec.get("cmd") {
+          Some(Value::Sequence(args)) => args
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(" "),
+          Some(cmd) => cmd.as_str().unwrap_or_default().to_string(),
+          None => String::new(),
+        };
+        kamal.roles.push((role, cmd));
+      }
+    }
+    _ => {}
+  }
+  Ok(kamal)
+}
+
+/// `env.clear` (or a flat env map) plus the `env.secret` keys .kamal/secrets has values for,
+/// and the secret keys it doesn't.
+fn kamal_env(
+  env: Option<&Value>,
+  secrets: &BTreeMap<String, String>,
+) -> (BTreeMap<String, String>, Vec<String>) {
+  let Some(env) = env else {
+    return (BTreeMap::new(), Vec::new());
+  };
+  let nested = env.get("clear").is_some() || env.get("secret").is_some();
+  let mut values = if nested {
+    string_map(env.get("clear"))
+  } else {
+    string_map(Some(env))
+  };
+  let mut unresolved = Vec::new();
+  for key in string_list(env.get("secret")) {
+    // `DB_PASSWORD:MAIN_DB_PASSWORD` reads the secret named after the colon
+    let (name, secret) = key.split_once(':').unwrap_or((&key, &key));
+    match secrets.get(secret) {
+      Some(value) => {
+        values.insert(name.to_string(), value.clone());
+      }
+      None => unresolved.push(name.to_string()),
+    }
+  }
+  (values, unresolved)
+}
+
+/// `KEY=value` lines of .kamal/secrets whose value is written out; values Kamal computes with
+/// `$(...)` or `$VAR` are skipped.
+fn parse_secrets(content: &str) -> BTreeMap<String, String> {
+  content
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    .filter_map(|line| line.split_once('='))
+    .map(|(key, value)| {
+      let value = value.trim();
+      let unquoted = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value);
+      (key.trim().to_string(), unquoted.to_string())
+    })
+    .filter(|(_, value)| !value.contains('$'))
+    .collect()
+}
+
+fn str_at<'a>(doc: &'a Value, path: &[&str]) -> Option<&'a str> {
+  path
+    .iter()
+    .try_fold(doc, |value, key| value.get(key))?
+    .as_str()
+}
+
+fn u16_at(doc: &Value, path: &[&str]) -> Option<u16> {
+  let value = path.iter().try_fold(doc, |value, key| value.get(key))?;
+  match value {
+    Value::Number(n) => n.as_u64().and_then(|n| u16::try_from(n).ok()),
+    Value::String(s) => s.parse().ok(),
+    _ => None,
+  }
+}
+
+fn string_list(value: Option<&Value>) -> Vec<String> {
+  match value {
+    Some(Value::Sequence(items)) => items
+      .iter()
+      .filter_map(|item| item.as_str().map(str::to_string))
+      .collect(),
+    Some(Value::String(item)) => vec![item.clone()],
+    _ => Vec::new(),
+  }
+}
+
+fn string_map(value: Option<&Value>) -> BTreeMap<String, String> {
+  let Some(Value::Mapping(map)) = value else {
+    return BTreeMap::new();
+  };
+  map
+    .iter()
+    .filter_map(|(k, v)| {
+      let value = match v {
+        Value::Null => return None,
+        Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other).ok()?.trim_end().to_string(),
+      };
+      Some((k.as_str()?.to_string(), value))
+    })
+    .collect()
+}
+
+/// Read `path` and the .kamal/secrets of the project it belongs to (config/deploy.yml's
+/// grandparent directory).
+pub fn read_kamal_import(path: &Path, app: Option<&str>) -> Result<KamalImport> {
+  let content =
+    std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+  let project = path
+    .parent()
+    .and_then(Path::parent)
+    .unwrap_or(Path::new("."));
+  let secrets = std::fs::read_to_string(project.join(".kamal/secrets")).ok();
+  parse_kamal_import(&content, secrets.as_deref(), app)
+    .with_context(|| format!("failed to import {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DEPLOY_YML: &str = r#"
+service: shop
+image: acme/shop
+servers:
+  web:
+    - 192.168.0.1
+  job:
+    hosts:
+      - 192.168.0.1
+    cmd: bin/jobs
+proxy:
+  ssl: true
+  host: shop.example.com
+  app_port: 3000
+  healthcheck:
+    path: /up
+registry:
+  server: ghcr.io
+  username: acme
+  password:
+    - KAMAL_REGISTRY_PASSWORD
+builder:
+  arch: amd64
+  args:
+    RUBY_VERSION: 3.3.0
+env:
+  clear:
+    DATABASE_URL: postgres://shop@shop-db:5432/shop_production
+    WEB_CONCURRENCY: 2
+  secret:
+    - RAILS_MASTER_KEY
+    - STRIPE_KEY
+volumes:
+  - "shop_storage:/rails/storage"
+accessories:
+  db:
+    image: postgres:16
+    host: 192.168.0.1
+    env:
+      clear:
+        POSTGRES_USER: shop
+        POSTGRES_DB: shop_production
+      secret:
+        - POSTGRES_PASSWORD
+  search:
+    image: getmeili/meilisearch:v1.6
+"#;
+
+  const SECRETS: &str = r#"
+KAMAL_REGISTRY_PASSWORD=$KAMAL_REGISTRY_PASSWORD
+RAILS_MASTER_KEY=$(cat config/master.key)
+STRIPE_KEY="sk_live_123"
+POSTGRES_PASSWORD=hunter2
+"#;
+
+  #[test]
+  fn test_parse_kamal_import() {
+    let kamal = parse_kamal_import(DEPLOY_YML, Some(SECRETS), None).unwrap();
+    assert_eq!(kamal.service, "shop");
+    assert_eq!(kamal.domain.as_deref(), Some("shop.example.com"));
+    assert_eq!(kamal.hosts, vec!["192.168.0.1"]);
+    assert_eq!(
+      kamal.roles,
+      vec![("job".to_string(), "bin/jobs".to_string())]
+    );
+    assert_eq!(kamal.unresolved_secrets, vec!["RAILS_MASTER_KEY"]);
+
+    let import = &kamal.import;
+    assert_eq!(import.image.as_deref(), Some("ghcr.io/acme/shop"));
+    assert_eq!(import.port, Some(3000));
+    assert_eq!(import.health_path.as_deref(), Some("/up"));
+    assert_eq!(import.build_args["RUBY_VERSION"], "3.3.0");
+    assert_eq!(import.volumes, vec!["shop_storage:/rails/storage"]);
+    assert_eq!(import.env["STRIPE_KEY"], "sk_live_123");
+    assert_eq!(import.env["WEB_CONCURRENCY"], "2");
+    assert_eq!(
+      import.env["DATABASE_URL"],
+      "postgres://shop@shop_pg:5432/shop_production"
+    );
+    assert_eq!(import.skipped, vec!["search"]);
+    assert_eq!(import.accessories.len(), 1);
+    assert_eq!(import.accessories[0].kind, "postgres");
+    assert_eq!(import.accessories[0].version.as_deref(), Some("16"));
+    assert_eq!(import.accessories[0].env["POSTGRES_PASSWORD"], "hunter2");
+  }
+}
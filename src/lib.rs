@@ -1,10 +1,51 @@
+//! hl's deploy pipeline as a library, for tools that embed it instead of running the CLI.
+//!
+//! [`deployer::Deployer`] deploys a commit pushed to an app's hl git repository and
+//! [`accessories::AccessoryManager`] sets up its accessories; both report failures as
+//! [`Error`]. The other modules are the building blocks the `hl` binary uses and may change
+//! between releases.
+
+pub mod accessories;
+pub mod apps;
+pub mod backup;
+pub mod completions;
+pub mod compose_import;
 pub mod config;
+pub mod deployer;
 pub mod discovery;
 pub mod docker;
+pub mod dockerfile;
+pub mod doctor;
+pub mod drift;
+pub mod engine;
 pub mod env;
+pub mod error;
+pub mod events;
+pub mod gate;
 pub mod git;
 pub mod health;
+pub mod heroku_import;
+pub mod kamal_import;
 pub mod log;
+pub mod metrics;
+pub mod notify;
+pub mod output;
+pub mod plugins;
 pub mod procfile;
+pub mod registry;
+pub mod releases;
+pub mod remote;
+pub mod rollout;
+pub mod secrets;
+pub mod sidecar;
+pub mod state;
+pub mod stats;
 pub mod systemd;
+pub mod systemd_bus;
+pub mod textdiff;
+pub mod time;
 pub mod units_spec_builder;
+pub mod verify;
+pub mod webhook;
+
+pub use error::{Error, Result};
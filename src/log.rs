@@ -1,36 +1,217 @@
 use colored::*;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Mutex;
 
-static VERBOSE: AtomicBool = AtomicBool::new(false);
+use crate::time::{format_rfc3339, unix_now};
 
-pub fn set_verbose(enabled: bool) {
-  VERBOSE.store(enabled, Ordering::Relaxed);
+/*
+Every message has a level. The terminal shows info and above by default, debug with `-v` and
+trace with `-vv`; warnings and errors always show. The log file set with `set_log_file` (deploy
+logs) gets every level, one `<rfc3339> <level> <message>` line per message and never colored,
+so it can be filtered and parsed later. `--timestamps` prefixes terminal lines the same way,
+and colors are off with `--no-color` or `NO_COLOR` set. `--quiet` leaves only errors.
+ */
+
+/// Severity of a message, lowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+  Trace,
+  Debug,
+  Info,
+  Warn,
+  Error,
+}
+
+impl Level {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Level::Trace => "trace",
+      Level::Debug => "debug",
+      Level::Info => "info",
+      Level::Warn => "warn",
+      Level::Error => "error",
+    }
+  }
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+static QUIET: AtomicBool = AtomicBool::new(false);
+static TIMESTAMPS: AtomicBool = AtomicBool::new(false);
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// 0 shows info and above, 1 (`-v`) adds debug, 2 or more (`-vv`) adds trace.
+pub fn set_verbosity(verbosity: u8) {
+  VERBOSITY.store(verbosity, Ordering::Relaxed);
 }
 
 pub fn is_verbose() -> bool {
-  VERBOSE.load(Ordering::Relaxed)
+  enabled(Level::Debug)
 }
 
-pub fn debug(msg: &str) {
-  if is_verbose() {
-    eprintln!("{} {}", "›".bright_black(), msg.dimmed());
+/// Only errors reach the terminal (`--quiet`); the log file still gets everything.
+pub fn set_quiet(enabled: bool) {
+  QUIET.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+  QUIET.load(Ordering::Relaxed)
+}
+
+/// Stdout for a subprocess whose output is progress rather than the result (pulls,
+/// migrations, systemctl): inherited, or discarded with `--quiet`. Its stderr stays, since
+/// that is where the errors are.
+pub fn progress_stdout() -> Stdio {
+  if is_quiet() {
+    Stdio::null()
+  } else {
+    Stdio::inherit()
   }
 }
 
+/// Whether messages at `level` reach the terminal.
+pub fn enabled(level: Level) -> bool {
+  if is_quiet() {
+    return level >= Level::Error;
+  }
+  let threshold = match VERBOSITY.load(Ordering::Relaxed) {
+    0 => Level::Info,
+    1 => Level::Debug,
+    _ => Level::Trace,
+  };
+  level >= threshold
+}
+
+/// Prefix terminal lines with the time, like the log file's.
+pub fn set_timestamps(enabled: bool) {
+  TIMESTAMPS.store(enabled, Ordering::Relaxed);
+}
+
+/// Turn colors off (they are already off when `NO_COLOR` is set).
+pub fn disable_color() {
+  colored::control::set_override(false);
+}
+
+/// Also append every message (debug ones included, without colors) to `path`, so operations
+/// run from a git hook leave a trace after the SSH session is gone.
+pub fn set_log_file(path: &Path) -> std::io::Result<()> {
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  let file = File::options().create(true).append(true).open(path)?;
+  *LOG_FILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+  Ok(())
+}
+
+/// Write a line to the log file only (no-op without one), e.g. the final error of a command.
+pub fn record(level: Level, msg: &str) {
+  let mut guard = LOG_FILE.lock().unwrap_or_else(|e| e.into_inner());
+  if let Some(file) = guard.as_mut() {
+    let _ = writeln!(
+      file,
+      "{} {:<5} {}",
+      format_rfc3339(unix_now()),
+      level.as_str(),
+      msg
+    );
+  }
+}
+
+/// Record `msg` and, if its level is enabled, print it behind `marker` on stdout (or stderr).
+fn emit(level: Level, marker: ColoredString, msg: ColoredString, stderr: bool) {
+  record(level, &msg.input);
+  if !enabled(level) {
+    return;
+  }
+  let line = if TIMESTAMPS.load(Ordering::Relaxed) {
+    format!(
+      "{} {} {}",
+      format_rfc3339(unix_now()).bright_black(),
+      marker,
+      msg
+    )
+  } else {
+    format!("{} {}", marker, msg)
+  };
+  if stderr {
+    eprintln!("{}", line);
+  } else {
+    println!("{}", line);
+  }
+}
+
+pub fn trace(msg: &str) {
+  emit(Level::Trace, "·".bright_black(), msg.dimmed(), true);
+}
+
+pub fn debug(msg: &str) {
+  emit(Level::Debug, "›".bright_black(), msg.dimmed(), true);
+}
+
 pub fn log(msg: &str) {
-  println!("{} {}", "•".bright_black(), msg);
+  emit(Level::Info, "•".bright_black(), msg.normal(), false);
 }
 
+/// An info message marking something done.
 pub fn ok(msg: &str) {
-  println!("{} {}", "✓".green(), msg.bold());
+  emit(Level::Info, "✓".green(), msg.bold(), false);
 }
 
 #[allow(dead_code)]
 pub fn warn(msg: &str) {
-  println!("{} {}", "!".yellow(), msg);
+  emit(Level::Warn, "!".yellow(), msg.normal(), false);
 }
 
 #[allow(dead_code)]
 pub fn err(msg: &str) {
-  eprintln!("{} {}", "x".red(), msg);
+  emit(Level::Error, "x".red(), msg.normal(), true);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serial_test::serial;
+  use tempfile::TempDir;
+
+  #[test]
+  fn test_log_file_tee() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("logs/deploy-abcdef0.log");
+    set_log_file(&path).unwrap();
+    log("building image");
+    debug("docker buildx build ...");
+    record(Level::Error, "deploy failed: boom");
+    *LOG_FILE.lock().unwrap() = None;
+    log("not recorded");
+
+    // Other tests may log concurrently, so only look for this test's lines
+    let content = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert!(lines.iter().any(|l| l.ends_with(" info  building image")));
+    assert!(lines
+      .iter()
+      .any(|l| l.ends_with(" debug docker buildx build ...")));
+    assert!(lines
+      .iter()
+      .any(|l| l.ends_with(" error deploy failed: boom")));
+    assert!(!content.contains("not recorded"));
+  }
+
+  #[test]
+  #[serial]
+  fn test_levels() {
+    // Verbosity is process-wide; put back whatever other tests run with
+    let previous = VERBOSITY.load(Ordering::Relaxed);
+    set_verbosity(0);
+    assert!(enabled(Level::Info) && enabled(Level::Error));
+    assert!(!enabled(Level::Debug) && !enabled(Level::Trace));
+    set_verbosity(2);
+    assert!(enabled(Level::Trace) && is_verbose());
+    set_verbosity(0);
+    assert!(!is_verbose());
+    set_verbosity(previous);
+  }
 }
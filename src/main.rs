@@ -1,17 +1,42 @@
 mod commands;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use hl::log::set_verbose;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use hl::error::exit_code_of;
+use hl::log::{disable_color, set_quiet, set_timestamps, set_verbosity};
+use hl::output::{set_output_format, OutputFormat};
+use hl::remote::{resolve_remote, run_remote};
 
 #[derive(Parser)]
 #[command(name = "hl")]
 #[command(about = "Homelab deploy toolbox", long_about = None)]
 #[command(version)]
 struct Cli {
-  /// Enable verbose/debug logging
-  #[arg(short, long, global = true)]
-  verbose: bool,
+  /// Show debug messages; twice (-vv) for trace messages too
+  #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+  verbose: u8,
+
+  /// Print only errors and a final `result=ok|error command=<name>` line, for automation
+  #[arg(short, long, global = true, conflicts_with = "verbose")]
+  quiet: bool,
+
+  /// Prefix messages with the time
+  #[arg(long, global = true)]
+  timestamps: bool,
+
+  /// Don't color output (also when NO_COLOR is set)
+  #[arg(long, global = true)]
+  no_color: bool,
+
+  /// Run the command on this SSH host (user@host) instead of locally; also HL_HOST or
+  /// `remote.host` in ~/.config/hl/config.yml
+  #[arg(long, global = true, value_name = "USER@HOST")]
+  host: Option<String>,
+
+  /// Print structured yaml or json instead of the human view, for commands that have one
+  /// (status, apps, info, env ls, accessory status, releases ls, config show)
+  #[arg(long, global = true, value_enum, value_name = "FORMAT")]
+  output: Option<OutputFormat>,
 
   #[command(subcommand)]
   command: Commands,
@@ -21,39 +46,229 @@ struct Cli {
 enum Commands {
   /// Manage accessories (postgres, redis, etc.)
   Accessory(commands::accessory::AccessoriesArgs),
+  /// List the apps on this host with their domain, processes, state and last deploy
+  Apps(commands::apps::AppsArgs),
+  /// Archive an app's config, .env, accessory data and deploy history
+  Backup(commands::backup::BackupArgs),
+  /// Print a shell completion script (bash, zsh or fish)
+  Completions(commands::completions::CompletionsArgs),
+  /// Inspect the app's hl.yml
+  Config(commands::config::ConfigArgs),
+  /// Open the configured console (e.g. rails console) in a one-off container
+  Console(commands::console::ConsoleArgs),
+  /// Open psql (or redis-cli) on the app's database, or run a query with --command
+  Db(commands::db::DbArgs),
   /// Build->push->migrate->restart->health (invoke from post-receive)
   Deploy(commands::deploy::DeployArgs),
+  /// Compare an app's desired state with its compose files, units, containers and images
+  Diff(commands::diff::DiffArgs),
+  /// Diagnose host and app setup problems
+  Doctor(commands::doctor::DoctorArgs),
+  /// Run a command in a running process or accessory container
+  Exec(commands::exec::ExecArgs),
+  /// Write an app's stack (base, process and accessory overlays) as one resolved compose file
+  Export(commands::export::ExportArgs),
+  /// Regenerate the git post-receive hooks from the current template
+  Hooks(commands::hooks::HooksArgs),
+  /// Create an app from another deploy tool's config (Kamal, Heroku)
+  Import(commands::import::ImportArgs),
+  /// Print an app's domains, image, deployed sha, processes, accessories and git remote
+  Info(commands::info::InfoArgs),
   /// Initializes a new app with its configuration files
   Init(commands::init::InitArgs),
   /// Stream logs from a service
   Logs(commands::logs::LogsArgs),
+  /// Print Prometheus metrics of every app's deploys, containers and health
+  Metrics(commands::metrics::MetricsArgs),
+  /// List the accessory and deploy hook plugins in ~/.config/hl/plugins
+  Plugins(commands::plugins::PluginsArgs),
+  /// Add sidecar processes from built-in templates
+  Process(commands::process::ProcessArgs),
+  /// Prune old release manifests, images, deploy logs and backups (same as `hl releases gc`)
+  Prune(commands::releases::GcArgs),
+  /// Log in to the container registry deploys push to
+  Registry(commands::registry::RegistryArgs),
+  /// Inspect and prune deployed releases
+  Releases(commands::releases::ReleasesArgs),
   /// Restart a service using systemctl
   Restart(commands::restart::RestartArgs),
+  /// Recreate an app from an `hl backup` archive
+  Restore(commands::restore::RestoreArgs),
   /// Retag :latest to a previous sha and restart (health-gated)
   Rollback(commands::rollback::RollbackArgs),
+  /// Run a one-off command in a new container of the app's image
+  Run(commands::run::RunArgs),
+  /// Set how many containers a process runs
+  Scale(commands::scale::ScaleArgs),
+  /// Run an HTTP server that deploys, rolls back and restarts apps for CI and git webhooks
+  Serve(commands::serve::ServeArgs),
+  /// Show process state and whether they run with the current .env
+  #[command(alias = "ps")]
+  Status(commands::status::StatusArgs),
+  /// Show or rebuild the app's recorded state (processes, accessories, release, scale)
+  State(commands::state::StateArgs),
   /// Manage .env environment variables
   Env(commands::env::EnvArgs),
+  /// Show the log of deploys, restarts, env changes and other operations
+  Events(commands::events::EventsArgs),
   /// Teardown an app (stop services, remove files, directories and git repo)
   Teardown(commands::teardown::TeardownArgs),
+  /// Show CPU and memory of the apps' containers (processes and accessories)
+  Top(commands::top::TopArgs),
+  /// Preview changes to the app's generated systemd units
+  Units(commands::units::UnitsArgs),
+  /// Cross-check hl.yml, compose files, units, .env, the release image and the public route
+  Verify(commands::verify::VerifyArgs),
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-  let cli = Cli::parse();
+  let matches = Cli::command().get_matches();
+  let cli = Cli::from_arg_matches(&matches)?;
+  let command_name = matches.subcommand_name().unwrap_or_default().to_string();
 
-  // Set verbose mode
-  set_verbose(cli.verbose);
+  set_verbosity(cli.verbose);
+  set_quiet(cli.quiet);
+  set_timestamps(cli.timestamps);
+  if cli.no_color {
+    disable_color();
+  }
+  set_output_format(cli.output);
+
+  if !runs_locally(&cli.command) {
+    if let Some(remote) = resolve_remote(cli.host.as_deref()).await? {
+      if let Some(problem) = local_file_arg(&cli.command) {
+        anyhow::bail!(
+          "{}, but this command runs on {}; copy it there and run hl on the server",
+          problem,
+          remote.host
+        );
+      }
+      let args: Vec<String> = std::env::args().skip(1).collect();
+      std::process::exit(run_remote(&remote, &args).await?);
+    }
+  }
+
+  let result = run(cli.command).await;
+  if cli.quiet {
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    println!("result={} command={}", outcome, command_name);
+  }
+  if let Err(e) = result {
+    // Reported like an error returned from main, but exiting with its category's code
+    eprintln!("Error: {:?}", e);
+    std::process::exit(exit_code_of(&e));
+  }
+  Ok(())
+}
 
-  match cli.command {
+async fn run(command: Commands) -> Result<()> {
+  match command {
     Commands::Accessory(args) => commands::accessory::execute(args).await?,
+    Commands::Apps(args) => commands::apps::execute(args).await?,
+    Commands::Backup(args) => commands::backup::execute(args).await?,
+    Commands::Completions(args) => commands::completions::execute(args).await?,
+    Commands::Config(args) => commands::config::execute(args).await?,
+    Commands::Console(args) => commands::console::execute(args).await?,
+    Commands::Db(args) => commands::db::execute(args).await?,
     Commands::Deploy(args) => commands::deploy::execute(args).await?,
+    Commands::Diff(args) => commands::diff::execute(args).await?,
+    Commands::Doctor(args) => commands::doctor::execute(args).await?,
+    Commands::Exec(args) => commands::exec::execute(args).await?,
+    Commands::Export(args) => commands::export::execute(args).await?,
+    Commands::Hooks(args) => commands::hooks::execute(args).await?,
+    Commands::Import(args) => commands::import::execute(args).await?,
+    Commands::Info(args) => commands::info::execute(args).await?,
     Commands::Init(args) => commands::init::execute(args).await?,
     Commands::Logs(args) => commands::logs::execute(args).await?,
+    Commands::Metrics(args) => commands::metrics::execute(args).await?,
+    Commands::Plugins(args) => commands::plugins::execute(args).await?,
+    Commands::Process(args) => commands::process::execute(args).await?,
+    Commands::Prune(args) => commands::releases::prune(args).await?,
+    Commands::Registry(args) => commands::registry::execute(args).await?,
+    Commands::Releases(args) => commands::releases::execute(args).await?,
     Commands::Restart(args) => commands::restart::execute(args).await?,
+    Commands::Restore(args) => commands::restore::execute(args).await?,
     Commands::Rollback(args) => commands::rollback::execute(args).await?,
+    Commands::Run(args) => commands::run::execute(args).await?,
+    Commands::Scale(args) => commands::scale::execute(args).await?,
+    Commands::Serve(args) => commands::serve::execute(args).await?,
+    Commands::Status(args) => commands::status::execute(args).await?,
+    Commands::State(args) => commands::state::execute(args).await?,
     Commands::Env(args) => commands::env::execute(args).await?,
+    Commands::Events(args) => commands::events::execute(args).await?,
     Commands::Teardown(args) => commands::teardown::execute(args).await?,
+    Commands::Top(args) => commands::top::execute(args).await?,
+    Commands::Units(args) => commands::units::execute(args).await?,
+    Commands::Verify(args) => commands::verify::execute(args).await?,
   }
 
   Ok(())
 }
+
+/// Commands that stay on the workstation in client mode: completion scripts are rendered
+/// locally, only the names they offer come from the server.
+fn runs_locally(command: &Commands) -> bool {
+  matches!(command, Commands::Completions(args) if args.list.is_none())
+}
+
+/// An argument naming a file on this machine, which a command forwarded to the server can't
+/// read or write. Dotenv files can still be pushed through stdin with `--file -`.
+fn local_file_arg(command: &Commands) -> Option<String> {
+  use commands::env::EnvCommands;
+  use commands::import::ImportSource;
+  let local =
+    |flag: &str, path: &std::path::Path| format!("{} {} is on this machine", flag, path.display());
+  match command {
+    Commands::Env(args) => match &args.command {
+      EnvCommands::Push { file, .. } if file != "-" => Some(format!(
+        "--file {} is on this machine (pipe it instead: hl env push --file - < {})",
+        file, file
+      )),
+      EnvCommands::Diff {
+        file: Some(file), ..
+      } => Some(format!("--file {} is on this machine", file)),
+      _ => None,
+    },
+    Commands::Backup(args) => args.out.as_deref().map(|out| local("--out", out)),
+    Commands::Export(args) => args.out.as_deref().map(|out| local("--out", out)),
+    Commands::Restore(args) => Some(format!("{} is on this machine", args.archive.display())),
+    Commands::Import(args) => Some(match &args.source {
+      ImportSource::Kamal(kamal) => format!("{} is on this machine", kamal.config.display()),
+      ImportSource::Heroku(heroku) => format!("{} is on this machine", heroku.dir.display()),
+    }),
+    Commands::Init(args) => args
+      .from_compose
+      .as_deref()
+      .map(|path| local("--from-compose", path))
+      .or_else(|| args.repo.as_deref().map(|path| local("--repo", path))),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_local_file_arg() {
+    let problem = |args: &[&str]| {
+      let cli = Cli::try_parse_from(std::iter::once("hl").chain(args.iter().copied())).unwrap();
+      local_file_arg(&cli.command)
+    };
+    assert_eq!(
+      problem(&["env", "push", "--file", ".env.production"]).unwrap(),
+      "--file .env.production is on this machine (pipe it instead: hl env push --file - < .env.production)"
+    );
+    assert_eq!(problem(&["env", "push", "--file", "-"]), None);
+    assert_eq!(
+      problem(&["backup", "--out", "blog.tar.gz"]).unwrap(),
+      "--out blog.tar.gz is on this machine"
+    );
+    assert!(problem(&["restore", "blog.tar.gz"]).is_some());
+    assert!(problem(&["import", "heroku", "--domain", "blog.example.com"]).is_some());
+    assert_eq!(problem(&["backup"]), None);
+    assert_eq!(problem(&["export"]), None);
+    assert_eq!(problem(&["env", "diff", "--build"]), None);
+  }
+}
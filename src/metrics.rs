@@ -0,0 +1,265 @@
+use crate::apps::list_apps;
+use crate::config::GlobalConfig;
+use crate::docker::inspect_compose_project;
+use crate::log::{debug, warn};
+use crate::releases::{list_logs, summarize_deploy_log, DeployLogSummary};
+use crate::systemd::user_unit_active_state;
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/*
+Metrics in the Prometheus text format, for node_exporter's textfile collector: the outcome of
+each app's last deploy (read back from its deploy log), whether its target is active, its
+containers by state and the healthcheck status of those that have one. `hl metrics
+--textfile-dir` writes them once; with `metrics.textfileDir` in the global config every deploy
+refreshes the file, and a timer running `hl metrics` keeps container state current between
+deploys.
+ */
+
+/// File written in the textfile collector directory.
+pub const TEXTFILE_NAME: &str = "hl.prom";
+
+/// What is reported for one app.
+#[derive(Debug, Default)]
+pub struct AppMetrics {
+  pub app: String,
+  pub target_active: bool,
+  pub last_deploy: Option<DeployLogSummary>,
+  pub running: u32,
+  pub stopped: u32,
+  /// (service, container, healthy) of containers with a healthcheck
+  pub health: Vec<(String, String, bool)>,
+}
+
+pub async fn collect_app_metrics(app: &str) -> AppMetrics {
+  let mut metrics = AppMetrics {
+    app: app.to_string(),
+    target_active: user_unit_active_state(&format!("app-{}.target", app))
+      .await
+      .is_ok_and(|state| state == "active"),
+    ..Default::default()
+  };
+  metrics.last_deploy = list_logs(app, "deploy-")
+    .ok()
+    .and_then(|logs| logs.into_iter().next())
+    .and_then(|path| std::fs::read_to_string(path).ok())
+    .map(|content| summarize_deploy_log(&content));
+
+  for project in [app.to_string(), format!("{}-acc", app)] {
+    let containers = match inspect_compose_project(&project).await {
+      Ok(containers) => containers,
+      Err(e) => {
+        debug(&format!("not counting containers of {}: {:#}", project, e));
+        continue;
+      }
+    };
+    for container in containers {
+      if container.state.status == "running" {
+        metrics.running += 1;
+      } else {
+        metrics.stopped += 1;
+      }
+      if let Some(health) = &container.state.health {
+        metrics.health.push((
+          container.compose_service().unwrap_or_default().to_string(),
+          container.name.trim_start_matches('/').to_string(),
+          health.status == "healthy",
+        ));
+      }
+    }
+  }
+  metrics
+}
+
+pub fn render_metrics(apps: &[AppMetrics]) -> String {
+  let mut out = String::new();
+  let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+      let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+  };
+  let app_label = |m: &AppMetrics| format!("app=\"{}\"", escape_label(&m.app));
+  let flag = |b: bool| if b { 1.0 } else { 0.0 };
+
+  family(
+    "hl_app_up",
+    "gauge",
+    "Whether the app's systemd target is active.",
+    apps
+      .iter()
+      .map(|m| (app_label(m), flag(m.target_active)))
+      .collect(),
+  );
+  family(
+    "hl_deploy_last_started_timestamp_seconds",
+    "gauge",
+    "When the app's last deploy started.",
+    apps
+      .iter()
+      .filter_map(|m| Some((app_label(m), m.last_deploy.as_ref()?.started? as f64)))
+      .collect(),
+  );
+  family(
+    "hl_deploy_last_duration_seconds",
+    "gauge",
+    "How long the app's last finished deploy took.",
+    apps
+      .iter()
+      .filter_map(|m| {
+        let deploy = m
+          .last_deploy
+          .as_ref()
+          .filter(|d| d.result != "incomplete")?;
+        let duration = deploy.finished?.saturating_sub(deploy.started?);
+        Some((app_label(m), duration as f64))
+      })
+      .collect(),
+  );
+  family(
+    "hl_deploy_last_success",
+    "gauge",
+    "Whether the app's last finished deploy succeeded.",
+    apps
+      .iter()
+      .filter_map(|m| {
+        let deploy = m
+          .last_deploy
+          .as_ref()
+          .filter(|d| d.result != "incomplete")?;
+        Some((app_label(m), flag(deploy.succeeded())))
+      })
+      .collect(),
+  );
+  family(
+    "hl_containers",
+    "gauge",
+    "The app's process and accessory containers by state.",
+    apps
+      .iter()
+      .flat_map(|m| {
+        [
+          (
+            format!("{},state=\"running\"", app_label(m)),
+            m.running as f64,
+          ),
+          (
+            format!("{},state=\"stopped\"", app_label(m)),
+            m.stopped as f64,
+          ),
+        ]
+      })
+      .collect(),
+  );
+  family(
+    "hl_container_healthy",
+    "gauge",
+    "Whether a container's healthcheck passes, for containers that have one.",
+    apps
+      .iter()
+      .flat_map(|m| {
+        m.health.iter().map(move |(service, container, healthy)| {
+          (
+            format!(
+              "{},service=\"{}\",container=\"{}\"",
+              app_label(m),
+              escape_label(service),
+              escape_label(container)
+            ),
+            flag(*healthy),
+          )
+        })
+      })
+      .collect(),
+  );
+  out
+}
+
+fn escape_label(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+/// Metrics of every app on this host.
+pub async fn collect_all() -> Result<String> {
+  let mut apps = Vec::new();
+  for app in list_apps()? {
+    apps.push(collect_app_metrics(&app).await);
+  }
+  Ok(render_metrics(&apps))
+}
+
+/// Replace `dir/hl.prom` atomically, so the collector never reads half a file.
+pub fn write_textfile(dir: &Path, content: &str) -> Result<PathBuf> {
+  let path = dir.join(TEXTFILE_NAME);
+  // The collector only reads *.prom, so the temp file is ignored until renamed
+  let tmp = dir.join(format!(".{}.tmp", TEXTFILE_NAME));
+  std::fs::write(&tmp, content).with_context(|| format!("Failed to write {}", tmp.display()))?;
+  std::fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))?;
+  Ok(path)
+}
+
+/// Rewrite the textfile after a deploy when the global config asks for one. Failures only
+/// warn: metrics never fail a deploy.
+pub async fn refresh_textfile(global: &GlobalConfig) {
+  let Some(dir) = global.metrics.textfile_dir() else {
+    return;
+  };
+  let result = match collect_all().await {
+    Ok(content) => write_textfile(&dir, &content),
+    Err(e) => Err(e),
+  };
+  match result {
+    Ok(path) => debug(&format!("refreshed metrics in {}", path.display())),
+    Err(e) => warn(&format!("failed to refresh metrics: {:#}", e)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_render_metrics() {
+    let apps = vec![
+      AppMetrics {
+        app: "blog".to_string(),
+        target_active: true,
+        last_deploy: Some(DeployLogSummary {
+          started: Some(1714557600),
+          finished: Some(1714557780),
+          result: "ok".to_string(),
+        }),
+        running: 2,
+        stopped: 1,
+        health: vec![("web".to_string(), "blog-web-1".to_string(), true)],
+      },
+      AppMetrics {
+        app: "shop".to_string(),
+        last_deploy: Some(DeployLogSummary {
+          started: Some(1714557600),
+          finished: Some(1714557610),
+          result: "incomplete".to_string(),
+        }),
+        ..Default::default()
+      },
+    ];
+    let text = render_metrics(&apps);
+    assert!(text
+      .contains("# TYPE hl_app_up gauge\nhl_app_up{app=\"blog\"} 1\nhl_app_up{app=\"shop\"} 0\n"));
+    assert!(text.contains("hl_deploy_last_started_timestamp_seconds{app=\"shop\"} 1714557600\n"));
+    assert!(text.contains("hl_deploy_last_duration_seconds{app=\"blog\"} 180\n"));
+    // A deploy still running has no duration or result yet
+    assert!(!text.contains("hl_deploy_last_duration_seconds{app=\"shop\"}"));
+    assert!(text.contains("hl_deploy_last_success{app=\"blog\"} 1\n"));
+    assert!(!text.contains("hl_deploy_last_success{app=\"shop\"}"));
+    assert!(text.contains("hl_containers{app=\"blog\",state=\"stopped\"} 1\n"));
+    assert!(text
+      .contains("hl_container_healthy{app=\"blog\",service=\"web\",container=\"blog-web-1\"} 1\n"));
+    assert_eq!(escape_label("a\"b\\"), "a\\\"b\\\\");
+  }
+}
@@ -0,0 +1,93 @@
+use crate::config::HLConfig;
+use crate::log::{debug, warn};
+use crate::output::{render, OutputFormat};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Body POSTed to each `notifications.webhooks` URL after a deploy.
+#[derive(Debug, Serialize)]
+pub struct DeployEvent<'a> {
+  pub app: &'a str,
+  pub sha: &'a str,
+  /// Pushed branch; None when a tag was pushed
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub branch: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub tag: Option<&'a str>,
+  /// "succeeded" or "failed"
+  pub status: &'a str,
+  pub error: Option<String>,
+}
+
+/// Post a deploy outcome to the configured webhooks. Delivery is best effort: failures are
+/// warned about and never change the deploy's result.
+pub async fn notify_deploy<E: std::fmt::Display>(
+  cfg: &HLConfig,
+  sha: &str,
+  branch: Option<&str>,
+  tag: Option<&str>,
+  result: &Result<(), E>,
+) {
+  if cfg.notifications.webhooks.is_empty() {
+    return;
+  }
+  let event = DeployEvent {
+    app: &cfg.app,
+    sha,
+    branch,
+    tag,
+    status: if result.is_ok() {
+      "succeeded"
+    } else {
+      "failed"
+    },
+    error: result.as_ref().err().map(|e| format!("{:#}", e)),
+  };
+  let body = match render(&event, OutputFormat::Json) {
+    Ok(body) => body,
+    Err(e) => return warn(&format!("not sending deploy notifications: {}", e)),
+  };
+  let client = match reqwest::Client::builder()
+    .timeout(Duration::from_secs(10))
+    .build()
+  {
+    Ok(client) => client,
+    Err(e) => return warn(&format!("not sending deploy notifications: {}", e)),
+  };
+
+  for url in &cfg.notifications.webhooks {
+    let sent = client
+      .post(url)
+      .header("content-type", "application/json")
+      .body(body.clone())
+      .send()
+      .await
+      .and_then(|resp| resp.error_for_status());
+    match sent {
+      Ok(_) => debug(&format!("notified {}", url)),
+      Err(e) => warn(&format!("deploy notification to {} failed: {}", url, e)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_deploy_event_json() {
+    let event = DeployEvent {
+      app: "myapp",
+      sha: "abc1234",
+      branch: Some("main"),
+      tag: None,
+      status: "failed",
+      error: Some("health check timed out".to_string()),
+    };
+    assert_eq!(
+      render(&event, OutputFormat::Json).unwrap(),
+      "{\n  \"app\": \"myapp\",\n  \"sha\": \"abc1234\",\n  \"branch\": \"main\",\n  \
+       \"status\": \"failed\",\n  \"error\": \"health check timed out\"\n}\n"
+    );
+  }
+}
@@ -0,0 +1,254 @@
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_yaml::Value;
+use std::sync::Mutex;
+
+/// Format requested with the global `--output` flag; None prints for people.
+static REQUESTED: Mutex<Option<OutputFormat>> = Mutex::new(None);
+
+/// Machine-readable formats for commands that print structured data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+  #[default]
+  Yaml,
+  Json,
+}
+
+pub fn set_output_format(format: Option<OutputFormat>) {
+  *REQUESTED.lock().unwrap_or_else(|e| e.into_inner()) = format;
+}
+
+pub fn output_format() -> Option<OutputFormat> {
+  *REQUESTED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Print `value` in the `--output` format, or with `human` when none was requested. Commands
+/// with structured output gather it into one value and print it through here, so scripts
+/// get the same fields the human view is built from.
+pub fn emit<T: Serialize>(value: &T, human: impl FnOnce(&T)) -> Result<()> {
+  match output_format() {
+    Some(format) => print!("{}", render(value, format)?),
+    None => human(value),
+  }
+  Ok(())
+}
+
+/// Render a value in the requested format, ending with a newline.
+pub fn render<T: Serialize>(value: &T, format: OutputFormat) -> Result<String> {
+  match format {
+    OutputFormat::Yaml => Ok(serde_yaml::to_string(value)?),
+    OutputFormat::Json => {
+      let mut out = String::new();
+      write_json(&serde_yaml::to_value(value)?, Some(0), &mut out);
+      out.push('\n');
+      Ok(out)
+    }
+  }
+}
+
+/// A value as compact single-line JSON, e.g. for a JSON Lines file (no trailing newline).
+pub fn to_json_line<T: Serialize>(value: &T) -> Result<String> {
+  let mut out = String::new();
+  write_json(&serde_yaml::to_value(value)?, None, &mut out);
+  Ok(out)
+}
+
+/// Print a YAML value as JSON: pretty with 2-space indent from level `indent`, or on one line
+/// when `None`. Non-string mapping keys are stringified; non-finite numbers become null.
+fn write_json(value: &Value, indent: Option<usize>, out: &mut String) {
+  let inner = indent.map(|i| i + 1);
+  match value {
+    Value::Null => out.push_str("null"),
+    Value::Bool(b) => out.push_str(&b.to_string()),
+    Value::Number(n) => match n.as_f64() {
+      Some(f) if !f.is_finite() => out.push_str("null"),
+      _ => out.push_str(&n.to_string()),
+    },
+    Value::String(s) => write_json_string(s, out),
+    Value::Sequence(items) => {
+      if items.is_empty() {
+        out.push_str("[]");
+        return;
+      }
+      out.push('[');
+      for (i, item) in items.iter().enumerate() {
+        push_indent(inner, out);
+        write_json(item, inner, out);
+        if i + 1 < items.len() {
+          out.push(',');
+        }
+      }
+      push_indent(indent, out);
+      out.push(']');
+    }
+    Value::Mapping(map) => {
+      if map.is_empty() {
+        out.push_str("{}");
+        return;
+      }
+      out.push('{');
+      for (i, (key, item)) in map.iter().enumerate() {
+        push_indent(inner, out);
+        let key = match key {
+          Value::String(s) => s.clone(),
+          other => serde_yaml::to_string(other)
+            .unwrap_or_default()
+            .trim_end()
+            .to_string(),
+        };
+        write_json_string(&key, out);
+        out.push(':');
+        if indent.is_some() {
+          out.push(' ');
+        }
+        write_json(item, inner, out);
+        if i + 1 < map.len() {
+          out.push(',');
+        }
+      }
+      push_indent(indent, out);
+      out.push('}');
+    }
+    Value::Tagged(tagged) => write_json(&tagged.value, indent, out),
+  }
+}
+
+/// Parse JSON text. hl has no JSON parser of its own and reads JSON through serde_yaml, which
+/// accepts nearly all of it; the exception is the `\ud83d\ude00` escapes JSON encoders write
+/// for characters outside the Basic Multilingual Plane (emoji, say), which YAML doesn't
+/// have. Those pairs are decoded before parsing, and lone surrogates become U+FFFD.
+pub fn from_json<T: DeserializeOwned>(json: &str) -> Result<T> {
+  serde_yaml::from_str(&decode_surrogate_escapes(json)).context("invalid JSON")
+}
+
+/// `json` with each `\uXXXX` escape of a UTF-16 surrogate replaced by the character it
+/// encodes. Other escapes, `\\` included, are copied as they are.
+fn decode_surrogate_escapes(json: &str) -> std::borrow::Cow<'_, str> {
+  if !json.contains("\\u") {
+    return json.into();
+  }
+  let surrogate = |s: &str, range: std::ops::RangeInclusive<u32>| {
+    let hex = s.strip_prefix("\\u")?.get(..4)?;
+    u32::from_str_radix(hex, 16)
+      .ok()
+      .filter(|u| range.contains(u))
+  };
+  let mut out = String::with_capacity(json.len());
+  let mut rest = json;
+  while let Some(pos) = rest.find('\\') {
+    out.push_str(&rest[..pos]);
+    rest = &rest[pos..];
+    if let Some(high) = surrogate(rest, 0xd800..=0xdbff) {
+      match surrogate(&rest[6..], 0xdc00..=0xdfff) {
+        Some(low) => {
+          let c = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+          out.push(char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER));
+          rest = &rest[12..];
+        }
+        None => {
+          out.push(char::REPLACEMENT_CHARACTER);
+          rest = &rest[6..];
+        }
+      }
+    } else if surrogate(rest, 0xdc00..=0xdfff).is_some() {
+      out.push(char::REPLACEMENT_CHARACTER);
+      rest = &rest[6..];
+    } else {
+      // The backslash and the character it escapes
+      let len = 1 + rest[1..].chars().next().map_or(0, char::len_utf8);
+      out.push_str(&rest[..len]);
+      rest = &rest[len..];
+    }
+  }
+  out.push_str(rest);
+  out.into()
+}
+
+fn write_json_string(s: &str, out: &mut String) {
+  out.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+}
+
+/// Newline and indentation before an item when pretty-printing.
+fn push_indent(level: Option<usize>, out: &mut String) {
+  if let Some(level) = level {
+    out.push('\n');
+    out.push_str(&"  ".repeat(level));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::BTreeMap;
+
+  #[derive(Serialize)]
+  #[serde(rename_all = "camelCase")]
+  struct Sample {
+    app: String,
+    service_port: u16,
+    secrets: Vec<String>,
+    env: BTreeMap<String, String>,
+    image: Option<String>,
+  }
+
+  #[test]
+  fn test_render_json() {
+    let sample = Sample {
+      app: "my\"app".to_string(),
+      service_port: 3000,
+      secrets: vec!["A".to_string(), "B".to_string()],
+      env: BTreeMap::new(),
+      image: None,
+    };
+    assert_eq!(
+      render(&sample, OutputFormat::Json).unwrap(),
+      r#"{
+  "app": "my\"app",
+  "servicePort": 3000,
+  "secrets": [
+    "A",
+    "B"
+  ],
+  "env": {},
+  "image": null
+}
+"#
+    );
+    assert_eq!(
+      to_json_line(&sample).unwrap(),
+      r#"{"app":"my\"app","servicePort":3000,"secrets":["A","B"],"env":{},"image":null}"#
+    );
+  }
+
+  #[test]
+  fn test_from_json() {
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Commit {
+      message: String,
+    }
+    let parse = |json: &str| from_json::<Commit>(json).unwrap().message;
+    assert_eq!(
+      parse(r#"{"message": "ship it \ud83d\ude00"}"#),
+      "ship it \u{1f600}"
+    );
+    assert_eq!(
+      parse(r#"{"message": "\uD83D\uDE80 \u00e9\n"}"#),
+      "\u{1f680} \u{e9}\n"
+    );
+    assert_eq!(parse(r#"{"message": "C:\\ud83d"}"#), "C:\\ud83d");
+    assert_eq!(parse(r#"{"message": "lone \ud83d!"}"#), "lone \u{fffd}!");
+    assert!(from_json::<Commit>("{\"message\":").is_err());
+  }
+}
@@ -0,0 +1,246 @@
+use crate::config::home_dir;
+use crate::error::Error;
+use crate::log::{debug, log, progress_stdout, warn};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/*
+Plugins are executables in ~/.config/hl/plugins/, their file name saying what they add:
+
+  accessory-<type>        an accessory type for `hl accessory add <type>`
+  <phase>[-<anything>]    a deploy hook, run at one of the `HookPhase`s
+
+An accessory plugin is run as `accessory-<type> add` and prints YAML on stdout: the
+`compose` overlay hl writes to compose.<type>.yml and the `env` it merges into the app's .env.
+Hooks of a phase run in file name order. A failing pre-build or pre-restart hook fails the
+deploy; the later phases only warn. Both get the app and deploy through HL_* variables.
+ */
+
+/// Points in a deploy where hook plugins run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPhase {
+  /// After the compose and unit files are written, before the image is built
+  PreBuild,
+  /// After migrations, before the processes restart onto the new image
+  PreRestart,
+  /// After a deploy succeeded
+  PostDeploy,
+  /// After a deploy failed, with the error in HL_ERROR
+  DeployFailed,
+}
+
+impl HookPhase {
+  pub const ALL: [HookPhase; 4] = [
+    HookPhase::PreBuild,
+    HookPhase::PreRestart,
+    HookPhase::PostDeploy,
+    HookPhase::DeployFailed,
+  ];
+
+  pub fn as_str(self) -> &'static str {
+    match self {
+      HookPhase::PreBuild => "pre-build",
+      HookPhase::PreRestart => "pre-restart",
+      HookPhase::PostDeploy => "post-deploy",
+      HookPhase::DeployFailed => "deploy-failed",
+    }
+  }
+
+  /// Whether a failing hook fails the deploy.
+  pub fn is_fatal(self) -> bool {
+    matches!(self, HookPhase::PreBuild | HookPhase::PreRestart)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PluginKind {
+  Accessory(String),
+  Hook(HookPhase),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plugin {
+  pub name: String,
+  pub path: PathBuf,
+  pub kind: PluginKind,
+}
+
+/// What an accessory plugin prints on stdout.
+#[derive(Debug, Deserialize)]
+pub struct AccessoryPluginOutput {
+  pub compose: String,
+  #[serde(default)]
+  pub env: BTreeMap<String, String>,
+}
+
+pub fn plugins_dir() -> PathBuf {
+  home_dir().join(".config/hl/plugins")
+}
+
+/// What a plugin file name contributes, or None for files that aren't plugins.
+pub fn plugin_kind(name: &str) -> Option<PluginKind> {
+  if let Some(kind) = name.strip_prefix("accessory-") {
+    return (!kind.is_empty()).then(|| PluginKind::Accessory(kind.to_string()));
+  }
+  HookPhase::ALL
+    .into_iter()
+    .find(|phase| {
+      name
+        .strip_prefix(phase.as_str())
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+    })
+    .map(PluginKind::Hook)
+}
+
+/// Executable plugins in `dir`, sorted by name; a missing directory has none.
+pub fn discover_plugins(dir: &Path) -> Result<Vec<Plugin>> {
+  let entries = match std::fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+    Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+  };
+  let mut plugins = Vec::new();
+  for entry in entries {
+    let path = entry?.path();
+    let name = path
+      .file_name()
+      .map(|n| n.to_string_lossy().to_string())
+      .unwrap_or_default();
+    let Some(kind) = plugin_kind(&name) else {
+      continue;
+    };
+    let executable =
+      std::fs::metadata(&path).is_ok_and(|md| md.is_file() && md.permissions().mode() & 0o111 != 0);
+    if !executable {
+      debug(&format!(
+        "ignoring {}: not an executable file",
+        path.display()
+      ));
+      continue;
+    }
+    plugins.push(Plugin { name, path, kind });
+  }
+  plugins.sort_by(|a, b| a.name.cmp(&b.name));
+  Ok(plugins)
+}
+
+/// The plugin providing accessory type `kind`, if one is installed.
+pub fn accessory_plugin(kind: &str) -> Result<Option<Plugin>> {
+  let wanted = PluginKind::Accessory(kind.to_string());
+  Ok(
+    discover_plugins(&plugins_dir())?
+      .into_iter()
+      .find(|p| p.kind == wanted),
+  )
+}
+
+/// Accessory types installed plugins add.
+pub fn accessory_plugin_types() -> Result<Vec<String>> {
+  Ok(
+    discover_plugins(&plugins_dir())?
+      .into_iter()
+      .filter_map(|p| match p.kind {
+        PluginKind::Accessory(kind) => Some(kind),
+        PluginKind::Hook(_) => None,
+      })
+      .collect(),
+  )
+}
+
+/// Run an accessory plugin's `add` with `env` and parse what it prints.
+pub async fn run_accessory_plugin(
+  plugin: &Plugin,
+  env: &[(&str, String)],
+) -> Result<AccessoryPluginOutput> {
+  debug(&format!("running {} add", plugin.path.display()));
+  let output = Command::new(&plugin.path)
+    .arg("add")
+    .envs(env.iter().map(|(k, v)| (k, v)))
+    .stderr(std::process::Stdio::inherit())
+    .output()
+    .await
+    .with_context(|| format!("Failed to run {}", plugin.path.display()))?;
+  if !output.status.success() {
+    anyhow::bail!("plugin {} failed ({})", plugin.name, output.status);
+  }
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  serde_yaml::from_str(&stdout)
+    .with_context(|| format!("plugin {} printed invalid output", plugin.name))
+}
+
+/// Run the hooks of `phase` in order with `env`. A failing hook of a fatal phase stops there
+/// and fails; in the other phases it is warned about and the rest still run.
+pub async fn run_hooks(phase: HookPhase, env: &[(&str, String)]) -> Result<()> {
+  let hooks: Vec<Plugin> = discover_plugins(&plugins_dir())?
+    .into_iter()
+    .filter(|p| p.kind == PluginKind::Hook(phase))
+    .collect();
+  for hook in hooks {
+    log(&format!("running {} hook {}", phase.as_str(), hook.name));
+    let status = Command::new(&hook.path)
+      .envs(env.iter().map(|(k, v)| (k, v)))
+      .env("HL_PHASE", phase.as_str())
+      .stdout(progress_stdout())
+      .status()
+      .await
+      .with_context(|| format!("Failed to run {}", hook.path.display()))?;
+    if !status.success() {
+      let message = format!("{} hook {} failed ({})", phase.as_str(), hook.name, status);
+      if phase.is_fatal() {
+        return Err(Error::Hook(anyhow::anyhow!(message)).into());
+      }
+      warn(&message);
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_discover_plugins() {
+    assert_eq!(
+      plugin_kind("accessory-mongo"),
+      Some(PluginKind::Accessory("mongo".to_string()))
+    );
+    assert_eq!(plugin_kind("accessory-"), None);
+    assert_eq!(
+      plugin_kind("post-deploy"),
+      Some(PluginKind::Hook(HookPhase::PostDeploy))
+    );
+    assert_eq!(
+      plugin_kind("pre-build-assets"),
+      Some(PluginKind::Hook(HookPhase::PreBuild))
+    );
+    assert_eq!(plugin_kind("pre-builder"), None);
+    assert_eq!(plugin_kind("README"), None);
+
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(
+      discover_plugins(&dir.path().join("missing")).unwrap(),
+      vec![]
+    );
+    for (name, mode) in [
+      ("post-deploy-slack", 0o755),
+      ("accessory-mongo", 0o755),
+      ("pre-build", 0o644),
+      ("notes.txt", 0o755),
+    ] {
+      let path = dir.path().join(name);
+      std::fs::write(&path, "#!/bin/sh\n").unwrap();
+      std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+    let names: Vec<String> = discover_plugins(dir.path())
+      .unwrap()
+      .into_iter()
+      .map(|p| p.name)
+      .collect();
+    assert_eq!(names, vec!["accessory-mongo", "post-deploy-slack"]);
+  }
+}
@@ -23,7 +23,11 @@ pub async fn parse_procfile(procfile_path: &Path) -> Result<HashMap<String, Stri
       "Failed to read Procfile at {}",
       procfile_path.display()
     ))?;
+  parse_procfile_content(&content)
+}
 
+/// Parse Procfile text, e.g. a Procfile read from a commit rather than a worktree.
+pub fn parse_procfile_content(content: &str) -> Result<HashMap<String, String>> {
   let mut processes = HashMap::new();
 
   for (line_num, line) in content.lines().enumerate() {
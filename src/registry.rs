@@ -0,0 +1,490 @@
+use crate::config::{hl_root, home_dir};
+use crate::log::{debug, warn};
+use crate::output::from_json;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Compose project of the registry `hl registry serve` runs.
+pub const REGISTRY_PROJECT: &str = "hl-registry";
+
+/// How long `docker login` gets to confirm stored credentials before we give up on it.
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Registry host of an image prefix (`registry.example.com/team` -> `registry.example.com`).
+/// Prefixes without a host (`myuser`) are on Docker Hub.
+pub fn registry_host(registry: &str) -> &str {
+  match registry.split_once('/') {
+    Some((host, _)) if host.contains(['.', ':']) || host == "localhost" => host,
+    None if registry.contains(['.', ':']) => registry,
+    _ => "docker.io",
+  }
+}
+
+/// Contents of docker's client config (`~/.docker/config.json`), empty when there is none.
+pub fn docker_config() -> String {
+  std::fs::read_to_string(home_dir().join(".docker/config.json")).unwrap_or_default()
+}
+
+/// Whether docker's config.json has credentials (or a credential helper) for `host`.
+pub fn has_registry_auth(config_json: &str, host: &str) -> bool {
+  let Ok(config) = from_json::<serde_yaml::Value>(config_json) else {
+    return false;
+  };
+  if config.get("credsStore").is_some() {
+    // The store can't be listed without running it; assume `docker login` went there
+    return true;
+  }
+  ["auths", "credHelpers"]
+    .iter()
+    .any(|section| config_entry(&config, section, host).is_some())
+}
+
+/// The entry of `host` in a config.json section (`auths`, `credHelpers`), whose keys may be
+/// URLs and name Docker Hub `index.docker.io`.
+fn config_entry<'a>(
+  config: &'a serde_yaml::Value,
+  section: &str,
+  host: &str,
+) -> Option<&'a serde_yaml::Value> {
+  let hub = host == "docker.io";
+  config
+    .get(section)?
+    .as_mapping()?
+    .iter()
+    .find(|(key, _)| {
+      key.as_str().is_some_and(|key| {
+        let key = key
+          .trim_start_matches("https://")
+          .trim_start_matches("http://");
+        key.split('/').next() == Some(host) || (hub && key.starts_with("index.docker.io"))
+      })
+    })
+    .map(|(_, entry)| entry)
+}
+
+/// Credentials for a registry in the form the Engine API takes in `X-Registry-Auth`.
+#[derive(Debug, Serialize, PartialEq, Default)]
+pub struct RegistryAuth {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub username: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub password: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub identitytoken: Option<String>,
+  pub serveraddress: String,
+}
+
+/// Where config.json keeps the credentials of a registry.
+#[derive(Debug, PartialEq)]
+pub enum StoredAuth {
+  /// In `auths`, base64 `user:password` or an identity token
+  Inline(RegistryAuth),
+  /// With a credential helper, `docker-credential-<name>`
+  Helper(String),
+  None,
+}
+
+/// Server address docker stores credentials under: Docker Hub keeps its legacy v1 URL.
+fn server_address(host: &str) -> String {
+  if host == "docker.io" {
+    "https://index.docker.io/v1/".to_string()
+  } else {
+    host.to_string()
+  }
+}
+
+/// How config.json stores the credentials of `host`, looked up in the order the docker CLI
+/// uses: `credHelpers`, then `credsStore`, then `auths`.
+pub fn stored_auth(config_json: &str, host: &str) -> StoredAuth {
+  let Ok(config) = from_json::<serde_yaml::Value>(config_json) else {
+    return StoredAuth::None;
+  };
+  let helper = config_entry(&config, "credHelpers", host)
+    .or_else(|| config.get("credsStore"))
+    .and_then(|v| v.as_str());
+  if let Some(helper) = helper {
+    return StoredAuth::Helper(helper.to_string());
+  }
+  let Some(entry) = config_entry(&config, "auths", host) else {
+    return StoredAuth::None;
+  };
+  let field = |name: &str| entry.get(name).and_then(|v| v.as_str()).map(str::to_string);
+  let decoded = field("auth")
+    .and_then(|auth| BASE64.decode(auth).ok())
+    .and_then(|bytes| String::from_utf8(bytes).ok());
+  let (username, password) = match decoded.as_deref().and_then(|d| d.split_once(':')) {
+    Some((user, password)) => (Some(user.to_string()), Some(password.to_string())),
+    None => (None, None),
+  };
+  let identitytoken = field("identitytoken");
+  if username.is_none() && identitytoken.is_none() {
+    return StoredAuth::None;
+  }
+  StoredAuth::Inline(RegistryAuth {
+    username,
+    password,
+    identitytoken,
+    serveraddress: server_address(host),
+  })
+}
+
+/// What `docker-credential-<helper> get` prints.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+  username: String,
+  secret: String,
+}
+
+/// Credentials for `host` from docker's config, running its credential helper if it has one.
+/// None when there are none, so the registry is used anonymously.
+pub async fn registry_auth(host: &str) -> Result<Option<RegistryAuth>> {
+  let helper = match stored_auth(&docker_config(), host) {
+    StoredAuth::Inline(auth) => return Ok(Some(auth)),
+    StoredAuth::None => return Ok(None),
+    StoredAuth::Helper(helper) => helper,
+  };
+  let program = format!("docker-credential-{}", helper);
+  let mut child = Command::new(&program)
+    .arg("get")
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .with_context(|| format!("failed to run {}", program))?;
+  if let Some(mut stdin) = child.stdin.take() {
+    stdin.write_all(server_address(host).as_bytes()).await?;
+  }
+  let output = child.wait_with_output().await?;
+  if !output.status.success() {
+    // Helpers exit non-zero when they hold nothing for the server
+    debug(&format!(
+      "{} get {}: {}",
+      program,
+      host,
+      String::from_utf8_lossy(&output.stdout).trim()
+    ));
+    return Ok(None);
+  }
+  let creds: HelperCredentials = from_json(&String::from_utf8_lossy(&output.stdout))
+    .with_context(|| format!("unexpected output from {}", program))?;
+  let serveraddress = server_address(host);
+  // Helpers hand out identity tokens under this username
+  Ok(Some(if creds.username == "<token>" {
+    RegistryAuth {
+      identitytoken: Some(creds.secret),
+      serveraddress,
+      ..Default::default()
+    }
+  } else {
+    RegistryAuth {
+      username: Some(creds.username),
+      password: Some(creds.secret),
+      identitytoken: None,
+      serveraddress,
+    }
+  }))
+}
+
+/// `docker login` arguments for `host`; Docker Hub is the CLI's default server.
+fn login_args<'a>(host: &'a str, username: Option<&'a str>) -> Vec<&'a str> {
+  let mut args = vec!["login"];
+  if host != "docker.io" {
+    args.push(host);
+  }
+  if let Some(username) = username {
+    args.extend(["--username", username]);
+  }
+  args
+}
+
+/// Log in to `host` through the docker CLI, which prompts for the password (or reads it from
+/// stdin with `password_stdin`) and stores it in the configured credential store.
+pub async fn login(host: &str, username: Option<&str>, password_stdin: bool) -> Result<()> {
+  let mut args = login_args(host, username);
+  if password_stdin {
+    args.push("--password-stdin");
+  }
+  let status = Command::new("docker")
+    .args(&args)
+    .stdin(Stdio::inherit())
+    .stdout(Stdio::inherit())
+    .stderr(Stdio::inherit())
+    .status()
+    .await
+    .context("failed to run docker")?;
+  if !status.success() {
+    anyhow::bail!("docker login to {} failed with status: {}", host, status);
+  }
+  Ok(())
+}
+
+/// Log in to `host` non-interactively, handing docker the password on stdin.
+pub async fn login_with_password(host: &str, username: &str, password: &str) -> Result<()> {
+  let mut args = login_args(host, Some(username));
+  args.push("--password-stdin");
+  let mut child = Command::new("docker")
+    .args(&args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::inherit())
+    .spawn()
+    .context("failed to run docker")?;
+  if let Some(mut stdin) = child.stdin.take() {
+    stdin.write_all(password.as_bytes()).await?;
+  }
+  let status = child.wait().await?;
+  if !status.success() {
+    anyhow::bail!("docker login to {} failed with status: {}", host, status);
+  }
+  Ok(())
+}
+
+/// Check that the stored credentials for `host` still work: `docker login` without a
+/// password re-authenticates with them and fails, rather than prompting, without a terminal.
+pub async fn verify_login(host: &str) -> Result<()> {
+  let output = Command::new("docker")
+    .args(login_args(host, None))
+    .stdin(Stdio::null())
+    .output();
+  let output = tokio::time::timeout(VERIFY_TIMEOUT, output)
+    .await
+    .with_context(|| format!("timed out checking the login to {}", host))?
+    .context("failed to run docker")?;
+  if !output.status.success() {
+    debug(&String::from_utf8_lossy(&output.stderr));
+    anyhow::bail!("credentials for {} are missing or expired", host);
+  }
+  Ok(())
+}
+
+/// Before a deploy spends minutes building, warn when the image likely can't be pushed.
+/// Registries may accept anonymous pushes (e.g. a local registry), which a credsStore or a
+/// failed re-login can't tell apart from a missing login, so this never fails the deploy: the
+/// push reports the registry's own error.
+pub async fn check_push_access(image: &str) {
+  let host = registry_host(image);
+  if host.starts_with("localhost") || host.starts_with("127.") {
+    return;
+  }
+  let problem = if !has_registry_auth(&docker_config(), host) {
+    "no credentials".to_string()
+  } else {
+    match verify_login(host).await {
+      Ok(()) => return,
+      Err(e) => format!("{:#}", e),
+    }
+  };
+  warn(&format!(
+    "{} for {}; pushing {} will fail if the registry requires a login (fix: hl registry login {})",
+    problem, host, image, host
+  ));
+}
+
+/// Where `hl registry serve` keeps the registry's compose file and htpasswd.
+pub fn registry_dir() -> PathBuf {
+  hl_root().join("registry")
+}
+
+/// Traefik route for a registry served under its own domain.
+pub struct RegistryRoute<'a> {
+  pub domain: &'a str,
+  pub network: &'a str,
+  pub resolver: &'a str,
+}
+
+/// compose.yml of the local registry: `registry:2` with its data in a named volume, published
+/// on localhost only (docker allows plain HTTP to localhost registries). With `route`, Traefik
+/// also serves it over HTTPS; with `htpasswd`, pushes and pulls need a login.
+pub fn render_registry_compose(port: u16, route: Option<&RegistryRoute>, htpasswd: bool) -> String {
+  let mut environment = String::new();
+  let mut volumes = String::from("\n      - data:/var/lib/registry");
+  if htpasswd {
+    environment.push_str(
+      r#"
+    environment:
+      REGISTRY_AUTH: htpasswd
+      REGISTRY_AUTH_HTPASSWD_REALM: hl
+      REGISTRY_AUTH_HTPASSWD_PATH: /auth/htpasswd"#,
+    );
+    volumes.push_str("\n      - ./htpasswd:/auth/htpasswd:ro");
+  }
+
+  let (labels, networks, external) = match route {
+    Some(route) => (
+      format!(
+        r#"
+    labels:
+      traefik.enable: true
+      traefik.http.routers.{project}.rule: Host(`{domain}`)
+      traefik.http.routers.{project}.entrypoints: websecure
+      traefik.http.routers.{project}.tls.certresolver: {resolver}
+      traefik.http.services.{project}.loadbalancer.server.port: 5000"#,
+        project = REGISTRY_PROJECT,
+        domain = route.domain,
+        resolver = route.resolver
+      ),
+      format!("\n    networks: [default, {}]", route.network),
+      format!(
+        "\n  {network}:\n    external: true\n    name: {network}",
+        network = route.network
+      ),
+    ),
+    None => (String::new(), String::new(), String::new()),
+  };
+
+  format!(
+    r#"services:
+  registry:
+    image: registry:2
+    restart: unless-stopped
+    ports: ["127.0.0.1:{port}:5000"]{environment}
+    volumes:{volumes}{labels}{networks}
+networks:
+  default:{external}
+volumes:
+  data:
+"#
+  )
+}
+
+/// A bcrypt htpasswd line for `username`, the only hash format the registry accepts. Made by
+/// the htpasswd in the httpd image, which saves a bcrypt dependency. The password goes in on
+/// stdin so it never shows up in the process list.
+pub async fn htpasswd_line(username: &str, password: &str) -> Result<String> {
+  let mut child = Command::new("docker")
+    .args([
+      "run",
+      "--rm",
+      "-i",
+      "--entrypoint",
+      "htpasswd",
+      "httpd:2-alpine",
+      "-iBn",
+      username,
+    ])
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::inherit())
+    .spawn()
+    .context("failed to run docker")?;
+  if let Some(mut stdin) = child.stdin.take() {
+    stdin.write_all(password.as_bytes()).await?;
+  }
+  let output = child.wait_with_output().await?;
+  if !output.status.success() {
+    anyhow::bail!(
+      "generating the htpasswd file failed with status: {}",
+      output.status
+    );
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).trim().to_string() + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_registry_host_and_auth() {
+    assert_eq!(
+      registry_host("registry.example.com/team"),
+      "registry.example.com"
+    );
+    assert_eq!(registry_host("localhost:5000"), "localhost:5000");
+    assert_eq!(registry_host("myuser"), "docker.io");
+
+    let config = r#"{"auths": {"registry.example.com": {"auth": "eA=="},
+      "https://index.docker.io/v1/": {}}}"#;
+    assert!(has_registry_auth(config, "registry.example.com"));
+    assert!(has_registry_auth(config, "docker.io"));
+    assert!(!has_registry_auth(config, "ghcr.io"));
+    assert!(has_registry_auth(
+      r#"{"credHelpers": {"ghcr.io": "gh"}}"#,
+      "ghcr.io"
+    ));
+
+    assert_eq!(
+      login_args("docker.io", Some("me")),
+      ["login", "--username", "me"]
+    );
+    assert_eq!(login_args("ghcr.io", None), ["login", "ghcr.io"]);
+  }
+
+  #[test]
+  fn test_stored_auth() {
+    // "me:s3cret"
+    let config = r#"{"auths": {"https://index.docker.io/v1/": {"auth": "bWU6czNjcmV0"},
+      "registry.example.com": {"identitytoken": "tok"}},
+      "credHelpers": {"ghcr.io": "gh"}}"#;
+    assert_eq!(
+      stored_auth(config, "docker.io"),
+      StoredAuth::Inline(RegistryAuth {
+        username: Some("me".to_string()),
+        password: Some("s3cret".to_string()),
+        identitytoken: None,
+        serveraddress: "https://index.docker.io/v1/".to_string(),
+      })
+    );
+    assert_eq!(
+      stored_auth(config, "registry.example.com"),
+      StoredAuth::Inline(RegistryAuth {
+        identitytoken: Some("tok".to_string()),
+        serveraddress: "registry.example.com".to_string(),
+        ..Default::default()
+      })
+    );
+    assert_eq!(
+      stored_auth(config, "ghcr.io"),
+      StoredAuth::Helper("gh".to_string())
+    );
+    assert_eq!(stored_auth(config, "quay.io"), StoredAuth::None);
+    assert_eq!(
+      stored_auth(r#"{"credsStore": "desktop"}"#, "quay.io"),
+      StoredAuth::Helper("desktop".to_string())
+    );
+  }
+
+  #[test]
+  fn test_render_registry_compose() {
+    let plain = render_registry_compose(5000, None, false);
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&plain).unwrap();
+    assert_eq!(
+      parsed["services"]["registry"]["ports"][0].as_str(),
+      Some("127.0.0.1:5000:5000")
+    );
+    assert!(!plain.contains("REGISTRY_AUTH"));
+    assert!(!plain.contains("traefik"));
+
+    let route = RegistryRoute {
+      domain: "registry.example.com",
+      network: "traefik_proxy",
+      resolver: "le",
+    };
+    let routed = render_registry_compose(5001, Some(&route), true);
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&routed).unwrap();
+    let service = &parsed["services"]["registry"];
+    assert_eq!(
+      service["environment"]["REGISTRY_AUTH"].as_str(),
+      Some("htpasswd")
+    );
+    assert_eq!(
+      service["volumes"][1].as_str(),
+      Some("./htpasswd:/auth/htpasswd:ro")
+    );
+    assert_eq!(
+      service["labels"]["traefik.http.routers.hl-registry.rule"].as_str(),
+      Some("Host(`registry.example.com`)")
+    );
+    assert_eq!(
+      parsed["networks"]["traefik_proxy"]["external"].as_bool(),
+      Some(true)
+    );
+  }
+}
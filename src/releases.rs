@@ -0,0 +1,723 @@
+use crate::config::{app_dir, env_file, hl_root, parse_duration, HLConfig, RetentionConfig};
+use crate::docker::{image_id, remove_image};
+use crate::git::prune_stale_worktrees;
+use crate::log::{debug, warn};
+use crate::time::parse_rfc3339;
+use anyhow::{Context, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+Every successful deploy writes a manifest to ~/hl/apps/<app>/releases/<deployed_at>-<sha>.yml,
+next to a snapshot of the app's .env (<deployed_at>-<sha>.env.enc) whose sha256 is kept in the
+manifest, so `hl env restore --release <sha>` can bring back what that code ran with. Snapshots
+are sealed with AES-256-GCM under a key generated once per host (~/hl/apps/.env-snapshot.key),
+which lives outside every app dir: release directories and `hl backup` archives only ever hold
+ciphertext, and restoring a snapshot on another host needs that key copied along.
+Manifests are what `hl releases gc` counts against the retention policy: when one expires its
+image tags are removed from the local docker daemon as well, unless a retained release or the
+current :latest still points at them. Deploy logs (logs/) and backups (backups/) are pruned in
+the same pass, by modification time.
+ */
+
+/// Record of a single successful deploy.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Release {
+  pub sha: String,
+  /// Pushed branch; None for releases deployed from a tag
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub branch: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub tag: Option<String>,
+  /// Image references built for this release, excluding :latest
+  pub images: Vec<String>,
+  /// Unix seconds
+  pub deployed_at: u64,
+  /// `env_digest` of the .env snapshot taken at deploy time
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub env_sha256: Option<String>,
+}
+
+impl Release {
+  pub fn short_sha(&self) -> &str {
+    short_sha(&self.sha)
+  }
+}
+
+/// The 7-character abbreviation hl names images, logs and manifests by; shorter input is
+/// returned as is.
+pub fn short_sha(sha: &str) -> &str {
+  sha.get(..7).unwrap_or(sha)
+}
+
+/// A release together with the manifest it was loaded from.
+pub type ReleaseEntry = (PathBuf, Release);
+
+/// A locally available image and every tag pointing at it, correlated with release manifests.
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageRelease {
+  pub id: String,
+  /// Short sha tag, usable as `hl rollback <sha>`
+  pub sha: Option<String>,
+  pub branch: Option<String>,
+  pub version: Option<String>,
+  pub created: String,
+  pub deployed_at: Option<u64>,
+  pub latest: bool,
+}
+
+/// What a gc pass removed.
+#[derive(Debug, Default, PartialEq)]
+pub struct GcReport {
+  pub manifests: usize,
+  pub images: usize,
+  pub logs: usize,
+  pub backups: usize,
+  pub worktrees: usize,
+}
+
+pub fn releases_dir(app: &str) -> PathBuf {
+  app_dir(app).join("releases")
+}
+
+pub fn logs_dir(app: &str) -> PathBuf {
+  app_dir(app).join("logs")
+}
+
+/// Full buildx output of the deploy of `sha`.
+pub fn build_log_path(app: &str, sha: &str) -> PathBuf {
+  logs_dir(app).join(format!("build-{}.log", short_sha(sha)))
+}
+
+/// Build log of the image `process` builds from its own Dockerfile in the release of `sha`.
+pub fn process_build_log_path(app: &str, sha: &str, process: &str) -> PathBuf {
+  logs_dir(app).join(format!("build-{}-{}.log", short_sha(sha), process))
+}
+
+/// Operation log (everything hl printed) of the deploy of `sha`.
+pub fn deploy_log_path(app: &str, sha: &str) -> PathBuf {
+  logs_dir(app).join(format!("deploy-{}.log", short_sha(sha)))
+}
+
+/// Build log for `sha` (full or short), or the most recent one when `sha` is None.
+pub fn find_build_log(app: &str, sha: Option<&str>) -> Result<Option<PathBuf>> {
+  match sha {
+    Some(sha) => {
+      let path = build_log_path(app, sha);
+      Ok(path.exists().then_some(path))
+    }
+    None => Ok(list_logs(app, "build-")?.into_iter().next()),
+  }
+}
+
+/// Logs in `logs_dir` whose name starts with `prefix`, newest first.
+pub fn list_logs(app: &str, prefix: &str) -> Result<Vec<PathBuf>> {
+  let dir = logs_dir(app);
+  if !dir.exists() {
+    return Ok(Vec::new());
+  }
+  let mut logs = Vec::new();
+  for entry in fs::read_dir(&dir)? {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().to_string();
+    if name.starts_with(prefix) && name.ends_with(".log") {
+      logs.push((entry.metadata()?.modified()?, entry.path()));
+    }
+  }
+  logs.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| b.1.cmp(&a.1)));
+  Ok(logs.into_iter().map(|(_, path)| path).collect())
+}
+
+/// How a deploy went, from its log (`<rfc3339> <level> <message>` lines).
+#[derive(Debug, PartialEq)]
+pub struct DeployLogSummary {
+  /// Start of the latest attempt; redeploying a sha appends to its log
+  pub started: Option<u64>,
+  /// Time of the last line
+  pub finished: Option<u64>,
+  /// `ok`, `failed: <error>` or `incomplete` (still running, or killed)
+  pub result: String,
+}
+
+impl DeployLogSummary {
+  pub fn succeeded(&self) -> bool {
+    self.result == "ok"
+  }
+}
+
+pub fn summarize_deploy_log(content: &str) -> DeployLogSummary {
+  let level = |line: &str| {
+    line
+      .split_whitespace()
+      .nth(1)
+      .unwrap_or_default()
+      .to_string()
+  };
+  let time = |line: &str| line.split_whitespace().next().and_then(parse_rfc3339);
+  let message = |line: &str| {
+    line
+      .splitn(3, char::is_whitespace)
+      .nth(2)
+      .unwrap_or_default()
+      .trim()
+      .to_string()
+  };
+  let lines: Vec<&str> = content.lines().collect();
+  let start = lines
+    .iter()
+    .rposition(|l| level(l) == "info" && message(l).starts_with("deploying "))
+    .unwrap_or(0);
+  let attempt = &lines[start..];
+  // Messages logged after the outcome (notifications, metrics) don't change it
+  let outcome = attempt.iter().find_map(|l| match level(l).as_str() {
+    "error" if message(l).starts_with("deploy failed") => {
+      Some((format!("failed: {}", message(l)), l))
+    }
+    // Logs from before levels were normalized record successes as `ok`
+    "info" | "ok" if message(l) == "deploy complete" => Some(("ok".to_string(), l)),
+    _ => None,
+  });
+  let (result, finished) = match outcome {
+    Some((result, line)) => (result, time(line)),
+    None => (
+      "incomplete".to_string(),
+      attempt.last().and_then(|l| time(l)),
+    ),
+  };
+  DeployLogSummary {
+    started: attempt.first().and_then(|l| time(l)),
+    finished,
+    result,
+  }
+}
+
+pub fn backups_dir(app: &str) -> PathBuf {
+  app_dir(app).join("backups")
+}
+
+/// Snapshot the app's current .env and write the manifest for a deploy. Returns the manifest
+/// path.
+pub fn record_release(app: &str, mut release: Release) -> Result<PathBuf> {
+  let dir = releases_dir(app);
+  fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+  let path = dir.join(format!(
+    "{}-{}.yml",
+    release.deployed_at,
+    release.short_sha()
+  ));
+
+  let env_path = env_file(app);
+  if env_path.exists() {
+    let content = fs::read(&env_path)?;
+    let snapshot = env_snapshot_path(&path);
+    write_private(&snapshot, &seal_snapshot(&content)?)
+      .with_context(|| format!("Failed to write env snapshot {}", snapshot.display()))?;
+    release.env_sha256 = Some(env_digest(&content));
+  }
+
+  fs::write(&path, serde_yaml::to_string(&release)?)
+    .with_context(|| format!("Failed to write release manifest {}", path.display()))?;
+  debug(&format!("recorded release manifest: {}", path.display()));
+  Ok(path)
+}
+
+/// Where the .env snapshot of the release described by `manifest` lives.
+pub fn env_snapshot_path(manifest: &Path) -> PathBuf {
+  manifest.with_extension("env.enc")
+}
+
+/// sha256 of an env file, hex-encoded.
+pub fn env_digest(content: &[u8]) -> String {
+  ring::digest::digest(&ring::digest::SHA256, content)
+    .as_ref()
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+fn snapshot_key_path() -> PathBuf {
+  hl_root().join(".env-snapshot.key")
+}
+
+/// The host's snapshot key, generated on first use when `create` is set.
+fn snapshot_key(create: bool) -> Result<LessSafeKey> {
+  let path = snapshot_key_path();
+  let bytes = if path.exists() || !create {
+    fs::read(&path).with_context(|| {
+      format!(
+        "Failed to read the env snapshot key {} (snapshots can only be opened on the host that took them, or with its key copied over)",
+        path.display()
+      )
+    })?
+  } else {
+    let mut bytes = vec![0u8; AES_256_GCM.key_len()];
+    SystemRandom::new()
+      .fill(&mut bytes)
+      .map_err(|_| anyhow::anyhow!("failed to generate an env snapshot key"))?;
+    fs::create_dir_all(hl_root())?;
+    write_private(&path, &bytes).with_context(|| format!("Failed to write {}", path.display()))?;
+    bytes
+  };
+  let key = UnboundKey::new(&AES_256_GCM, &bytes)
+    .map_err(|_| anyhow::anyhow!("{} is not a valid env snapshot key", path.display()))?;
+  Ok(LessSafeKey::new(key))
+}
+
+/// `content` encrypted with the host's snapshot key: a random nonce, then the ciphertext and
+/// its tag.
+fn seal_snapshot(content: &[u8]) -> Result<Vec<u8>> {
+  let key = snapshot_key(true)?;
+  let mut nonce = [0u8; NONCE_LEN];
+  SystemRandom::new()
+    .fill(&mut nonce)
+    .map_err(|_| anyhow::anyhow!("failed to generate a nonce"))?;
+  let mut sealed = content.to_vec();
+  key
+    .seal_in_place_append_tag(
+      Nonce::assume_unique_for_key(nonce),
+      Aad::empty(),
+      &mut sealed,
+    )
+    .map_err(|_| anyhow::anyhow!("failed to encrypt the env snapshot"))?;
+  Ok([nonce.as_slice(), &sealed].concat())
+}
+
+/// Decrypt a snapshot written by `seal_snapshot`.
+pub fn open_snapshot(sealed: &[u8]) -> Result<Vec<u8>> {
+  let key = snapshot_key(false)?;
+  if sealed.len() < NONCE_LEN {
+    anyhow::bail!("env snapshot is truncated");
+  }
+  let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+  let nonce = Nonce::try_assume_unique_for_key(nonce)
+    .map_err(|_| anyhow::anyhow!("env snapshot is truncated"))?;
+  let mut content = ciphertext.to_vec();
+  let plain = key
+    .open_in_place(nonce, Aad::empty(), &mut content)
+    .map_err(|_| anyhow::anyhow!("env snapshot can't be decrypted with this host's key"))?;
+  Ok(plain.to_vec())
+}
+
+/// Write a file only its owner can read.
+fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+  use std::io::Write;
+  use std::os::unix::fs::OpenOptionsExt;
+  let mut file = fs::OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .mode(0o600)
+    .open(path)?;
+  file.write_all(content)
+}
+
+/// Newest release whose sha starts with `sha` (full or short).
+pub fn find_release(app: &str, sha: &str) -> Result<Option<ReleaseEntry>> {
+  Ok(
+    load_releases(&releases_dir(app))?
+      .into_iter()
+      .find(|(_, r)| r.sha.starts_with(sha) || sha.starts_with(r.short_sha())),
+  )
+}
+
+/// Load every manifest in `dir`, newest first. Unreadable manifests are skipped with a warning.
+pub fn load_releases(dir: &Path) -> Result<Vec<ReleaseEntry>> {
+  if !dir.exists() {
+    return Ok(Vec::new());
+  }
+  let mut releases = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.extension().and_then(|e| e.to_str()) != Some("yml") {
+      continue;
+    }
+    let parsed = fs::read_to_string(&path)
+      .map_err(anyhow::Error::from)
+      .and_then(|content| Ok(serde_yaml::from_str::<Release>(&content)?));
+    match parsed {
+      Ok(release) => releases.push((path, release)),
+      Err(e) => warn(&format!("skipping {}: {}", path.display(), e)),
+    }
+  }
+  releases.sort_by_key(|(_, r)| Reverse(r.deployed_at));
+  Ok(releases)
+}
+
+/// Split releases (newest first) into those to keep and those past retention. At least one
+/// release is always kept.
+pub fn partition_releases(
+  releases: Vec<ReleaseEntry>,
+  keep: usize,
+) -> (Vec<ReleaseEntry>, Vec<ReleaseEntry>) {
+  let mut kept = releases;
+  let expired = kept.split_off(keep.max(1).min(kept.len()));
+  (kept, expired)
+}
+
+/// Files in `dir` beyond the newest `keep`, by modification time.
+pub fn expired_files(dir: &Path, keep: usize) -> Result<Vec<PathBuf>> {
+  if !dir.exists() {
+    return Ok(Vec::new());
+  }
+  let mut files = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let meta = entry.metadata()?;
+    if meta.is_file() {
+      files.push((meta.modified()?, entry.path()));
+    }
+  }
+  files.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| b.1.cmp(&a.1)));
+  Ok(files.into_iter().skip(keep).map(|(_, p)| p).collect())
+}
+
+/// Group `docker image ls` lines (see `list_image_tags`) by image ID, classify each tag
+/// (`latest`, `<sha>`, `<branch>-<sha>` or a release version) and fill in what the manifests
+/// know about the sha. Keeps docker's newest-first order.
+pub fn correlate_image_tags(listing: &str, releases: &[ReleaseEntry]) -> Vec<ImageRelease> {
+  let short_sha = regex::Regex::new(r"^[0-9a-f]{7}$").expect("valid regex");
+  let branch_sha = regex::Regex::new(r"^(.+)-([0-9a-f]{7})$").expect("valid regex");
+
+  let mut images: Vec<ImageRelease> = Vec::new();
+  for line in listing.lines() {
+    let mut fields = line.split('\t');
+    let (Some(tag), Some(id)) = (fields.next(), fields.next()) else {
+      continue;
+    };
+    if tag == "<none>" {
+      continue;
+    }
+    let created = fields.next().unwrap_or_default();
+
+    let idx = match images.iter().position(|i| i.id == id) {
+      Some(idx) => idx,
+      None => {
+        images.push(ImageRelease {
+          id: id.to_string(),
+          created: created.to_string(),
+          ..Default::default()
+        });
+        images.len() - 1
+      }
+    };
+    let image = &mut images[idx];
+
+    if tag == "latest" {
+      image.latest = true;
+    } else if short_sha.is_match(tag) {
+      image.sha = Some(tag.to_string());
+    } else if let Some(caps) = branch_sha.captures(tag) {
+      image.branch = Some(caps[1].to_string());
+      image.sha.get_or_insert_with(|| caps[2].to_string());
+    } else {
+      image.version = Some(tag.to_string());
+    }
+  }
+
+  for image in &mut images {
+    let Some(sha) = &image.sha else {
+      continue;
+    };
+    // Manifests are newest first, so a redeployed sha reports its latest deploy
+    if let Some((_, release)) = releases.iter().find(|(_, r)| r.short_sha() == sha) {
+      image.deployed_at = Some(release.deployed_at);
+      if image.branch.is_none() {
+        image.branch = release.branch.clone();
+      }
+      if image.version.is_none() {
+        image.version = release.tag.clone();
+      }
+    }
+  }
+
+  images
+}
+
+/// Prune release manifests, their images, deploy logs and backups past the retention policy.
+pub async fn gc(cfg: &HLConfig, retention: &RetentionConfig) -> Result<GcReport> {
+  let mut report = GcReport::default();
+
+  let releases = load_releases(&releases_dir(&cfg.app))?;
+  let (kept, expired) = partition_releases(releases, retention.releases);
+
+  // Images still referenced by a retained release (e.g. the same sha redeployed) or by
+  // :latest (e.g. after a rollback to an old release) must survive.
+  let retained: HashSet<&String> = kept.iter().flat_map(|(_, r)| r.images.iter()).collect();
+  let latest_id = image_id(&format!("{}:latest", cfg.image)).await?;
+
+  for (path, release) in &expired {
+    for image in &release.images {
+      if retained.contains(image) {
+        continue;
+      }
+      let Some(id) = image_id(image).await? else {
+        continue;
+      };
+      if latest_id.as_ref() == Some(&id) {
+        debug(&format!("keeping {}: it is the current :latest", image));
+        continue;
+      }
+      match remove_image(image).await {
+        Ok(()) => report.images += 1,
+        Err(e) => warn(&format!("failed to remove image {}: {}", image, e)),
+      }
+    }
+    let snapshot = env_snapshot_path(path);
+    if snapshot.exists() {
+      fs::remove_file(&snapshot)
+        .with_context(|| format!("Failed to remove {}", snapshot.display()))?;
+    }
+    fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    report.manifests += 1;
+  }
+
+  for path in expired_files(&logs_dir(&cfg.app), retention.logs)? {
+    fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    report.logs += 1;
+  }
+  for path in expired_files(&backups_dir(&cfg.app), retention.backups)? {
+    fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    report.backups += 1;
+  }
+
+  let max_age = parse_duration(&retention.worktrees).context("invalid retention.worktrees")?;
+  report.worktrees = prune_stale_worktrees(
+    &std::env::temp_dir(),
+    std::time::Duration::from_millis(max_age),
+  )?;
+
+  Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serial_test::serial;
+  use tempfile::TempDir;
+
+  #[test]
+  fn test_summarize_deploy_log() {
+    let ok = "2024-05-01T10:00:00Z info  deploying abcdef0 (main)\n\
+              2024-05-01T10:03:00Z ok    deploy complete\n";
+    assert_eq!(
+      summarize_deploy_log(ok),
+      DeployLogSummary {
+        started: Some(1714557600),
+        finished: Some(1714557780),
+        result: "ok".to_string()
+      }
+    );
+    let ok = "2024-05-01T10:00:00Z info  deploying abcdef0 (main)\n\
+              2024-05-01T10:03:00Z info  deploy complete\n";
+    assert!(summarize_deploy_log(ok).succeeded());
+
+    // A failed attempt followed by a redeploy of the same sha
+    let failed = "2024-05-01T10:00:00Z info  deploying abcdef0 (main)\n\
+                  2024-05-01T10:01:00Z error deploy failed: health check timed out\n\
+                  2024-05-01T11:00:00Z info  deploying abcdef0 (main)\n\
+                  2024-05-01T11:00:30Z error deploy failed: health check timed out\n";
+    let summary = summarize_deploy_log(failed);
+    assert_eq!(
+      summary.result,
+      "failed: deploy failed: health check timed out"
+    );
+    assert_eq!(summary.started, Some(1714561200));
+    assert_eq!(summary.finished, Some(1714561230));
+
+    assert_eq!(summarize_deploy_log("").result, "incomplete");
+
+    let notified = "2024-05-01T10:00:00Z info  deploying abcdef0 (main)\n\
+                    2024-05-01T10:03:00Z info  deploy complete\n\
+                    2024-05-01T10:03:01Z debug refreshed metrics in /var/lib/node/hl.prom\n";
+    let summary = summarize_deploy_log(notified);
+    assert!(summary.succeeded());
+    assert_eq!(summary.finished, Some(1714557780));
+  }
+
+  fn release(sha: &str, deployed_at: u64) -> Release {
+    Release {
+      sha: sha.to_string(),
+      branch: Some("master".to_string()),
+      tag: None,
+      images: vec![format!("registry.example.com/testapp:{}", short_sha(sha))],
+      deployed_at,
+      env_sha256: None,
+    }
+  }
+
+  #[test]
+  #[serial]
+  fn test_record_and_load_releases_newest_first() {
+    let temp_dir = TempDir::new().unwrap();
+    std::env::set_var("HL_ROOT_OVERRIDE", temp_dir.path());
+
+    record_release("testapp", release("aaaaaaa111", 100)).unwrap();
+    let path = record_release("testapp", release("bbbbbbb222", 200)).unwrap();
+    assert!(path.ends_with("releases/200-bbbbbbb.yml"));
+
+    let releases = load_releases(&releases_dir("testapp")).unwrap();
+    let shas: Vec<_> = releases.iter().map(|(_, r)| r.sha.as_str()).collect();
+    assert_eq!(shas, vec!["bbbbbbb222", "aaaaaaa111"]);
+    assert_eq!(releases[0].1, release("bbbbbbb222", 200));
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+  }
+
+  #[test]
+  #[serial]
+  fn test_record_release_snapshots_env() {
+    let temp_dir = TempDir::new().unwrap();
+    std::env::set_var("HL_ROOT_OVERRIDE", temp_dir.path());
+    fs::create_dir_all(app_dir("testapp")).unwrap();
+    fs::write(env_file("testapp"), "KEY=old\n").unwrap();
+
+    let path = record_release("testapp", release("aaaaaaa111", 100)).unwrap();
+    fs::write(env_file("testapp"), "KEY=new\n").unwrap();
+
+    let (found, recorded) = find_release("testapp", "aaaaaaa").unwrap().unwrap();
+    assert_eq!(found, path);
+    assert_eq!(recorded.env_sha256, Some(env_digest(b"KEY=old\n")));
+    let sealed = fs::read(env_snapshot_path(&path)).unwrap();
+    assert!(!sealed.windows(7).any(|w| w == b"KEY=old"));
+    assert_eq!(open_snapshot(&sealed).unwrap(), b"KEY=old\n");
+    // Tampering with the ciphertext is detected
+    let mut tampered = sealed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(open_snapshot(&tampered).is_err());
+    assert!(find_release("testapp", "bbbbbbb").unwrap().is_none());
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+  }
+
+  #[test]
+  #[serial]
+  fn test_find_build_log() {
+    let temp_dir = TempDir::new().unwrap();
+    std::env::set_var("HL_ROOT_OVERRIDE", temp_dir.path());
+    assert!(find_build_log("testapp", None).unwrap().is_none());
+
+    fs::create_dir_all(logs_dir("testapp")).unwrap();
+    let path = build_log_path("testapp", "abcdef0123456");
+    assert!(path.ends_with("logs/build-abcdef0.log"));
+    fs::write(&path, "#1 [internal] load build definition\n").unwrap();
+
+    assert_eq!(find_build_log("testapp", None).unwrap(), Some(path.clone()));
+    assert_eq!(
+      find_build_log("testapp", Some("abcdef0")).unwrap(),
+      Some(path)
+    );
+    assert!(find_build_log("testapp", Some("1234567"))
+      .unwrap()
+      .is_none());
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+  }
+
+  #[test]
+  fn test_partition_releases_keeps_at_least_one() {
+    let releases: Vec<_> = [300, 200, 100]
+      .iter()
+      .map(|&t| {
+        (
+          PathBuf::from(format!("{}.yml", t)),
+          release("abcdef0123", t),
+        )
+      })
+      .collect();
+
+    let (kept, expired) = partition_releases(releases.clone(), 2);
+    assert_eq!(kept.len(), 2);
+    assert_eq!(expired[0].1.deployed_at, 100);
+
+    let (kept, expired) = partition_releases(releases.clone(), 0);
+    assert_eq!(kept.len(), 1);
+    assert_eq!(expired.len(), 2);
+
+    let (kept, expired) = partition_releases(releases, 10);
+    assert_eq!(kept.len(), 3);
+    assert!(expired.is_empty());
+  }
+
+  #[test]
+  fn test_correlate_image_tags() {
+    let listing = "latest\tid2\t2 hours ago\n\
+                   v1.4.2\tid2\t2 hours ago\n\
+                   bbbbbbb\tid2\t2 hours ago\n\
+                   main-bbbbbbb\tid2\t2 hours ago\n\
+                   aaaaaaa\tid1\t3 days ago\n\
+                   <none>\tid0\t5 days ago\n\
+                   feature-x-ccccccc\tid3\t6 days ago\n";
+    let mut old = release("aaaaaaa111", 100);
+    old.branch = Some("master".to_string());
+    let releases = vec![(PathBuf::from("100-aaaaaaa.yml"), old)];
+
+    let images = correlate_image_tags(listing, &releases);
+
+    assert_eq!(images.len(), 3);
+    assert_eq!(
+      images[0],
+      ImageRelease {
+        id: "id2".to_string(),
+        sha: Some("bbbbbbb".to_string()),
+        branch: Some("main".to_string()),
+        version: Some("v1.4.2".to_string()),
+        created: "2 hours ago".to_string(),
+        deployed_at: None,
+        latest: true,
+      }
+    );
+    assert_eq!(images[1].sha.as_deref(), Some("aaaaaaa"));
+    assert_eq!(images[1].branch.as_deref(), Some("master"));
+    assert_eq!(images[1].deployed_at, Some(100));
+    assert!(!images[1].latest);
+    assert_eq!(images[2].branch.as_deref(), Some("feature-x"));
+    assert_eq!(images[2].sha.as_deref(), Some("ccccccc"));
+  }
+
+  #[test]
+  fn test_short_sha() {
+    assert_eq!(short_sha("abc1234567890"), "abc1234");
+    assert_eq!(short_sha("abc12"), "abc12");
+    assert_eq!(short_sha("abcdef€gh"), "abcdef€gh");
+  }
+
+  #[test]
+  fn test_correlate_tag_release() {
+    // A pushed tag gets :<sha> and :<version>, but no <branch>-<sha> named after the tag
+    let listing = "v1.4.2\tid4\t1 hour ago\n\
+                   ddddddd\tid4\t1 hour ago\n";
+    let mut tagged = release("ddddddd444", 400);
+    tagged.branch = None;
+    tagged.tag = Some("v1.4.2".to_string());
+    let releases = vec![(PathBuf::from("400-ddddddd.yml"), tagged)];
+
+    let images = correlate_image_tags(listing, &releases);
+
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].sha.as_deref(), Some("ddddddd"));
+    assert_eq!(images[0].version.as_deref(), Some("v1.4.2"));
+    assert_eq!(images[0].branch, None);
+    assert_eq!(images[0].deployed_at, Some(400));
+  }
+
+  #[test]
+  fn test_expired_files() {
+    let temp_dir = TempDir::new().unwrap();
+    for name in ["a.log", "b.log", "c.log"] {
+      fs::write(temp_dir.path().join(name), "").unwrap();
+    }
+
+    assert_eq!(expired_files(temp_dir.path(), 2).unwrap().len(), 1);
+    assert!(expired_files(temp_dir.path(), 5).unwrap().is_empty());
+    assert!(expired_files(&temp_dir.path().join("missing"), 0)
+      .unwrap()
+      .is_empty());
+  }
+}
@@ -0,0 +1,200 @@
+use crate::config::load_global_config;
+use crate::git::parse_app_name_from_remote_url;
+use crate::log::debug;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::io::IsTerminal;
+use tokio::process::Command;
+
+/// Where `hl` is installed on the server unless `remote.hl` says otherwise.
+const DEFAULT_REMOTE_HL: &str = "~/.local/bin/hl";
+
+/// `remote:` in `~/.config/hl/config.yml` on a workstation: run every command on this host.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct RemoteConfig {
+  /// SSH destination, e.g. `deploy@homelab.local` or a Host alias from ~/.ssh/config
+  pub host: String,
+  /// Path of the hl binary on the server
+  #[serde(default)]
+  pub hl: Option<String>,
+}
+
+/// Host to forward the command to: `--host`, then `HL_HOST`, then `remote.host` in the
+/// global config. `None` runs the command on this machine.
+pub async fn resolve_remote(flag: Option<&str>) -> Result<Option<RemoteConfig>> {
+  let configured = load_global_config().await?.remote;
+  let host = flag
+    .map(str::to_string)
+    .or_else(|| std::env::var("HL_HOST").ok())
+    .filter(|h| !h.trim().is_empty());
+  let remote = match host {
+    Some(host) => Some(RemoteConfig {
+      host,
+      hl: configured.and_then(|r| r.hl),
+    }),
+    None => configured,
+  };
+  if let Some(remote) = &remote {
+    validate_host(&remote.host)?;
+  }
+  Ok(remote)
+}
+
+/// ssh would take a destination starting with `-` for one of its options (`-oProxyCommand=...`
+/// runs a local command).
+fn validate_host(host: &str) -> Result<()> {
+  if host.starts_with('-') {
+    anyhow::bail!("invalid host {:?}: it can't start with '-'", host);
+  }
+  Ok(())
+}
+
+/// Run `hl <args>` on the remote host over SSH, streaming its output, and return its exit code.
+/// `args` are this process's arguments; `--host` is dropped so the server runs locally.
+pub async fn run_remote(remote: &RemoteConfig, args: &[String]) -> Result<i32> {
+  let app = match std::env::var("HL_APP") {
+    Ok(app) if !app.trim().is_empty() => Some(app),
+    _ => infer_remote_app().await?,
+  };
+  let hl = remote.hl.as_deref().unwrap_or(DEFAULT_REMOTE_HL);
+  let command = remote_command(hl, app.as_deref(), &strip_host_arg(args));
+  let tty = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+  let ssh_args = ssh_args(&remote.host, tty, &command);
+  debug(&format!("ssh {}", ssh_args.join(" ")));
+
+  let status = Command::new("ssh")
+    .args(&ssh_args)
+    .status()
+    .await
+    .context("failed to run ssh")?;
+  // ssh exits 255 on its own errors, otherwise with the remote command's status
+  Ok(status.code().unwrap_or(1))
+}
+
+/// App of the current checkout, from its `.../hl/git/<app>.git` remote.
+async fn infer_remote_app() -> Result<Option<String>> {
+  let output = Command::new("git").args(["remote", "-v"]).output().await;
+  let Ok(output) = output else {
+    return Ok(None);
+  };
+  let mut apps: Vec<String> = String::from_utf8_lossy(&output.stdout)
+    .lines()
+    .filter_map(|line| line.split_whitespace().nth(1))
+    .filter_map(parse_app_name_from_remote_url)
+    .collect();
+  apps.sort();
+  apps.dedup();
+  match apps.len() {
+    0 => Ok(None),
+    1 => Ok(apps.pop()),
+    _ => anyhow::bail!(
+      "multiple hl remotes found ({}); set HL_APP explicitly",
+      apps.join(", ")
+    ),
+  }
+}
+
+/// Drop `--host <h>` / `--host=<h>` from the arguments, leaving anything after `--` alone.
+fn strip_host_arg(args: &[String]) -> Vec<String> {
+  let mut out = Vec::with_capacity(args.len());
+  let mut iter = args.iter();
+  while let Some(arg) = iter.next() {
+    if arg == "--" {
+      out.push(arg.clone());
+      out.extend(iter.cloned());
+      break;
+    }
+    if arg == "--host" {
+      iter.next();
+    } else if !arg.starts_with("--host=") {
+      out.push(arg.clone());
+    }
+  }
+  out
+}
+
+/// Shell command line the server runs. ssh joins its arguments with spaces and hands them to
+/// the remote shell, so every argument is quoted to arrive exactly as typed here.
+fn remote_command(hl: &str, app: Option<&str>, args: &[String]) -> String {
+  let mut words = Vec::new();
+  if let Some(app) = app {
+    words.push(format!("HL_APP={}", shell_words::quote(app)));
+  }
+  // Keep a leading ~/ unquoted so the remote shell expands it
+  words.push(match hl.strip_prefix("~/") {
+    Some(rest) => format!("~/{}", shell_words::quote(rest)),
+    None => shell_words::quote(hl).to_string(),
+  });
+  words.extend(args.iter().map(|a| shell_words::quote(a).to_string()));
+  words.join(" ")
+}
+
+/// Arguments for `ssh`: a terminal is allocated when attached to one, so consoles, psql and
+/// `env edit` work and Ctrl-C reaches the remote process. Options end before the host so it
+/// is never read as one.
+fn ssh_args(host: &str, tty: bool, command: &str) -> Vec<String> {
+  vec![
+    if tty { "-t" } else { "-T" }.to_string(),
+    "-o".to_string(),
+    "LogLevel=ERROR".to_string(),
+    "--".to_string(),
+    host.to_string(),
+    command.to_string(),
+  ]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn test_strip_host_arg() {
+    assert_eq!(
+      strip_host_arg(&strings(&["--host", "me@box", "deploy", "--sha", "abc"])),
+      strings(&["deploy", "--sha", "abc"])
+    );
+    assert_eq!(
+      strip_host_arg(&strings(&["-v", "logs", "--host=box", "web"])),
+      strings(&["-v", "logs", "web"])
+    );
+    assert_eq!(
+      strip_host_arg(&strings(&["run", "--", "echo", "--host", "x"])),
+      strings(&["run", "--", "echo", "--host", "x"])
+    );
+  }
+
+  #[test]
+  fn test_remote_command_quotes_arguments() {
+    let command = remote_command(
+      "~/.local/bin/hl",
+      Some("shop"),
+      &strings(&["run", "--", "rails", "runner", "puts 'hi'; exit 1"]),
+    );
+    assert_eq!(
+      command,
+      "HL_APP=shop ~/.local/bin/hl run -- rails runner 'puts '\\''hi'\\''; exit 1'"
+    );
+    let words = shell_words::split(&command).unwrap();
+    assert_eq!(words.last().unwrap(), "puts 'hi'; exit 1");
+
+    assert_eq!(
+      remote_command("/usr/local/bin/hl", None, &strings(&["status"])),
+      "/usr/local/bin/hl status"
+    );
+    assert_eq!(ssh_args("box", true, "hl status")[0], "-t");
+  }
+
+  #[test]
+  fn test_host_is_never_an_ssh_option() {
+    assert!(validate_host("deploy@homelab.local").is_ok());
+    assert!(validate_host("-oProxyCommand=touch /tmp/pwned").is_err());
+    assert_eq!(
+      ssh_args("box", false, "hl status")[3..],
+      strings(&["--", "box", "hl status"])
+    );
+  }
+}
@@ -0,0 +1,321 @@
+use crate::config::{app_dir, parse_duration, HLConfig};
+use crate::docker::command_yaml;
+use crate::health::wait_for_healthy_at;
+use crate::log::{debug, log, progress_stdout, warn};
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::{fs, process::Command, time::sleep};
+
+/*
+Gradual rollouts run the new release as a canary next to the current web container and let
+Traefik split traffic between them:
+
+- `rollout.compose.yml`   temporary overlay running `web-canary` from the new sha tag, in its own
+                          compose project (<app>-canary) so `--remove-orphans` on the web unit
+                          never touches it.
+- `hl-<app>-rollout.yml`  Traefik file-provider config with a high-priority router for the app's
+                          domain pointing at a weighted service (<app>@docker / <app>-canary@docker).
+
+Weights are rewritten at every step. Once the canary takes 100% of traffic, the regular
+retag/restart/health-gate runs against the stable containers and `finish_rollout` removes both
+files, handing traffic back to the docker-label router.
+ */
+
+const CANARY_OVERLAY: &str = "rollout.compose.yml";
+
+fn canary_project(app: &str) -> String {
+  format!("{}-canary", app)
+}
+
+fn canary_container(app: &str) -> String {
+  format!("{}-canary", app)
+}
+
+fn dynamic_config_path(cfg: &HLConfig) -> Result<PathBuf> {
+  let dir = cfg
+    .deploy
+    .traefik_dynamic_dir
+    .as_ref()
+    .context("deploy.strategy is gradual but deploy.traefikDynamicDir is not set in hl.yml")?;
+  Ok(PathBuf::from(dir).join(format!("hl-{}-rollout.yml", cfg.app)))
+}
+
+/// Normalize configured steps: strictly increasing, within 1..=100 and always ending at 100.
+pub fn rollout_steps(steps: &[u8]) -> Result<Vec<u8>> {
+  let mut out: Vec<u8> = Vec::new();
+  for &step in steps {
+    if step == 0 || step > 100 {
+      anyhow::bail!("invalid rollout step {}%: must be between 1 and 100", step);
+    }
+    if out.last().is_some_and(|&last| step <= last) {
+      anyhow::bail!("rollout steps must be strictly increasing: {:?}", steps);
+    }
+    out.push(step);
+  }
+  if out.last() != Some(&100) {
+    out.push(100);
+  }
+  Ok(out)
+}
+
+/// Replace the host of a health URL, e.g. `http://myapp:8080/up` -> `http://myapp-canary:8080/up`.
+pub fn with_host(url: &str, host: &str) -> Result<String> {
+  let (scheme, rest) = url
+    .split_once("://")
+    .with_context(|| format!("health url has no scheme: {}", url))?;
+  let host_end = rest.find([':', '/']).unwrap_or(rest.len());
+  Ok(format!("{}://{}{}", scheme, host, &rest[host_end..]))
+}
+
+/// Render the temporary overlay running the new release as `web-canary`.
+pub fn render_canary_compose(app: &str, image_tag: &str, command: Option<&String>) -> String {
+  let mut service_def = format!(
+    r#"
+services:
+  web-canary:
+    extends:
+      file: ./compose.yml
+      service: base
+    image: {image_tag}
+    container_name: {container}
+    labels:
+      traefik.enable: true
+      traefik.http.services.{container}.loadbalancer.server.port: ${{SERVICE_PORT}}"#,
+    image_tag = image_tag,
+    container = canary_container(app)
+  );
+  if let Some(cmd) = command {
+    service_def.push_str(&format!(
+      r#"
+    command: [{}]"#,
+      command_yaml(cmd)
+    ));
+  }
+  service_def.push('\n');
+  service_def
+}
+
+/// Render the Traefik file-provider config splitting traffic between stable and canary.
+pub fn render_weighted_config(cfg: &HLConfig, canary_percent: u8) -> String {
+  let app = &cfg.app;
+  format!(
+    r#"http:
+  routers:
+    {app}-rollout:
+      rule: {rule}
+      entryPoints: [websecure]
+      priority: 10000
+      service: {app}-rollout
+      tls:
+        certResolver: {resolver}
+  services:
+    {app}-rollout:
+      weighted:
+        services:
+          - name: {app}@docker
+            weight: {stable}
+          - name: {canary}@docker
+            weight: {canary_weight}
+"#,
+    app = app,
+    rule = cfg.host_rule(&cfg.domain),
+    resolver = cfg.resolver,
+    canary = canary_container(app),
+    stable = 100 - canary_percent,
+    canary_weight = canary_percent,
+  )
+}
+
+async fn canary_compose(app: &str, action: &[&str]) -> Result<()> {
+  let dir = app_dir(app);
+  let project = canary_project(app);
+  let mut args = vec![
+    "compose",
+    "-p",
+    &project,
+    "-f",
+    "compose.yml",
+    "-f",
+    CANARY_OVERLAY,
+  ];
+  args.extend_from_slice(action);
+  debug(&format!("executing: docker {}", args.join(" ")));
+
+  let status = Command::new("docker")
+    .args(&args)
+    .current_dir(&dir)
+    .stdin(Stdio::null())
+    .stdout(progress_stdout())
+    .stderr(Stdio::inherit())
+    .status()
+    .await?;
+  if !status.success() {
+    anyhow::bail!(
+      "docker compose {} failed with status: {}",
+      action.join(" "),
+      status
+    );
+  }
+  Ok(())
+}
+
+/// Start the canary from `image_tag` and walk the configured steps, health-checking the canary
+/// at each one. Returns once the canary serves 100% of traffic; on failure the rollout is
+/// aborted and traffic goes back to the current release.
+pub async fn shift_traffic(
+  cfg: &HLConfig,
+  image_tag: &str,
+  command: Option<&String>,
+) -> Result<()> {
+  let steps = rollout_steps(&cfg.deploy.steps)?;
+  let step_interval = Duration::from_millis(parse_duration(&cfg.deploy.step_interval)?);
+  let dynamic_config = dynamic_config_path(cfg)?;
+  let canary_url = with_host(&cfg.health.url, &canary_container(&cfg.app))?;
+
+  let overlay = app_dir(&cfg.app).join(CANARY_OVERLAY);
+  fs::write(
+    &overlay,
+    render_canary_compose(&cfg.app, image_tag, command),
+  )
+  .await?;
+
+  log("starting canary");
+  if let Err(e) = canary_compose(&cfg.app, &["up", "-d", "web-canary"]).await {
+    abort_rollout(cfg).await;
+    return Err(e);
+  }
+  if let Err(e) = wait_for_healthy_at(cfg, &canary_url).await {
+    abort_rollout(cfg).await;
+    return Err(e.context("canary never became healthy; rollout aborted"));
+  }
+
+  for step in steps {
+    log(&format!("routing {}% of traffic to the new release", step));
+    if let Err(e) = fs::write(&dynamic_config, render_weighted_config(cfg, step)).await {
+      abort_rollout(cfg).await;
+      return Err(e).with_context(|| format!("Failed to write {}", dynamic_config.display()));
+    }
+    if step < 100 {
+      sleep(step_interval).await;
+    }
+    if let Err(e) = wait_for_healthy_at(cfg, &canary_url).await {
+      abort_rollout(cfg).await;
+      return Err(e.context(format!("rollout aborted at {}%", step)));
+    }
+  }
+
+  Ok(())
+}
+
+/// Hand traffic back to the docker-label router and remove the canary.
+pub async fn finish_rollout(cfg: &HLConfig) -> Result<()> {
+  let dynamic_config = dynamic_config_path(cfg)?;
+  if dynamic_config.exists() {
+    fs::remove_file(&dynamic_config).await?;
+  }
+  canary_compose(&cfg.app, &["down"]).await?;
+  let overlay = app_dir(&cfg.app).join(CANARY_OVERLAY);
+  if overlay.exists() {
+    fs::remove_file(&overlay).await?;
+  }
+  Ok(())
+}
+
+async fn abort_rollout(cfg: &HLConfig) {
+  warn("aborting rollout, routing all traffic back to the current release");
+  if let Err(e) = finish_rollout(cfg).await {
+    warn(&format!("failed to clean up rollout: {}", e));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn test_config() -> HLConfig {
+    serde_yaml::from_str(
+      r#"
+app: testapp
+image: registry.example.com/testapp
+domain: testapp.example.com
+servicePort: 3000
+health:
+  url: http://testapp:3000/healthz
+deploy:
+  strategy: gradual
+  traefikDynamicDir: /etc/traefik/dynamic
+"#,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn test_rollout_steps() {
+    assert_eq!(rollout_steps(&[10, 50, 100]).unwrap(), vec![10, 50, 100]);
+    assert_eq!(rollout_steps(&[25]).unwrap(), vec![25, 100]);
+    assert!(rollout_steps(&[50, 10]).is_err());
+    assert!(rollout_steps(&[0, 100]).is_err());
+  }
+
+  #[test]
+  fn test_with_host() {
+    assert_eq!(
+      with_host("http://testapp:3000/healthz", "testapp-canary").unwrap(),
+      "http://testapp-canary:3000/healthz"
+    );
+    assert_eq!(
+      with_host("http://testapp/up", "testapp-canary").unwrap(),
+      "http://testapp-canary/up"
+    );
+    assert!(with_host("testapp:3000", "x").is_err());
+  }
+
+  #[test]
+  fn test_render_weighted_config() {
+    let cfg = test_config();
+    let expected = r#"http:
+  routers:
+    testapp-rollout:
+      rule: Host(`testapp.example.com`)
+      entryPoints: [websecure]
+      priority: 10000
+      service: testapp-rollout
+      tls:
+        certResolver: myresolver
+  services:
+    testapp-rollout:
+      weighted:
+        services:
+          - name: testapp@docker
+            weight: 90
+          - name: testapp-canary@docker
+            weight: 10
+"#;
+    assert_eq!(render_weighted_config(&cfg, 10), expected);
+  }
+
+  #[test]
+  fn test_render_canary_compose() {
+    let result = render_canary_compose(
+      "testapp",
+      "registry.example.com/testapp:abc1234",
+      Some(&"bundle exec puma".to_string()),
+    );
+    let expected = r#"
+services:
+  web-canary:
+    extends:
+      file: ./compose.yml
+      service: base
+    image: registry.example.com/testapp:abc1234
+    container_name: testapp-canary
+    labels:
+      traefik.enable: true
+      traefik.http.services.testapp-canary.loadbalancer.server.port: ${SERVICE_PORT}
+    command: ["bundle","exec","puma"]
+"#;
+    assert_eq!(result, expected);
+  }
+}
@@ -0,0 +1,207 @@
+use crate::config::{app_dir, HLConfig, SecretsProvider, SecretsProviderConfig};
+use crate::docker::BuildSecret;
+use crate::log::debug;
+use anyhow::{Context, Result};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Resolve the hl.yml `secrets:` list through the configured provider. Values are fetched
+/// per deploy and only ever passed to the build, never written to disk.
+pub async fn resolve_secrets(cfg: &HLConfig) -> Result<Vec<BuildSecret>> {
+  let Some(provider) = &cfg.secrets_provider else {
+    return Ok(Vec::new());
+  };
+  if cfg.secrets.is_empty() {
+    return Ok(Vec::new());
+  }
+
+  match provider.kind {
+    SecretsProvider::Sops => {
+      let args = sops_command(cfg, provider)?;
+      let decrypted = run_provider(&args).await?;
+      select_dotenv(&decrypted, &cfg.secrets)
+    }
+    SecretsProvider::Op => resolve_per_key(PerKeyProvider::Op, provider, &cfg.secrets).await,
+    SecretsProvider::Vault => resolve_per_key(PerKeyProvider::Vault, provider, &cfg.secrets).await,
+  }
+}
+
+/// Providers queried once per secret.
+#[derive(Debug, Clone, Copy)]
+enum PerKeyProvider {
+  Op,
+  Vault,
+}
+
+async fn resolve_per_key(
+  kind: PerKeyProvider,
+  provider: &SecretsProviderConfig,
+  keys: &[String],
+) -> Result<Vec<BuildSecret>> {
+  let mut secrets = Vec::new();
+  for key in keys {
+    let args = per_key_command(kind, provider, key)?;
+    let value = run_provider(&args).await?;
+    secrets.push(BuildSecret::from_kv(
+      key,
+      value.strip_suffix('\n').unwrap_or(&value),
+    ));
+  }
+  Ok(secrets)
+}
+
+/// Command that prints the value of one secret (`op read`, `vault kv get -field`).
+fn per_key_command(
+  kind: PerKeyProvider,
+  provider: &SecretsProviderConfig,
+  key: &str,
+) -> Result<Vec<String>> {
+  match kind {
+    PerKeyProvider::Op => {
+      let reference = provider.reference.as_deref().context(
+        "secretsProvider.reference is required for op (e.g. op://Production/myapp/{key})",
+      )?;
+      Ok(vec![
+        "op".to_string(),
+        "read".to_string(),
+        "--no-newline".to_string(),
+        reference.replace("{key}", key),
+      ])
+    }
+    PerKeyProvider::Vault => {
+      let path = provider
+        .path
+        .as_deref()
+        .context("secretsProvider.path is required for vault (e.g. secret/myapp)")?;
+      Ok(vec![
+        "vault".to_string(),
+        "kv".to_string(),
+        "get".to_string(),
+        format!("-field={}", key),
+        path.to_string(),
+      ])
+    }
+  }
+}
+
+/// `sops --decrypt` of the encrypted dotenv file; relative paths are under the app dir.
+fn sops_command(cfg: &HLConfig, provider: &SecretsProviderConfig) -> Result<Vec<String>> {
+  let file = provider
+    .file
+    .as_deref()
+    .context("secretsProvider.file is required for sops (e.g. secrets.enc.env)")?;
+  let path = app_dir(&cfg.app).join(file);
+  Ok(vec![
+    "sops".to_string(),
+    "--decrypt".to_string(),
+    "--output-type".to_string(),
+    "dotenv".to_string(),
+    path.to_string_lossy().to_string(),
+  ])
+}
+
+async fn run_provider(args: &[String]) -> Result<String> {
+  // Arguments are references and paths, not values, so they're safe to log
+  debug(&format!("resolving secret: {}", args.join(" ")));
+  let output = Command::new(&args[0])
+    .args(&args[1..])
+    .stdin(Stdio::null())
+    .output()
+    .await
+    .with_context(|| format!("failed to run {}; is it installed?", args[0]))?;
+  if !output.status.success() {
+    anyhow::bail!(
+      "{} failed with status {}: {}",
+      args[0],
+      output.status,
+      String::from_utf8_lossy(&output.stderr).trim()
+    );
+  }
+  String::from_utf8(output.stdout).context("secret value is not valid UTF-8")
+}
+
+/// Pick `keys` out of decrypted dotenv content; every key must be present.
+fn select_dotenv(content: &str, keys: &[String]) -> Result<Vec<BuildSecret>> {
+  let mut values = std::collections::HashMap::new();
+  for item in dotenvy::from_read_iter(content.as_bytes()) {
+    let (k, v) = item.context("decrypted sops file is not valid dotenv")?;
+    values.insert(k, v);
+  }
+  keys
+    .iter()
+    .map(|key| {
+      let value = values
+        .get(key)
+        .with_context(|| format!("{} is not in the sops file", key))?;
+      Ok(BuildSecret::from_kv(key, value))
+    })
+    .collect()
+}
+
+/// Secrets from the provider take precedence over `.env.build` entries with the same id.
+pub fn merge_build_secrets(
+  local: Vec<BuildSecret>,
+  resolved: Vec<BuildSecret>,
+) -> Vec<BuildSecret> {
+  let mut merged: Vec<BuildSecret> = local
+    .into_iter()
+    .filter(|l| !resolved.iter().any(|r| r.id == l.id))
+    .collect();
+  merged.extend(resolved);
+  merged
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn provider(yaml: &str) -> SecretsProviderConfig {
+    serde_yaml::from_str(yaml).unwrap()
+  }
+
+  #[test]
+  fn test_per_key_commands() {
+    let op = provider("type: op\nreference: op://Production/myapp/{key}\n");
+    assert_eq!(
+      per_key_command(PerKeyProvider::Op, &op, "RAILS_MASTER_KEY")
+        .unwrap()
+        .join(" "),
+      "op read --no-newline op://Production/myapp/RAILS_MASTER_KEY"
+    );
+
+    let vault = provider("type: vault\npath: secret/myapp\n");
+    assert_eq!(
+      per_key_command(PerKeyProvider::Vault, &vault, "NPM_TOKEN")
+        .unwrap()
+        .join(" "),
+      "vault kv get -field=NPM_TOKEN secret/myapp"
+    );
+
+    assert!(per_key_command(PerKeyProvider::Op, &provider("type: op\n"), "X").is_err());
+  }
+
+  #[test]
+  fn test_select_dotenv() {
+    let content = "RAILS_MASTER_KEY=abc\nOTHER=1\n";
+    let secrets = select_dotenv(content, &["RAILS_MASTER_KEY".to_string()]).unwrap();
+    assert_eq!(secrets.len(), 1);
+    assert_eq!(secrets[0].value, "abc");
+    assert!(select_dotenv(content, &["MISSING".to_string()]).is_err());
+  }
+
+  #[test]
+  fn test_merge_build_secrets() {
+    let merged = merge_build_secrets(
+      vec![
+        BuildSecret::from_kv("A", "local"),
+        BuildSecret::from_kv("B", "local"),
+      ],
+      vec![BuildSecret::from_kv("A", "vault")],
+    );
+    let pairs: Vec<_> = merged
+      .iter()
+      .map(|s| (s.id.as_str(), s.value.as_str()))
+      .collect();
+    assert_eq!(pairs, vec![("B", "local"), ("A", "vault")]);
+  }
+}
@@ -0,0 +1,225 @@
+use crate::config::{HLConfig, SidecarConfig, SidecarTemplate};
+use crate::docker::command_yaml;
+use crate::log::debug;
+use anyhow::Result;
+use std::path::Path;
+
+impl SidecarTemplate {
+  pub fn parse(name: &str) -> Result<SidecarTemplate> {
+    match name {
+      "nginx-static" => Ok(SidecarTemplate::NginxStatic),
+      "cron-runner" => Ok(SidecarTemplate::CronRunner),
+      "queue-dashboard" => Ok(SidecarTemplate::QueueDashboard),
+      _ => anyhow::bail!(
+        "unknown process template {:?}; available: nginx-static, cron-runner, queue-dashboard",
+        name
+      ),
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      SidecarTemplate::NginxStatic => "nginx-static",
+      SidecarTemplate::CronRunner => "cron-runner",
+      SidecarTemplate::QueueDashboard => "queue-dashboard",
+    }
+  }
+
+  /// Process name used when `hl process add` isn't given one.
+  pub fn default_process_name(&self) -> &'static str {
+    match self {
+      SidecarTemplate::NginxStatic => "static",
+      SidecarTemplate::CronRunner => "cron",
+      SidecarTemplate::QueueDashboard => "dashboard",
+    }
+  }
+
+  /// The hl.yml `sidecars:` entry `hl process add` writes, with every option spelled out.
+  pub fn snippet(&self, process: &str) -> String {
+    let options = match self {
+      SidecarTemplate::NginxStatic => "    image: nginx:alpine\n    root: public\n    pathPrefix: /assets\n",
+      SidecarTemplate::CronRunner => "    crontab: crontab\n",
+      SidecarTemplate::QueueDashboard => {
+        "    command: bundle exec rackup sidekiq.ru -p 9292 -o 0.0.0.0\n    port: 9292\n    pathPrefix: /queues\n"
+      }
+    };
+    format!("  {}:\n    template: {}\n{}", process, self.name(), options)
+  }
+}
+
+/// Compose overlay (`compose.<process>.yml`) for a sidecar process. Sidecars run in the app's
+/// compose project next to the Procfile processes and get a unit of their own.
+pub fn render_sidecar_compose(
+  cfg: &HLConfig,
+  process: &str,
+  sidecar: &SidecarConfig,
+) -> Result<String> {
+  let mut service = format!("\nservices:\n  {}:\n", process);
+  match sidecar.template {
+    SidecarTemplate::NginxStatic => {
+      // Serves files from the app dir; doesn't need the app image or its env
+      let image = sidecar.image.as_deref().unwrap_or("nginx:alpine");
+      let root = sidecar.root.as_deref().unwrap_or("public");
+      service.push_str(&format!(
+        "    image: {image}\n    restart: unless-stopped\n    networks: [{network}]\n    volumes:\n      - ./{root}:/usr/share/nginx/html:ro\n",
+        image = image,
+        network = cfg.network,
+        root = root.trim_start_matches("./"),
+      ));
+      service.push_str(&route_labels(cfg, process, sidecar, "/assets", 80));
+    }
+    SidecarTemplate::CronRunner => {
+      // The app image with its .env, running supercronic (which must be in the image)
+      let crontab = sidecar.crontab.as_deref().unwrap_or("crontab");
+      service.push_str(&base_service(sidecar));
+      service.push_str(&format!(
+        "    command: [{}]\n",
+        command_yaml(&format!("supercronic {}", crontab))
+      ));
+    }
+    SidecarTemplate::QueueDashboard => {
+      let command = sidecar
+        .command
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("sidecars.{}: queue-dashboard needs a command", process))?;
+      service.push_str(&base_service(sidecar));
+      service.push_str(&format!("    command: [{}]\n", command_yaml(command)));
+      service.push_str(&route_labels(cfg, process, sidecar, "/queues", 9292));
+    }
+  }
+  Ok(service)
+}
+
+/// Write `compose.<process>.yml` for every hl.yml sidecar and return their process names.
+pub async fn write_sidecar_compose_files(dir: &Path, cfg: &HLConfig) -> Result<Vec<String>> {
+  let mut names: Vec<String> = cfg.sidecars.keys().cloned().collect();
+  names.sort();
+  for name in &names {
+    let compose = render_sidecar_compose(cfg, name, &cfg.sidecars[name])?;
+    let path = dir.join(format!("compose.{}.yml", name));
+    tokio::fs::write(&path, compose).await?;
+    debug(&format!("wrote sidecar compose file: {}", path.display()));
+  }
+  Ok(names)
+}
+
+/// Extend the app's base service, optionally swapping the image.
+fn base_service(sidecar: &SidecarConfig) -> String {
+  let mut service = "    extends:\n      file: ./compose.yml\n      service: base\n".to_string();
+  if let Some(image) = &sidecar.image {
+    service.push_str(&format!("    image: {}\n", image));
+  }
+  service
+}
+
+/// Traefik labels routing `<domain><pathPrefix>` to the sidecar. The longer rule outranks the
+/// web router, so the prefix is taken out of the app's hands.
+fn route_labels(
+  cfg: &HLConfig,
+  process: &str,
+  sidecar: &SidecarConfig,
+  default_prefix: &str,
+  default_port: u16,
+) -> String {
+  let router = format!("{}-{}", cfg.app, process);
+  let mut hosts = cfg.host_rule("${DOMAIN}");
+  if hosts.contains(" || ") {
+    hosts = format!("({})", hosts);
+  }
+  format!(
+    r#"    labels:
+      traefik.enable: true
+      traefik.http.routers.{router}.rule: {hosts} && PathPrefix(`{prefix}`)
+      traefik.http.routers.{router}.entrypoints: websecure
+      traefik.http.routers.{router}.tls.certresolver: {resolver}
+      traefik.http.services.{router}.loadbalancer.server.port: {port}
+"#,
+    router = router,
+    hosts = hosts,
+    prefix = sidecar.path_prefix.as_deref().unwrap_or(default_prefix),
+    resolver = cfg.resolver,
+    port = sidecar.port.unwrap_or(default_port),
+  )
+}
+
+/// Insert a `sidecars:` entry into hl.yml text, keeping comments and layout: right below an
+/// existing top-level `sidecars:` key, or as a new section at the end.
+pub fn insert_sidecar_entry(hl_yml: &str, snippet: &str) -> String {
+  let mut out = String::with_capacity(hl_yml.len() + snippet.len() + 10);
+  let mut inserted = false;
+  for line in hl_yml.lines() {
+    out.push_str(line);
+    out.push('\n');
+    if !inserted && line.trim_end() == "sidecars:" {
+      out.push_str(snippet);
+      inserted = true;
+    }
+  }
+  if !inserted {
+    out.push_str("sidecars:\n");
+    out.push_str(snippet);
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const HL_YML: &str = r#"app: myapp
+image: registry.example.com/myapp
+domain: myapp.example.com
+servicePort: 3000
+health:
+  url: http://myapp:3000/up
+"#;
+
+  fn config(extra: &str) -> HLConfig {
+    serde_yaml::from_str(&format!("{}{}", HL_YML, extra)).unwrap()
+  }
+
+  #[test]
+  fn test_insert_sidecar_entry() {
+    let snippet = SidecarTemplate::CronRunner.snippet("cron");
+    let added = insert_sidecar_entry(HL_YML, &snippet);
+    let cfg: HLConfig = serde_yaml::from_str(&added).unwrap();
+    assert_eq!(cfg.sidecars["cron"].template, SidecarTemplate::CronRunner);
+
+    // A second entry lands under the existing key, keeping the first
+    let snippet = SidecarTemplate::NginxStatic.snippet("static");
+    let cfg: HLConfig = serde_yaml::from_str(&insert_sidecar_entry(&added, &snippet)).unwrap();
+    assert_eq!(cfg.sidecars.len(), 2);
+    assert_eq!(
+      cfg.sidecars["static"].path_prefix.as_deref(),
+      Some("/assets")
+    );
+  }
+
+  #[test]
+  fn test_render_nginx_static() {
+    let cfg = config("sidecars:\n  static:\n    template: nginx-static\n");
+    let compose = render_sidecar_compose(&cfg, "static", &cfg.sidecars["static"]).unwrap();
+    assert!(compose.contains("    image: nginx:alpine\n"));
+    assert!(compose.contains("      - ./public:/usr/share/nginx/html:ro\n"));
+    assert!(compose.contains(
+      "traefik.http.routers.myapp-static.rule: Host(`${DOMAIN}`) && PathPrefix(`/assets`)\n"
+    ));
+    assert!(compose.contains("traefik.http.services.myapp-static.loadbalancer.server.port: 80\n"));
+  }
+
+  #[test]
+  fn test_render_cron_runner() {
+    let cfg =
+      config("sidecars:\n  cron:\n    template: cron-runner\n    crontab: config/crontab\n");
+    let compose = render_sidecar_compose(&cfg, "cron", &cfg.sidecars["cron"]).unwrap();
+    assert!(compose.contains("      service: base\n"));
+    assert!(compose.contains(r#"    command: ["supercronic","config/crontab"]"#));
+    assert!(!compose.contains("traefik"));
+  }
+
+  #[test]
+  fn test_queue_dashboard_requires_command() {
+    let cfg = config("sidecars:\n  dashboard:\n    template: queue-dashboard\n");
+    assert!(render_sidecar_compose(&cfg, "dashboard", &cfg.sidecars["dashboard"]).is_err());
+  }
+}
@@ -0,0 +1,162 @@
+use crate::config::{app_dir, env_file};
+use crate::discovery::{scan_accessories, scan_processes};
+use crate::env::load_env_file_contents;
+use crate::log::{debug, warn};
+use crate::releases::{load_releases, releases_dir};
+use crate::units_spec_builder::scale_env_var;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+~/hl/apps/<app>/state.yml records what hl has set up for an app: its processes and
+accessories, the release running and the process scale. Commands that change any of these
+rewrite it (write to a temp file, then rename) right after the change, and discovery reads it
+instead of inferring names from unit and compose file names, which breaks as soon as names
+collide (a process called `postgres`). Apps from before state.yml, or whose state.yml was
+lost, fall back to that scanning; `hl state rebuild` writes a fresh state.yml from it.
+ */
+
+/// What hl has set up for an app.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AppState {
+  #[serde(default)]
+  pub processes: Vec<String>,
+  #[serde(default)]
+  pub accessories: Vec<String>,
+  /// Commit sha of the release running, if one was deployed
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub release: Option<String>,
+  /// Replica count of each scaled process (unscaled processes run one)
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub scale: BTreeMap<String, u32>,
+}
+
+pub fn state_path(app_dir: &Path) -> PathBuf {
+  app_dir.join("state.yml")
+}
+
+/// The app's recorded state, or None when there is none (or it can't be read, in which case
+/// callers fall back to scanning).
+pub fn load_state(app_dir: &Path) -> Option<AppState> {
+  let path = state_path(app_dir);
+  let content = fs::read_to_string(&path).ok()?;
+  match serde_yaml::from_str(&content) {
+    Ok(state) => Some(state),
+    Err(e) => {
+      warn(&format!(
+        "ignoring unreadable {} ({}); repair it with hl state rebuild",
+        path.display(),
+        e
+      ));
+      None
+    }
+  }
+}
+
+/// Replace the app's state.yml atomically, so readers never see half a file.
+pub fn save_state(app_dir: &Path, state: &AppState) -> Result<()> {
+  let path = state_path(app_dir);
+  let tmp = app_dir.join(".state.yml.tmp");
+  fs::write(&tmp, serde_yaml::to_string(state)?)
+    .with_context(|| format!("Failed to write {}", tmp.display()))?;
+  fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))?;
+  debug(&format!("recorded state in {}", path.display()));
+  Ok(())
+}
+
+/// Change the app's recorded state, starting from a scan when there is no state.yml yet.
+/// Apps whose directory doesn't exist have no state to record.
+pub fn update_state(
+  app: &str,
+  systemd_dir: &Path,
+  change: impl FnOnce(&mut AppState),
+) -> Result<()> {
+  let dir = app_dir(app);
+  if !dir.exists() {
+    return Ok(());
+  }
+  let mut state = match load_state(&dir) {
+    Some(state) => state,
+    None => scan_state(app, systemd_dir)?,
+  };
+  change(&mut state);
+  state.processes.sort();
+  state.processes.dedup();
+  state.accessories.sort();
+  state.accessories.dedup();
+  save_state(&dir, &state)
+}
+
+/// Infer the app's state from what is on disk: unit and compose file names, the newest
+/// release manifest and the `*_SCALE` variables in .env.
+pub fn scan_state(app: &str, systemd_dir: &Path) -> Result<AppState> {
+  let dir = app_dir(app);
+  let processes = scan_processes(systemd_dir, app)?;
+  let accessories = scan_accessories(systemd_dir, &dir, app, &processes)?;
+  let release = load_releases(&releases_dir(app))?
+    .into_iter()
+    .next()
+    .map(|(_, release)| release.sha);
+  let env = load_env_file_contents(&env_file(app)).unwrap_or_default();
+  let scale = processes
+    .iter()
+    .filter_map(|p| {
+      let count = env.get(&scale_env_var(p))?.parse().ok()?;
+      Some((p.clone(), count))
+    })
+    .collect();
+  Ok(AppState {
+    processes,
+    accessories,
+    release,
+    scale,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serial_test::serial;
+  use tempfile::TempDir;
+
+  #[test]
+  #[serial]
+  fn test_update_state_starts_from_scan() {
+    let root = TempDir::new().unwrap();
+    let units = TempDir::new().unwrap();
+    std::env::set_var("HL_ROOT_OVERRIDE", root.path());
+    let dir = app_dir("testapp");
+    fs::create_dir_all(&dir).unwrap();
+    for unit in ["app-testapp-web.service", "app-testapp-postgres.service"] {
+      fs::write(units.path().join(unit), "").unwrap();
+    }
+    fs::write(dir.join(".env"), "POSTGRES_SCALE=2\n").unwrap();
+
+    assert_eq!(load_state(&dir), None);
+    update_state("testapp", units.path(), |state| {
+      state.release = Some("abc1234".to_string())
+    })
+    .unwrap();
+    let state = load_state(&dir).unwrap();
+    // A process that happens to be called postgres stays a process
+    assert_eq!(state.processes, ["postgres", "web"]);
+    assert!(state.accessories.is_empty());
+    assert_eq!(state.scale.get("postgres"), Some(&2));
+    assert_eq!(state.release.as_deref(), Some("abc1234"));
+
+    // Once recorded, the state wins over what the files suggest
+    fs::write(dir.join("compose.redis.yml"), "").unwrap();
+    update_state("testapp", units.path(), |_| {}).unwrap();
+    assert!(load_state(&dir).unwrap().accessories.is_empty());
+    assert!(scan_state("testapp", units.path())
+      .unwrap()
+      .accessories
+      .contains(&"redis".to_string()));
+
+    std::env::remove_var("HL_ROOT_OVERRIDE");
+  }
+}
@@ -0,0 +1,219 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use tokio::process::Command;
+
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+
+/// Resource usage of one running container, from `docker stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerStats {
+  pub name: String,
+  /// App the container belongs to (processes and accessories alike)
+  pub app: String,
+  /// Compose service, e.g. web or pg
+  pub service: String,
+  /// Percent of one CPU, so it can exceed 100 on multi-core hosts
+  pub cpu: f64,
+  pub mem_bytes: u64,
+}
+
+/// Usage summed over an app's containers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppStats {
+  pub app: String,
+  pub containers: usize,
+  pub cpu: f64,
+  pub mem_bytes: u64,
+}
+
+/// Snapshot of the containers of `apps` (compose projects `<app>` and `<app>-acc`).
+/// Containers of anything else on the host are left out.
+pub async fn container_stats(apps: &[String]) -> Result<Vec<ContainerStats>> {
+  let listing = docker_output(&[
+    "ps",
+    "--format",
+    &format!(
+      "{{{{.Names}}}}\t{{{{.Label \"{}\"}}}}\t{{{{.Label \"{}\"}}}}",
+      COMPOSE_PROJECT_LABEL, COMPOSE_SERVICE_LABEL
+    ),
+  ])
+  .await?;
+  // name -> (app, service)
+  let mut owners = BTreeMap::new();
+  for line in listing.lines() {
+    let mut fields = line.split('\t');
+    let (Some(name), Some(project), Some(service)) = (fields.next(), fields.next(), fields.next())
+    else {
+      continue;
+    };
+    if let Some(app) = project_app(project, apps) {
+      owners.insert(name.to_string(), (app, service.to_string()));
+    }
+  }
+  if owners.is_empty() {
+    return Ok(Vec::new());
+  }
+
+  let mut args = vec![
+    "stats",
+    "--no-stream",
+    "--format",
+    "{{.Name}}\t{{.CPUPerc}}\t{{.MemUsage}}",
+  ];
+  args.extend(owners.keys().map(String::as_str));
+  let stats = docker_output(&args).await?;
+  Ok(
+    stats
+      .lines()
+      .filter_map(|line| {
+        let (name, cpu, mem) = parse_stats_line(line)?;
+        let (app, service) = owners.get(name)?.clone();
+        Some(ContainerStats {
+          name: name.to_string(),
+          app,
+          service,
+          cpu,
+          mem_bytes: mem,
+        })
+      })
+      .collect(),
+  )
+}
+
+async fn docker_output(args: &[&str]) -> Result<String> {
+  let output = Command::new("docker")
+    .args(args)
+    .stdin(Stdio::null())
+    .output()
+    .await?;
+  if !output.status.success() {
+    anyhow::bail!(
+      "docker {} failed: {}",
+      args[0],
+      String::from_utf8_lossy(&output.stderr).trim()
+    );
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// App owning a compose project: the app itself or its `-acc` accessories project.
+fn project_app(project: &str, apps: &[String]) -> Option<String> {
+  let app = project.strip_suffix("-acc").unwrap_or(project);
+  apps.iter().find(|a| *a == app || *a == project).cloned()
+}
+
+/// `name\t12.5%\t256MiB / 1.9GiB` -> (name, 12.5, bytes used)
+fn parse_stats_line(line: &str) -> Option<(&str, f64, u64)> {
+  let mut fields = line.split('\t');
+  let name = fields.next()?;
+  let cpu = fields.next()?.trim().trim_end_matches('%').parse().ok()?;
+  let used = fields.next()?.split('/').next()?;
+  Some((name, cpu, parse_size(used)?))
+}
+
+/// Sizes as docker prints them: `512B`, `1.5KiB`, `256MiB`, `1.2GB`.
+pub fn parse_size(size: &str) -> Option<u64> {
+  let size = size.trim();
+  let split = size
+    .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+    .unwrap_or(size.len());
+  let (number, unit) = size.split_at(split);
+  let number: f64 = number.parse().ok()?;
+  let factor: f64 = match unit.trim() {
+    "B" | "" => 1.0,
+    "KiB" => 1024.0,
+    "MiB" => 1024.0 * 1024.0,
+    "GiB" => 1024.0 * 1024.0 * 1024.0,
+    "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+    "kB" | "KB" => 1e3,
+    "MB" => 1e6,
+    "GB" => 1e9,
+    "TB" => 1e12,
+    _ => return None,
+  };
+  Some((number * factor) as u64)
+}
+
+/// Human-readable binary size, e.g. `1.5 GiB`.
+pub fn format_size(bytes: u64) -> String {
+  let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let mut value = bytes as f64;
+  let mut unit = 0;
+  while value >= 1024.0 && unit + 1 < units.len() {
+    value /= 1024.0;
+    unit += 1;
+  }
+  if unit == 0 {
+    format!("{} B", bytes)
+  } else {
+    format!("{:.1} {}", value, units[unit])
+  }
+}
+
+/// Sum container usage per app.
+pub fn aggregate(stats: &[ContainerStats]) -> Vec<AppStats> {
+  let mut apps: BTreeMap<&str, AppStats> = BTreeMap::new();
+  for s in stats {
+    let entry = apps.entry(&s.app).or_insert_with(|| AppStats {
+      app: s.app.clone(),
+      containers: 0,
+      cpu: 0.0,
+      mem_bytes: 0,
+    });
+    entry.containers += 1;
+    entry.cpu += s.cpu;
+    entry.mem_bytes += s.mem_bytes;
+  }
+  apps.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_stats() {
+    assert_eq!(parse_size("512B"), Some(512));
+    assert_eq!(parse_size("1.5KiB"), Some(1536));
+    assert_eq!(parse_size("256MiB "), Some(256 * 1024 * 1024));
+    assert_eq!(parse_size("1.2GB"), Some(1_200_000_000));
+    assert_eq!(parse_size("lots"), None);
+    assert_eq!(
+      parse_stats_line("myapp\t12.50%\t256MiB / 1.944GiB"),
+      Some(("myapp", 12.5, 256 * 1024 * 1024))
+    );
+    assert_eq!(format_size(1536), "1.5 KiB");
+    assert_eq!(format_size(100), "100 B");
+
+    let apps = vec!["shop".to_string(), "shop-staging".to_string()];
+    assert_eq!(project_app("shop-acc", &apps).as_deref(), Some("shop"));
+    assert_eq!(
+      project_app("shop-staging", &apps).as_deref(),
+      Some("shop-staging")
+    );
+    assert_eq!(project_app("traefik", &apps), None);
+  }
+
+  #[test]
+  fn test_aggregate() {
+    let container = |app: &str, cpu: f64, mem: u64| ContainerStats {
+      name: format!("{}-x", app),
+      app: app.to_string(),
+      service: "web".to_string(),
+      cpu,
+      mem_bytes: mem,
+    };
+    let apps = aggregate(&[
+      container("shop", 10.0, 100),
+      container("blog", 1.0, 50),
+      container("shop", 5.5, 200),
+    ]);
+    assert_eq!(apps.len(), 2);
+    assert_eq!(apps[1].app, "shop");
+    assert_eq!(apps[1].containers, 2);
+    assert_eq!(apps[1].cpu, 15.5);
+    assert_eq!(apps[1].mem_bytes, 300);
+  }
+}
@@ -1,34 +1,67 @@
-use crate::log::{debug, log};
-use crate::units_spec_builder::{render_and_write, UnitsSpec, WriteOutcome};
-use anyhow::Result;
+use crate::config::{
+  app_dir, deploy_home, load_config, split_env_app_name, system_unit_user, systemd_dir,
+  systemd_scope, SystemdMode, SystemdScope,
+};
+use crate::discovery::discover_processes;
+use crate::env::record_applied_env;
+use crate::error::Error;
+use crate::log::{debug, log, progress_stdout, warn};
+use crate::state::update_state;
+use crate::systemd_bus::{plan_ops, BusError, Manager};
+use crate::textdiff::{colorize_diff, format_diff};
+use crate::time::format_rfc3339;
+use crate::units_spec_builder::{
+  cron_unit, plan_units, render_and_write, CronUnit, UnitChange, UnitsSpec, WriteOutcome,
+};
+use anyhow::{Context, Result};
 use std::fs;
+use std::path::PathBuf;
 use std::process::Stdio;
+use std::time::{Duration, Instant};
 use tokio::process::Command;
 
+/// How long process units get to report `active` after a restart.
+const UNIT_START_TIMEOUT: Duration = Duration::from_secs(60);
+/// Journal lines shown for a unit that failed to come up.
+const JOURNAL_TAIL_LINES: &str = "30";
+
 /*
 - app-<app>.target          A virtual “stack switch” for your app.
 - app-<app>-acc.service     Accessories (Redis/Postgres) Compose project (<app>-acc).
 - app-<app>-web.service     Web process (service name = "web") in Compose project <app>.
 - app-<app>-worker.service  Worker process (service name = "worker") in Compose project <app>.
-- All process units: Type=oneshot, RemainAfterExit=yes (Docker keeps containers running).
+- All process units: Type=oneshot, RemainAfterExit=yes (Docker keeps containers running),
+  or with `systemd.mode: attached`, Type=exec running `compose up` in the foreground.
 - Process units declare `After=app-<app>-acc.service` and `Wants=app-<app>-acc.service`
   when accessories exist; otherwise they just `After=docker.service network-online.target`.
+- app-<app>-cron-<name>.service/.timer  hl.yml `cron:` jobs, one-off containers on a timer.
  */
 
-/// Clean up orphaned unit files for processes/accessories that no longer exist.
-///
-/// This function:
-/// 1. Scans the systemd directory for unit files matching app-<app>-*.service
-/// 2. Identifies orphaned services (not in current processes or accessories list)
-/// 3. Stops and disables each orphaned service
-/// 4. Deletes the orphaned unit file
-/// 5. Logs all actions
-async fn cleanup_orphaned_units_impl(
+/// Whether `file`, a unit file or drop-in directory in a systemd directory holding `files`,
+/// belongs to `app`. Names alone are ambiguous: `app-myapp-staging-web.service` could be a
+/// `staging-web` process of `myapp`, but while `app-myapp-staging.target` exists it is the web
+/// process of `myapp-staging` (the staging environment of `myapp`) and not `myapp`'s.
+pub fn is_app_unit_file(app: &str, file: &str, files: &[String]) -> bool {
+  let owned_by = |name: &str| {
+    file.starts_with(&format!("app-{}-", name)) || file.starts_with(&format!("app-{}.", name))
+  };
+  owned_by(app)
+    && !files.iter().any(|f| {
+      f.strip_prefix("app-")
+        .and_then(|f| f.strip_suffix(".target"))
+        .is_some_and(|other| other.starts_with(&format!("{}-", app)) && owned_by(other))
+    })
+}
+
+/// Unit files of the app in `systemd_dir` that no current process, accessory or cron job
+/// accounts for (targets excluded).
+fn orphaned_units(
   app: &str,
   processes: &[String],
   accessories: &[String],
+  cron_jobs: &[String],
   systemd_dir: &std::path::Path,
-) -> Result<()> {
+) -> Vec<std::path::PathBuf> {
   // Read directory entries
   let entries = match fs::read_dir(systemd_dir) {
     Ok(entries) => entries,
@@ -38,7 +71,7 @@ async fn cleanup_orphaned_units_impl(
         systemd_dir.display(),
         e
       ));
-      return Ok(());
+      return Vec::new();
     }
   };
 
@@ -55,38 +88,61 @@ async fn cleanup_orphaned_units_impl(
     expected_units.insert(format!("app-{}-{}.service", app, proc));
   }
 
-  // Find orphaned service files
-  let pattern = format!("app-{}-", app);
-  for entry in entries.flatten() {
-    let file_name = entry.file_name();
-    let file_name_str = file_name.to_string_lossy();
+  for name in cron_jobs {
+    let unit = cron_unit(app, name);
+    expected_units.insert(format!("{}.service", unit));
+    expected_units.insert(format!("{}.timer", unit));
+  }
 
-    // Only consider service files matching our app pattern (exclude target files)
-    if !file_name_str.starts_with(&pattern) || !file_name_str.ends_with(".service") {
-      continue;
-    }
+  let files: Vec<String> = entries
+    .flatten()
+    .map(|entry| entry.file_name().to_string_lossy().to_string())
+    .collect();
+  let mut orphans: Vec<_> = files
+    .iter()
+    .filter(|file_name| {
+      // Only consider the app's service and timer files (exclude target files)
+      is_app_unit_file(app, file_name, &files)
+        && (file_name.ends_with(".service") || file_name.ends_with(".timer"))
+        && !expected_units.contains(file_name.as_str())
+    })
+    .map(|file_name| systemd_dir.join(file_name))
+    .collect();
+  orphans.sort();
+  orphans
+}
 
-    // Skip if this is an expected unit
-    if expected_units.contains(file_name_str.as_ref()) {
+/// Clean up orphaned unit files for processes/accessories that no longer exist.
+///
+/// This function:
+/// 1. Scans the systemd directory for unit files matching app-<app>-*.service (and .timer)
+/// 2. Identifies orphaned units (not in current processes, accessories or cron jobs)
+/// 3. Stops and disables each orphaned service
+/// 4. Deletes the orphaned unit file
+/// 5. Logs all actions
+async fn cleanup_orphaned_units_impl(
+  app: &str,
+  processes: &[String],
+  accessories: &[String],
+  cron_jobs: &[String],
+  systemd_dir: &std::path::Path,
+) -> Result<()> {
+  for unit_path in orphaned_units(app, processes, accessories, cron_jobs, systemd_dir) {
+    let Some(unit_name) = unit_path
+      .file_name()
+      .map(|n| n.to_string_lossy().to_string())
+    else {
       continue;
-    }
-
-    // Found an orphaned unit file
-    let unit_name = file_name_str.to_string();
-    let unit_path = entry.path();
+    };
 
     log(&format!("Found orphaned unit: {}", unit_name));
 
     // Stop the service (log warning if it fails, but don't propagate error)
-    let _ = systemctl_status_ok(
-      &["--user", "stop", &unit_name],
-      Some(&format!("stop {}", unit_name)),
-    )
-    .await;
+    let _ = systemctl_status_ok(&["stop", &unit_name], Some(&format!("stop {}", unit_name))).await;
 
     // Disable the service (log warning if it fails, but don't propagate error)
     let _ = systemctl_status_ok(
-      &["--user", "disable", &unit_name],
+      &["disable", &unit_name],
       Some(&format!("disable {}", unit_name)),
     )
     .await;
@@ -112,40 +168,131 @@ async fn cleanup_orphaned_units(
   app: &str,
   processes: &[String],
   accessories: &[String],
+  cron_jobs: &[String],
 ) -> Result<()> {
   let spec = UnitsSpec::builder(app)?.build();
-  cleanup_orphaned_units_impl(app, processes, accessories, &spec.systemd_dir).await
+  cleanup_orphaned_units_impl(app, processes, accessories, cron_jobs, &spec.systemd_dir).await
+}
+
+/// Cron units for the app's hl.yml, or none before it has one (e.g. during `hl init`).
+async fn cron_units(app: &str) -> Result<Vec<CronUnit>> {
+  if !app_dir(app).join("hl.yml").exists() && split_env_app_name(app).is_none() {
+    return Ok(vec![]);
+  }
+  let cfg = load_config(app).await?;
+  for job in &cfg.cron {
+    if job.name.is_empty()
+      || !job
+        .name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+      anyhow::bail!(
+        "invalid cron job name '{}': use lowercase letters, digits and dashes",
+        job.name
+      );
+    }
+    if job.command.is_empty() {
+      anyhow::bail!("cron job '{}' has no command", job.name);
+    }
+  }
+  Ok(CronUnit::from_config(&cfg))
 }
 
 /// Write systemd unit files for the given app, processes, and accessories.
 /// This function first cleans up any orphaned units, then generates and writes
 /// the necessary unit files based on the provided processes and accessories.
 /// It logs the outcome of each write operation.
-pub async fn write_unit(app: &str, processes: &[String], accessories: &[String]) -> Result<()> {
+pub async fn write_unit(
+  app: &str,
+  processes: &[String],
+  accessories: &[String],
+  mode: SystemdMode,
+) -> Result<()> {
+  let spec = units_spec(app, processes, accessories, mode).await?;
+  let cron_names: Vec<String> = spec.cron.iter().map(|c| c.name.clone()).collect();
+  warn_if_not_lingering().await;
   // Clean up orphaned units before writing new ones
-  cleanup_orphaned_units(app, processes, accessories).await?;
+  cleanup_orphaned_units(app, processes, accessories, &cron_names).await?;
 
-  let spec_builder = UnitsSpec::builder(app)?;
-  let spec = spec_builder
-    .processes(processes.to_vec())
-    .accessories(accessories.to_vec())
-    .build();
   let outcomes = render_and_write(&spec)?;
   for o in outcomes {
     match o {
       WriteOutcome::Created(p) => debug(&format!("Created {}", p.display())),
       WriteOutcome::Updated(p) => debug(&format!("Updated {}", p.display())),
       WriteOutcome::Unchanged(p) => debug(&format!("Unchanged {}", p.display())),
+      WriteOutcome::Removed(p) => debug(&format!("Removed {}", p.display())),
     }
   }
 
-  Ok(())
+  // The units now run exactly these; record them for discovery
+  update_state(app, &systemd_dir(), |state| {
+    state.processes = processes.to_vec();
+    state.accessories = accessories.to_vec();
+    state.scale.retain(|p, _| processes.contains(p));
+  })
+}
+
+/// Everything `write_unit` renders for the app, including its hl.yml cron jobs.
+async fn units_spec(
+  app: &str,
+  processes: &[String],
+  accessories: &[String],
+  mode: SystemdMode,
+) -> Result<UnitsSpec> {
+  let cron = cron_units(app).await?;
+  Ok(
+    UnitsSpec::builder(app)?
+      .processes(processes.to_vec())
+      .accessories(accessories.to_vec())
+      .mode(mode)
+      .cron(cron)
+      .build(),
+  )
+}
+
+/// What `write_unit` would change, without touching any file: unit files it would create or
+/// update, and orphans it would remove. Unchanged units are left out.
+pub async fn plan_unit_changes(
+  app: &str,
+  processes: &[String],
+  accessories: &[String],
+  mode: SystemdMode,
+) -> Result<Vec<UnitChange>> {
+  let spec = units_spec(app, processes, accessories, mode).await?;
+  let cron_names: Vec<String> = spec.cron.iter().map(|c| c.name.clone()).collect();
+  let mut changes: Vec<UnitChange> = plan_units(&spec)
+    .into_iter()
+    .filter(|c| !matches!(c.outcome, WriteOutcome::Unchanged(_)))
+    .collect();
+  for path in orphaned_units(app, processes, accessories, &cron_names, &spec.systemd_dir) {
+    changes.push(UnitChange {
+      old: fs::read_to_string(&path).unwrap_or_default(),
+      new: String::new(),
+      outcome: WriteOutcome::Removed(path),
+    });
+  }
+  Ok(changes)
+}
+
+/// Print each planned unit change with a colored diff.
+pub fn print_unit_changes(changes: &[UnitChange]) {
+  for change in changes {
+    let (verb, path) = match &change.outcome {
+      WriteOutcome::Created(p) => ("create", p),
+      WriteOutcome::Updated(p) => ("update", p),
+      WriteOutcome::Removed(p) => ("remove", p),
+      WriteOutcome::Unchanged(p) => ("keep", p),
+    };
+    log(&format!("{} {}", verb, path.display()));
+    print!("{}", colorize_diff(&format_diff(&change.old, &change.new)));
+  }
 }
 
 pub async fn enable_accessories(app: &str) -> Result<()> {
   let unit = format!("app-{}-acc.service", app);
   debug(&format!("enabling systemd service: {}", unit));
-  systemctl_cmd(&["--user", "enable", "--now", &unit]).await
+  systemctl_cmd(&["enable", "--now", &unit]).await
 }
 
 pub async fn enable_accessories_if_present(app: &str, accessories: &[String]) -> Result<()> {
@@ -157,50 +304,415 @@ pub async fn enable_accessories_if_present(app: &str, accessories: &[String]) ->
   enable_accessories(app).await
 }
 
+/// Enable and (re)start the timers of hl.yml `cron:` jobs, so a changed schedule applies.
+pub async fn enable_cron_timers(app: &str, names: &[String]) -> Result<()> {
+  for name in names {
+    let timer = format!("{}.timer", cron_unit(app, name));
+    debug(&format!("enabling systemd timer: {}", timer));
+    systemctl_cmd(&["enable", &timer]).await?;
+    systemctl_cmd(&["restart", &timer]).await?;
+  }
+  Ok(())
+}
+
 pub async fn restart_accessories(app: &str) -> Result<()> {
   let unit = format!("app-{}-acc.service", app);
   debug(&format!("restarting systemd service: {}", unit));
-  systemctl_cmd(&["--user", "restart", &unit]).await
+  systemctl_cmd(&["restart", &unit]).await
 }
 
 pub async fn start_accessories(app: &str) -> Result<()> {
   let unit = format!("app-{}-acc.service", app);
   debug(&format!("starting systemd service: {}", unit));
-  systemctl_cmd(&["--user", "start", &unit]).await
+  systemctl_cmd(&["start", &unit]).await
 }
 
 pub async fn restart_app_target(app: &str) -> Result<()> {
   let unit = format!("app-{}.target", app);
   debug(&format!("restarting systemd service: {}", unit));
-  systemctl_cmd(&["--user", "restart", &unit]).await
+  systemctl_cmd(&["restart", &unit]).await?;
+  // Processes now run with the current .env; `hl status` compares against this
+  if let Err(e) = record_applied_env(app) {
+    warn(&format!("failed to record applied .env: {}", e));
+  }
+  wait_for_process_units(app).await
+}
+
+/// Restart some of an app's process units, leaving the rest (and its accessories) running.
+pub async fn restart_process_units(app: &str, processes: &[String]) -> Result<()> {
+  let units: Vec<String> = processes
+    .iter()
+    .map(|process| format!("app-{}-{}.service", app, process))
+    .collect();
+  debug(&format!("restarting systemd services: {}", units.join(" ")));
+  let mut args = vec!["restart"];
+  args.extend(units.iter().map(String::as_str));
+  systemctl_cmd(&args).await?;
+  // Unchanged processes already run with the current .env: it is part of their fingerprint
+  if let Err(e) = record_applied_env(app) {
+    warn(&format!("failed to record applied .env: {}", e));
+  }
+  wait_for_process_units(app).await
+}
+
+/// Outcome of one `is-active` poll over an app's process units.
+#[derive(Debug, PartialEq)]
+enum UnitsVerdict {
+  Active,
+  Pending,
+  Failed(String),
+}
+
+fn units_verdict(states: &[(String, String)]) -> UnitsVerdict {
+  if let Some((unit, _)) = states.iter().find(|(_, state)| state == "failed") {
+    return UnitsVerdict::Failed(unit.clone());
+  }
+  if states.iter().all(|(_, state)| state == "active") {
+    UnitsVerdict::Active
+  } else {
+    UnitsVerdict::Pending
+  }
+}
+
+/// `systemctl restart` of the target succeeds even when a process unit fails to start, so
+/// poll the units until they are all active (twice in a row, to catch attached containers
+/// that exit right after starting) and fail with the unit's journal when one doesn't make it.
+async fn wait_for_process_units(app: &str) -> Result<()> {
+  let units: Vec<String> = discover_processes(&systemd_dir(), app)?
+    .iter()
+    .map(|process| format!("app-{}-{}.service", app, process))
+    .collect();
+  if units.is_empty() {
+    return Ok(());
+  }
+
+  let deadline = Instant::now() + UNIT_START_TIMEOUT;
+  let mut active_polls = 0;
+  loop {
+    let mut states = Vec::with_capacity(units.len());
+    for unit in &units {
+      states.push((unit.clone(), user_unit_active_state(unit).await?));
+    }
+    let pending = match units_verdict(&states) {
+      UnitsVerdict::Active => {
+        active_polls += 1;
+        if active_polls >= 2 {
+          debug(&format!("all process units of {} are active", app));
+          return Ok(());
+        }
+        None
+      }
+      UnitsVerdict::Pending => {
+        active_polls = 0;
+        states.into_iter().find(|(_, state)| state != "active")
+      }
+      UnitsVerdict::Failed(unit) => {
+        return Err(
+          Error::Systemd(anyhow::anyhow!(
+            "{} failed to start\n{}",
+            unit,
+            journal_tail(&unit).await
+          ))
+          .into(),
+        );
+      }
+    };
+    if Instant::now() >= deadline {
+      // Active on the last poll is good enough once the time is up
+      let Some((unit, state)) = pending else {
+        return Ok(());
+      };
+      return Err(
+        Error::Systemd(anyhow::anyhow!(
+          "{} is still {} after {}s\n{}",
+          unit,
+          state,
+          UNIT_START_TIMEOUT.as_secs(),
+          journal_tail(&unit).await
+        ))
+        .into(),
+      );
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+  }
+}
+
+/// The last journal lines of an app unit, for error messages; a hint when they can't be read.
+async fn journal_tail(unit: &str) -> String {
+  let mut args = vec![
+    "-u",
+    unit,
+    "-n",
+    JOURNAL_TAIL_LINES,
+    "--no-pager",
+    "-o",
+    "cat",
+  ];
+  if systemd_scope() == SystemdScope::User {
+    args.insert(0, "--user");
+  }
+  let output = Command::new("journalctl")
+    .args(&args)
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .await;
+  match output {
+    Ok(output) if output.status.success() && !output.stdout.is_empty() => {
+      String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string()
+    }
+    _ => format!("(no journal output; try: journalctl {})", args.join(" ")),
+  }
 }
 
 pub async fn reload_systemd_daemon() -> Result<()> {
-  systemctl_cmd(&["--user", "daemon-reload"]).await
+  systemctl_cmd(&["daemon-reload"]).await
+}
+
+pub async fn stop_app_target(app: &str) -> Result<()> {
+  let unit = format!("app-{}.target", app);
+  debug(&format!("stopping systemd target: {}", unit));
+  systemctl_cmd(&["stop", &unit]).await
 }
 
 pub async fn stop_disable_app_target(app: &str) -> Result<()> {
   let unit = format!("app-{}.target", app);
   debug(&format!("stopping and disabling systemd target: {}", unit));
-  systemctl_cmd(&["--user", "stop", &unit]).await?;
-  systemctl_cmd(&["--user", "disable", &unit]).await?;
+  systemctl_cmd(&["stop", &unit]).await?;
+  systemctl_cmd(&["disable", &unit]).await?;
 
   Ok(())
 }
 
+/// Base name of the units that drop `<key>_PREVIOUS` once a rotation's grace period ends.
+/// Deliberately not `app-<app>-*` so orphan cleanup on deploy leaves them alone.
+pub fn env_cleanup_unit(app: &str, key: &str) -> String {
+  format!("hl-{}-env-cleanup-{}", app, key)
+}
+
+/// Render the (service, timer) pair that runs `hl env finish-rotation <key>` at `at`
+/// (unix seconds). The timer is persistent so a cleanup missed while the host was down
+/// runs on the next boot. `run_as` renders a system unit, where %h would be root's home.
+pub fn render_env_cleanup_units(
+  app: &str,
+  key: &str,
+  at: u64,
+  run_as: Option<&str>,
+) -> (String, String) {
+  let unit = env_cleanup_unit(app, key);
+  let when = format_rfc3339(at).replace('T', " ").replace('Z', " UTC");
+  let (user, hl) = match run_as {
+    Some(user) => (
+      format!("User={}\n", user),
+      deploy_home().join(".local/bin/hl").display().to_string(),
+    ),
+    None => (String::new(), "%h/.local/bin/hl".to_string()),
+  };
+  let service = format!(
+    "[Unit]\n\
+     Description=Drop {key}_PREVIOUS from {app} after secret rotation\n\
+     \n\
+     [Service]\n\
+     Type=oneshot\n\
+     {user}\
+     Environment=HL_APP={app}\n\
+     ExecStart={hl} env finish-rotation {key}\n"
+  );
+  let timer = format!(
+    "[Unit]\n\
+     Description=Scheduled cleanup of {key}_PREVIOUS for {app}\n\
+     \n\
+     [Timer]\n\
+     OnCalendar={when}\n\
+     Persistent=true\n\
+     Unit={unit}.service\n\
+     \n\
+     [Install]\n\
+     WantedBy=timers.target\n"
+  );
+  (service, timer)
+}
+
+/// Install (or reschedule) the cleanup timer for a rotated key.
+pub async fn schedule_env_cleanup(app: &str, key: &str, at: u64) -> Result<()> {
+  let dir = systemd_dir();
+  let unit = env_cleanup_unit(app, key);
+  let run_as = system_unit_user()?;
+  let (service, timer) = render_env_cleanup_units(app, key, at, run_as.as_deref());
+  fs::create_dir_all(&dir)?;
+  fs::write(dir.join(format!("{}.service", unit)), service)?;
+  fs::write(dir.join(format!("{}.timer", unit)), timer)?;
+
+  let timer_unit = format!("{}.timer", unit);
+  debug(&format!(
+    "scheduling {} at {}",
+    timer_unit,
+    format_rfc3339(at)
+  ));
+  reload_systemd_daemon().await?;
+  systemctl_cmd(&["enable", &timer_unit]).await?;
+  // restart rather than start so a rescheduled timer picks up its new OnCalendar
+  systemctl_cmd(&["restart", &timer_unit]).await
+}
+
+/// Disable and delete a rotation cleanup timer. Missing units are not an error.
+pub async fn cancel_env_cleanup(app: &str, key: &str) -> Result<()> {
+  let dir = systemd_dir();
+  let unit = env_cleanup_unit(app, key);
+  let timer_unit = format!("{}.timer", unit);
+  let _ = systemctl_status_ok(
+    &["disable", "--now", &timer_unit],
+    Some(&format!("disable {}", timer_unit)),
+  )
+  .await;
+  for suffix in ["timer", "service"] {
+    let path = dir.join(format!("{}.{}", unit, suffix));
+    if path.exists() {
+      fs::remove_file(&path)?;
+    }
+  }
+  reload_systemd_daemon().await
+}
+
+/// Unit running `hl serve`. Deliberately not `app-*` so orphan cleanup on deploy leaves it alone.
+pub const SERVE_UNIT: &str = "hl-serve.service";
+
+/// Render the service that keeps `hl serve --listen <listen>` running. `run_as` renders a system
+/// unit, where %h would be root's home.
+pub fn render_serve_unit(listen: &str, run_as: Option<&str>) -> String {
+  let (user, hl, after, boot) = match run_as {
+    Some(user) => (
+      format!("User={}\n", user),
+      deploy_home().join(".local/bin/hl").display().to_string(),
+      "docker.service network-online.target",
+      "multi-user.target",
+    ),
+    None => (
+      String::new(),
+      "%h/.local/bin/hl".to_string(),
+      "default.target",
+      "default.target",
+    ),
+  };
+  format!(
+    "[Unit]\n\
+     Description=hl webhook server on {listen}\n\
+     After={after}\n\
+     \n\
+     [Service]\n\
+     {user}\
+     ExecStart={hl} serve --listen {listen}\n\
+     Restart=on-failure\n\
+     RestartSec=5\n\
+     \n\
+     [Install]\n\
+     WantedBy={boot}\n"
+  )
+}
+
+/// Write, enable and (re)start the `hl serve` unit.
+pub async fn install_serve_unit(listen: &str) -> Result<PathBuf> {
+  let dir = systemd_dir();
+  let run_as = system_unit_user()?;
+  let path = dir.join(SERVE_UNIT);
+  fs::create_dir_all(&dir)?;
+  fs::write(&path, render_serve_unit(listen, run_as.as_deref()))?;
+  reload_systemd_daemon().await?;
+  systemctl_cmd(&["enable", SERVE_UNIT]).await?;
+  // restart rather than start so a changed --listen takes effect
+  systemctl_cmd(&["restart", SERVE_UNIT]).await?;
+  Ok(path)
+}
+
+/// `systemctl` arguments addressing the manager that owns app units: `--user` unless the
+/// global config sets `systemd.scope: system`.
+fn scoped<'a>(args: &[&'a str]) -> Vec<&'a str> {
+  match systemd_scope() {
+    SystemdScope::User => std::iter::once("--user")
+      .chain(args.iter().copied())
+      .collect(),
+    SystemdScope::System => args.to_vec(),
+  }
+}
+
+/// A systemctl command line to show the user, e.g. in a fix-it hint.
+pub fn systemctl_hint(args: &str) -> String {
+  match systemd_scope() {
+    SystemdScope::User => format!("systemctl --user {}", args),
+    SystemdScope::System => format!("sudo systemctl {}", args),
+  }
+}
+
+/// Run systemctl `args` (without `--user`) over the manager's D-Bus API instead. None when
+/// they aren't a command the bus backend knows or the bus can't be used, to run systemctl.
+async fn via_bus(args: &[&str]) -> Option<Result<(), BusError>> {
+  let ops = plan_ops(args)?;
+  match Manager::new(systemd_scope()).run(&ops).await {
+    Err(BusError::Unavailable(reason)) => {
+      debug(&format!("{}; falling back to systemctl", reason));
+      None
+    }
+    result => Some(result),
+  }
+}
+
+/// `is-active` or `is-enabled` of `unit` under the manager of `scope`, over D-Bus when
+/// possible.
+async fn unit_state(scope: SystemdScope, query: &str, unit: &str) -> Result<String> {
+  let manager = Manager::new(scope);
+  let state = if query == "is-active" {
+    manager.active_state(unit).await
+  } else {
+    manager.unit_file_state(unit).await
+  };
+  match state {
+    Ok(state) => return Ok(state),
+    Err(BusError::NoSuchUnit(_)) if query == "is-active" => return Ok("inactive".to_string()),
+    Err(BusError::NoSuchUnit(_)) => return Ok("not-found".to_string()),
+    Err(BusError::Unavailable(reason)) => debug(&format!("{}; falling back to systemctl", reason)),
+    Err(e) => return Err(Error::Systemd(e.into()).into()),
+  }
+  let args = match scope {
+    SystemdScope::User => vec!["--user", query, unit],
+    SystemdScope::System => vec![query, unit],
+  };
+  systemctl_output(&args).await
+}
+
+/// Fallback for what the bus backend doesn't cover, or when busctl is missing. The output we
+/// parse (`is-active`, `is-enabled`) is never localized or colored.
+fn systemctl() -> Command {
+  let mut cmd = Command::new("systemctl");
+  cmd.env("LC_ALL", "C").env("SYSTEMD_COLORS", "0");
+  cmd
+}
+
 // Lightweight status check that does NOT error on non-zero exit.
 // When operation_desc is provided, logs warnings on failure.
 async fn systemctl_status_ok(args: &[&str], operation_desc: Option<&str>) -> Result<bool> {
-  let status = Command::new("systemctl").args(args).status().await;
+  let status = match via_bus(args).await {
+    Some(Ok(())) => Ok(true),
+    Some(Err(e)) => {
+      debug(&format!("{}", e));
+      Ok(false)
+    }
+    None => systemctl()
+      .args(scoped(args))
+      .status()
+      .await
+      .map(|s| s.success()),
+  };
 
   match status {
-    Ok(s) if s.success() => {
+    Ok(true) => {
       if let Some(desc) = operation_desc {
         debug(&format!("Successfully {}", desc));
       }
       Ok(true)
     }
-    Ok(_) => {
+    Ok(false) => {
       if let Some(desc) = operation_desc {
         log(&format!(
           "Warning: Failed to {} - may require manual intervention",
@@ -221,6 +733,79 @@ async fn systemctl_status_ok(args: &[&str], operation_desc: Option<&str>) -> Res
   }
 }
 
+// Runs systemctl and returns its trimmed stdout regardless of exit status.
+// Used for queries like `is-enabled` whose answer is printed rather than signalled.
+async fn systemctl_output(args: &[&str]) -> Result<String> {
+  let output = systemctl()
+    .args(args)
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .await?;
+  Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Returns the `is-enabled` state of an app unit (e.g. "enabled", "disabled", "not-found").
+pub async fn user_unit_enabled_state(unit: &str) -> Result<String> {
+  unit_state(systemd_scope(), "is-enabled", unit).await
+}
+
+/// Returns the `is-active` state of an app unit (e.g. "active", "inactive", "failed").
+pub async fn user_unit_active_state(unit: &str) -> Result<String> {
+  unit_state(systemd_scope(), "is-active", unit).await
+}
+
+/// Returns the `is-enabled` state of a system unit such as `docker.service`.
+pub async fn system_unit_enabled_state(unit: &str) -> Result<String> {
+  unit_state(SystemdScope::System, "is-enabled", unit).await
+}
+
+/// Returns true when lingering is enabled for the current user, which keeps the user
+/// manager (and therefore all app units) running without an active login session.
+pub async fn is_lingering_enabled() -> Result<bool> {
+  let user = std::env::var("USER").context("USER environment variable not set")?;
+  let output = Command::new("loginctl")
+    .args(["show-user", &user, "--property=Linger", "--value"])
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .await
+    .context("Failed to run loginctl")?;
+  Ok(output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "yes")
+}
+
+/// Let the user manager run without a login session. Enabling it for yourself is allowed by
+/// logind's default policy on most distributions; elsewhere it takes root.
+pub async fn enable_lingering() -> Result<()> {
+  let user = std::env::var("USER").context("USER environment variable not set")?;
+  let status = Command::new("loginctl")
+    .args(["enable-linger", &user])
+    .status()
+    .await
+    .context("Failed to run loginctl")?;
+  if !status.success() {
+    anyhow::bail!(
+      "loginctl enable-linger failed; run it as root: sudo loginctl enable-linger {}",
+      user
+    );
+  }
+  Ok(())
+}
+
+/// Units written without lingering only run while their user is logged in, so say so at the
+/// moment they are created rather than after the next reboot.
+async fn warn_if_not_lingering() {
+  if systemd_scope() != SystemdScope::User {
+    return;
+  }
+  if let Ok(false) = is_lingering_enabled().await {
+    warn(
+      "lingering is disabled for this user: app units stop when the last session ends and \
+       won't start at boot (fix: sudo loginctl enable-linger $USER)",
+    );
+  }
+}
+
 /// Reload unit files, then:
 /// - if `unit` is active -> enable + restart (to pick up changes)
 /// - else                -> enable --now (start if new/inactive, no extra bounce)
@@ -228,30 +813,41 @@ pub async fn apply_unit_changes(unit: &str) -> Result<()> {
   // 1) Make systemd read updated unit files
   reload_systemd_daemon().await?;
   // 2) Check if it's currently active
-  let is_active = systemctl_status_ok(&["--user", "is-active", unit], None).await?;
+  let is_active = user_unit_active_state(unit).await? == "active";
   if is_active {
     // Known & running: ensure enabled, then restart to apply new unit config
-    systemctl_cmd(&["--user", "enable", unit]).await?;
-    systemctl_cmd(&["--user", "restart", unit]).await?;
+    systemctl_cmd(&["enable", unit]).await?;
+    systemctl_cmd(&["restart", unit]).await?;
   } else {
     // New or stopped: enable and start once (no redundant restart)
-    systemctl_cmd(&["--user", "enable", "--now", unit]).await?;
+    systemctl_cmd(&["enable", "--now", unit]).await?;
   }
 
   Ok(())
 }
 
 async fn systemctl_cmd(args: &[&str]) -> Result<()> {
-  let status = Command::new("systemctl")
-    .args(args)
+  if let Some(result) = via_bus(args).await {
+    return result.map_err(|e| Error::Systemd(e.into()).into());
+  }
+  let args = scoped(args);
+  let status = systemctl()
+    .args(&args)
     .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
+    .stdout(progress_stdout())
     .stderr(Stdio::inherit())
     .status()
     .await?;
 
   if !status.success() {
-    anyhow::bail!("systemctl --user {:?} failed with status: {}", args, status);
+    return Err(
+      Error::Systemd(anyhow::anyhow!(
+        "systemctl {:?} failed with status: {}",
+        args,
+        status
+      ))
+      .into(),
+    );
   }
 
   Ok(())
@@ -264,6 +860,65 @@ mod tests {
   use std::io::Write;
   use tempfile::TempDir;
 
+  #[test]
+  fn test_render_env_cleanup_units() {
+    let (service, timer) =
+      render_env_cleanup_units("myapp", "SECRET_KEY_BASE", 1_700_000_000, None);
+    assert!(service.contains("Environment=HL_APP=myapp\n"));
+    assert!(service.contains("ExecStart=%h/.local/bin/hl env finish-rotation SECRET_KEY_BASE\n"));
+    assert!(timer.contains("OnCalendar=2023-11-14 22:13:20 UTC\n"));
+    assert!(timer.contains("Unit=hl-myapp-env-cleanup-SECRET_KEY_BASE.service\n"));
+    assert!(timer.contains("Persistent=true\n"));
+
+    let (service, _) =
+      render_env_cleanup_units("myapp", "SECRET_KEY_BASE", 1_700_000_000, Some("deploy"));
+    assert!(service.contains("User=deploy\n"));
+    assert!(!service.contains("%h"));
+  }
+
+  #[test]
+  fn test_render_serve_unit() {
+    let unit = render_serve_unit("127.0.0.1:9876", None);
+    assert!(unit.contains("ExecStart=%h/.local/bin/hl serve --listen 127.0.0.1:9876\n"));
+    assert!(unit.contains("WantedBy=default.target\n"));
+
+    let unit = render_serve_unit("0.0.0.0:9876", Some("deploy"));
+    assert!(unit.contains("User=deploy\n"));
+    assert!(unit.contains("WantedBy=multi-user.target\n"));
+    assert!(!unit.contains("%h"));
+  }
+
+  #[test]
+  fn test_units_verdict() {
+    let states = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+      pairs
+        .iter()
+        .map(|(unit, state)| (unit.to_string(), state.to_string()))
+        .collect()
+    };
+    assert_eq!(
+      units_verdict(&states(&[
+        ("app-a-web.service", "active"),
+        ("app-a-worker.service", "active")
+      ])),
+      UnitsVerdict::Active
+    );
+    assert_eq!(
+      units_verdict(&states(&[
+        ("app-a-web.service", "active"),
+        ("app-a-worker.service", "activating")
+      ])),
+      UnitsVerdict::Pending
+    );
+    assert_eq!(
+      units_verdict(&states(&[
+        ("app-a-web.service", "activating"),
+        ("app-a-worker.service", "failed")
+      ])),
+      UnitsVerdict::Failed("app-a-worker.service".to_string())
+    );
+  }
+
   #[tokio::test]
   async fn test_cleanup_orphaned_units() -> Result<()> {
     let temp_dir = TempDir::new()?;
@@ -347,7 +1002,7 @@ Description=Test app target"#
     // For this, we need to make it pub(crate) or test it through write_unit
 
     // Call cleanup_orphaned_units_impl with our test directory
-    cleanup_orphaned_units_impl("testapp", &processes, &accessories, &systemd_dir).await?;
+    cleanup_orphaned_units_impl("testapp", &processes, &accessories, &[], &systemd_dir).await?;
 
     // Verify orphaned files are deleted
     for file_name in &orphaned_files {
@@ -377,6 +1032,31 @@ Description=Test app target"#
     Ok(())
   }
 
+  #[test]
+  fn test_orphaned_units_skip_environment_units() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let systemd_dir = temp_dir.path();
+    for name in [
+      "app-myapp.target",
+      "app-myapp-web.service",
+      "app-myapp-old.service",
+      "app-myapp-staging.target",
+      "app-myapp-staging-web.service",
+      "app-myapp-staging-cron-nightly.timer",
+    ] {
+      File::create(systemd_dir.join(name))?;
+    }
+
+    let orphans = orphaned_units("myapp", &["web".to_string()], &[], &[], systemd_dir);
+    assert_eq!(orphans, vec![systemd_dir.join("app-myapp-old.service")]);
+
+    // Without the environment's target, the same file is an old `staging-web` process
+    fs::remove_file(systemd_dir.join("app-myapp-staging.target"))?;
+    let orphans = orphaned_units("myapp", &["web".to_string()], &[], &[], systemd_dir);
+    assert!(orphans.contains(&systemd_dir.join("app-myapp-staging-web.service")));
+    Ok(())
+  }
+
   #[tokio::test]
   async fn test_cleanup_orphaned_units_with_accessories() -> Result<()> {
     let temp_dir = TempDir::new()?;
@@ -418,7 +1098,7 @@ ExecStart=/bin/true"#
     let processes = vec!["web".to_string()];
     let accessories: Vec<String> = vec![];
 
-    cleanup_orphaned_units_impl("testapp", &processes, &accessories, &systemd_dir).await?;
+    cleanup_orphaned_units_impl("testapp", &processes, &accessories, &[], &systemd_dir).await?;
 
     // Verify both are deleted
     assert!(
@@ -444,8 +1124,9 @@ ExecStart=/bin/true"#
     let current_web = systemd_dir.join("app-myapp-web.service");
     let current_worker = systemd_dir.join("app-myapp-worker.service");
     let current_acc = systemd_dir.join("app-myapp-acc.service");
+    let current_cron = systemd_dir.join("app-myapp-cron-nightly.timer");
 
-    for path in &[&current_web, &current_worker, &current_acc] {
+    for path in &[&current_web, &current_worker, &current_acc, &current_cron] {
       let mut file = File::create(path)?;
       writeln!(
         file,
@@ -475,7 +1156,13 @@ ExecStart=/bin/true"#
     let processes = vec!["web".to_string(), "worker".to_string()];
     let accessories = vec!["postgres".to_string()];
 
-    cleanup_orphaned_units_impl("myapp", &processes, &accessories, &systemd_dir).await?;
+    // A cron job that was removed from hl.yml
+    let orphaned_cron = systemd_dir.join("app-myapp-cron-weekly.timer");
+    File::create(&orphaned_cron)?;
+
+    let cron_jobs = vec!["nightly".to_string()];
+    cleanup_orphaned_units_impl("myapp", &processes, &accessories, &cron_jobs, &systemd_dir)
+      .await?;
 
     // Verify current units are preserved
     assert!(
@@ -491,8 +1178,17 @@ ExecStart=/bin/true"#
       "Current accessories service should be preserved"
     );
 
+    assert!(
+      current_cron.exists(),
+      "Current cron timer should be preserved"
+    );
+
     // Verify orphaned is deleted
     assert!(!orphaned.exists(), "Orphaned service should be deleted");
+    assert!(
+      !orphaned_cron.exists(),
+      "Orphaned cron timer should be deleted"
+    );
 
     Ok(())
   }
@@ -505,7 +1201,7 @@ ExecStart=/bin/true"#
     let accessories: Vec<String> = vec![];
 
     // Should complete successfully without panicking
-    let result = cleanup_orphaned_units("testapp", &processes, &accessories).await;
+    let result = cleanup_orphaned_units("testapp", &processes, &accessories, &[]).await;
 
     assert!(
       result.is_ok(),
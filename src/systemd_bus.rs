@@ -0,0 +1,520 @@
+/*
+The systemd manager's D-Bus API (org.freedesktop.systemd1), driven through busctl. Unlike
+systemctl, a method call answers with the job it queued, which is polled until it is done, and
+failures come back as the bus error rather than as localized text to parse. No D-Bus client
+library is needed: busctl ships with systemd and replies in JSON.
+
+When busctl isn't installed or can't reach the bus, calls fail with `BusError::Unavailable`
+and systemd.rs runs systemctl instead.
+ */
+
+use crate::config::SystemdScope;
+use crate::log::debug;
+use crate::output::from_json;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+const DESTINATION: &str = "org.freedesktop.systemd1";
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER_IFACE: &str = "org.freedesktop.systemd1.Manager";
+const UNIT_IFACE: &str = "org.freedesktop.systemd1.Unit";
+const SERVICE_IFACE: &str = "org.freedesktop.systemd1.Service";
+const JOB_IFACE: &str = "org.freedesktop.systemd1.Job";
+
+/// How often a queued job is checked for completion.
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, thiserror::Error)]
+pub enum BusError {
+  /// busctl isn't installed or the manager's bus can't be reached
+  #[error("{0}")]
+  Unavailable(String),
+  /// The unit (or its unit file) doesn't exist
+  #[error("unit {0} not found")]
+  NoSuchUnit(String),
+  /// The manager refused the call, e.g. a system unit changed without root
+  #[error("access denied to {method}: {message}")]
+  AccessDenied { method: String, message: String },
+  /// The job ran but left the unit down
+  #[error("{verb} {unit} failed: unit is {state}{}", result.as_ref().map(|r| format!(" (result: {})", r)).unwrap_or_default())]
+  JobFailed {
+    verb: &'static str,
+    unit: String,
+    state: String,
+    result: Option<String>,
+  },
+  /// Any other error the bus answered with
+  #[error("{method} failed: {message}")]
+  Call { method: String, message: String },
+}
+
+/// One systemctl step in terms of the manager's methods.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnitOp {
+  /// `daemon-reload`
+  Reload,
+  Enable(String),
+  Disable(String),
+  Start(String),
+  Stop(String),
+  Restart(String),
+}
+
+impl UnitOp {
+  fn verb(&self) -> &'static str {
+    match self {
+      UnitOp::Reload => "daemon-reload",
+      UnitOp::Enable(_) => "enable",
+      UnitOp::Disable(_) => "disable",
+      UnitOp::Start(_) => "start",
+      UnitOp::Stop(_) => "stop",
+      UnitOp::Restart(_) => "restart",
+    }
+  }
+}
+
+/// The steps of a systemctl command line (without `--user`), e.g. `enable --now x.service` is
+/// an enable and a start. None for anything else, which is left to systemctl.
+pub fn plan_ops(args: &[&str]) -> Option<Vec<UnitOp>> {
+  let (verb, rest) = args.split_first()?;
+  let now = rest.first() == Some(&"--now");
+  let units: Vec<String> = rest
+    .iter()
+    .skip(usize::from(now))
+    .map(|u| u.to_string())
+    .collect();
+  if units.iter().any(|u| u.starts_with('-')) {
+    return None;
+  }
+  let each = |op: fn(String) -> UnitOp| units.iter().cloned().map(op);
+  let ops: Vec<UnitOp> = match (*verb, now) {
+    ("daemon-reload", false) if units.is_empty() => return Some(vec![UnitOp::Reload]),
+    ("enable", false) => each(UnitOp::Enable).collect(),
+    ("enable", true) => each(UnitOp::Enable).chain(each(UnitOp::Start)).collect(),
+    ("disable", false) => each(UnitOp::Disable).collect(),
+    ("disable", true) => each(UnitOp::Stop).chain(each(UnitOp::Disable)).collect(),
+    ("start", false) => each(UnitOp::Start).collect(),
+    ("stop", false) => each(UnitOp::Stop).collect(),
+    ("restart", false) => each(UnitOp::Restart).collect(),
+    _ => return None,
+  };
+  (!ops.is_empty()).then_some(ops)
+}
+
+/// The service manager of one scope.
+pub struct Manager {
+  scope: SystemdScope,
+}
+
+impl Manager {
+  pub fn new(scope: SystemdScope) -> Self {
+    Self { scope }
+  }
+
+  /// Run `ops` in order. Jobs queued back to back (restarting several units) run together,
+  /// and each is waited for before the next unit file change or reload.
+  pub async fn run(&self, ops: &[UnitOp]) -> Result<(), BusError> {
+    let mut pending = Vec::new();
+    for op in ops {
+      let (method, unit) = match op {
+        UnitOp::Start(unit) => ("StartUnit", unit),
+        UnitOp::Stop(unit) => ("StopUnit", unit),
+        UnitOp::Restart(unit) => ("RestartUnit", unit),
+        UnitOp::Reload | UnitOp::Enable(_) | UnitOp::Disable(_) => {
+          self.finish_jobs(std::mem::take(&mut pending)).await?;
+          self.change_unit_files(op).await?;
+          continue;
+        }
+      };
+      let reply: Vec<String> = self
+        .call(
+          MANAGER_PATH,
+          MANAGER_IFACE,
+          method,
+          &["ss", unit, "replace"],
+          Some(unit),
+        )
+        .await?;
+      let job = reply.into_iter().next().ok_or_else(|| BusError::Call {
+        method: method.to_string(),
+        message: "reply has no job".to_string(),
+      })?;
+      debug(&format!("{} {}: job {}", op.verb(), unit, job));
+      pending.push((op, job));
+    }
+    self.finish_jobs(pending).await
+  }
+
+  async fn change_unit_files(&self, op: &UnitOp) -> Result<(), BusError> {
+    match op {
+      UnitOp::Enable(unit) => {
+        // (carries install info, changes): nothing hl acts on
+        self
+          .call_ignoring_reply(
+            "EnableUnitFiles",
+            &["asbb", "1", unit, "false", "false"],
+            Some(unit),
+          )
+          .await?;
+      }
+      UnitOp::Disable(unit) => {
+        self
+          .call_ignoring_reply("DisableUnitFiles", &["asb", "1", unit, "false"], Some(unit))
+          .await?;
+      }
+      _ => {}
+    }
+    // systemctl enable/disable reload the manager too, so the new symlinks take effect
+    self.call_ignoring_reply("Reload", &[], None).await
+  }
+
+  /// Wait for each queued job, then check started units actually came up.
+  async fn finish_jobs(&self, jobs: Vec<(&UnitOp, String)>) -> Result<(), BusError> {
+    for (op, job) in jobs {
+      self.wait_for_job(&job).await?;
+      if let UnitOp::Start(unit) | UnitOp::Restart(unit) = op {
+        self.check_started(op.verb(), unit).await?;
+      }
+    }
+    Ok(())
+  }
+
+  /// A job object exists until the job is done.
+  async fn wait_for_job(&self, job: &str) -> Result<(), BusError> {
+    loop {
+      match self.property::<String>(job, JOB_IFACE, "State", None).await {
+        Ok(_) => tokio::time::sleep(JOB_POLL_INTERVAL).await,
+        Err(BusError::Call { message, .. }) if message.contains("Unknown object") => return Ok(()),
+        Err(e) => return Err(e),
+      }
+    }
+  }
+
+  async fn check_started(&self, verb: &'static str, unit: &str) -> Result<(), BusError> {
+    let state = self.active_state(unit).await?;
+    if matches!(state.as_str(), "active" | "activating" | "reloading") {
+      return Ok(());
+    }
+    let result = if unit.ends_with(".service") {
+      let path = self.unit_path(unit).await?;
+      self
+        .property::<String>(&path, SERVICE_IFACE, "Result", Some(unit))
+        .await
+        .ok()
+    } else {
+      None
+    };
+    // A oneshot service without RemainAfterExit is inactive once it ran successfully
+    if state == "inactive" && result.as_deref() == Some("success") {
+      return Ok(());
+    }
+    Err(BusError::JobFailed {
+      verb,
+      unit: unit.to_string(),
+      state,
+      result,
+    })
+  }
+
+  /// `ActiveState` of `unit`, e.g. "active", "inactive" or "failed". Units that don't exist are
+  /// "inactive", as with `systemctl is-active`.
+  pub async fn active_state(&self, unit: &str) -> Result<String, BusError> {
+    let path = self.unit_path(unit).await?;
+    self
+      .property(&path, UNIT_IFACE, "ActiveState", Some(unit))
+      .await
+  }
+
+  /// Enablement of `unit`'s file, e.g. "enabled" or "disabled", as with `systemctl is-enabled`.
+  pub async fn unit_file_state(&self, unit: &str) -> Result<String, BusError> {
+    let reply: Vec<String> = self
+      .call(
+        MANAGER_PATH,
+        MANAGER_IFACE,
+        "GetUnitFileState",
+        &["s", unit],
+        Some(unit),
+      )
+      .await?;
+    Ok(reply.into_iter().next().unwrap_or_default())
+  }
+
+  /// Object path of `unit`, loading it if needed.
+  async fn unit_path(&self, unit: &str) -> Result<String, BusError> {
+    let reply: Vec<String> = self
+      .call(
+        MANAGER_PATH,
+        MANAGER_IFACE,
+        "LoadUnit",
+        &["s", unit],
+        Some(unit),
+      )
+      .await?;
+    reply.into_iter().next().ok_or_else(|| BusError::Call {
+      method: "LoadUnit".to_string(),
+      message: "reply has no unit path".to_string(),
+    })
+  }
+
+  async fn call<T: DeserializeOwned>(
+    &self,
+    path: &str,
+    iface: &str,
+    method: &str,
+    args: &[&str],
+    unit: Option<&str>,
+  ) -> Result<T, BusError> {
+    let mut tail = vec![path, iface, method];
+    tail.extend(args);
+    let stdout = self.busctl("call", &tail, method, unit).await?;
+    parse_reply(&stdout, method)
+  }
+
+  async fn call_ignoring_reply(
+    &self,
+    method: &str,
+    args: &[&str],
+    unit: Option<&str>,
+  ) -> Result<(), BusError> {
+    let mut tail = vec![MANAGER_PATH, MANAGER_IFACE, method];
+    tail.extend(args);
+    self.busctl("call", &tail, method, unit).await.map(|_| ())
+  }
+
+  async fn property<T: DeserializeOwned>(
+    &self,
+    path: &str,
+    iface: &str,
+    name: &str,
+    unit: Option<&str>,
+  ) -> Result<T, BusError> {
+    let stdout = self
+      .busctl("get-property", &[path, iface, name], name, unit)
+      .await?;
+    parse_reply(&stdout, name)
+  }
+
+  async fn busctl(
+    &self,
+    verb: &str,
+    tail: &[&str],
+    method: &str,
+    unit: Option<&str>,
+  ) -> Result<String, BusError> {
+    let output = Command::new("busctl")
+      .args(busctl_args(self.scope, verb, tail))
+      .env("LC_ALL", "C")
+      .stdin(Stdio::null())
+      .output()
+      .await
+      .map_err(|e| BusError::Unavailable(format!("can't run busctl: {}", e)))?;
+    if output.status.success() {
+      return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+    }
+    Err(classify(
+      method,
+      unit,
+      String::from_utf8_lossy(&output.stderr).trim(),
+    ))
+  }
+}
+
+/// busctl arguments for `verb` (call, get-property) on the manager of `scope`.
+fn busctl_args(scope: SystemdScope, verb: &str, tail: &[&str]) -> Vec<String> {
+  let bus = match scope {
+    SystemdScope::User => "--user",
+    SystemdScope::System => "--system",
+  };
+  [bus, "--json=short", verb, DESTINATION]
+    .iter()
+    .chain(tail)
+    .map(|a| a.to_string())
+    .collect()
+}
+
+/// busctl prints replies as `{"type":"<signature>","data":...}`.
+#[derive(Deserialize)]
+struct Reply<T> {
+  data: T,
+}
+
+fn parse_reply<T: DeserializeOwned>(stdout: &str, method: &str) -> Result<T, BusError> {
+  from_json::<Reply<T>>(stdout.trim())
+    .map(|reply| reply.data)
+    .map_err(|e| BusError::Call {
+      method: method.to_string(),
+      message: format!("{:#}: {}", e, stdout.trim()),
+    })
+}
+
+/// The error behind busctl's message for a failed call of `method` on `unit`.
+fn classify(method: &str, unit: Option<&str>, stderr: &str) -> BusError {
+  let message = stderr
+    .strip_prefix("Call failed: ")
+    .unwrap_or(stderr)
+    .to_string();
+  if message.starts_with("Failed to connect to bus") {
+    return BusError::Unavailable(message);
+  }
+  if message.contains("Access denied") || message.contains("Interactive authentication required") {
+    return BusError::AccessDenied {
+      method: method.to_string(),
+      message,
+    };
+  }
+  match unit {
+    Some(unit)
+      if message.contains("not found")
+        || message.contains("not loaded")
+        || message.contains("does not exist")
+        || message.contains("No such file or directory") =>
+    {
+      BusError::NoSuchUnit(unit.to_string())
+    }
+    _ => BusError::Call {
+      method: method.to_string(),
+      message,
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn units(names: &[&str]) -> Vec<String> {
+    names.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn test_plan_ops() {
+    assert_eq!(plan_ops(&["daemon-reload"]), Some(vec![UnitOp::Reload]));
+    assert_eq!(
+      plan_ops(&["enable", "--now", "app-x-acc.service"]),
+      Some(vec![
+        UnitOp::Enable("app-x-acc.service".to_string()),
+        UnitOp::Start("app-x-acc.service".to_string()),
+      ])
+    );
+    assert_eq!(
+      plan_ops(&["disable", "--now", "t.timer"]),
+      Some(vec![
+        UnitOp::Stop("t.timer".to_string()),
+        UnitOp::Disable("t.timer".to_string()),
+      ])
+    );
+    assert_eq!(
+      plan_ops(&["restart", "a.service", "b.service"]),
+      Some(
+        units(&["a.service", "b.service"])
+          .into_iter()
+          .map(UnitOp::Restart)
+          .collect()
+      )
+    );
+    // Left to systemctl
+    assert_eq!(plan_ops(&["is-active", "a.service"]), None);
+    assert_eq!(plan_ops(&["restart", "--no-block", "a.service"]), None);
+    assert_eq!(plan_ops(&["start"]), None);
+    assert_eq!(plan_ops(&[]), None);
+  }
+
+  #[test]
+  fn test_busctl_args() {
+    assert_eq!(
+      busctl_args(
+        SystemdScope::User,
+        "call",
+        &[
+          MANAGER_PATH,
+          MANAGER_IFACE,
+          "StartUnit",
+          "ss",
+          "a.service",
+          "replace"
+        ]
+      ),
+      units(&[
+        "--user",
+        "--json=short",
+        "call",
+        "org.freedesktop.systemd1",
+        "/org/freedesktop/systemd1",
+        "org.freedesktop.systemd1.Manager",
+        "StartUnit",
+        "ss",
+        "a.service",
+        "replace",
+      ])
+    );
+    assert_eq!(
+      busctl_args(
+        SystemdScope::System,
+        "get-property",
+        &["/p", UNIT_IFACE, "ActiveState"]
+      )[0],
+      "--system"
+    );
+  }
+
+  #[test]
+  fn test_parse_reply() {
+    let job: Vec<String> = parse_reply(
+      "{\"type\":\"o\",\"data\":[\"/org/freedesktop/systemd1/job/42\"]}\n",
+      "StartUnit",
+    )
+    .unwrap();
+    assert_eq!(job, units(&["/org/freedesktop/systemd1/job/42"]));
+    let state: String = parse_reply("{\"type\":\"s\",\"data\":\"failed\"}", "ActiveState").unwrap();
+    assert_eq!(state, "failed");
+    assert!(matches!(
+      parse_reply::<String>("", "ActiveState"),
+      Err(BusError::Call { .. })
+    ));
+  }
+
+  #[test]
+  fn test_classify() {
+    assert!(matches!(
+      classify("StopUnit", Some("a.service"), "Call failed: Unit a.service not loaded."),
+      BusError::NoSuchUnit(unit) if unit == "a.service"
+    ));
+    assert!(matches!(
+      classify(
+        "StartUnit",
+        Some("a.service"),
+        "Call failed: Interactive authentication required."
+      ),
+      BusError::AccessDenied { .. }
+    ));
+    assert!(matches!(
+      classify(
+        "Reload",
+        None,
+        "Failed to connect to bus: No such file or directory"
+      ),
+      BusError::Unavailable(_)
+    ));
+    assert!(matches!(
+      classify(
+        "State",
+        None,
+        "Failed to get property State on interface org.freedesktop.systemd1.Job: Unknown object '/org/freedesktop/systemd1/job/42'."
+      ),
+      BusError::Call { message, .. } if message.contains("Unknown object")
+    ));
+    assert_eq!(
+      BusError::JobFailed {
+        verb: "restart",
+        unit: "app-x-web.service".to_string(),
+        state: "failed".to_string(),
+        result: Some("exit-code".to_string()),
+      }
+      .to_string(),
+      "restart app-x-web.service failed: unit is failed (result: exit-code)"
+    );
+  }
+}
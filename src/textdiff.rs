@@ -0,0 +1,113 @@
+use colored::Colorize;
+
+/// Lines of context shown around each change.
+const CONTEXT: usize = 2;
+
+#[derive(Debug, PartialEq)]
+pub enum DiffLine<'a> {
+  Same(&'a str),
+  Removed(&'a str),
+  Added(&'a str),
+}
+
+/// Line-by-line diff of two texts (longest common subsequence; meant for config-sized files).
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+  let old: Vec<&str> = old.lines().collect();
+  let new: Vec<&str> = new.lines().collect();
+
+  // lcs[i][j]: length of the common subsequence of old[i..] and new[j..]
+  let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+  for i in (0..old.len()).rev() {
+    for j in (0..new.len()).rev() {
+      lcs[i][j] = if old[i] == new[j] {
+        lcs[i + 1][j + 1] + 1
+      } else {
+        lcs[i + 1][j].max(lcs[i][j + 1])
+      };
+    }
+  }
+
+  let mut lines = Vec::new();
+  let (mut i, mut j) = (0, 0);
+  while i < old.len() || j < new.len() {
+    if i < old.len() && j < new.len() && old[i] == new[j] {
+      lines.push(DiffLine::Same(old[i]));
+      i += 1;
+      j += 1;
+    } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+      lines.push(DiffLine::Removed(old[i]));
+      i += 1;
+    } else {
+      lines.push(DiffLine::Added(new[j]));
+      j += 1;
+    }
+  }
+  lines
+}
+
+/// `-`/`+` lines for what changed, with a little context and `...` between distant changes.
+/// Empty when the texts have the same lines.
+pub fn format_diff(old: &str, new: &str) -> String {
+  let lines = diff_lines(old, new);
+  let changed: Vec<usize> = (0..lines.len())
+    .filter(|&i| !matches!(lines[i], DiffLine::Same(_)))
+    .collect();
+
+  let mut out = String::new();
+  let mut last_shown: Option<usize> = None;
+  for (i, line) in lines.iter().enumerate() {
+    let near_change = changed
+      .iter()
+      .any(|&c| i + CONTEXT >= c && i <= c + CONTEXT);
+    if !near_change {
+      continue;
+    }
+    if last_shown.is_some_and(|last| i > last + 1) {
+      out.push_str("  ...\n");
+    }
+    last_shown = Some(i);
+    match line {
+      DiffLine::Same(l) => out.push_str(&format!("  {}\n", l)),
+      DiffLine::Removed(l) => out.push_str(&format!("- {}\n", l)),
+      DiffLine::Added(l) => out.push_str(&format!("+ {}\n", l)),
+    }
+  }
+  out
+}
+
+/// Color the `-`/`+` lines of a `format_diff` red and green (unless colors are disabled).
+pub fn colorize_diff(diff: &str) -> String {
+  diff
+    .lines()
+    .map(|line| {
+      if line.starts_with("- ") {
+        format!("{}\n", line.red())
+      } else if line.starts_with("+ ") {
+        format!("{}\n", line.green())
+      } else {
+        format!("{}\n", line)
+      }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_format_diff() {
+    let old = "app: myapp\nimage: a\nport: 1\none\ntwo\nthree\nfour\nfive\nsix\n";
+    let new = "app: myapp\nimage: b\nport: 1\none\ntwo\nthree\nfour\nfive\nsix\nseven\n";
+    assert_eq!(
+      format_diff(old, new),
+      "  app: myapp\n- image: a\n+ image: b\n  port: 1\n  one\n  ...\n  five\n  six\n+ seven\n"
+    );
+    assert_eq!(format_diff(old, old), "");
+    assert_eq!(
+      diff_lines("", "a"),
+      vec![DiffLine::Added("a")],
+      "new files are all additions"
+    );
+  }
+}
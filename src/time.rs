@@ -0,0 +1,120 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the unix epoch.
+pub fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0)
+}
+
+/// Format unix seconds as an RFC 3339 UTC timestamp, e.g. `2024-05-01T10:00:00Z`.
+pub fn format_rfc3339(secs: u64) -> String {
+  let days = (secs / 86_400) as i64;
+  let rem = secs % 86_400;
+  let (y, m, d) = civil_from_days(days);
+  format!(
+    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+    y,
+    m,
+    d,
+    rem / 3600,
+    (rem % 3600) / 60,
+    rem % 60
+  )
+}
+
+/// Parse an RFC 3339 UTC timestamp as produced by docker (`2024-05-01T10:00:00.123456789Z`)
+/// into unix seconds. Fractional seconds are dropped; non-UTC offsets are not supported.
+pub fn parse_rfc3339(s: &str) -> Option<u64> {
+  let s = s.strip_suffix('Z')?;
+  let (date, time) = s.split_once('T')?;
+  let mut date_parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+  let (y, m, d) = (
+    date_parts.next()??,
+    date_parts.next()??,
+    date_parts.next()??,
+  );
+  let time = time.split('.').next()?;
+  let mut time_parts = time.splitn(3, ':').map(|p| p.parse::<u64>().ok());
+  let (hh, mm, ss) = (
+    time_parts.next()??,
+    time_parts.next()??,
+    time_parts.next()??,
+  );
+  let days = days_from_civil(y, m as u32, d as u32);
+  if days < 0 {
+    return None;
+  }
+  Some(days as u64 * 86_400 + hh * 3600 + mm * 60 + ss)
+}
+
+/// Compact duration, e.g. "45s", "5m", "3h", "2d".
+pub fn format_duration(secs: u64) -> String {
+  match secs {
+    0..=59 => format!("{}s", secs),
+    60..=3599 => format!("{}m", secs / 60),
+    3600..=86_399 => format!("{}h", secs / 3600),
+    _ => format!("{}d", secs / 86_400),
+  }
+}
+
+/// Human-friendly age, e.g. "5m ago", "3h ago", "2d ago".
+pub fn format_age(secs: u64, now: u64) -> String {
+  format!("{} ago", format_duration(now.saturating_sub(secs)))
+}
+
+// Howard Hinnant's days-to-civil algorithm (proleptic Gregorian calendar).
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+  let z = z + 719_468;
+  let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+  let doe = z - era * 146_097;
+  let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+  let y = yoe + era * 400;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+  let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+  (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+  let y = if m <= 2 { y - 1 } else { y };
+  let era = if y >= 0 { y } else { y - 399 } / 400;
+  let yoe = y - era * 400;
+  let mp = (m as i64 + 9) % 12;
+  let doy = (153 * mp + 2) / 5 + d as i64 - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_format_rfc3339() {
+    assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00Z");
+    assert_eq!(format_rfc3339(1_714_557_600), "2024-05-01T10:00:00Z");
+    assert_eq!(format_rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+  }
+
+  #[test]
+  fn test_parse_rfc3339() {
+    assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+    assert_eq!(
+      parse_rfc3339("2024-05-01T10:00:00.123456789Z"),
+      Some(1_714_557_600)
+    );
+    assert_eq!(parse_rfc3339("2000-02-29T00:00:00Z"), Some(951_782_400));
+    assert_eq!(parse_rfc3339("2024-05-01T10:00:00+02:00"), None);
+    assert_eq!(parse_rfc3339("0001-01-01T00:00:00Z"), None);
+  }
+
+  #[test]
+  fn test_format_age() {
+    assert_eq!(format_age(100, 130), "30s ago");
+    assert_eq!(format_age(0, 7200), "2h ago");
+    assert_eq!(format_age(0, 3 * 86_400), "3d ago");
+  }
+}
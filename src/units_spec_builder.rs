@@ -4,7 +4,8 @@ use std::fs::{self, File};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 
-use crate::config::{app_dir, systemd_dir};
+use crate::config::{app_dir, env_file, system_unit_user, systemd_dir, HLConfig, SystemdMode};
+use crate::docker::{build_run_args, override_file};
 
 #[derive(Debug, Clone)]
 pub struct UnitsSpec {
@@ -17,6 +18,44 @@ pub struct UnitsSpec {
   pub app_dir: PathBuf,
   /// Optional environment file for worker scaling, etc. (e.g., /etc/default/app-myapp)
   pub env_file: Option<PathBuf>,
+  /// Whether process units detach from or stay attached to their containers
+  pub mode: SystemdMode,
+  /// hl.yml `cron:` jobs, each rendered into a service + timer pair
+  pub cron: Vec<CronUnit>,
+  /// Account the services run as when rendering system units; None renders user units
+  pub run_as: Option<String>,
+}
+
+/// A scheduled one-off container: `app-<app>-cron-<name>.service` runs `docker <run_args>`
+/// whenever `app-<app>-cron-<name>.timer` fires.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronUnit {
+  pub name: String,
+  pub schedule: String,
+  pub run_args: Vec<String>,
+}
+
+impl CronUnit {
+  /// Cron units for hl.yml `cron:`, running the `latest` image with the app's .env and
+  /// network (and the same extras as migrations).
+  pub fn from_config(cfg: &HLConfig) -> Vec<CronUnit> {
+    let image = format!("{}:latest", cfg.image);
+    let env_path = env_file(&cfg.app).to_string_lossy().to_string();
+    cfg
+      .cron
+      .iter()
+      .map(|job| CronUnit {
+        name: job.name.clone(),
+        schedule: job.schedule.clone(),
+        run_args: build_run_args(cfg, &image, &env_path, &job.command, &[]),
+      })
+      .collect()
+  }
+}
+
+/// Base name of a cron job's service and timer.
+pub fn cron_unit(app: &str, name: &str) -> String {
+  format!("app-{}-cron-{}", app, name)
 }
 
 impl UnitsSpec {
@@ -28,8 +67,37 @@ impl UnitsSpec {
       systemd_dir: systemd_dir(),
       app_dir: app_dir(app_name),
       env_file: app_dir(app_name).join(".env").into(),
+      mode: SystemdMode::default(),
+      cron: vec![],
+      run_as: system_unit_user()?,
     })
   }
+
+  /// Target the app hooks into at boot.
+  fn boot_target(&self) -> &'static str {
+    match self.run_as {
+      Some(_) => "multi-user.target",
+      None => "default.target",
+    }
+  }
+
+  /// What every app unit is ordered after. System units name docker directly so they also
+  /// stop before it on shutdown.
+  fn start_after(&self) -> &'static str {
+    match self.run_as {
+      Some(_) => "docker.service network-online.target",
+      None => "default.target",
+    }
+  }
+
+  /// `User=` line for system units (empty for user units).
+  fn user_directive(&self) -> String {
+    self
+      .run_as
+      .as_ref()
+      .map(|user| format!("User={}\n", user))
+      .unwrap_or_default()
+  }
 }
 
 pub struct UnitsSpecBuilder {
@@ -39,6 +107,9 @@ pub struct UnitsSpecBuilder {
   systemd_dir: PathBuf,
   app_dir: PathBuf,
   env_file: Option<PathBuf>,
+  mode: SystemdMode,
+  cron: Vec<CronUnit>,
+  run_as: Option<String>,
 }
 
 impl UnitsSpecBuilder {
@@ -50,6 +121,18 @@ impl UnitsSpecBuilder {
     self.accessories = accs.into();
     self
   }
+  pub fn mode(mut self, mode: SystemdMode) -> Self {
+    self.mode = mode;
+    self
+  }
+  pub fn cron(mut self, cron: impl Into<Vec<CronUnit>>) -> Self {
+    self.cron = cron.into();
+    self
+  }
+  pub fn run_as(mut self, user: Option<String>) -> Self {
+    self.run_as = user;
+    self
+  }
   pub fn build(self) -> UnitsSpec {
     UnitsSpec {
       app_name: self.app_name,
@@ -58,60 +141,144 @@ impl UnitsSpecBuilder {
       systemd_dir: self.systemd_dir,
       app_dir: self.app_dir,
       env_file: self.env_file,
+      mode: self.mode,
+      cron: self.cron,
+      run_as: self.run_as,
     }
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum WriteOutcome {
   Created(PathBuf),
   Updated(PathBuf),
   Unchanged(PathBuf),
+  /// Orphaned unit that regeneration stops, disables and deletes
+  Removed(PathBuf),
+}
+
+/// A unit file as regeneration would leave it, next to what is on disk now.
+#[derive(Debug)]
+pub struct UnitChange {
+  pub outcome: WriteOutcome,
+  pub old: String,
+  pub new: String,
 }
 
 pub fn render_and_write(spec: &UnitsSpec) -> std::io::Result<Vec<WriteOutcome>> {
   fs::create_dir_all(&spec.systemd_dir)?;
 
-  let mut outcomes = Vec::new();
+  render_units(spec)
+    .iter()
+    .map(|(path, content)| {
+      // Drop-in directory for local overrides; created empty once, never written to
+      fs::create_dir_all(drop_in_dir(path))?;
+      write_if_changed(path, content)
+    })
+    .collect()
+}
+
+/// `<unit>.d/` next to a unit file: systemd applies `*.conf` files in it on top of the unit.
+pub fn drop_in_dir(unit_path: &Path) -> PathBuf {
+  let mut name = unit_path.file_name().unwrap_or_default().to_os_string();
+  name.push(".d");
+  unit_path.with_file_name(name)
+}
+
+/// Comment heading every generated unit: it is rewritten on each deploy, so customizations
+/// belong in its drop-in directory.
+fn unit_header(spec: &UnitsSpec, unit: &str) -> String {
+  let edit = match spec.run_as {
+    Some(_) => format!("sudo systemctl edit {}", unit),
+    None => format!("systemctl --user edit {}", unit),
+  };
+  format!(
+    "# Generated by hl for app {app}; rewritten on every deploy, so edits here are lost.\n\
+     # Customize it with a drop-in in {unit}.d/ (e.g. override.conf), which hl never touches:\n\
+     #   {edit}\n",
+    app = spec.app_name,
+  )
+}
+
+/// What `render_and_write` would do for the spec, without writing anything.
+pub fn plan_units(spec: &UnitsSpec) -> Vec<UnitChange> {
+  render_units(spec)
+    .into_iter()
+    .map(|(path, new)| {
+      let old = read_existing(&path);
+      UnitChange {
+        outcome: outcome_for(path, &old, &new),
+        old,
+        new,
+      }
+    })
+    .collect()
+}
+
+/// Paths and contents of every unit file `render_and_write` writes for the spec.
+pub fn render_units(spec: &UnitsSpec) -> Vec<(PathBuf, String)> {
+  let mut units = Vec::new();
 
   // 1) Target
   let target_name = format!("app-{}.target", spec.app_name);
-  let target_path = spec.systemd_dir.join(&target_name);
-  let target_content = render_target(
-    &spec.app_name,
-    &spec.processes,
-    !spec.accessories.is_empty(),
-  );
-  outcomes.push(write_if_changed(&target_path, &target_content)?);
+  let target_content = render_target(spec);
+  units.push((spec.systemd_dir.join(&target_name), target_content));
 
   // 2) Accessories service (only if accessories exist)
   if !spec.accessories.is_empty() {
     let acc_name = format!("app-{}-acc.service", spec.app_name);
-    let acc_path = spec.systemd_dir.join(&acc_name);
     let acc_content = render_accessories_service(spec);
-    outcomes.push(write_if_changed(&acc_path, &acc_content)?);
+    units.push((spec.systemd_dir.join(&acc_name), acc_content));
   }
 
   // 3) Per-process services
   for proc_name in &spec.processes {
     let svc_name = format!("app-{}-{}.service", spec.app_name, proc_name);
-    let svc_path = spec.systemd_dir.join(&svc_name);
     let svc_content = render_process_service(spec, proc_name);
-    outcomes.push(write_if_changed(&svc_path, &svc_content)?);
+    units.push((spec.systemd_dir.join(&svc_name), svc_content));
   }
 
-  Ok(outcomes)
+  // 4) Cron jobs
+  for job in &spec.cron {
+    let unit = cron_unit(&spec.app_name, &job.name);
+    let (service, timer) = render_cron_units(spec, job);
+    units.push((spec.systemd_dir.join(format!("{}.service", unit)), service));
+    units.push((spec.systemd_dir.join(format!("{}.timer", unit)), timer));
+  }
+
+  units
+    .into_iter()
+    .map(|(path, content)| {
+      let unit = path.file_name().unwrap_or_default().to_string_lossy();
+      let content = format!("{}{}", unit_header(spec, &unit), content);
+      (path, content)
+    })
+    .collect()
 }
 
-fn write_if_changed(path: &Path, desired: &str) -> std::io::Result<WriteOutcome> {
-  // Read existing (if any)
+fn read_existing(path: &Path) -> String {
   let mut existing = String::new();
   if let Ok(mut f) = File::open(path) {
     f.read_to_string(&mut existing).ok();
   }
+  existing
+}
 
-  if normalize(&existing) == normalize(desired) {
-    return Ok(WriteOutcome::Unchanged(path.to_path_buf()));
+fn outcome_for(path: PathBuf, existing: &str, desired: &str) -> WriteOutcome {
+  if normalize(existing) == normalize(desired) {
+    WriteOutcome::Unchanged(path)
+  } else if existing.is_empty() {
+    WriteOutcome::Created(path)
+  } else {
+    WriteOutcome::Updated(path)
+  }
+}
+
+fn write_if_changed(path: &Path, desired: &str) -> std::io::Result<WriteOutcome> {
+  let existing = read_existing(path);
+  let outcome = outcome_for(path.to_path_buf(), &existing, desired);
+  if let WriteOutcome::Unchanged(_) = outcome {
+    return Ok(outcome);
   }
 
   // Write atomically: .tmp then rename
@@ -126,11 +293,7 @@ fn write_if_changed(path: &Path, desired: &str) -> std::io::Result<WriteOutcome>
   }
   fs::rename(&tmp_path, path)?;
 
-  if existing.is_empty() {
-    Ok(WriteOutcome::Created(path.to_path_buf()))
-  } else {
-    Ok(WriteOutcome::Updated(path.to_path_buf()))
-  }
+  Ok(outcome)
 }
 
 fn normalize(s: &str) -> String {
@@ -141,12 +304,13 @@ fn normalize(s: &str) -> String {
     .join("\n")
 }
 
-fn render_target(app: &str, processes: &[String], has_acc: bool) -> String {
+fn render_target(spec: &UnitsSpec) -> String {
+  let app = &spec.app_name;
   let mut wants = Vec::new();
-  if has_acc {
+  if !spec.accessories.is_empty() {
     wants.push(format!("app-{}-acc.service", app));
   }
-  for p in processes {
+  for p in &spec.processes {
     wants.push(format!("app-{}-{}.service", app, p));
   }
   let mut unit = String::new();
@@ -154,17 +318,19 @@ fn render_target(app: &str, processes: &[String], has_acc: bool) -> String {
     &mut unit,
     r#"[Unit]
 Description=App {app} stack
-After=default.target
+After={after}
 Wants={wants}
 "#,
     app = app,
+    after = spec.start_after(),
     wants = wants.join(" ")
   )
   .unwrap();
   writeln!(
     &mut unit,
     r#"[Install]
-WantedBy=default.target"#
+WantedBy={boot}"#,
+    boot = spec.boot_target()
   )
   .unwrap();
   unit
@@ -193,13 +359,13 @@ fn render_accessories_service(spec: &UnitsSpec) -> String {
         &mut body,
         r#"[Unit]
 Description=App {app} accessories (Redis/Postgres/etc.)
-After=default.target
+After={after}
 PartOf=app-{app}.target
 
 [Service]
 Type=oneshot
 RemainAfterExit=yes
-ExecStartPre=/usr/bin/bash -lc 'for i in {{1..30}}; do docker version >/dev/null 2>&1 && exit 0; sleep 1; done; echo "Docker unavailable" >&2; exit 1'
+{user}ExecStartPre=/usr/bin/bash -lc 'for i in {{1..30}}; do docker version >/dev/null 2>&1 && exit 0; sleep 1; done; echo "Docker unavailable" >&2; exit 1'
 WorkingDirectory={app_dir}
 ExecStart=/usr/bin/docker compose -p {project} \
   -f {base} \
@@ -212,9 +378,12 @@ ExecStop=/usr/bin/docker compose -p {project} \
 Restart=no
 
 [Install]
-WantedBy=default.target
+WantedBy={boot}
 "#,
         app = app,
+        after = spec.start_after(),
+        user = spec.user_directive(),
+        boot = spec.boot_target(),
         project = project,
         base = base.display(),
         app_dir = app_dir.display(),
@@ -230,7 +399,7 @@ fn render_process_service(spec: &UnitsSpec, proc_name: &str) -> String {
   let project = app; // app project
   let base = app_dir.join("compose.yml");
   let overlay = app_dir.join(format!("compose.{proc}.yml", proc = proc_name));
-  let mut after = vec!["default.target".to_string()];
+  let mut after = vec![spec.start_after().to_string()];
   let mut wants = Vec::new();
   if !spec.accessories.is_empty() {
     after.push(format!("app-{}-acc.service", spec.app_name));
@@ -258,31 +427,66 @@ PartOf=app-{app}.target
   )
   .unwrap();
 
+  // Attached units keep `docker compose up` in the foreground so systemd sees the real
+  // process state, restarts it on failure and collects its output in the journal. The base
+  // compose file drops docker's restart policy in this mode, and --exit-code-from makes
+  // compose exit with the container's status, so a crash fails the unit.
+  let (service_type, compose_flags, up_flags, restart) = match spec.mode {
+    SystemdMode::Oneshot => (
+      "Type=oneshot\nRemainAfterExit=yes",
+      "",
+      "-d ".to_string(),
+      "Restart=no",
+    ),
+    SystemdMode::Attached => (
+      "Type=exec",
+      "--ansi never ",
+      format!("--no-log-prefix --exit-code-from {} ", proc_name),
+      "Restart=on-failure\nRestartSec=5",
+    ),
+  };
+
   writeln!(
     &mut unit,
     r#"[Service]
-Type=oneshot
-RemainAfterExit=yes
-ExecStartPre=/usr/bin/bash -lc 'for i in {{1..30}}; do docker version >/dev/null 2>&1 && exit 0; sleep 1; done; echo "Docker unavailable" >&2; exit 1'"#
+{service_type}
+{user}ExecStartPre=/usr/bin/bash -lc 'for i in {{1..30}}; do docker version >/dev/null 2>&1 && exit 0; sleep 1; done; echo "Docker unavailable" >&2; exit 1'"#,
+    user = spec.user_directive()
   )
   .unwrap();
+  // Non-web processes run as many replicas as <PROC>_SCALE in the env file says (`hl scale`).
+  // web keeps a fixed container name, which Traefik and health checks address it by.
+  let scale_var = (proc_name != "web" && spec.env_file.is_some()).then(|| scale_env_var(proc_name));
+  if let Some(var) = &scale_var {
+    writeln!(&mut unit, "Environment={}=1", var).unwrap();
+  }
   if let Some(env_file) = &spec.env_file {
     writeln!(&mut unit, "EnvironmentFile=-{}", env_file.display()).unwrap();
   }
+  let scale = scale_var
+    .map(|var| format!("  --scale {}=${{{}}} \\\n", proc_name, var))
+    .unwrap_or_default();
+  // The user's own overlay goes last so it wins; it's picked up whenever units are rewritten
+  let user_override = app_dir.join(override_file(proc_name));
+  let user_override = if user_override.exists() {
+    format!("  -f {} \\\n", user_override.display())
+  } else {
+    String::new()
+  };
 
   writeln!(
     &mut unit,
     r#"WorkingDirectory={working_dir}
-ExecStart=/usr/bin/docker compose -p {project} \
+ExecStart=/usr/bin/docker compose {compose_flags}-p {project} \
   -f {base} \
   -f {overlay} \
-  up -d {svc} \
-  --remove-orphans
+{user_override}  up {up_flags}{svc} \
+{scale}  --remove-orphans
 ExecStop=/usr/bin/docker compose -p {project} \
   -f {base} \
   -f {overlay} \
-  stop {svc}
-Restart=no
+{user_override}  stop {svc}
+{restart}
 
 [Install]
 WantedBy=app-{app}.target"#,
@@ -298,6 +502,77 @@ WantedBy=app-{app}.target"#,
   unit
 }
 
+/// Variable in the app's env file holding a process' replica count, e.g. `WORKER_SCALE`.
+pub fn scale_env_var(process: &str) -> String {
+  format!("{}_SCALE", process.to_uppercase().replace('-', "_"))
+}
+
+/// Render the (service, timer) pair of a cron job. The timer is persistent so a run missed
+/// while the host was down happens on the next boot.
+fn render_cron_units(spec: &UnitsSpec, job: &CronUnit) -> (String, String) {
+  let app = &spec.app_name;
+  let unit = cron_unit(app, &job.name);
+  let mut after = vec![spec.start_after().to_string()];
+  let mut wants = String::new();
+  if !spec.accessories.is_empty() {
+    after.push(format!("app-{}-acc.service", app));
+    wants = format!("Wants=app-{}-acc.service\n", app);
+  }
+  let exec = job
+    .run_args
+    .iter()
+    .map(|a| systemd_quote(a))
+    .collect::<Vec<_>>()
+    .join(" ");
+
+  let service = format!(
+    r#"[Unit]
+Description=App {app} cron job {name}
+After={after}
+{wants}
+[Service]
+Type=oneshot
+{user}ExecStartPre=/usr/bin/bash -lc 'for i in {{1..30}}; do docker version >/dev/null 2>&1 && exit 0; sleep 1; done; echo "Docker unavailable" >&2; exit 1'
+WorkingDirectory={app_dir}
+ExecStart=/usr/bin/docker {exec}
+"#,
+    name = job.name,
+    after = after.join(" "),
+    user = spec.user_directive(),
+    app_dir = spec.app_dir.display(),
+  );
+  let timer = format!(
+    r#"[Unit]
+Description=Schedule of app {app} cron job {name}
+
+[Timer]
+OnCalendar={schedule}
+Persistent=true
+Unit={unit}.service
+
+[Install]
+WantedBy=timers.target
+"#,
+    name = job.name,
+    schedule = job.schedule,
+  );
+  (service, timer)
+}
+
+/// Quote one ExecStart= argument: `%` and `$` are escaped so systemd passes them through,
+/// and arguments with spaces or quotes are double-quoted.
+fn systemd_quote(arg: &str) -> String {
+  let escaped = arg.replace('%', "%%").replace('$', "$$");
+  if !escaped.is_empty()
+    && !escaped
+      .chars()
+      .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'))
+  {
+    return escaped;
+  }
+  format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
 /// Minimal escaping helper for Environment= values (spaces are rare, but be safe).
 fn systemd_escape(s: &str) -> String {
   // systemd is forgiving here; we'll just avoid raw newlines and quotes.
@@ -323,6 +598,9 @@ mod tests {
       systemd_dir: systemd_dir.clone(),
       app_dir: app_dir.clone(),
       env_file: Some(app_dir.join(".env")),
+      mode: SystemdMode::Oneshot,
+      run_as: None,
+      cron: vec![],
     };
 
     let outcomes = render_and_write(&spec)?;
@@ -342,7 +620,10 @@ mod tests {
     let target_path = systemd_dir.join("app-testapp.target");
     assert!(target_path.exists(), "Target file should exist");
     let target_content = fs::read_to_string(&target_path)?;
-    let expected_target = r#"[Unit]
+    let expected_target = r#"# Generated by hl for app testapp; rewritten on every deploy, so edits here are lost.
+# Customize it with a drop-in in app-testapp.target.d/ (e.g. override.conf), which hl never touches:
+#   systemctl --user edit app-testapp.target
+[Unit]
 Description=App testapp stack
 After=default.target
 Wants=app-testapp-acc.service app-testapp-web.service app-testapp-worker.service
@@ -351,6 +632,8 @@ Wants=app-testapp-acc.service app-testapp-web.service app-testapp-worker.service
 WantedBy=default.target
 "#;
     assert_eq!(target_content, expected_target);
+    assert!(systemd_dir.join("app-testapp.target.d").is_dir());
+    assert!(systemd_dir.join("app-testapp-web.service.d").is_dir());
 
     // 2. Verify accessories service
     let acc_path = systemd_dir.join("app-testapp-acc.service");
@@ -385,7 +668,10 @@ WantedBy=default.target
 "#,
       app_dir = app_dir_str
     );
-    assert_eq!(acc_content, expected_acc);
+    assert_eq!(
+      acc_content,
+      unit_header(&spec, "app-testapp-acc.service") + &expected_acc
+    );
 
     // 3. Verify web service
     let web_path = systemd_dir.join("app-testapp-web.service");
@@ -420,7 +706,10 @@ WantedBy=app-testapp.target
 "#,
       app_dir = app_dir_str
     );
-    assert_eq!(web_content, expected_web);
+    assert_eq!(
+      web_content,
+      unit_header(&spec, "app-testapp-web.service") + &expected_web
+    );
 
     // 4. Verify worker service
     let worker_path = systemd_dir.join("app-testapp-worker.service");
@@ -437,12 +726,14 @@ PartOf=app-testapp.target
 Type=oneshot
 RemainAfterExit=yes
 ExecStartPre=/usr/bin/bash -lc 'for i in {{1..30}}; do docker version >/dev/null 2>&1 && exit 0; sleep 1; done; echo "Docker unavailable" >&2; exit 1'
+Environment=WORKER_SCALE=1
 EnvironmentFile=-{app_dir}/.env
 WorkingDirectory={app_dir}
 ExecStart=/usr/bin/docker compose -p testapp \
   -f {app_dir}/compose.yml \
   -f {app_dir}/compose.worker.yml \
   up -d worker \
+  --scale worker=${{WORKER_SCALE}} \
   --remove-orphans
 ExecStop=/usr/bin/docker compose -p testapp \
   -f {app_dir}/compose.yml \
@@ -455,11 +746,182 @@ WantedBy=app-testapp.target
 "#,
       app_dir = app_dir_str
     );
-    assert_eq!(worker_content, expected_worker);
+    assert_eq!(
+      worker_content,
+      unit_header(&spec, "app-testapp-worker.service") + &expected_worker
+    );
 
     Ok(())
   }
 
+  #[test]
+  fn test_render_attached_process_service() -> std::io::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let systemd_dir = temp_dir.path().join("systemd");
+    let spec = UnitsSpec {
+      app_name: "testapp".to_string(),
+      processes: vec!["web".to_string()],
+      accessories: vec![],
+      systemd_dir: systemd_dir.clone(),
+      app_dir: temp_dir.path().join("apps").join("testapp"),
+      env_file: None,
+      mode: SystemdMode::Attached,
+      run_as: None,
+      cron: vec![],
+    };
+
+    render_and_write(&spec)?;
+    let web = fs::read_to_string(systemd_dir.join("app-testapp-web.service"))?;
+    assert!(web.contains("Type=exec\n"));
+    assert!(!web.contains("RemainAfterExit"));
+    assert!(web.contains("ExecStart=/usr/bin/docker compose --ansi never -p testapp \\\n"));
+    assert!(web.contains("  up --no-log-prefix --exit-code-from web web \\\n"));
+    assert!(web.contains("Restart=on-failure\nRestartSec=5\n"));
+    Ok(())
+  }
+
+  #[test]
+  fn test_render_process_service_with_user_override() -> std::io::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let app_dir = temp_dir.path().join("apps").join("testapp");
+    fs::create_dir_all(&app_dir)?;
+    fs::write(
+      app_dir.join("compose.override.worker.yml"),
+      "services: {}\n",
+    )?;
+    let spec = UnitsSpec {
+      app_name: "testapp".to_string(),
+      processes: vec!["web".to_string(), "worker".to_string()],
+      accessories: vec![],
+      systemd_dir: temp_dir.path().join("systemd"),
+      app_dir: app_dir.clone(),
+      env_file: None,
+      mode: SystemdMode::Oneshot,
+      run_as: None,
+      cron: vec![],
+    };
+
+    let units = render_units(&spec);
+    let unit = |name: &str| {
+      units
+        .iter()
+        .find(|(path, _)| path.ends_with(name))
+        .map(|(_, content)| content.clone())
+        .unwrap()
+    };
+    let worker = unit("app-testapp-worker.service");
+    let overlays = format!(
+      "  -f {dir}/compose.worker.yml \\\n  -f {dir}/compose.override.worker.yml \\\n",
+      dir = app_dir.display()
+    );
+    assert!(worker.contains(&format!("{}  up -d worker", overlays)));
+    assert!(worker.contains(&format!("{}  stop worker", overlays)));
+    assert!(!unit("app-testapp-web.service").contains("compose.override"));
+    Ok(())
+  }
+
+  #[test]
+  fn test_plan_units_writes_nothing() -> std::io::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let systemd_dir = temp_dir.path().join("systemd");
+    let mut spec = UnitsSpec {
+      app_name: "testapp".to_string(),
+      processes: vec!["web".to_string()],
+      accessories: vec![],
+      systemd_dir: systemd_dir.clone(),
+      app_dir: temp_dir.path().join("apps").join("testapp"),
+      env_file: None,
+      mode: SystemdMode::Oneshot,
+      cron: vec![],
+      run_as: None,
+    };
+    render_and_write(&spec)?;
+
+    spec.mode = SystemdMode::Attached;
+    spec.processes.push("worker".to_string());
+    let plan = plan_units(&spec);
+    let web = systemd_dir.join("app-testapp-web.service");
+    let worker = systemd_dir.join("app-testapp-worker.service");
+    assert!(plan
+      .iter()
+      .any(|c| c.outcome == WriteOutcome::Updated(web.clone()) && c.new.contains("Type=exec")));
+    assert!(plan
+      .iter()
+      .any(|c| c.outcome == WriteOutcome::Created(worker.clone()) && c.old.is_empty()));
+    assert!(!worker.exists());
+    assert!(fs::read_to_string(&web)?.contains("Type=oneshot"));
+    Ok(())
+  }
+
+  #[test]
+  fn test_render_system_units() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec = UnitsSpec {
+      app_name: "testapp".to_string(),
+      processes: vec!["web".to_string()],
+      accessories: vec!["postgres".to_string()],
+      systemd_dir: temp_dir.path().join("systemd"),
+      app_dir: temp_dir.path().join("apps").join("testapp"),
+      env_file: None,
+      mode: SystemdMode::Oneshot,
+      cron: vec![],
+      run_as: Some("deploy".to_string()),
+    };
+
+    let units = render_units(&spec);
+    let (_, target) = &units[0];
+    assert!(target.contains("After=docker.service network-online.target\n"));
+    assert!(target.contains("WantedBy=multi-user.target"));
+    for (path, content) in &units[1..] {
+      assert!(content.contains("\nUser=deploy\n"), "{}", path.display());
+      assert!(!content.contains("default.target"), "{}", path.display());
+    }
+  }
+
+  #[test]
+  fn test_render_cron_units() -> std::io::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let systemd_dir = temp_dir.path().join("systemd");
+    let spec = UnitsSpec {
+      app_name: "testapp".to_string(),
+      processes: vec!["web".to_string()],
+      accessories: vec!["postgres".to_string()],
+      systemd_dir: systemd_dir.clone(),
+      app_dir: temp_dir.path().join("apps").join("testapp"),
+      env_file: None,
+      mode: SystemdMode::Oneshot,
+      run_as: None,
+      cron: vec![CronUnit {
+        name: "nightly".to_string(),
+        schedule: "*-*-* 03:30:00".to_string(),
+        run_args: [
+          "run",
+          "--rm",
+          "img:latest",
+          "sh",
+          "-c",
+          "echo \"$HOME\" 100%",
+        ]
+        .map(String::from)
+        .to_vec(),
+      }],
+    };
+
+    render_and_write(&spec)?;
+    let service = fs::read_to_string(systemd_dir.join("app-testapp-cron-nightly.service"))?;
+    assert!(service.contains("After=default.target app-testapp-acc.service\n"));
+    assert!(service.contains("Wants=app-testapp-acc.service\n"));
+    assert!(service.contains("Type=oneshot\n"));
+    assert!(service.contains(
+      "ExecStart=/usr/bin/docker run --rm img:latest sh -c \"echo \\\"$$HOME\\\" 100%%\"\n"
+    ));
+    let timer = fs::read_to_string(systemd_dir.join("app-testapp-cron-nightly.timer"))?;
+    assert!(timer.contains("OnCalendar=*-*-* 03:30:00\n"));
+    assert!(timer.contains("Unit=app-testapp-cron-nightly.service\n"));
+    assert!(timer.contains("WantedBy=timers.target\n"));
+    Ok(())
+  }
+
   #[test]
   fn test_render_and_write_no_accessories() -> std::io::Result<()> {
     let temp_dir = TempDir::new()?;
@@ -474,6 +936,9 @@ WantedBy=app-testapp.target
       systemd_dir: systemd_dir.clone(),
       app_dir: app_dir.clone(),
       env_file: None,
+      mode: SystemdMode::Oneshot,
+      run_as: None,
+      cron: vec![],
     };
 
     let outcomes = render_and_write(&spec)?;
@@ -495,7 +960,10 @@ Wants=app-simpleapp-web.service
 
 [Install]
 WantedBy=default.target\n";
-    assert_eq!(target_content, expected_target);
+    assert_eq!(
+      target_content,
+      unit_header(&spec, "app-simpleapp.target") + expected_target
+    );
 
     // Verify web service
     let web_path = systemd_dir.join("app-simpleapp-web.service");
@@ -528,7 +996,10 @@ WantedBy=app-simpleapp.target
 "#,
       app_dir = app_dir_str
     );
-    assert_eq!(web_content, expected_web);
+    assert_eq!(
+      web_content,
+      unit_header(&spec, "app-simpleapp-web.service") + &expected_web
+    );
 
     Ok(())
   }
@@ -546,6 +1017,9 @@ WantedBy=app-simpleapp.target
       systemd_dir: systemd_dir.clone(),
       app_dir: app_dir.clone(),
       env_file: Some(app_dir.join(".env")),
+      mode: SystemdMode::Oneshot,
+      run_as: None,
+      cron: vec![],
     };
 
     // First write
@@ -584,6 +1058,9 @@ WantedBy=app-simpleapp.target
       systemd_dir: systemd_dir.clone(),
       app_dir: app_dir.clone(),
       env_file: None,
+      mode: SystemdMode::Oneshot,
+      run_as: None,
+      cron: vec![],
     };
 
     // First write
@@ -598,6 +1075,9 @@ WantedBy=app-simpleapp.target
       systemd_dir: systemd_dir.clone(),
       app_dir: app_dir.clone(),
       env_file: None,
+      mode: SystemdMode::Oneshot,
+      run_as: None,
+      cron: vec![],
     };
 
     // Second write (should update target, web, and create acc)
@@ -619,7 +1099,7 @@ WantedBy=app-simpleapp.target
         WriteOutcome::Updated(_) => {
           has_updated = true;
         }
-        WriteOutcome::Unchanged(_) => {}
+        WriteOutcome::Unchanged(_) | WriteOutcome::Removed(_) => {}
       }
     }
 
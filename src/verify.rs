@@ -0,0 +1,270 @@
+use crate::config::HLConfig;
+use crate::doctor::Check;
+use crate::releases::Release;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Variables each accessory wires into `.env` when it is added.
+pub const ACCESSORY_ENV: &[(&str, &str)] = &[("postgres", "DATABASE_URL"), ("redis", "REDIS_URL")];
+
+/// Every compose file a unit references must exist, every hl.yml sidecar must have been
+/// deployed, and no overlay should be left over that no unit uses.
+pub fn compose_checks(
+  app_dir: &Path,
+  processes: &[String],
+  accessories: &[String],
+  sidecars: &[String],
+) -> Vec<Check> {
+  let mut checks = Vec::new();
+  let deploy_hint = "redeploy the app to regenerate its compose files";
+
+  if app_dir.join("compose.yml").exists() {
+    checks.push(Check::pass("compose.yml", "present"));
+  } else {
+    checks.push(Check::fail("compose.yml", "missing", deploy_hint));
+  }
+
+  for name in processes.iter().chain(accessories) {
+    let file = format!("compose.{}.yml", name);
+    if app_dir.join(&file).exists() {
+      checks.push(Check::pass(&file, "present"));
+    } else {
+      checks.push(Check::fail(
+        &file,
+        "referenced by a unit but missing",
+        deploy_hint,
+      ));
+    }
+  }
+
+  for sidecar in sidecars {
+    if !processes.contains(sidecar) {
+      checks.push(Check::fail(
+        &format!("sidecar {}", sidecar),
+        "in hl.yml but has no unit",
+        deploy_hint,
+      ));
+    }
+  }
+
+  if let Ok(entries) = std::fs::read_dir(app_dir) {
+    let mut stray: Vec<String> = entries
+      .flatten()
+      .filter_map(|e| {
+        let name = e.file_name().to_string_lossy().to_string();
+        // The user's own overlays are never hl's to remove
+        if name.starts_with("compose.override.") {
+          return None;
+        }
+        let stem = name
+          .strip_prefix("compose.")?
+          .strip_suffix(".yml")?
+          .to_string();
+        (!processes.contains(&stem) && !accessories.contains(&stem)).then_some(name)
+      })
+      .collect();
+    stray.sort();
+    for file in stray {
+      checks.push(Check::fail(
+        &file,
+        "not used by any unit",
+        format!("remove {}", app_dir.join(&file).display()),
+      ));
+    }
+  }
+
+  checks
+}
+
+/// `.env` must define what the generated compose files expect, and `.env.build` the build
+/// secrets hl.yml lists.
+pub fn env_checks(
+  cfg: &HLConfig,
+  env: &HashMap<String, String>,
+  build_env: &HashMap<String, String>,
+  accessories: &[String],
+) -> Vec<Check> {
+  // (key, needed by, is a build secret)
+  let mut required: Vec<(String, &str, bool)> = vec![
+    ("DOMAIN".to_string(), "web router rule", false),
+    ("SERVICE_PORT".to_string(), "web service port", false),
+  ];
+  // Secrets resolved by a provider at deploy time don't live in .env.build
+  if cfg.secrets_provider.is_none() {
+    for secret in &cfg.secrets {
+      required.push((secret.clone(), "hl.yml secrets", true));
+    }
+  }
+  for (accessory, key) in ACCESSORY_ENV {
+    if accessories.iter().any(|a| a == accessory) {
+      required.push((key.to_string(), accessory, false));
+    }
+  }
+
+  required
+    .into_iter()
+    .map(|(key, used_by, build)| {
+      let (name, file, set, vars) = if build {
+        let name = format!("build secret {}", key);
+        (name, ".env.build", "set --build", build_env)
+      } else {
+        (format!("env {}", key), ".env", "set", env)
+      };
+      if vars.get(&key).is_some_and(|v| !v.is_empty()) {
+        Check::pass(&name, format!("set (needed by {})", used_by))
+      } else {
+        Check::fail(
+          &name,
+          format!("missing from {} (needed by {})", file, used_by),
+          format!("hl env {} {}=...", set, key),
+        )
+      }
+    })
+    .collect()
+}
+
+/// The image of the current release must still exist in the registry, or the next restart
+/// (which pulls) will fail.
+pub async fn image_check(cfg: &HLConfig, release: Option<&Release>) -> Check {
+  let Some(release) = release else {
+    return Check::fail(
+      "image",
+      "no recorded release",
+      "deploy with git push to record one",
+    );
+  };
+  let image = format!("{}:{}", cfg.image, release.short_sha());
+  let found = Command::new("docker")
+    .args(["manifest", "inspect", &image])
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+    .await
+    .is_ok_and(|s| s.success());
+  if found {
+    Check::pass("image", format!("{} is in the registry", image))
+  } else {
+    Check::fail(
+      "image",
+      format!("{} not found in the registry", image),
+      "check registry credentials (docker login) or redeploy",
+    )
+  }
+}
+
+/// The health path must answer through Traefik on the public domain.
+pub async fn route_check(cfg: &HLConfig) -> Check {
+  let path = reqwest::Url::parse(&cfg.health.url)
+    .map(|u| u.path().to_string())
+    .unwrap_or_else(|_| "/".to_string());
+  let url = format!("https://{}{}", cfg.domain, path);
+  let client = match reqwest::Client::builder()
+    .timeout(Duration::from_secs(10))
+    .build()
+  {
+    Ok(client) => client,
+    Err(e) => return Check::fail("route", e.to_string(), "check TLS setup"),
+  };
+  match client.get(&url).send().await {
+    Ok(resp) if resp.status().is_success() => {
+      Check::pass("route", format!("{} answered {}", url, resp.status()))
+    }
+    Ok(resp) => Check::fail(
+      "route",
+      format!("{} answered {}", url, resp.status()),
+      "check the Traefik router for this app (docker logs traefik) and the web container",
+    ),
+    Err(e) => Check::fail(
+      "route",
+      format!("{} is unreachable: {}", url, e),
+      "check DNS for the domain and that Traefik is running",
+    ),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn test_compose_checks() {
+    let temp_dir = TempDir::new().unwrap();
+    for file in [
+      "compose.yml",
+      "compose.web.yml",
+      "compose.override.web.yml",
+      "compose.old.yml",
+    ] {
+      std::fs::write(temp_dir.path().join(file), "").unwrap();
+    }
+
+    let checks = compose_checks(
+      temp_dir.path(),
+      &strings(&["web"]),
+      &strings(&["postgres"]),
+      &strings(&["cron"]),
+    );
+    let failed: Vec<_> = checks
+      .iter()
+      .filter(|c| !c.ok)
+      .map(|c| c.name.as_str())
+      .collect();
+    assert_eq!(
+      failed,
+      vec!["compose.postgres.yml", "sidecar cron", "compose.old.yml"]
+    );
+  }
+
+  #[test]
+  fn test_env_checks() {
+    let cfg: HLConfig = serde_yaml::from_str(
+      "app: myapp\nimage: img\ndomain: myapp.example.com\nservicePort: 3000\n\
+       health:\n  url: http://myapp:3000/up\nsecrets: [SECRET_KEY_BASE]\n",
+    )
+    .unwrap();
+    // A runtime value doesn't make the build secret available to the build
+    let env = HashMap::from([
+      ("DOMAIN".to_string(), "myapp.example.com".to_string()),
+      ("SERVICE_PORT".to_string(), "3000".to_string()),
+      ("SECRET_KEY_BASE".to_string(), "runtime".to_string()),
+    ]);
+    let build_env = HashMap::new();
+
+    let checks = env_checks(&cfg, &env, &build_env, &strings(&["redis"]));
+    let failed: Vec<_> = checks
+      .iter()
+      .filter(|c| !c.ok)
+      .map(|c| c.name.as_str())
+      .collect();
+    assert_eq!(
+      failed,
+      vec!["build secret SECRET_KEY_BASE", "env REDIS_URL"]
+    );
+    assert!(checks.iter().any(|c| c.detail.contains(".env.build")
+      && c.hint.as_deref() == Some("hl env set --build SECRET_KEY_BASE=...")));
+
+    let build_env = HashMap::from([("SECRET_KEY_BASE".to_string(), "s3cret".to_string())]);
+    assert!(env_checks(&cfg, &HashMap::new(), &build_env, &[])
+      .iter()
+      .any(|c| c.name == "build secret SECRET_KEY_BASE" && c.ok));
+
+    let cfg: HLConfig = serde_yaml::from_str(
+      "app: myapp\nimage: img\ndomain: myapp.example.com\nservicePort: 3000\n\
+       health:\n  url: http://myapp:3000/up\nsecrets: [SECRET_KEY_BASE]\n\
+       secretsProvider:\n  type: vault\n  path: secret/myapp\n",
+    )
+    .unwrap();
+    assert!(env_checks(&cfg, &env, &HashMap::new(), &[])
+      .iter()
+      .all(|c| c.ok));
+  }
+}
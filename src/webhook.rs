@@ -0,0 +1,545 @@
+use crate::config::app_dir;
+use crate::env::generate_secret;
+use crate::git::{is_commit_sha, is_ref_name};
+use crate::output::from_json;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+/*
+`hl serve` is a small HTTP server CI triggers deploys, rollbacks and restarts through, for
+runners that have no SSH access to the host:
+
+  POST /apps/<app>/deploy?sha=<sha>[&branch=<branch>|&tag=<tag>][&wait=1]
+  POST /apps/<app>/rollback?sha=<sha>[&wait=1]
+  POST /apps/<app>/restart[?wait=1]
+
+Each app has its own token, kept in webhook.yml in its app dir (`hl serve token`). A request
+carries it as `Authorization: Bearer <token>` or GitLab's `X-Gitlab-Token`, or signs its body
+with it as GitHub's `X-Hub-Signature-256`. For deploys, GitHub and GitLab push events are read
+for the pushed ref and commit; a commit the bare repository doesn't have yet is fetched from
+webhook.yml's `remote`, else from the repository URL in the payload.
+ */
+
+/// webhook.yml in an app dir.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WebhookConfig {
+  /// Bearer token, GitLab secret token and GitHub signing secret of the app's endpoints
+  pub token: String,
+  /// Repository pushed commits are fetched from when the bare repository lacks them
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub remote: Option<String>,
+}
+
+pub fn webhook_config_path(app: &str) -> PathBuf {
+  app_dir(app).join("webhook.yml")
+}
+
+/// The app's webhook.yml, or None when `hl serve token` hasn't been run for it.
+pub fn load_webhook_config(app: &str) -> Result<Option<WebhookConfig>> {
+  let path = webhook_config_path(app);
+  let content = match std::fs::read_to_string(&path) {
+    Ok(content) => content,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+  };
+  serde_yaml::from_str(&content)
+    .map(Some)
+    .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Write the app's webhook.yml, readable by the deploy user only.
+pub fn save_webhook_config(app: &str, cfg: &WebhookConfig) -> Result<()> {
+  let path = webhook_config_path(app);
+  std::fs::write(&path, serde_yaml::to_string(cfg)?)
+    .with_context(|| format!("Failed to write {}", path.display()))?;
+  std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+  Ok(())
+}
+
+pub fn generate_token() -> Result<String> {
+  generate_secret("hex32")
+}
+
+/// What an endpoint does to its app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+  Deploy,
+  Rollback,
+  Restart,
+}
+
+impl Action {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Action::Deploy => "deploy",
+      Action::Rollback => "rollback",
+      Action::Restart => "restart",
+    }
+  }
+}
+
+/// An HTTP request, with header names lowercased.
+#[derive(Debug, Default)]
+pub struct Request {
+  pub method: String,
+  pub path: String,
+  pub query: BTreeMap<String, String>,
+  pub headers: BTreeMap<String, String>,
+  pub body: Vec<u8>,
+}
+
+impl Request {
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self.headers.get(name).map(String::as_str)
+  }
+
+  /// Content-Length, 0 without one.
+  pub fn content_length(&self) -> Result<usize> {
+    match self.header("content-length") {
+      Some(len) => len.trim().parse().context("invalid Content-Length"),
+      None => Ok(0),
+    }
+  }
+
+  /// Whether `?wait=1` asks to answer once the action finished instead of right away.
+  pub fn wait(&self) -> bool {
+    self
+      .query
+      .get("wait")
+      .is_some_and(|v| matches!(v.as_str(), "1" | "true" | "yes"))
+  }
+}
+
+/// Parse a request line and headers (everything before the blank line).
+pub fn parse_request_head(head: &str) -> Result<Request> {
+  let mut lines = head.split("\r\n");
+  let request_line = lines.next().unwrap_or_default();
+  let mut parts = request_line.split(' ');
+  let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+  else {
+    anyhow::bail!("malformed request line {:?}", request_line);
+  };
+  if !version.starts_with("HTTP/1.") {
+    anyhow::bail!("unsupported protocol {:?}", version);
+  }
+  let (path, query) = target.split_once('?').unwrap_or((target, ""));
+  let mut request = Request {
+    method: method.to_string(),
+    path: path.to_string(),
+    query: parse_query(query),
+    ..Default::default()
+  };
+  for line in lines.filter(|l| !l.is_empty()) {
+    let (name, value) = line
+      .split_once(':')
+      .with_context(|| format!("malformed header {:?}", line))?;
+    request
+      .headers
+      .insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+  }
+  Ok(request)
+}
+
+fn parse_query(query: &str) -> BTreeMap<String, String> {
+  query
+    .split('&')
+    .filter(|pair| !pair.is_empty())
+    .map(|pair| {
+      let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+      (percent_decode(key), percent_decode(value))
+    })
+    .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+  let bytes = s.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'+' => out.push(b' '),
+      b'%' if i + 2 < bytes.len() => {
+        let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+          Some(byte) => {
+            out.push(byte);
+            i += 2;
+          }
+          None => out.push(b'%'),
+        }
+      }
+      byte => out.push(byte),
+    }
+    i += 1;
+  }
+  String::from_utf8_lossy(&out).to_string()
+}
+
+/// The app and action a `/apps/<app>/<action>` path addresses.
+pub fn route(path: &str) -> Option<(String, Action)> {
+  let rest = path.strip_prefix("/apps/")?;
+  let (app, action) = rest.trim_end_matches('/').split_once('/')?;
+  let valid_app = !app.is_empty()
+    && app
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+  let action = match action {
+    "deploy" => Action::Deploy,
+    "rollback" => Action::Rollback,
+    "restart" => Action::Restart,
+    _ => return None,
+  };
+  valid_app.then(|| (app.to_string(), action))
+}
+
+/// Whether the request carries `token`: as a bearer token, a GitLab secret token, or a GitHub
+/// HMAC-SHA256 signature of its body.
+pub fn authorize(request: &Request, token: &str) -> bool {
+  if token.is_empty() {
+    return false;
+  }
+  if let Some(bearer) = request
+    .header("authorization")
+    .and_then(|h| h.strip_prefix("Bearer "))
+  {
+    return constant_time_eq(bearer.trim().as_bytes(), token.as_bytes());
+  }
+  if let Some(gitlab) = request.header("x-gitlab-token") {
+    return constant_time_eq(gitlab.as_bytes(), token.as_bytes());
+  }
+  if let Some(signature) = request
+    .header("x-hub-signature-256")
+    .and_then(|h| h.strip_prefix("sha256="))
+    .and_then(decode_hex)
+  {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, token.as_bytes());
+    return ring::hmac::verify(&key, &request.body, &signature).is_ok();
+  }
+  false
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+  if !s.len().is_multiple_of(2) {
+    return None;
+  }
+  (0..s.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+    .collect()
+}
+
+/// A commit to deploy and the ref it was pushed to.
+#[derive(Debug, PartialEq)]
+pub struct DeployTrigger {
+  pub sha: String,
+  /// Pushed branch; None for tag pushes
+  pub branch: Option<String>,
+  pub tag: Option<String>,
+  /// Pushed ref, for hl.yml's `deploy:` filters; None for direct API calls
+  pub refname: Option<String>,
+  /// Repository URL from a push event's payload
+  pub clone_url: Option<String>,
+}
+
+/// What a request asks for.
+#[derive(Debug, PartialEq)]
+pub enum Trigger {
+  Deploy(DeployTrigger),
+  Rollback(String),
+  Restart,
+  /// GitHub's ping when a webhook is created
+  Ping,
+  /// An event there is nothing to do for, and why
+  Ignored(String),
+}
+
+/// Read what `action` should do from the request: a GitHub or GitLab event payload, or the
+/// query of a direct API call.
+pub fn parse_trigger(request: &Request, action: Action) -> Result<Trigger> {
+  let github = request.header("x-github-event");
+  if github == Some("ping") {
+    return Ok(Trigger::Ping);
+  }
+  let gitlab = request.header("x-gitlab-event");
+  match action {
+    Action::Deploy if github.is_some() || gitlab.is_some() => {
+      let event = github.or(gitlab).unwrap_or_default();
+      if !matches!(event, "push" | "Push Hook" | "Tag Push Hook") {
+        return Ok(Trigger::Ignored(format!("{} events don't deploy", event)));
+      }
+      parse_push_event(&request.body, gitlab.is_some())
+    }
+    Action::Deploy => {
+      let sha = sha_param(request)?;
+      let tag = request.query.get("tag").cloned();
+      let branch = match (&tag, request.query.get("branch")) {
+        (_, Some(branch)) => Some(branch.clone()),
+        (Some(_), None) => None,
+        (None, None) => Some("master".to_string()),
+      };
+      if let Some(name) = branch.iter().chain(&tag).find(|name| !is_ref_name(name)) {
+        anyhow::bail!("invalid branch or tag {:?}", name);
+      }
+      Ok(Trigger::Deploy(DeployTrigger {
+        sha,
+        branch,
+        tag,
+        refname: None,
+        clone_url: None,
+      }))
+    }
+    Action::Rollback => Ok(Trigger::Rollback(sha_param(request)?)),
+    Action::Restart => Ok(Trigger::Restart),
+  }
+}
+
+/// `?sha=`, which must be a commit SHA since it ends up on git and hl command lines.
+fn sha_param(request: &Request) -> Result<String> {
+  let sha = request
+    .query
+    .get("sha")
+    .filter(|v| !v.is_empty())
+    .context("missing ?sha= parameter")?;
+  if !is_commit_sha(sha) {
+    anyhow::bail!("invalid sha {:?}: expected a commit SHA", sha);
+  }
+  Ok(sha.clone())
+}
+
+/// The ref and commit of a GitHub or GitLab push event.
+fn parse_push_event(body: &[u8], gitlab: bool) -> Result<Trigger> {
+  let payload: Value = std::str::from_utf8(body)
+    .map_err(anyhow::Error::from)
+    .and_then(from_json)
+    .context(
+      "push event payload is not JSON (set the webhook's content type to application/json)",
+    )?;
+  let refname = payload
+    .get("ref")
+    .and_then(Value::as_str)
+    .context("push event has no ref")?
+    .to_string();
+  let (sha, clone_url) = if gitlab {
+    (
+      payload.get("checkout_sha").and_then(Value::as_str),
+      payload
+        .get("project")
+        .and_then(|p| p.get("git_http_url"))
+        .and_then(Value::as_str),
+    )
+  } else {
+    let deleted = payload.get("deleted").and_then(Value::as_bool) == Some(true);
+    (
+      payload
+        .get("after")
+        .and_then(Value::as_str)
+        .filter(|_| !deleted),
+      payload
+        .get("repository")
+        .and_then(|r| r.get("clone_url"))
+        .and_then(Value::as_str),
+    )
+  };
+  let Some(sha) = sha.filter(|sha| !sha.bytes().all(|b| b == b'0')) else {
+    return Ok(Trigger::Ignored(format!("{} was deleted", refname)));
+  };
+  if !is_commit_sha(sha) {
+    anyhow::bail!("push event has an invalid commit {:?}", sha);
+  }
+  if let Some(url) = clone_url.filter(|url| url.starts_with('-')) {
+    anyhow::bail!("push event has an invalid repository URL {:?}", url);
+  }
+  let (branch, tag) = if let Some(tag) = refname.strip_prefix("refs/tags/") {
+    (None, Some(tag.to_string()))
+  } else if let Some(branch) = refname.strip_prefix("refs/heads/") {
+    (Some(branch.to_string()), None)
+  } else {
+    return Ok(Trigger::Ignored(format!(
+      "{} is not a branch or tag",
+      refname
+    )));
+  };
+  if !branch.iter().chain(&tag).all(|name| is_ref_name(name)) {
+    anyhow::bail!("push event has an invalid ref {:?}", refname);
+  }
+  Ok(Trigger::Deploy(DeployTrigger {
+    sha: sha.to_string(),
+    branch,
+    tag,
+    refname: Some(refname),
+    clone_url: clone_url.map(str::to_string),
+  }))
+}
+
+/// A complete `Connection: close` response with a plain text body.
+pub fn response(status: u16, body: &str) -> String {
+  let reason = match status {
+    200 => "OK",
+    202 => "Accepted",
+    400 => "Bad Request",
+    401 => "Unauthorized",
+    404 => "Not Found",
+    405 => "Method Not Allowed",
+    413 => "Payload Too Large",
+    _ => "Internal Server Error",
+  };
+  let body = if body.ends_with('\n') || body.is_empty() {
+    body.to_string()
+  } else {
+    format!("{}\n", body)
+  };
+  format!(
+    "HTTP/1.1 {} {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status,
+    reason,
+    body.len(),
+    body
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn request(head: &str, body: &str) -> Request {
+    let mut request = parse_request_head(head).unwrap();
+    request.body = body.as_bytes().to_vec();
+    request
+  }
+
+  #[test]
+  fn test_parse_request() {
+    let req = request(
+      "POST /apps/blog/deploy?sha=abc123&branch=feature%2Fx&wait=1 HTTP/1.1\r\n\
+       Host: localhost\r\nAuthorization: Bearer s3cret\r\nContent-Length: 0",
+      "",
+    );
+    assert_eq!(req.method, "POST");
+    assert_eq!(route(&req.path), Some(("blog".to_string(), Action::Deploy)));
+    assert_eq!(req.query["branch"], "feature/x");
+    assert!(req.wait());
+    assert_eq!(req.content_length().unwrap(), 0);
+    assert!(authorize(&req, "s3cret"));
+    assert!(!authorize(&req, "s3cret2"));
+    assert_eq!(
+      parse_trigger(&req, Action::Deploy).unwrap(),
+      Trigger::Deploy(DeployTrigger {
+        sha: "abc123".to_string(),
+        branch: Some("feature/x".to_string()),
+        tag: None,
+        refname: None,
+        clone_url: None,
+      })
+    );
+    assert!(parse_trigger(&req, Action::Rollback).is_ok());
+    for query in [
+      "sha=--upload-pack=touch%20x",
+      "sha=abc123&branch=-x",
+      "sha=abc123&tag=a:b",
+    ] {
+      let bad = request(
+        &format!("POST /apps/blog/deploy?{} HTTP/1.1\r\n", query),
+        "",
+      );
+      assert!(parse_trigger(&bad, Action::Deploy).is_err(), "{}", query);
+    }
+    let no_sha = request("POST /apps/blog/rollback HTTP/1.1\r\n", "");
+    assert!(parse_trigger(&no_sha, Action::Rollback).is_err());
+    assert!(!authorize(&no_sha, "s3cret"));
+    let empty = request(
+      "POST /apps/blog/restart HTTP/1.1\r\nAuthorization: Bearer ",
+      "",
+    );
+    assert!(!authorize(&empty, ""));
+
+    assert_eq!(route("/apps/../deploy"), None);
+    assert_eq!(route("/apps/blog/destroy"), None);
+    assert_eq!(
+      route("/apps/blog-staging/restart/"),
+      Some(("blog-staging".to_string(), Action::Restart))
+    );
+    assert!(parse_request_head("garbage").is_err());
+  }
+
+  #[test]
+  fn test_webhook_events() {
+    let body = r#"{"ref":"refs/tags/v1.2.0","after":"0123abcd","deleted":false,
+      "head_commit":{"message":"Release \ud83d\ude80"},
+      "repository":{"clone_url":"https://github.com/acme/blog.git"}}"#;
+    // Signature GitHub sends for `body` with secret "s3cret"
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"s3cret");
+    let signature: String = ring::hmac::sign(&key, body.as_bytes())
+      .as_ref()
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect();
+    let github = request(
+      &format!(
+        "POST /apps/blog/deploy HTTP/1.1\r\nX-GitHub-Event: push\r\nX-Hub-Signature-256: sha256={}",
+        signature
+      ),
+      body,
+    );
+    assert!(authorize(&github, "s3cret"));
+    assert!(!authorize(&github, "other"));
+    assert_eq!(
+      parse_trigger(&github, Action::Deploy).unwrap(),
+      Trigger::Deploy(DeployTrigger {
+        sha: "0123abcd".to_string(),
+        branch: None,
+        tag: Some("v1.2.0".to_string()),
+        refname: Some("refs/tags/v1.2.0".to_string()),
+        clone_url: Some("https://github.com/acme/blog.git".to_string()),
+      })
+    );
+
+    let ping = request(
+      "POST /apps/blog/deploy HTTP/1.1\r\nX-GitHub-Event: ping",
+      "{}",
+    );
+    assert_eq!(parse_trigger(&ping, Action::Deploy).unwrap(), Trigger::Ping);
+    let issues = request(
+      "POST /apps/blog/deploy HTTP/1.1\r\nX-GitHub-Event: issues",
+      "{}",
+    );
+    assert!(matches!(
+      parse_trigger(&issues, Action::Deploy).unwrap(),
+      Trigger::Ignored(_)
+    ));
+
+    let gitlab = request(
+      "POST /apps/blog/deploy HTTP/1.1\r\nX-Gitlab-Event: Push Hook\r\nX-Gitlab-Token: s3cret",
+      r#"{"ref":"refs/heads/main","checkout_sha":"89ab",
+        "project":{"git_http_url":"https://gitlab.com/acme/blog.git"}}"#,
+    );
+    assert!(authorize(&gitlab, "s3cret"));
+    let Trigger::Deploy(deploy) = parse_trigger(&gitlab, Action::Deploy).unwrap() else {
+      panic!("expected a deploy");
+    };
+    assert_eq!(deploy.branch.as_deref(), Some("main"));
+    assert_eq!(deploy.refname.as_deref(), Some("refs/heads/main"));
+
+    let injected = request(
+      "POST /apps/blog/deploy HTTP/1.1\r\nX-GitHub-Event: push",
+      r#"{"ref":"refs/heads/main","after":"0123abcd","repository":{"clone_url":"--upload-pack=x"}}"#,
+    );
+    assert!(parse_trigger(&injected, Action::Deploy).is_err());
+
+    let deleted = request(
+      "POST /apps/blog/deploy HTTP/1.1\r\nX-Gitlab-Event: Push Hook",
+      r#"{"ref":"refs/heads/old","checkout_sha":null}"#,
+    );
+    assert!(matches!(
+      parse_trigger(&deleted, Action::Deploy).unwrap(),
+      Trigger::Ignored(_)
+    ));
+  }
+}